@@ -1,8 +1,7 @@
-use crc32fast::Hasher;
 use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
-use rand::SeedableRng as _;
-use rand_pcg::Pcg64Mcg;
+use randstream::checksum::ChecksumAlgorithm;
 use randstream::generate::generate_chunk;
+use randstream::rng::{RngAlgorithm, Seed};
 use randstream::validate::validate_chunk;
 use std::hint::black_box;
 use std::process::{Command, Stdio};
@@ -33,19 +32,12 @@ fn bench_generate_chunk(c: &mut Criterion) {
             BenchmarkId::from_parameter(format!("{}B", chunk_size)),
             chunk_size,
             |b, &chunk_size| {
-                let mut rng = Pcg64Mcg::seed_from_u64(0);
+                let mut rng = RngAlgorithm::Pcg64.rng(Seed::U64(0));
                 let mut buffer = vec![0u8; chunk_size];
-                let mut hasher = Hasher::new();
-                let mut local_hasher = Hasher::new();
+                let mut checksum = ChecksumAlgorithm::Crc32.stream_checksum();
 
                 b.iter(|| {
-                    generate_chunk(
-                        &mut rng,
-                        &mut buffer,
-                        chunk_size,
-                        &mut hasher,
-                        &mut local_hasher,
-                    );
+                    generate_chunk(&mut rng, &mut buffer, chunk_size, &mut checksum);
                 });
             },
         );
@@ -66,16 +58,15 @@ fn bench_validate_chunk(c: &mut Criterion) {
             chunk_size,
             |b, &chunk_size| {
                 // Pre-generate a valid chunk so validate_chunk never errors.
-                let mut rng = Pcg64Mcg::seed_from_u64(0);
+                let mut rng = RngAlgorithm::Pcg64.rng(Seed::U64(0));
                 let mut buffer = vec![0u8; chunk_size];
-                let mut hasher = Hasher::new();
-                let mut local_hasher = Hasher::new();
-                generate_chunk(&mut rng, &mut buffer, chunk_size, &mut hasher, &mut local_hasher);
+                let mut checksum = ChecksumAlgorithm::Crc32.stream_checksum();
+                generate_chunk(&mut rng, &mut buffer, chunk_size, &mut checksum);
                 let chunk_index: u64 = 0;
 
                 b.iter(|| {
-                    let mut h = Hasher::new();
-                    validate_chunk(black_box(chunk_index), black_box(&buffer), &mut h).unwrap();
+                    let mut c = ChecksumAlgorithm::Crc32.stream_checksum();
+                    validate_chunk(black_box(chunk_index), black_box(&buffer), &mut c).unwrap();
                 });
             },
         );
@@ -0,0 +1,58 @@
+/*
+ * The C bindings of randstream: generate and validate the streams of
+ * `randstream generate` and `randstream validate`, with the default random
+ * generator and checksum.
+ */
+#ifndef RANDSTREAM_H
+#define RANDSTREAM_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct randstream_generator randstream_generator;
+typedef struct randstream_validator randstream_validator;
+
+/* The result of a validation */
+typedef struct randstream_report {
+    uint64_t bytes;
+    /* The checksum of the stream, as logged by `randstream validate` in hexadecimal */
+    uint64_t checksum;
+    uint64_t corrupted_chunks;
+    /* The index of the first corrupted chunk, if any */
+    uint64_t first_corrupted_chunk;
+} randstream_report;
+
+/* Create the generator of the stream of len bytes, or return NULL if the chunk size is 0 */
+randstream_generator *randstream_generator_new(uint64_t seed, size_t chunk_size, uint64_t len);
+
+/* Fill the buffer with the next bytes of the stream, and return their number, 0 at the end */
+size_t randstream_generator_fill(randstream_generator *generator, uint8_t *buf, size_t len);
+
+/* The checksum of the bytes generated so far, the one of the whole stream once it's all filled */
+uint64_t randstream_generator_checksum(const randstream_generator *generator);
+
+void randstream_generator_free(randstream_generator *generator);
+
+/* Create the validator of a stream, or return NULL if the chunk size is 0 */
+randstream_validator *randstream_validator_new(size_t chunk_size);
+
+/* Validate the next bytes of the stream */
+void randstream_validator_write(randstream_validator *validator, const uint8_t *buf, size_t len);
+
+/*
+ * Validate the end of the stream, fill the report if not NULL, and free the validator
+ *
+ * Returns 0 if the stream is valid, or 2, the exit code of `randstream validate` for a
+ * corrupted chunk.
+ */
+int randstream_validator_finish(randstream_validator *validator, randstream_report *report);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
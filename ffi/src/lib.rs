@@ -0,0 +1,170 @@
+//! The C bindings of randstream, declared in `include/randstream.h`
+//!
+//! The streams are the ones of `randstream generate` and `randstream validate`, with the default
+//! random generator and checksum.
+
+use std::io::{Read as _, Write as _};
+use std::{ptr, slice};
+
+use randstream::error::exit_code;
+use randstream::rng::Seed;
+use randstream::stream::{RandomStream, ValidatingWriter};
+
+/// The generator of a stream
+pub struct Generator(RandomStream);
+
+/// The validator of a stream
+pub struct Validator(ValidatingWriter);
+
+/// The result of a validation
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct Report {
+    pub bytes: u64,
+    /// The checksum of the stream, as logged by `randstream validate` in hexadecimal
+    pub checksum: u64,
+    pub corrupted_chunks: u64,
+    /// The index of the first corrupted chunk, if any
+    pub first_corrupted_chunk: u64,
+}
+
+/// Create the generator of the stream of `len` bytes, or return NULL if the chunk size is 0
+#[unsafe(no_mangle)]
+pub extern "C" fn randstream_generator_new(
+    seed: u64,
+    chunk_size: usize,
+    len: u64,
+) -> *mut Generator {
+    if chunk_size == 0 {
+        return ptr::null_mut();
+    }
+    Box::into_raw(Box::new(Generator(RandomStream::new(Seed::U64(seed), chunk_size, len))))
+}
+
+/// Fill the buffer with the next bytes of the stream, and return their number, 0 at the end
+///
+/// # Safety
+///
+/// `generator` must come from `randstream_generator_new()`, and `buf` must be valid for `len`
+/// bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn randstream_generator_fill(
+    generator: *mut Generator,
+    buf: *mut u8,
+    len: usize,
+) -> usize {
+    let generator = unsafe { &mut *generator };
+    let buf = unsafe { slice::from_raw_parts_mut(buf, len) };
+    let mut filled = 0;
+    while filled < len {
+        match generator.0.read(&mut buf[filled..]) {
+            Ok(0) | Err(_) => break,
+            Ok(size) => filled += size,
+        }
+    }
+    filled
+}
+
+/// The checksum of the bytes generated so far, the one of the whole stream once it's all filled
+///
+/// # Safety
+///
+/// `generator` must come from `randstream_generator_new()`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn randstream_generator_checksum(generator: *const Generator) -> u64 {
+    let generator = unsafe { &*generator };
+    parse_checksum(&generator.0.checksum())
+}
+
+/// Free the generator
+///
+/// # Safety
+///
+/// `generator` must come from `randstream_generator_new()`, or be NULL.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn randstream_generator_free(generator: *mut Generator) {
+    if !generator.is_null() {
+        drop(unsafe { Box::from_raw(generator) });
+    }
+}
+
+/// Create the validator of a stream, or return NULL if the chunk size is 0
+#[unsafe(no_mangle)]
+pub extern "C" fn randstream_validator_new(chunk_size: usize) -> *mut Validator {
+    if chunk_size == 0 {
+        return ptr::null_mut();
+    }
+    Box::into_raw(Box::new(Validator(ValidatingWriter::new(chunk_size))))
+}
+
+/// Validate the next bytes of the stream
+///
+/// # Safety
+///
+/// `validator` must come from `randstream_validator_new()`, and `buf` must be valid for `len`
+/// bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn randstream_validator_write(
+    validator: *mut Validator,
+    buf: *const u8,
+    len: usize,
+) {
+    let validator = unsafe { &mut *validator };
+    let buf = unsafe { slice::from_raw_parts(buf, len) };
+    // the validation never fails, the corrupted chunks are reported at the end
+    validator.0.write_all(buf).unwrap();
+}
+
+/// Validate the end of the stream, fill the report if not NULL, and free the validator
+///
+/// Returns 0 if the stream is valid, or 2, the exit code of `randstream validate` for a corrupted
+/// chunk.
+///
+/// # Safety
+///
+/// `validator` must come from `randstream_validator_new()`, and `report` must be valid or NULL.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn randstream_validator_finish(
+    validator: *mut Validator,
+    report: *mut Report,
+) -> i32 {
+    let validator = unsafe { Box::from_raw(validator) };
+    let result = validator.0.finish();
+    if let Some(report) = unsafe { report.as_mut() } {
+        *report = Report {
+            bytes: result.bytes,
+            checksum: parse_checksum(&result.checksum),
+            corrupted_chunks: result.corrupted.len() as u64,
+            first_corrupted_chunk: result.corrupted.first().map_or(0, |c| c.chunk),
+        };
+    }
+    if result.is_valid() { 0 } else { exit_code::CHUNK_MISMATCH }
+}
+
+fn parse_checksum(checksum: &str) -> u64 {
+    u64::from_str_radix(checksum, 16).expect("an hexadecimal checksum")
+}
+
+#[test]
+fn generate_and_validate() {
+    let generator = randstream_generator_new(2, 32 * 1024, 33 * 1024);
+    let mut data = vec![0; 40 * 1024];
+    let len = unsafe { randstream_generator_fill(generator, data.as_mut_ptr(), data.len()) };
+    assert_eq!(len, 33 * 1024);
+    // the checksum logged by `generate --size 33Ki --chunk-size 32Ki --seed 2`
+    assert_eq!(unsafe { randstream_generator_checksum(generator) }, 0xaa0e5a26);
+    unsafe { randstream_generator_free(generator) };
+
+    data[33_000] ^= 1;
+    let validator = randstream_validator_new(32 * 1024);
+    for part in data[..len].chunks(10_000) {
+        unsafe { randstream_validator_write(validator, part.as_ptr(), part.len()) };
+    }
+    let mut report = Report::default();
+    assert_eq!(unsafe { randstream_validator_finish(validator, &mut report) }, 2);
+    assert_eq!(
+        (report.bytes, report.corrupted_chunks, report.first_corrupted_chunk),
+        (33 * 1024, 1, 1)
+    );
+    assert!(randstream_validator_new(0).is_null());
+}
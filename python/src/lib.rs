@@ -0,0 +1,164 @@
+//! The Python bindings of randstream, built with maturin
+//!
+//! The streams are the ones of `randstream generate` and `randstream validate`, with the default
+//! random generator and checksum. The data is passed as bytes, or any object supporting the
+//! buffer protocol, like a `bytearray` or a `memoryview`.
+
+use pyo3::buffer::PyBuffer;
+use pyo3::exceptions::{PyBufferError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::io::{Read as _, Write as _};
+
+use randstream::rng::Seed;
+use randstream::stream::{self, ValidatingWriter};
+
+/// A random stream, read like a binary file
+///
+/// It's the stream written by `randstream generate --seed <seed> --chunk-size <chunk_size>
+/// --size <size>`, so it can be uploaded with boto3 `upload_fileobj()`, for instance.
+#[pyclass(module = "randstream")]
+struct RandomStream {
+    stream: stream::RandomStream,
+    /// The bytes read so far
+    position: u64,
+}
+
+#[pymethods]
+impl RandomStream {
+    #[new]
+    fn new(seed: u64, chunk_size: usize, size: u64) -> PyResult<Self> {
+        check_chunk_size(chunk_size)?;
+        let stream = stream::RandomStream::new(Seed::U64(seed), chunk_size, size);
+        Ok(RandomStream { stream, position: 0 })
+    }
+
+    /// Read up to `size` bytes, or up to the end of the stream
+    #[pyo3(signature = (size = -1))]
+    fn read<'py>(&mut self, py: Python<'py>, size: i64) -> Bound<'py, PyBytes> {
+        let remaining = self.stream.len() - self.position;
+        let size = u64::try_from(size).map_or(remaining, |size| size.min(remaining));
+        let mut data = vec![0; size as usize];
+        py.detach(|| self.fill(&mut data));
+        PyBytes::new(py, &data)
+    }
+
+    /// Read into the writable buffer, and return the number of bytes read
+    fn readinto(&mut self, py: Python<'_>, buffer: PyBuffer<u8>) -> PyResult<usize> {
+        let cells = buffer
+            .as_mut_slice(py)
+            .ok_or_else(|| PyBufferError::new_err("expected a writable contiguous buffer"))?;
+        let remaining = self.stream.len() - self.position;
+        let mut data = vec![0; (cells.len() as u64).min(remaining) as usize];
+        py.detach(|| self.fill(&mut data));
+        cells.iter().zip(&data).for_each(|(cell, byte)| cell.set(*byte));
+        Ok(data.len())
+    }
+
+    fn readable(&self) -> bool {
+        true
+    }
+
+    /// The size of the stream
+    #[getter]
+    fn size(&self) -> u64 {
+        self.stream.len()
+    }
+
+    /// The checksum of the bytes read so far, the one of the whole stream once it's all read
+    #[getter]
+    fn checksum(&self) -> String {
+        self.stream.checksum()
+    }
+}
+
+impl RandomStream {
+    fn fill(&mut self, data: &mut [u8]) {
+        // the data is generated in memory, so it can't fail
+        self.stream.read_exact(data).unwrap();
+        self.position += data.len() as u64;
+    }
+}
+
+/// Validates the chunks of a random stream as it's written, like `randstream validate
+/// --keep-going`
+#[pyclass(module = "randstream")]
+struct Validator {
+    /// The writer, until the validation is finished
+    writer: Option<ValidatingWriter>,
+}
+
+#[pymethods]
+impl Validator {
+    #[new]
+    fn new(chunk_size: usize) -> PyResult<Self> {
+        check_chunk_size(chunk_size)?;
+        Ok(Validator { writer: Some(ValidatingWriter::new(chunk_size)) })
+    }
+
+    /// Validate the next bytes of the stream, and return their number
+    fn write(&mut self, py: Python<'_>, data: PyBuffer<u8>) -> PyResult<usize> {
+        let writer = self.writer()?;
+        let data = data.to_vec(py)?;
+        // the corrupted chunks are reported at the end
+        py.detach(|| writer.write_all(&data).unwrap());
+        Ok(data.len())
+    }
+
+    /// Validate the end of the stream, and return the result
+    fn finish(&mut self) -> PyResult<Report> {
+        self.writer()?;
+        let report = self.writer.take().unwrap().finish();
+        Ok(Report {
+            bytes: report.bytes,
+            checksum: report.checksum,
+            corrupted: report.corrupted.iter().map(|c| c.chunk).collect(),
+        })
+    }
+}
+
+impl Validator {
+    fn writer(&mut self) -> PyResult<&mut ValidatingWriter> {
+        self.writer.as_mut().ok_or_else(|| PyValueError::new_err("the validation is finished"))
+    }
+}
+
+/// The result of a validation
+#[pyclass(module = "randstream", frozen, get_all)]
+struct Report {
+    bytes: u64,
+    /// The checksum of the stream, as logged by `randstream validate`
+    checksum: String,
+    /// The index of the chunks which don't match their checksum
+    corrupted: Vec<u64>,
+}
+
+#[pymethods]
+impl Report {
+    #[getter]
+    fn valid(&self) -> bool {
+        self.corrupted.is_empty()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Report(bytes={}, checksum='{}', corrupted={:?})",
+            self.bytes, self.checksum, self.corrupted
+        )
+    }
+}
+
+fn check_chunk_size(chunk_size: usize) -> PyResult<()> {
+    if chunk_size == 0 {
+        return Err(PyValueError::new_err("the chunk size must be greater than 0"));
+    }
+    Ok(())
+}
+
+#[pymodule(name = "randstream")]
+fn randstream_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<RandomStream>()?;
+    m.add_class::<Validator>()?;
+    m.add_class::<Report>()?;
+    Ok(())
+}
@@ -0,0 +1,45 @@
+import unittest
+
+import randstream
+
+
+class TestRandstream(unittest.TestCase):
+    def test_read_and_validate(self):
+        stream = randstream.RandomStream(2, 32 * 1024, 33 * 1024)
+        self.assertEqual(stream.size, 33 * 1024)
+        data = stream.read(1000) + stream.read()
+        self.assertEqual(len(data), 33 * 1024)
+        self.assertEqual(stream.read(), b"")
+        # the checksum logged by `generate --size 33Ki --chunk-size 32Ki --seed 2`
+        self.assertEqual(stream.checksum, "aa0e5a26")
+
+        validator = randstream.Validator(32 * 1024)
+        validator.write(data[:5000])
+        validator.write(memoryview(data)[5000:])
+        report = validator.finish()
+        self.assertTrue(report.valid)
+        self.assertEqual((report.bytes, report.checksum), (33 * 1024, "aa0e5a26"))
+        with self.assertRaises(ValueError):
+            validator.finish()
+
+    def test_readinto_and_corruption(self):
+        stream = randstream.RandomStream(7, 4096, 10000)
+        buffer = bytearray(8192)
+        self.assertEqual(stream.readinto(buffer), 8192)
+        rest = bytearray(8192)
+        self.assertEqual(stream.readinto(rest), 10000 - 8192)
+        buffer[5000] ^= 1
+        validator = randstream.Validator(4096)
+        validator.write(buffer)
+        validator.write(rest[: 10000 - 8192])
+        report = validator.finish()
+        self.assertFalse(report.valid)
+        self.assertEqual(report.corrupted, [1])
+
+    def test_invalid_chunk_size(self):
+        with self.assertRaises(ValueError):
+            randstream.RandomStream(0, 0, 100)
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,61 @@
+//! AES-128 in counter mode, used as a random generator.
+
+use aes::Aes128;
+use ctr::cipher::{KeyIvInit as _, StreamCipher as _, StreamCipherSeek as _};
+
+type Ctr = ctr::Ctr128LE<Aes128>;
+
+/// A random generator producing the AES-128 encryption of a little endian counter
+pub struct AesCtr(Ctr);
+
+impl std::fmt::Debug for AesCtr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AesCtr").field("position", &self.0.current_pos::<u128>()).finish()
+    }
+}
+
+impl AesCtr {
+    pub fn new(key: [u8; 16]) -> Self {
+        AesCtr(Ctr::new(&key.into(), &[0; 16].into()))
+    }
+
+    pub fn fill_bytes(&mut self, out: &mut [u8]) {
+        self.0.write_keystream(out);
+    }
+
+    /// Skip `bytes` bytes of output
+    pub fn advance(&mut self, bytes: u128) {
+        let position: u128 = self.0.current_pos();
+        self.0.seek(position.wrapping_add(bytes));
+    }
+}
+
+#[test]
+fn aes_ctr_counter_layout() {
+    use aes::cipher::{BlockCipherEncrypt as _, KeyInit as _};
+    let key: [u8; 16] = std::array::from_fn(|i| i as u8);
+    let counter: u128 = 0x0123_4567_89ab_cdef;
+    let mut rng = AesCtr::new(key);
+    rng.advance(counter * 16);
+    let mut block = [0u8; 16];
+    rng.fill_bytes(&mut block);
+    let mut expected = counter.to_le_bytes().into();
+    Aes128::new(&key.into()).encrypt_block(&mut expected);
+    assert_eq!(block, expected.0);
+}
+
+#[test]
+fn aes_ctr_partial_blocks() {
+    let key: [u8; 16] = std::array::from_fn(|i| (i * 7) as u8);
+    let mut whole = vec![0u8; 8 + 1000 + 24 + 4096 + 3];
+    AesCtr::new(key).fill_bytes(&mut whole);
+    let mut rng = AesCtr::new(key);
+    let mut start = 0;
+    // uneven sizes to go through the partial blocks
+    for size in [8, 1000, 24, 4096, 3] {
+        let mut part = vec![0u8; size];
+        rng.fill_bytes(&mut part);
+        assert_eq!(part, whole[start..start + size], "{size}");
+        start += size;
+    }
+}
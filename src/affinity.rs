@@ -0,0 +1,162 @@
+//! The CPUs the worker threads run on, with `--cpu-affinity` and `--numa-local`
+//!
+//! Each thread is pinned to one of the CPUs, in turn. The buffers of a thread are allocated and
+//! first written by the thread itself, so once it's pinned, the kernel places their pages on the
+//! memory node of its CPU.
+
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use log::{debug, info, warn};
+
+use crate::error::usage;
+
+/// The CPUs the threads are pinned to, none leaving them to the scheduler
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Affinity {
+    cpus: Arc<[usize]>,
+}
+
+impl Affinity {
+    pub fn new(cpus: Vec<usize>) -> Self {
+        Affinity { cpus: cpus.into() }
+    }
+
+    /// The CPUs of the NUMA node closest to the device holding the file, with `--numa-local`
+    pub(crate) fn numa_local(file: &Path) -> anyhow::Result<Affinity> {
+        let Some(node) = numa_node(file)? else {
+            warn!("no NUMA node found for {}, the threads aren't pinned", file.display());
+            return Ok(Affinity::default());
+        };
+        let cpulist = format!("/sys/devices/system/node/node{node}/cpulist");
+        let cpus = std::fs::read_to_string(&cpulist)?;
+        let cpus = parse_cpu_list(cpus.trim()).map_err(|e| anyhow::anyhow!("{cpulist}: {e}"))?;
+        info!("NUMA node of {}: {node}, CPUs {}", file.display(), cpus.len());
+        Ok(Affinity::new(cpus))
+    }
+
+    /// Check that the CPUs given with `--cpu-affinity` are available to the process
+    #[cfg(target_os = "linux")]
+    pub(crate) fn check(&self) -> anyhow::Result<()> {
+        use nix::sched::{CpuSet, sched_getaffinity};
+        use nix::unistd::Pid;
+
+        let allowed = sched_getaffinity(Pid::from_raw(0))?;
+        let unavailable =
+            |cpu: &&usize| **cpu >= CpuSet::count() || !allowed.is_set(**cpu).unwrap();
+        match self.cpus.iter().find(unavailable) {
+            Some(cpu) => Err(usage(format!("The CPU {cpu} isn't available"))),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn check(&self) -> anyhow::Result<()> {
+        Err(usage("--cpu-affinity is only available on Linux"))
+    }
+
+    /// Pin the calling thread, the `index`th worker, to its CPU
+    pub fn pin(&self, index: usize) -> io::Result<()> {
+        if self.cpus.is_empty() {
+            return Ok(());
+        }
+        let cpu = self.cpus[index % self.cpus.len()];
+        debug!("thread {index}: CPU {cpu}");
+        set_affinity(cpu).map_err(|e| {
+            io::Error::new(e.kind(), format!("Can't pin thread {index} to CPU {cpu}: {e}"))
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_affinity(cpu: usize) -> io::Result<()> {
+    use nix::sched::{CpuSet, sched_setaffinity};
+    use nix::unistd::Pid;
+
+    let mut set = CpuSet::new();
+    set.set(cpu)?;
+    Ok(sched_setaffinity(Pid::from_raw(0), &set)?)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_affinity(_cpu: usize) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "only available on Linux"))
+}
+
+/// The CPUs given with `--cpu-affinity`
+pub(crate) fn parse_affinity(s: &str) -> Result<Affinity, String> {
+    parse_cpu_list(s).map(Affinity::new)
+}
+
+/// A list of CPUs, like `0-7,16-23`, as in `taskset -c` and sysfs
+fn parse_cpu_list(s: &str) -> Result<Vec<usize>, String> {
+    let mut cpus = Vec::new();
+    for range in s.split(',') {
+        let (first, last) = range.split_once('-').unwrap_or((range, range));
+        let first: usize = first.trim().parse().map_err(|_| format!("invalid CPU {first}"))?;
+        let last: usize = last.trim().parse().map_err(|_| format!("invalid CPU {last}"))?;
+        if first > last {
+            return Err(format!("invalid range of CPUs {range}"));
+        }
+        cpus.extend(first..=last);
+    }
+    cpus.dedup();
+    Ok(cpus)
+}
+
+/// The NUMA node of the device holding the file, or of the block device itself
+#[cfg(target_os = "linux")]
+fn numa_node(file: &Path) -> anyhow::Result<Option<u32>> {
+    use std::os::unix::fs::{FileTypeExt as _, MetadataExt as _};
+
+    let metadata = std::fs::metadata(file)?;
+    let dev = match metadata.file_type().is_block_device() {
+        true => metadata.rdev(),
+        false => metadata.dev(),
+    };
+    let device = format!("/sys/dev/block/{}:{}", nix::libc::major(dev), nix::libc::minor(dev));
+    Ok(std::fs::canonicalize(device).ok().and_then(|device| device_node(&device)))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn numa_node(_file: &Path) -> anyhow::Result<Option<u32>> {
+    Err(usage("--numa-local is only available on Linux"))
+}
+
+/// The NUMA node of the closest parent of the sysfs directory of a device with one, its PCIe
+/// device
+///
+/// The virtual devices, like the device mapper ones, have none.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn device_node(device: &Path) -> Option<u32> {
+    device.ancestors().find_map(|dir| {
+        let node = std::fs::read_to_string(dir.join("numa_node")).ok()?;
+        // -1 when the node isn't known, on the hosts with a single node
+        node.trim().parse().ok()
+    })
+}
+
+#[test]
+fn cpu_lists() {
+    assert_eq!(parse_cpu_list("3"), Ok(vec![3]));
+    assert_eq!(parse_cpu_list("0-3,8,10-11"), Ok(vec![0, 1, 2, 3, 8, 10, 11]));
+    assert!(parse_cpu_list("3-1").is_err());
+    assert!(parse_cpu_list("0,a").is_err());
+    assert!(parse_cpu_list("").is_err());
+}
+
+#[test]
+fn numa_node_from_sysfs() {
+    use std::fs;
+
+    let dir = tempfile::tempdir().unwrap();
+    let pci = dir.path().join("pci0000:40/0000:40:01.1/0000:41:00.0");
+    let disk = pci.join("nvme/nvme0/nvme0n1");
+    fs::create_dir_all(disk.join("nvme0n1p1")).unwrap();
+    assert_eq!(device_node(&disk), None);
+    fs::write(pci.join("numa_node"), "-1\n").unwrap();
+    assert_eq!(device_node(&disk), None);
+    fs::write(pci.join("numa_node"), "1\n").unwrap();
+    assert_eq!(device_node(&disk.join("nvme0n1p1")), Some(1));
+}
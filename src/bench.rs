@@ -0,0 +1,612 @@
+//! A benchmark of a file or a device, like a minimal fio, with the data validated on the way
+
+use clap::{Args, ValueEnum, ValueHint};
+use human_units::{FormatDuration as _, FormatSize as _};
+use log::{debug, info};
+use parse_size::parse_size;
+use std::fs::{self, OpenOptions};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::checksum::{ChecksumAlgorithm, StreamChecksum};
+use crate::chunk::{ChunkFormat, ChunkHeader};
+use crate::direct::{self, AlignedBuffer};
+use crate::engine::IoEngine;
+use crate::error::{Error, exit_code, usage};
+use crate::generate::{ChunkLayout, generate_chunk_with_layout};
+use crate::report::{self, Latencies, OutputFormat, Phase, Report, ThreadStats};
+use crate::rng::{RngAlgorithm, Seed, StreamRng, splitmix64};
+#[cfg(target_os = "linux")]
+use crate::uring::BufferQueue;
+use crate::validate::{locate_corruption, validate_chunk, validate_chunk_header};
+use crate::{SeekableRng as _, read_block_size, read_file_size};
+
+/// Measure the IOPS, the throughput and the latency of a file or a device, with the data validated
+///
+/// The threads write the chunks of the stream for the given time, then read back the chunks
+/// written and validate them for the same time. Each I/O is a whole chunk, at its place in the
+/// stream, so a file written in full is validated by `randstream validate` too.
+#[derive(Args, Debug)]
+pub struct BenchArgs {
+    /// The file or the block device
+    pub file: PathBuf,
+
+    /// The size of the region written and read
+    ///
+    /// Defaults to the size of the file
+    #[clap(short, long, value_name = "SIZE", value_hint = ValueHint::Other, value_parser=|s: &str| parse_size(s))]
+    pub size: Option<u64>,
+
+    /// The time of each phase, in seconds
+    #[clap(long, default_value = "10", value_parser = clap::value_parser!(u64).range(1..))]
+    pub time: u64,
+
+    /// The number of parallel jobs, each one working on its own part of the region
+    ///
+    /// Defaults to the number of physical cores on the host
+    #[clap(short, long)]
+    pub jobs: Option<usize>,
+
+    /// The chunk size, the size of each I/O
+    #[clap(short, long, default_value = "4ki", value_name = "SIZE", value_hint = ValueHint::Other, value_parser=|s: &str| parse_size(s))]
+    pub chunk_size: u64,
+
+    /// The seed of the stream
+    #[clap(short = 'S', long, default_value = "0", value_parser = Seed::parse)]
+    pub seed: Seed,
+
+    /// The checksum algorithm used to seal each chunk
+    #[clap(long, value_enum, default_value_t)]
+    pub checksum: ChecksumAlgorithm,
+
+    /// The random generator
+    #[clap(long, value_enum, default_value_t)]
+    pub rng: RngAlgorithm,
+
+    /// The order the chunks are written and read in
+    #[clap(long, value_enum, default_value_t)]
+    pub access: Access,
+
+    /// The layout of the chunks
+    ///
+    /// With v2, each chunk starts with a header holding its index, so a chunk written or read at
+    /// the wrong place is told from a corrupted one.
+    #[clap(long, value_enum, default_value_t)]
+    pub format: ChunkFormat,
+
+    /// The I/O engine, sync or io-uring
+    #[clap(long, value_enum, default_value_t)]
+    pub engine: IoEngine,
+
+    /// The number of I/O in flight per thread, with the io-uring engine
+    ///
+    /// The queue depth achieved, over all the threads, is reported with the latency.
+    #[clap(long, alias = "queue-depth", default_value = "1", value_parser = clap::value_parser!(u32).range(1..=4096))]
+    pub iodepth: u32,
+
+    /// Open the file with O_DIRECT, to bypass the page cache
+    ///
+    /// The chunk size is rounded up to the logical block size of the file.
+    #[clap(long)]
+    pub direct: bool,
+
+    /// Write the latency distribution of each phase to this file, in the `.hgrm` format of
+    /// HdrHistogram, for plotting
+    ///
+    /// The name of the phase is inserted before the extension: `lat.hgrm` gives `lat.write.hgrm`
+    /// and `lat.read.hgrm`. The latencies are in milliseconds.
+    #[clap(long, value_name = "PATH")]
+    pub latency_hist: Option<PathBuf>,
+
+    /// The format of the result, printed at the end of the run
+    #[clap(long, value_enum, default_value_t)]
+    pub output: OutputFormat,
+}
+
+pub fn bench(args: &BenchArgs, cancel: Arc<AtomicBool>) -> Result<i32, Error> {
+    let mut report = Report::new("bench", args.output);
+    let result = bench_file(args, &cancel, &mut report);
+    report.finish(&result, true);
+    result.map_err(Error::from)
+}
+
+/// The order of the I/O
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Access {
+    /// Each thread goes through its part of the region from its start
+    #[default]
+    Sequential,
+    /// Each thread goes through its part of the region in a pseudorandom order, reproducible
+    /// from the seed, different for the writes and the reads
+    Random,
+}
+
+/// The I/O of a phase
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operation {
+    Write,
+    Read,
+}
+
+/// How the chunks are written and read
+struct BenchParams {
+    chunk_size: usize,
+    alignment: usize,
+    /// The size of the buffers, the generator being advanced by a multiple of 64 bits
+    buffer_size: usize,
+    time: Duration,
+    /// The run ID recorded in the chunk headers, with `--format v2`
+    run_id: u64,
+    /// The number of chunks of the region
+    num_chunks: u64,
+}
+
+/// The chunks of a thread
+#[derive(Clone)]
+struct ThreadWork {
+    /// Its part of the region
+    range: Range<u64>,
+    /// The number of chunks to read, the first ones in the order of the writes
+    written: u64,
+}
+
+/// What a thread has done during a phase
+struct ThreadResult {
+    /// The chunks of its range processed at least once, the first ones in the order of the I/O
+    chunks: u64,
+    latencies: Latencies,
+    stats: ThreadStats,
+}
+
+fn bench_file(args: &BenchArgs, cancel: &AtomicBool, report: &mut Report) -> anyhow::Result<i32> {
+    match args.engine {
+        IoEngine::Sync if args.iodepth > 1 => {
+            return Err(usage("--iodepth requires the io-uring engine"));
+        }
+        #[cfg(not(target_os = "linux"))]
+        IoEngine::IoUring => return Err(usage("The io-uring engine is only available on Linux")),
+        IoEngine::Mmap => return Err(usage("bench can't use the mmap engine")),
+        _ => {}
+    }
+    let size = match args.size {
+        Some(size) => size,
+        None if args.file.exists() => read_file_size(&args.file)?,
+        None => return Err(usage("--size is required, the file doesn't exist")),
+    };
+    // the file isn't truncated, a device or a larger file is only written in the region
+    OpenOptions::new().write(true).create(true).truncate(false).open(&args.file)?;
+    let alignment = if args.direct { read_block_size(&args.file)? } else { 1 };
+    let chunk_size = direct::align_chunk_size(args.chunk_size as usize, 0, alignment)?;
+    // all the I/O have the same size
+    let num_chunks = size / chunk_size as u64;
+    if num_chunks == 0 {
+        return Err(usage(format!("The size {size} can't hold a chunk of {chunk_size} bytes")));
+    }
+    let num_threads = args.jobs.unwrap_or(num_cpus::get_physical()).clamp(1, num_chunks as usize);
+    debug!("number of threads: {num_threads}");
+    let params = BenchParams {
+        chunk_size,
+        alignment,
+        buffer_size: chunk_size.div_ceil(8) * 8,
+        time: Duration::from_secs(args.time),
+        run_id: rand::random(),
+        num_chunks,
+    };
+    let mut work: Vec<_> = (0..num_threads as u64)
+        .map(|t| {
+            let range =
+                t * num_chunks / num_threads as u64..(t + 1) * num_chunks / num_threads as u64;
+            ThreadWork { written: range.end - range.start, range }
+        })
+        .collect();
+
+    info!("writing for {}s", args.time);
+    let written = run_phase(args, &params, Operation::Write, &work, cancel, report)?;
+    if cancel.load(Ordering::Relaxed) {
+        return Ok(exit_code::INTERRUPTED);
+    }
+    // only the chunks written are read back
+    work.iter_mut().zip(written).for_each(|(work, written)| work.written = written);
+    info!("reading for {}s", args.time);
+    run_phase(args, &params, Operation::Read, &work, cancel, report)?;
+    if cancel.load(Ordering::Relaxed) {
+        return Ok(exit_code::INTERRUPTED);
+    }
+    Ok(0)
+}
+
+/// Run the threads of a phase, log its statistics, and return the chunks each thread processed
+fn run_phase(
+    args: &BenchArgs,
+    params: &BenchParams,
+    operation: Operation,
+    work: &[ThreadWork],
+    cancel: &AtomicBool,
+    report: &mut Report,
+) -> anyhow::Result<Vec<u64>> {
+    let start = Instant::now();
+    // the other threads stop at the first failure
+    let failed = AtomicBool::new(false);
+    let stop = [cancel, &failed];
+    let results: Vec<_> = thread::scope(|scope| {
+        let handles: Vec<_> = work
+            .iter()
+            .map(|work| {
+                let (stop, failed) = (&stop, &failed);
+                scope.spawn(move || {
+                    let result = run_thread(args, params, operation, work, start, stop);
+                    if result.is_err() {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    result
+                })
+            })
+            .collect();
+        handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+    });
+    let elapsed = start.elapsed();
+    let mut chunks = Vec::new();
+    let mut latencies = Latencies::default();
+    let mut errors = Vec::new();
+    for result in results {
+        match result {
+            Ok(result) => {
+                chunks.push(result.chunks);
+                latencies.merge(&result.latencies);
+                report.threads.push(result.stats);
+            }
+            Err(e) => errors.push(e),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(report.thread_errors(errors));
+    }
+    let bytes = latencies.count() * params.chunk_size as u64;
+    let name = match operation {
+        Operation::Write => "write",
+        Operation::Read => "read",
+    };
+    let phase = Phase {
+        name,
+        pass: 1,
+        stats: ThreadStats { bytes, elapsed },
+        latency: latencies.summary(elapsed),
+    };
+    log_phase(&phase);
+    if let Some(path) = &args.latency_hist {
+        let path = phase_path(path, name);
+        fs::write(&path, latencies.percentile_distribution())?;
+        info!("{name} latency histogram: {}", path.display());
+    }
+    report.bytes += bytes;
+    report.phases.push(phase);
+    Ok(chunks)
+}
+
+/// Write or read the chunks of the range in a loop, until the time is up
+///
+/// The writes start over once the range is written, in the same order, with the same data. The
+/// reads validate each chunk against its index, whatever the order.
+fn run_thread(
+    args: &BenchArgs,
+    params: &BenchParams,
+    operation: Operation,
+    work: &ThreadWork,
+    start: Instant,
+    stop: &[&AtomicBool],
+) -> anyhow::Result<ThreadResult> {
+    let mut chunks = ThreadChunks::new(args, params, work, operation);
+    let count = chunks.count;
+    let running = || {
+        count > 0
+            && start.elapsed() < params.time
+            && !stop.iter().any(|s| s.load(Ordering::Relaxed))
+    };
+    let latencies = match args.engine {
+        #[cfg(target_os = "linux")]
+        IoEngine::IoUring => run_uring(args, params, &mut chunks, running)?,
+        _ => run_sync(args, params, &mut chunks, running)?,
+    };
+    let bytes = latencies.count() * params.chunk_size as u64;
+    Ok(ThreadResult {
+        chunks: chunks.done,
+        latencies,
+        stats: ThreadStats { bytes, elapsed: start.elapsed() },
+    })
+}
+
+/// Write or read one chunk at a time
+fn run_sync(
+    args: &BenchArgs,
+    params: &BenchParams,
+    chunks: &mut ThreadChunks,
+    running: impl Fn() -> bool,
+) -> anyhow::Result<Latencies> {
+    let write = chunks.operation == Operation::Write;
+    let file = direct::open(&args.file, write, params.alignment, None)?;
+    let mut buffer = AlignedBuffer::new(params.buffer_size, params.alignment);
+    let chunk_size = params.chunk_size;
+    let mut latencies = Latencies::default();
+    while running() {
+        let chunk = chunks.next();
+        let offset = chunk * chunk_size as u64;
+        if write {
+            chunks.generate(chunk, &mut buffer);
+            let io_start = Instant::now();
+            direct::write_all_at(&file, &buffer[..chunk_size], offset)?;
+            latencies.record(io_start.elapsed());
+        } else {
+            let data = &mut buffer[..chunk_size];
+            let io_start = Instant::now();
+            direct::read_exact_at(&file, data, offset)?;
+            latencies.record(io_start.elapsed());
+            chunks.validate(chunk, data)?;
+        }
+    }
+    Ok(latencies)
+}
+
+/// Write or read with up to `--iodepth` chunks in flight through io_uring
+///
+/// The latency of an I/O runs from its submission to the processing of its completion.
+#[cfg(target_os = "linux")]
+fn run_uring(
+    args: &BenchArgs,
+    params: &BenchParams,
+    chunks: &mut ThreadChunks,
+    running: impl Fn() -> bool,
+) -> anyhow::Result<Latencies> {
+    use rustix::io_uring::IoringOp;
+    use std::os::fd::AsRawFd as _;
+
+    let write = chunks.operation == Operation::Write;
+    let file = direct::open(&args.file, write, params.alignment, None)?;
+    let op = if write { IoringOp::Write } else { IoringOp::Read };
+    let mut queue =
+        BufferQueue::new(op, file.as_raw_fd(), args.iodepth, params.buffer_size, params.alignment)?;
+    let chunk_size = params.chunk_size;
+    let mut submitted = vec![Instant::now(); args.iodepth as usize];
+    let mut latencies = Latencies::default();
+    loop {
+        if running()
+            && let Some(index) = queue.free_buffer()
+        {
+            let chunk = chunks.next();
+            if write {
+                chunks.generate(chunk, queue.buffer_mut(index));
+            }
+            submitted[index] = Instant::now();
+            queue.submit(index, chunk * chunk_size as u64, chunk_size, chunk)?;
+            continue;
+        }
+        if queue.in_flight() == 0 {
+            return Ok(latencies);
+        }
+        for (index, chunk) in queue.wait()? {
+            latencies.record(submitted[index].elapsed());
+            if !write {
+                if queue.transferred(index) < chunk_size {
+                    return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+                }
+                chunks.validate(chunk, &queue.buffer(index)[..chunk_size])?;
+            }
+            queue.release(index);
+        }
+    }
+}
+
+/// The chunks of a thread, in the order of its I/O, and their data
+struct ThreadChunks<'a> {
+    args: &'a BenchArgs,
+    params: &'a BenchParams,
+    range: Range<u64>,
+    operation: Operation,
+    /// The order of the writes, with `--access random`
+    write_order: Option<Permutation>,
+    /// The order of the reads of the chunks written, with `--access random`
+    read_order: Option<Permutation>,
+    /// The number of positions, the I/O starting over at the first one after the last one
+    count: u64,
+    position: u64,
+    /// The positions processed at least once
+    done: u64,
+    rng: StreamRng,
+    checksum: StreamChecksum,
+}
+
+impl<'a> ThreadChunks<'a> {
+    fn new(
+        args: &'a BenchArgs,
+        params: &'a BenchParams,
+        work: &ThreadWork,
+        operation: Operation,
+    ) -> Self {
+        let range = work.range.clone();
+        let order = |len, operation| {
+            (args.access == Access::Random)
+                .then(|| Permutation::new(len, args.seed, range.start, operation))
+        };
+        ThreadChunks {
+            args,
+            params,
+            write_order: order(range.end - range.start, Operation::Write),
+            // the reads go through the chunks written, in another order
+            read_order: order(work.written, Operation::Read),
+            count: match operation {
+                Operation::Write => range.end - range.start,
+                Operation::Read => work.written,
+            },
+            range,
+            operation,
+            position: 0,
+            done: 0,
+            rng: args.rng.rng(args.seed),
+            checksum: args.checksum.stream_checksum(),
+        }
+    }
+
+    /// The next chunk to write or read, with the generator positioned at it for a write
+    fn next(&mut self) -> u64 {
+        let position = match (self.operation, &self.read_order) {
+            (Operation::Read, Some(order)) => order.get(self.position),
+            _ => self.position,
+        };
+        let chunk =
+            self.range.start + self.write_order.as_ref().map_or(position, |p| p.get(position));
+        // the generator follows the chunks in sequence, and is positioned at each one otherwise
+        if self.operation == Operation::Write && (self.position == 0 || self.write_order.is_some())
+        {
+            self.rng = self.args.rng.rng(self.args.seed);
+            self.rng.advance(chunk * self.params.buffer_size as u64);
+        }
+        self.done = self.done.max(self.position + 1);
+        self.position = if self.position + 1 == self.count { 0 } else { self.position + 1 };
+        chunk
+    }
+
+    fn header(&self, chunk: u64) -> ChunkHeader {
+        ChunkHeader {
+            index: chunk,
+            fingerprint: self.args.seed.fingerprint(),
+            length: self.params.chunk_size as u64,
+            run_id: self.params.run_id,
+        }
+    }
+
+    fn generate(&mut self, chunk: u64, buffer: &mut [u8]) {
+        let layout = ChunkLayout {
+            header: (self.args.format == ChunkFormat::V2).then(|| self.header(chunk)),
+            ..Default::default()
+        };
+        let chunk_size = self.params.chunk_size;
+        generate_chunk_with_layout(&mut self.rng, buffer, chunk_size, &layout, &mut self.checksum);
+    }
+
+    /// Validate the chunk, and its header with `--format v2`
+    fn validate(&mut self, chunk: u64, data: &[u8]) -> anyhow::Result<()> {
+        let chunk_size = self.params.chunk_size;
+        let offset = chunk * chunk_size as u64;
+        validate_chunk(chunk, data, &mut self.checksum)
+            .and_then(|()| match self.args.format {
+                ChunkFormat::V1 => Ok(()),
+                ChunkFormat::V2 => {
+                    let width = self.args.checksum.width();
+                    validate_chunk_header(
+                        chunk,
+                        offset,
+                        data,
+                        &self.header(chunk),
+                        chunk_size,
+                        width,
+                        self.params.num_chunks,
+                    )
+                }
+            })
+            .map_err(|e| locate_corruption(e, chunk, offset, chunk_size))
+    }
+}
+
+/// A pseudorandom permutation of the positions of the chunks of a range, for `--access random`
+///
+/// It's a Feistel network on the smallest even power of 2 holding the range, the values out of
+/// the range being encrypted again until they fall in it, so the order is computed chunk by chunk
+/// without being stored.
+struct Permutation {
+    len: u64,
+    half_bits: u32,
+    keys: [u64; 4],
+}
+
+impl Permutation {
+    /// The permutation of `0..len`, for the range starting at that chunk
+    fn new(len: u64, seed: Seed, first_chunk: u64, operation: Operation) -> Self {
+        let half_bits = (u64::BITS - len.saturating_sub(1).leading_zeros()).div_ceil(2).max(1);
+        let mut state = seed.fingerprint() ^ first_chunk ^ ((operation as u64) << 63);
+        let keys = [(); 4].map(|()| splitmix64(&mut state));
+        Permutation { len, half_bits, keys }
+    }
+
+    /// The value at that position
+    fn get(&self, position: u64) -> u64 {
+        let mut value = position;
+        loop {
+            value = self.encrypt(value);
+            if value < self.len {
+                return value;
+            }
+        }
+    }
+
+    fn encrypt(&self, value: u64) -> u64 {
+        let mask = (1 << self.half_bits) - 1;
+        let (mut left, mut right) = (value >> self.half_bits, value & mask);
+        for key in self.keys {
+            let mut state = right ^ key;
+            (left, right) = (right, left ^ (splitmix64(&mut state) & mask));
+        }
+        (left << self.half_bits) | right
+    }
+}
+
+/// The file of the latency histogram of a phase, with its name before the extension
+fn phase_path(path: &Path, name: &str) -> PathBuf {
+    let mut file_name = path.file_stem().unwrap_or_default().to_os_string();
+    file_name.push(format!(".{name}"));
+    if let Some(extension) = path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    path.with_file_name(file_name)
+}
+
+/// Log the throughput and the latency of a phase
+fn log_phase(phase: &Phase) {
+    let Some(latency) = &phase.latency else { return };
+    info!(
+        "{}: {} in {}, {} IOPS, {}/s, queue depth {:.1}",
+        phase.name,
+        phase.stats.bytes.format_size(),
+        phase.stats.elapsed.format_duration(),
+        latency.iops,
+        report::throughput(phase.stats.bytes, phase.stats.elapsed).format_size(),
+        latency.queue_depth
+    );
+    info!(
+        "{} latency: min {}, mean {}, p50 {}, p99 {}, max {}",
+        phase.name,
+        latency.min.format_duration(),
+        latency.mean.format_duration(),
+        latency.p50.format_duration(),
+        latency.p99.format_duration(),
+        latency.max.format_duration()
+    );
+}
+
+#[test]
+fn phase_paths() {
+    assert_eq!(phase_path(Path::new("out/lat.hgrm"), "read"), Path::new("out/lat.read.hgrm"));
+    assert_eq!(phase_path(Path::new("lat"), "write"), Path::new("lat.write"));
+}
+
+#[test]
+fn permutations() {
+    for len in [1, 2, 3, 10, 1000, 4097] {
+        let permutation = Permutation::new(len, Seed::U64(1), 5, Operation::Write);
+        let mut chunks: Vec<_> = (0..len).map(|position| permutation.get(position)).collect();
+        if len == 1000 {
+            assert_ne!(chunks[..10], (0..10).collect::<Vec<_>>());
+        }
+        chunks.sort();
+        assert_eq!(chunks, (0..len).collect::<Vec<_>>(), "{len}");
+    }
+    let write = Permutation::new(1000, Seed::U64(1), 0, Operation::Write);
+    let read = Permutation::new(1000, Seed::U64(1), 0, Operation::Read);
+    assert_ne!(
+        (0..10).map(|p| write.get(p)).collect::<Vec<_>>(),
+        (0..10).map(|p| read.get(p)).collect::<Vec<_>>()
+    );
+}
@@ -0,0 +1,324 @@
+//! A portable implementation of the BLAKE3 hash, able to hash separate ranges of a stream
+//! in parallel and to combine them afterwards.
+
+const IV: [u32; 8] = [
+    0x6A09E667, 0xBB67AE85, 0x3C6EF372, 0xA54FF53A, 0x510E527F, 0x9B05688C, 0x1F83D9AB, 0x5BE0CD19,
+];
+const MSG_PERMUTATION: [usize; 16] = [2, 6, 3, 10, 7, 0, 4, 13, 1, 11, 12, 5, 9, 14, 15, 8];
+
+const BLOCK_LEN: usize = 64;
+const CHUNK_LEN: usize = 1024;
+
+const CHUNK_START: u32 = 1 << 0;
+const CHUNK_END: u32 = 1 << 1;
+const PARENT: u32 = 1 << 2;
+const ROOT: u32 = 1 << 3;
+
+#[inline(always)]
+fn g(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize, mx: u32, my: u32) {
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(mx);
+    state[d] = (state[d] ^ state[a]).rotate_right(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(12);
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(my);
+    state[d] = (state[d] ^ state[a]).rotate_right(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(7);
+}
+
+#[inline(always)]
+fn round(state: &mut [u32; 16], m: &[u32; 16]) {
+    g(state, 0, 4, 8, 12, m[0], m[1]);
+    g(state, 1, 5, 9, 13, m[2], m[3]);
+    g(state, 2, 6, 10, 14, m[4], m[5]);
+    g(state, 3, 7, 11, 15, m[6], m[7]);
+    g(state, 0, 5, 10, 15, m[8], m[9]);
+    g(state, 1, 6, 11, 12, m[10], m[11]);
+    g(state, 2, 7, 8, 13, m[12], m[13]);
+    g(state, 3, 4, 9, 14, m[14], m[15]);
+}
+
+fn compress(
+    cv: &[u32; 8],
+    block: &[u32; 16],
+    counter: u64,
+    block_len: u32,
+    flags: u32,
+) -> [u32; 16] {
+    let mut state = [
+        cv[0],
+        cv[1],
+        cv[2],
+        cv[3],
+        cv[4],
+        cv[5],
+        cv[6],
+        cv[7],
+        IV[0],
+        IV[1],
+        IV[2],
+        IV[3],
+        counter as u32,
+        (counter >> 32) as u32,
+        block_len,
+        flags,
+    ];
+    let mut m = *block;
+    for i in 0..7 {
+        round(&mut state, &m);
+        if i < 6 {
+            m = MSG_PERMUTATION.map(|p| m[p]);
+        }
+    }
+    for i in 0..8 {
+        state[i] ^= state[i + 8];
+        state[i + 8] ^= cv[i];
+    }
+    state
+}
+
+fn first_8_words(words: [u32; 16]) -> [u32; 8] {
+    words[..8].try_into().unwrap()
+}
+
+fn block_words(block: &[u8]) -> [u32; 16] {
+    let mut words = [0u32; 16];
+    for (word, bytes) in words.iter_mut().zip(block.chunks(4)) {
+        let mut le = [0u8; 4];
+        le[..bytes.len()].copy_from_slice(bytes);
+        *word = u32::from_le_bytes(le);
+    }
+    words
+}
+
+/// The last compression of a node, kept aside until we know whether it's the root
+struct Output {
+    input_cv: [u32; 8],
+    block: [u32; 16],
+    counter: u64,
+    block_len: u32,
+    flags: u32,
+}
+
+impl Output {
+    fn chaining_value(&self) -> [u32; 8] {
+        first_8_words(compress(
+            &self.input_cv,
+            &self.block,
+            self.counter,
+            self.block_len,
+            self.flags,
+        ))
+    }
+
+    fn root_hash(&self) -> [u8; 32] {
+        let words = compress(&self.input_cv, &self.block, 0, self.block_len, self.flags | ROOT);
+        let mut hash = [0u8; 32];
+        for (bytes, word) in hash.chunks_mut(4).zip(words) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+        hash
+    }
+}
+
+fn chunk_output(counter: u64, data: &[u8]) -> Output {
+    let mut cv = IV;
+    let mut flags = CHUNK_START;
+    let mut blocks = data.chunks(BLOCK_LEN).peekable();
+    let mut last = &data[..0];
+    while let Some(block) = blocks.next() {
+        if blocks.peek().is_none() {
+            last = block;
+            break;
+        }
+        cv = first_8_words(compress(&cv, &block_words(block), counter, BLOCK_LEN as u32, flags));
+        flags = 0;
+    }
+    Output {
+        input_cv: cv,
+        block: block_words(last),
+        counter,
+        block_len: last.len() as u32,
+        flags: flags | CHUNK_END,
+    }
+}
+
+fn parent_output(left: &[u32; 8], right: &[u32; 8]) -> Output {
+    let mut block = [0u32; 16];
+    block[..8].copy_from_slice(left);
+    block[8..].copy_from_slice(right);
+    Output { input_cv: IV, block, counter: 0, block_len: BLOCK_LEN as u32, flags: PARENT }
+}
+
+/// A complete subtree of 2^level chunks, starting at chunk `counter`
+#[derive(Clone, Debug)]
+struct Subtree {
+    counter: u64,
+    level: u32,
+    cv: [u32; 8],
+}
+
+/// The BLAKE3 state of a range of the stream
+///
+/// The bytes before the first chunk boundary and the bytes of the last chunk are kept as is,
+/// so the range can later be appended to the state of the range preceding it.
+#[derive(Clone, Debug)]
+pub struct Blake3 {
+    offset: u64,
+    head: Vec<u8>,
+    subtrees: Vec<Subtree>,
+    chunk: Vec<u8>,
+}
+
+impl Blake3 {
+    /// Start hashing the stream at `offset`
+    pub fn new(offset: u64) -> Self {
+        Blake3 {
+            offset,
+            head: Vec::new(),
+            subtrees: Vec::new(),
+            chunk: Vec::with_capacity(CHUNK_LEN),
+        }
+    }
+
+    fn in_head(&self) -> bool {
+        self.subtrees.is_empty()
+            && self.chunk.is_empty()
+            && !self.offset.is_multiple_of(CHUNK_LEN as u64)
+    }
+
+    fn chunk_counter(&self) -> u64 {
+        (self.offset - self.chunk.len() as u64) / CHUNK_LEN as u64
+    }
+
+    fn push(&mut self, subtree: Subtree) {
+        self.subtrees.push(subtree);
+        // merge the last two subtrees as long as they are the two halves of a bigger one
+        while let [.., left, right] = &self.subtrees[..] {
+            if left.level != right.level || !left.counter.is_multiple_of(2u64 << left.level) {
+                break;
+            }
+            let merged = Subtree {
+                counter: left.counter,
+                level: left.level + 1,
+                cv: parent_output(&left.cv, &right.cv).chaining_value(),
+            };
+            self.subtrees.truncate(self.subtrees.len() - 2);
+            self.subtrees.push(merged);
+        }
+    }
+
+    /// Hash the buffered chunk, now that we know it's not the last one
+    fn flush_chunk(&mut self) {
+        if self.chunk.len() == CHUNK_LEN {
+            let counter = self.chunk_counter();
+            let cv = chunk_output(counter, &self.chunk).chaining_value();
+            self.chunk.clear();
+            self.push(Subtree { counter, level: 0, cv });
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        if self.in_head() {
+            let take = (CHUNK_LEN - (self.offset % CHUNK_LEN as u64) as usize).min(data.len());
+            self.head.extend_from_slice(&data[..take]);
+            self.offset += take as u64;
+            data = &data[take..];
+        }
+        while !data.is_empty() {
+            self.flush_chunk();
+            if self.chunk.is_empty() && data.len() > CHUNK_LEN {
+                // hash the whole chunks directly from the input
+                let counter = self.offset / CHUNK_LEN as u64;
+                let cv = chunk_output(counter, &data[..CHUNK_LEN]).chaining_value();
+                self.push(Subtree { counter, level: 0, cv });
+                self.offset += CHUNK_LEN as u64;
+                data = &data[CHUNK_LEN..];
+            } else {
+                let take = (CHUNK_LEN - self.chunk.len()).min(data.len());
+                self.chunk.extend_from_slice(&data[..take]);
+                self.offset += take as u64;
+                data = &data[take..];
+            }
+        }
+    }
+
+    /// Append the state of the range following this one
+    pub fn combine(&mut self, other: &Blake3) {
+        assert_eq!(self.offset, other.offset - other.len(), "ranges must be contiguous");
+        self.update(&other.head);
+        for subtree in &other.subtrees {
+            self.flush_chunk();
+            assert!(self.chunk.is_empty());
+            self.push(subtree.clone());
+            self.offset += (CHUNK_LEN as u64) << subtree.level;
+        }
+        self.update(&other.chunk);
+    }
+
+    /// The number of bytes hashed in this range
+    fn len(&self) -> u64 {
+        let subtrees: u64 = self.subtrees.iter().map(|s| (CHUNK_LEN as u64) << s.level).sum();
+        self.head.len() as u64 + subtrees + self.chunk.len() as u64
+    }
+
+    /// The hash of the stream. The range must start at the beginning of the stream.
+    pub fn finalize(&self) -> [u8; 32] {
+        assert_eq!(self.offset, self.len(), "the range must start at the beginning of the stream");
+        let mut output = chunk_output(self.chunk_counter(), &self.chunk);
+        for subtree in self.subtrees.iter().rev() {
+            output = parent_output(&subtree.cv, &output.chaining_value());
+        }
+        output.root_hash()
+    }
+}
+
+#[cfg(test)]
+fn hex(hash: [u8; 32]) -> String {
+    hash.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[test]
+fn blake3_reference_values() {
+    let data: Vec<u8> = (0..3072u32).map(|i| (i % 251) as u8).collect();
+    for (len, expected) in [
+        (0, "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"),
+        (1, "2d3adedff11b61f14c886e35afa036736dcd87a74d27b5c1510225d0f592e213"),
+        (1024, "42214739f095a406f3fc83deb889744ac00df831c10daa55189b5d121c855af7"),
+        (1025, "d00278ae47eb27b34faecf67b4fe263f82d5412916c1ffd97c8cb7fb814b8444"),
+        (2048, "e776b6028c7cd22a4d0ba182a8bf62205d2ef576467e838ed6f2529b85fba24a"),
+        (3072, "b98cb0ff3623be03326b373de6b9095218513e64f1ee2edd2525c7ad1e5cffd2"),
+    ] {
+        let mut hasher = Blake3::new(0);
+        hasher.update(&data[..len]);
+        assert_eq!(hex(hasher.finalize()), expected, "length {len}");
+    }
+    let mut hasher = Blake3::new(0);
+    hasher.update(b"abc");
+    assert_eq!(
+        hex(hasher.finalize()),
+        "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+    );
+}
+
+#[test]
+fn blake3_combined_ranges_match_sequential_hash() {
+    let data: Vec<u8> = (0..50_000u32).map(|i| (i * 31 % 253) as u8).collect();
+    let mut sequential = Blake3::new(0);
+    sequential.update(&data);
+    for split in [[1, 2, 3], [1000, 1024, 5000], [1024, 2048, 4096], [7, 33_000, 49_999]] {
+        let mut bounds = vec![0];
+        bounds.extend(split);
+        bounds.push(data.len());
+        let mut combined = Blake3::new(0);
+        for range in bounds.windows(2) {
+            let mut hasher = Blake3::new(range[0] as u64);
+            // feed the data in small pieces to exercise the buffering
+            for piece in data[range[0]..range[1]].chunks(700) {
+                hasher.update(piece);
+            }
+            combined.combine(&hasher);
+        }
+        assert_eq!(combined.finalize(), sequential.finalize(), "{split:?}");
+    }
+}
@@ -0,0 +1,121 @@
+//! The page cache control, through posix_fadvise
+//!
+//! Windows and macOS have no equivalent: the advice is ignored, and the page cache can only be
+//! bypassed with `--direct`.
+
+use clap::ValueEnum;
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+use nix::fcntl::{PosixFadviseAdvice, posix_fadvise};
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+
+/// How often the processed data is dropped from the page cache, with `--drop-cache`
+const DROP_CACHE_INTERVAL: u64 = 64 << 20;
+
+/// The access pattern advised to the kernel
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Advice {
+    /// The default readahead
+    Normal,
+    /// A larger readahead
+    Sequential,
+    /// No readahead
+    Random,
+}
+
+/// What is told to the kernel about a range of the file
+#[derive(Clone, Copy, Debug)]
+enum Hint {
+    Access(Advice),
+    /// The data won't be used anymore
+    DontNeed,
+}
+
+/// What to tell the kernel about the use of the page cache
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CachePolicy {
+    pub advice: Option<Advice>,
+    pub drop_cache: bool,
+}
+
+impl CachePolicy {
+    /// Apply the policy to the range of the file processed by a thread
+    pub fn apply(self, file: &File, range: Range<u64>, write: bool) -> io::Result<CacheRange> {
+        let (offset, len) = (range.start, range.end - range.start);
+        let range = CacheRange { policy: self, write, dropped: range.start, end: range.end };
+        if let Some(advice) = self.advice {
+            fadvise(file, offset, len, Hint::Access(advice))?;
+        }
+        if self.drop_cache && !write {
+            // make sure the data is read from the device
+            fadvise(file, offset, len, Hint::DontNeed)?;
+        }
+        Ok(range)
+    }
+}
+
+/// The range of the file processed by a thread
+pub struct CacheRange {
+    policy: CachePolicy,
+    write: bool,
+    /// The end of the data already dropped from the page cache
+    dropped: u64,
+    end: u64,
+}
+
+impl CacheRange {
+    /// Account for the data processed up to `offset`, and drop it from the page cache from time
+    /// to time, so a large stream doesn't evict everything else
+    pub fn processed(&mut self, file: &File, offset: u64) -> io::Result<()> {
+        if self.policy.drop_cache && offset >= self.dropped + DROP_CACHE_INTERVAL {
+            self.drop_until(file, offset)?;
+        }
+        Ok(())
+    }
+
+    /// Drop the rest of the range from the page cache
+    pub fn finish(&mut self, file: &File) -> io::Result<()> {
+        if self.policy.drop_cache {
+            self.drop_until(file, self.end)?;
+        }
+        Ok(())
+    }
+
+    fn drop_until(&mut self, file: &File, offset: u64) -> io::Result<()> {
+        if self.write {
+            // only the clean pages can be dropped
+            file.sync_data()?;
+        }
+        fadvise(file, self.dropped, offset - self.dropped, Hint::DontNeed)?;
+        self.dropped = offset;
+        Ok(())
+    }
+}
+
+/// Flush the range of the file, and drop it from the page cache, so it's read from the device
+pub fn evict(file: &File, range: Range<u64>) -> io::Result<()> {
+    file.sync_data()?;
+    fadvise(file, range.start, range.end - range.start, Hint::DontNeed)
+}
+
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+fn fadvise(file: &File, offset: u64, len: u64, hint: Hint) -> io::Result<()> {
+    if len == 0 {
+        // which would mean up to the end of the file
+        return Ok(());
+    }
+    let advice = match hint {
+        Hint::Access(Advice::Normal) => PosixFadviseAdvice::POSIX_FADV_NORMAL,
+        Hint::Access(Advice::Sequential) => PosixFadviseAdvice::POSIX_FADV_SEQUENTIAL,
+        Hint::Access(Advice::Random) => PosixFadviseAdvice::POSIX_FADV_RANDOM,
+        Hint::DontNeed => PosixFadviseAdvice::POSIX_FADV_DONTNEED,
+    };
+    posix_fadvise(file, offset as i64, len as i64, advice)
+        .map_err(|e| io::Error::from_raw_os_error(e as i32))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "freebsd")))]
+fn fadvise(_file: &File, _offset: u64, _len: u64, _hint: Hint) -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,354 @@
+//! The checkpoint of a long run, to resume it after an interruption
+//!
+//! Each thread processes a contiguous range of chunks. The checkpoint records, for each range, the
+//! chunks done contiguously from its start, and the state of the stream checksum of those chunks,
+//! so the range can be resumed without processing them again.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{self, Write as _};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::checksum::{ChecksumAlgorithm, StreamChecksum};
+use crate::error::usage;
+use crate::rng::Seed;
+use crate::work::ThreadWork;
+
+const MAGIC: &str = "randstream-checkpoint 1";
+
+/// How often the threads update the checkpoint
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the checkpoint is saved
+const SAVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The progress of the range of chunks of a thread
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct RangeState {
+    pub first_chunk: u64,
+    /// The first chunk not done yet
+    pub next_chunk: u64,
+    pub end_chunk: u64,
+    /// The size of the chunks done
+    pub bytes: u64,
+    /// The size of the chunks payloads, covered by the stream checksum
+    pub payload: u64,
+    /// The state of the stream checksum of the chunks done
+    pub checksum: u64,
+}
+
+impl RangeState {
+    fn new(work: &ThreadWork) -> Self {
+        RangeState {
+            first_chunk: work.first_chunk,
+            next_chunk: work.first_chunk,
+            end_chunk: work.end_chunk,
+            bytes: 0,
+            payload: 0,
+            checksum: 0,
+        }
+    }
+
+    /// The chunks left to process
+    pub fn work(&self) -> ThreadWork {
+        ThreadWork { first_chunk: self.next_chunk, end_chunk: self.end_chunk, step: 1 }
+    }
+
+    /// The stream checksum of the chunks done
+    pub fn stream_checksum(&self, algorithm: ChecksumAlgorithm) -> StreamChecksum {
+        if self.next_chunk == self.first_chunk {
+            return algorithm.stream_checksum();
+        }
+        algorithm.resume(self.checksum, self.payload, self.next_chunk - self.first_chunk)
+    }
+}
+
+/// A checkpoint read from its file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct SavedCheckpoint {
+    pub seed: Seed,
+    /// The run ID written in the chunk headers, which must be kept when resuming
+    pub run_id: u64,
+    /// Identifies the stream, so the checkpoint isn't resumed with other parameters
+    pub stream: String,
+    pub ranges: Vec<RangeState>,
+}
+
+impl SavedCheckpoint {
+    /// Read the checkpoint, if the file exists
+    pub fn load(path: &Path) -> anyhow::Result<Option<SavedCheckpoint>> {
+        match fs::read_to_string(path) {
+            Ok(content) => {
+                Ok(Some(Self::decode(&content).ok_or_else(|| {
+                    usage(format!("The checkpoint {} is invalid", path.display()))
+                })?))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn encode(&self) -> String {
+        let mut content = format!(
+            "{MAGIC}\nseed {}\nrun {:016x}\nstream {}\n",
+            self.seed, self.run_id, self.stream
+        );
+        for r in &self.ranges {
+            content.push_str(&format!(
+                "range {} {} {} {} {} {:x}\n",
+                r.first_chunk, r.next_chunk, r.end_chunk, r.bytes, r.payload, r.checksum
+            ));
+        }
+        content
+    }
+
+    fn decode(content: &str) -> Option<SavedCheckpoint> {
+        let mut lines = content.lines();
+        if lines.next()? != MAGIC {
+            return None;
+        }
+        let seed = Seed::parse(lines.next()?.strip_prefix("seed ")?).ok()?;
+        let run_id = u64::from_str_radix(lines.next()?.strip_prefix("run ")?, 16).ok()?;
+        let stream = lines.next()?.strip_prefix("stream ")?.to_string();
+        let ranges = lines
+            .map(|line| {
+                let values: Vec<_> = line.strip_prefix("range ")?.split(' ').collect();
+                let [first_chunk, next_chunk, end_chunk, bytes, payload, checksum] =
+                    values.as_slice()
+                else {
+                    return None;
+                };
+                Some(RangeState {
+                    first_chunk: first_chunk.parse().ok()?,
+                    next_chunk: next_chunk.parse().ok()?,
+                    end_chunk: end_chunk.parse().ok()?,
+                    bytes: bytes.parse().ok()?,
+                    payload: payload.parse().ok()?,
+                    checksum: u64::from_str_radix(checksum, 16).ok()?,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if ranges.is_empty() {
+            return None;
+        }
+        Some(SavedCheckpoint { seed, run_id, stream, ranges })
+    }
+}
+
+/// The checkpoint of the running threads, saved periodically
+pub(crate) struct Checkpoint {
+    path: PathBuf,
+    /// The file written, flushed before saving the checkpoint, so it doesn't record the chunks
+    /// still in the page cache
+    file: Option<File>,
+    saved: Mutex<(SavedCheckpoint, Instant)>,
+}
+
+impl Checkpoint {
+    /// Start a checkpoint, from the saved one if resumed, or else from the work of the threads
+    pub fn new(
+        path: &Path,
+        file: Option<File>,
+        seed: Seed,
+        run_id: u64,
+        stream: String,
+        resumed: Option<SavedCheckpoint>,
+        works: &[ThreadWork],
+    ) -> anyhow::Result<Arc<Checkpoint>> {
+        let saved = match resumed {
+            Some(saved)
+                if saved.stream != stream || saved.seed != seed || saved.run_id != run_id =>
+            {
+                return Err(usage(format!(
+                    "The checkpoint {} was saved for another stream",
+                    path.display()
+                )));
+            }
+            Some(saved) => saved,
+            None => SavedCheckpoint {
+                seed,
+                run_id,
+                stream,
+                ranges: works.iter().map(RangeState::new).collect(),
+            },
+        };
+        Ok(Arc::new(Checkpoint {
+            path: path.to_path_buf(),
+            file,
+            saved: Mutex::new((saved, Instant::now())),
+        }))
+    }
+
+    pub fn ranges(&self) -> Vec<RangeState> {
+        self.saved.lock().unwrap().0.ranges.clone()
+    }
+
+    /// The tracker of the chunks done by the thread processing the range `index`
+    pub fn tracker(self: &Arc<Self>, index: usize) -> CheckpointTracker {
+        let range = self.saved.lock().unwrap().0.ranges[index].clone();
+        CheckpointTracker {
+            checkpoint: self.clone(),
+            index,
+            next_recorded: range.next_chunk,
+            range,
+            recorded: BTreeMap::new(),
+            done: BTreeSet::new(),
+            last_update: Instant::now(),
+        }
+    }
+
+    /// Update the range of a thread, and save the checkpoint if it's time to
+    fn update(&self, index: usize, range: &RangeState, save: bool) -> io::Result<()> {
+        let mut saved = self.saved.lock().unwrap();
+        saved.0.ranges[index] = range.clone();
+        if save && saved.1.elapsed() >= SAVE_INTERVAL {
+            saved.1 = Instant::now();
+            self.write(&saved.0)?;
+        }
+        Ok(())
+    }
+
+    /// Save the checkpoint now
+    pub fn save(&self) -> io::Result<()> {
+        let saved = self.saved.lock().unwrap();
+        self.write(&saved.0)
+    }
+
+    fn write(&self, saved: &SavedCheckpoint) -> io::Result<()> {
+        if let Some(file) = &self.file {
+            file.sync_data()?;
+        }
+        // replace the previous checkpoint atomically
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let mut f = File::create(&tmp)?;
+        f.write_all(saved.encode().as_bytes())?;
+        f.sync_all()?;
+        fs::rename(&tmp, &self.path)
+    }
+
+    /// Remove the checkpoint, once the run is complete
+    pub fn remove(&self) -> io::Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Tracks the chunks done by a thread, which may be recorded before being done, and done out of
+/// order
+pub(crate) struct CheckpointTracker {
+    checkpoint: Arc<Checkpoint>,
+    index: usize,
+    range: RangeState,
+    next_recorded: u64,
+    /// The state of the range after each chunk recorded, but not done yet
+    recorded: BTreeMap<u64, (u64, u64, u64)>,
+    /// The chunks done, after a chunk not done yet
+    done: BTreeSet<u64>,
+    last_update: Instant,
+}
+
+impl CheckpointTracker {
+    pub fn range(&self) -> &RangeState {
+        &self.range
+    }
+
+    /// Record the next chunk of the range, just added to the stream checksum
+    pub fn recorded(&mut self, size: usize, checksum: &StreamChecksum) {
+        let (bytes, payload) = self
+            .recorded
+            .last_key_value()
+            .map(|(_, (bytes, payload, _))| (*bytes, *payload))
+            .unwrap_or((self.range.bytes, self.range.payload));
+        let width = checksum.algorithm().width();
+        let chunk_payload = if size >= width { size - width } else { size };
+        self.recorded.insert(
+            self.next_recorded,
+            (bytes + size as u64, payload + chunk_payload as u64, checksum.state()),
+        );
+        self.next_recorded += 1;
+    }
+
+    /// Mark a recorded chunk as done: written to the file, or validated
+    pub fn done(&mut self, chunk: u64) -> io::Result<()> {
+        self.done.insert(chunk);
+        while self.done.remove(&self.range.next_chunk) {
+            let (bytes, payload, checksum) =
+                self.recorded.remove(&self.range.next_chunk).expect("a recorded chunk");
+            self.range = RangeState {
+                next_chunk: self.range.next_chunk + 1,
+                bytes,
+                payload,
+                checksum,
+                ..self.range
+            };
+        }
+        if self.last_update.elapsed() >= UPDATE_INTERVAL {
+            self.last_update = Instant::now();
+            self.checkpoint.update(self.index, &self.range, true)?;
+        }
+        Ok(())
+    }
+
+    /// Update the checkpoint with the last chunks done, to be saved by the main thread
+    pub fn finish(self) {
+        // can't fail without saving
+        let _ = self.checkpoint.update(self.index, &self.range, false);
+    }
+}
+
+#[test]
+fn checkpoint_round_trip() {
+    let saved = SavedCheckpoint {
+        seed: Seed::U64(42),
+        run_id: 0xabc,
+        stream: "generate size=1000".to_string(),
+        ranges: vec![
+            RangeState {
+                first_chunk: 0,
+                next_chunk: 3,
+                end_chunk: 5,
+                bytes: 300,
+                payload: 288,
+                checksum: 0xabcd,
+            },
+            RangeState::new(&ThreadWork { first_chunk: 5, end_chunk: 10, step: 1 }),
+        ],
+    };
+    assert_eq!(SavedCheckpoint::decode(&saved.encode()), Some(saved));
+    assert_eq!(SavedCheckpoint::decode("randstream-checkpoint 1\nseed 0\nrun 0\n"), None);
+    assert_eq!(SavedCheckpoint::decode(""), None);
+}
+
+#[test]
+fn tracker_follows_the_contiguous_chunks_done() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let works = [ThreadWork { first_chunk: 10, end_chunk: 20, step: 1 }];
+    let path = dir.path().join("checkpoint");
+    let checkpoint =
+        Checkpoint::new(&path, None, Seed::U64(0), 0, String::new(), None, &works).unwrap();
+    let mut tracker = checkpoint.tracker(0);
+    let mut checksum = ChecksumAlgorithm::Crc32.stream_checksum();
+    for chunk in 0..3 {
+        crate::ChunkChecksum::update(&mut checksum, &[chunk; 96]);
+        tracker.recorded(100, &checksum);
+    }
+    tracker.done(11).unwrap();
+    assert_eq!(tracker.range().next_chunk, 10);
+    tracker.done(10).unwrap();
+    assert_eq!(tracker.range().next_chunk, 12);
+    assert_eq!(tracker.range().bytes, 200);
+    tracker.done(12).unwrap();
+    assert_eq!(tracker.range().payload, 288);
+    assert_eq!(tracker.range().checksum, checksum.state());
+    tracker.finish();
+    checkpoint.save().unwrap();
+    let saved = SavedCheckpoint::load(&path).unwrap().unwrap();
+    assert_eq!(saved.ranges[0].work().first_chunk, 13);
+    checkpoint.remove().unwrap();
+    assert_eq!(SavedCheckpoint::load(&path).unwrap(), None);
+}
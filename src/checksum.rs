@@ -0,0 +1,218 @@
+use clap::ValueEnum;
+use crc32fast::Hasher;
+
+use crate::ChunkChecksum;
+use crate::crc64::Crc64;
+use crate::xxh3::xxh3_64;
+
+/// The algorithm used to seal each chunk
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    /// CRC32, stored on 4 bytes
+    #[default]
+    Crc32,
+    /// CRC-64/NVME, stored on 8 bytes
+    Crc64,
+    /// XXH3 64 bits, stored on 8 bytes
+    Xxh3,
+}
+
+impl ChecksumAlgorithm {
+    /// The number of bytes used by the checksum at the end of each chunk
+    pub fn width(self) -> usize {
+        match self {
+            ChecksumAlgorithm::Crc32 => 4,
+            ChecksumAlgorithm::Crc64 | ChecksumAlgorithm::Xxh3 => 8,
+        }
+    }
+
+    /// Create an empty stream checksum for this algorithm
+    pub fn stream_checksum(self) -> StreamChecksum {
+        match self {
+            ChecksumAlgorithm::Crc32 => {
+                StreamChecksum::Crc32 { stream: Hasher::new(), chunk: Hasher::new() }
+            }
+            ChecksumAlgorithm::Crc64 => {
+                StreamChecksum::Crc64 { stream: Crc64::new(), chunk: Crc64::new() }
+            }
+            ChecksumAlgorithm::Xxh3 => StreamChecksum::Xxh3 { hash: 0, chunks: 0 },
+        }
+    }
+
+    /// Resume a stream checksum from its state, after `payload` bytes in `chunks` chunks
+    pub fn resume(self, state: u64, payload: u64, chunks: u64) -> StreamChecksum {
+        match self {
+            ChecksumAlgorithm::Crc32 => StreamChecksum::Crc32 {
+                stream: Hasher::new_with_initial_len(state as u32, payload),
+                chunk: Hasher::new(),
+            },
+            ChecksumAlgorithm::Crc64 => StreamChecksum::Crc64 {
+                stream: Crc64::new_with_initial_len(state, payload),
+                chunk: Crc64::new(),
+            },
+            ChecksumAlgorithm::Xxh3 => StreamChecksum::Xxh3 { hash: state, chunks },
+        }
+    }
+
+    /// Format a checksum value produced by this algorithm
+    pub fn format(self, checksum: u64) -> String {
+        format!("{checksum:0width$x}", width = self.width() * 2)
+    }
+}
+
+/// Accumulates the checksum of the whole stream, chunk after chunk
+///
+/// With CRC32 and CRC-64, the stream checksum is the CRC of all the chunk payloads. XXH3 can't be
+/// combined, so the stream checksum is computed from the sequence of the chunk checksums.
+#[derive(Clone, Debug)]
+pub enum StreamChecksum {
+    Crc32 { stream: Hasher, chunk: Hasher },
+    Crc64 { stream: Crc64, chunk: Crc64 },
+    Xxh3 { hash: u64, chunks: u64 },
+}
+
+/// Multiplier of the polynomial accumulation of the XXH3 chunk checksums
+const XXH3_STREAM_MULTIPLIER: u64 = 0x9E3779B185EBCA87;
+
+impl StreamChecksum {
+    pub fn algorithm(&self) -> ChecksumAlgorithm {
+        match self {
+            StreamChecksum::Crc32 { .. } => ChecksumAlgorithm::Crc32,
+            StreamChecksum::Crc64 { .. } => ChecksumAlgorithm::Crc64,
+            StreamChecksum::Xxh3 { .. } => ChecksumAlgorithm::Xxh3,
+        }
+    }
+
+    /// The state of the checksum, to resume it later with `ChecksumAlgorithm::resume()`
+    pub fn state(&self) -> u64 {
+        match self {
+            StreamChecksum::Crc32 { stream, .. } => stream.clone().finalize() as u64,
+            StreamChecksum::Crc64 { stream, .. } => stream.finalize(),
+            StreamChecksum::Xxh3 { hash, .. } => *hash,
+        }
+    }
+}
+
+impl ChunkChecksum for StreamChecksum {
+    fn width(&self) -> usize {
+        self.algorithm().width()
+    }
+
+    fn update(&mut self, payload: &[u8]) -> u64 {
+        match self {
+            StreamChecksum::Crc32 { stream, chunk } => {
+                chunk.reset();
+                chunk.update(payload);
+                stream.combine(chunk);
+                chunk.clone().finalize() as u64
+            }
+            StreamChecksum::Crc64 { stream, chunk } => {
+                chunk.reset();
+                chunk.update(payload);
+                stream.combine(chunk);
+                chunk.finalize()
+            }
+            StreamChecksum::Xxh3 { hash, chunks } => {
+                let checksum = xxh3_64(payload);
+                *hash = hash.wrapping_mul(XXH3_STREAM_MULTIPLIER).wrapping_add(checksum);
+                *chunks += 1;
+                checksum
+            }
+        }
+    }
+
+    fn combine(&mut self, other: &Self) {
+        match (self, other) {
+            (StreamChecksum::Crc32 { stream, .. }, StreamChecksum::Crc32 { stream: other, .. }) => {
+                stream.combine(other)
+            }
+            (StreamChecksum::Crc64 { stream, .. }, StreamChecksum::Crc64 { stream: other, .. }) => {
+                stream.combine(other)
+            }
+            (
+                StreamChecksum::Xxh3 { hash, chunks },
+                StreamChecksum::Xxh3 { hash: other_hash, chunks: other_chunks },
+            ) => {
+                let multiplier = wrapping_pow(XXH3_STREAM_MULTIPLIER, *other_chunks);
+                *hash = hash.wrapping_mul(multiplier).wrapping_add(*other_hash);
+                *chunks += other_chunks;
+            }
+            _ => panic!("can't combine checksums computed with different algorithms"),
+        }
+    }
+
+    fn finalize(&self) -> u64 {
+        match self {
+            StreamChecksum::Crc32 { stream, .. } => stream.clone().finalize() as u64,
+            StreamChecksum::Crc64 { stream, .. } => stream.finalize(),
+            StreamChecksum::Xxh3 { hash, chunks } => {
+                let mut data = [0u8; 16];
+                data[..8].copy_from_slice(&hash.to_le_bytes());
+                data[8..].copy_from_slice(&chunks.to_le_bytes());
+                xxh3_64(&data)
+            }
+        }
+    }
+}
+
+fn wrapping_pow(mut base: u64, mut exponent: u64) -> u64 {
+    let mut result: u64 = 1;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = result.wrapping_mul(base);
+        }
+        base = base.wrapping_mul(base);
+        exponent >>= 1;
+    }
+    result
+}
+
+#[test]
+fn combined_checksum_matches_sequential_checksum() {
+    let data: Vec<u8> = (0..10_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    for algorithm in ChecksumAlgorithm::value_variants() {
+        let mut sequential = algorithm.stream_checksum();
+        for chunk in data.chunks(1000) {
+            sequential.update(chunk);
+        }
+        let mut first = algorithm.stream_checksum();
+        let mut second = algorithm.stream_checksum();
+        for (i, chunk) in data.chunks(1000).enumerate() {
+            if i < 3 {
+                first.update(chunk)
+            } else {
+                second.update(chunk)
+            };
+        }
+        first.combine(&second);
+        assert_eq!(first.finalize(), sequential.finalize(), "{algorithm:?}");
+    }
+}
+
+#[test]
+fn resumed_checksum_matches_sequential_checksum() {
+    let data: Vec<u8> = (0..10_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    for algorithm in ChecksumAlgorithm::value_variants() {
+        let mut sequential = algorithm.stream_checksum();
+        for chunk in data.chunks(1000) {
+            sequential.update(chunk);
+        }
+        let mut checksum = algorithm.stream_checksum();
+        for chunk in data.chunks(1000).take(3) {
+            checksum.update(chunk);
+        }
+        let mut resumed = algorithm.resume(checksum.state(), 3000, 3);
+        for chunk in data.chunks(1000).skip(3) {
+            resumed.update(chunk);
+        }
+        assert_eq!(resumed.finalize(), sequential.finalize(), "{algorithm:?}");
+        // the resumed checksum can be combined too
+        let mut first = algorithm.resume(checksum.state(), 3000, 3);
+        let mut second = algorithm.stream_checksum();
+        for chunk in data.chunks(1000).skip(3) {
+            second.update(chunk);
+        }
+        first.combine(&second);
+        assert_eq!(first.finalize(), sequential.finalize(), "{algorithm:?}");
+    }
+}
@@ -0,0 +1,153 @@
+//! The header of the chunks, with `--format v2`
+//!
+//! It's written at the start of each chunk, over the random data, so it's sealed with the rest of
+//! the chunk by its checksum. The chunks too short to hold the header and the checksum don't have
+//! one.
+
+use clap::ValueEnum;
+
+use crate::rng::splitmix64;
+
+pub const CHUNK_HEADER_SIZE: usize = 40;
+
+const MAGIC: &[u8; 8] = b"RSCHUNK2";
+
+/// The layout of the chunks
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChunkFormat {
+    /// Random data, sealed by a checksum
+    #[default]
+    V1,
+    /// A header identifying the chunk, then random data, sealed by a checksum
+    V2,
+}
+
+impl ChunkFormat {
+    pub fn id(self) -> u8 {
+        match self {
+            ChunkFormat::V1 => 0,
+            ChunkFormat::V2 => 1,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::value_variants().iter().copied().find(|format| format.id() == id)
+    }
+}
+
+/// Parse a run ID, as logged by generate: up to 16 hexadecimal digits
+pub fn parse_run_id(s: &str) -> Result<u64, String> {
+    let hex = s.strip_prefix("0x").unwrap_or(s);
+    u64::from_str_radix(hex, 16).map_err(|e| e.to_string())
+}
+
+/// The fingerprint recorded in the chunk headers of a region of the stream, with `--region-size`
+///
+/// It's derived from the fingerprint of the seed of the stream, and the other way around: the
+/// first region has the fingerprint of the stream.
+pub fn region_fingerprint(fingerprint: u64, region: u64) -> u64 {
+    if region == 0 {
+        return fingerprint;
+    }
+    let mut state = region;
+    fingerprint ^ splitmix64(&mut state)
+}
+
+/// The header layout:
+///
+/// | offset | size | content                          |
+/// |--------|------|----------------------------------|
+/// | 0      | 8    | `RSCHUNK2`                       |
+/// | 8      | 8    | chunk index, in little endian    |
+/// | 16     | 8    | seed fingerprint                 |
+/// | 24     | 8    | chunk length, in little endian   |
+/// | 32     | 8    | run ID                           |
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkHeader {
+    /// The index of the chunk in the stream
+    pub index: u64,
+    /// Identifies the seed of the stream, see `Seed::fingerprint()`
+    pub fingerprint: u64,
+    /// The size of the whole chunk, checksum included
+    pub length: u64,
+    /// Identifies the run which wrote the chunk, to tell it from the data left by a previous run
+    /// with the same seed
+    pub run_id: u64,
+}
+
+impl ChunkHeader {
+    /// Whether a chunk of that size holds a header, along with a checksum of `width` bytes
+    pub fn fits(length: usize, width: usize) -> bool {
+        length >= CHUNK_HEADER_SIZE + width
+    }
+
+    /// The header of the chunk coming `chunks` chunks after this one, of the given length
+    ///
+    /// With regions of `region_chunks` chunks, the fingerprint is the one of the region of the
+    /// chunk.
+    pub fn following(&self, chunks: u64, length: u64, region_chunks: Option<u64>) -> ChunkHeader {
+        let index = self.index + chunks;
+        let fingerprint = match region_chunks {
+            Some(region_chunks) if index / region_chunks != self.index / region_chunks => {
+                // back to the fingerprint of the stream, then to the one of the other region
+                let stream = region_fingerprint(self.fingerprint, self.index / region_chunks);
+                region_fingerprint(stream, index / region_chunks)
+            }
+            _ => self.fingerprint,
+        };
+        ChunkHeader { index, fingerprint, length, ..*self }
+    }
+
+    pub fn encode(&self) -> [u8; CHUNK_HEADER_SIZE] {
+        let mut header = [0u8; CHUNK_HEADER_SIZE];
+        header[..8].copy_from_slice(MAGIC);
+        header[8..16].copy_from_slice(&self.index.to_le_bytes());
+        header[16..24].copy_from_slice(&self.fingerprint.to_le_bytes());
+        header[24..32].copy_from_slice(&self.length.to_le_bytes());
+        header[32..].copy_from_slice(&self.run_id.to_le_bytes());
+        header
+    }
+
+    /// Read the header at the start of the chunk, if any
+    pub fn decode(data: &[u8]) -> Option<ChunkHeader> {
+        let header = data.get(..CHUNK_HEADER_SIZE)?;
+        if &header[..8] != MAGIC {
+            return None;
+        }
+        let field =
+            |range: std::ops::Range<usize>| u64::from_le_bytes(header[range].try_into().unwrap());
+        Some(ChunkHeader {
+            index: field(8..16),
+            fingerprint: field(16..24),
+            length: field(24..32),
+            run_id: field(32..40),
+        })
+    }
+}
+
+#[test]
+fn chunk_header_round_trip() {
+    let header =
+        ChunkHeader { index: 12, fingerprint: 0x0123_4567_89ab_cdef, length: 32768, run_id: 7 };
+    let mut data = header.encode().to_vec();
+    data.extend_from_slice(b"random data");
+    assert_eq!(ChunkHeader::decode(&data), Some(header));
+    data[0] ^= 1;
+    assert_eq!(ChunkHeader::decode(&data), None);
+    assert_eq!(ChunkHeader::decode(MAGIC), None);
+    let next = header.following(4, 100, Some(16));
+    assert_eq!(
+        (next.index, next.fingerprint, next.length),
+        (16, region_fingerprint(0x0123_4567_89ab_cdef, 1), 100)
+    );
+    assert_eq!(
+        next.following(40, 100, Some(16)).fingerprint,
+        region_fingerprint(header.fingerprint, 3)
+    );
+    assert_eq!(header.following(3, 100, Some(16)).fingerprint, header.fingerprint);
+    assert!(ChunkHeader::fits(44, 4));
+    assert!(!ChunkHeader::fits(47, 8));
+    assert_eq!(ChunkFormat::from_id(ChunkFormat::V2.id()), Some(ChunkFormat::V2));
+    assert_eq!(parse_run_id("0x00000000000000ff"), Ok(255));
+    assert!(parse_run_id("run").is_err());
+}
@@ -1,8 +1,31 @@
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueHint};
 use clap_verbosity_flag::{InfoLevel, Verbosity};
+use log::info;
 use parse_size::parse_size;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use crate::{generate::GenerateArgs, validate::ValidateArgs};
+use crate::affinity::{Affinity, parse_affinity};
+use crate::cache::{Advice, CachePolicy};
+use crate::checksum::ChecksumAlgorithm;
+use crate::chunk::{CHUNK_HEADER_SIZE, ChunkFormat, ChunkHeader};
+use crate::compress::{Compressibility, parse_compress_ratio};
+use crate::digest::DigestAlgorithm;
+use crate::engine::IoEngine;
+use crate::error::usage;
+use crate::journal::Journal;
+use crate::pattern::Pattern;
+use crate::report::OutputFormat;
+use crate::rng::RngAlgorithm;
+use crate::rotational;
+use crate::sector::parse_sector_size;
+use crate::throttle::Throttle;
+use crate::{ProgressCallback, ProgressFormat};
+use crate::{
+    bench::BenchArgs, completions::CompletionsArgs, corrupt::CorruptArgs, discard::DiscardTestArgs,
+    generate::GenerateArgs, perf::PerfArgs, repair::RepairArgs, schedule::ScrubArgs,
+    serve::ServeArgs, validate::ValidateArgs, verify::VerifyArgs,
+};
 
 /// This utility creates and validate a random stream of data with built-in validation.
 ///
@@ -17,35 +40,426 @@ pub struct Cli {
 
     #[command(flatten)]
     pub verbose: Verbosity<InfoLevel>,
+
+    /// The configuration file supplying the default options
+    ///
+    /// Defaults to `randstream/randstream.toml` in `$XDG_CONFIG_HOME` or `~/.config`, then in
+    /// `$XDG_CONFIG_DIRS` or `/etc/xdg`. The options given on the command line take precedence.
+    #[clap(long, global = true, value_name = "FILE", env = "RANDSTREAM_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Don't read any configuration file
+    #[clap(long, global = true, conflicts_with = "config")]
+    pub no_config: bool,
 }
 
-#[derive(Args, Debug)]
+#[derive(Args, Clone, Debug)]
 pub struct CommonArgs {
     /// The stream size
     ///
     /// Defaults to the provided file size
-    #[clap(short, long, value_parser=|s: &str| parse_size(s))]
+    #[clap(short, long, value_name = "SIZE", value_hint = ValueHint::Other, value_parser=|s: &str| parse_size(s))]
     pub size: Option<u64>,
 
     /// The number of parallel jobs
     ///
-    /// Defaults to the number of physical cores on the host
-    #[clap(short, long)]
+    /// Defaults to the number of physical cores on the host, or to 1 for a file or device on a
+    /// rotational device, read or written sequentially
+    #[clap(short, long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
     pub jobs: Option<usize>,
 
     /// The chunk size
-    #[clap(short, long, default_value = "32ki", value_parser=|s: &str| parse_size(s))]
+    ///
+    /// With validate, `auto` detects it at the start of the file: it's read from the header of the
+    /// first chunk with `--format v2`, or else it's the first of the usual sizes, the powers of two
+    /// from 512 to 16Mi, whose chunks match their checksum.
+    #[clap(short, long, default_value = "32ki", value_name = "SIZE", value_hint = ValueHint::Other, value_parser = parse_chunk_size)]
     pub chunk_size: u64,
 
+    /// The checksum algorithm used to seal each chunk
+    #[clap(long, alias = "chunk-checksum", value_enum, default_value_t)]
+    pub checksum: ChecksumAlgorithm,
+
+    /// The version of the layout of the chunks
+    ///
+    /// With v2, each chunk starts with a header holding its index, the fingerprint of the seed and
+    /// its length, so the validation tells the chunks written at the wrong place, or coming from
+    /// another stream, from the corrupted ones. The validation detects the chunk headers, so it
+    /// only needs it to require them.
+    #[clap(long, visible_alias = "format-version", value_enum, default_value_t)]
+    pub format: ChunkFormat,
+
+    /// The ratio the generated data compresses to, like `2.5`, for the storage compressing it
+    ///
+    /// Each 4 KiB block starts with random data and ends with zeros, in the proportion giving the
+    /// ratio. The chunks are still sealed by their checksum. Defaults to 1, incompressible.
+    #[clap(long, value_parser = parse_compress_ratio)]
+    pub compress_ratio: Option<f64>,
+
+    /// The number of consecutive 4 KiB blocks sharing the same data, for the storage deduplicating
+    /// it
+    ///
+    /// The chunk headers and checksums stay unique, so the blocks holding them aren't
+    /// deduplicated. The chunk size must be a multiple of 4 KiB.
+    #[clap(long, value_parser = clap::value_parser!(u16).range(1..))]
+    pub dedupe_ratio: Option<u16>,
+
+    /// Write a classic test pattern instead of random data, still sealed by the chunk checksums
+    ///
+    /// The pattern depends only on the offset in the stream. The validation doesn't need it,
+    /// unless with `--regenerate`.
+    #[clap(long, value_enum, conflicts_with_all = ["compress_ratio", "dedupe_ratio"])]
+    pub pattern: Option<Pattern>,
+
+    /// Also compute a digest of the whole stream
+    #[clap(long, value_enum)]
+    pub digest: Option<DigestAlgorithm>,
+
+    /// The random generator
+    ///
+    /// The validation doesn't depend on it, unless with `--regenerate`: the chunks are validated
+    /// with their checksum
+    #[clap(long, value_enum, default_value_t)]
+    pub rng: RngAlgorithm,
+
+    /// Divide the stream in regions of that size, each one generated with its own seed
+    ///
+    /// The seed of each region is derived from the seed, the first region using the seed itself,
+    /// so a region can be regenerated on its own. With `--format v2`, the chunk headers of each
+    /// region record the fingerprint of its seed. The size must be a multiple of the chunk size.
+    #[clap(long, value_name = "SIZE", value_hint = ValueHint::Other, value_parser=|s: &str| parse_size(s))]
+    pub region_size: Option<u64>,
+
+    /// Also seal each sector of that size, 512 or 4Ki, with a CRC32 at its end
+    ///
+    /// The validation reports the damaged sectors of the chunks not matching their checksum, and
+    /// `--badblocks-out` lists their blocks only. The chunk size must be a multiple of the sector
+    /// size. The validation needs it too, to locate the damaged sectors or regenerate the data.
+    #[clap(long, value_name = "SIZE", value_hint = ValueHint::Other, value_parser = parse_sector_size)]
+    pub sector_checksums: Option<usize>,
+
+    /// The I/O engine used to read or write the file
+    #[clap(long, value_enum, default_value_t)]
+    pub engine: IoEngine,
+
+    /// The number of reads or writes in flight per thread, with the io-uring engine
+    #[clap(long, alias = "iodepth", default_value = "32", value_parser = clap::value_parser!(u32).range(1..=4096))]
+    pub queue_depth: u32,
+
+    /// The number of consecutive chunks written or read with a single system call, with the sync
+    /// engine
+    #[clap(long, default_value = "1", value_parser = clap::value_parser!(u32).range(1..=1024))]
+    pub batch_chunks: u32,
+
+    /// Open the file with O_DIRECT, to bypass the page cache
+    ///
+    /// The chunk size is rounded up to the logical block size of the file, and the stream
+    /// position must be a multiple of it.
+    #[clap(long, requires = "file")]
+    pub direct: bool,
+
+    /// Drop the data from the page cache, after writing it or before reading it
+    ///
+    /// The data is also dropped while being processed, so a large stream doesn't evict the
+    /// whole page cache.
+    #[clap(long, requires = "file")]
+    pub drop_cache: bool,
+
+    /// The access pattern advised to the kernel for the file
+    #[clap(long, value_enum, requires = "file")]
+    pub advise: Option<Advice>,
+
+    /// Pin the threads to these CPUs, like `0-7,16-23`, each thread to one of them in turn
+    ///
+    /// Only on Linux.
+    #[clap(long, value_name = "CPUS", value_parser = parse_affinity)]
+    pub cpu_affinity: Option<Affinity>,
+
+    /// Pin the threads to the CPUs of the NUMA node closest to the device, the one of its PCIe
+    /// root
+    ///
+    /// The buffers of the threads are then allocated in the memory of that node, so the data
+    /// doesn't cross the link between the sockets. The threads aren't pinned if the device has no
+    /// NUMA node. Only on Linux.
+    #[clap(long, requires = "file", conflicts_with = "cpu_affinity")]
+    pub numa_local: bool,
+
+    /// The maximum throughput, in bytes per second, shared by all the threads
+    #[clap(long, value_parser = parse_bandwidth)]
+    pub bwlimit: Option<u64>,
+
+    /// Stop the run cleanly after this wall time, like `90m` or `2h`
+    ///
+    /// The threads take no more chunks once it has elapsed, and finish the ones they hold: the
+    /// chunks done are the start of the stream, whose size and checksum are reported. A file
+    /// generated is truncated to them, and they can be validated with `--size`. Only for a local
+    /// file or device, read or written from the queue of chunks shared by the threads.
+    #[clap(long, value_name = "DURATION", value_parser = parse_duration, requires = "file")]
+    pub duration: Option<Duration>,
+
+    /// Append a line to this file for each chunk written or read, with its offset, size, start and
+    /// end times, result and checksum
+    ///
+    /// Each line is written once the operation has completed, so the journal tells what had been
+    /// done when a run stops abruptly. Only with the sync engine.
+    #[clap(long, requires = "file")]
+    pub journal: Option<PathBuf>,
+
+    /// Warn when the slowest thread takes more than this percentage longer than the mean of the
+    /// threads
+    ///
+    /// Each thread processes its own chunks of the stream, so a slow region makes the whole run
+    /// take the time of its thread. The bytes, duration and throughput of each thread are logged
+    /// with `-v`, and listed in the JSON report. The imbalance of a run shorter than a second is
+    /// only logged, without a warning.
+    #[clap(long, default_value = "50", value_name = "PERCENT", value_parser = parse_imbalance_threshold)]
+    pub imbalance_threshold: f64,
+
+    /// The format of the result, printed at the end of the run
+    #[clap(long, value_enum, default_value_t)]
+    pub output: OutputFormat,
+
+    /// Print the checksum of the stream alone on stdout, once the run has succeeded
+    ///
+    /// For the scripts, with `--quiet`: `expected=$(randstream generate -q --print-checksum ...)`
+    #[clap(long, conflicts_with = "output")]
+    pub print_checksum: bool,
+
+    /// How the progress is reported
+    ///
+    /// With `json`, a JSON object per line is printed on stderr every second, with the number of
+    /// bytes done, the total, the rate in bytes per second, and the estimated remaining time.
+    #[clap(long, value_enum, default_value_t)]
+    pub progress: ProgressFormat,
+
     /// Hide the progress bar
     #[clap(short = 'P', long)]
     pub no_progress: bool,
+
+    /// The device labeling the progress, when several are processed at once
+    #[clap(skip)]
+    pub device: Option<String>,
+
+    /// The callback receiving the progress, instead of the progress bar, in the library API
+    #[clap(skip)]
+    pub progress_callback: Option<ProgressCallback>,
+}
+
+impl CommonArgs {
+    pub fn cache_policy(&self) -> CachePolicy {
+        CachePolicy { advice: self.advise, drop_cache: self.drop_cache }
+    }
+
+    /// The compressibility of the data, with `--compress-ratio`
+    pub fn compressibility(&self) -> Option<Compressibility> {
+        self.compress_ratio.and_then(Compressibility::new)
+    }
+
+    /// The number of chunks of each region, with `--region-size`
+    pub(crate) fn region_chunks(&self, chunk_size: usize) -> anyhow::Result<Option<u64>> {
+        match self.region_size {
+            Some(size) if size == 0 || !size.is_multiple_of(chunk_size as u64) => Err(usage(
+                format!("The region size must be a multiple of the chunk size, {chunk_size}"),
+            )),
+            Some(size) if size / chunk_size as u64 > u32::MAX as u64 => {
+                Err(usage("The region size is too large for the chunk size"))
+            }
+            Some(size) => Ok(Some(size / chunk_size as u64)),
+            None => Ok(None),
+        }
+    }
+
+    /// The size of the sectors sealed by their own checksum, with `--sector-checksums`
+    pub(crate) fn sector_size(&self, chunk_size: usize) -> anyhow::Result<Option<usize>> {
+        match self.sector_checksums {
+            Some(size) if !chunk_size.is_multiple_of(size) => {
+                Err(usage(format!("The chunk size must be a multiple of the sector size, {size}")))
+            }
+            size => Ok(size),
+        }
+    }
+
+    /// Check the chunks hold a header with `--format v2`
+    ///
+    /// Only the short chunk at the end of the stream may lack it.
+    pub(crate) fn check_format(&self) -> anyhow::Result<()> {
+        let width = self.checksum.width();
+        match self.format {
+            ChunkFormat::V2
+                if self.chunk_size != AUTO_CHUNK_SIZE
+                    && !ChunkHeader::fits(self.chunk_size as usize, width) =>
+            {
+                Err(usage(format!(
+                    "--format v2 requires a chunk size of at least {} bytes, to hold the chunk \
+                     header and checksum",
+                    CHUNK_HEADER_SIZE + width
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The CPUs the threads are pinned to, with `--cpu-affinity` or `--numa-local`
+    ///
+    /// `file` is the local file or device read or written, if any.
+    pub(crate) fn affinity(&self, file: Option<&Path>) -> anyhow::Result<Affinity> {
+        match (&self.cpu_affinity, file) {
+            (Some(affinity), _) => {
+                affinity.check()?;
+                Ok(affinity.clone())
+            }
+            (None, Some(file)) if self.numa_local => Affinity::numa_local(file),
+            (None, None) if self.numa_local => {
+                Err(usage("--numa-local requires a local file or device"))
+            }
+            (None, _) => Ok(Affinity::default()),
+        }
+    }
+
+    /// The number of threads reading or writing the local file or device
+    ///
+    /// A single thread processes the stream in order on a rotational device, unless `--jobs` is
+    /// given.
+    pub(crate) fn num_threads(&self, file: &Path) -> usize {
+        match self.jobs {
+            Some(jobs) => jobs,
+            None if rotational::is_rotational(file) => {
+                info!(
+                    "{} is on a rotational device, processed by a single thread: use --jobs to \
+                     force more",
+                    file.display()
+                );
+                1
+            }
+            None => num_cpus::get_physical(),
+        }
+    }
+
+    pub fn throttle(&self) -> Option<Throttle> {
+        self.bwlimit.map(Throttle::new)
+    }
+
+    /// The journal of the chunk operations, with `--journal`
+    pub(crate) fn journal(&self) -> anyhow::Result<Option<Journal>> {
+        let Some(path) = &self.journal else {
+            return Ok(None);
+        };
+        if self.engine != IoEngine::Sync {
+            return Err(usage("--journal requires the sync engine"));
+        }
+        Ok(Some(Journal::open(path, self.checksum.width())?))
+    }
+
+    /// The arguments of a device processed along with others
+    pub fn for_device(&self, file: &Path) -> CommonArgs {
+        CommonArgs { device: Some(file.display().to_string()), ..self.clone() }
+    }
+
+    /// The prefix of the result lines of a device processed along with others, telling them apart
+    pub(crate) fn device_prefix(&self) -> String {
+        self.device.as_deref().map(|device| format!("{device}: ")).unwrap_or_default()
+    }
+}
+
+/// The chunk size given as `auto`, detected by validate
+pub const AUTO_CHUNK_SIZE: u64 = 0;
+
+fn parse_chunk_size(s: &str) -> Result<u64, String> {
+    if s == "auto" {
+        return Ok(AUTO_CHUNK_SIZE);
+    }
+    match parse_size(s).map_err(|e| e.to_string())? {
+        0 => Err("the chunk size can't be 0".to_string()),
+        size => Ok(size),
+    }
+}
+
+fn parse_bandwidth(s: &str) -> Result<u64, String> {
+    match parse_size(s).map_err(|e| e.to_string())? {
+        0 => Err("the bandwidth limit must be greater than 0".to_string()),
+        limit => Ok(limit),
+    }
+}
+
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    match parse_seconds(s)? {
+        0 => Err("the duration can't be 0".to_string()),
+        seconds => Ok(Duration::from_secs(seconds)),
+    }
+}
+
+/// A number of seconds, or of minutes, hours, days or weeks with the `m`, `h`, `d` or `w` suffix
+pub(crate) fn parse_seconds(s: &str) -> Result<u64, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (count, unit) = s.split_at(split);
+    let unit = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        "w" => 7 * 86400,
+        _ => return Err(format!("unknown unit {unit}, expected s, m, h, d or w")),
+    };
+    let count = count.parse::<u64>().map_err(|e| e.to_string())?;
+    count.checked_mul(unit).ok_or_else(|| "the duration is too long".to_string())
+}
+
+fn parse_imbalance_threshold(s: &str) -> Result<f64, String> {
+    let percent: f64 = s.strip_suffix('%').unwrap_or(s).parse().map_err(|e| format!("{e}"))?;
+    if percent >= 0.0 { Ok(percent) } else { Err("expected a positive percentage".to_string()) }
 }
 
 #[derive(Subcommand, Debug)]
 pub enum Commands {
+    #[command(visible_alias = "send", alias = "write")]
     Generate(GenerateArgs),
+    #[command(visible_alias = "receive", alias = "read")]
     Validate(ValidateArgs),
+    Verify(VerifyArgs),
+    Serve(ServeArgs),
+    Perf(PerfArgs),
+    Bench(BenchArgs),
+    DiscardTest(DiscardTestArgs),
+    Corrupt(CorruptArgs),
+    Repair(RepairArgs),
+    Scrub(ScrubArgs),
+    Completions(CompletionsArgs),
+}
+
+impl Commands {
+    /// The name of the command
+    pub fn name(&self) -> &'static str {
+        match self {
+            Commands::Generate(_) => "generate",
+            Commands::Validate(_) => "validate",
+            Commands::Verify(_) => "verify",
+            Commands::Serve(_) => "serve",
+            Commands::Perf(_) => "perf",
+            Commands::Bench(_) => "bench",
+            Commands::DiscardTest(_) => "discard-test",
+            Commands::Corrupt(_) => "corrupt",
+            Commands::Repair(_) => "repair",
+            Commands::Scrub(_) => "scrub",
+            Commands::Completions(_) => "completions",
+        }
+    }
+
+    /// The file or device the command is run on, if any
+    pub fn target(&self) -> Option<&Path> {
+        match self {
+            Commands::Generate(args) => args.file.as_deref(),
+            Commands::Validate(args) => args.file.as_deref(),
+            Commands::Verify(args) => args.generate.file.as_deref(),
+            Commands::Serve(_) => None,
+            Commands::Perf(args) => Some(&args.endpoint),
+            Commands::Bench(args) => Some(&args.file),
+            Commands::DiscardTest(args) => args.generate.file.as_deref(),
+            Commands::Corrupt(args) => Some(&args.file),
+            Commands::Repair(args) => Some(&args.file),
+            Commands::Scrub(_) => None,
+            Commands::Completions(_) => None,
+        }
+    }
 }
 
 #[test]
@@ -53,3 +467,14 @@ fn verify_cli() {
     use clap::CommandFactory;
     Cli::command().debug_assert()
 }
+
+#[test]
+fn parse_durations() {
+    assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+    assert_eq!(parse_duration("90m"), Ok(Duration::from_secs(5400)));
+    assert_eq!(parse_duration("45"), Ok(Duration::from_secs(45)));
+    assert!(parse_duration("0s").is_err());
+    assert!(parse_duration("2y").is_err());
+    assert!(parse_duration("1.5h").is_err());
+    assert!(parse_duration(&format!("{}w", u64::MAX)).is_err());
+}
@@ -0,0 +1,30 @@
+//! The completion scripts of the shells, generated from the definition of the command line
+
+use clap::{Args, CommandFactory as _};
+use clap_complete::Shell;
+use std::io::{self, Write as _};
+
+use crate::Error;
+use crate::cli::Cli;
+
+/// Print the completion script of a shell
+///
+/// The script completes the commands and their aliases, the options, and the values of the options
+/// taking a choice or a file. For bash, it can be loaded with
+/// `source <(randstream completions bash)`.
+#[derive(Args, Debug)]
+pub struct CompletionsArgs {
+    /// The shell to complete the command line of
+    #[clap(value_enum)]
+    pub shell: Shell,
+}
+
+pub fn completions(args: &CompletionsArgs) -> Result<i32, Error> {
+    let mut command = Cli::command();
+    let name = command.get_name().to_string();
+    // generated at once, to report the failure of the write instead of panicking
+    let mut script = Vec::new();
+    clap_complete::generate(args.shell, &mut command, name, &mut script);
+    io::stdout().write_all(&script)?;
+    Ok(0)
+}
@@ -0,0 +1,74 @@
+//! The compressibility of the generated data, with `--compress-ratio`
+
+/// The size of the blocks the compressibility applies to
+///
+/// It's the block size of VDO, and divides the record size of ZFS, so each block compresses on its
+/// own to the target ratio.
+pub const BLOCK_SIZE: usize = 4096;
+
+/// The share of random data in each block of the chunks
+///
+/// Each block starts with random data, and ends with zeros.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Compressibility {
+    /// The number of random bytes at the start of each block
+    random_bytes: u16,
+}
+
+impl Compressibility {
+    /// The compressibility giving the ratio, or `None` for incompressible data
+    pub fn new(ratio: f64) -> Option<Self> {
+        let random_bytes = (BLOCK_SIZE as f64 / ratio).round().max(1.0) as usize;
+        Self::from_random_bytes(random_bytes as u16)
+    }
+
+    /// The compressibility with that many random bytes per block, as recorded in the stream header
+    pub fn from_random_bytes(random_bytes: u16) -> Option<Self> {
+        (random_bytes > 0 && (random_bytes as usize) < BLOCK_SIZE)
+            .then_some(Compressibility { random_bytes })
+    }
+
+    pub fn random_bytes(self) -> u16 {
+        self.random_bytes
+    }
+
+    /// The compression ratio of the data
+    pub fn ratio(self) -> f64 {
+        BLOCK_SIZE as f64 / self.random_bytes as f64
+    }
+
+    /// Replace the end of each block of the random data with zeros
+    pub fn apply(self, data: &mut [u8]) {
+        for block in data.chunks_mut(BLOCK_SIZE) {
+            if let Some(tail) = block.get_mut(self.random_bytes as usize..) {
+                tail.fill(0);
+            }
+        }
+    }
+}
+
+/// Parse a compression ratio: at least 1, 1 being incompressible random data
+pub fn parse_compress_ratio(s: &str) -> Result<f64, String> {
+    let ratio: f64 = s.parse().map_err(|e| format!("{e}"))?;
+    if ratio >= 1.0 && ratio <= BLOCK_SIZE as f64 {
+        Ok(ratio)
+    } else {
+        Err(format!("expected a ratio between 1 and {BLOCK_SIZE}"))
+    }
+}
+
+#[test]
+fn compressibility_zeroes_the_end_of_the_blocks() {
+    assert_eq!(Compressibility::new(1.0), None);
+    let compressibility = Compressibility::new(4.0).unwrap();
+    assert_eq!(compressibility.random_bytes(), 1024);
+    assert_eq!(compressibility.ratio(), 4.0);
+    let mut data = vec![0xffu8; BLOCK_SIZE + 2000];
+    compressibility.apply(&mut data);
+    assert!(data[..1024].iter().all(|b| *b == 0xff));
+    assert!(data[1024..BLOCK_SIZE].iter().all(|b| *b == 0));
+    assert!(data[BLOCK_SIZE..BLOCK_SIZE + 1024].iter().all(|b| *b == 0xff));
+    assert!(data[BLOCK_SIZE + 1024..].iter().all(|b| *b == 0));
+    assert_eq!(parse_compress_ratio("2.5"), Ok(2.5));
+    assert!(parse_compress_ratio("0.5").is_err());
+}
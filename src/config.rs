@@ -0,0 +1,303 @@
+//! The configuration file supplying the default options, `randstream.toml`
+//!
+//! ```toml
+//! [defaults]
+//! chunk-size = "1Mi"
+//! jobs = 8
+//! checksum = "xxh3"
+//!
+//! [targets."/dev/sdb"]
+//! engine = "io-uring"
+//! direct = true
+//! ```
+//!
+//! The keys are the long options of the commands. The defaults apply to all the commands taking
+//! the option, and the options of a target to the commands run on it. The options given on the
+//! command line take precedence: the ones of the configuration are inserted before them, and the
+//! ones conflicting with an option of the command line are skipped.
+
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+use clap::parser::ValueSource;
+use clap::{Arg, CommandFactory as _};
+use log::debug;
+use toml_edit::{Document, Item, Table, Value};
+
+use crate::cli::Cli;
+use crate::error::usage;
+
+const FILE_NAME: &str = "randstream/randstream.toml";
+
+/// The configuration file to read, if any
+///
+/// It's the one given with `--config`, or the first `randstream/randstream.toml` found in the XDG
+/// configuration directories.
+pub fn path(cli: &Cli) -> Option<PathBuf> {
+    if cli.no_config {
+        return None;
+    }
+    if let Some(path) = &cli.config {
+        return Some(path.clone());
+    }
+    let home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+    let dirs = std::env::var_os("XDG_CONFIG_DIRS")
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/etc/xdg".into());
+    home.into_iter()
+        .chain(std::env::split_paths(&dirs))
+        .map(|dir| dir.join(FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+/// The command line, with the options of the configuration file inserted after the command
+///
+/// Returns `None` if the configuration file doesn't add any option.
+pub fn apply(cli: &Cli, path: &Path, args: &[OsString]) -> anyhow::Result<Option<Vec<OsString>>> {
+    let Some(command) = &cli.command else {
+        return Ok(None);
+    };
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Can't read the configuration file {}", path.display()))?;
+    let document = Document::parse(text)
+        .map_err(|e| usage(format!("Invalid configuration file {}: {e}", path.display())))?;
+    let options = options(document.as_table(), command.target())
+        .map_err(|e| usage(format!("Invalid configuration file {}: {e}", path.display())))?;
+    // the options given on the command line
+    let matches = Cli::command().try_get_matches_from(args)?;
+    let given: Vec<_> = match matches.subcommand_matches(command.name()) {
+        Some(matches) => matches
+            .ids()
+            .filter(|id| matches.value_source(id.as_str()) == Some(ValueSource::CommandLine))
+            .map(|id| id.to_string())
+            .collect(),
+        None => Vec::new(),
+    };
+    let options = command_options(command.name(), options, &given)?;
+    if options.is_empty() {
+        return Ok(None);
+    }
+    debug!("options from {}: {}", path.display(), options.join(" "));
+    let Some(position) = command_position(args) else {
+        return Ok(None);
+    };
+    let mut args = args.to_vec();
+    args.splice(position + 1..position + 1, options.into_iter().map(OsString::from));
+    Ok(Some(args))
+}
+
+/// The targets of a file in the format of the configuration, with the options of the command for
+/// each one
+///
+/// It's the targets file of `randstream scrub`: each section of `[targets]` is a target, with the
+/// defaults applied to all of them.
+pub(crate) fn targets(path: &Path, command: &str) -> anyhow::Result<Vec<(PathBuf, Vec<String>)>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Can't read the targets file {}", path.display()))?;
+    let invalid =
+        |e: &dyn std::fmt::Display| usage(format!("Invalid targets file {}: {e}", path.display()));
+    let document = Document::parse(text).map_err(|e| invalid(&e))?;
+    let table = document.as_table();
+    let keys: Vec<_> = match table.get("targets").and_then(|t| t.as_table_like()) {
+        Some(targets) => targets.iter().map(|(key, _)| key.to_string()).collect(),
+        None => Vec::new(),
+    };
+    if keys.is_empty() {
+        return Err(invalid(&"no target"));
+    }
+    keys.into_iter()
+        .map(|key| {
+            let options = options(table, Some(Path::new(&key))).map_err(|e| invalid(&e))?;
+            Ok((PathBuf::from(key), command_options(command, options, &[])?))
+        })
+        .collect()
+}
+
+/// The value of an option in the configuration
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Setting {
+    /// A flag, set or not, or a boolean value
+    Flag(bool),
+    Value(String),
+}
+
+/// The options of the configuration for the target, the ones of the target last
+fn options(table: &Table, target: Option<&Path>) -> Result<Vec<(String, Setting)>, String> {
+    if let Some((name, _)) = table.iter().find(|(name, _)| !["defaults", "targets"].contains(name))
+    {
+        return Err(format!("unknown section {name}"));
+    }
+    let mut options = match table.get("defaults") {
+        Some(item) => section_options("defaults", item)?,
+        None => Vec::new(),
+    };
+    if let Some(targets) = table.get("targets") {
+        let targets = targets.as_table_like().ok_or("targets must be a table")?;
+        for (key, item) in targets.iter() {
+            // all the targets are checked, even the ones not used
+            let target_options = section_options(&format!("targets.{key}"), item)?;
+            if target.is_some_and(|target| is_target(Path::new(key), target)) {
+                options.extend(target_options);
+            }
+        }
+    }
+    // the last setting of an option wins, even to unset a flag
+    let mut settings: Vec<(String, Setting)> = Vec::new();
+    for (key, setting) in options {
+        settings.retain(|(k, _)| *k != key);
+        settings.push((key, setting));
+    }
+    Ok(settings)
+}
+
+fn section_options(name: &str, item: &Item) -> Result<Vec<(String, Setting)>, String> {
+    let table = item.as_table_like().ok_or_else(|| format!("{name} must be a table"))?;
+    table
+        .iter()
+        .map(|(key, item)| {
+            let setting = match item.as_value() {
+                Some(Value::Boolean(b)) => Setting::Flag(*b.value()),
+                Some(Value::String(s)) => Setting::Value(s.value().clone()),
+                Some(Value::Integer(i)) => Setting::Value(i.value().to_string()),
+                Some(Value::Float(f)) => Setting::Value(f.value().to_string()),
+                _ => return Err(format!("{name}.{key} must be a string, a number or a boolean")),
+            };
+            Ok((key.to_string(), setting))
+        })
+        .collect()
+}
+
+/// Whether the target of a section is the one of the command, maybe through a link
+fn is_target(key: &Path, target: &Path) -> bool {
+    key == target
+        || matches!((fs::canonicalize(key), fs::canonicalize(target)), (Ok(a), Ok(b)) if a == b)
+}
+
+/// The command line options of the settings taken by the command
+///
+/// The options taken by other commands only are skipped, and the unknown ones rejected. The ones
+/// conflicting with the options `given` on the command line are skipped too, so the defaults never
+/// make the command line a usage error.
+fn command_options(
+    command: &str,
+    options: Vec<(String, Setting)>,
+    given: &[String],
+) -> anyhow::Result<Vec<String>> {
+    let cli = Cli::command();
+    let subcommand = cli.find_subcommand(command).expect("a defined command");
+    let mut args = Vec::new();
+    for (key, setting) in options {
+        let arg = subcommand
+            .get_arguments()
+            .find(|arg| !arg.is_global_set() && arg.get_long() == Some(&key));
+        let Some(arg) = arg else {
+            let known = cli
+                .get_subcommands()
+                .flat_map(|c| c.get_arguments())
+                .any(|arg| !arg.is_global_set() && arg.get_long() == Some(&key));
+            if !known {
+                return Err(usage(format!("Unknown option {key} in the configuration file")));
+            }
+            continue;
+        };
+        if let Some(other) = given.iter().find(|id| conflicts(subcommand, arg, id)) {
+            debug!(
+                "--{key} of the configuration skipped, conflicting with --{}",
+                other.replace('_', "-")
+            );
+            continue;
+        }
+        let takes_values = arg.get_action().takes_values();
+        match setting {
+            Setting::Flag(true) if !takes_values => args.push(format!("--{key}")),
+            Setting::Flag(false) if !takes_values => {}
+            Setting::Flag(b) => args.push(format!("--{key}={b}")),
+            Setting::Value(value) if takes_values => args.push(format!("--{key}={value}")),
+            Setting::Value(_) => {
+                return Err(usage(format!("--{key} takes no value, in the configuration file")));
+            }
+        }
+    }
+    Ok(args)
+}
+
+/// Whether the argument conflicts with the one of the id, either way
+fn conflicts(command: &clap::Command, arg: &Arg, id: &str) -> bool {
+    let Some(other) = command.get_arguments().find(|a| a.get_id() == id) else {
+        return false;
+    };
+    command.get_arg_conflicts_with(arg).iter().any(|a| a.get_id() == other.get_id())
+        || command.get_arg_conflicts_with(other).iter().any(|a| a.get_id() == arg.get_id())
+}
+
+/// The position of the command in the arguments, after the global options
+fn command_position(args: &[OsString]) -> Option<usize> {
+    let mut args = args.iter().enumerate().skip(1);
+    while let Some((i, arg)) = args.next() {
+        match arg.to_str() {
+            Some("--") => return None,
+            // the only global option taking a separate value
+            Some("--config") => {
+                args.next();
+            }
+            Some(arg) if arg.starts_with('-') => {}
+            _ => return Some(i),
+        }
+    }
+    None
+}
+
+#[test]
+fn options_of_the_target_follow_the_defaults() {
+    let document = Document::parse(
+        r#"
+        [defaults]
+        chunk-size = "1Mi"
+        jobs = 8
+        direct = false
+
+        [targets."/dev/sdb"]
+        direct = true
+        jobs = 2
+
+        [targets."/dev/sdc"]
+        engine = "mmap"
+        "#,
+    )
+    .unwrap();
+    let value = |v: &str| Setting::Value(v.to_string());
+    let defaults = vec![
+        ("chunk-size".to_string(), value("1Mi")),
+        ("jobs".to_string(), value("8")),
+        ("direct".to_string(), Setting::Flag(false)),
+    ];
+    assert_eq!(options(document.as_table(), None), Ok(defaults.clone()));
+    let sdb = vec![
+        ("chunk-size".to_string(), value("1Mi")),
+        ("direct".to_string(), Setting::Flag(true)),
+        ("jobs".to_string(), value("2")),
+    ];
+    assert_eq!(options(document.as_table(), Some(Path::new("/dev/sdb"))), Ok(sdb));
+
+    let document = Document::parse("[default]\njobs = 8").unwrap();
+    assert_eq!(options(document.as_table(), None), Err("unknown section default".to_string()));
+    let document = Document::parse("[defaults]\njobs = [8]").unwrap();
+    assert!(options(document.as_table(), None).is_err());
+}
+
+#[test]
+fn command_after_the_global_options() {
+    let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+    assert_eq!(command_position(&args(&["randstream", "generate", "out.bin"])), Some(1));
+    assert_eq!(
+        command_position(&args(&["randstream", "-vv", "--config", "a.toml", "read"])),
+        Some(4)
+    );
+    assert_eq!(command_position(&args(&["randstream", "--config=a.toml", "read"])), Some(2));
+    assert_eq!(command_position(&args(&["randstream", "-q"])), None);
+}
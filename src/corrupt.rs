@@ -0,0 +1,209 @@
+//! Corrupt a random stream on purpose, to check that validate reports each kind of corruption
+
+use clap::{ArgGroup, Args, ValueHint};
+use log::info;
+use parse_size::parse_size;
+use rand::RngExt as _;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use crate::error::{Error, usage};
+use crate::header::{HEADER_SIZE, StreamHeader};
+use crate::rng::splitmix64;
+use crate::{read_exact_or_eof, read_file_size};
+
+/// Corrupt a file or a device holding a random stream, in a controlled way
+///
+/// Each corruption is logged with the offset and the chunk it hits, so the tests know what
+/// validate must report. The chunks are counted from the stream position, after the stream header
+/// if any. The corruptions are applied in the order of the options below.
+#[derive(Args, Debug)]
+#[clap(group(
+    ArgGroup::new("corruption")
+        .required(true)
+        .multiple(true)
+        .args(["swap_chunks", "zero_chunk", "flip_bits", "flip_at", "truncate"])
+))]
+pub struct CorruptArgs {
+    /// The file or the block device
+    #[arg(value_hint = ValueHint::AnyPath)]
+    pub file: PathBuf,
+
+    /// The stream position
+    #[clap(short, long, default_value = "0", value_name = "SIZE", value_hint = ValueHint::Other, value_parser=|s: &str| parse_size(s))]
+    pub position: u64,
+
+    /// The chunk size of the stream
+    #[clap(short, long, default_value = "32ki", value_name = "SIZE", value_hint = ValueHint::Other, value_parser=|s: &str| parse_size(s))]
+    pub chunk_size: u64,
+
+    /// Swap the content of two chunks, like `3,7`
+    ///
+    /// Both chunks still match their checksum: only the headers of the `--format v2` chunks tell
+    /// they're misplaced.
+    #[clap(long, value_name = "CHUNK,CHUNK", value_parser = parse_chunk_pair)]
+    pub swap_chunks: Option<(u64, u64)>,
+
+    /// Fill a chunk with zeros, like a lost write on a thin-provisioned device
+    #[clap(long, value_name = "CHUNK")]
+    pub zero_chunk: Vec<u64>,
+
+    /// Flip this number of bits, at random offsets of the stream
+    #[clap(long, value_name = "COUNT")]
+    pub flip_bits: Option<u64>,
+
+    /// Flip the lowest bit of the byte at this offset of the file
+    #[clap(long, value_name = "OFFSET", value_parser=|s: &str| parse_size(s))]
+    pub flip_at: Vec<u64>,
+
+    /// The seed of the random offsets of `--flip-bits`
+    ///
+    /// Picked at random and logged, if not set, so the corruption can be reproduced.
+    #[clap(short = 'S', long, requires = "flip_bits")]
+    pub seed: Option<u64>,
+
+    /// Remove this size from the end of the file, like a torn write at the end of the stream
+    ///
+    /// Only for a regular file.
+    #[clap(long, value_name = "SIZE", value_hint = ValueHint::Other, value_parser=|s: &str| parse_size(s))]
+    pub truncate: Option<u64>,
+}
+
+fn parse_chunk_pair(s: &str) -> Result<(u64, u64), String> {
+    let pair = s.split_once(',').and_then(|(a, b)| Some((a.parse().ok()?, b.parse().ok()?)));
+    match pair {
+        Some((a, b)) if a != b => Ok((a, b)),
+        Some(_) => Err("the chunks must differ".to_string()),
+        None => Err("expected two chunk numbers, like 3,7".to_string()),
+    }
+}
+
+pub fn corrupt(args: &CorruptArgs) -> Result<i32, Error> {
+    corrupt_stream(args).map_err(Error::from)
+}
+
+/// The corrupted file, with the offset of the stream chunks
+struct Target {
+    file: File,
+    /// The offset of the first chunk, after the stream header if any
+    start: u64,
+    size: u64,
+    chunk_size: u64,
+}
+
+impl Target {
+    /// The chunk holding the byte at this offset of the file
+    fn chunk(&self, offset: u64) -> Option<u64> {
+        offset.checked_sub(self.start).map(|offset| offset / self.chunk_size)
+    }
+
+    /// The range of the file holding the chunk
+    fn chunk_range(&self, chunk: u64) -> anyhow::Result<(u64, usize)> {
+        let offset = self.start + chunk * self.chunk_size;
+        if offset >= self.size {
+            return Err(usage(format!("The chunk {chunk} is past the end of the stream")));
+        }
+        Ok((offset, (self.size - offset).min(self.chunk_size) as usize))
+    }
+
+    fn read(&mut self, offset: u64, data: &mut [u8]) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(data)
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(data)
+    }
+
+    fn flip(&mut self, offset: u64, bit: u8) -> anyhow::Result<()> {
+        if offset >= self.size {
+            return Err(usage(format!("The offset {offset} is past the end of the file")));
+        }
+        let mut byte = [0];
+        self.read(offset, &mut byte)?;
+        byte[0] ^= 1 << bit;
+        self.write(offset, &byte)?;
+        match self.chunk(offset) {
+            Some(chunk) => info!("flipped bit {bit} at offset {offset}, in chunk {chunk}"),
+            None => info!("flipped bit {bit} at offset {offset}, before the stream"),
+        }
+        Ok(())
+    }
+}
+
+fn corrupt_stream(args: &CorruptArgs) -> anyhow::Result<i32> {
+    if args.chunk_size == 0 {
+        return Err(usage("The chunk size can't be 0"));
+    }
+    let size = read_file_size(&args.file)?;
+    if args.position > size {
+        return Err(usage(format!(
+            "The position {} is greater than the file size {size}",
+            args.position
+        )));
+    }
+    if args.truncate.is_some() && !args.file.metadata()?.is_file() {
+        return Err(usage("--truncate requires a regular file"));
+    }
+    let mut file = OpenOptions::new().read(true).write(true).open(&args.file)?;
+    let mut header = [0; HEADER_SIZE];
+    file.seek(SeekFrom::Start(args.position))?;
+    let header_size = read_exact_or_eof(&mut file, &mut header)?;
+    let start = match StreamHeader::decode(&header[..header_size]) {
+        Some(_) => args.position + HEADER_SIZE as u64,
+        None => args.position,
+    };
+    let mut target = Target { file, start, size, chunk_size: args.chunk_size };
+
+    if let Some((a, b)) = args.swap_chunks {
+        let (offset_a, size_a) = target.chunk_range(a)?;
+        let (offset_b, size_b) = target.chunk_range(b)?;
+        if size_a != size_b {
+            return Err(usage(format!("The chunks {a} and {b} don't have the same size")));
+        }
+        let (mut data_a, mut data_b) = (vec![0; size_a], vec![0; size_b]);
+        target.read(offset_a, &mut data_a)?;
+        target.read(offset_b, &mut data_b)?;
+        target.write(offset_a, &data_b)?;
+        target.write(offset_b, &data_a)?;
+        info!("swapped chunk {a} at offset {offset_a} with chunk {b} at offset {offset_b}");
+    }
+    for chunk in &args.zero_chunk {
+        let (offset, size) = target.chunk_range(*chunk)?;
+        target.write(offset, &vec![0; size])?;
+        info!("zeroed chunk {chunk} at offset {offset}");
+    }
+    if let Some(count) = args.flip_bits {
+        if start >= size {
+            return Err(usage("The stream is empty, there's no bit to flip"));
+        }
+        let seed = args.seed.unwrap_or_else(|| rand::rng().random());
+        info!("seed: {seed}");
+        let mut state = seed;
+        for _ in 0..count {
+            let value = splitmix64(&mut state);
+            let offset = start + (value >> 3) % (size - start);
+            target.flip(offset, (value & 7) as u8)?;
+        }
+    }
+    for offset in &args.flip_at {
+        target.flip(*offset, 0)?;
+    }
+    target.file.sync_all()?;
+    if let Some(length) = args.truncate {
+        // the stream header is kept
+        let size = size.saturating_sub(length).max(start);
+        target.file.set_len(size)?;
+        info!("truncated the file to {size} bytes");
+    }
+    Ok(0)
+}
+
+#[test]
+fn chunk_pairs() {
+    assert_eq!(parse_chunk_pair("3,7"), Ok((3, 7)));
+    assert!(parse_chunk_pair("3,3").is_err());
+    assert!(parse_chunk_pair("3").is_err());
+}
@@ -0,0 +1,140 @@
+//! A table driven implementation of CRC-64/NVME, with the same interface as `crc32fast::Hasher`.
+
+/// The reflected NVMe polynomial
+const POLY: u64 = 0x9A6C9329AC4BC9B5;
+
+const TABLE: [u64; 256] = make_table();
+
+const fn make_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ POLY } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Crc64 {
+    state: u64,
+    amount: u64,
+}
+
+impl Crc64 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resume a CRC computed on `amount` bytes
+    pub fn new_with_initial_len(state: u64, amount: u64) -> Self {
+        Crc64 { state, amount }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        let mut crc = !self.state;
+        for &byte in data {
+            crc = TABLE[((crc as u8) ^ byte) as usize] ^ (crc >> 8);
+        }
+        self.state = !crc;
+        self.amount += data.len() as u64;
+    }
+
+    pub fn finalize(&self) -> u64 {
+        self.state
+    }
+
+    /// Append the CRC of the data following this one
+    pub fn combine(&mut self, other: &Self) {
+        self.state = combine(self.state, other.state, other.amount);
+        self.amount += other.amount;
+    }
+}
+
+fn gf2_matrix_times(matrix: &[u64; 64], mut vector: u64) -> u64 {
+    let mut sum = 0;
+    let mut i = 0;
+    while vector != 0 {
+        if vector & 1 == 1 {
+            sum ^= matrix[i];
+        }
+        vector >>= 1;
+        i += 1;
+    }
+    sum
+}
+
+fn gf2_matrix_square(square: &mut [u64; 64], matrix: &[u64; 64]) {
+    for (s, &m) in square.iter_mut().zip(matrix) {
+        *s = gf2_matrix_times(matrix, m);
+    }
+}
+
+/// The CRC of the concatenation of two messages, as in zlib's `crc32_combine()`
+fn combine(mut crc1: u64, crc2: u64, mut len2: u64) -> u64 {
+    if len2 == 0 {
+        return crc1;
+    }
+    // the operator of a single zero bit
+    let mut odd = [0u64; 64];
+    odd[0] = POLY;
+    for (i, row) in odd.iter_mut().enumerate().skip(1) {
+        *row = 1 << (i - 1);
+    }
+    let mut even = [0u64; 64];
+    // two zero bits, then four
+    gf2_matrix_square(&mut even, &odd);
+    gf2_matrix_square(&mut odd, &even);
+    // apply len2 zero bytes to crc1
+    loop {
+        gf2_matrix_square(&mut even, &odd);
+        if len2 & 1 == 1 {
+            crc1 = gf2_matrix_times(&even, crc1);
+        }
+        len2 >>= 1;
+        if len2 == 0 {
+            break;
+        }
+        gf2_matrix_square(&mut odd, &even);
+        if len2 & 1 == 1 {
+            crc1 = gf2_matrix_times(&odd, crc1);
+        }
+        len2 >>= 1;
+        if len2 == 0 {
+            break;
+        }
+    }
+    crc1 ^ crc2
+}
+
+#[test]
+fn crc64_reference_value() {
+    let mut crc = Crc64::new();
+    crc.update(b"123456789");
+    assert_eq!(crc.finalize(), 0xae8b14860a799888);
+}
+
+#[test]
+fn crc64_combine() {
+    let data: Vec<u8> = (0..5000u32).map(|i| (i * 13 % 251) as u8).collect();
+    let mut sequential = Crc64::new();
+    sequential.update(&data);
+    for split in [0, 1, 7, 4096, 5000] {
+        let mut first = Crc64::new();
+        first.update(&data[..split]);
+        let mut second = Crc64::new();
+        second.update(&data[split..]);
+        first.combine(&second);
+        assert_eq!(first.finalize(), sequential.finalize(), "{split}");
+    }
+}
@@ -0,0 +1,171 @@
+//! The compressed streams, decompressed before being validated, with `validate --decompress`
+//!
+//! The data goes through the `gzip`, `zstd` or `xz` command, fed by a thread which counts the
+//! compressed bytes read, logged at the end. The throughput, and the offsets of the corrupted
+//! chunks, are the ones of the decompressed stream, not of the compressed file.
+
+use clap::ValueEnum;
+use log::info;
+use std::io::{self, Cursor, Read, Write as _};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::thread::{self, JoinHandle};
+
+use crate::read_exact_or_eof;
+
+/// The compression of the input
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Decompress {
+    /// Read the input as it is
+    #[default]
+    None,
+    /// Detect the compression from the magic bytes at the start of the input
+    Auto,
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+/// The magic bytes of each compression format
+const MAGIC: [(Decompress, &[u8]); 3] = [
+    (Decompress::Gzip, &[0x1f, 0x8b]),
+    (Decompress::Zstd, &[0x28, 0xb5, 0x2f, 0xfd]),
+    (Decompress::Xz, &[0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00]),
+];
+
+impl Decompress {
+    /// The compression of the data starting with these bytes, if any
+    pub fn detect(data: &[u8]) -> Decompress {
+        let found = MAGIC.iter().find(|(_, magic)| data.starts_with(magic));
+        found.map_or(Decompress::None, |(format, _)| *format)
+    }
+
+    /// The compression of the input, read from its start with `auto`
+    ///
+    /// The bytes read are put back in front of the input returned.
+    pub fn resolve(
+        self,
+        mut input: Box<dyn Read + Send>,
+    ) -> io::Result<(Decompress, Box<dyn Read + Send>)> {
+        if self != Decompress::Auto {
+            return Ok((self, input));
+        }
+        let mut prefix = vec![0; 6];
+        let size = read_exact_or_eof(&mut input, &mut prefix)?;
+        prefix.truncate(size);
+        let format = Decompress::detect(&prefix);
+        Ok((format, Box::new(Cursor::new(prefix).chain(input))))
+    }
+
+    /// The command decompressing its input to its output
+    fn program(self) -> Option<&'static str> {
+        match self {
+            Decompress::None | Decompress::Auto => None,
+            Decompress::Gzip => Some("gzip"),
+            Decompress::Zstd => Some("zstd"),
+            Decompress::Xz => Some("xz"),
+        }
+    }
+
+    /// The input decompressed, with `auto` detecting its compression
+    pub fn reader(self, input: Box<dyn Read + Send>) -> io::Result<Box<dyn Read>> {
+        let (format, input) = self.resolve(input)?;
+        let Some(program) = format.program() else {
+            return Ok(input);
+        };
+        info!("decompressing the input with {program}");
+        let mut child = Command::new(program)
+            .args(["-d", "-c", "-q"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| io::Error::new(e.kind(), format!("Can't run {program}: {e}")))?;
+        let mut stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let feeder = thread::spawn(move || {
+            let mut input = input;
+            let mut buf = vec![0; 1 << 20];
+            let mut compressed = 0;
+            loop {
+                let size = match input.read(&mut buf) {
+                    Ok(0) => return Ok(compressed),
+                    Ok(size) => size,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                };
+                match stdin.write_all(&buf[..size]) {
+                    // the decompressor stopped, and tells why
+                    Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(compressed),
+                    result => result?,
+                }
+                compressed += size as u64;
+            }
+        });
+        Ok(Box::new(Decompressor { program, child: Some(child), stdout, feeder: Some(feeder) }))
+    }
+}
+
+/// The output of the decompressor, ending with an error if the input couldn't be read or
+/// decompressed
+struct Decompressor {
+    program: &'static str,
+    child: Option<Child>,
+    stdout: ChildStdout,
+    /// The thread feeding the compressed input, returning its size
+    feeder: Option<JoinHandle<io::Result<u64>>>,
+}
+
+impl Decompressor {
+    fn finish(&mut self) -> io::Result<()> {
+        let (Some(mut child), Some(feeder)) = (self.child.take(), self.feeder.take()) else {
+            return Ok(());
+        };
+        let compressed =
+            feeder.join().map_err(|_| io::Error::other("The input thread panicked"))?;
+        let status = child.wait()?;
+        let compressed = compressed?;
+        if !status.success() {
+            return Err(io::Error::other(format!(
+                "{} can't decompress the input: {status}",
+                self.program
+            )));
+        }
+        info!("compressed bytes read: {compressed}");
+        Ok(())
+    }
+}
+
+impl Read for Decompressor {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.stdout.read(buf)?;
+        if size == 0 && !buf.is_empty() {
+            self.finish()?;
+        }
+        Ok(size)
+    }
+}
+
+impl Drop for Decompressor {
+    fn drop(&mut self) {
+        // the validation stopped before the end of the stream
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+#[test]
+fn detect_compression() {
+    assert_eq!(Decompress::detect(&[0x1f, 0x8b, 8, 0]), Decompress::Gzip);
+    assert_eq!(Decompress::detect(&[0x28, 0xb5, 0x2f, 0xfd, 0x24]), Decompress::Zstd);
+    assert_eq!(Decompress::detect(b"\xfd7zXZ\0\0"), Decompress::Xz);
+    assert_eq!(Decompress::detect(b"\xfd7zX"), Decompress::None);
+    assert_eq!(Decompress::detect(&[]), Decompress::None);
+
+    let input = Box::new(Cursor::new(b"\x1f\x8b data".to_vec()));
+    let (format, mut input) = Decompress::Auto.resolve(input).unwrap();
+    assert_eq!(format, Decompress::Gzip);
+    let mut data = Vec::new();
+    input.read_to_end(&mut data).unwrap();
+    assert_eq!(data, b"\x1f\x8b data");
+}
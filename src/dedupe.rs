@@ -0,0 +1,118 @@
+//! The duplicated blocks of the generated data, with `--dedupe-ratio`
+
+use std::ops::Range;
+
+use crate::SeekableRng;
+use crate::compress::BLOCK_SIZE;
+use crate::rng::{RngAlgorithm, Seed, splitmix64};
+
+/// Each group of `ratio` consecutive blocks of the stream shares the same random data
+///
+/// The data of a group is derived from the seed and the index of the group, so the chunks can
+/// still be generated in any order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Dedupe {
+    ratio: u16,
+    rng: RngAlgorithm,
+    key: u64,
+}
+
+impl Dedupe {
+    /// The duplication of the blocks for the ratio, or `None` for unique blocks
+    pub fn new(ratio: u16, rng: RngAlgorithm, seed: Seed) -> Option<Self> {
+        (ratio > 1).then_some(Dedupe { ratio, rng, key: seed.fingerprint() })
+    }
+
+    pub fn ratio(self) -> u16 {
+        self.ratio
+    }
+
+    /// The ratio of the blocks of the stream to its unique ones
+    ///
+    /// It's lower than the requested ratio, since the first block of each chunk, holding its
+    /// header with `--format v2`, and its last one, holding its checksum, are unique. The groups
+    /// of blocks repeat with the chunks every `lcm(ratio, blocks per chunk)` blocks, so only the
+    /// chunks of one period and of the end of the stream are counted.
+    pub fn achieved_ratio(self, stream_size: u64, chunk_size: u64, header: bool) -> f64 {
+        let block_size = BLOCK_SIZE as u64;
+        let chunk_blocks = chunk_size.div_ceil(block_size);
+        let num_blocks = stream_size.div_ceil(block_size);
+        if num_blocks == 0 {
+            return 1.0;
+        }
+        let ratio = self.ratio as u64;
+        let period_chunks = lcm(ratio, chunk_blocks) / chunk_blocks;
+        let num_chunks = stream_size.div_ceil(chunk_size);
+        let periods = (num_chunks - 1) / period_chunks;
+        // the unique blocks of the chunks, a group spanning two chunks being counted once
+        let unique = |chunks: Range<u64>| {
+            let mut unique = 0;
+            let mut last_group = None;
+            for chunk in chunks {
+                let first = chunk * chunk_blocks;
+                let end = (first + chunk_blocks).min(num_blocks);
+                // the blocks between the header and checksum ones
+                let data_start = (first + header as u64).min(end);
+                let data_end = (end - 1).max(data_start);
+                unique += (end - first) - (data_end - data_start);
+                if data_start < data_end {
+                    let groups = data_start / ratio..=(data_end - 1) / ratio;
+                    unique += groups.end() - groups.start() + 1;
+                    unique -= (last_group == Some(*groups.start())) as u64;
+                    last_group = Some(*groups.end());
+                }
+            }
+            unique
+        };
+        let unique =
+            periods * unique(0..period_chunks) + unique(periods * period_chunks..num_chunks);
+        num_blocks as f64 / unique as f64
+    }
+
+    /// Fill the data starting at the block `first_block` of the stream
+    pub fn fill(self, first_block: u64, data: &mut [u8]) {
+        for (i, block) in data.chunks_mut(BLOCK_SIZE).enumerate() {
+            let mut state = self.key ^ ((first_block + i as u64) / self.ratio as u64);
+            self.rng.rng(Seed::U64(splitmix64(&mut state))).fill_bytes(block);
+        }
+    }
+}
+
+/// The least common multiple
+fn lcm(a: u64, b: u64) -> u64 {
+    let (mut x, mut y) = (a, b);
+    while y != 0 {
+        (x, y) = (y, x % y);
+    }
+    a / x * b
+}
+
+#[test]
+fn dedupe_repeats_the_blocks() {
+    assert_eq!(Dedupe::new(1, RngAlgorithm::Pcg64, Seed::U64(1)), None);
+    let dedupe = Dedupe::new(3, RngAlgorithm::Pcg64, Seed::U64(1)).unwrap();
+    let mut data = vec![0u8; 4 * BLOCK_SIZE];
+    dedupe.fill(1, &mut data);
+    let blocks: Vec<_> = data.chunks(BLOCK_SIZE).collect();
+    assert_eq!(blocks[0], blocks[1]);
+    assert_ne!(blocks[1], blocks[2]);
+    assert_eq!(blocks[2], blocks[3]);
+    let mut other = vec![0u8; BLOCK_SIZE];
+    dedupe.fill(5, &mut other);
+    assert_eq!(blocks[3], &other[..]);
+}
+
+#[test]
+fn achieved_ratio_counts_the_unique_blocks() {
+    let dedupe = Dedupe::new(4, RngAlgorithm::Pcg64, Seed::U64(1)).unwrap();
+    // 4 groups and a checksum block in each chunk of 16 blocks
+    assert_eq!(dedupe.achieved_ratio(1 << 20, 64 << 10, false), 256.0 / 80.0);
+    // the header block too, leaving 14 blocks in 4 groups
+    assert_eq!(dedupe.achieved_ratio(1 << 20, 64 << 10, true), 256.0 / 96.0);
+    // groups spanning the chunks, a period of 2 chunks of 6 blocks: 0-3 4 | 6-7 8-11
+    assert_eq!(dedupe.achieved_ratio(96 << 10, 24 << 10, false), 24.0 / 10.0);
+    // a short chunk at the end
+    assert_eq!(dedupe.achieved_ratio(72 << 10, 24 << 10, false), 18.0 / 8.0);
+    // no data block in a chunk of a single one
+    assert_eq!(dedupe.achieved_ratio(1 << 20, 4 << 10, false), 1.0);
+}
@@ -0,0 +1,262 @@
+//! Several files or devices processed at once, each with its own stream
+
+use anyhow::Context as _;
+use human_units::FormatSize as _;
+use log::{error, info};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::usage;
+use crate::report::{self, OutputFormat, Report};
+
+/// Run the command on the files, `jobs` at a time, then log the result of each one in a table
+///
+/// `run` processes the stream of a file, and gets the index of the file to derive its seed. The
+/// exit code is the one of the first file which failed.
+pub fn run<F>(
+    command: &'static str,
+    output: OutputFormat,
+    files: &[PathBuf],
+    jobs: usize,
+    cancel: &AtomicBool,
+    run: F,
+) -> i32
+where
+    F: Fn(usize, &Path, Arc<AtomicBool>, &mut Report) -> anyhow::Result<i32> + Sync,
+{
+    let start = Instant::now();
+    let results = process(command, output, files, jobs, cancel, run);
+    if output == OutputFormat::Json {
+        println!("{}", report::devices_json(command, &results));
+    } else {
+        log_table("device", &results);
+        log_summary(&results, start.elapsed());
+    }
+    exit_code(&results)
+}
+
+/// Run the command on the files, `jobs` at a time, and return the result of each one
+///
+/// Each file has its own cancel flag, so a failing one doesn't stop the others, but they all stop
+/// on an interruption.
+pub(crate) fn process<F>(
+    command: &'static str,
+    output: OutputFormat,
+    files: &[PathBuf],
+    jobs: usize,
+    cancel: &AtomicBool,
+    run: F,
+) -> Vec<(String, Report, i32)>
+where
+    F: Fn(usize, &Path, Arc<AtomicBool>, &mut Report) -> anyhow::Result<i32> + Sync,
+{
+    let cancels: Vec<_> = files.iter().map(|_| Arc::new(AtomicBool::new(false))).collect();
+    let done = AtomicBool::new(false);
+    // the next file to process, taken by the first idle worker
+    let next = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            while !done.load(Ordering::Relaxed) {
+                if cancel.load(Ordering::Relaxed) {
+                    cancels.iter().for_each(|c| c.store(true, Ordering::Relaxed));
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        });
+        let handles: Vec<_> = (0..jobs.clamp(1, files.len().max(1)))
+            .map(|_| {
+                let (run, next, cancels) = (&run, &next, &cancels);
+                scope.spawn(move || {
+                    let mut results = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(file) = files.get(index) else {
+                            return results;
+                        };
+                        let mut report = Report::new(command, output);
+                        let result = run(index, file, cancels[index].clone(), &mut report);
+                        let exit_code = report.record(&result);
+                        results.push((index, (file.display().to_string(), report, exit_code)));
+                    }
+                })
+            })
+            .collect();
+        let mut results: Vec<_> =
+            handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect();
+        done.store(true, Ordering::Relaxed);
+        results.sort_by_key(|(index, _)| *index);
+        results.into_iter().map(|(_, result)| result).collect()
+    })
+}
+
+/// The exit code of the first file which failed
+pub(crate) fn exit_code(results: &[(String, Report, i32)]) -> i32 {
+    results.iter().map(|(_, _, code)| *code).find(|code| *code != 0).unwrap_or(0)
+}
+
+/// Log the number of files which passed and failed, and the bytes processed by all of them
+pub(crate) fn log_summary(results: &[(String, Report, i32)], elapsed: Duration) {
+    let failed = results.iter().filter(|(_, _, code)| *code != 0).count();
+    let bytes: u64 = results.iter().map(|(_, report, _)| report.bytes).sum();
+    info!(
+        "files: {}, passed: {}, failed: {failed}, {} in {:.1}s, {}/s",
+        results.len(),
+        results.len() - failed,
+        bytes.format_size(),
+        elapsed.as_secs_f64(),
+        report::throughput(bytes, elapsed).format_size()
+    );
+}
+
+/// Log a line per device, or per sender, with its verdict, the bytes processed and the checksum,
+/// or the error
+pub(crate) fn log_table(column: &str, results: &[(String, Report, i32)]) {
+    let width = results.iter().map(|(device, _, _)| device.len()).max().unwrap_or(0);
+    let width = width.max(column.len());
+    info!("{column:<width$}  result  {:>10}  {:>12}  checksum", "bytes", "throughput");
+    for (device, report, exit_code) in results {
+        let stats = report.stats();
+        let bytes = stats.bytes.format_size().to_string();
+        let throughput =
+            format!("{}/s", report::throughput(stats.bytes, stats.elapsed).format_size());
+        if *exit_code == 0 {
+            let checksum = report.checksum.as_deref().unwrap_or("-");
+            info!("{device:<width$}  pass    {bytes:>10}  {throughput:>12}  {checksum}");
+        } else {
+            let reason = match report.errors.first() {
+                Some(e) => e.clone(),
+                None => format!("exit code {exit_code}"),
+            };
+            error!("{device:<width$}  fail    {bytes:>10}  {throughput:>12}  {reason}");
+        }
+    }
+}
+
+/// The files listed in a file, one per line, or on stdin with `-`
+///
+/// The empty lines and the ones starting with `#` are skipped.
+pub(crate) fn read_list(path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let list = if path == Path::new("-") {
+        io::read_to_string(io::stdin())?
+    } else {
+        fs::read_to_string(path)
+            .with_context(|| format!("Can't read the list of files {}", path.display()))?
+    };
+    Ok(list
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(PathBuf::from)
+        .collect())
+}
+
+/// Whether the path is a pattern to expand, like `/exports/*.img`, rather than a file or an
+/// address
+pub(crate) fn is_pattern(path: &Path) -> bool {
+    let s = path.to_string_lossy();
+    s.contains(['*', '?', '[']) && !s.contains("://") && !path.exists()
+}
+
+/// The files matching a pattern, sorted
+///
+/// `*` matches any part of a name, `?` any character, and `[a-z]` or `[!a]` a character of a set.
+/// The names starting with a dot are only matched by a pattern starting with one.
+pub(crate) fn expand(pattern: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut paths = vec![PathBuf::new()];
+    for component in pattern.components() {
+        let name = component.as_os_str().to_string_lossy();
+        if !name.contains(['*', '?', '[']) {
+            paths.iter_mut().for_each(|path| path.push(component));
+            continue;
+        }
+        let pattern: Vec<char> = name.chars().collect();
+        let mut matched = Vec::new();
+        for path in paths {
+            let dir = if path.as_os_str().is_empty() { Path::new(".") } else { &path };
+            let Ok(entries) = fs::read_dir(dir) else {
+                continue;
+            };
+            for entry in entries {
+                let entry_name = entry?.file_name().to_string_lossy().to_string();
+                if entry_name.starts_with('.') && pattern[0] != '.' {
+                    continue;
+                }
+                if matches(&pattern, &entry_name.chars().collect::<Vec<_>>()) {
+                    matched.push(path.join(entry_name));
+                }
+            }
+        }
+        paths = matched;
+    }
+    paths.retain(|path| path.exists());
+    paths.sort();
+    if paths.is_empty() {
+        return Err(usage(format!("No file matches {}", pattern.display())));
+    }
+    Ok(paths)
+}
+
+/// Whether a name matches the pattern of a path component
+fn matches(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|skip| matches(&pattern[1..], &name[skip..])),
+        Some('?') => !name.is_empty() && matches(&pattern[1..], &name[1..]),
+        Some('[') => {
+            let Some(end) = pattern.iter().skip(2).position(|c| *c == ']').map(|i| i + 2) else {
+                // not a set, a plain character
+                return name.first() == Some(&'[') && matches(&pattern[1..], &name[1..]);
+            };
+            let (negated, set) = match pattern[1] {
+                '!' => (true, &pattern[2..end]),
+                _ => (false, &pattern[1..end]),
+            };
+            let Some(c) = name.first() else {
+                return false;
+            };
+            in_set(set, *c) != negated && matches(&pattern[end + 1..], &name[1..])
+        }
+        Some(c) => name.first() == Some(c) && matches(&pattern[1..], &name[1..]),
+    }
+}
+
+/// Whether the character is in the set of a pattern, like `a-z_`
+fn in_set(set: &[char], c: char) -> bool {
+    let mut i = 0;
+    while i < set.len() {
+        if set.get(i + 1) == Some(&'-') && i + 2 < set.len() {
+            if (set[i]..=set[i + 2]).contains(&c) {
+                return true;
+            }
+            i += 3;
+        } else {
+            if set[i] == c {
+                return true;
+            }
+            i += 1;
+        }
+    }
+    false
+}
+
+#[test]
+fn names_matching_a_pattern() {
+    let matches = |pattern: &str, name: &str| {
+        matches(&pattern.chars().collect::<Vec<_>>(), &name.chars().collect::<Vec<_>>())
+    };
+    assert!(matches("*.img", "disk.img"));
+    assert!(matches("*.img", ".img"));
+    assert!(!matches("*.img", "disk.iso"));
+    assert!(matches("disk-?.img", "disk-1.img"));
+    assert!(!matches("disk-?.img", "disk-10.img"));
+    assert!(matches("disk-[0-9].img", "disk-7.img"));
+    assert!(!matches("disk-[!0-9].img", "disk-7.img"));
+    assert!(matches("disk-[ab]*", "disk-b.img"));
+    assert!(matches("disk-[a-c_].img", "disk-_.img"));
+    assert!(matches("[x", "[x"));
+}
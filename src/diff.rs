@@ -0,0 +1,123 @@
+//! The byte-level comparison of a corrupted chunk with its regenerated data, with `--diff`
+
+use log::warn;
+use std::ops::Range;
+
+/// The number of differing bytes detailed in the report
+const MAX_SAMPLES: usize = 16;
+
+/// The differing bytes separated by fewer matching bytes are reported in the same damaged region
+///
+/// Two random bytes are equal once in 256 times, so a region overwritten with other data still has
+/// some bytes matching by chance.
+const REGION_GAP: u64 = 16;
+
+/// A differing byte
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ByteDiff {
+    pub offset: u64,
+    pub expected: u8,
+    pub found: u8,
+}
+
+/// How the data of a chunk differs from the expected one
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChunkDiff {
+    /// The number of differing bytes
+    pub bytes: u64,
+    /// The damaged regions, as offsets in the file
+    pub regions: Vec<Range<u64>>,
+    /// The first differing bytes
+    pub samples: Vec<ByteDiff>,
+    /// The number of differing bytes by number of flipped bits, from 1 to 8
+    pub bit_flips: [u64; 8],
+}
+
+impl ChunkDiff {
+    /// Compare the data found at `offset` with the expected one
+    pub fn new(offset: u64, found: &[u8], expected: &[u8]) -> Self {
+        let mut diff = ChunkDiff::default();
+        for (i, (&found, &expected)) in found.iter().zip(expected).enumerate() {
+            if found == expected {
+                continue;
+            }
+            let offset = offset + i as u64;
+            diff.bytes += 1;
+            match diff.regions.last_mut() {
+                Some(last) if offset - last.end < REGION_GAP => last.end = offset + 1,
+                _ => diff.regions.push(offset..offset + 1),
+            }
+            if diff.samples.len() < MAX_SAMPLES {
+                diff.samples.push(ByteDiff { offset, expected, found });
+            }
+            diff.bit_flips[(found ^ expected).count_ones() as usize - 1] += 1;
+        }
+        diff
+    }
+
+    /// The offsets of the first and last differing bytes
+    pub fn bounds(&self) -> Option<(u64, u64)> {
+        Some((self.regions.first()?.start, self.regions.last()?.end - 1))
+    }
+
+    /// The total number of flipped bits
+    pub fn flipped_bits(&self) -> u64 {
+        self.bit_flips.iter().zip(1..).map(|(bytes, bits)| bytes * bits).sum()
+    }
+
+    /// Log the report
+    pub fn log(&self, chunk: u64) {
+        if self.bytes == 0 {
+            warn!("diff of chunk {chunk}: the payload matches the regenerated data");
+            return;
+        }
+        warn!(
+            "diff of chunk {chunk}: {} bytes differ, {} bits flipped, in {} damaged regions",
+            self.bytes,
+            self.flipped_bits(),
+            self.regions.len()
+        );
+        for region in &self.regions {
+            warn!(
+                "  damaged region: {}..{} ({} bytes)",
+                region.start,
+                region.end,
+                region.end - region.start
+            );
+        }
+        for sample in &self.samples {
+            warn!(
+                "  offset {}: expected {:02x}, found {:02x}",
+                sample.offset, sample.expected, sample.found
+            );
+        }
+        if self.bytes > self.samples.len() as u64 {
+            warn!("  ... and {} more bytes", self.bytes - self.samples.len() as u64);
+        }
+        let histogram = self
+            .bit_flips
+            .iter()
+            .zip(1..)
+            .filter(|(bytes, _)| **bytes > 0)
+            .map(|(bytes, bits)| format!("{bits} bits: {bytes}"))
+            .collect::<Vec<_>>();
+        warn!("  bytes by flipped bits: {}", histogram.join(", "));
+    }
+}
+
+#[test]
+fn chunk_diff_of_a_bit_flip_and_a_torn_write() {
+    let expected = vec![0x55u8; 100];
+    let mut found = expected.clone();
+    found[3] ^= 0x04;
+    found[50..60].fill(0);
+    found[55] = 0x55;
+    let diff = ChunkDiff::new(1000, &found, &expected);
+    assert_eq!(diff.bytes, 10);
+    assert_eq!(diff.regions, [1003..1004, 1050..1060]);
+    assert_eq!(diff.bounds(), Some((1003, 1059)));
+    assert_eq!(diff.samples[0], ByteDiff { offset: 1003, expected: 0x55, found: 0x51 });
+    assert_eq!(diff.bit_flips, [1, 0, 0, 9, 0, 0, 0, 0]);
+    assert_eq!(diff.flipped_bits(), 37);
+    assert_eq!(ChunkDiff::new(0, &expected, &expected).bounds(), None);
+}
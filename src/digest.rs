@@ -0,0 +1,72 @@
+use clap::ValueEnum;
+
+use crate::blake3::Blake3;
+use crate::sha256::Sha256;
+
+/// The algorithm used to compute a digest of the whole stream
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DigestAlgorithm {
+    /// BLAKE3, 256 bits
+    Blake3,
+    /// SHA-256
+    Sha256,
+}
+
+impl DigestAlgorithm {
+    /// Create an empty digest of the range of the stream starting at `offset`
+    pub fn stream_digest(self, offset: u64) -> StreamDigest {
+        match self {
+            DigestAlgorithm::Blake3 => StreamDigest(DigestState::Blake3(Blake3::new(offset))),
+            DigestAlgorithm::Sha256 => {
+                assert_eq!(offset, 0, "SHA-256 can only be computed from the start of the stream");
+                StreamDigest(DigestState::Sha256(Sha256::default()))
+            }
+        }
+    }
+
+    /// Whether the digests of separate ranges of the stream can be combined
+    ///
+    /// Otherwise, the data must be hashed in stream order.
+    pub fn is_combinable(self) -> bool {
+        match self {
+            DigestAlgorithm::Blake3 => true,
+            DigestAlgorithm::Sha256 => false,
+        }
+    }
+}
+
+/// Computes the digest of a range of the stream
+#[derive(Clone, Debug)]
+pub struct StreamDigest(DigestState);
+
+#[derive(Clone, Debug)]
+enum DigestState {
+    Blake3(Blake3),
+    Sha256(Sha256),
+}
+
+impl StreamDigest {
+    pub fn update(&mut self, data: &[u8]) {
+        match &mut self.0 {
+            DigestState::Blake3(hasher) => hasher.update(data),
+            DigestState::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    /// Append the digest of the range following this one
+    pub fn combine(&mut self, other: &Self) {
+        match (&mut self.0, &other.0) {
+            (DigestState::Blake3(hasher), DigestState::Blake3(other)) => hasher.combine(other),
+            _ => panic!("can't combine these digests"),
+        }
+    }
+
+    /// The digest of the stream, formatted in hexadecimal
+    pub fn finalize(&self) -> String {
+        let digest = match &self.0 {
+            DigestState::Blake3(hasher) => hasher.finalize(),
+            DigestState::Sha256(hasher) => hasher.finalize(),
+        };
+        digest.iter().map(|b| format!("{b:02x}")).collect()
+    }
+}
@@ -0,0 +1,235 @@
+//! The direct I/O support, bypassing the page cache.
+//!
+//! With O_DIRECT, the buffers, offsets and sizes of the reads and writes must be aligned on the
+//! logical block size of the file. The chunk size is rounded up to it, and the last chunk of the
+//! stream, which may be shorter, is written through the page cache.
+//!
+//! On Windows, the files are opened with FILE_FLAG_NO_BUFFERING, which has the same constraints.
+//! On macOS, which has no O_DIRECT, the page cache is bypassed with F_NOCACHE, and the raw disks
+//! like `/dev/rdisk2` require aligned I/O even without `--direct`.
+
+use log::warn;
+use std::alloc::{self, Layout};
+use std::fs::{File, OpenOptions};
+use std::io::{self, IoSliceMut, Read};
+use std::ops::{Deref, DerefMut};
+#[cfg(unix)]
+use std::os::unix::fs::{FileExt as _, OpenOptionsExt as _};
+#[cfg(windows)]
+use std::os::windows::fs::{FileExt as _, OpenOptionsExt as _};
+use std::path::Path;
+use std::ptr::NonNull;
+
+use crate::cli::CommonArgs;
+use crate::engine::IoEngine;
+use crate::error::usage;
+use crate::fsync::SyncMode;
+use crate::{read_block_size, read_physical_block_size};
+
+/// A zeroed buffer, aligned for direct I/O
+pub struct AlignedBuffer {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+// SAFETY: the buffer owns its memory
+unsafe impl Send for AlignedBuffer {}
+
+impl AlignedBuffer {
+    pub fn new(size: usize, alignment: usize) -> Self {
+        let layout = Layout::from_size_align(size.max(1), alignment).expect("a valid alignment");
+        // SAFETY: the layout isn't empty
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        let Some(ptr) = NonNull::new(ptr) else { alloc::handle_alloc_error(layout) };
+        AlignedBuffer { ptr, layout }
+    }
+}
+
+impl Deref for AlignedBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the memory is allocated and initialized
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.layout.size()) }
+    }
+}
+
+impl DerefMut for AlignedBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: the memory is allocated and initialized
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.layout.size()) }
+    }
+}
+
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        // SAFETY: allocated with the same layout
+        unsafe { alloc::dealloc(self.ptr.as_ptr(), self.layout) };
+    }
+}
+
+/// The alignment of the I/O on the file, 1 without `--direct`
+pub fn alignment(common: &CommonArgs, file: &Path) -> anyhow::Result<usize> {
+    #[cfg(target_os = "macos")]
+    if !common.direct && is_raw_disk(file) {
+        return read_block_size(file);
+    }
+    if !common.direct {
+        return Ok(1);
+    }
+    if common.engine == IoEngine::Mmap {
+        return Err(usage("--direct can't be used with the mmap engine"));
+    }
+    // the file may not be created yet
+    let path = if file.exists() {
+        file
+    } else {
+        file.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."))
+    };
+    read_block_size(path)
+}
+
+/// Whether the file is a raw disk, a character device only accepting aligned I/O
+#[cfg(target_os = "macos")]
+fn is_raw_disk(file: &Path) -> bool {
+    use std::os::unix::fs::FileTypeExt as _;
+    std::fs::metadata(file).is_ok_and(|m| m.file_type().is_char_device())
+}
+
+/// Round the chunk size up to the alignment, and check that the stream position is aligned
+pub fn align_chunk_size(
+    chunk_size: usize,
+    position: u64,
+    alignment: usize,
+) -> anyhow::Result<usize> {
+    if !position.is_multiple_of(alignment as u64) {
+        return Err(usage(format!(
+            "The stream position {position} must be a multiple of the logical block size \
+             {alignment} with --direct"
+        )));
+    }
+    let aligned = chunk_size.next_multiple_of(alignment);
+    if aligned != chunk_size {
+        warn!("chunk size rounded up to {aligned}, the logical block size being {alignment}");
+    }
+    Ok(aligned)
+}
+
+/// Warn when the chunks written on the block device aren't aligned on its physical sectors
+///
+/// The device reads, updates and writes back each sector partly written, slowing down the whole
+/// stream.
+pub fn check_physical_alignment(file: &Path, chunk_size: usize, position: u64) {
+    let Some(sector_size) = read_physical_block_size(file) else { return };
+    if !chunk_size.is_multiple_of(sector_size) {
+        warn!(
+            "the chunk size {chunk_size} isn't a multiple of the physical sector size \
+             {sector_size} of the device: its sectors are read, modified and written back"
+        );
+    }
+    if !position.is_multiple_of(sector_size as u64) {
+        warn!(
+            "the stream position {position} isn't a multiple of the physical sector size \
+             {sector_size} of the device: its sectors are read, modified and written back"
+        );
+    }
+}
+
+/// Open the file for reading or writing, with O_DIRECT if the alignment isn't 1
+pub fn open(
+    path: &Path,
+    write: bool,
+    alignment: usize,
+    sync: Option<SyncMode>,
+) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.read(!write).write(write);
+    let mut flags = sync.map(SyncMode::flag).unwrap_or(0);
+    if alignment > 1 {
+        #[cfg(all(unix, not(target_os = "macos")))]
+        {
+            flags |= nix::libc::O_DIRECT;
+        }
+        #[cfg(windows)]
+        {
+            flags |= windows_sys::Win32::Storage::FileSystem::FILE_FLAG_NO_BUFFERING;
+        }
+    }
+    options.custom_flags(flags);
+    let file = options.open(path)?;
+    #[cfg(target_os = "macos")]
+    if alignment > 1 {
+        use std::os::fd::AsRawFd as _;
+        // SAFETY: F_NOCACHE only takes an integer
+        if unsafe { nix::libc::fcntl(file.as_raw_fd(), nix::libc::F_NOCACHE, 1) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(file)
+}
+
+/// Write data which size isn't aligned, through the page cache
+pub fn write_unaligned(path: &Path, data: &[u8], offset: u64) -> io::Result<()> {
+    let file = OpenOptions::new().write(true).open(path)?;
+    write_all_at(&file, data, offset)?;
+    file.sync_data()
+}
+
+/// Write all the data at the offset of the file, without moving its cursor on Unix
+pub fn write_all_at(file: &File, data: &[u8], offset: u64) -> io::Result<()> {
+    #[cfg(unix)]
+    file.write_all_at(data, offset)?;
+    #[cfg(windows)]
+    {
+        let mut written = 0;
+        while written < data.len() {
+            match file.seek_write(&data[written..], offset + written as u64)? {
+                0 => return Err(io::ErrorKind::WriteZero.into()),
+                n => written += n,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Fill the buffer with the data at the offset of the file, without moving its cursor on Unix
+pub fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    #[cfg(unix)]
+    file.read_exact_at(buf, offset)?;
+    #[cfg(windows)]
+    {
+        let mut read = 0;
+        while read < buf.len() {
+            match file.seek_read(&mut buf[read..], offset + read as u64)? {
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                n => read += n,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Fill the buffers, up to the end of the file, and return the number of bytes read
+///
+/// A read ending on an unaligned offset can only be at the end of the file, and isn't followed
+/// by another read, which would fail with O_DIRECT.
+pub fn read_vectored_aligned(
+    reader: &mut impl Read,
+    mut buffers: &mut [IoSliceMut],
+    alignment: usize,
+) -> io::Result<usize> {
+    let mut bytes_read = 0;
+    IoSliceMut::advance_slices(&mut buffers, 0);
+    while !buffers.is_empty() {
+        let n = match reader.read_vectored(buffers) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            result => result?,
+        };
+        bytes_read += n;
+        if n == 0 || !bytes_read.is_multiple_of(alignment) {
+            break;
+        }
+        IoSliceMut::advance_slices(&mut buffers, n);
+    }
+    Ok(bytes_read)
+}
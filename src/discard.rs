@@ -0,0 +1,303 @@
+//! Discard parts of a random stream, and check what's read back
+
+use clap::{Args, ValueEnum};
+use human_units::{FormatDuration as _, FormatSize as _};
+use log::{debug, info, warn};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use crate::direct::{self, AlignedBuffer};
+use crate::error::{Error, ValidationError, exit_code, usage};
+use crate::generate::{GenerateArgs, generate_stream};
+use crate::net;
+use crate::read_block_size;
+use crate::report::{Phase, Report, ThreadStats};
+use crate::rng::{Seed, splitmix64};
+use crate::validate::{locate_corruption, validate_chunk};
+
+/// Generate a random stream in a file, discard some of its chunks, then check them
+///
+/// The chunks are discarded with BLKDISCARD on a block device, or by punching holes in a file.
+/// The discarded chunks must then read as zeros, when the device guarantees it, and all the other
+/// chunks must still match their checksum. Only available on Linux.
+#[derive(Args, Debug)]
+pub struct DiscardTestArgs {
+    #[clap(flatten)]
+    pub generate: GenerateArgs,
+
+    /// The number of ranges of chunks discarded, spread over the stream
+    ///
+    /// Each range is surrounded by chunks which are kept. Their position and length are derived
+    /// from the seed.
+    #[clap(long, default_value = "8", value_parser = clap::value_parser!(u64).range(1..))]
+    pub ranges: u64,
+
+    /// Whether the discarded chunks must read as zeros
+    #[clap(long, value_enum, default_value_t)]
+    pub expect_zeroes: ExpectZeroes,
+}
+
+/// What the discarded chunks must read as
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExpectZeroes {
+    /// Zeros for the holes of a file, and for a device advertising that its discarded blocks read
+    /// as zeros; anything otherwise
+    #[default]
+    Auto,
+    /// Zeros
+    Always,
+    /// Anything, the content is only reported
+    Never,
+}
+
+pub fn discard_test(args: &DiscardTestArgs, cancel: Arc<AtomicBool>) -> Result<i32, Error> {
+    let mut report = Report::new("discard-test", args.generate.common.output);
+    let result = discard_stream(args, cancel, &mut report);
+    report.finish(&result, true);
+    result.map_err(Error::from)
+}
+
+/// What the discarded chunks read as
+#[derive(Debug, Default)]
+struct DiscardedChunks {
+    zeroed: u64,
+    /// Still holding their data, the discard being ignored
+    unchanged: u64,
+    /// Holding anything else
+    other: u64,
+}
+
+fn discard_stream(
+    args: &DiscardTestArgs,
+    cancel: Arc<AtomicBool>,
+    report: &mut Report,
+) -> anyhow::Result<i32> {
+    let generate = &args.generate.with_device_seed()?;
+    let common = &generate.common;
+    let Some(file) = generate.file.as_deref() else {
+        return Err(usage("discard-test needs a file to write the stream to"));
+    };
+    if !generate.more_files.is_empty() {
+        return Err(usage("discard-test takes a single file"));
+    }
+    net::reject_options(
+        "discard-test",
+        &[
+            ("--print-checksum", common.print_checksum),
+            ("--checksum-file", generate.checksum_file.is_some()),
+            ("--duration", common.duration.is_some()),
+        ],
+    )?;
+    if !cfg!(target_os = "linux") {
+        return Err(usage("discard-test is only available on Linux"));
+    }
+
+    info!("writing the stream");
+    let mut written = Report::new("generate", common.output);
+    let result = generate_stream(generate, cancel.clone(), &mut written);
+    report.phases.push(log_phase(written.phase(1)));
+    report.extend_threads(&written);
+    report.bytes += written.bytes;
+    report.checksum = written.checksum;
+    let code = result?;
+    if code != 0 {
+        return Ok(code);
+    }
+
+    let device = is_block_device(file);
+    let alignment = if common.direct { read_block_size(file)? } else { 1 };
+    // the chunk size used by generate, rounded up with --direct
+    let chunk_size = (common.chunk_size as usize).next_multiple_of(alignment);
+    if device {
+        let block_size = read_block_size(file)? as u64;
+        if !(chunk_size as u64).is_multiple_of(block_size)
+            || !generate.position.is_multiple_of(block_size)
+        {
+            return Err(usage(format!(
+                "The chunk size and the stream position must be multiples of the logical block \
+                 size {block_size} to discard chunks"
+            )));
+        }
+    }
+    let ranges = discarded_ranges(written.bytes / chunk_size as u64, args.ranges, generate.seed());
+    if ranges.is_empty() {
+        return Err(usage("The stream must hold at least 3 chunks, to discard one between two"));
+    }
+    let expect_zeroes = match args.expect_zeroes {
+        ExpectZeroes::Auto => !device || discard_zeroes_data(file),
+        ExpectZeroes::Always => true,
+        ExpectZeroes::Never => false,
+    };
+    debug!("discarded chunks expected to read as zeros: {expect_zeroes}");
+
+    let byte_range = |chunks: &Range<u64>| {
+        generate.position + chunks.start * chunk_size as u64
+            ..generate.position + chunks.end * chunk_size as u64
+    };
+    info!("discarding {} ranges", ranges.len());
+    let start = Instant::now();
+    for chunks in &ranges {
+        debug!("discarding the chunks {chunks:?}");
+        discard(file, device, byte_range(chunks))?;
+    }
+    let bytes = ranges.iter().map(|r| byte_range(r).end - byte_range(r).start).sum();
+    report.phases.push(log_phase(Phase {
+        name: "discard",
+        pass: 1,
+        stats: ThreadStats { bytes, elapsed: start.elapsed() },
+        latency: None,
+    }));
+
+    info!("reading the stream back");
+    let start = Instant::now();
+    let reader = direct::open(file, false, alignment, None)?;
+    let mut buffer = AlignedBuffer::new(chunk_size, alignment);
+    let mut checksum = common.checksum.stream_checksum();
+    let mut discarded = DiscardedChunks::default();
+    let mut read = 0;
+    let num_chunks = written.bytes.div_ceil(chunk_size as u64);
+    for chunk in 0..num_chunks {
+        if cancel.load(Ordering::Relaxed) {
+            return Ok(exit_code::INTERRUPTED);
+        }
+        let length = (written.bytes - chunk * chunk_size as u64).min(chunk_size as u64) as usize;
+        let offset = generate.position + chunk * chunk_size as u64;
+        // a short chunk at the end of the stream is read in full with O_DIRECT
+        let data = &mut buffer[..length.next_multiple_of(alignment)];
+        direct::read_exact_at(&reader, data, offset)?;
+        let data = &data[..length];
+        read += length as u64;
+        if !ranges.iter().any(|r| r.contains(&chunk)) {
+            validate_chunk(chunk, data, &mut checksum)
+                .map_err(|e| locate_corruption(e, chunk, offset, length))?;
+        } else if data.iter().all(|&b| b == 0) {
+            discarded.zeroed += 1;
+        } else if expect_zeroes {
+            let error = ValidationError::NotZeroed { chunk, offset };
+            return Err(locate_corruption(error.into(), chunk, offset, length));
+        } else if validate_chunk(chunk, data, &mut checksum).is_ok() {
+            discarded.unchanged += 1;
+        } else {
+            discarded.other += 1;
+        }
+    }
+    report.phases.push(log_phase(Phase {
+        name: "validate",
+        pass: 1,
+        stats: ThreadStats { bytes: read, elapsed: start.elapsed() },
+        latency: None,
+    }));
+    report.bytes += read;
+    info!(
+        "discarded chunks: {} zeroed, {} unchanged, {} other",
+        discarded.zeroed, discarded.unchanged, discarded.other
+    );
+    if discarded.unchanged > 0 {
+        warn!("the device has ignored some discards");
+    }
+    Ok(0)
+}
+
+/// The chunks discarded: a run of chunks in each of `count` parts of the stream, with at least one
+/// chunk kept on each side
+fn discarded_ranges(num_chunks: u64, count: u64, seed: Seed) -> Vec<Range<u64>> {
+    let count = count.min(num_chunks / 3);
+    let mut state = seed.fingerprint();
+    (0..count)
+        .map(|i| {
+            let part = i * num_chunks / count..(i + 1) * num_chunks / count;
+            // the chunks which may be discarded, without the first and the last ones of the part
+            let room = part.end - part.start - 2;
+            let len = 1 + splitmix64(&mut state) % room.div_ceil(2);
+            let start = part.start + 1 + splitmix64(&mut state) % (room - len + 1);
+            start..start + len
+        })
+        .collect()
+}
+
+/// Log the throughput of a phase, and return it for the report
+fn log_phase(phase: Phase) -> Phase {
+    info!(
+        "{}: {} in {}",
+        phase.name,
+        phase.stats.bytes.format_size(),
+        phase.stats.elapsed.format_duration()
+    );
+    phase
+}
+
+#[cfg(unix)]
+fn is_block_device(file: &Path) -> bool {
+    use std::os::unix::fs::FileTypeExt as _;
+    std::fs::metadata(file).is_ok_and(|m| m.file_type().is_block_device())
+}
+
+#[cfg(not(unix))]
+fn is_block_device(_file: &Path) -> bool {
+    false
+}
+
+/// Discard the bytes of the range, with BLKDISCARD on a device or by punching a hole in a file
+#[cfg(target_os = "linux")]
+fn discard(file: &Path, device: bool, range: Range<u64>) -> anyhow::Result<()> {
+    use nix::fcntl::{FallocateFlags, fallocate};
+    use std::os::fd::AsRawFd as _;
+
+    let file = std::fs::OpenOptions::new().write(true).open(file)?;
+    let len = range.end - range.start;
+    if device {
+        // SAFETY: the ioctl only reads the range
+        unsafe { crate::blk::blkdiscard(file.as_raw_fd(), &[range.start, len]) }
+            .map_err(|e| std::io::Error::from_raw_os_error(e as i32))?;
+    } else {
+        let flags = FallocateFlags::FALLOC_FL_PUNCH_HOLE | FallocateFlags::FALLOC_FL_KEEP_SIZE;
+        fallocate(&file, flags, range.start as i64, len as i64)
+            .map_err(|e| std::io::Error::from_raw_os_error(e as i32))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn discard(_file: &Path, _device: bool, _range: Range<u64>) -> anyhow::Result<()> {
+    Err(usage("discard-test is only available on Linux"))
+}
+
+/// The device advertises that its discarded blocks read as zeros
+#[cfg(target_os = "linux")]
+fn discard_zeroes_data(file: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt as _;
+
+    let Ok(metadata) = std::fs::metadata(file) else { return false };
+    let rdev = metadata.rdev();
+    let device = format!("/sys/dev/block/{}:{}", nix::libc::major(rdev), nix::libc::minor(rdev));
+    // a partition shares the queue of its disk
+    ["queue", "../queue"]
+        .iter()
+        .find_map(|queue| {
+            std::fs::read_to_string(Path::new(&device).join(queue).join("discard_zeroes_data")).ok()
+        })
+        .is_some_and(|value| value.trim() == "1")
+}
+
+#[cfg(not(target_os = "linux"))]
+fn discard_zeroes_data(_file: &Path) -> bool {
+    false
+}
+
+#[test]
+fn discarded_ranges_between_kept_chunks() {
+    for (num_chunks, count) in [(3, 1), (10, 3), (1000, 8), (1001, 1000), (2, 1)] {
+        let ranges = discarded_ranges(num_chunks, count, Seed::U64(1));
+        assert_eq!(ranges.len() as u64, count.min(num_chunks / 3));
+        let mut kept = 0;
+        for range in &ranges {
+            assert!(range.start > kept && range.start < range.end, "{ranges:?}");
+            kept = range.end + 1;
+        }
+        assert!(ranges.last().is_none_or(|r| r.end < num_chunks), "{ranges:?}");
+    }
+    assert_eq!(discarded_ranges(3, 8, Seed::U64(1)), vec![1..2]);
+}
@@ -0,0 +1,19 @@
+use clap::ValueEnum;
+
+/// The way the chunks are read from and written to the files
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IoEngine {
+    /// Blocking reads and writes, one chunk at a time
+    #[default]
+    Sync,
+    /// Asynchronous reads and writes through io_uring, several chunks in flight per thread
+    ///
+    /// Only available on Linux.
+    IoUring,
+    /// Reads and writes directly in a memory mapping of the file
+    ///
+    /// It avoids a copy through the buffers, for the memory backed files like tmpfs or pmem.
+    ///
+    /// Only available on Unix.
+    Mmap,
+}
@@ -0,0 +1,395 @@
+//! The classes of failures, and the exit code of each one
+
+use std::fmt;
+use std::io;
+use std::ops::Range;
+
+/// The exit codes, which are stable so the scripts can rely on them
+pub mod exit_code {
+    /// Any other failure
+    pub const FAILURE: i32 = 1;
+    /// A chunk doesn't match its checksum
+    pub const CHUNK_MISMATCH: i32 = 2;
+    /// The checksum or the digest of the stream doesn't match the expected one
+    pub const STREAM_MISMATCH: i32 = 3;
+    /// The file couldn't be read or written
+    pub const IO_ERROR: i32 = 4;
+    /// The command line is invalid
+    pub const USAGE: i32 = 5;
+    /// The run was interrupted
+    pub const INTERRUPTED: i32 = 130;
+}
+
+/// The stream doesn't have the expected content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The checksum at the end of a chunk doesn't match its payload
+    ///
+    /// With `--sector-checksums`, the sectors not matching their own checksum, as ranges of the
+    /// chunk.
+    ChunkChecksum { chunk: u64, expected: u64, found: u64, width: usize, sectors: Vec<Range<u64>> },
+    /// The chunk too short to hold a checksum isn't zeroed
+    NonZeroTail,
+    /// The chunk doesn't start with a header, with `--format v2`
+    MissingChunkHeader { chunk: u64 },
+    /// The chunk holds the data of another chunk of the stream, with `--format v2`
+    ///
+    /// The offsets are the one of the chunk in the file, and the one where its data belongs, if
+    /// that one is inside the stream.
+    Misplaced { chunk: u64, offset: u64, data_offset: Option<u64> },
+    /// The chunk has been written by another run, with the same seed, with `--format v2`
+    Stale { chunk: u64, offset: u64, expected: u64, found: u64 },
+    /// The chunk matches its checksum, but not the data regenerated from the seed
+    DataMismatch { chunk: u64, bytes: u64, first_offset: u64, last_offset: u64 },
+    /// The chunk has been generated with another seed
+    SeedFingerprint { chunk: u64, expected: u64, found: u64 },
+    /// The header of the chunk records another length than the expected one
+    ChunkLength { chunk: u64, expected: u64, found: u64 },
+    /// The stream checksum isn't the expected one
+    StreamChecksum { expected: String, actual: String },
+    /// The stream digest isn't the expected one
+    Digest { expected: String, actual: String },
+    /// Some chunks are corrupted or misplaced, with `--keep-going`
+    Corrupted { chunks: usize, misplaced: usize, stale: usize },
+    /// Some datagrams are lost or corrupted, with a `udp://` address
+    Datagrams { lost: u64, corrupted: u64 },
+    /// A discarded chunk doesn't read as zeros, with `discard-test`
+    NotZeroed { chunk: u64, offset: u64 },
+    /// The chunk read back isn't the data just written, with `--verify-after-write`
+    ReadBack { chunk: u64, bytes: u64, first_offset: u64, last_offset: u64 },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValidationError::ChunkChecksum { chunk, expected, found, width, sectors } => {
+                write!(
+                    f,
+                    "Invalid checksum at chunk {chunk}. Expected {expected:0digits$x}, found \
+                     {found:0digits$x}.",
+                    digits = width * 2
+                )?;
+                if !sectors.is_empty() {
+                    let sectors: Vec<_> =
+                        sectors.iter().map(|s| format!("{}..{}", s.start, s.end)).collect();
+                    write!(f, " Damaged sectors at bytes {} of the chunk.", sectors.join(", "))?;
+                }
+                Ok(())
+            }
+            ValidationError::NonZeroTail => {
+                write!(f, "Invalid non-zero value at the end of the file")
+            }
+            ValidationError::MissingChunkHeader { chunk } => {
+                write!(f, "Missing header at chunk {chunk}.")
+            }
+            ValidationError::Misplaced { chunk, offset, data_offset: Some(data_offset) } => write!(
+                f,
+                "Misplaced chunk {chunk} at offset {offset}: contains data for offset \
+                 {data_offset}."
+            ),
+            ValidationError::Misplaced { chunk, offset, data_offset: None } => write!(
+                f,
+                "Misplaced chunk {chunk} at offset {offset}: contains data from outside the \
+                 stream."
+            ),
+            ValidationError::Stale { chunk, offset, expected, found } => write!(
+                f,
+                "Stale chunk {chunk} at offset {offset}: written by the run {found:016x}, \
+                 instead of {expected:016x}."
+            ),
+            ValidationError::DataMismatch { chunk, bytes, first_offset, last_offset } => write!(
+                f,
+                "Chunk {chunk} doesn't match the regenerated data: {bytes} bytes differ, from \
+                 offset {first_offset} to {last_offset}."
+            ),
+            ValidationError::SeedFingerprint { chunk, expected, found } => write!(
+                f,
+                "Chunk {chunk} comes from another stream. Expected the seed fingerprint \
+                 {expected:016x}, found {found:016x}."
+            ),
+            ValidationError::ChunkLength { chunk, expected, found } => {
+                write!(f, "Invalid length at chunk {chunk}. Expected {expected}, found {found}.")
+            }
+            ValidationError::StreamChecksum { expected, actual } => write!(
+                f,
+                "Checksum mismatch. It was expected to be {expected}, but is actually {actual}"
+            ),
+            ValidationError::Digest { expected, actual } => write!(
+                f,
+                "Digest mismatch. It was expected to be {expected}, but is actually {actual}"
+            ),
+            ValidationError::Corrupted { chunks, misplaced, stale } => {
+                write!(f, "{} corrupted chunks", chunks - misplaced - stale)?;
+                if *misplaced > 0 {
+                    write!(f, ", {misplaced} misplaced chunks")?;
+                }
+                if *stale > 0 {
+                    write!(f, ", {stale} stale chunks")?;
+                }
+                Ok(())
+            }
+            ValidationError::Datagrams { lost, corrupted } => {
+                write!(f, "{lost} lost datagrams, {corrupted} corrupted datagrams")
+            }
+            ValidationError::NotZeroed { chunk, offset } => {
+                write!(f, "Discarded chunk {chunk} at offset {offset} doesn't read as zeros.")
+            }
+            ValidationError::ReadBack { chunk, bytes, first_offset, last_offset } => write!(
+                f,
+                "Chunk {chunk} doesn't read back as written: {bytes} bytes differ, from offset \
+                 {first_offset} to {last_offset}."
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl ValidationError {
+    /// The exit code of the validation failing with this error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ValidationError::ChunkChecksum { .. }
+            | ValidationError::NonZeroTail
+            | ValidationError::MissingChunkHeader { .. }
+            | ValidationError::Misplaced { .. }
+            | ValidationError::Stale { .. }
+            | ValidationError::DataMismatch { .. }
+            | ValidationError::SeedFingerprint { .. }
+            | ValidationError::ChunkLength { .. }
+            | ValidationError::Corrupted { .. }
+            | ValidationError::Datagrams { .. }
+            | ValidationError::NotZeroed { .. }
+            | ValidationError::ReadBack { .. } => exit_code::CHUNK_MISMATCH,
+            ValidationError::StreamChecksum { .. } | ValidationError::Digest { .. } => {
+                exit_code::STREAM_MISMATCH
+            }
+        }
+    }
+}
+
+/// A chunk which failed the validation, with `--keep-going`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorruptedChunk {
+    pub chunk: u64,
+    /// The offset of the chunk in the file
+    pub offset: u64,
+    pub length: u64,
+    pub error: ValidationError,
+}
+
+impl CorruptedChunk {
+    /// The ranges of the file damaged, the sectors not matching their checksum if known, or else
+    /// the whole chunk
+    pub fn damaged_ranges(&self) -> Vec<Range<u64>> {
+        match &self.error {
+            ValidationError::ChunkChecksum { sectors, .. } if !sectors.is_empty() => sectors
+                .iter()
+                .map(|sector| self.offset + sector.start..self.offset + sector.end)
+                .collect(),
+            _ => std::iter::once(self.offset..self.offset + self.length).collect(),
+        }
+    }
+}
+
+impl fmt::Display for CorruptedChunk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl std::error::Error for CorruptedChunk {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// The ranges of the file covered by the corrupted chunks, or by their damaged sectors with
+/// `--sector-checksums`, sorted by offset
+///
+/// The consecutive ranges are merged in a single one.
+pub fn corrupted_ranges(chunks: &[CorruptedChunk]) -> Vec<Range<u64>> {
+    let mut ranges: Vec<Range<u64>> = Vec::new();
+    for range in chunks.iter().flat_map(CorruptedChunk::damaged_ranges) {
+        match ranges.last_mut() {
+            Some(last) if last.end == range.start => last.end = range.end,
+            _ => ranges.push(range),
+        }
+    }
+    ranges
+}
+
+/// The command line is invalid, or the options don't apply to the file
+#[derive(Debug)]
+pub struct UsageError(pub String);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+/// A usage error, with the given message
+pub(crate) fn usage(message: impl Into<String>) -> anyhow::Error {
+    UsageError(message.into()).into()
+}
+
+/// The exit code matching the class of the error
+pub fn exit_code(error: &anyhow::Error) -> i32 {
+    for cause in error.chain() {
+        if let Some(e) = cause.downcast_ref::<ValidationError>() {
+            return e.exit_code();
+        }
+        if cause.is::<UsageError>() {
+            return exit_code::USAGE;
+        }
+        if cause.is::<io::Error>() {
+            return exit_code::IO_ERROR;
+        }
+    }
+    exit_code::FAILURE
+}
+
+/// The failure of a run of the library API, by class
+#[derive(Debug)]
+pub enum Error {
+    /// The file or the connection couldn't be read or written
+    Io(io::Error),
+    /// A chunk doesn't have the expected content
+    ChunkMismatch(CorruptedChunk),
+    /// The stream is corrupted, like with `--keep-going`, without a single chunk to point at
+    Corruption(ValidationError),
+    /// The checksum or the digest of the stream isn't the expected one
+    StreamMismatch(ValidationError),
+    /// The options are invalid, or don't apply to the file
+    Config(String),
+    Other(String),
+}
+
+impl Error {
+    /// The exit code of the command failing with this error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Io(_) => exit_code::IO_ERROR,
+            Error::ChunkMismatch(_) | Error::Corruption(_) => exit_code::CHUNK_MISMATCH,
+            Error::StreamMismatch(_) => exit_code::STREAM_MISMATCH,
+            Error::Config(_) => exit_code::USAGE,
+            Error::Other(_) => exit_code::FAILURE,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => e.fmt(f),
+            Error::ChunkMismatch(chunk) => chunk.fmt(f),
+            Error::Corruption(e) | Error::StreamMismatch(e) => e.fmt(f),
+            Error::Config(message) | Error::Other(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::ChunkMismatch(chunk) => Some(&chunk.error),
+            Error::Corruption(e) | Error::StreamMismatch(e) => Some(e),
+            Error::Config(_) | Error::Other(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+/// The class of the errors raised inside the crate
+impl From<anyhow::Error> for Error {
+    fn from(error: anyhow::Error) -> Self {
+        let error = match error.downcast::<CorruptedChunk>() {
+            Ok(chunk) => return Error::ChunkMismatch(chunk),
+            Err(error) => error,
+        };
+        let error = match error.downcast::<ValidationError>() {
+            Ok(e) if e.exit_code() == exit_code::STREAM_MISMATCH => {
+                return Error::StreamMismatch(e);
+            }
+            Ok(e) => return Error::Corruption(e),
+            Err(error) => error,
+        };
+        let error = match error.downcast::<UsageError>() {
+            Ok(UsageError(message)) => return Error::Config(message),
+            Err(error) => error,
+        };
+        match error.downcast::<io::Error>() {
+            Ok(e) => Error::Io(e),
+            Err(error) => Error::Other(error.to_string()),
+        }
+    }
+}
+
+#[test]
+fn exit_code_of_the_errors() {
+    let chunk = ValidationError::ChunkChecksum {
+        chunk: 3,
+        expected: 0x12,
+        found: 0x34,
+        width: 4,
+        sectors: Vec::new(),
+    };
+    assert_eq!(
+        chunk.to_string(),
+        "Invalid checksum at chunk 3. Expected 00000012, found 00000034."
+    );
+    assert_eq!(exit_code(&chunk.into()), exit_code::CHUNK_MISMATCH);
+    let digest = ValidationError::Digest { expected: "ab".into(), actual: "cd".into() };
+    assert_eq!(exit_code(&digest.into()), exit_code::STREAM_MISMATCH);
+    let io = anyhow::Error::from(io::Error::from(io::ErrorKind::NotFound)).context("opening");
+    assert_eq!(exit_code(&io), exit_code::IO_ERROR);
+    assert_eq!(exit_code(&usage("bad option")), exit_code::USAGE);
+    assert_eq!(exit_code(&anyhow::anyhow!("other")), exit_code::FAILURE);
+}
+
+#[test]
+fn class_of_the_errors() {
+    let error = ValidationError::ChunkChecksum {
+        chunk: 3,
+        expected: 0x12,
+        found: 0x34,
+        width: 4,
+        sectors: Vec::new(),
+    };
+    let chunk = CorruptedChunk { chunk: 3, offset: 300, length: 100, error: error.clone() };
+    let converted = Error::from(anyhow::Error::from(chunk.clone()));
+    assert!(matches!(&converted, Error::ChunkMismatch(c) if *c == chunk));
+    assert_eq!(converted.to_string(), error.to_string());
+    assert_eq!(exit_code(&chunk.into()), exit_code::CHUNK_MISMATCH);
+    let digest = ValidationError::Digest { expected: "ab".into(), actual: "cd".into() };
+    assert!(matches!(Error::from(anyhow::Error::from(digest)), Error::StreamMismatch(_)));
+    let io = io::Error::from(io::ErrorKind::NotFound);
+    assert!(matches!(Error::from(anyhow::Error::from(io)), Error::Io(_)));
+    let converted = Error::from(usage("bad option"));
+    assert!(matches!(&converted, Error::Config(message) if message == "bad option"));
+    assert_eq!(converted.exit_code(), exit_code::USAGE);
+    let converted = Error::from(anyhow::anyhow!("other"));
+    assert_eq!((converted.exit_code(), converted.to_string()), (1, "other".to_string()));
+}
+
+#[test]
+fn consecutive_corrupted_chunks_are_merged() {
+    let chunk = |chunk, length| CorruptedChunk {
+        chunk,
+        offset: chunk * 100,
+        length,
+        error: ValidationError::NonZeroTail,
+    };
+    let chunks = [chunk(1, 100), chunk(2, 100), chunk(5, 100), chunk(6, 10)];
+    assert_eq!(corrupted_ranges(&chunks), [100..300, 500..610]);
+    assert!(corrupted_ranges(&[]).is_empty());
+}
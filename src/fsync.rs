@@ -0,0 +1,98 @@
+//! The flush of the written data to the media, with `--fsync-every`, `--sync` or `--dsync`
+
+use parse_size::parse_size;
+use std::fs::File;
+use std::io;
+
+/// How much data a thread writes between two flushes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FsyncInterval {
+    Bytes(u64),
+    Chunks(u64),
+}
+
+impl FsyncInterval {
+    /// Parse a size, like `64Mi`, or a number of chunks, like `16chunks`
+    pub fn parse(s: &str) -> Result<FsyncInterval, String> {
+        let interval = match s.strip_suffix("chunks").or_else(|| s.strip_suffix("chunk")) {
+            Some(chunks) => {
+                FsyncInterval::Chunks(chunks.trim().parse::<u64>().map_err(|e| e.to_string())?)
+            }
+            None => FsyncInterval::Bytes(parse_size(s).map_err(|e| e.to_string())?),
+        };
+        match interval {
+            FsyncInterval::Bytes(0) | FsyncInterval::Chunks(0) => {
+                Err("the interval must be greater than 0".to_string())
+            }
+            interval => Ok(interval),
+        }
+    }
+}
+
+/// The synchronous I/O mode the output file is opened with, so each write is durable when it
+/// returns
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncMode {
+    /// O_SYNC: the data and all the metadata are flushed
+    Sync,
+    /// O_DSYNC: the data and the metadata needed to read it back are flushed
+    Dsync,
+}
+
+impl SyncMode {
+    #[cfg(unix)]
+    pub fn flag(self) -> i32 {
+        match self {
+            SyncMode::Sync => nix::libc::O_SYNC,
+            SyncMode::Dsync => nix::libc::O_DSYNC,
+        }
+    }
+
+    /// Windows only has a write through mode, flushing the data and the metadata
+    #[cfg(windows)]
+    pub fn flag(self) -> u32 {
+        windows_sys::Win32::Storage::FileSystem::FILE_FLAG_WRITE_THROUGH
+    }
+}
+
+/// Accounts for the data written by a thread since the last flush
+pub struct FsyncTracker {
+    interval: Option<FsyncInterval>,
+    bytes: u64,
+    chunks: u64,
+}
+
+impl FsyncTracker {
+    pub fn new(interval: Option<FsyncInterval>) -> Self {
+        FsyncTracker { interval, bytes: 0, chunks: 0 }
+    }
+
+    /// Account for `chunks` chunks of `bytes` bytes written, and flush the file once the interval
+    /// is reached
+    pub fn written(&mut self, file: &File, bytes: u64, chunks: u64) -> io::Result<()> {
+        self.bytes += bytes;
+        self.chunks += chunks;
+        let reached = match self.interval {
+            Some(FsyncInterval::Bytes(interval)) => self.bytes >= interval,
+            Some(FsyncInterval::Chunks(interval)) => self.chunks >= interval,
+            None => false,
+        };
+        if reached {
+            file.sync_data()?;
+            self.bytes = 0;
+            self.chunks = 0;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn parse_fsync_interval() {
+    assert_eq!(FsyncInterval::parse("64Mi"), Ok(FsyncInterval::Bytes(64 << 20)));
+    assert_eq!(FsyncInterval::parse("4096"), Ok(FsyncInterval::Bytes(4096)));
+    assert_eq!(FsyncInterval::parse("16chunks"), Ok(FsyncInterval::Chunks(16)));
+    assert_eq!(FsyncInterval::parse("1chunk"), Ok(FsyncInterval::Chunks(1)));
+    assert!(FsyncInterval::parse("0chunks").is_err());
+    assert!(FsyncInterval::parse("0").is_err());
+    assert!(FsyncInterval::parse("xchunks").is_err());
+}
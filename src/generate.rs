@@ -1,95 +1,815 @@
 use anyhow::anyhow;
-use clap::Args;
-use crc32fast::Hasher;
+use clap::{ArgGroup, Args, ValueEnum as _, ValueHint};
+use human_units::FormatDuration as _;
 use itertools::Itertools as _;
 use log::{debug, info};
 use parse_size::parse_size;
-use rand::Rng as _;
-use rand::SeedableRng;
-use rand_pcg::Pcg64Mcg;
-use std::fs::OpenOptions;
-use std::io::{self, Seek as _, Write};
-use std::path::PathBuf;
+use std::fs::{File, OpenOptions};
+use std::io::{self, IoSlice, Seek as _, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, mpsc};
 use std::thread;
-use std::time::Instant;
+use std::time::{Instant, SystemTime};
 
-use crate::cli::CommonArgs;
-use crate::{Progress, log_metrics, read_file_size, receive_progress};
+#[cfg(unix)]
+use crate::cache::Advice;
+use crate::cache::{self, CachePolicy};
+use crate::checkpoint::{Checkpoint, SavedCheckpoint};
+use crate::checksum::ChecksumAlgorithm;
+use crate::chunk::{CHUNK_HEADER_SIZE, ChunkFormat, ChunkHeader, parse_run_id, region_fingerprint};
+use crate::cli::{AUTO_CHUNK_SIZE, CommonArgs};
+use crate::compress::{BLOCK_SIZE, Compressibility};
+use crate::dedupe::Dedupe;
+use crate::devices;
+use crate::diff::ChunkDiff;
+use crate::direct::{self, AlignedBuffer};
+use crate::engine::IoEngine;
+use crate::error::{Error, ValidationError, exit_code, usage};
+use crate::fsync::{FsyncInterval, FsyncTracker, SyncMode};
+use crate::header::{HEADER_SIZE, StreamHeader};
+use crate::http::{self, HttpMethod, Upload};
+use crate::identity;
+use crate::inuse;
+use crate::journal::{Journal, Operation};
+#[cfg(unix)]
+use crate::mapping::{self, FileMapping};
+use crate::nbd::{ExportStream, NbdUri};
+use crate::net::{self, Endpoint, Scheme};
+use crate::pattern::Pattern;
+use crate::report::Report;
+use crate::rng::{RngAlgorithm, Seed, StreamRng};
+use crate::s3::S3Object;
+use crate::sector;
+use crate::shard::{Shards, parse_shard_size};
+use crate::ssh::SshTarget;
+use crate::sumfile::ChecksumFile;
+use crate::tar;
+use crate::throttle::Throttle;
+use crate::tree;
+use crate::udp::{DatagramWriter, MAX_DATAGRAM_SIZE};
+#[cfg(target_os = "linux")]
+use crate::uring::BufferQueue;
+use crate::validate::locate_corruption;
+use crate::watch::TargetWatch;
+use crate::work::{
+    self, Assignment, ChunkRecorder, Summarizer, ThreadOutput, ThreadPositions, ThreadWork,
+};
+use crate::{
+    ChunkChecksum, Progress, ProgressPhase, SeekableRng, StreamSummary, combine_summaries,
+    log_metrics, read_file_size, receive_progress, write_all_vectored,
+};
 
 /// Describes the logical random stream being generated
 #[derive(Clone, Debug)]
 struct StreamParams {
-    seed: u64,
+    rng: RngAlgorithm,
+    seed: Seed,
     position: u64,
     stream_size: u64,
     chunk_size: usize,
     buffer_size: usize,
+    /// The alignment of the writes, for direct I/O
+    alignment: usize,
+    cache: CachePolicy,
+    /// The number of consecutive chunks written at once
+    batch_chunks: usize,
+    throttle: Option<Throttle>,
+    fsync_every: Option<FsyncInterval>,
+    sync: Option<SyncMode>,
+    format: ChunkFormat,
+    /// The fingerprint of the seed, recorded in the chunk headers
+    fingerprint: u64,
+    /// The number of chunks of each region with its own seed, with `--region-size`
+    region_chunks: Option<u64>,
+    /// The size of the sectors sealed by their own checksum, with `--sector-checksums`
+    sector_size: Option<usize>,
+    /// The ID of the run, recorded in the chunk headers
+    run_id: u64,
+    compressibility: Option<Compressibility>,
+    dedupe: Option<Dedupe>,
+    pattern: Option<Pattern>,
+    /// The index in the stream of the first chunk of the file, with `--shard-size`
+    first_chunk: u64,
+    /// Read each chunk back once written, with `--verify-after-write`
+    verify_after_write: bool,
+    journal: Option<Journal>,
 }
 
-/// Describes the work slice assigned to one thread
-#[derive(Clone, Debug)]
-struct ThreadWork {
-    thread_index: u64,
-    chunks_per_thread: u64,
-    num_chunks: u64,
+impl StreamParams {
+    /// Identifies the stream in its checkpoint
+    fn identity(&self, checksum: ChecksumAlgorithm) -> String {
+        let mut identity = format!(
+            "generate rng={} checksum={} format={} position={} size={} chunk={}",
+            self.rng.to_possible_value().unwrap().get_name(),
+            checksum.to_possible_value().unwrap().get_name(),
+            self.format.to_possible_value().unwrap().get_name(),
+            self.position,
+            self.stream_size,
+            self.chunk_size
+        );
+        if let Some(compressibility) = self.compressibility {
+            identity += &format!(" random-bytes={}", compressibility.random_bytes());
+        }
+        if let Some(dedupe) = self.dedupe {
+            identity += &format!(" dedupe={}", dedupe.ratio());
+        }
+        if let Some(pattern) = self.pattern {
+            identity += &format!(" pattern={}", pattern.to_possible_value().unwrap().get_name());
+        }
+        if let Some(region_chunks) = self.region_chunks {
+            identity += &format!(" region-chunks={region_chunks}");
+        }
+        if let Some(sector_size) = self.sector_size {
+            identity += &format!(" sector-size={sector_size}");
+        }
+        identity
+    }
+
+    /// The random generator, positioned at the first chunk of the file
+    fn stream_rng(&self) -> StreamRng {
+        let mut rng = match self.region_chunks {
+            Some(chunks) => self.rng.region_rng(self.seed, chunks * self.buffer_size as u64),
+            None => self.rng.rng(self.seed),
+        };
+        rng.advance(self.first_chunk * self.buffer_size as u64);
+        rng
+    }
+
+    /// The layout of the chunk of the file, with its header with `--format v2`
+    fn chunk_layout(&self, chunk: u64, write_size: usize) -> ChunkLayout {
+        let chunk = self.first_chunk + chunk;
+        ChunkLayout {
+            header: (self.format == ChunkFormat::V2).then_some(ChunkHeader {
+                index: chunk,
+                fingerprint: match self.region_chunks {
+                    Some(region_chunks) => {
+                        region_fingerprint(self.fingerprint, chunk / region_chunks)
+                    }
+                    None => self.fingerprint,
+                },
+                length: write_size as u64,
+                run_id: self.run_id,
+            }),
+            compressibility: self.compressibility,
+            dedupe: self.dedupe,
+            pattern: self.pattern,
+            sector_size: self.sector_size,
+            offset: chunk * self.chunk_size as u64,
+        }
+    }
+
+    /// The range of the file written by a thread
+    fn file_range(&self, work: &ThreadWork) -> Range<u64> {
+        let range = work.byte_range(self.chunk_size, self.stream_size);
+        self.position + range.start..self.position + range.end
+    }
 }
 
 /// Generate a random stream
-#[derive(Args, Debug)]
-#[command(alias = "write")]
+#[derive(Args, Clone, Debug)]
+#[clap(group(ArgGroup::new("many_files").args(["tree", "tar"])))]
 pub struct GenerateArgs {
-    /// The output file
+    /// The output file, a `tcp://host:port` or `udp://host:port` address to send the stream to, an
+    /// `nbd://host[:port]/export` or `nbd+unix:///export?socket=path` export to write, an
+    /// `http://` or `https://` URL to upload the stream to, an `s3://bucket/key` object, or an
+    /// `ssh://[user@]host[:port]/path` remote file
     #[arg()]
     pub file: Option<PathBuf>,
 
+    /// More files or devices, written at once with the first one, each with its own stream
+    ///
+    /// The seed of each one is derived from the seed, the first file using the seed itself. A
+    /// table of the result of each file is logged at the end.
+    #[arg(value_name = "FILES", conflicts_with_all = ["checkpoint", "shard_size", "checksum_file"])]
+    pub more_files: Vec<PathBuf>,
+
+    /// Populate this directory with many files, each with its own stream, instead of writing a
+    /// single stream
+    ///
+    /// The files are spread in subdirectories of 256 files, like `DIR/0001/00000300.bin`. The
+    /// seed of each one is derived from the seed, like with several files. `--jobs` files are
+    /// written at once, each by a thread.
+    #[clap(
+        long,
+        value_name = "DIR",
+        value_hint = ValueHint::DirPath,
+        conflicts_with_all = [
+            "file", "position", "size", "random_seed", "seed_from_device", "checkpoint",
+            "checksum_file", "shard_size", "print_checksum"
+        ]
+    )]
+    pub tree: Option<PathBuf>,
+
+    /// Write a tar archive of that number of files on stdout, instead of a single stream
+    ///
+    /// The files are the ones of `--tree`, with the same paths, sizes and seeds, so the archive
+    /// can be extracted, then validated with `validate --manifest`. `validate --tar` validates
+    /// each file of the archive.
+    #[clap(
+        long,
+        value_name = "COUNT",
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = [
+            "file", "position", "size", "random_seed", "seed_from_device", "checkpoint",
+            "checksum_file", "shard_size", "print_checksum"
+        ]
+    )]
+    pub tar: Option<u64>,
+
+    /// The number of files of the tree
+    #[clap(long, default_value = "100", requires = "tree", value_parser = clap::value_parser!(u64).range(1..))]
+    pub files: u64,
+
+    /// The sizes of the files of the tree or the archive, like `1Mi..1Gi`, spread evenly across
+    /// the orders of magnitude, or a single size
+    #[clap(long, default_value = "4Ki..1Mi", value_name = "MIN..MAX", requires = "many_files", value_parser = tree::parse_size_range)]
+    pub file_size_dist: (u64, u64),
+
+    /// Write the checksum and the size of each file of the tree or the archive to this manifest,
+    /// read by `validate --manifest`
+    #[clap(long, value_name = "FILE", value_hint = ValueHint::FilePath, requires = "many_files")]
+    pub manifest: Option<PathBuf>,
+
     /// The stream position
-    #[clap(short, long, default_value = "0", value_parser=|s: &str| parse_size(s), requires="file")]
+    #[clap(short, long, default_value = "0", value_name = "SIZE", value_hint = ValueHint::Other, value_parser=|s: &str| parse_size(s), requires="file")]
     pub position: u64,
 
     /// The random generator seed
-    #[clap(short = 'S', long, default_value = "0")]
-    pub seed: u64,
+    ///
+    /// A decimal value, or an hexadecimal value of up to 256 bits prefixed with `0x`
+    #[clap(short = 'S', long, default_value = "0", value_parser = Seed::parse)]
+    pub seed: Seed,
+
+    /// Derive the random generator seed from an arbitrary string, like a ticket ID or a hostname
+    #[clap(long, conflicts_with = "seed")]
+    pub seed_string: Option<String>,
+
+    /// Pick a random seed, and record it in a header at the start of the stream
+    ///
+    /// The header is read back by validate, so the stream can be reproduced later.
+    #[clap(long, conflicts_with_all = ["seed", "seed_string"])]
+    pub random_seed: bool,
+
+    /// Derive the random generator seed from the WWN or serial number of the device
+    ///
+    /// Each disk gets its own stream, with no seed to keep track of, and the stream of a disk
+    /// isn't validated on another one, with `validate --seed-from-device`. Only on Linux.
+    #[clap(long, requires = "file", conflicts_with_all = ["seed", "seed_string", "random_seed"])]
+    pub seed_from_device: bool,
 
     /// Don't truncate the file
     #[clap(short = 't', long)]
     pub no_truncate: bool,
 
+    /// Write to the block device even if it's in use: mounted, used as swap, held by an md array
+    /// or an LVM volume, or opened exclusively, like by ZFS, or if one of its partitions is
+    #[clap(long)]
+    pub force: bool,
+
+    /// Allocate the space of the whole stream in the file, before writing it
+    ///
+    /// The file system can allocate contiguous extents, and a lack of space is reported before
+    /// writing anything, instead of in the middle of the run.
+    #[clap(long, requires = "file")]
+    pub preallocate: bool,
+
+    /// Flush the data to the media each time a thread has written the given size, like `64Mi`, or
+    /// number of chunks, like `16chunks`
+    #[clap(long, value_parser = FsyncInterval::parse, requires = "file")]
+    pub fsync_every: Option<FsyncInterval>,
+
+    /// Flush the data to the media at the end, so the throughput reported is the durable one
+    #[clap(long, requires = "file")]
+    pub fsync_at_end: bool,
+
+    /// Open the file with O_SYNC, so each write is durable, with its metadata, when it returns
+    #[clap(long, requires = "file", conflicts_with = "dsync")]
+    pub sync: bool,
+
+    /// Open the file with O_DSYNC, so each write is durable when it returns
+    #[clap(long, requires = "file")]
+    pub dsync: bool,
+
+    /// Read each chunk back right after writing it, and stop at the first one differing from the
+    /// data written
+    ///
+    /// Without `--direct`, the chunk is flushed and dropped from the page cache first, so it's
+    /// read from the device. Only with the sync engine.
+    #[clap(long, requires = "file")]
+    pub verify_after_write: bool,
+
+    /// Record the progress in this file, and resume from it if it exists
+    ///
+    /// The chunks done by each thread are saved every few seconds, once flushed to the media, so
+    /// an interrupted run can be resumed without writing them again. The checkpoint is removed
+    /// once the stream is complete.
+    #[clap(long, requires = "file", conflicts_with = "digest")]
+    pub checkpoint: Option<PathBuf>,
+
+    /// Write the checksum of the stream to this file, with the parameters needed to validate it
+    ///
+    /// `validate --checksum-file` reads them back, instead of passing them by hand.
+    #[clap(long)]
+    pub checksum_file: Option<PathBuf>,
+
+    /// The ID of the run, recorded in the chunk headers with `--format v2`
+    ///
+    /// Up to 16 hexadecimal digits. Defaults to a random ID, logged so it can be passed to
+    /// validate.
+    #[clap(long, value_parser = parse_run_id)]
+    pub run_id: Option<u64>,
+
+    /// Split the stream across several files of that size
+    ///
+    /// The file name holds the index of the files, like `out-%04d.bin`. The size must be a
+    /// multiple of the chunk size, the last file holding the rest of the stream.
+    #[clap(
+        long,
+        value_parser = parse_shard_size,
+        requires_all = ["file", "size"],
+        conflicts_with_all = ["position", "random_seed", "checkpoint", "digest"]
+    )]
+    pub shard_size: Option<u64>,
+
+    /// The number of times the connection to a `tcp://` address is retried, a second apart
+    #[clap(long, default_value = "10")]
+    pub connect_retries: u32,
+
+    /// The method of the request uploading the stream to an `http://` or `https://` URL
+    #[clap(long, value_enum, default_value_t)]
+    pub http_method: HttpMethod,
+
     #[clap(flatten)]
     pub common: CommonArgs,
 }
 
-pub fn generate(args: &GenerateArgs, cancel: Arc<AtomicBool>) -> anyhow::Result<i32> {
+impl GenerateArgs {
+    pub fn seed(&self) -> Seed {
+        self.seed_string.as_deref().map(Seed::from_string).unwrap_or(self.seed)
+    }
+
+    /// The arguments with the seed derived from the device, with `--seed-from-device`
+    pub(crate) fn with_device_seed(&self) -> anyhow::Result<GenerateArgs> {
+        match &self.file {
+            Some(file) if self.seed_from_device => Ok(GenerateArgs {
+                seed: identity::device_seed(file)?,
+                seed_string: None,
+                seed_from_device: false,
+                ..self.clone()
+            }),
+            _ => Ok(self.clone()),
+        }
+    }
+
+    /// All the files to write, with `FILES`
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.file.iter().chain(&self.more_files).cloned().collect()
+    }
+
+    /// The arguments writing one of the files, with its own seed
+    pub fn for_device(&self, index: usize, file: &Path) -> GenerateArgs {
+        GenerateArgs {
+            file: Some(file.to_path_buf()),
+            more_files: Vec::new(),
+            seed: self.seed().for_device(index),
+            seed_string: None,
+            common: self.common.for_device(file),
+            ..self.clone()
+        }
+    }
+
+    pub fn sync_mode(&self) -> Option<SyncMode> {
+        if self.sync {
+            Some(SyncMode::Sync)
+        } else if self.dsync {
+            Some(SyncMode::Dsync)
+        } else {
+            None
+        }
+    }
+}
+
+pub fn generate(args: &GenerateArgs, cancel: Arc<AtomicBool>) -> Result<i32, Error> {
+    if args.common.print_checksum {
+        if args.file.is_none() {
+            return Err(usage(
+                "--print-checksum requires a file, the stream being written on stdout",
+            )
+            .into());
+        }
+        if !args.more_files.is_empty() {
+            return Err(usage("--print-checksum takes a single file").into());
+        }
+    }
+    if args.tree.is_some() || args.tar.is_some() {
+        net::reject_options("--tree and --tar", &[("--duration", args.common.duration.is_some())])?;
+    }
+    if args.tree.is_some() {
+        return tree::generate_tree(args, cancel).map_err(Error::from);
+    }
+    if args.tar.is_some() {
+        let mut report = Report::new("generate", args.common.output);
+        let result = tar::generate_tar(args, cancel, &mut report);
+        // the archive is written on stdout
+        report.finish(&result, false);
+        return result.map_err(Error::from);
+    }
+    if !args.more_files.is_empty() {
+        let files = args.files();
+        let output = args.common.output;
+        return Ok(devices::run(
+            "generate",
+            output,
+            &files,
+            files.len(),
+            &cancel,
+            |index, file, cancel, report| {
+                generate_stream(&args.for_device(index, file).with_device_seed()?, cancel, report)
+            },
+        ));
+    }
+    let args = &args.with_device_seed()?;
+    let mut report = Report::new("generate", args.common.output);
+    let result = generate_stream(args, cancel, &mut report);
+    let result = result.and_then(|code| save_checksum_file(args, &report, code));
+    if args.common.print_checksum {
+        report.print_checksum(&result);
+    }
+    // the stream may be written on stdout
+    report.finish(&result, args.file.is_some());
+    result.map_err(Error::from)
+}
+
+/// Write the checksum file, with `--checksum-file`, once the stream has been generated
+pub(crate) fn save_checksum_file(
+    args: &GenerateArgs,
+    report: &Report,
+    code: i32,
+) -> anyhow::Result<i32> {
+    if code == 0
+        && let Some(path) = &args.checksum_file
+        && let Some(file) = ChecksumFile::new(args, report)
+    {
+        file.save(path)?;
+        debug!("checksum file: {}", path.display());
+    }
+    Ok(code)
+}
+
+pub(crate) fn generate_stream(
+    args: &GenerateArgs,
+    cancel: Arc<AtomicBool>,
+    report: &mut Report,
+) -> anyhow::Result<i32> {
+    generate_stream_to(args, Box::new(io::stdout()), cancel, report)
+}
+
+/// Same as generate_stream, with the writer receiving the stream when no file is given
+pub(crate) fn generate_stream_to(
+    args: &GenerateArgs,
+    output: Box<dyn Write + Send>,
+    cancel: Arc<AtomicBool>,
+    report: &mut Report,
+) -> anyhow::Result<i32> {
     let start = Instant::now();
-    let chunk_size = args.common.chunk_size as usize;
+    if args.common.chunk_size == AUTO_CHUNK_SIZE {
+        return Err(usage("--chunk-size auto only applies to validate"));
+    }
+    args.common.check_format()?;
+    if args.tree.is_some() || args.tar.is_some() {
+        return Err(usage("--tree and --tar only apply to generate"));
+    }
+    let endpoint = args.file.as_deref().map(Endpoint::parse).transpose()?.flatten();
+    let url = args.file.as_deref().and_then(http::url);
+    let object = args.file.as_deref().map(S3Object::parse).transpose()?.flatten();
+    let ssh = args.file.as_deref().map(SshTarget::parse).transpose()?.flatten();
+    let remote = endpoint.is_some() || url.is_some() || object.is_some() || ssh.is_some();
+    if remote {
+        reject_file_options(args, "a network address")?;
+    }
+    if let Some(endpoint) = &endpoint
+        && endpoint.scheme() == Scheme::Udp
+    {
+        // the receiver needs the index of each chunk
+        if args.common.format != ChunkFormat::V2 {
+            return Err(usage("udp:// requires --format v2"));
+        }
+        if args.common.chunk_size > MAX_DATAGRAM_SIZE as u64 {
+            return Err(usage(format!(
+                "The chunk size must be at most {MAX_DATAGRAM_SIZE} to fit in a datagram"
+            )));
+        }
+        net::reject_options("udp://", &[("--random-seed", args.random_seed)])?;
+    }
+    let export = match args.file.as_deref().map(NbdUri::parse).transpose()?.flatten() {
+        Some(uri) => {
+            net::reject_options(
+                "an NBD export",
+                &[
+                    ("--preallocate", args.preallocate),
+                    ("--fsync-every", args.fsync_every.is_some()),
+                    ("--sync", args.sync),
+                    ("--dsync", args.dsync),
+                    ("--verify-after-write", args.verify_after_write),
+                    ("--journal", args.common.journal.is_some()),
+                    ("--checkpoint", args.checkpoint.is_some()),
+                    ("--shard-size", args.shard_size.is_some()),
+                    ("--direct", args.common.direct),
+                    ("--drop-cache", args.common.drop_cache),
+                    ("--advise", args.common.advise.is_some()),
+                    ("--duration", args.common.duration.is_some()),
+                ],
+            )?;
+            let export = uri.connect()?;
+            if export.is_read_only() {
+                return Err(anyhow!("The export {uri} is read-only"));
+            }
+            Some(export)
+        }
+        None => None,
+    };
+    let total_size = match &export {
+        Some(export) => export_stream_size(args, export.size())?,
+        None => resolve_stream_size(args)?,
+    };
+    let mut pb = Progress::new(
+        Some(total_size),
+        args.common.no_progress,
+        args.common.progress,
+        args.common.device.as_deref(),
+        args.common.progress_callback.as_ref(),
+    )?;
+
+    let resumed = match &args.checkpoint {
+        Some(path) => SavedCheckpoint::load(path)?,
+        None => None,
+    };
+    if args.common.dedupe_ratio.is_some()
+        && !args.common.chunk_size.is_multiple_of(BLOCK_SIZE as u64)
+    {
+        return Err(usage("--dedupe-ratio requires a chunk size multiple of 4 KiB"));
+    }
+    let run_id = match args.common.format {
+        ChunkFormat::V1 if args.run_id.is_some() => {
+            return Err(usage("--run-id requires --format v2"));
+        }
+        ChunkFormat::V1 => 0,
+        // a resumed stream keeps its run ID
+        ChunkFormat::V2 => {
+            args.run_id.or(resumed.as_ref().map(|c| c.run_id)).unwrap_or_else(rand::random)
+        }
+    };
+    // and its random seed
+    let header = args.random_seed.then(|| StreamHeader {
+        seed: resumed.as_ref().map(|c| c.seed).unwrap_or_else(|| Seed::U64(rand::random())),
+        rng: args.common.rng,
+        format: args.common.format,
+        run_id,
+        compressibility: args.common.compressibility(),
+        dedupe_ratio: args.common.dedupe_ratio.unwrap_or(1),
+        pattern: args.common.pattern,
+        region_chunks: None,
+    });
+    let header_size = if header.is_some() { HEADER_SIZE as u64 } else { 0 };
+    if total_size < header_size {
+        return Err(usage(format!(
+            "The stream size must be at least {HEADER_SIZE} to hold the header"
+        )));
+    }
+    let position = args.position + header_size;
+    let alignment = match &args.file {
+        Some(file) if !remote && export.is_none() => direct::alignment(&args.common, file)?,
+        _ => 1,
+    };
+    if args.sync_mode().is_some() && args.common.engine == IoEngine::Mmap {
+        return Err(usage("--sync and --dsync can't be used with the mmap engine"));
+    }
+    if args.verify_after_write && args.common.engine != IoEngine::Sync {
+        return Err(usage("--verify-after-write requires the sync engine"));
+    }
+    if args.common.duration.is_some() {
+        // the threads must take the chunks from the queue, in the stream order
+        net::reject_options(
+            "--duration",
+            &[
+                ("--checkpoint", args.checkpoint.is_some()),
+                ("--fsync-every", args.fsync_every.is_some()),
+                ("--shard-size", args.shard_size.is_some()),
+                ("--digest sha256", args.common.digest.is_some_and(|d| !d.is_combinable())),
+            ],
+        )?;
+    }
+    let chunk_size =
+        direct::align_chunk_size(args.common.chunk_size as usize, position, alignment)?;
+    if let Some(file) = args.file.as_deref().filter(|_| !remote && export.is_none()) {
+        direct::check_physical_alignment(file, chunk_size, position);
+    }
     // we need to write a multiple a 64 bits to be able to use advance()
     let buffer_size = chunk_size.div_ceil(8) * 8;
-    let stream_size = resolve_stream_size(args)?;
-    let mut pb = Progress::new(Some(stream_size), args.common.no_progress)?;
+    let region_chunks = args.common.region_chunks(chunk_size)?;
+    // the regions are recorded along with the seed they're derived from
+    let header =
+        header.map(|h| StreamHeader { region_chunks: region_chunks.map(|c| c as u32), ..h });
+    let seed = header.map(|h| h.seed).unwrap_or_else(|| args.seed());
+    let stream = StreamParams {
+        rng: args.common.rng,
+        seed,
+        position,
+        stream_size: total_size - header_size,
+        chunk_size,
+        buffer_size,
+        alignment,
+        cache: args.common.cache_policy(),
+        batch_chunks: args.common.batch_chunks as usize,
+        throttle: args.common.throttle(),
+        fsync_every: args.fsync_every,
+        sync: args.sync_mode(),
+        format: args.common.format,
+        fingerprint: seed.fingerprint(),
+        region_chunks,
+        sector_size: args.common.sector_size(chunk_size)?,
+        run_id,
+        compressibility: args.common.compressibility(),
+        dedupe: args
+            .common
+            .dedupe_ratio
+            .and_then(|ratio| Dedupe::new(ratio, args.common.rng, seed)),
+        pattern: args.common.pattern,
+        first_chunk: 0,
+        verify_after_write: args.verify_after_write,
+        journal: args.common.journal()?,
+    };
 
     debug!("position: {}", args.position);
-    debug!("stream size: {stream_size}");
+    debug!("stream size: {}", stream.stream_size);
     debug!("chunk size: {chunk_size}");
-    debug!("seed: {}", args.seed);
+    if header.is_some() {
+        info!("random seed: {}", stream.seed);
+    } else {
+        debug!("seed: {}", stream.seed);
+    }
+    if args.common.format == ChunkFormat::V2 {
+        info!("run id: {run_id:016x}");
+    }
+    if let Some(compressibility) = stream.compressibility {
+        info!("compress ratio: {:.2}", compressibility.ratio());
+    }
+    if let Some(dedupe) = stream.dedupe {
+        let header = stream.format == ChunkFormat::V2;
+        let ratio = dedupe.achieved_ratio(stream.stream_size, chunk_size as u64, header);
+        info!("dedupe ratio: {ratio:.2}, with groups of {} blocks", dedupe.ratio());
+    }
+    if let Some(pattern) = stream.pattern {
+        info!("pattern: {}", pattern.to_possible_value().unwrap().get_name());
+    }
+    if let Some(region_chunks) = region_chunks {
+        info!("regions: {} chunks each", region_chunks);
+    }
+    if let Some(sector_size) = stream.sector_size {
+        info!("sector checksums: every {sector_size} bytes");
+    }
+    debug!("random generator: {:?}", args.common.rng);
+    debug!("engine: {:?}", args.common.engine);
+    debug!("alignment: {alignment}");
 
-    let (bytes_generated, checksum) = if let Some(file) = &args.file {
-        generate_to_file(args, file, stream_size, chunk_size, buffer_size, &mut pb, &cancel)?
+    let mut summary = if let Some(endpoint) = &endpoint {
+        let writer: Box<dyn Write + Send> = match endpoint.scheme() {
+            Scheme::Tcp => Box::new(endpoint.connect(args.connect_retries)?),
+            Scheme::Udp => Box::new(DatagramWriter::new(endpoint.datagram_sender()?)),
+        };
+        generate_to_writer(args, &stream, header, writer, &mut pb, &cancel, report)?
+    } else if let Some(export) = export {
+        let writer = Box::new(ExportStream::new(export, args.position));
+        generate_to_writer(args, &stream, header, writer, &mut pb, &cancel, report)?
+    } else if let Some(url) = url {
+        let (writer, upload) = Upload::start(url, args.http_method, total_size);
+        let summary =
+            generate_to_writer(args, &stream, header, Box::new(writer), &mut pb, &cancel, report);
+        // the answer of the server tells why the upload has stopped
+        let uploaded = upload.finish();
+        if !cancel.load(Ordering::Relaxed) {
+            uploaded?;
+        }
+        summary?
+    } else if let Some(object) = object {
+        let (writer, upload) = object.upload(total_size);
+        let summary =
+            generate_to_writer(args, &stream, header, Box::new(writer), &mut pb, &cancel, report);
+        let uploaded = upload.finish();
+        if !cancel.load(Ordering::Relaxed) {
+            uploaded?;
+        }
+        summary?
+    } else if let Some(ssh) = ssh {
+        let (writer, command) = ssh.write()?;
+        let summary =
+            generate_to_writer(args, &stream, header, Box::new(writer), &mut pb, &cancel, report);
+        let written = command.finish();
+        if !cancel.load(Ordering::Relaxed) {
+            written?;
+        }
+        summary?
+    } else if let Some(shard_size) = args.shard_size {
+        let template = args.file.as_deref().unwrap();
+        let shards = Shards::new(template, shard_size, chunk_size)?;
+        generate_shards(args, &shards, &stream, &cancel, report)?
+    } else if let Some(file) = &args.file {
+        generate_to_file(args, file, &stream, header, resumed, &mut pb, &cancel, report)?
     } else {
-        generate_to_stdout(args, stream_size, chunk_size, &mut pb)?
+        generate_to_writer(args, &stream, header, output, &mut pb, &cancel, report)?
     };
+    summary.bytes += header_size;
+    report.bytes = summary.bytes;
 
     // Check if operation was cancelled
     if cancel.load(Ordering::Relaxed) {
-        log_metrics(start, bytes_generated, "written bytes");
-        return Ok(130);
+        log_metrics(start, summary.bytes, "written bytes");
+        return Ok(exit_code::INTERRUPTED);
+    }
+    if args.common.duration.is_some() && summary.bytes < total_size {
+        info!(
+            "stopped at the --duration, {} of {total_size} bytes written: validate them with \
+             --size {}",
+            summary.bytes, summary.bytes
+        );
     }
 
-    info!("checksum: {checksum:08x}");
-    log_metrics(start, bytes_generated, "written bytes");
+    let checksum = args.common.checksum.format(summary.checksum.finalize());
+    let digest = summary.digest.as_ref().map(|d| d.finalize());
+    if !report.is_json() {
+        let prefix = args.common.device_prefix();
+        info!("{prefix}checksum: {checksum}");
+        if let Some(digest) = &digest {
+            info!("{prefix}digest: {digest}");
+        }
+    }
+    report.checksum = Some(checksum);
+    report.digest = digest;
+    log_metrics(start, summary.bytes, "written bytes");
     Ok(0)
 }
 
+/// Reject the options only applying to a file, for the target which isn't one
+pub(crate) fn reject_file_options(args: &GenerateArgs, target: &str) -> anyhow::Result<()> {
+    net::reject_options(
+        target,
+        &[
+            ("--position", args.position > 0),
+            ("--preallocate", args.preallocate),
+            ("--fsync-every", args.fsync_every.is_some()),
+            ("--fsync-at-end", args.fsync_at_end),
+            ("--sync", args.sync),
+            ("--dsync", args.dsync),
+            ("--verify-after-write", args.verify_after_write),
+            ("--journal", args.common.journal.is_some()),
+            ("--checkpoint", args.checkpoint.is_some()),
+            ("--shard-size", args.shard_size.is_some()),
+            ("--direct", args.common.direct),
+            ("--drop-cache", args.common.drop_cache),
+            ("--advise", args.common.advise.is_some()),
+            ("--duration", args.common.duration.is_some()),
+        ],
+    )
+}
+
+/// Write the stream across the shards, one after the other
+///
+/// Each shard has its own progress, the threads reporting the bytes done since its start.
+fn generate_shards(
+    args: &GenerateArgs,
+    shards: &Shards,
+    stream: &StreamParams,
+    cancel: &Arc<AtomicBool>,
+    report: &mut Report,
+) -> anyhow::Result<StreamSummary> {
+    let mut summaries = Vec::new();
+    for index in 0..shards.count(stream.stream_size) {
+        let range = shards.range(index, stream.stream_size);
+        let shard = StreamParams {
+            stream_size: range.end - range.start,
+            first_chunk: range.start / stream.chunk_size as u64,
+            ..stream.clone()
+        };
+        let path = shards.path(index);
+        debug!("shard {index}: {}", path.display());
+        let mut pb = Progress::new(
+            Some(shard.stream_size),
+            args.common.no_progress,
+            args.common.progress,
+            args.common.device.as_deref(),
+            args.common.progress_callback.as_ref(),
+        )?;
+        let summary = generate_to_file(args, &path, &shard, None, None, &mut pb, cancel, report)?;
+        summaries.push(summary);
+        if cancel.load(Ordering::Relaxed) {
+            break;
+        }
+    }
+    Ok(combine_summaries(summaries))
+}
+
 fn resolve_stream_size(args: &GenerateArgs) -> anyhow::Result<u64> {
     if let Some(size) = &args.common.size {
         return Ok(*size);
@@ -99,58 +819,168 @@ fn resolve_stream_size(args: &GenerateArgs) -> anyhow::Result<u64> {
     {
         let size = read_file_size(file)?;
         if args.position > size {
-            return Err(anyhow!(
+            return Err(usage(format!(
                 "The position {} is greater than the file size {size}",
                 args.position
-            ));
+            )));
         }
         return Ok(size - args.position);
     }
-    Err(anyhow!("Size can't be determined. Use --size to provide a stream size."))
+    Err(usage("Size can't be determined. Use --size to provide a stream size."))
+}
+
+/// The size of the stream written to an NBD export, up to its end by default
+fn export_stream_size(args: &GenerateArgs, export_size: u64) -> anyhow::Result<u64> {
+    let Some(available) = export_size.checked_sub(args.position) else {
+        return Err(usage(format!(
+            "The position {} is greater than the export size {export_size}",
+            args.position
+        )));
+    };
+    match args.common.size {
+        Some(size) if size > available => Err(usage(format!(
+            "The stream size {size} doesn't fit in the export of {export_size} bytes"
+        ))),
+        Some(size) => Ok(size),
+        None => Ok(available),
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn generate_to_file(
     args: &GenerateArgs,
     file: &PathBuf,
-    stream_size: u64,
-    chunk_size: usize,
-    buffer_size: usize,
+    stream: &StreamParams,
+    header: Option<StreamHeader>,
+    resumed: Option<SavedCheckpoint>,
     pb: &mut Option<Progress>,
     cancel: &Arc<AtomicBool>,
-) -> anyhow::Result<(u64, u32)> {
+    report: &mut Report,
+) -> anyhow::Result<StreamSummary> {
+    if !args.force {
+        inuse::check_unused(file)?;
+    }
     // make sure the output file exists, before opening it in the threads
-    let f = OpenOptions::new().create(true).truncate(false).write(true).open(file)?;
+    let mut f = OpenOptions::new().create(true).truncate(false).write(true).open(file)?;
     // and that the file size matches the requested size
     if file.is_file() {
-        let end_position = stream_size + args.position;
+        let end_position = stream.position + stream.stream_size;
         if end_position > f.metadata()?.len() || !args.no_truncate {
             f.set_len(end_position)?;
         }
+        let len = end_position - args.position;
+        if args.preallocate && len > 0 {
+            preallocate(&f, args.position, len).map_err(|e| {
+                io::Error::new(e.kind(), format!("Can't preallocate {len} bytes in the file: {e}"))
+            })?;
+        }
+    }
+    if let Some(header) = header {
+        f.seek(io::SeekFrom::Start(args.position))?;
+        f.write_all(&header.encode())?;
     }
+    let stream_size = stream.stream_size;
+    let chunk_size = stream.chunk_size;
 
-    let num_threads = args.common.jobs.unwrap_or(num_cpus::get_physical());
+    let num_threads = args.common.num_threads(file);
     debug!("number of threads: {num_threads}");
+    let affinity = args.common.affinity(Some(file))?;
     let num_chunks = stream_size.div_ceil(chunk_size as u64);
-    let chunks_per_thread = num_chunks.div_ceil(num_threads as u64);
+    let mut summarizer = Summarizer::new(
+        args.common.checksum,
+        args.common.digest,
+        stream_size,
+        num_chunks,
+        num_threads,
+    );
     let (tx, rx) = mpsc::channel::<u64>();
 
-    let stream = StreamParams {
-        seed: args.seed,
-        position: args.position,
-        stream_size,
-        chunk_size,
-        buffer_size,
+    let mut works = summarizer.split(num_chunks, num_threads);
+    let checkpoint = match &args.checkpoint {
+        Some(path) => {
+            let identity = stream.identity(args.common.checksum);
+            let checkpoint = Checkpoint::new(
+                path,
+                Some(f.try_clone()?),
+                stream.seed,
+                stream.run_id,
+                identity,
+                resumed,
+                &works,
+            )?;
+            let ranges = checkpoint.ranges();
+            let done: u64 = ranges.iter().map(|r| r.bytes).sum();
+            if done > 0 {
+                info!("resuming from the checkpoint, {done} bytes already written");
+                tx.send(done)?;
+            }
+            works = ranges.iter().map(|r| r.work()).collect();
+            Some(checkpoint)
+        }
+        None => None,
+    };
+    // the threads take the chunks a batch at a time, unless each one must keep its own range
+    let queue = match (&checkpoint, stream.fsync_every) {
+        (None, None) => {
+            let deadline = args.common.duration.map(|duration| Instant::now() + duration);
+            summarizer.queue(num_chunks, num_threads, chunk_size, deadline, args.common.bwlimit)
+        }
+        _ => None,
     };
+    if let Some(queue) = &queue {
+        debug!("batches of {} chunks", queue.batch_chunks());
+    }
 
-    let handles: Vec<_> = (0..num_threads as u64)
-        .map(|i| {
+    let positions =
+        ThreadPositions::new(works.len(), stream.position, stream.chunk_size, stream.stream_size);
+    let watch = TargetWatch::start(file, stream.position + stream.stream_size, cancel.clone())?;
+    let handles: Vec<_> = works
+        .into_iter()
+        .enumerate()
+        .map(|(i, work)| {
             let file = file.clone();
             let tx = tx.clone();
             let cancel = cancel.clone();
             let stream = stream.clone();
+            let assignment = match &queue {
+                Some(queue) => summarizer.queued(queue, chunk_size),
+                None => {
+                    let mut recorder = summarizer.recorder(i, &work, chunk_size);
+                    if let Some(checkpoint) = &checkpoint {
+                        recorder = recorder.checkpointed(checkpoint.tracker(i));
+                    }
+                    Assignment::Fixed(work, Box::new(recorder))
+                }
+            }
+            .positioned(&positions, i);
+            let (engine, queue_depth) = (args.common.engine, args.common.queue_depth);
+            let affinity = affinity.clone();
             thread::spawn(move || -> anyhow::Result<_> {
-                let work = ThreadWork { thread_index: i, chunks_per_thread, num_chunks };
-                let result = write_chunk_range(&file, &stream, &work, &tx, &cancel);
+                affinity.pin(i)?;
+                let result = assignment.run(&cancel, |work, recorder| match engine {
+                    IoEngine::Sync => write_chunks(&file, &stream, work, recorder, &tx, &cancel),
+                    #[cfg(target_os = "linux")]
+                    IoEngine::IoUring => write_chunks_uring(
+                        &file,
+                        &stream,
+                        work,
+                        recorder,
+                        &tx,
+                        &cancel,
+                        queue_depth,
+                    ),
+                    #[cfg(unix)]
+                    IoEngine::Mmap => {
+                        write_chunks_mmap(&file, &stream, work, recorder, &tx, &cancel)
+                    }
+                    #[cfg(not(unix))]
+                    IoEngine::Mmap => Err(usage("The mmap engine is only available on Unix")),
+                    #[cfg(not(target_os = "linux"))]
+                    IoEngine::IoUring => {
+                        let _ = queue_depth;
+                        Err(usage("The io-uring engine is only available on Linux"))
+                    }
+                });
                 if result.is_err() {
                     // tell the other threads to stop
                     cancel.store(true, Ordering::Relaxed);
@@ -160,103 +990,599 @@ fn generate_to_file(
         })
         .collect();
 
-    receive_progress(pb, &rx, tx);
-    let thread_data: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).try_collect()?;
+    let total = Some(stream.stream_size);
+    receive_progress(pb, &rx, tx, ProgressPhase::Generate, total, Some(&positions));
+    let outputs = work::join(handles);
+    if let Some(checkpoint) = &checkpoint {
+        if outputs.is_ok() && !cancel.load(Ordering::Relaxed) {
+            checkpoint.remove()?;
+        } else {
+            checkpoint.save()?;
+            info!("checkpoint saved, the run can be resumed");
+        }
+    }
+    watch.finish(&positions.offsets())?;
+    let outputs = outputs.map_err(|errors| report.thread_errors(errors))?;
+    report.add_threads(outputs.iter().map(|o| o.stats.clone()), args.common.imbalance_threshold);
+    let summary = summarizer.finish(outputs)?;
+    if queue.is_some_and(|queue| queue.expired())
+        && !cancel.load(Ordering::Relaxed)
+        && file.is_file()
+        && !args.no_truncate
+    {
+        // the file holds the start of the stream written before the --duration
+        f.set_len(stream.position + summary.bytes)?;
+    }
+    if args.fsync_at_end {
+        let start = Instant::now();
+        f.sync_all()?;
+        debug!("flushed to the media in {}", start.elapsed().format_duration());
+    }
+
+    Ok(summary)
+}
+
+/// Allocate the range of the file
+#[cfg(all(unix, not(target_os = "macos")))]
+fn preallocate(file: &File, offset: u64, len: u64) -> io::Result<()> {
+    Ok(nix::fcntl::posix_fallocate(file, offset as i64, len as i64)?)
+}
 
-    let write_bytes = thread_data.iter().map(|(b, _)| b).sum();
-    let thread_hashers: Vec<_> = thread_data.iter().map(|(_, h)| h).collect();
-    let mut hasher = thread_hashers[0].clone();
-    for partial_hasher in thread_hashers[1..].iter() {
-        hasher.combine(partial_hasher);
+/// Allocate the range of the file, and extend its size to cover it on macOS
+#[cfg(target_os = "macos")]
+fn preallocate(file: &File, offset: u64, len: u64) -> io::Result<()> {
+    use nix::fcntl::{FcntlArg, fcntl};
+    use nix::libc::{F_ALLOCATEALL, F_VOLPOSMODE, fstore_t};
+    let mut store = fstore_t {
+        fst_flags: F_ALLOCATEALL,
+        fst_posmode: F_VOLPOSMODE,
+        fst_offset: offset as i64,
+        fst_length: len as i64,
+        fst_bytesalloc: 0,
+    };
+    fcntl(file, FcntlArg::F_PREALLOCATE(&mut store))?;
+    // like posix_fallocate, extend the file but never shrink it
+    if file.metadata()?.len() < offset + len {
+        file.set_len(offset + len)?;
     }
+    Ok(())
+}
 
-    Ok((write_bytes, hasher.finalize()))
+/// Allocate the range of the file, from its start on Windows
+#[cfg(windows)]
+fn preallocate(file: &File, offset: u64, len: u64) -> io::Result<()> {
+    crate::windows::allocate(file, offset + len)
 }
 
-fn write_chunk_range(
-    file: &PathBuf,
+fn write_chunks(
+    file: &Path,
+    stream: &StreamParams,
+    work: &ThreadWork,
+    mut recorder: ChunkRecorder,
+    tx: &mpsc::Sender<u64>,
+    cancel: &AtomicBool,
+) -> anyhow::Result<ThreadOutput> {
+    let mut writer = direct::open(file, true, stream.alignment, stream.sync)?;
+    let mut cache = stream.cache.apply(&writer, stream.file_range(work), true)?;
+    let mut rng = stream.stream_rng();
+    let mut buffers: Vec<_> = (0..stream.batch_chunks)
+        .map(|_| AlignedBuffer::new(stream.buffer_size, stream.alignment))
+        .collect();
+    let mut sizes = Vec::with_capacity(stream.batch_chunks);
+    let mut fsync = FsyncTracker::new(stream.fsync_every);
+    let mut read_back =
+        stream.verify_after_write.then(|| ReadBack::open(file, stream)).transpose()?;
+    writer.seek(io::SeekFrom::Start(stream.position))?;
+    let mut next_chunk = 0;
+    let mut progress_bytes: u64 = 0;
+    let mut n: u64 = 0;
+    // only the consecutive chunks can be written at once
+    let batch_chunks = if work.step == 1 { stream.batch_chunks } else { 1 };
+    'batches: for batch in &work.chunks().chunks(batch_chunks) {
+        let mut batch = batch.peekable();
+        let first_chunk = *batch.peek().unwrap();
+        if first_chunk != next_chunk {
+            writer.seek(io::SeekFrom::Start(
+                stream.position + first_chunk * stream.chunk_size as u64,
+            ))?;
+            let advance_amount =
+                (first_chunk - next_chunk).checked_mul(stream.buffer_size as u64).ok_or_else(
+                    || anyhow!("arithmetic overflow: chunk * buffer_size exceeds u64 max"),
+                )?;
+            rng.advance(advance_amount);
+        }
+        sizes.clear();
+        for (chunk, buffer) in batch.zip(&mut buffers) {
+            next_chunk = chunk + 1;
+            let write_size = (stream.stream_size - chunk * stream.chunk_size as u64)
+                .min(stream.chunk_size as u64) as usize;
+            generate_chunk_with_layout(
+                &mut rng,
+                buffer,
+                write_size,
+                &stream.chunk_layout(chunk, write_size),
+                recorder.checksum(),
+            );
+            if !recorder.record(&buffer[..write_size]) {
+                // the digest thread has stopped, because another thread failed
+                break 'batches;
+            }
+            sizes.push(write_size);
+        }
+        let written: usize = sizes.iter().sum();
+        if let Some(throttle) = &stream.throttle {
+            throttle.consume(written as u64);
+        }
+        let start = SystemTime::now();
+        let result = write_batch(&mut writer, file, stream, first_chunk, &buffers, &sizes);
+        if let Some(journal) = &stream.journal {
+            let error = result.as_ref().err().map(ToString::to_string);
+            for (i, (buffer, size)) in buffers.iter().zip(&sizes).enumerate() {
+                let chunk = first_chunk + i as u64;
+                let offset = stream.position + chunk * stream.chunk_size as u64;
+                let result = error.as_deref().map_or(Ok(&buffer[..*size]), Err);
+                let chunk = stream.first_chunk + chunk;
+                journal.record(Operation::Write, chunk, offset, *size, result, start)?;
+            }
+        }
+        result?;
+        fsync.written(&writer, written as u64, sizes.len() as u64)?;
+        if let Some(read_back) = &mut read_back {
+            read_back.check(&writer, file, stream, first_chunk, &buffers, &sizes)?;
+        }
+        for chunk in first_chunk..first_chunk + sizes.len() as u64 {
+            recorder.done(chunk)?;
+        }
+        let end = stream.position + first_chunk * stream.chunk_size as u64 + written as u64;
+        cache.processed(&writer, end)?;
+        for size in &sizes {
+            progress_bytes += *size as u64;
+            if n.is_multiple_of(100) {
+                tx.send(progress_bytes)?;
+                progress_bytes = 0;
+            }
+            n += 1;
+        }
+        if cancel.load(Ordering::Relaxed) {
+            break;
+        }
+    }
+    cache.finish(&writer)?;
+    // the bytes processed since the last update
+    tx.send(progress_bytes)?;
+    Ok(recorder.finish())
+}
+
+/// Write the consecutive chunks starting at `first_chunk`, with a single system call if possible
+fn write_batch(
+    writer: &mut File,
+    file: &Path,
+    stream: &StreamParams,
+    first_chunk: u64,
+    buffers: &[AlignedBuffer],
+    sizes: &[usize],
+) -> io::Result<()> {
+    // only the last chunk of the stream may not be aligned
+    let aligned = sizes.iter().take_while(|size| size.is_multiple_of(stream.alignment)).count();
+    let mut slices: Vec<_> =
+        buffers.iter().zip(&sizes[..aligned]).map(|(b, size)| IoSlice::new(&b[..*size])).collect();
+    write_all_vectored(writer, &mut slices)?;
+    if aligned < sizes.len() {
+        let offset = stream.position + (first_chunk + aligned as u64) * stream.chunk_size as u64;
+        direct::write_unaligned(file, &buffers[aligned][..sizes[aligned]], offset)?;
+    }
+    Ok(())
+}
+
+/// The chunks read back once written, with `--verify-after-write`
+struct ReadBack {
+    reader: File,
+    buffer: AlignedBuffer,
+}
+
+impl ReadBack {
+    fn open(file: &Path, stream: &StreamParams) -> io::Result<Self> {
+        Ok(ReadBack {
+            reader: direct::open(file, false, stream.alignment, None)?,
+            buffer: AlignedBuffer::new(stream.buffer_size, stream.alignment),
+        })
+    }
+
+    /// Read back the consecutive chunks starting at `first_chunk`, and compare them with the data
+    /// written
+    fn check(
+        &mut self,
+        writer: &File,
+        file: &Path,
+        stream: &StreamParams,
+        first_chunk: u64,
+        buffers: &[AlignedBuffer],
+        sizes: &[usize],
+    ) -> anyhow::Result<()> {
+        let start = stream.position + first_chunk * stream.chunk_size as u64;
+        if stream.alignment == 1 {
+            let written: usize = sizes.iter().sum();
+            cache::evict(writer, start..start + written as u64)?;
+        }
+        for (i, (buffer, &size)) in buffers.iter().zip(sizes).enumerate() {
+            let chunk = first_chunk + i as u64;
+            let offset = start + i as u64 * stream.chunk_size as u64;
+            let data = &mut self.buffer[..size];
+            if size.is_multiple_of(stream.alignment) {
+                direct::read_exact_at(&self.reader, data, offset)?;
+            } else {
+                // the last chunk has been written through the page cache
+                let reader = File::open(file)?;
+                cache::evict(&reader, offset..offset + size as u64)?;
+                direct::read_exact_at(&reader, data, offset)?;
+            }
+            if *data != buffer[..size] {
+                let diff = ChunkDiff::new(offset, data, &buffer[..size]);
+                let (first_offset, last_offset) = diff.bounds().expect("some differing bytes");
+                let error = ValidationError::ReadBack {
+                    chunk,
+                    bytes: diff.bytes,
+                    first_offset,
+                    last_offset,
+                };
+                return Err(locate_corruption(error.into(), chunk, offset, size));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Same as write_chunks, but with up to `queue_depth` writes in flight through io_uring
+#[cfg(target_os = "linux")]
+fn write_chunks_uring(
+    file: &Path,
+    stream: &StreamParams,
+    work: &ThreadWork,
+    mut recorder: ChunkRecorder,
+    tx: &mpsc::Sender<u64>,
+    cancel: &AtomicBool,
+    queue_depth: u32,
+) -> anyhow::Result<ThreadOutput> {
+    use rustix::io_uring::IoringOp;
+    use std::os::fd::AsRawFd as _;
+
+    let writer = direct::open(file, true, stream.alignment, stream.sync)?;
+    let mut queue = BufferQueue::new(
+        IoringOp::Write,
+        writer.as_raw_fd(),
+        queue_depth,
+        stream.buffer_size,
+        stream.alignment,
+    )?;
+    let mut cache = stream.cache.apply(&writer, stream.file_range(work), true)?;
+    // the writes still in flight can't be dropped from the page cache yet
+    let in_flight_bytes = queue_depth as u64 * work.step * stream.chunk_size as u64;
+    let mut rng = stream.stream_rng();
+    let mut next_chunk = 0;
+    let mut progress_bytes: u64 = 0;
+    let mut written_chunks: u64 = 0;
+    let mut fsync = FsyncTracker::new(stream.fsync_every);
+    let chunk_write_size = |chunk: u64| {
+        (stream.stream_size - chunk * stream.chunk_size as u64).min(stream.chunk_size as u64)
+    };
+    let mut wait = |queue: &mut BufferQueue, recorder: &mut ChunkRecorder| -> anyhow::Result<()> {
+        for (index, chunk) in queue.wait()? {
+            queue.release(index);
+            recorder.done(chunk)?;
+            let write_size = chunk_write_size(chunk);
+            fsync.written(&writer, write_size, 1)?;
+            progress_bytes += write_size;
+            written_chunks += 1;
+            if written_chunks.is_multiple_of(100) {
+                tx.send(progress_bytes)?;
+                progress_bytes = 0;
+            }
+        }
+        Ok(())
+    };
+    for chunk in work.chunks() {
+        let index = match queue.free_buffer() {
+            Some(index) => index,
+            None => {
+                wait(&mut queue, &mut recorder)?;
+                queue.free_buffer().unwrap()
+            }
+        };
+        if chunk != next_chunk {
+            let advance_amount =
+                (chunk - next_chunk).checked_mul(stream.buffer_size as u64).ok_or_else(|| {
+                    anyhow!("arithmetic overflow: chunk * buffer_size exceeds u64 max")
+                })?;
+            rng.advance(advance_amount);
+        }
+        next_chunk = chunk + 1;
+        let write_size = chunk_write_size(chunk) as usize;
+        let buffer = queue.buffer_mut(index);
+        generate_chunk_with_layout(
+            &mut rng,
+            buffer,
+            write_size,
+            &stream.chunk_layout(chunk, write_size),
+            recorder.checksum(),
+        );
+        if !recorder.record(&buffer[..write_size]) {
+            // the digest thread has stopped, because another thread failed
+            break;
+        }
+        if let Some(throttle) = &stream.throttle {
+            throttle.consume(write_size as u64);
+        }
+        let offset = stream.position + chunk * stream.chunk_size as u64;
+        if write_size.is_multiple_of(stream.alignment) {
+            queue.submit(index, offset, write_size, chunk)?;
+        } else {
+            direct::write_unaligned(file, &buffer[..write_size], offset)?;
+            queue.release(index);
+            recorder.done(chunk)?;
+        }
+        cache.processed(&writer, offset.saturating_sub(in_flight_bytes))?;
+        if cancel.load(Ordering::Relaxed) {
+            break;
+        }
+    }
+    while queue.in_flight() > 0 {
+        wait(&mut queue, &mut recorder)?;
+    }
+    cache.finish(&writer)?;
+    // the bytes processed since the last update
+    tx.send(progress_bytes)?;
+    Ok(recorder.finish())
+}
+
+/// Same as write_chunks, but generating the chunks directly in a mapping of the file
+#[cfg(unix)]
+fn write_chunks_mmap(
+    file: &Path,
     stream: &StreamParams,
     work: &ThreadWork,
+    mut recorder: ChunkRecorder,
     tx: &mpsc::Sender<u64>,
     cancel: &AtomicBool,
-) -> anyhow::Result<(u64, Hasher)> {
-    let mut writer = OpenOptions::new().write(true).open(file)?;
-    let mut thread_hasher = Hasher::new();
-    let mut local_hasher = Hasher::new();
-    let mut rng = Pcg64Mcg::seed_from_u64(stream.seed);
+) -> anyhow::Result<ThreadOutput> {
+    // a shared writable mapping requires the file to be opened for reading too
+    let writer = OpenOptions::new().read(true).write(true).open(file)?;
+    let range = stream.file_range(work);
+    if range.is_empty() {
+        return Ok(recorder.finish());
+    }
+    let size = mapping::file_size(&writer)?;
+    if range.end > size {
+        return Err(anyhow!("The stream doesn't fit in the file of size {size}"));
+    }
+    let mut cache = stream.cache.apply(&writer, range.clone(), true)?;
+    let advice = stream.cache.advice.unwrap_or(Advice::Sequential);
+    let mut mapping = FileMapping::new(&writer, range.clone(), true, advice)?;
+    let mut rng = stream.stream_rng();
+    // for the chunks which don't have the size of the buffer
     let mut buffer = vec![0; stream.buffer_size];
-    let start_chunk = work.thread_index * work.chunks_per_thread;
-    let end_chunk = ((work.thread_index + 1) * work.chunks_per_thread).min(work.num_chunks);
-    writer.seek(io::SeekFrom::Start(stream.position + start_chunk * stream.chunk_size as u64))?;
-    let advance_amount = start_chunk
-        .checked_mul(stream.buffer_size as u64)
-        .ok_or_else(|| anyhow!("arithmetic overflow: start_chunk * buffer_size exceeds u64 max"))?
-        / 8;
-    rng.advance(advance_amount.into());
-    let mut total_write_size: u64 = 0;
+    let mut fsync = FsyncTracker::new(stream.fsync_every);
+    let mut next_chunk = 0;
     let mut progress_bytes: u64 = 0;
-    for chunk in start_chunk..end_chunk {
+    for (n, chunk) in work.chunks().enumerate() {
+        if chunk != next_chunk {
+            let advance_amount =
+                (chunk - next_chunk).checked_mul(stream.buffer_size as u64).ok_or_else(|| {
+                    anyhow!("arithmetic overflow: chunk * buffer_size exceeds u64 max")
+                })?;
+            rng.advance(advance_amount);
+        }
+        next_chunk = chunk + 1;
         let write_size = (stream.stream_size - chunk * stream.chunk_size as u64)
             .min(stream.chunk_size as u64) as usize;
-        generate_chunk(&mut rng, &mut buffer, write_size, &mut thread_hasher, &mut local_hasher);
-        writer.write_all(&buffer[..write_size])?;
-        total_write_size += write_size as u64;
+        if let Some(throttle) = &stream.throttle {
+            throttle.consume(write_size as u64);
+        }
+        let offset = stream.position + chunk * stream.chunk_size as u64;
+        let start = (offset - range.start) as usize;
+        let data = mapping.slice_mut(start..start + write_size);
+        if write_size == stream.buffer_size {
+            generate_chunk_with_layout(
+                &mut rng,
+                data,
+                write_size,
+                &stream.chunk_layout(chunk, write_size),
+                recorder.checksum(),
+            );
+        } else {
+            generate_chunk_with_layout(
+                &mut rng,
+                &mut buffer,
+                write_size,
+                &stream.chunk_layout(chunk, write_size),
+                recorder.checksum(),
+            );
+            data.copy_from_slice(&buffer[..write_size]);
+        }
+        if !recorder.record(data) {
+            // the digest thread has stopped, because another thread failed
+            break;
+        }
+        recorder.done(chunk)?;
+        fsync.written(&writer, write_size as u64, 1)?;
+        cache.processed(&writer, offset + write_size as u64)?;
         progress_bytes += write_size as u64;
-        if chunk % 100 == 0 {
+        if n % 100 == 0 {
             tx.send(progress_bytes)?;
             progress_bytes = 0;
         }
         if cancel.load(Ordering::Relaxed) {
-            return Ok((total_write_size, thread_hasher));
+            break;
         }
     }
-    Ok((total_write_size, thread_hasher))
+    drop(mapping);
+    cache.finish(&writer)?;
+    // the bytes processed since the last update
+    tx.send(progress_bytes)?;
+    Ok(recorder.finish())
 }
 
-fn generate_to_stdout(
+/// Write the stream on stdout, or to a network connection
+///
+/// The chunks are generated by several threads, and written in order by another one.
+fn generate_to_writer(
     args: &GenerateArgs,
-    stream_size: u64,
-    chunk_size: usize,
+    stream: &StreamParams,
+    header: Option<StreamHeader>,
+    mut writer: Box<dyn Write + Send>,
     pb: &mut Option<Progress>,
-) -> anyhow::Result<(u64, u32)> {
-    debug!("number of threads: 1");
-    let mut writer = io::stdout();
-    let mut rng = Pcg64Mcg::seed_from_u64(args.seed);
-    let mut buffer = vec![0u8; chunk_size];
-    let mut bytes_generated: u64 = 0;
-    let mut hasher = Hasher::new();
-    let mut local_hasher = Hasher::new();
-    while bytes_generated < stream_size {
-        let write_size = (stream_size - bytes_generated).min(chunk_size as u64) as usize;
-        generate_chunk(&mut rng, &mut buffer, write_size, &mut hasher, &mut local_hasher);
-        writer.write_all(&buffer[..write_size])?;
-        bytes_generated += write_size as u64;
-        if let Some(p) = pb {
-            p.tick(bytes_generated);
-        }
-    }
-    Ok((bytes_generated, hasher.finalize()))
+    cancel: &Arc<AtomicBool>,
+    report: &mut Report,
+) -> anyhow::Result<StreamSummary> {
+    if let Some(header) = header {
+        writer.write_all(&header.encode())?;
+    }
+    let num_threads = args.common.jobs.unwrap_or(num_cpus::get_physical());
+    debug!("number of threads: {num_threads}");
+    let affinity = args.common.affinity(None)?;
+    let num_chunks = stream.stream_size.div_ceil(stream.chunk_size as u64);
+    let mut summarizer = Summarizer::ordered(
+        args.common.checksum,
+        args.common.digest,
+        num_chunks,
+        num_threads,
+        Some(writer),
+    );
+    let (tx, rx) = mpsc::channel::<u64>();
+    let handles: Vec<_> = summarizer
+        .split(num_chunks, num_threads)
+        .into_iter()
+        .enumerate()
+        .map(|(i, work)| {
+            let tx = tx.clone();
+            let cancel = cancel.clone();
+            let stream = stream.clone();
+            let recorder = summarizer.recorder(i, &work, stream.chunk_size);
+            let affinity = affinity.clone();
+            thread::spawn(move || {
+                affinity.pin(i)?;
+                generate_chunks(&stream, &work, recorder, &tx, &cancel)
+            })
+        })
+        .collect();
+
+    receive_progress(pb, &rx, tx, ProgressPhase::Generate, Some(stream.stream_size), None);
+    let outputs = work::join(handles).map_err(|errors| report.thread_errors(errors))?;
+    report.add_threads(outputs.iter().map(|o| o.stats.clone()), args.common.imbalance_threshold);
+    Ok(summarizer.finish(outputs)?)
+}
+
+/// Generate the chunks of the thread, and hand them over to the recorder
+fn generate_chunks(
+    stream: &StreamParams,
+    work: &ThreadWork,
+    mut recorder: ChunkRecorder,
+    tx: &mpsc::Sender<u64>,
+    cancel: &AtomicBool,
+) -> anyhow::Result<ThreadOutput> {
+    let mut rng = stream.stream_rng();
+    let mut buffer = vec![0u8; stream.buffer_size];
+    let mut next_chunk = 0;
+    let mut progress_bytes: u64 = 0;
+    for (n, chunk) in work.chunks().enumerate() {
+        if chunk != next_chunk {
+            let advance_amount =
+                (chunk - next_chunk).checked_mul(stream.buffer_size as u64).ok_or_else(|| {
+                    anyhow!("arithmetic overflow: chunk * buffer_size exceeds u64 max")
+                })?;
+            rng.advance(advance_amount);
+        }
+        next_chunk = chunk + 1;
+        let write_size = (stream.stream_size - chunk * stream.chunk_size as u64)
+            .min(stream.chunk_size as u64) as usize;
+        if let Some(throttle) = &stream.throttle {
+            throttle.consume(write_size as u64);
+        }
+        generate_chunk_with_layout(
+            &mut rng,
+            &mut buffer,
+            write_size,
+            &stream.chunk_layout(chunk, write_size),
+            recorder.checksum(),
+        );
+        if !recorder.record(&buffer[..write_size]) {
+            // the writing thread has stopped
+            break;
+        }
+        progress_bytes += write_size as u64;
+        if n.is_multiple_of(100) {
+            tx.send(progress_bytes)?;
+            progress_bytes = 0;
+        }
+        if cancel.load(Ordering::Relaxed) {
+            break;
+        }
+    }
+    // the bytes processed since the last update
+    tx.send(progress_bytes)?;
+    Ok(recorder.finish())
+}
+
+pub fn generate_chunk<R: SeekableRng + ?Sized, C: ChunkChecksum>(
+    rng: &mut R,
+    buffer: &mut [u8],
+    write_size: usize,
+    stream_checksum: &mut C,
+) {
+    let layout = ChunkLayout::default();
+    generate_chunk_with_layout(rng, buffer, write_size, &layout, stream_checksum)
+}
+
+/// What a chunk holds besides the random data and its checksum
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ChunkLayout {
+    /// The header of the chunk, with `--format v2`
+    pub header: Option<ChunkHeader>,
+    pub compressibility: Option<Compressibility>,
+    pub dedupe: Option<Dedupe>,
+    pub pattern: Option<Pattern>,
+    /// The size of the sectors sealed by their own checksum, with `--sector-checksums`
+    pub sector_size: Option<usize>,
+    /// The offset of the chunk in the stream, with `--dedupe-ratio` or `--pattern`
+    pub offset: u64,
 }
 
-pub fn generate_chunk(
-    rng: &mut Pcg64Mcg,
+/// Same as generate_chunk, with the header, pattern, compressibility, duplicated blocks and sector
+/// checksums of the layout
+pub fn generate_chunk_with_layout<R: SeekableRng + ?Sized, C: ChunkChecksum>(
+    rng: &mut R,
     buffer: &mut [u8],
     write_size: usize,
-    global_hasher: &mut Hasher,
-    local_hasher: &mut Hasher,
+    layout: &ChunkLayout,
+    stream_checksum: &mut C,
 ) {
-    if write_size >= 4 {
-        rng.fill_bytes(&mut buffer[..]);
-        local_hasher.reset();
-        local_hasher.update(&buffer[..write_size - 4]);
-        global_hasher.combine(local_hasher);
-        let checksum_bytes = local_hasher.clone().finalize().to_le_bytes();
-        let end_slice = &mut buffer[write_size - 4..write_size];
-        end_slice.copy_from_slice(&checksum_bytes);
-        // global_hasher.update(&checksum_bytes);
+    let width = stream_checksum.width();
+    if write_size >= width {
+        if let Some(pattern) = layout.pattern {
+            pattern.fill(layout.offset, &mut buffer[..write_size]);
+        } else {
+            // the generator is still advanced by a whole chunk, to be positioned at the next one
+            rng.fill_bytes(&mut buffer[..]);
+        }
+        if let Some(dedupe) = layout.dedupe {
+            dedupe.fill(layout.offset / BLOCK_SIZE as u64, &mut buffer[..write_size]);
+        }
+        if let Some(compressibility) = layout.compressibility {
+            compressibility.apply(&mut buffer[..write_size]);
+        }
+        if let Some(header) = layout.header
+            && ChunkHeader::fits(write_size, width)
+        {
+            buffer[..CHUNK_HEADER_SIZE].copy_from_slice(&header.encode());
+        }
+        if let Some(sector_size) = layout.sector_size {
+            sector::seal(&mut buffer[..write_size - width], sector_size);
+        }
+        let checksum_bytes = stream_checksum.update(&buffer[..write_size - width]).to_le_bytes();
+        let end_slice = &mut buffer[write_size - width..write_size];
+        end_slice.copy_from_slice(&checksum_bytes[..width]);
     } else {
         // not enough room to fit the checksum, just push some zeros in there
         buffer[..write_size].fill(0);
-        global_hasher.update(&buffer[..write_size]);
+        stream_checksum.update(&buffer[..write_size]);
     }
 }
@@ -0,0 +1,119 @@
+//! The optional header at the start of a stream, recording how it was generated
+//!
+//! It's only written when the seed is picked randomly, so the stream can be reproduced later.
+
+use crate::chunk::ChunkFormat;
+use crate::compress::Compressibility;
+use crate::pattern::Pattern;
+use crate::rng::{RngAlgorithm, Seed};
+
+pub const HEADER_SIZE: usize = 64;
+
+const MAGIC: &[u8; 8] = b"RANDSTRM";
+const VERSION: u8 = 1;
+
+/// The header layout:
+///
+/// | offset | size | content                        |
+/// |--------|------|--------------------------------|
+/// | 0      | 8    | `RANDSTRM`                     |
+/// | 8      | 1    | version                        |
+/// | 9      | 1    | random generator               |
+/// | 10     | 1    | chunk format                   |
+/// | 11     | 1    | pattern, 0 for random data     |
+/// | 12     | 2    | random bytes per 4 KiB block   |
+/// | 14     | 2    | dedupe ratio                   |
+/// | 16     | 32   | seed, in little endian         |
+/// | 48     | 8    | run ID, with the v2 format     |
+/// | 56     | 4    | chunks per region, or 0        |
+/// | 60     | 4    | CRC32 of the previous bytes    |
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StreamHeader {
+    pub seed: Seed,
+    pub rng: RngAlgorithm,
+    pub format: ChunkFormat,
+    pub run_id: u64,
+    /// The compressibility of the data, with `--compress-ratio`
+    pub compressibility: Option<Compressibility>,
+    /// The number of consecutive blocks sharing the same data, with `--dedupe-ratio`
+    pub dedupe_ratio: u16,
+    /// The pattern written instead of random data, with `--pattern`
+    pub pattern: Option<Pattern>,
+    /// The number of chunks of each region with its own seed, with `--region-size`
+    pub region_chunks: Option<u32>,
+}
+
+impl StreamHeader {
+    pub fn encode(&self) -> [u8; HEADER_SIZE] {
+        let mut header = [0u8; HEADER_SIZE];
+        header[..8].copy_from_slice(MAGIC);
+        header[8] = VERSION;
+        header[9] = self.rng.id();
+        header[10] = self.format.id();
+        header[11] = self.pattern.map_or(0, |p| p.id());
+        let random_bytes = self.compressibility.map_or(0, |c| c.random_bytes());
+        header[12..14].copy_from_slice(&random_bytes.to_le_bytes());
+        header[14..16].copy_from_slice(&self.dedupe_ratio.max(1).to_le_bytes());
+        header[16..48].copy_from_slice(&self.seed.to_bytes());
+        header[48..56].copy_from_slice(&self.run_id.to_le_bytes());
+        header[56..60].copy_from_slice(&self.region_chunks.unwrap_or(0).to_le_bytes());
+        let crc = crc32fast::hash(&header[..HEADER_SIZE - 4]);
+        header[HEADER_SIZE - 4..].copy_from_slice(&crc.to_le_bytes());
+        header
+    }
+
+    /// Read the header at the start of the data, if any
+    pub fn decode(data: &[u8]) -> Option<StreamHeader> {
+        let header = data.get(..HEADER_SIZE)?;
+        if &header[..8] != MAGIC || header[8] != VERSION {
+            return None;
+        }
+        let crc = u32::from_le_bytes(header[HEADER_SIZE - 4..].try_into().unwrap());
+        if crc != crc32fast::hash(&header[..HEADER_SIZE - 4]) {
+            return None;
+        }
+        Some(StreamHeader {
+            seed: Seed::from_bytes(header[16..48].try_into().unwrap()),
+            rng: RngAlgorithm::from_id(header[9])?,
+            format: ChunkFormat::from_id(header[10])?,
+            run_id: u64::from_le_bytes(header[48..56].try_into().unwrap()),
+            compressibility: Compressibility::from_random_bytes(u16::from_le_bytes(
+                header[12..14].try_into().unwrap(),
+            )),
+            // the headers written before the dedupe ratio have 0
+            dedupe_ratio: u16::from_le_bytes(header[14..16].try_into().unwrap()).max(1),
+            pattern: Pattern::from_id(header[11]),
+            // the headers written before the regions have 0
+            region_chunks: Some(u32::from_le_bytes(header[56..60].try_into().unwrap()))
+                .filter(|chunks| *chunks > 0),
+        })
+    }
+}
+
+#[test]
+fn header_round_trip() {
+    for (rng, format, compressibility, dedupe_ratio, pattern, region_chunks) in [
+        (RngAlgorithm::Pcg64, ChunkFormat::V1, None, 1, None, None),
+        (RngAlgorithm::AesCtr, ChunkFormat::V2, Compressibility::new(2.5), 4, None, None),
+        (RngAlgorithm::Pcg64, ChunkFormat::V2, None, 1, Some(Pattern::WalkingOnes), Some(32768)),
+    ] {
+        let seed = Seed::parse("0x1234567890abcdef1234").unwrap();
+        let header = StreamHeader {
+            seed,
+            rng,
+            format,
+            run_id: 42,
+            compressibility,
+            dedupe_ratio,
+            pattern,
+            region_chunks,
+        };
+        let mut data = header.encode().to_vec();
+        data.extend_from_slice(b"some data");
+        assert_eq!(StreamHeader::decode(&data), Some(header));
+        data[20] ^= 1;
+        assert_eq!(StreamHeader::decode(&data), None);
+    }
+    assert_eq!(StreamHeader::decode(&[0u8; HEADER_SIZE]), None);
+    assert_eq!(StreamHeader::decode(MAGIC), None);
+}
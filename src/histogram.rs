@@ -0,0 +1,222 @@
+//! A histogram of latencies, like HdrHistogram, with a bounded relative error
+//!
+//! The values below 2048 are counted exactly. The larger ones are counted in buckets of 1024
+//! values sharing their 11 most significant bits, so their error is below 0.1%, whatever their
+//! magnitude, in a fixed amount of memory.
+
+use std::fmt::Write as _;
+
+/// The number of bits of the values counted exactly
+const SUB_BUCKET_BITS: u32 = 11;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+/// The number of buckets of 1024 values, for the values larger than 2048
+const BUCKETS: usize = (u64::BITS - SUB_BUCKET_BITS) as usize;
+
+/// The count of the values recorded, by range of values
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    total: u64,
+    min: u64,
+    max: u64,
+    sum: u128,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            counts: vec![0; SUB_BUCKETS + BUCKETS * SUB_BUCKETS / 2],
+            total: 0,
+            min: u64::MAX,
+            max: 0,
+            sum: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn record(&mut self, value: u64) {
+        self.counts[index(value)] += 1;
+        self.total += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value as u128;
+    }
+
+    /// Add the values of another histogram
+    pub fn merge(&mut self, other: &Histogram) {
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+        self.total += other.total;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum += other.sum;
+    }
+
+    /// The number of values recorded
+    pub fn len(&self) -> u64 {
+        self.total
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+
+    pub fn min(&self) -> u64 {
+        if self.is_empty() { 0 } else { self.min }
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.is_empty() { 0.0 } else { self.sum as f64 / self.total as f64 }
+    }
+
+    /// The largest value of the first `percentile` percents of the values, within the error
+    pub fn value_at_percentile(&self, percentile: f64) -> u64 {
+        if self.is_empty() {
+            return 0;
+        }
+        let rank = ((percentile / 100.0 * self.total as f64).ceil() as u64).clamp(1, self.total);
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return highest_value(index).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    fn std_deviation(&self) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let mean = self.mean();
+        let squares: f64 = self
+            .counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(index, count)| (highest_value(index) as f64 - mean).powi(2) * *count as f64)
+            .sum();
+        (squares / self.total as f64).sqrt()
+    }
+
+    /// The percentile distribution, in the `.hgrm` format of HdrHistogram, read by its plotter
+    ///
+    /// The values are divided by `scale`, like 1e6 to get milliseconds from nanoseconds. The
+    /// percentiles get closer as they reach 100%, 5 per halving of the remaining distance.
+    pub fn percentile_distribution(&self, scale: f64) -> String {
+        let mut out = String::new();
+        writeln!(
+            out,
+            "{:>12} {:>14} {:>10} {:>14}\n",
+            "Value", "Percentile", "TotalCount", "1/(1-Percentile)"
+        )
+        .unwrap();
+        let mut percentile = 0.0_f64;
+        while !self.is_empty() {
+            let value = self.value_at_percentile(percentile);
+            let count = self.count_up_to(value);
+            if count == self.total {
+                break;
+            }
+            writeln!(
+                out,
+                "{:12.3} {:2.12} {count:10} {:14.2}",
+                value as f64 / scale,
+                percentile / 100.0,
+                1.0 / (1.0 - percentile / 100.0)
+            )
+            .unwrap();
+            let halvings = (100.0 / (100.0 - percentile)).log2().floor() as i32 + 1;
+            percentile += 100.0 / (5.0 * 2.0_f64.powi(halvings));
+        }
+        writeln!(out, "{:12.3} {:2.12} {:10}", self.max as f64 / scale, 1.0, self.total).unwrap();
+        writeln!(
+            out,
+            "#[Mean    = {:12.3}, StdDeviation   = {:12.3}]",
+            self.mean() / scale,
+            self.std_deviation() / scale
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "#[Max     = {:12.3}, Total count    = {:12}]",
+            self.max as f64 / scale,
+            self.total
+        )
+        .unwrap();
+        writeln!(out, "#[Buckets = {BUCKETS:12}, SubBuckets     = {SUB_BUCKETS:12}]").unwrap();
+        out
+    }
+
+    /// The number of values up to the bucket of that value
+    fn count_up_to(&self, value: u64) -> u64 {
+        self.counts[..=index(value)].iter().sum()
+    }
+}
+
+/// The index of the bucket counting the value
+fn index(value: u64) -> usize {
+    if value < SUB_BUCKETS as u64 {
+        return value as usize;
+    }
+    // the shift keeping the 11 most significant bits
+    let shift = u64::BITS - SUB_BUCKET_BITS - value.leading_zeros();
+    let sub_bucket = (value >> shift) as usize - SUB_BUCKETS / 2;
+    SUB_BUCKETS + (shift as usize - 1) * SUB_BUCKETS / 2 + sub_bucket
+}
+
+/// The largest value counted by the bucket
+fn highest_value(index: usize) -> u64 {
+    if index < SUB_BUCKETS {
+        return index as u64;
+    }
+    let shift = ((index - SUB_BUCKETS) / (SUB_BUCKETS / 2) + 1) as u32;
+    let sub_bucket = ((index - SUB_BUCKETS) % (SUB_BUCKETS / 2) + SUB_BUCKETS / 2) as u64;
+    ((sub_bucket + 1) << shift).wrapping_sub(1)
+}
+
+#[test]
+fn values_within_the_error() {
+    let mut histogram = Histogram::default();
+    for value in [0, 1, 2047, 2048, 2049, 4095, 4096, 123_456_789, u64::MAX] {
+        let index = index(value);
+        assert!(highest_value(index) >= value, "{value}");
+        assert!(index == 0 || highest_value(index - 1) < value, "{value}");
+        assert!((highest_value(index) - value) as f64 <= value as f64 / 1000.0, "{value}");
+        histogram.record(value);
+    }
+    assert_eq!(index(u64::MAX), histogram.counts.len() - 1);
+    assert_eq!((histogram.len(), histogram.min(), histogram.max()), (9, 0, u64::MAX));
+    assert_eq!(histogram.value_at_percentile(50.0), 2049);
+    assert_eq!(histogram.value_at_percentile(100.0), u64::MAX);
+}
+
+#[test]
+fn percentile_distribution() {
+    let mut histogram = Histogram::default();
+    for value in 1..=1000 {
+        histogram.record(value * 1000);
+    }
+    let mut other = Histogram::default();
+    other.record(5_000_000);
+    histogram.merge(&other);
+    assert_eq!(histogram.value_at_percentile(50.0), 501_247);
+    let hgrm = histogram.percentile_distribution(1e6);
+    let lines: Vec<_> = hgrm.lines().collect();
+    assert_eq!(lines[0], "       Value     Percentile TotalCount 1/(1-Percentile)");
+    assert_eq!(lines[2], "       0.001 0.000000000000          1           1.00");
+    assert_eq!(lines[3], "       0.101 0.100000000000        101           1.11");
+    assert!(lines.contains(&"       5.000 1.000000000000       1001"), "{hgrm}");
+    assert!(hgrm.ends_with(
+        "#[Max     =        5.000, Total count    =         1001]\n\
+         #[Buckets =           53, SubBuckets     =         2048]\n"
+    ));
+    assert_eq!(Histogram::default().percentile_distribution(1.0).lines().count(), 6);
+}
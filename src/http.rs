@@ -0,0 +1,307 @@
+//! The streams uploaded to or downloaded from an `http://` or `https://` URL given instead of a
+//! file
+//!
+//! The stream is uploaded as the body of a single request. It's downloaded with parallel range
+//! requests when the server supports them, or else with a single one.
+
+use anyhow::anyhow;
+use clap::ValueEnum;
+use log::{debug, info};
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+use ureq::SendBody;
+
+/// The size of the ranges downloaded by each connection
+const RANGE_SIZE: u64 = 8 << 20;
+
+/// The number of data buffers or ranges queued, waiting to be sent or read
+const QUEUE_DEPTH: usize = 4;
+
+/// The method of the upload request
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HttpMethod {
+    #[default]
+    Put,
+    Post,
+}
+
+/// The URL given as file, if it's an `http://` or `https://` one
+pub fn url(file: &Path) -> Option<&str> {
+    file.to_str().filter(|f| f.starts_with("http://") || f.starts_with("https://"))
+}
+
+/// The data written, sent as the body of the request
+pub struct UploadWriter {
+    tx: SyncSender<Vec<u8>>,
+}
+
+impl Write for UploadWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.tx.send(buf.to_vec()) {
+            Ok(()) => Ok(buf.len()),
+            // the request has failed
+            Err(_) => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The body of the request, read from the data written
+pub(crate) struct UploadBody {
+    rx: Receiver<Vec<u8>>,
+    data: io::Cursor<Vec<u8>>,
+}
+
+impl Read for UploadBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let size = self.data.read(buf)?;
+            if size > 0 || buf.is_empty() {
+                return Ok(size);
+            }
+            match self.rx.recv() {
+                Ok(data) => self.data = io::Cursor::new(data),
+                // the writer is closed
+                Err(_) => return Ok(0),
+            }
+        }
+    }
+}
+
+/// The request uploading the data written, until the writer is dropped
+pub struct Upload {
+    handle: JoinHandle<anyhow::Result<()>>,
+}
+
+impl Upload {
+    /// Start the request, sending a body of that size
+    pub fn start(url: &str, method: HttpMethod, size: u64) -> (UploadWriter, Upload) {
+        let url = url.to_string();
+        Upload::spawn(move |body| {
+            let request = match method {
+                HttpMethod::Put => ureq::put(&url),
+                HttpMethod::Post => ureq::post(&url),
+            };
+            let response = request
+                .header("content-length", size)
+                .header("content-type", "application/octet-stream")
+                .send(SendBody::from_owned_reader(body))
+                .map_err(|e| io::Error::other(format!("The upload to {url} failed: {e}")))?;
+            info!("uploaded to {url}: {}", response.status());
+            Ok(())
+        })
+    }
+
+    /// Upload the body read from the data written, in another thread
+    pub(crate) fn spawn<F>(upload: F) -> (UploadWriter, Upload)
+    where
+        F: FnOnce(UploadBody) -> anyhow::Result<()> + Send + 'static,
+    {
+        let (tx, rx) = mpsc::sync_channel(QUEUE_DEPTH);
+        let handle =
+            thread::spawn(move || upload(UploadBody { rx, data: io::Cursor::new(Vec::new()) }));
+        (UploadWriter { tx }, Upload { handle })
+    }
+
+    /// Wait for the answer of the server, once the writer is dropped
+    pub fn finish(self) -> anyhow::Result<()> {
+        self.handle.join().unwrap()
+    }
+}
+
+/// Download the data from the position, up to the end if no size is given
+///
+/// The ranges are downloaded with several connections, if the server supports range requests and
+/// tells the size of the data. The size available from the position is returned too, if known.
+pub fn download(
+    url: &str,
+    position: u64,
+    size: Option<u64>,
+    connections: usize,
+) -> anyhow::Result<(Box<dyn Read>, Option<u64>)> {
+    // some servers only answer GET requests
+    let head = ureq::head(url).call().ok();
+    let length = head.as_ref().and_then(|r| r.headers().get("content-length"));
+    let length = length.and_then(|l| l.to_str().ok()).and_then(|l| l.parse::<u64>().ok());
+    let ranges = head
+        .as_ref()
+        .and_then(|r| r.headers().get("accept-ranges"))
+        .is_some_and(|r| r.as_bytes() == b"bytes");
+    let available = length.map(|length| length.saturating_sub(position));
+    let end = match (size, length) {
+        (Some(size), Some(length)) => Some((position + size).min(length)),
+        (Some(size), None) => Some(position + size),
+        (None, length) => length,
+    };
+    if let (true, Some(end)) = (ranges, end) {
+        debug!("downloading {url} with {connections} connections");
+        let url = url.to_string();
+        let fetch = Arc::new(move |range| download_range(&url, range));
+        return Ok((Box::new(RangeReader::new(fetch, position, end, connections)), available));
+    }
+    let mut request = ureq::get(url);
+    if position > 0 {
+        request = request.header("range", format!("bytes={position}-"));
+    }
+    let response = request
+        .call()
+        .map_err(|e| io::Error::other(format!("The download of {url} failed: {e}")))?;
+    if position > 0 && response.status() != 206 {
+        return Err(anyhow!("The server of {url} doesn't support range requests"));
+    }
+    Ok((Box::new(response.into_body().into_reader()), available))
+}
+
+/// Download a range of the data
+pub(crate) type FetchRange = Arc<dyn Fn(Range<u64>) -> io::Result<Vec<u8>> + Send + Sync>;
+
+/// The ranges downloaded by several threads, read in order
+///
+/// The thread `i` out of `n` downloads the ranges `i`, `i + n`, `i + 2n`...
+pub(crate) struct RangeReader {
+    receivers: Vec<Receiver<io::Result<Vec<u8>>>>,
+    next: usize,
+    data: io::Cursor<Vec<u8>>,
+}
+
+impl RangeReader {
+    pub(crate) fn new(fetch: FetchRange, start: u64, end: u64, connections: usize) -> Self {
+        let connections = connections.max(1);
+        let receivers = (0..connections)
+            .map(|i| {
+                let (tx, rx) = mpsc::sync_channel(QUEUE_DEPTH);
+                let fetch = fetch.clone();
+                let ranges = (start + i as u64 * RANGE_SIZE..end)
+                    .step_by(connections * RANGE_SIZE as usize)
+                    .map(move |range_start| range_start..(range_start + RANGE_SIZE).min(end));
+                thread::spawn(move || {
+                    for range in ranges {
+                        let result = fetch(range);
+                        let failed = result.is_err();
+                        // the reader is dropped, or the download has failed
+                        if tx.send(result).is_err() || failed {
+                            break;
+                        }
+                    }
+                });
+                rx
+            })
+            .collect();
+        RangeReader { receivers, next: 0, data: io::Cursor::new(Vec::new()) }
+    }
+}
+
+impl Read for RangeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let size = self.data.read(buf)?;
+            if size > 0 || buf.is_empty() {
+                return Ok(size);
+            }
+            match self.receivers[self.next].recv() {
+                Ok(data) => self.data = io::Cursor::new(data?),
+                // all the ranges have been read
+                Err(_) => return Ok(0),
+            }
+            self.next = (self.next + 1) % self.receivers.len();
+        }
+    }
+}
+
+fn download_range(url: &str, range: Range<u64>) -> io::Result<Vec<u8>> {
+    let response = ureq::get(url)
+        .header("range", format!("bytes={}-{}", range.start, range.end - 1))
+        .call()
+        .map_err(|e| io::Error::other(format!("The download of {url} failed: {e}")))?;
+    if response.status() != 206 {
+        return Err(io::Error::other(format!("The server of {url} ignored the range request")));
+    }
+    let mut data = Vec::with_capacity((range.end - range.start) as usize);
+    response.into_body().into_reader().read_to_end(&mut data)?;
+    if data.len() as u64 != range.end - range.start {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(data)
+}
+
+/// Serve the PUT, HEAD and GET requests, with ranges, for the data uploaded
+#[cfg(test)]
+fn serve(listener: std::net::TcpListener, requests: usize) -> Vec<u8> {
+    use std::io::BufRead as _;
+    let mut stored = Vec::new();
+    for _ in 0..requests {
+        let mut socket = io::BufReader::new(listener.accept().unwrap().0);
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            socket.read_line(&mut line).unwrap();
+            if line.trim().is_empty() {
+                break;
+            }
+            lines.push(line.trim().to_lowercase());
+        }
+        let header = |name: &str| lines.iter().find_map(|l| l.strip_prefix(name));
+        let method = lines[0].split(' ').next().unwrap().to_string();
+        let response = if method == "put" {
+            let length = header("content-length: ").unwrap().parse().unwrap();
+            stored = vec![0; length];
+            socket.read_exact(&mut stored).unwrap();
+            "HTTP/1.1 201 Created\r\nconnection: close\r\ncontent-length: 0\r\n\r\n"
+                .as_bytes()
+                .to_vec()
+        } else if let Some(range) = header("range: bytes=") {
+            let (start, end) = range.split_once('-').unwrap();
+            let start: usize = start.parse().unwrap();
+            let end = end.parse::<usize>().map(|e| e + 1).unwrap_or(stored.len());
+            let mut response = format!(
+                "HTTP/1.1 206 Partial Content\r\nconnection: close\r\ncontent-length: {}\r\n\r\n",
+                end - start
+            )
+            .into_bytes();
+            response.extend(&stored[start..end]);
+            response
+        } else {
+            let mut response = format!(
+                "HTTP/1.1 200 OK\r\nconnection: close\r\naccept-ranges: bytes\r\ncontent-length: {}\r\n\r\n",
+                stored.len()
+            )
+            .into_bytes();
+            if method == "get" {
+                response.extend(&stored);
+            }
+            response
+        };
+        let mut socket = socket.into_inner();
+        socket.write_all(&response).unwrap();
+    }
+    stored
+}
+
+#[test]
+fn upload_and_download_ranges() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://127.0.0.1:{}/stream", listener.local_addr().unwrap().port());
+    let size = 3 * RANGE_SIZE as usize + 1000;
+    let data: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+    // an upload, then a HEAD and 3 range requests
+    let server = thread::spawn(move || serve(listener, 5));
+    let (mut writer, upload) = Upload::start(&url, HttpMethod::Put, size as u64);
+    for part in data.chunks(100_000) {
+        writer.write_all(part).unwrap();
+    }
+    drop(writer);
+    upload.finish().unwrap();
+    let (mut reader, available) = download(&url, 1000, None, 2).unwrap();
+    assert_eq!(available, Some(size as u64 - 1000));
+    let mut downloaded = Vec::new();
+    reader.read_to_end(&mut downloaded).unwrap();
+    assert!(downloaded == data[1000..]);
+    assert!(server.join().unwrap() == data);
+}
@@ -0,0 +1,110 @@
+//! The identity of a block device, its WWN or serial number, with `--seed-from-device`
+//!
+//! The seed of the stream is derived from the identity, so each disk of a chassis gets its own
+//! stream, and the stream of a disk isn't validated on another one when the cables are swapped.
+
+use std::path::Path;
+
+use log::info;
+
+use crate::error::usage;
+use crate::rng::Seed;
+
+/// The seed derived from the identity of the device
+pub(crate) fn device_seed(file: &Path) -> anyhow::Result<Seed> {
+    let identity = device_identity(file)?;
+    info!("device identity: {identity}");
+    Ok(Seed::from_string(&identity))
+}
+
+/// The identity of the device, from sysfs or else from the links udev creates in
+/// `/dev/disk/by-id`
+#[cfg(target_os = "linux")]
+fn device_identity(file: &Path) -> anyhow::Result<String> {
+    use std::os::unix::fs::{FileTypeExt as _, MetadataExt as _};
+
+    let metadata = std::fs::metadata(file).ok().filter(|m| m.file_type().is_block_device());
+    let Some(metadata) = metadata else {
+        return Err(usage(format!(
+            "--seed-from-device requires a block device, {} isn't one",
+            file.display()
+        )));
+    };
+    let rdev = metadata.rdev();
+    let device = format!("/sys/dev/block/{}:{}", nix::libc::major(rdev), nix::libc::minor(rdev));
+    sysfs_identity(Path::new(&device))
+        .or_else(|| udev_identity(file))
+        .ok_or_else(|| usage(format!("No WWN or serial number found for {}", file.display())))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn device_identity(_file: &Path) -> anyhow::Result<String> {
+    Err(usage("--seed-from-device is only available on Linux"))
+}
+
+/// The WWN or serial number of the device, from its sysfs directory
+///
+/// A partition is identified by the one of its disk, and its number.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn sysfs_identity(device: &Path) -> Option<String> {
+    let partition = attribute(&device.join("partition"));
+    let disk = match partition {
+        Some(_) => device.join(".."),
+        None => device.to_path_buf(),
+    };
+    // NVMe and SCSI disks have a WWID, virtio ones a serial number only
+    let attributes = [
+        ("wwid", "wwid"),
+        ("device/wwid", "wwid"),
+        ("device/serial", "serial"),
+        ("serial", "serial"),
+    ];
+    let identity = attributes.into_iter().find_map(|(name, kind)| {
+        attribute(&disk.join(name)).map(|value| format!("{kind}:{value}"))
+    })?;
+    Some(match partition {
+        Some(number) => format!("{identity}-part{number}"),
+        None => identity,
+    })
+}
+
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn attribute(path: &Path) -> Option<String> {
+    let value = std::fs::read_to_string(path).ok()?;
+    Some(value.trim().to_string()).filter(|value| !value.is_empty())
+}
+
+/// The name of the link to the device in `/dev/disk/by-id`, the `wwn-` one if any
+#[cfg(target_os = "linux")]
+fn udev_identity(file: &Path) -> Option<String> {
+    let device = std::fs::canonicalize(file).ok()?;
+    let mut names: Vec<_> = std::fs::read_dir("/dev/disk/by-id")
+        .ok()?
+        .flatten()
+        .filter(|entry| std::fs::canonicalize(entry.path()).is_ok_and(|path| path == device))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+    names.sort_by_key(|name| (!name.starts_with("wwn-"), name.clone()));
+    names.into_iter().next().map(|name| format!("udev:{name}"))
+}
+
+#[test]
+fn identity_from_sysfs() {
+    use std::fs;
+
+    let dir = tempfile::tempdir().unwrap();
+    let disk = dir.path().join("sda");
+    fs::create_dir_all(disk.join("device")).unwrap();
+    fs::create_dir(disk.join("sda1")).unwrap();
+    fs::write(disk.join("sda1/partition"), "1\n").unwrap();
+    assert_eq!(sysfs_identity(&disk), None);
+
+    fs::write(disk.join("device/serial"), "  S4EVNX0R123456 \n").unwrap();
+    assert_eq!(sysfs_identity(&disk).as_deref(), Some("serial:S4EVNX0R123456"));
+    fs::write(disk.join("device/wwid"), "naa.5000c500a1b2c3d4\n").unwrap();
+    assert_eq!(sysfs_identity(&disk).as_deref(), Some("wwid:naa.5000c500a1b2c3d4"));
+    assert_eq!(
+        sysfs_identity(&disk.join("sda1")).as_deref(),
+        Some("wwid:naa.5000c500a1b2c3d4-part1")
+    );
+}
@@ -0,0 +1,149 @@
+//! The block devices in use, refused by generate unless `--force` is given
+//!
+//! A device, or one of its partitions, is in use when it's mounted, used as swap, held by
+//! another device, like an md array or a device mapper volume of LVM, or opened exclusively, like
+//! the disks of a ZFS pool. Writing the stream would destroy the data of a live system.
+
+use std::path::Path;
+
+/// Refuse to write to the block device if it's in use
+#[cfg(target_os = "linux")]
+pub(crate) fn check_unused(file: &Path) -> anyhow::Result<()> {
+    use std::os::unix::fs::{FileTypeExt as _, MetadataExt as _};
+
+    use crate::error::usage;
+
+    let Ok(metadata) = std::fs::metadata(file) else { return Ok(()) };
+    if !metadata.file_type().is_block_device() {
+        return Ok(());
+    }
+    let rdev = metadata.rdev();
+    let device = format!("/sys/dev/block/{}:{}", nix::libc::major(rdev), nix::libc::minor(rdev));
+    let Ok(device) = std::fs::canonicalize(device) else { return Ok(()) };
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo").unwrap_or_default();
+    let swaps = std::fs::read_to_string("/proc/swaps").unwrap_or_default();
+    for (index, device) in devices(&device).iter().enumerate() {
+        let Some(name) = device.file_name().map(|name| name.to_string_lossy().into_owned()) else {
+            continue;
+        };
+        let use_ = attribute(&device.join("dev"))
+            .and_then(|dev| mount_point(&mountinfo, &dev))
+            .map(|mount_point| format!("mounted on {mount_point}"))
+            .or_else(|| is_swap(&swaps, &name).then(|| "used as swap".to_string()))
+            .or_else(|| holders(device).map(|holders| format!("held by {holders}")))
+            .or_else(|| is_open_exclusively(&name).then(|| "opened exclusively".to_string()));
+        if let Some(use_) = use_ {
+            let use_ = match index {
+                0 => use_,
+                _ => format!("its partition /dev/{name} is {use_}"),
+            };
+            return Err(usage(format!(
+                "{} is in use, {use_}: use --force to overwrite it anyway",
+                file.display()
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn check_unused(_file: &Path) -> anyhow::Result<()> {
+    Ok(())
+}
+
+/// The sysfs directories of the device and of its partitions, if it's a disk
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn devices(device: &Path) -> Vec<std::path::PathBuf> {
+    let mut devices = vec![device.to_path_buf()];
+    if !device.join("partition").exists()
+        && let Ok(entries) = std::fs::read_dir(device)
+    {
+        let mut partitions: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.join("partition").exists())
+            .collect();
+        partitions.sort();
+        devices.extend(partitions);
+    }
+    devices
+}
+
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn attribute(path: &Path) -> Option<String> {
+    let value = std::fs::read_to_string(path).ok()?;
+    Some(value.trim().to_string()).filter(|value| !value.is_empty())
+}
+
+/// Where the device, as `major:minor`, is mounted, from `/proc/self/mountinfo`
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn mount_point(mountinfo: &str, dev: &str) -> Option<String> {
+    mountinfo.lines().find_map(|line| {
+        let mut fields = line.split(' ').skip(2);
+        (fields.next()? == dev).then(|| fields.nth(1).map(str::to_string))?
+    })
+}
+
+/// Whether the device is a swap area in use, from `/proc/swaps`
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn is_swap(swaps: &str, name: &str) -> bool {
+    let device = format!("/dev/{name}");
+    swaps.lines().skip(1).any(|line| line.split_whitespace().next() == Some(&device))
+}
+
+/// The devices built on top of the device, like md arrays or device mapper volumes
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn holders(device: &Path) -> Option<String> {
+    let mut holders: Vec<_> = std::fs::read_dir(device.join("holders"))
+        .ok()?
+        .filter_map(|entry| {
+            entry.ok().map(|entry| entry.file_name().to_string_lossy().into_owned())
+        })
+        .collect();
+    holders.sort();
+    (!holders.is_empty()).then(|| holders.join(", "))
+}
+
+/// Whether the device is opened exclusively, by the kernel or another process: opening it with
+/// O_EXCL fails then
+#[cfg(target_os = "linux")]
+fn is_open_exclusively(name: &str) -> bool {
+    use std::os::unix::fs::OpenOptionsExt as _;
+
+    let open = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(nix::libc::O_EXCL)
+        .open(Path::new("/dev").join(name));
+    open.is_err_and(|e| e.raw_os_error() == Some(nix::libc::EBUSY))
+}
+
+#[test]
+fn devices_in_use() {
+    use std::fs;
+
+    let mountinfo = "\
+        22 1 8:2 / / rw,relatime shared:1 - ext4 /dev/sda2 rw\n\
+        25 22 0:22 / /proc rw,nosuid shared:12 - proc proc rw\n\
+        30 22 8:1 / /boot/efi rw,relatime shared:2 - vfat /dev/sda1 rw\n";
+    assert_eq!(mount_point(mountinfo, "8:1"), Some("/boot/efi".to_string()));
+    assert_eq!(mount_point(mountinfo, "8:2"), Some("/".to_string()));
+    assert_eq!(mount_point(mountinfo, "8:16"), None);
+
+    let swaps = "Filename\tType\tSize\tUsed\tPriority\n/dev/sda3 partition 8388604 0 -2\n";
+    assert!(is_swap(swaps, "sda3"));
+    assert!(!is_swap(swaps, "sda"));
+
+    let dir = tempfile::tempdir().unwrap();
+    let disk = dir.path().join("sdb");
+    for partition in ["sdb2", "sdb1"] {
+        fs::create_dir_all(disk.join(partition).join("holders")).unwrap();
+        fs::write(disk.join(partition).join("partition"), "1\n").unwrap();
+    }
+    fs::create_dir_all(disk.join("holders")).unwrap();
+    fs::create_dir_all(disk.join("queue")).unwrap();
+    assert_eq!(devices(&disk), [disk.clone(), disk.join("sdb1"), disk.join("sdb2")]);
+    assert_eq!(devices(&disk.join("sdb1")), [disk.join("sdb1")]);
+    assert_eq!(holders(&disk.join("sdb2")), None);
+    fs::create_dir(disk.join("sdb2/holders/md0")).unwrap();
+    fs::create_dir(disk.join("sdb2/holders/dm-3")).unwrap();
+    assert_eq!(holders(&disk.join("sdb2")), Some("dm-3, md0".to_string()));
+}
@@ -0,0 +1,118 @@
+//! The journal of the chunk operations, with `--journal`, for the postmortem analysis of a run
+//!
+//! Each operation is appended to the file as a JSON object per line, with a single write once it
+//! has completed, so the journal of a run stopped by a crash or a device dropping off the bus
+//! still tells what had been done, and when.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write as _};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::report::quote;
+
+/// What was done to a chunk
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Operation {
+    Write,
+    Read,
+}
+
+/// The journal shared by the threads of a run
+#[derive(Clone, Debug)]
+pub(crate) struct Journal {
+    file: Arc<Mutex<File>>,
+    /// The width of the checksum at the end of the chunks
+    width: usize,
+}
+
+impl Journal {
+    /// Open the journal, appending to it if it already exists
+    pub fn open(path: &Path, width: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Journal { file: Arc::new(Mutex::new(file)), width })
+    }
+
+    /// Record an operation on a chunk, started at `start` and just completed
+    ///
+    /// `offset` is the offset of the chunk in the file. The result is the data of the chunk, or the
+    /// reason of its failure.
+    pub fn record(
+        &self,
+        operation: Operation,
+        chunk: u64,
+        offset: u64,
+        size: usize,
+        result: Result<&[u8], &str>,
+        start: SystemTime,
+    ) -> io::Result<()> {
+        let end = SystemTime::now();
+        let line = entry(operation, chunk, offset, size, result, self.width, start, end);
+        self.file.lock().unwrap().write_all(line.as_bytes())
+    }
+}
+
+/// The line of the journal recording an operation
+#[allow(clippy::too_many_arguments)]
+fn entry(
+    operation: Operation,
+    chunk: u64,
+    offset: u64,
+    size: usize,
+    result: Result<&[u8], &str>,
+    width: usize,
+    start: SystemTime,
+    end: SystemTime,
+) -> String {
+    let operation = match operation {
+        Operation::Write => "write",
+        Operation::Read => "read",
+    };
+    let (result, checksum) = match result {
+        // the checksum sealing the chunk, as found at its end
+        Ok(data) if data.len() > width => {
+            let mut bytes = [0; 8];
+            bytes[..width].copy_from_slice(&data[data.len() - width..]);
+            let checksum = format!("{:0digits$x}", u64::from_le_bytes(bytes), digits = width * 2);
+            ("\"ok\"".to_string(), quote(&checksum))
+        }
+        Ok(_) => ("\"ok\"".to_string(), "null".to_string()),
+        Err(error) => (format!("\"error\",\"error\":{}", quote(error)), "null".to_string()),
+    };
+    format!(
+        "{{\"op\":\"{operation}\",\"chunk\":{chunk},\"offset\":{offset},\"size\":{},\"start\":{},\
+         \"end\":{},\"result\":{result},\"checksum\":{checksum}}}\n",
+        size,
+        timestamp(start),
+        timestamp(end),
+    )
+}
+
+/// The time since the epoch, in seconds with a microsecond precision
+fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("{}.{:06}", since_epoch.as_secs(), since_epoch.subsec_micros())
+}
+
+#[test]
+fn journal_entries_as_json_lines() {
+    use std::time::Duration;
+
+    let start = UNIX_EPOCH + Duration::from_micros(1_700_000_000_000_042);
+    let end = start + Duration::from_millis(3);
+    let data = [0, 1, 2, 3, 0x78, 0x56, 0x34, 0x12];
+    assert_eq!(
+        entry(Operation::Write, 2, 64, 8, Ok(&data), 4, start, end),
+        "{\"op\":\"write\",\"chunk\":2,\"offset\":64,\"size\":8,\"start\":1700000000.000042,\
+         \"end\":1700000000.003042,\"result\":\"ok\",\"checksum\":\"12345678\"}\n"
+    );
+    assert_eq!(
+        entry(Operation::Read, 3, 96, 8, Err("Invalid \"chunk\""), 4, start, end),
+        "{\"op\":\"read\",\"chunk\":3,\"offset\":96,\"size\":8,\"start\":1700000000.000042,\
+         \"end\":1700000000.003042,\"result\":\"error\",\"error\":\"Invalid \\\"chunk\\\"\",\
+         \"checksum\":null}\n"
+    );
+    let short = entry(Operation::Write, 4, 128, 4, Ok(&data[..4]), 4, start, end);
+    assert!(short.ends_with("\"result\":\"ok\",\"checksum\":null}\n"));
+}
@@ -1,33 +1,152 @@
 use std::fs::File;
 use std::io;
 use std::io::IsTerminal as _;
-use std::os::fd::AsRawFd as _;
-use std::sync::mpsc::{Receiver, Sender};
+use std::io::{IoSlice, Write};
+#[cfg(unix)]
+use std::os::{fd::AsRawFd as _, unix::fs::FileTypeExt as _};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, LazyLock, Mutex};
 use std::time::{Duration, Instant};
-use std::{io::Read, os::unix::fs::FileTypeExt, path::Path};
+use std::{io::Read, path::Path};
 
+use clap::ValueEnum;
 use human_units::{FormatDuration, FormatSize as _};
-use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
 
 extern crate log;
 use log::debug;
 
+use crate::checksum::{ChecksumAlgorithm, StreamChecksum};
+use crate::digest::{DigestAlgorithm, StreamDigest};
+use crate::work::ThreadPositions;
+
+mod aes;
+pub mod affinity;
+pub mod bench;
+mod blake3;
+pub mod cache;
+mod checkpoint;
+pub mod checksum;
+pub mod chunk;
 pub mod cli;
+pub mod completions;
+pub mod compress;
+pub mod config;
+pub mod corrupt;
+mod crc64;
+pub mod decompress;
+pub mod dedupe;
+mod devices;
+pub mod diff;
+pub mod digest;
+mod direct;
+pub mod discard;
+pub mod engine;
+pub mod error;
+pub mod fsync;
 pub mod generate;
+pub mod header;
+mod histogram;
+pub mod http;
+mod identity;
+mod inuse;
+mod journal;
+#[cfg(unix)]
+mod mapping;
+pub mod nbd;
+pub mod net;
+pub mod options;
+pub mod pattern;
+pub mod perf;
+pub mod repair;
+pub mod report;
+mod retry;
+pub mod rng;
+mod rotational;
+pub mod s3;
+pub mod schedule;
+mod scrub;
+mod sector;
+pub mod serve;
+mod sha256;
+mod shard;
+mod sparse;
+pub mod ssh;
+pub mod status;
+pub mod stream;
+mod sumfile;
+pub mod systemd;
+mod tar;
+pub mod throttle;
+mod tree;
+pub mod udp;
+#[cfg(target_os = "linux")]
+mod uring;
 pub mod validate;
+pub mod verify;
+mod watch;
+#[cfg(windows)]
+mod windows;
+mod work;
+mod xoshiro;
+mod xxh3;
+
+pub use error::Error;
+
+/// The checksum sealing each chunk of a stream
+///
+/// It also accumulates the checksum of the whole stream, chunk after chunk. The checksum of a
+/// chunk is stored at the end of the chunk, on `width()` bytes, in little endian.
+pub trait ChunkChecksum {
+    /// The number of bytes used by the checksum at the end of each chunk, at most 8
+    fn width(&self) -> usize;
+
+    /// Add a chunk payload to the stream checksum, and return the checksum of that payload
+    fn update(&mut self, payload: &[u8]) -> u64;
+
+    /// Append the stream checksum of the chunks following the ones of this checksum
+    fn combine(&mut self, other: &Self);
+
+    /// The checksum of the whole stream
+    fn finalize(&self) -> u64;
+}
+
+/// The random generator filling the chunks of a stream
+///
+/// The threads generating separate ranges of the stream skip the output of the generator up to
+/// the start of their range, so the stream doesn't depend on the number of threads.
+pub trait SeekableRng {
+    /// Fill the buffer with the next bytes of the output
+    fn fill_bytes(&mut self, buffer: &mut [u8]);
+
+    /// Skip the next `bytes` bytes of the output, a multiple of 8
+    fn advance(&mut self, bytes: u64);
+}
 
 #[cfg(target_os = "linux")]
 mod blk {
-    use nix::ioctl_read;
+    use nix::{ioctl_read, ioctl_read_bad, ioctl_write_ptr_bad, request_code_none};
     ioctl_read!(blkgetsize64, 0x12, 114, u64);
+    ioctl_read_bad!(blksszget, request_code_none!(0x12, 104), i32);
+    ioctl_read_bad!(blkpbszget, request_code_none!(0x12, 123), u32);
+    ioctl_write_ptr_bad!(blkdiscard, request_code_none!(0x12, 119), [u64; 2]);
 }
 
 #[cfg(target_os = "freebsd")]
 mod blk {
     use nix::ioctl_read;
     ioctl_read!(diocgmediasize, b'd', 129, u64);
+    ioctl_read!(diocgsectorsize, b'd', 128, u32);
 }
 
+#[cfg(target_os = "macos")]
+mod blk {
+    use nix::ioctl_read;
+    ioctl_read!(dkiocgetblocksize, b'd', 24, u32);
+    ioctl_read!(dkiocgetblockcount, b'd', 25, u64);
+}
+
+#[cfg(unix)]
 pub fn read_file_size(path: &Path) -> anyhow::Result<u64> {
     let file_type = std::fs::metadata(path)?.file_type();
     if file_type.is_block_device() || file_type.is_char_device() {
@@ -48,11 +167,100 @@ pub fn read_file_size(path: &Path) -> anyhow::Result<u64> {
                 .map_err(|e| io::Error::from_raw_os_error(e as i32))?;
             Ok(size)
         }
+
+        #[cfg(target_os = "macos")]
+        unsafe {
+            let mut block_size: u32 = 0;
+            let mut block_count: u64 = 0;
+            blk::dkiocgetblocksize(fd, &mut block_size)
+                .map_err(|e| io::Error::from_raw_os_error(e as i32))?;
+            blk::dkiocgetblockcount(fd, &mut block_count)
+                .map_err(|e| io::Error::from_raw_os_error(e as i32))?;
+            Ok(block_count * block_size as u64)
+        }
+    } else {
+        Ok(path.metadata()?.len())
+    }
+}
+
+#[cfg(windows)]
+pub fn read_file_size(path: &Path) -> anyhow::Result<u64> {
+    if windows::is_device(path) {
+        Ok(windows::device_size(&File::open(path)?)?)
     } else {
         Ok(path.metadata()?.len())
     }
 }
 
+/// The logical block size of the file, to which the direct I/O must be aligned
+#[cfg(unix)]
+pub fn read_block_size(path: &Path) -> anyhow::Result<usize> {
+    let metadata = std::fs::metadata(path)?;
+    let file_type = metadata.file_type();
+    if file_type.is_block_device() || file_type.is_char_device() {
+        let file = File::open(path)?;
+        let fd = file.as_raw_fd();
+
+        #[cfg(target_os = "linux")]
+        unsafe {
+            let mut size: i32 = 0;
+            blk::blksszget(fd, &mut size).map_err(|e| io::Error::from_raw_os_error(e as i32))?;
+            Ok(size as usize)
+        }
+
+        #[cfg(target_os = "freebsd")]
+        unsafe {
+            let mut size: u32 = 0;
+            blk::diocgsectorsize(fd, &mut size)
+                .map_err(|e| io::Error::from_raw_os_error(e as i32))?;
+            Ok(size as usize)
+        }
+
+        #[cfg(target_os = "macos")]
+        unsafe {
+            let mut size: u32 = 0;
+            blk::dkiocgetblocksize(fd, &mut size)
+                .map_err(|e| io::Error::from_raw_os_error(e as i32))?;
+            Ok(size as usize)
+        }
+    } else {
+        // the file system block size, which is always a multiple of the device one
+        use std::os::unix::fs::MetadataExt as _;
+        Ok(metadata.blksize() as usize)
+    }
+}
+
+/// The physical sector size of the block device, the unit it writes in, larger than the logical
+/// one on the Advanced Format disks
+#[cfg(target_os = "linux")]
+pub fn read_physical_block_size(path: &Path) -> Option<usize> {
+    let metadata = std::fs::metadata(path).ok()?;
+    if !metadata.file_type().is_block_device() {
+        return None;
+    }
+    let file = File::open(path).ok()?;
+    let mut size: u32 = 0;
+    // SAFETY: the output of the ioctl is an unsigned int
+    unsafe { blk::blkpbszget(file.as_raw_fd(), &mut size) }.ok()?;
+    Some(size as usize).filter(|size| *size > 0)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn read_physical_block_size(_path: &Path) -> Option<usize> {
+    None
+}
+
+/// The logical block size of the file, to which the direct I/O must be aligned
+#[cfg(windows)]
+pub fn read_block_size(path: &Path) -> anyhow::Result<usize> {
+    if windows::is_device(path) {
+        Ok(windows::sector_size(&File::open(path)?)?)
+    } else {
+        // a multiple of the sector sizes in use, and the size of the pages
+        Ok(4096)
+    }
+}
+
 fn read_exact_or_eof(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
     let mut bytes_read = 0;
     while bytes_read < buffer.len() {
@@ -65,9 +273,62 @@ fn read_exact_or_eof(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<us
     Ok(bytes_read)
 }
 
+/// Write all the slices, with as few system calls as possible
+fn write_all_vectored(writer: &mut impl Write, mut slices: &mut [IoSlice]) -> io::Result<()> {
+    IoSlice::advance_slices(&mut slices, 0);
+    while !slices.is_empty() {
+        match writer.write_vectored(slices) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => IoSlice::advance_slices(&mut slices, n),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// What has been written or read in a range of the stream
+#[derive(Clone, Debug)]
+pub struct StreamSummary {
+    pub bytes: u64,
+    pub checksum: StreamChecksum,
+    pub digest: Option<StreamDigest>,
+}
+
+impl StreamSummary {
+    /// Create an empty summary for the range of the stream starting at `offset`
+    pub fn new(checksum: ChecksumAlgorithm, digest: Option<DigestAlgorithm>, offset: u64) -> Self {
+        StreamSummary {
+            bytes: 0,
+            checksum: checksum.stream_checksum(),
+            digest: digest.map(|d| d.stream_digest(offset)),
+        }
+    }
+
+    /// Append the summary of the range following this one
+    pub fn combine(&mut self, other: &Self) {
+        self.bytes += other.bytes;
+        self.checksum.combine(&other.checksum);
+        if let (Some(digest), Some(other)) = (&mut self.digest, &other.digest) {
+            digest.combine(other);
+        }
+    }
+}
+
+/// Merge the summaries of consecutive ranges of the stream
+pub fn combine_summaries(summaries: Vec<StreamSummary>) -> StreamSummary {
+    let mut summaries = summaries.into_iter();
+    let mut summary = summaries.next().expect("at least one summary");
+    for partial_summary in summaries {
+        summary.combine(&partial_summary);
+    }
+    summary
+}
+
 /// Progress tracking for TTY (animated bar) or non-TTY (periodic log lines)
 #[derive(Debug)]
 pub struct LogProgress {
+    device: Option<String>,
     stream_size: u64,
     last_print: Instant,
     last_pct: u8,
@@ -76,9 +337,81 @@ pub struct LogProgress {
     interval_start: Instant,
 }
 
+/// Progress events, as JSON lines, for the tools orchestrating the runs
+#[derive(Debug)]
+pub struct JsonProgress {
+    device: Option<String>,
+    stream_size: Option<u64>,
+    start: Instant,
+    last_print: Instant,
+    prev_bytes: u64,
+    bytes_done: u64,
+}
+
+/// How the progress is reported
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProgressFormat {
+    /// A progress bar on a terminal, and log lines otherwise
+    #[default]
+    Auto,
+    /// A JSON object per line, every second
+    Json,
+}
+
 pub enum Progress {
     Bar(ProgressBar),
     Log(LogProgress),
+    Json(JsonProgress),
+    Callback(CallbackProgress),
+}
+
+/// The command reporting its progress to a callback
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProgressPhase {
+    Generate,
+    Validate,
+}
+
+/// The progress passed to a callback, instead of the progress bar
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProgressEvent {
+    pub phase: ProgressPhase,
+    pub bytes_done: u64,
+    /// The size of the stream, if known
+    pub total: Option<u64>,
+    /// The last event of the run
+    pub finished: bool,
+}
+
+/// A callback receiving the progress of a run, shared by the devices processed at once
+#[derive(Clone)]
+pub struct ProgressCallback {
+    phase: ProgressPhase,
+    callback: Arc<Mutex<dyn FnMut(ProgressEvent) + Send>>,
+}
+
+impl ProgressCallback {
+    pub fn new(phase: ProgressPhase, callback: impl FnMut(ProgressEvent) + Send + 'static) -> Self {
+        ProgressCallback { phase, callback: Arc::new(Mutex::new(callback)) }
+    }
+
+    fn call(&self, bytes_done: u64, total: Option<u64>, finished: bool) {
+        let event = ProgressEvent { phase: self.phase, bytes_done, total, finished };
+        (self.callback.lock().unwrap())(event);
+    }
+}
+
+impl std::fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProgressCallback").field("phase", &self.phase).finish_non_exhaustive()
+    }
+}
+
+/// Progress reported to a callback
+pub struct CallbackProgress {
+    callback: ProgressCallback,
+    stream_size: Option<u64>,
+    bytes_done: u64,
 }
 
 /// Metrics wrapper for tracking elapsed time, bytes processed, and throughput
@@ -90,15 +423,41 @@ pub struct Metrics {
 
 impl Progress {
     /// Create a new progress tracker. Returns `None` if progress is disabled or cannot be tracked.
-    pub fn new(stream_size: Option<u64>, no_progress: bool) -> anyhow::Result<Option<Self>> {
+    ///
+    /// The progress of a device processed along with others is labeled with its name. A callback
+    /// replaces any other progress report.
+    pub fn new(
+        stream_size: Option<u64>,
+        no_progress: bool,
+        format: ProgressFormat,
+        device: Option<&str>,
+        callback: Option<&ProgressCallback>,
+    ) -> anyhow::Result<Option<Self>> {
+        if let Some(callback) = callback {
+            return Ok(Some(Progress::Callback(CallbackProgress {
+                callback: callback.clone(),
+                stream_size,
+                bytes_done: 0,
+            })));
+        }
         if no_progress {
             return Ok(None);
         }
-        if std::io::stderr().is_terminal() {
-            Ok(Some(Progress::Bar(set_up_progress_bar(stream_size)?)))
+        if format == ProgressFormat::Json {
+            Ok(Some(Progress::Json(JsonProgress {
+                device: device.map(str::to_string),
+                stream_size,
+                start: Instant::now(),
+                last_print: Instant::now(),
+                prev_bytes: 0,
+                bytes_done: 0,
+            })))
+        } else if std::io::stderr().is_terminal() {
+            Ok(Some(Progress::Bar(set_up_progress_bar(stream_size, device)?)))
         } else if let Some(size) = stream_size {
             // Non-TTY with known size: use log-based progress
             Ok(Some(Progress::Log(LogProgress {
+                device: device.map(str::to_string),
                 stream_size: size,
                 last_print: Instant::now(),
                 last_pct: 0,
@@ -117,13 +476,21 @@ impl Progress {
         match self {
             Progress::Bar(pb) => pb.set_position(bytes_done),
             Progress::Log(lp) => lp.tick(bytes_done),
+            Progress::Json(jp) => jp.tick(bytes_done),
+            Progress::Callback(cp) => {
+                cp.bytes_done = bytes_done;
+                cp.callback.call(bytes_done, cp.stream_size, false);
+            }
         }
     }
 
     /// Finish progress tracking
     pub fn finish(&mut self) {
-        if let Progress::Bar(pb) = self {
-            pb.finish_and_clear();
+        match self {
+            Progress::Bar(pb) => pb.finish_and_clear(),
+            Progress::Log(_) => {}
+            Progress::Json(jp) => jp.print("done", Instant::now()),
+            Progress::Callback(cp) => cp.callback.call(cp.bytes_done, cp.stream_size, true),
         }
     }
 }
@@ -132,7 +499,7 @@ impl Metrics {
     /// Create a new metrics tracker
     pub fn new(stream_size: Option<u64>, no_progress: bool) -> anyhow::Result<Self> {
         Ok(Metrics {
-            progress: Progress::new(stream_size, no_progress)?,
+            progress: Progress::new(stream_size, no_progress, ProgressFormat::Auto, None, None)?,
             start_time: Instant::now(),
             bytes_processed: 0,
         })
@@ -169,7 +536,12 @@ impl LogProgress {
             let elapsed = now.duration_since(self.interval_start).as_micros() as f32;
             let throughput = (self.interval_bytes as f32 / elapsed * 1_000_000.0) as usize;
 
-            eprintln!("progress: {pct}% - {}/s", throughput.format_size());
+            match &self.device {
+                Some(device) => {
+                    eprintln!("progress: {device}: {pct}% - {}/s", throughput.format_size())
+                }
+                None => eprintln!("progress: {pct}% - {}/s", throughput.format_size()),
+            }
 
             self.last_pct = pct;
             self.last_print = now;
@@ -184,32 +556,114 @@ impl LogProgress {
     }
 }
 
-fn set_up_progress_bar(stream_size: Option<u64>) -> anyhow::Result<ProgressBar> {
-    let pb = ProgressBar::with_draw_target(stream_size, ProgressDrawTarget::stderr_with_hz(10));
-    pb.set_style(
-        ProgressStyle::with_template(
-            "[{elapsed_precise}] [{wide_bar}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})",
-        )?
-        .progress_chars(if supports_unicode::on(supports_unicode::Stream::Stdout) {
+impl JsonProgress {
+    fn tick(&mut self, bytes_done: u64) {
+        self.bytes_done = bytes_done;
+        let now = Instant::now();
+        if now.duration_since(self.last_print) >= Duration::from_secs(1) {
+            self.print("progress", now);
+        }
+    }
+
+    /// Print an event, with the throughput since the previous one
+    fn print(&mut self, event: &str, now: Instant) {
+        let interval = now.duration_since(self.last_print).as_secs_f64();
+        let rate = if interval > 0.0 {
+            ((self.bytes_done - self.prev_bytes) as f64 / interval) as u64
+        } else {
+            0
+        };
+        let total = self.stream_size.map_or("null".to_string(), |s| s.to_string());
+        let eta = match self.stream_size {
+            Some(size) if rate > 0 => {
+                format!("{:.1}", size.saturating_sub(self.bytes_done) as f64 / rate as f64)
+            }
+            _ => "null".to_string(),
+        };
+        let device = match &self.device {
+            Some(device) => format!(",\"device\":{}", report::quote(device)),
+            None => String::new(),
+        };
+        eprintln!(
+            "{{\"event\":\"{event}\"{device},\"bytes_done\":{},\"total\":{total},\"rate\":{rate},\"eta\":{eta},\"elapsed\":{:.1}}}",
+            self.bytes_done,
+            now.duration_since(self.start).as_secs_f64()
+        );
+        self.last_print = now;
+        self.prev_bytes = self.bytes_done;
+    }
+}
+
+/// The progress bars of the devices processed at once, drawn together
+static DEVICE_BARS: LazyLock<MultiProgress> =
+    LazyLock::new(|| MultiProgress::with_draw_target(ProgressDrawTarget::stderr_with_hz(10)));
+
+fn set_up_progress_bar(
+    stream_size: Option<u64>,
+    device: Option<&str>,
+) -> anyhow::Result<ProgressBar> {
+    let mut pb = ProgressBar::with_draw_target(stream_size, ProgressDrawTarget::stderr_with_hz(10));
+    let mut template =
+        "[{elapsed_precise}] [{wide_bar}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})"
+            .to_string();
+    if let Some(device) = device {
+        template.insert_str(0, "{prefix} ");
+        pb.set_prefix(device.to_string());
+        pb = DEVICE_BARS.add(pb);
+    }
+    pb.set_style(ProgressStyle::with_template(&template)?.progress_chars(
+        if supports_unicode::on(supports_unicode::Stream::Stdout) {
             "█▉▊▋▌▍▎▏  "
         } else {
             "=> "
-        }),
-    );
+        },
+    ));
     Ok(pb)
 }
 
-pub fn receive_progress(pb: &mut Option<Progress>, rx: &Receiver<u64>, tx: Sender<u64>) {
+/// Report the progress sent by the threads, until they're all done
+///
+/// The progress of the `phase` of the stream of size `total` is also sent to systemd, and a
+/// snapshot is printed on SIGUSR1, with the `positions` of the threads if known.
+pub(crate) fn receive_progress(
+    pb: &mut Option<Progress>,
+    rx: &Receiver<u64>,
+    tx: Sender<u64>,
+    phase: ProgressPhase,
+    total: Option<u64>,
+    positions: Option<&ThreadPositions>,
+) {
     drop(tx);
+    let start = Instant::now();
+    let mut throughput = status::Throughput::new();
     let mut total_bytes = 0;
-    if let Some(p) = pb {
-        while let Ok(bytes) = rx.recv() {
-            total_bytes += bytes;
-            p.tick(total_bytes);
+    loop {
+        match rx.recv_timeout(Duration::from_millis(200)) {
+            Ok(bytes) => {
+                total_bytes += bytes;
+                throughput.add(total_bytes);
+                if let Some(p) = pb {
+                    p.tick(total_bytes);
+                }
+                systemd::progress(phase, total_bytes, total);
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
         }
+        if status::requested() {
+            let snapshot = status::Snapshot {
+                phase,
+                bytes_done: total_bytes,
+                total,
+                rate: throughput.rate(total_bytes),
+                elapsed: start.elapsed(),
+                positions,
+            };
+            snapshot.print(pb.as_ref());
+        }
+    }
+    if let Some(p) = pb {
         p.finish();
-    } else {
-        while rx.recv().is_ok() {}
     }
 }
 
@@ -226,3 +680,65 @@ pub fn log_metrics(start: Instant, bytes: u64, label: &str) {
     debug!("throughput: {}/s", throughput.format_size());
     debug!("run in {}", elapsed.format_duration());
 }
+
+#[test]
+fn custom_chunk_checksum_round_trip() {
+    /// A 16 bits sum of the payloads, which can be combined by addition
+    #[derive(Default)]
+    struct Sum16(u16);
+
+    impl ChunkChecksum for Sum16 {
+        fn width(&self) -> usize {
+            2
+        }
+        fn update(&mut self, payload: &[u8]) -> u64 {
+            let sum = payload.iter().fold(0u16, |sum, b| sum.wrapping_add(*b as u16));
+            self.0 = self.0.wrapping_add(sum);
+            sum as u64
+        }
+        fn combine(&mut self, other: &Self) {
+            self.0 = self.0.wrapping_add(other.0);
+        }
+        fn finalize(&self) -> u64 {
+            self.0 as u64
+        }
+    }
+
+    let mut rng = rng::RngAlgorithm::Pcg64.rng(rng::Seed::U64(0));
+    let mut buffer = vec![0u8; 1000];
+    let mut generated = Sum16::default();
+    let mut validated = Sum16::default();
+    for (chunk, size) in [1000, 1000, 1].into_iter().enumerate() {
+        generate::generate_chunk(&mut rng, &mut buffer, size, &mut generated);
+        validate::validate_chunk(chunk as u64, &buffer[..size], &mut validated).unwrap();
+    }
+    assert_eq!(generated.finalize(), validated.finalize());
+    buffer[10] ^= 1;
+    assert!(validate::validate_chunk(0, &buffer, &mut validated).is_err());
+}
+
+#[test]
+fn custom_rng_fills_the_chunks() {
+    /// The output is the sequence of the 64 bits integers
+    struct Counter(u64);
+
+    impl SeekableRng for Counter {
+        fn fill_bytes(&mut self, buffer: &mut [u8]) {
+            for word in buffer.chunks_mut(8) {
+                word.copy_from_slice(&self.0.to_le_bytes()[..word.len()]);
+                self.0 += 1;
+            }
+        }
+        fn advance(&mut self, bytes: u64) {
+            self.0 += bytes / 8;
+        }
+    }
+
+    let mut rng = Counter(0);
+    rng.advance(16);
+    let mut buffer = vec![0u8; 32];
+    let mut checksum = checksum::ChecksumAlgorithm::Crc32.stream_checksum();
+    generate::generate_chunk(&mut rng, &mut buffer, 32, &mut checksum);
+    assert_eq!(buffer[..8], 2u64.to_le_bytes());
+    validate::validate_chunk(0, &buffer, &mut checksum).unwrap();
+}
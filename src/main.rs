@@ -1,20 +1,54 @@
 #[macro_use]
 extern crate log;
 
-use clap::Parser;
+use clap::{CommandFactory as _, FromArgMatches as _, Parser};
 use std::sync::Arc;
 use std::sync::atomic::Ordering;
 
+use randstream::Error;
 use randstream::cli;
+use randstream::config;
+use randstream::error::exit_code;
 
+use randstream::bench::bench;
+use randstream::completions::completions;
+use randstream::corrupt::corrupt;
+use randstream::discard::discard_test;
 use randstream::generate::generate;
+use randstream::perf::perf;
+use randstream::repair::repair;
+use randstream::schedule::scrub;
+use randstream::serve::serve;
+use randstream::status;
+use randstream::systemd;
 use randstream::validate::validate;
+use randstream::verify::verify;
 
-fn run() -> anyhow::Result<i32> {
-    let cli = cli::Cli::parse();
+fn exit_on_parse_error(e: clap::Error) -> ! {
+    if e.use_stderr() {
+        let _ = e.print();
+        std::process::exit(exit_code::USAGE);
+    }
+    // the help or the version
+    e.exit()
+}
+
+fn run() -> Result<i32, Error> {
+    let mut cli = cli::Cli::try_parse().unwrap_or_else(|e| exit_on_parse_error(e));
     if let Some(level) = cli.verbose.log_level() {
         ocli::init(level).unwrap();
     }
+    if let Some(path) = config::path(&cli) {
+        let args: Vec<_> = std::env::args_os().collect();
+        if let Some(args) = config::apply(&cli, &path, &args)? {
+            // the options given on the command line override the ones of the configuration
+            let matches = cli::Cli::command()
+                .args_override_self(true)
+                .try_get_matches_from(args)
+                .unwrap_or_else(|e| exit_on_parse_error(e));
+            cli = cli::Cli::from_arg_matches(&matches).unwrap_or_else(|e| exit_on_parse_error(e));
+        }
+    }
 
     // Initialize the cancel flag
     let cancel = Arc::new(std::sync::atomic::AtomicBool::new(false));
@@ -23,11 +57,27 @@ fn run() -> anyhow::Result<i32> {
     let cancel_clone = cancel.clone();
     ctrlc::set_handler(move || {
         cancel_clone.store(true, Ordering::Relaxed);
-    })?;
+    })
+    .map_err(|e| Error::Other(e.to_string()))?;
+    status::install()?;
 
-    match &cli.command.unwrap() {
+    let command = cli.command.unwrap();
+    // the server is ready once listening
+    if !matches!(command, cli::Commands::Serve(_) | cli::Commands::Completions(_)) {
+        systemd::ready();
+    }
+    match &command {
         cli::Commands::Generate(args) => generate(args, cancel),
         cli::Commands::Validate(args) => validate(args, cancel),
+        cli::Commands::Verify(args) => verify(args, cancel),
+        cli::Commands::Serve(args) => serve(args, cancel),
+        cli::Commands::Perf(args) => perf(args, cancel),
+        cli::Commands::Bench(args) => bench(args, cancel),
+        cli::Commands::DiscardTest(args) => discard_test(args, cancel),
+        cli::Commands::Corrupt(args) => corrupt(args),
+        cli::Commands::Repair(args) => repair(args, cancel),
+        cli::Commands::Scrub(args) => scrub(args, cancel),
+        cli::Commands::Completions(args) => completions(args),
     }
 }
 
@@ -36,7 +86,7 @@ fn main() {
         Ok(exit_code) => std::process::exit(exit_code),
         Err(err) => {
             error!("{err}");
-            std::process::exit(1);
+            std::process::exit(err.exit_code());
         }
     }
 }
@@ -0,0 +1,79 @@
+//! The memory mappings used by the mmap engine
+
+use anyhow::anyhow;
+use nix::sys::mman::{MapFlags, MmapAdvise, ProtFlags, madvise, mmap, munmap};
+use std::ffi::c_void;
+use std::fs::File;
+use std::io::{self, Seek as _};
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use std::ptr::NonNull;
+
+use crate::cache::Advice;
+
+/// A non-empty range of a file, mapped in memory
+pub struct FileMapping {
+    ptr: NonNull<c_void>,
+    len: usize,
+    /// The offset of the range in the mapping, which must start on a page boundary
+    skip: usize,
+}
+
+impl FileMapping {
+    /// Map the range of the file, for writing if `write` is true
+    ///
+    /// The range must be within the file, see `file_size()`: an access beyond its end is fatal.
+    pub fn new(
+        file: &File,
+        range: Range<u64>,
+        write: bool,
+        advice: Advice,
+    ) -> anyhow::Result<Self> {
+        // SAFETY: sysconf has no precondition
+        let page_size = unsafe { nix::libc::sysconf(nix::libc::_SC_PAGESIZE) } as u64;
+        let start = range.start / page_size * page_size;
+        let skip = (range.start - start) as usize;
+        let len = usize::try_from(range.end - start)?;
+        let length = NonZeroUsize::new(len).ok_or_else(|| anyhow!("Can't map an empty range"))?;
+        let prot =
+            if write { ProtFlags::PROT_READ | ProtFlags::PROT_WRITE } else { ProtFlags::PROT_READ };
+        // SAFETY: a new mapping, not aliasing any existing memory
+        let ptr = unsafe { mmap(None, length, prot, MapFlags::MAP_SHARED, file, start as i64) }?;
+        let advice = match advice {
+            Advice::Normal => MmapAdvise::MADV_NORMAL,
+            Advice::Sequential => MmapAdvise::MADV_SEQUENTIAL,
+            Advice::Random => MmapAdvise::MADV_RANDOM,
+        };
+        let mapping = FileMapping { ptr, len, skip };
+        // SAFETY: the whole mapping
+        unsafe { madvise(ptr, len, advice) }?;
+        Ok(mapping)
+    }
+
+    /// The bytes at `range`, relative to the start of the mapped range
+    pub fn slice(&self, range: Range<usize>) -> &[u8] {
+        // SAFETY: the memory is mapped
+        let data = unsafe { std::slice::from_raw_parts(self.ptr.as_ptr().cast::<u8>(), self.len) };
+        &data[self.skip..][range]
+    }
+
+    /// Same as slice, for a writable mapping
+    pub fn slice_mut(&mut self, range: Range<usize>) -> &mut [u8] {
+        // SAFETY: the memory is mapped, and only accessed through self
+        let data =
+            unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr().cast::<u8>(), self.len) };
+        &mut data[self.skip..][range]
+    }
+}
+
+impl Drop for FileMapping {
+    fn drop(&mut self) {
+        // SAFETY: the mapping isn't used anymore
+        let _ = unsafe { munmap(self.ptr, self.len) };
+    }
+}
+
+/// The size of the file, which also works with the block devices
+pub fn file_size(mut file: &File) -> io::Result<u64> {
+    file.seek(io::SeekFrom::End(0))
+}
@@ -0,0 +1,463 @@
+//! The streams written to or read from an NBD export, given as `nbd://host:port/export` or
+//! `nbd+unix:///export?socket=path` instead of a file
+//!
+//! The NBD protocol is spoken directly, without attaching the export to a kernel block device:
+//! the fixed newstyle handshake, then simple replies to the read, write and flush requests.
+
+use anyhow::anyhow;
+use log::info;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+use crate::error::usage;
+
+/// The port of the NBD servers
+const DEFAULT_PORT: u16 = 10809;
+
+/// The largest read or write request, most servers refusing larger ones
+const MAX_REQUEST_SIZE: usize = 32 << 20;
+
+const NBDMAGIC: u64 = 0x4e42_444d_4147_4943;
+const IHAVEOPT: u64 = 0x4948_4156_454f_5054;
+const OPTION_REPLY_MAGIC: u64 = 0x0003_e889_0455_65a9;
+const REQUEST_MAGIC: u32 = 0x2560_9513;
+const SIMPLE_REPLY_MAGIC: u32 = 0x6744_6698;
+
+const FLAG_FIXED_NEWSTYLE: u16 = 1 << 0;
+const FLAG_NO_ZEROES: u16 = 1 << 1;
+
+const OPT_EXPORT_NAME: u32 = 1;
+const OPT_GO: u32 = 7;
+
+const REP_ACK: u32 = 1;
+const REP_INFO: u32 = 3;
+const REP_ERR_UNSUP: u32 = (1 << 31) | 1;
+const INFO_EXPORT: u16 = 0;
+
+const TRANSMISSION_READ_ONLY: u16 = 1 << 1;
+const TRANSMISSION_SEND_FLUSH: u16 = 1 << 2;
+
+const CMD_READ: u16 = 0;
+const CMD_WRITE: u16 = 1;
+const CMD_DISC: u16 = 2;
+const CMD_FLUSH: u16 = 3;
+
+/// How the server is reached
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Address {
+    Tcp { host: String, port: u16 },
+    Unix(PathBuf),
+}
+
+/// The export given as file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NbdUri {
+    address: Address,
+    /// The name of the export, empty for the default one
+    export: String,
+}
+
+impl NbdUri {
+    /// The export given as file, if it's an `nbd://` or `nbd+unix://` URI
+    pub fn parse(file: &Path) -> anyhow::Result<Option<Self>> {
+        let Some(file) = file.to_str() else {
+            return Ok(None);
+        };
+        if let Some(rest) = file.strip_prefix("nbd+unix://") {
+            let (export, query) = rest.split_once('?').unwrap_or((rest, ""));
+            let socket = query.split('&').find_map(|param| param.strip_prefix("socket="));
+            let (Some(export), Some(socket)) = (export.strip_prefix('/'), socket) else {
+                return Err(usage(format!(
+                    "The URI {file} must be like nbd+unix:///export?socket=path"
+                )));
+            };
+            let address = Address::Unix(PathBuf::from(socket));
+            return Ok(Some(NbdUri { address, export: export.to_string() }));
+        }
+        let Some(rest) = file.strip_prefix("nbd://") else {
+            return Ok(None);
+        };
+        let (authority, export) = rest.split_once('/').unwrap_or((rest, ""));
+        // an IPv6 address is written between brackets
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(bracketed) => {
+                let (host, port) = bracketed.split_once(']').unwrap_or((bracketed, ""));
+                (host, port.strip_prefix(':'))
+            }
+            None => match authority.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        if host.is_empty() {
+            return Err(usage(format!("The URI {file} must hold a host, like nbd://host/export")));
+        }
+        let port = match port {
+            Some(port) => port.parse().map_err(|_| usage(format!("Invalid port {port}")))?,
+            None => DEFAULT_PORT,
+        };
+        let address = Address::Tcp { host: host.to_string(), port };
+        Ok(Some(NbdUri { address, export: export.to_string() }))
+    }
+
+    /// Connect to the server, and open the export
+    pub fn connect(&self) -> anyhow::Result<Export> {
+        let connection: Box<dyn Connection> =
+            match &self.address {
+                Address::Tcp { host, port } => {
+                    let socket = TcpStream::connect((host.as_str(), *port)).map_err(|e| {
+                        io::Error::new(e.kind(), format!("Can't connect to {self}: {e}"))
+                    })?;
+                    socket.set_nodelay(true)?;
+                    Box::new(socket)
+                }
+                #[cfg(unix)]
+                Address::Unix(path) => Box::new(UnixStream::connect(path).map_err(|e| {
+                    io::Error::new(e.kind(), format!("Can't connect to {self}: {e}"))
+                })?),
+                #[cfg(not(unix))]
+                Address::Unix(_) => return Err(usage("nbd+unix:// is only supported on Unix")),
+            };
+        let export = Export::open(connection, &self.export)
+            .map_err(|e| anyhow!("Can't open the export {self}: {e}"))?;
+        info!("connected to {self}, export size: {}", export.size);
+        Ok(export)
+    }
+}
+
+impl fmt::Display for NbdUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.address {
+            Address::Tcp { host, port } if host.contains(':') => {
+                write!(f, "nbd://[{host}]:{port}/{}", self.export)
+            }
+            Address::Tcp { host, port } => write!(f, "nbd://{host}:{port}/{}", self.export),
+            Address::Unix(path) => {
+                write!(f, "nbd+unix:///{}?socket={}", self.export, path.display())
+            }
+        }
+    }
+}
+
+/// A TCP or Unix socket
+trait Connection: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Connection for T {}
+
+/// An export opened on the server, ready for the read and write requests
+pub struct Export {
+    connection: Box<dyn Connection>,
+    size: u64,
+    flags: u16,
+    cookie: u64,
+}
+
+impl Export {
+    /// Negotiate the export with the server
+    fn open(mut connection: Box<dyn Connection>, name: &str) -> io::Result<Self> {
+        if read_u64(&mut connection)? != NBDMAGIC || read_u64(&mut connection)? != IHAVEOPT {
+            return Err(protocol_error("not a newstyle NBD server"));
+        }
+        let server_flags = read_u16(&mut connection)?;
+        let client_flags = server_flags & (FLAG_FIXED_NEWSTYLE | FLAG_NO_ZEROES);
+        connection.write_all(&u32::from(client_flags).to_be_bytes())?;
+
+        if server_flags & FLAG_FIXED_NEWSTYLE != 0 {
+            let mut data = Vec::with_capacity(name.len() + 6);
+            data.extend((name.len() as u32).to_be_bytes());
+            data.extend(name.as_bytes());
+            // no information requested, beyond the size and flags of the export
+            data.extend(0u16.to_be_bytes());
+            send_option(&mut connection, OPT_GO, &data)?;
+            if let Some((size, flags)) = receive_go_replies(&mut connection)? {
+                return Ok(Export { connection, size, flags, cookie: 0 });
+            }
+        }
+        // an older server, which closes the connection if the export doesn't exist
+        send_option(&mut connection, OPT_EXPORT_NAME, name.as_bytes())?;
+        let size = read_u64(&mut connection)?;
+        let flags = read_u16(&mut connection)?;
+        if client_flags & FLAG_NO_ZEROES == 0 {
+            io::copy(&mut (&mut connection).take(124), &mut io::sink())?;
+        }
+        Ok(Export { connection, size, flags, cookie: 0 })
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.flags & TRANSMISSION_READ_ONLY != 0
+    }
+
+    /// Send a request, and check its reply
+    fn request(&mut self, command: u16, offset: u64, length: u32, data: &[u8]) -> io::Result<()> {
+        self.cookie += 1;
+        let mut request = Vec::with_capacity(28);
+        request.extend(REQUEST_MAGIC.to_be_bytes());
+        request.extend(0u16.to_be_bytes());
+        request.extend(command.to_be_bytes());
+        request.extend(self.cookie.to_be_bytes());
+        request.extend(offset.to_be_bytes());
+        request.extend(length.to_be_bytes());
+        self.connection.write_all(&request)?;
+        self.connection.write_all(data)?;
+        if command == CMD_DISC {
+            return Ok(());
+        }
+        if read_u32(&mut self.connection)? != SIMPLE_REPLY_MAGIC {
+            return Err(protocol_error("invalid reply magic"));
+        }
+        let error = read_u32(&mut self.connection)?;
+        if read_u64(&mut self.connection)? != self.cookie {
+            return Err(protocol_error("reply to another request"));
+        }
+        match error {
+            0 => Ok(()),
+            error => Err(request_error(error)),
+        }
+    }
+
+    /// Read the data at that offset of the export
+    pub fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        for (i, part) in buf.chunks_mut(MAX_REQUEST_SIZE).enumerate() {
+            let offset = offset + (i * MAX_REQUEST_SIZE) as u64;
+            self.request(CMD_READ, offset, part.len() as u32, &[])?;
+            self.connection.read_exact(part)?;
+        }
+        Ok(())
+    }
+
+    /// Write the data at that offset of the export
+    pub fn write_at(&mut self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        for (i, part) in buf.chunks(MAX_REQUEST_SIZE).enumerate() {
+            let offset = offset + (i * MAX_REQUEST_SIZE) as u64;
+            self.request(CMD_WRITE, offset, part.len() as u32, part)?;
+        }
+        Ok(())
+    }
+
+    /// Make the data written durable, if the server needs it
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.flags & TRANSMISSION_SEND_FLUSH == 0 {
+            return Ok(());
+        }
+        self.request(CMD_FLUSH, 0, 0, &[])
+    }
+}
+
+impl Drop for Export {
+    fn drop(&mut self) {
+        // the server may already be gone
+        let _ = self.request(CMD_DISC, 0, 0, &[]);
+    }
+}
+
+/// The export read or written sequentially, from an offset
+pub struct ExportStream {
+    export: Export,
+    offset: u64,
+}
+
+impl ExportStream {
+    pub fn new(export: Export, offset: u64) -> Self {
+        ExportStream { export, offset }
+    }
+}
+
+impl Read for ExportStream {
+    /// Read up to the end of the export
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.export.size.saturating_sub(self.offset);
+        let size = buf.len().min(MAX_REQUEST_SIZE).min(remaining as usize);
+        self.export.read_at(self.offset, &mut buf[..size])?;
+        self.offset += size as u64;
+        Ok(size)
+    }
+}
+
+impl Write for ExportStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let size = buf.len().min(MAX_REQUEST_SIZE);
+        self.export.write_at(self.offset, &buf[..size])?;
+        self.offset += size as u64;
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.export.flush()
+    }
+}
+
+fn send_option(connection: &mut dyn Connection, option: u32, data: &[u8]) -> io::Result<()> {
+    let mut request = Vec::with_capacity(16 + data.len());
+    request.extend(IHAVEOPT.to_be_bytes());
+    request.extend(option.to_be_bytes());
+    request.extend((data.len() as u32).to_be_bytes());
+    request.extend(data);
+    connection.write_all(&request)
+}
+
+/// The size and flags of the export, or `None` if the server doesn't support `NBD_OPT_GO`
+fn receive_go_replies(connection: &mut dyn Connection) -> io::Result<Option<(u64, u16)>> {
+    let mut export = None;
+    loop {
+        if read_u64(connection)? != OPTION_REPLY_MAGIC || read_u32(connection)? != OPT_GO {
+            return Err(protocol_error("invalid option reply"));
+        }
+        let reply = read_u32(connection)?;
+        let mut data = vec![0; read_u32(connection)? as usize];
+        connection.read_exact(&mut data)?;
+        match reply {
+            REP_ACK => {
+                return export
+                    .map(Some)
+                    .ok_or_else(|| protocol_error("the size of the export is missing"));
+            }
+            REP_INFO if data.len() >= 12 && data[..2] == INFO_EXPORT.to_be_bytes() => {
+                let size = u64::from_be_bytes(data[2..10].try_into().unwrap());
+                let flags = u16::from_be_bytes(data[10..12].try_into().unwrap());
+                export = Some((size, flags));
+            }
+            REP_ERR_UNSUP => return Ok(None),
+            reply if reply & (1 << 31) != 0 => {
+                let message = String::from_utf8_lossy(&data);
+                return Err(protocol_error(&format!("error {reply:#x} {message}")));
+            }
+            // other information
+            _ => {}
+        }
+    }
+}
+
+fn read_u16(connection: &mut dyn Connection) -> io::Result<u16> {
+    let mut bytes = [0; 2];
+    connection.read_exact(&mut bytes)?;
+    Ok(u16::from_be_bytes(bytes))
+}
+
+fn read_u32(connection: &mut dyn Connection) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    connection.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn read_u64(connection: &mut dyn Connection) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    connection.read_exact(&mut bytes)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+fn protocol_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("NBD protocol error: {message}"))
+}
+
+/// The error of a request, an errno value
+fn request_error(error: u32) -> io::Error {
+    let (kind, message) = match error {
+        1 => (io::ErrorKind::PermissionDenied, "operation not permitted"),
+        5 => (io::ErrorKind::Other, "I/O error"),
+        12 => (io::ErrorKind::OutOfMemory, "out of memory"),
+        22 => (io::ErrorKind::InvalidInput, "invalid argument"),
+        28 => (io::ErrorKind::StorageFull, "no space left on the export"),
+        75 => (io::ErrorKind::InvalidInput, "beyond the end of the export"),
+        95 => (io::ErrorKind::Unsupported, "not supported"),
+        108 => (io::ErrorKind::ConnectionAborted, "the server is shutting down"),
+        _ => (io::ErrorKind::Other, "unknown error"),
+    };
+    io::Error::new(kind, format!("NBD request failed: {message} ({error})"))
+}
+
+#[test]
+fn parse_nbd_uris() {
+    let parse = |s: &str| NbdUri::parse(Path::new(s));
+    assert_eq!(parse("out.bin").unwrap(), None);
+    assert_eq!(parse("tcp://host:9000").unwrap(), None);
+    let uri = parse("nbd://host/vdi").unwrap().unwrap();
+    let tcp = |host: &str, port| Address::Tcp { host: host.to_string(), port };
+    assert_eq!(uri, NbdUri { address: tcp("host", 10809), export: "vdi".to_string() });
+    assert_eq!(uri.to_string(), "nbd://host:10809/vdi");
+    let uri = parse("nbd://[::1]:9000").unwrap().unwrap();
+    assert_eq!(uri, NbdUri { address: tcp("::1", 9000), export: String::new() });
+    assert_eq!(uri.to_string(), "nbd://[::1]:9000/");
+    let uri = parse("nbd+unix:///vdi?socket=/run/nbd.sock").unwrap().unwrap();
+    assert_eq!(uri.address, Address::Unix(PathBuf::from("/run/nbd.sock")));
+    assert_eq!(uri.export, "vdi");
+    assert!(parse("nbd+unix:///vdi").is_err());
+    assert!(parse("nbd:///vdi").is_err());
+    assert!(parse("nbd://host:http/vdi").is_err());
+}
+
+#[cfg(test)]
+fn serve_export(listener: std::net::TcpListener, export: &mut [u8]) -> io::Result<()> {
+    let mut socket = listener.accept()?.0;
+    let mut handshake = NBDMAGIC.to_be_bytes().to_vec();
+    handshake.extend(IHAVEOPT.to_be_bytes());
+    handshake.extend((FLAG_FIXED_NEWSTYLE | FLAG_NO_ZEROES).to_be_bytes());
+    socket.write_all(&handshake)?;
+    let mut negotiation = [0; 20];
+    socket.read_exact(&mut negotiation)?;
+    let mut name = vec![0; u32::from_be_bytes(negotiation[16..].try_into().unwrap()) as usize];
+    socket.read_exact(&mut name)?;
+    let mut info = INFO_EXPORT.to_be_bytes().to_vec();
+    info.extend((export.len() as u64).to_be_bytes());
+    info.extend(TRANSMISSION_SEND_FLUSH.to_be_bytes());
+    for (reply, data) in [(REP_INFO, info), (REP_ACK, Vec::new())] {
+        let mut message = OPTION_REPLY_MAGIC.to_be_bytes().to_vec();
+        message.extend(OPT_GO.to_be_bytes());
+        message.extend(reply.to_be_bytes());
+        message.extend((data.len() as u32).to_be_bytes());
+        message.extend(data);
+        socket.write_all(&message)?;
+    }
+    loop {
+        let mut request = [0; 28];
+        socket.read_exact(&mut request)?;
+        let command = u16::from_be_bytes(request[6..8].try_into().unwrap());
+        let offset = u64::from_be_bytes(request[16..24].try_into().unwrap()) as usize;
+        let length = u32::from_be_bytes(request[24..].try_into().unwrap()) as usize;
+        let mut reply = SIMPLE_REPLY_MAGIC.to_be_bytes().to_vec();
+        reply.extend(0u32.to_be_bytes());
+        reply.extend(&request[8..16]);
+        match command {
+            CMD_READ => reply.extend(&export[offset..offset + length]),
+            CMD_WRITE => socket.read_exact(&mut export[offset..offset + length])?,
+            CMD_DISC => return Ok(()),
+            _ => {}
+        }
+        socket.write_all(&reply)?;
+    }
+}
+
+#[test]
+fn read_and_write_an_export() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let uri = format!("nbd://127.0.0.1:{}/vdi", listener.local_addr().unwrap().port());
+    let server = std::thread::spawn(move || {
+        let mut export = vec![0u8; 1000];
+        serve_export(listener, &mut export).map(|_| export)
+    });
+    let export = NbdUri::parse(Path::new(&uri)).unwrap().unwrap().connect().unwrap();
+    assert_eq!(export.size(), 1000);
+    assert!(!export.is_read_only());
+    let mut stream = ExportStream::new(export, 100);
+    stream.write_all(&[7; 50]).unwrap();
+    stream.flush().unwrap();
+    let mut export = stream.export;
+    let mut data = [0; 60];
+    export.read_at(95, &mut data).unwrap();
+    assert_eq!(data[..5], [0; 5]);
+    assert_eq!(data[5..55], [7; 50]);
+    let mut stream = ExportStream::new(export, 990);
+    let mut data = Vec::new();
+    stream.read_to_end(&mut data).unwrap();
+    assert_eq!(data.len(), 10);
+    drop(stream);
+    let export = server.join().unwrap().unwrap();
+    assert_eq!(export[100..150], [7; 50]);
+}
@@ -0,0 +1,181 @@
+//! The streams sent over the network, to a `tcp://host:port` or `udp://host:port` address given
+//! instead of a file
+
+use log::{info, warn};
+use std::fmt;
+use std::io;
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crate::error::usage;
+
+/// The delay between the connection attempts
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// How often the cancellation is checked, while waiting for a connection
+pub(crate) const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The transport of the stream
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scheme {
+    /// A TCP connection, carrying the stream as is
+    Tcp,
+    /// UDP datagrams, each one carrying a chunk
+    Udp,
+}
+
+impl Scheme {
+    fn name(self) -> &'static str {
+        match self {
+            Scheme::Tcp => "tcp",
+            Scheme::Udp => "udp",
+        }
+    }
+}
+
+/// The address a stream is sent to or received from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Endpoint {
+    scheme: Scheme,
+    /// The host name or IP address, empty to listen on all the interfaces
+    host: String,
+    port: u16,
+}
+
+impl Endpoint {
+    /// The address given as file, if it's a `tcp://` or `udp://` URL
+    pub fn parse(file: &Path) -> anyhow::Result<Option<Self>> {
+        let Some((scheme, address)) = file.to_str().and_then(|f| {
+            [Scheme::Tcp, Scheme::Udp].into_iter().find_map(|scheme| {
+                Some((scheme, f.strip_prefix(scheme.name())?.strip_prefix("://")?))
+            })
+        }) else {
+            return Ok(None);
+        };
+        let (host, port) = address.rsplit_once(':').ok_or_else(|| {
+            usage(format!(
+                "The address {} must hold a port, like {}://host:port",
+                file.display(),
+                scheme.name()
+            ))
+        })?;
+        let port = port.parse().map_err(|_| usage(format!("Invalid port {port}")))?;
+        // an IPv6 address is written between brackets
+        let host = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')).unwrap_or(host);
+        Ok(Some(Endpoint { scheme, host: host.to_string(), port }))
+    }
+
+    pub fn scheme(&self) -> Scheme {
+        self.scheme
+    }
+
+    /// The host to listen on
+    fn listening_host(&self) -> &str {
+        if self.host.is_empty() { "0.0.0.0" } else { self.host.as_str() }
+    }
+
+    /// Connect to the receiver, retrying while it's not reachable
+    pub fn connect(&self, retries: u32) -> io::Result<TcpStream> {
+        let mut attempt = 0;
+        loop {
+            match TcpStream::connect((self.host.as_str(), self.port)) {
+                Ok(socket) => {
+                    info!("connected to {}", socket.peer_addr()?);
+                    return Ok(socket);
+                }
+                Err(e) if attempt < retries => {
+                    warn!("can't connect to {self}: {e}, retrying");
+                    attempt += 1;
+                    thread::sleep(RETRY_DELAY);
+                }
+                Err(e) => {
+                    return Err(io::Error::new(e.kind(), format!("Can't connect to {self}: {e}")));
+                }
+            }
+        }
+    }
+
+    /// Listen for the connections of the senders, polled without blocking
+    pub fn listen(&self) -> io::Result<TcpListener> {
+        let listener = TcpListener::bind((self.listening_host(), self.port))?;
+        info!("listening on {}", listener.local_addr()?);
+        listener.set_nonblocking(true)?;
+        Ok(listener)
+    }
+
+    /// Wait for a sender to connect, unless cancelled
+    pub fn accept(&self, cancel: &AtomicBool) -> io::Result<Option<TcpStream>> {
+        let listener = self.listen()?;
+        while !cancel.load(Ordering::Relaxed) {
+            match listener.accept() {
+                Ok((socket, peer)) => {
+                    info!("connected from {peer}");
+                    socket.set_nonblocking(false)?;
+                    return Ok(Some(socket));
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(ACCEPT_POLL_INTERVAL)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(None)
+    }
+
+    /// A socket sending datagrams to the receiver
+    pub fn datagram_sender(&self) -> io::Result<UdpSocket> {
+        let local = if self.host.contains(':') { "[::]:0" } else { "0.0.0.0:0" };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect((self.host.as_str(), self.port))?;
+        info!("sending datagrams to {}", socket.peer_addr()?);
+        Ok(socket)
+    }
+
+    /// A socket receiving the datagrams sent to the address
+    pub fn datagram_receiver(&self) -> io::Result<UdpSocket> {
+        let socket = UdpSocket::bind((self.listening_host(), self.port))?;
+        info!("listening on {}", socket.local_addr()?);
+        Ok(socket)
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = self.scheme.name();
+        if self.host.contains(':') {
+            write!(f, "{scheme}://[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{scheme}://{}:{}", self.host, self.port)
+        }
+    }
+}
+
+/// Reject the options given, which don't apply to the target, like `a network address`
+///
+/// Each option is given with whether it's used.
+pub fn reject_options(target: &str, options: &[(&str, bool)]) -> anyhow::Result<()> {
+    match options.iter().find(|(_, used)| *used) {
+        Some((name, _)) => Err(usage(format!("{name} can't be used with {target}"))),
+        None => Ok(()),
+    }
+}
+
+#[test]
+fn parse_endpoints() {
+    let parse = |s: &str| Endpoint::parse(Path::new(s));
+    assert_eq!(parse("out.bin").unwrap(), None);
+    let endpoint = parse("tcp://host:9000").unwrap().unwrap();
+    assert_eq!(endpoint, Endpoint { scheme: Scheme::Tcp, host: "host".to_string(), port: 9000 });
+    assert_eq!(endpoint.to_string(), "tcp://host:9000");
+    assert_eq!(parse("tcp://:9000").unwrap().unwrap().host, "");
+    let endpoint = parse("tcp://[::1]:9000").unwrap().unwrap();
+    assert_eq!(endpoint.host, "::1");
+    assert_eq!(endpoint.to_string(), "tcp://[::1]:9000");
+    assert_eq!(parse("udp://host:53").unwrap().unwrap().scheme(), Scheme::Udp);
+    assert_eq!(parse("udp:/host:53").unwrap(), None);
+    assert!(parse("tcp://host").is_err());
+    assert!(parse("tcp://host:http").is_err());
+}
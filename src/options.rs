@@ -0,0 +1,357 @@
+//! The commands run by the applications using randstream as a library, without a command line
+//!
+//! The options start from the defaults of the command line, and the clap arguments convert into
+//! them, so a run gets the same result either way.
+
+use clap::{Args, Command, FromArgMatches};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::checksum::ChecksumAlgorithm;
+use crate::error::{CorruptedChunk, Error, exit_code};
+use crate::generate::{GenerateArgs, generate_stream};
+use crate::report::{OutputFormat, Report};
+use crate::rng::{RngAlgorithm, Seed};
+use crate::validate::{ValidateArgs, validate_stream};
+use crate::{ProgressCallback, ProgressEvent, ProgressPhase};
+
+/// Cancels a run from another thread, like Ctrl-C does for the command
+///
+/// The threads stop within a chunk, and the run returns the statistics of the data processed so
+/// far, with the exit code of an interrupted command.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The outcome of a run, as reported by `--output json`
+#[derive(Debug)]
+pub struct RunResult {
+    /// The exit code of the command
+    pub exit_code: i32,
+    /// The bytes written or read
+    pub bytes: u64,
+    pub checksum: Option<String>,
+    pub digest: Option<String>,
+    pub duration: Duration,
+    /// The error which failed the run, if any
+    pub error: Option<Error>,
+    /// The chunks found corrupted, with `keep_going()`
+    pub corrupted: Vec<CorruptedChunk>,
+    /// The messages of all the errors, as logged by the command
+    pub errors: Vec<String>,
+}
+
+impl RunResult {
+    fn new(mut report: Report, result: anyhow::Result<i32>) -> Self {
+        let exit_code = report.record(&result);
+        RunResult {
+            exit_code,
+            error: result.err().map(Error::from),
+            corrupted: std::mem::take(&mut report.corrupted),
+            bytes: report.bytes,
+            duration: report.stats().elapsed,
+            checksum: report.checksum,
+            digest: report.digest,
+            errors: report.errors,
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.exit_code == 0
+    }
+
+    /// The run was cancelled, its statistics are the ones of the data processed so far
+    pub fn is_cancelled(&self) -> bool {
+        self.exit_code == exit_code::INTERRUPTED
+    }
+}
+
+/// The options of `randstream generate`
+#[derive(Clone, Debug)]
+pub struct GenerateOptions {
+    args: GenerateArgs,
+    cancel: CancellationToken,
+}
+
+impl GenerateOptions {
+    /// Write the stream to the target, a file or any address taken by the command
+    pub fn new(target: impl Into<PathBuf>) -> Self {
+        let mut args: GenerateArgs = defaults("generate");
+        args.file = Some(target.into());
+        args.common.no_progress = true;
+        GenerateOptions { args, cancel: CancellationToken::new() }
+    }
+
+    pub fn seed(mut self, seed: Seed) -> Self {
+        self.args.seed = seed;
+        self
+    }
+
+    /// The stream size, the size of the target by default
+    pub fn size(mut self, size: u64) -> Self {
+        self.args.common.size = Some(size);
+        self
+    }
+
+    pub fn chunk_size(mut self, chunk_size: u64) -> Self {
+        self.args.common.chunk_size = chunk_size;
+        self
+    }
+
+    /// The number of parallel jobs, the number of physical cores by default
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.args.common.jobs = Some(jobs.max(1));
+        self
+    }
+
+    pub fn rng(mut self, rng: RngAlgorithm) -> Self {
+        self.args.common.rng = rng;
+        self
+    }
+
+    pub fn checksum(mut self, checksum: ChecksumAlgorithm) -> Self {
+        self.args.common.checksum = checksum;
+        self
+    }
+
+    /// Stop cleanly after this wall time, like with `--duration`, the stream written being the
+    /// bytes of the result
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.args.common.duration = Some(duration);
+        self
+    }
+
+    /// Write to the block device even if it's in use, like with `--force`
+    pub fn force(mut self) -> Self {
+        self.args.force = true;
+        self
+    }
+
+    /// Stop the run once the token is cancelled
+    pub fn cancellation(mut self, token: &CancellationToken) -> Self {
+        self.cancel = token.clone();
+        self
+    }
+
+    /// Report the progress to the callback, instead of the progress bar
+    pub fn progress(mut self, callback: impl FnMut(ProgressEvent) + Send + 'static) -> Self {
+        let callback = ProgressCallback::new(ProgressPhase::Generate, callback);
+        self.args.common.progress_callback = Some(callback);
+        self
+    }
+
+    /// Write the stream
+    pub fn run(&self) -> RunResult {
+        let mut report = Report::new("generate", OutputFormat::Text);
+        let result = generate_stream(&self.args, self.cancel.0.clone(), &mut report);
+        RunResult::new(report, result)
+    }
+}
+
+impl From<GenerateArgs> for GenerateOptions {
+    fn from(args: GenerateArgs) -> Self {
+        GenerateOptions { args, cancel: CancellationToken::new() }
+    }
+}
+
+/// The options of `randstream validate`
+#[derive(Clone, Debug)]
+pub struct ValidateOptions {
+    args: ValidateArgs,
+    cancel: CancellationToken,
+}
+
+impl ValidateOptions {
+    /// Validate the stream of the target, a file or any address taken by the command
+    pub fn new(target: impl Into<PathBuf>) -> Self {
+        let mut args: ValidateArgs = defaults("validate");
+        args.file = Some(target.into());
+        args.common.no_progress = true;
+        ValidateOptions { args, cancel: CancellationToken::new() }
+    }
+
+    /// Also compare the data with the one regenerated from the seed, like with `--regenerate`
+    pub fn seed(mut self, seed: Seed) -> Self {
+        self.args.seed = Some(seed);
+        self.args.regenerate = true;
+        self
+    }
+
+    /// The stream size, the size of the target by default
+    pub fn size(mut self, size: u64) -> Self {
+        self.args.common.size = Some(size);
+        self
+    }
+
+    pub fn chunk_size(mut self, chunk_size: u64) -> Self {
+        self.args.common.chunk_size = chunk_size;
+        self
+    }
+
+    /// The number of parallel jobs, the number of physical cores by default
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.args.common.jobs = Some(jobs.max(1));
+        self
+    }
+
+    pub fn checksum(mut self, checksum: ChecksumAlgorithm) -> Self {
+        self.args.common.checksum = checksum;
+        self
+    }
+
+    /// The checksum the stream must have
+    pub fn expected_checksum(mut self, checksum: impl Into<String>) -> Self {
+        self.args.expected_checksum = Some(checksum.into());
+        self
+    }
+
+    /// Stop cleanly after this wall time, like with `--duration`, the start of the stream being
+    /// validated
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.args.common.duration = Some(duration);
+        self
+    }
+
+    /// Validate all the chunks, instead of stopping at the first corrupted one
+    pub fn keep_going(mut self) -> Self {
+        self.args.corruption.keep_going = true;
+        self
+    }
+
+    /// Stop the run once the token is cancelled
+    pub fn cancellation(mut self, token: &CancellationToken) -> Self {
+        self.cancel = token.clone();
+        self
+    }
+
+    /// Report the progress to the callback, instead of the progress bar
+    pub fn progress(mut self, callback: impl FnMut(ProgressEvent) + Send + 'static) -> Self {
+        let callback = ProgressCallback::new(ProgressPhase::Validate, callback);
+        self.args.common.progress_callback = Some(callback);
+        self
+    }
+
+    /// Validate the stream
+    pub fn run(&self) -> RunResult {
+        let mut report = Report::new("validate", OutputFormat::Text);
+        let result = validate_stream(&self.args, self.cancel.0.clone(), &mut report);
+        RunResult::new(report, result)
+    }
+}
+
+impl From<ValidateArgs> for ValidateOptions {
+    fn from(args: ValidateArgs) -> Self {
+        ValidateOptions { args, cancel: CancellationToken::new() }
+    }
+}
+
+/// The arguments of the command, with their default values
+pub(crate) fn defaults<T: Args + FromArgMatches>(command: &'static str) -> T {
+    let matches = T::augment_args(Command::new(command))
+        .no_binary_name(true)
+        .try_get_matches_from(Vec::<String>::new())
+        .expect("the arguments have defaults");
+    T::from_arg_matches(&matches).expect("the arguments have defaults")
+}
+
+#[test]
+fn generate_and_validate_a_file() {
+    use std::fs::OpenOptions;
+    use std::io::{Seek as _, SeekFrom, Write as _};
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("out.bin");
+    let generated = GenerateOptions::new(&path).seed(Seed::U64(2)).size(33 << 10).jobs(2).run();
+    assert!(generated.is_success(), "{:?}", generated.errors);
+    assert_eq!((generated.bytes, generated.checksum.as_deref()), (33 << 10, Some("aa0e5a26")));
+    let validated = ValidateOptions::new(&path).seed(Seed::U64(2)).run();
+    assert!(validated.is_success(), "{:?}", validated.errors);
+    assert_eq!(validated.checksum, generated.checksum);
+    let validated = ValidateOptions::new(&path).expected_checksum("00000000").run();
+    assert!(matches!(validated.error, Some(Error::StreamMismatch(_))));
+
+    let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.seek(SeekFrom::Start(100)).unwrap();
+    file.write_all(b"corrupted").unwrap();
+    let validated = ValidateOptions::new(&path).keep_going().run();
+    assert_eq!(validated.exit_code, exit_code::CHUNK_MISMATCH);
+    assert!(validated.errors[0].contains("Invalid checksum at chunk 0"), "{:?}", validated.errors);
+    assert!(matches!(validated.error, Some(Error::Corruption(_))));
+    assert_eq!((validated.corrupted.len(), validated.corrupted[0].offset), (1, 0));
+    let validated = ValidateOptions::new(&path).run();
+    let Some(Error::ChunkMismatch(chunk)) = validated.error else {
+        panic!("{:?}", validated.error)
+    };
+    assert_eq!((chunk.chunk, chunk.offset, chunk.length), (0, 0, 32 << 10));
+    let validated = ValidateOptions::new(dir.path().join("missing.bin")).run();
+    assert_eq!(validated.exit_code, exit_code::IO_ERROR);
+    assert!(matches!(validated.error, Some(Error::Io(_))));
+}
+
+#[test]
+fn report_the_progress_to_a_callback() {
+    use std::sync::Mutex;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("out.bin");
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let received = events.clone();
+    let options = GenerateOptions::new(&path).size(1 << 20).chunk_size(1 << 10).jobs(2);
+    let result = options.progress(move |event| received.lock().unwrap().push(event)).run();
+    assert!(result.is_success(), "{:?}", result.errors);
+    let events = events.lock().unwrap();
+    let last = events.last().unwrap();
+    assert_eq!(
+        last,
+        &ProgressEvent {
+            phase: ProgressPhase::Generate,
+            bytes_done: 1 << 20,
+            total: Some(1 << 20),
+            finished: true
+        }
+    );
+    assert!(events.windows(2).all(|w| w[0].bytes_done <= w[1].bytes_done));
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let received = events.clone();
+    let options = ValidateOptions::new(&path).chunk_size(1 << 10);
+    let result = options.progress(move |event| received.lock().unwrap().push(event)).run();
+    assert!(result.is_success(), "{:?}", result.errors);
+    let last = *events.lock().unwrap().last().unwrap();
+    assert_eq!(
+        (last.phase, last.bytes_done, last.finished),
+        (ProgressPhase::Validate, 1 << 20, true)
+    );
+}
+
+#[test]
+fn cancel_a_run() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("out.bin");
+    let token = CancellationToken::new();
+    let cancel = token.clone();
+    let options = GenerateOptions::new(&path).size(1 << 30).chunk_size(1 << 10).jobs(2);
+    let result = options.cancellation(&token).progress(move |_| cancel.cancel()).run();
+    assert!(result.is_cancelled(), "{:?}", result.errors);
+    assert!(result.bytes > 0 && result.bytes < 1 << 30, "{}", result.bytes);
+
+    let token = CancellationToken::new();
+    token.cancel();
+    let result =
+        ValidateOptions::new(&path).size(1 << 20).chunk_size(1 << 10).cancellation(&token).run();
+    assert!(result.is_cancelled(), "{:?}", result.errors);
+    assert!(result.bytes < 1 << 20);
+}
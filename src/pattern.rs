@@ -0,0 +1,68 @@
+//! The classic test patterns, written instead of random data with `--pattern`
+
+use clap::ValueEnum;
+
+/// A deterministic pattern, depending only on the offset in the stream
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    /// All bits cleared, 0x00
+    Zeros,
+    /// All bits set, 0xff
+    Ones,
+    /// Alternating bits, 0x55 and 0xaa
+    Checkerboard,
+    /// A single bit set, moving by one bit every byte: 0x01, 0x02 ... 0x80
+    WalkingOnes,
+    /// A single bit cleared, moving by one bit every byte: 0xfe, 0xfd ... 0x7f
+    WalkingZeros,
+}
+
+impl Pattern {
+    pub fn id(self) -> u8 {
+        match self {
+            Pattern::Zeros => 1,
+            Pattern::Ones => 2,
+            Pattern::Checkerboard => 3,
+            Pattern::WalkingOnes => 4,
+            Pattern::WalkingZeros => 5,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::value_variants().iter().copied().find(|pattern| pattern.id() == id)
+    }
+
+    /// The byte at that offset of the stream
+    fn byte(self, offset: u64) -> u8 {
+        match self {
+            Pattern::Zeros => 0x00,
+            Pattern::Ones => 0xff,
+            Pattern::Checkerboard if offset.is_multiple_of(2) => 0x55,
+            Pattern::Checkerboard => 0xaa,
+            Pattern::WalkingOnes => 1 << (offset % 8),
+            Pattern::WalkingZeros => !(1 << (offset % 8)),
+        }
+    }
+
+    /// Fill the data found at that offset of the stream
+    pub fn fill(self, offset: u64, data: &mut [u8]) {
+        // the patterns repeat every 8 bytes
+        let period: [u8; 8] = std::array::from_fn(|i| self.byte(offset + i as u64));
+        for bytes in data.chunks_mut(8) {
+            bytes.copy_from_slice(&period[..bytes.len()]);
+        }
+    }
+}
+
+#[test]
+fn patterns_depend_on_the_offset() {
+    let mut data = [0u8; 10];
+    Pattern::WalkingOnes.fill(6, &mut data);
+    assert_eq!(data, [0x40, 0x80, 0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80]);
+    Pattern::Checkerboard.fill(1, &mut data[..3]);
+    assert_eq!(data[..3], [0xaa, 0x55, 0xaa]);
+    Pattern::WalkingZeros.fill(0, &mut data[..2]);
+    assert_eq!(data[..2], [0xfe, 0xfd]);
+    assert_eq!(Pattern::from_id(Pattern::Ones.id()), Some(Pattern::Ones));
+    assert_eq!(Pattern::from_id(0), None);
+}
@@ -0,0 +1,246 @@
+//! A benchmark of the network between two hosts, like iperf, with the data validated on the way
+
+use anyhow::anyhow;
+use clap::{Args, ValueHint};
+use human_units::FormatSize as _;
+use log::info;
+use std::io::{self, Read as _, Write};
+use std::net::{Shutdown, TcpStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::chunk::parse_run_id;
+use crate::cli::CommonArgs;
+use crate::devices;
+use crate::error::{Error, exit_code, usage};
+use crate::generate::{GenerateArgs, generate_stream_to, reject_file_options};
+use crate::net::{self, ACCEPT_POLL_INTERVAL, Endpoint, Scheme};
+use crate::options::defaults;
+use crate::report::{self, OutputFormat, Report};
+use crate::rng::Seed;
+use crate::serve::Verdict;
+
+/// The size of the stream of a connection, without `--size`: it's sent until the time is up
+const UNLIMITED_SIZE: u64 = i64::MAX as u64;
+
+/// How long the result of a stream is waited for, once it's sent
+const RESULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Measure the throughput of the network to `randstream serve --listen`, like iperf, with the
+/// streams validated by the server
+///
+/// Each connection sends a stream until the time is up, then gets its result from the server: the
+/// bandwidth and any corruption are reported together.
+#[derive(Args, Debug)]
+pub struct PerfArgs {
+    /// The `tcp://host:port` address of `randstream serve --listen`
+    // the file the options of a file require, which perf rejects
+    #[arg(id = "file", value_name = "ENDPOINT", value_hint = ValueHint::Url)]
+    pub endpoint: PathBuf,
+
+    /// The time to send the streams for, in seconds
+    #[clap(long, default_value = "10", value_parser = clap::value_parser!(u64).range(1..))]
+    pub time: u64,
+
+    /// The number of connections sending a stream at once
+    #[clap(long, default_value = "1", value_parser = clap::value_parser!(u64).range(1..=1024))]
+    pub parallel: u64,
+
+    /// How often the throughput of all the connections is logged, in seconds
+    #[clap(long, default_value = "1", value_parser = clap::value_parser!(u64).range(1..))]
+    pub interval: u64,
+
+    /// The random generator seed of the streams
+    ///
+    /// A decimal value, or an hexadecimal value of up to 256 bits prefixed with `0x`
+    #[clap(short = 'S', long, default_value = "0", value_parser = Seed::parse)]
+    pub seed: Seed,
+
+    /// Derive the random generator seed from an arbitrary string, like a ticket ID or a hostname
+    #[clap(long, conflicts_with = "seed")]
+    pub seed_string: Option<String>,
+
+    /// The ID of the run, recorded in the chunk headers with `--format v2`
+    #[clap(long, value_parser = parse_run_id)]
+    pub run_id: Option<u64>,
+
+    /// The number of times each connection is retried, a second apart
+    #[clap(long, default_value = "10")]
+    pub connect_retries: u32,
+
+    #[clap(flatten)]
+    pub common: CommonArgs,
+}
+
+impl PerfArgs {
+    /// The arguments generating the stream of each connection
+    fn generate(&self) -> GenerateArgs {
+        GenerateArgs {
+            seed: self.seed,
+            seed_string: self.seed_string.clone(),
+            run_id: self.run_id,
+            connect_retries: self.connect_retries,
+            common: self.common.clone(),
+            ..defaults("generate")
+        }
+    }
+}
+
+pub fn perf(args: &PerfArgs, cancel: Arc<AtomicBool>) -> Result<i32, Error> {
+    let generate = &args.generate();
+    let endpoint = match Endpoint::parse(&args.endpoint)? {
+        Some(endpoint) if endpoint.scheme() == Scheme::Tcp => endpoint,
+        _ => return Err(usage("perf sends the streams to a single tcp://host:port address").into()),
+    };
+    reject_file_options(generate, "perf")?;
+    net::reject_options("perf", &[("--print-checksum", generate.common.print_checksum)])?;
+    let output = generate.common.output;
+    // the time starts once all the connections are established
+    let sockets = (0..args.parallel)
+        .map(|_| endpoint.connect(generate.connect_retries))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let start = Instant::now();
+    let time = Duration::from_secs(args.time);
+    let interval = Duration::from_secs(args.interval);
+    let sent = Arc::new(AtomicU64::new(0));
+    let cancels: Vec<_> = sockets.iter().map(|_| Arc::new(AtomicBool::new(false))).collect();
+    let results: Vec<_> = thread::scope(|scope| {
+        let handles: Vec<_> = sockets
+            .into_iter()
+            .zip(&cancels)
+            .enumerate()
+            .map(|(index, (socket, cancel))| {
+                let device = format!("connection {}", index + 1);
+                let args = GenerateArgs {
+                    file: None,
+                    common: CommonArgs {
+                        size: Some(generate.common.size.unwrap_or(UNLIMITED_SIZE)),
+                        no_progress: true,
+                        device: Some(device.clone()),
+                        ..generate.common.clone()
+                    },
+                    ..generate.clone()
+                };
+                let sent = sent.clone();
+                let cancel = cancel.clone();
+                scope.spawn(move || {
+                    let mut report = Report::new("perf", output);
+                    let result = send_stream(&args, socket, sent, cancel, &mut report);
+                    let exit_code = report.record(&result);
+                    (device, report, exit_code)
+                })
+            })
+            .collect();
+        let mut logged = (Duration::ZERO, 0);
+        while !handles.iter().all(|h| h.is_finished()) {
+            let elapsed = start.elapsed();
+            if cancel.load(Ordering::Relaxed) || elapsed >= time {
+                cancels.iter().for_each(|c| c.store(true, Ordering::Relaxed));
+            }
+            if elapsed >= logged.0 + interval && logged.0 < time {
+                let now = (logged.0 + interval).min(time);
+                let bytes = sent.load(Ordering::Relaxed);
+                log_interval(logged.0, now, bytes - logged.1);
+                logged = (now, bytes);
+            }
+            thread::sleep(ACCEPT_POLL_INTERVAL);
+        }
+        handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+    });
+
+    if output == OutputFormat::Json {
+        println!("{}", report::devices_json("perf", &results));
+    } else {
+        devices::log_table("connection", &results);
+        let bytes = results.iter().map(|(_, report, _)| report.bytes).sum::<u64>();
+        let passed = results.iter().filter(|(_, _, code)| *code == 0).count();
+        info!(
+            "total: {} sent at {}/s, {passed} of {} streams valid",
+            bytes.format_size(),
+            report::throughput(bytes, start.elapsed()).format_size(),
+            results.len()
+        );
+    }
+    let failure = results.iter().map(|(_, _, code)| *code).find(|code| *code != 0);
+    Ok(match failure {
+        Some(code) => code,
+        None if cancel.load(Ordering::Relaxed) => exit_code::INTERRUPTED,
+        None => 0,
+    })
+}
+
+/// Send a stream on the connection until cancelled, and get its result from the receiver
+fn send_stream(
+    args: &GenerateArgs,
+    socket: TcpStream,
+    sent: Arc<AtomicU64>,
+    cancel: Arc<AtomicBool>,
+    report: &mut Report,
+) -> anyhow::Result<i32> {
+    let reply = socket.try_clone()?;
+    let generated = generate_stream_to(args, Box::new(PerfWriter { socket, sent }), cancel, report);
+    // the receiver sends the result once the stream has ended
+    reply.set_read_timeout(Some(RESULT_TIMEOUT))?;
+    let mut line = String::new();
+    let verdict = (&reply).take(4096).read_to_string(&mut line).ok();
+    let Some(verdict) = verdict.and_then(|_| Verdict::parse(&line)) else {
+        // the connection may have failed
+        generated?;
+        return Err(anyhow!("The receiver sent no result, it must be randstream serve"));
+    };
+    if verdict.exit_code != 0 {
+        // the receiver stops reading a corrupted stream, which may fail the sender too
+        report.errors.push(verdict.detail);
+        return Ok(verdict.exit_code);
+    }
+    match generated? {
+        0 | exit_code::INTERRUPTED => {}
+        code => return Ok(code),
+    }
+    if verdict.bytes != report.bytes {
+        report.errors.push(format!(
+            "The receiver has validated {} of the {} bytes sent",
+            verdict.bytes, report.bytes
+        ));
+        return Ok(exit_code::STREAM_MISMATCH);
+    }
+    report.checksum = Some(verdict.detail);
+    Ok(0)
+}
+
+/// Log the throughput of all the connections during the interval
+fn log_interval(from: Duration, to: Duration, bytes: u64) {
+    let interval = format!("{}-{}s", from.as_secs(), to.as_secs());
+    let throughput = format!("{}/s", report::throughput(bytes, to - from).format_size());
+    info!("{interval:>11}  {:>10}  {throughput:>12}", bytes.format_size());
+}
+
+/// The connection the stream is sent to, closed for writing once the stream is sent
+struct PerfWriter {
+    socket: TcpStream,
+    /// The bytes sent by all the connections
+    sent: Arc<AtomicU64>,
+}
+
+impl Write for PerfWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let size = self.socket.write(buf)?;
+        self.sent.fetch_add(size as u64, Ordering::Relaxed);
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.socket.flush()
+    }
+}
+
+impl Drop for PerfWriter {
+    fn drop(&mut self) {
+        // tells the receiver the stream has ended, while the result can still be read
+        let _ = self.socket.shutdown(Shutdown::Write);
+    }
+}
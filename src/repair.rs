@@ -0,0 +1,182 @@
+//! Rewrite the corrupted chunks of a stream, regenerated from its seed
+
+use clap::{Args, ValueHint};
+use log::info;
+use parse_size::parse_size;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
+use crate::chunk::parse_run_id;
+use crate::cli::CommonArgs;
+use crate::decompress::Decompress;
+use crate::error::{Error, ValidationError, usage};
+use crate::identity;
+use crate::inuse;
+use crate::net;
+use crate::report::{self, Report};
+use crate::rng::Seed;
+use crate::validate::{CorruptionArgs, ValidateArgs, repair_chunks, validate_stream};
+
+/// Rewrite only the corrupted chunks of a stream in a file, regenerated from its seed
+///
+/// The corrupted chunks are found by validating the stream against its regenerated data, or read
+/// from the map written by `validate --error-map`. The stream is described with the options used
+/// to generate it. Once rewritten, the chunks are read back, and must match the regenerated data.
+/// With `--format v2`, the first chunk must still hold its header.
+#[derive(Args, Debug)]
+pub struct RepairArgs {
+    /// The file or the block device
+    #[arg(value_hint = ValueHint::AnyPath)]
+    pub file: PathBuf,
+
+    /// The stream position
+    #[clap(short, long, default_value = "0", value_name = "SIZE", value_hint = ValueHint::Other, value_parser=|s: &str| parse_size(s))]
+    pub position: u64,
+
+    /// The random generator seed of the stream
+    ///
+    /// A decimal value, or an hexadecimal value of up to 256 bits prefixed with `0x`. The seed
+    /// recorded in the stream header, if any, is used instead.
+    #[clap(short = 'S', long, default_value = "0", value_parser = Seed::parse)]
+    pub seed: Seed,
+
+    /// Derive the random generator seed from an arbitrary string, like a ticket ID or a hostname
+    #[clap(long, conflicts_with = "seed")]
+    pub seed_string: Option<String>,
+
+    /// Derive the random generator seed from the WWN or serial number of the device
+    ///
+    /// Only on Linux.
+    #[clap(long, conflicts_with_all = ["seed", "seed_string"])]
+    pub seed_from_device: bool,
+
+    /// The ID of the run recorded in the chunk headers with `--format v2`
+    ///
+    /// Defaults to the one of most of the first chunks.
+    #[clap(long, value_parser = parse_run_id)]
+    pub run_id: Option<u64>,
+
+    /// Write to the block device even if it's in use
+    #[clap(long)]
+    pub force: bool,
+
+    /// Rewrite the chunks listed in this map, written by `validate --error-map`, instead of
+    /// validating the stream to find them
+    #[clap(long, value_name = "FILE")]
+    pub error_map: Option<PathBuf>,
+
+    #[clap(flatten)]
+    pub common: CommonArgs,
+}
+
+impl RepairArgs {
+    /// The seed of the stream, derived from the device with `--seed-from-device`
+    fn seed(&self) -> anyhow::Result<Seed> {
+        if self.seed_from_device {
+            return identity::device_seed(&self.file);
+        }
+        Ok(self.seed_string.as_deref().map(Seed::from_string).unwrap_or(self.seed))
+    }
+}
+
+pub fn repair(args: &RepairArgs, cancel: Arc<AtomicBool>) -> Result<i32, Error> {
+    let mut report = Report::new("repair", args.common.output);
+    let result = repair_stream(args, cancel, &mut report);
+    report.finish(&result, true);
+    result.map_err(Error::from)
+}
+
+fn repair_stream(
+    args: &RepairArgs,
+    cancel: Arc<AtomicBool>,
+    report: &mut Report,
+) -> anyhow::Result<i32> {
+    let file = args.file.as_path();
+    if !file.exists() {
+        return Err(usage("repair needs an existing file or device"));
+    }
+    // the options only generating or validating a whole stream
+    net::reject_options(
+        "repair",
+        &[
+            ("--digest", args.common.digest.is_some()),
+            ("--print-checksum", args.common.print_checksum),
+            ("--duration", args.common.duration.is_some()),
+        ],
+    )?;
+    // the stream is validated against its regenerated data, reading the seed from the stream
+    // header if any
+    let validate_args = ValidateArgs {
+        file: Some(args.file.clone()),
+        more_files: Vec::new(),
+        from_file: None,
+        manifest: None,
+        tar: false,
+        decompress: Decompress::None,
+        file_jobs: 16,
+        position: args.position,
+        length: None,
+        expected_checksum: None,
+        expected_digest: None,
+        checksum_file: None,
+        checkpoint: None,
+        shard_size: None,
+        run_id: args.run_id,
+        regenerate: true,
+        diff: false,
+        seed: Some(args.seed()?),
+        seed_string: None,
+        seed_from_device: false,
+        sample: None,
+        sample_chunks: None,
+        sparse: false,
+        incremental: None,
+        incremental_window: 30,
+        incremental_size: None,
+        read_retries: 0,
+        retry_delay: 100,
+        retry_reopen: false,
+        corruption: CorruptionArgs {
+            keep_going: true,
+            error_map: None,
+            badblocks_out: None,
+            badblocks_block_size: 512,
+        },
+        common: args.common.clone(),
+    };
+    let mut chunks = match &args.error_map {
+        Some(path) => {
+            let map = fs::read(path)?;
+            report::error_map_chunks(&String::from_utf8_lossy(&map)).ok_or_else(|| {
+                usage(format!("{} isn't an error map written by validate", path.display()))
+            })?
+        }
+        None => {
+            info!("looking for the corrupted chunks");
+            let mut validated = Report::new("validate", args.common.output);
+            match validate_stream(&validate_args, cancel, &mut validated) {
+                Ok(0) => Vec::new(),
+                Ok(code) => return Ok(code),
+                Err(e) if matches!(e.downcast_ref(), Some(ValidationError::Corrupted { .. })) => {
+                    validated.corrupted.iter().map(|c| c.chunk).collect()
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    };
+    chunks.sort();
+    chunks.dedup();
+    if chunks.is_empty() {
+        info!("no corrupted chunk to repair");
+        return Ok(0);
+    }
+    if !args.force {
+        inuse::check_unused(file)?;
+    }
+    repair_chunks(&validate_args, file, &chunks)?;
+    info!("repaired chunks: {}", chunks.len());
+    report.bytes = chunks.len() as u64 * args.common.chunk_size;
+    Ok(0)
+}
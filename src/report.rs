@@ -0,0 +1,530 @@
+//! The machine readable report of a run, with `--output json`
+
+use clap::ValueEnum;
+use human_units::{FormatDuration as _, FormatSize as _};
+use log::{Level, debug, log};
+use std::fmt::Write as _;
+use std::time::{Duration, Instant};
+
+use crate::error::{CorruptedChunk, ValidationError, corrupted_ranges};
+use crate::histogram::Histogram;
+
+/// The format of the result of a run
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Log lines
+    #[default]
+    Text,
+    /// A JSON object, printed at the end of the run
+    Json,
+}
+
+/// The time under which the imbalance between the threads is only logged at the info level:
+/// a short run, like a few batches per thread, is dominated by the start of the threads
+const IMBALANCE_MIN_ELAPSED: Duration = Duration::from_secs(1);
+
+/// What a worker thread has processed
+#[derive(Clone, Debug)]
+pub struct ThreadStats {
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+/// A phase of a run writing and reading back a stream
+#[derive(Clone, Debug)]
+pub struct Phase {
+    pub name: &'static str,
+    /// The pass of a multi-pass run, starting at 1
+    pub pass: u32,
+    pub stats: ThreadStats,
+    /// The latency of the I/O, with `randstream bench`
+    pub latency: Option<LatencySummary>,
+}
+
+/// The latency of each I/O of a phase, in nanoseconds
+#[derive(Clone, Debug, Default)]
+pub struct Latencies {
+    histogram: Histogram,
+}
+
+/// The distribution of the latency of the I/O of a phase
+#[derive(Clone, Debug, PartialEq)]
+pub struct LatencySummary {
+    pub ops: u64,
+    /// The operations per second
+    pub iops: u64,
+    /// The mean number of I/O in flight, over all the threads
+    pub queue_depth: f64,
+    pub min: Duration,
+    pub mean: Duration,
+    pub p50: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl Latencies {
+    pub fn record(&mut self, latency: Duration) {
+        self.histogram.record(latency.as_nanos() as u64);
+    }
+
+    /// Add the latencies of another thread
+    pub fn merge(&mut self, other: &Latencies) {
+        self.histogram.merge(&other.histogram);
+    }
+
+    /// The number of I/O recorded
+    pub fn count(&self) -> u64 {
+        self.histogram.len()
+    }
+
+    /// The distribution of the latencies, of the I/O done in that time, if any
+    ///
+    /// The percentiles are within 0.1% of the actual latencies.
+    pub fn summary(&self, elapsed: Duration) -> Option<LatencySummary> {
+        let histogram = &self.histogram;
+        if histogram.is_empty() {
+            return None;
+        }
+        let ops = histogram.len();
+        let percentile = |p| Duration::from_nanos(histogram.value_at_percentile(p));
+        Some(LatencySummary {
+            ops,
+            iops: (ops as f64 / elapsed.as_secs_f64()) as u64,
+            // the time spent in the I/O, spread over the phase
+            queue_depth: histogram.mean() * ops as f64 / elapsed.as_nanos() as f64,
+            min: Duration::from_nanos(histogram.min()),
+            mean: Duration::from_nanos(histogram.mean() as u64),
+            p50: percentile(50.0),
+            p99: percentile(99.0),
+            max: Duration::from_nanos(histogram.max()),
+        })
+    }
+
+    /// The percentile distribution of the latencies in milliseconds, in the `.hgrm` format of
+    /// HdrHistogram
+    pub fn percentile_distribution(&self) -> String {
+        self.histogram.percentile_distribution(1e6)
+    }
+}
+
+/// The result of a run, filled as it goes
+#[derive(Debug)]
+pub struct Report {
+    format: OutputFormat,
+    command: &'static str,
+    start: Instant,
+    /// The duration of the run, once its outcome is recorded
+    elapsed: Option<Duration>,
+    pub bytes: u64,
+    pub checksum: Option<String>,
+    pub digest: Option<String>,
+    pub threads: Vec<ThreadStats>,
+    /// How much longer the slowest thread took than the mean of the threads, as a fraction
+    pub imbalance: Option<f64>,
+    pub phases: Vec<Phase>,
+    pub errors: Vec<String>,
+    /// The chunks found corrupted, with `--keep-going`
+    pub corrupted: Vec<CorruptedChunk>,
+    /// The chunks read after retrying, with `--read-retries`
+    pub retried_chunks: u64,
+}
+
+impl Report {
+    pub fn new(command: &'static str, format: OutputFormat) -> Self {
+        Report {
+            format,
+            command,
+            start: Instant::now(),
+            elapsed: None,
+            bytes: 0,
+            checksum: None,
+            digest: None,
+            threads: Vec::new(),
+            imbalance: None,
+            phases: Vec::new(),
+            errors: Vec::new(),
+            corrupted: Vec::new(),
+            retried_chunks: 0,
+        }
+    }
+
+    /// Record what the threads of a run have processed, logging each one, and warn if the
+    /// slowest one took more than the threshold, in percent, longer than the mean, unless the run
+    /// was too short to tell
+    pub fn add_threads(&mut self, threads: impl IntoIterator<Item = ThreadStats>, threshold: f64) {
+        let threads: Vec<_> = threads.into_iter().collect();
+        for (index, t) in threads.iter().enumerate() {
+            debug!(
+                "thread {index}: {} bytes in {}, {}/s",
+                t.bytes,
+                t.elapsed.format_duration(),
+                throughput(t.bytes, t.elapsed).format_size()
+            );
+        }
+        if let Some(imbalance) = imbalance(&threads) {
+            if imbalance * 100.0 > threshold {
+                let slowest = threads.iter().map(|t| t.elapsed).max().unwrap_or_default();
+                let level = match slowest < IMBALANCE_MIN_ELAPSED {
+                    true => Level::Info,
+                    false => Level::Warn,
+                };
+                log!(
+                    level,
+                    "thread imbalance: the slowest thread took {:.0}% longer than the mean, {}",
+                    imbalance * 100.0,
+                    slowest.format_duration()
+                );
+            }
+            self.record_imbalance(imbalance);
+        }
+        self.threads.extend(threads);
+    }
+
+    /// Record the threads of a phase of the run, covered by the other report
+    pub fn extend_threads(&mut self, other: &Report) {
+        self.threads.extend(other.threads.iter().cloned());
+        if let Some(imbalance) = other.imbalance {
+            self.record_imbalance(imbalance);
+        }
+    }
+
+    fn record_imbalance(&mut self, imbalance: f64) {
+        self.imbalance = Some(self.imbalance.map_or(imbalance, |i| i.max(imbalance)));
+    }
+
+    /// What the run has processed so far
+    pub fn stats(&self) -> ThreadStats {
+        ThreadStats { bytes: self.bytes, elapsed: self.elapsed() }
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.elapsed.unwrap_or_else(|| self.start.elapsed())
+    }
+
+    /// The phase of a larger run covered by this report
+    pub fn phase(&self, pass: u32) -> Phase {
+        Phase { name: self.command, pass, stats: self.stats(), latency: None }
+    }
+
+    pub fn is_json(&self) -> bool {
+        self.format == OutputFormat::Json
+    }
+
+    /// Record the errors of the threads which failed, and return the first one
+    pub fn thread_errors(&mut self, errors: Vec<anyhow::Error>) -> anyhow::Error {
+        self.errors.extend(errors.iter().map(|e| e.to_string()));
+        errors.into_iter().next().expect("at least one error")
+    }
+
+    /// Print the report, with the outcome of the run, if requested
+    ///
+    /// It's printed on stdout, unless the stream is written there.
+    pub fn finish(mut self, result: &anyhow::Result<i32>, stdout: bool) {
+        if !self.is_json() {
+            return;
+        }
+        let exit_code = self.record(result);
+        let json = self.to_json(exit_code);
+        if stdout {
+            println!("{json}");
+        } else {
+            eprintln!("{json}");
+        }
+    }
+
+    /// Print the checksum of the stream on stdout, with `--print-checksum`, if the run succeeded
+    pub fn print_checksum(&self, result: &anyhow::Result<i32>) {
+        if let (Ok(0), Some(checksum)) = (result, &self.checksum) {
+            println!("{checksum}");
+        }
+    }
+
+    /// Record the outcome of the run, and return its exit code
+    pub fn record(&mut self, result: &anyhow::Result<i32>) -> i32 {
+        self.elapsed.get_or_insert(self.start.elapsed());
+        match result {
+            Ok(code) => *code,
+            Err(e) => {
+                let message = e.to_string();
+                if !self.errors.contains(&message) {
+                    self.errors.push(message);
+                }
+                crate::error::exit_code(e)
+            }
+        }
+    }
+
+    fn to_json(&self, exit_code: i32) -> String {
+        let elapsed = self.elapsed();
+        let mut json = String::new();
+        write!(json, "{{\"command\":{}", quote(self.command)).unwrap();
+        write!(json, ",\"success\":{}", exit_code == 0).unwrap();
+        write!(json, ",\"exit_code\":{exit_code}").unwrap();
+        write!(json, ",\"bytes\":{}", self.bytes).unwrap();
+        write!(json, ",\"checksum\":{}", optional(&self.checksum)).unwrap();
+        write!(json, ",\"digest\":{}", optional(&self.digest)).unwrap();
+        write!(json, ",\"duration\":{:.6}", elapsed.as_secs_f64()).unwrap();
+        write!(json, ",\"throughput\":{}", throughput(self.bytes, elapsed)).unwrap();
+        if self.retried_chunks > 0 {
+            write!(json, ",\"retried_chunks\":{}", self.retried_chunks).unwrap();
+        }
+        let threads = self.threads.iter().map(|t| {
+            format!(
+                "{{\"bytes\":{},\"duration\":{:.6},\"throughput\":{}}}",
+                t.bytes,
+                t.elapsed.as_secs_f64(),
+                throughput(t.bytes, t.elapsed)
+            )
+        });
+        write!(json, ",\"threads\":[{}]", threads.collect::<Vec<_>>().join(",")).unwrap();
+        if let Some(imbalance) = self.imbalance {
+            write!(json, ",\"imbalance\":{imbalance:.4}").unwrap();
+        }
+        if !self.phases.is_empty() {
+            let phases = self.phases.iter().map(|p| {
+                let latency = match &p.latency {
+                    Some(l) => format!(
+                        ",\"ops\":{},\"iops\":{},\"queue_depth\":{:.2},\"latency\":{{\"min\":{:.9},\"mean\":{:.9},\"p50\":{:.9},\"p99\":{:.9},\"max\":{:.9}}}",
+                        l.ops,
+                        l.iops,
+                        l.queue_depth,
+                        l.min.as_secs_f64(),
+                        l.mean.as_secs_f64(),
+                        l.p50.as_secs_f64(),
+                        l.p99.as_secs_f64(),
+                        l.max.as_secs_f64()
+                    ),
+                    None => String::new(),
+                };
+                format!(
+                    "{{\"name\":{},\"pass\":{},\"bytes\":{},\"duration\":{:.6},\"throughput\":{}{latency}}}",
+                    quote(p.name),
+                    p.pass,
+                    p.stats.bytes,
+                    p.stats.elapsed.as_secs_f64(),
+                    throughput(p.stats.bytes, p.stats.elapsed)
+                )
+            });
+            write!(json, ",\"phases\":[{}]", phases.collect::<Vec<_>>().join(",")).unwrap();
+        }
+        let errors = self.errors.iter().map(|e| quote(e));
+        write!(json, ",\"errors\":[{}]}}", errors.collect::<Vec<_>>().join(",")).unwrap();
+        json
+    }
+}
+
+/// The report of a run on several devices, with the totals and the report of each one
+///
+/// The exit code is the one of the first device which failed.
+pub fn devices_json(command: &str, devices: &[(String, Report, i32)]) -> String {
+    let exit_code = devices.iter().map(|(_, _, code)| *code).find(|code| *code != 0).unwrap_or(0);
+    let failed = devices.iter().filter(|(_, _, code)| *code != 0).count();
+    let bytes: u64 = devices.iter().map(|(_, report, _)| report.bytes).sum();
+    let devices = devices.iter().map(|(device, report, code)| {
+        // the report of the device, starting with its name
+        format!("{{\"device\":{},{}", quote(device), &report.to_json(*code)[1..])
+    });
+    format!(
+        "{{\"command\":{},\"success\":{},\"exit_code\":{exit_code},\"passed\":{},\"failed\":{failed},\"bytes\":{bytes},\"devices\":[{}]}}",
+        quote(command),
+        exit_code == 0,
+        devices.len() - failed,
+        devices.collect::<Vec<_>>().join(",")
+    )
+}
+
+/// The map of the corrupted chunks, with `--error-map`
+///
+/// The expected and found checksums are `null` for the short chunk at the end of the stream,
+/// which must be zeroed instead, and the found offset is `null` for a misplaced chunk holding
+/// data from outside the stream.
+pub fn error_map(chunks: &[CorruptedChunk]) -> String {
+    let chunks = chunks.iter().map(|c| {
+        let (expected, found) = match c.error {
+            ValidationError::ChunkChecksum { expected, found, width, .. } => (
+                quote(&format!("{expected:0digits$x}", digits = width * 2)),
+                quote(&format!("{found:0digits$x}", digits = width * 2)),
+            ),
+            ValidationError::Misplaced { offset, data_offset, .. } => {
+                (offset.to_string(), data_offset.map_or("null".to_string(), |o| o.to_string()))
+            }
+            ValidationError::ChunkLength { expected, found, .. } => {
+                (expected.to_string(), found.to_string())
+            }
+            ValidationError::SeedFingerprint { expected, found, .. }
+            | ValidationError::Stale { expected, found, .. } => {
+                (quote(&format!("{expected:016x}")), quote(&format!("{found:016x}")))
+            }
+            _ => ("null".to_string(), "null".to_string()),
+        };
+        format!(
+            "{{\"chunk\":{},\"offset\":{},\"length\":{},\"expected\":{expected},\"found\":{found}}}",
+            c.chunk, c.offset, c.length
+        )
+    });
+    format!("{{\"corrupted_chunks\":[{}]}}", chunks.collect::<Vec<_>>().join(","))
+}
+
+/// The chunks listed in a map written by `error_map()`, for `repair --error-map`
+pub fn error_map_chunks(map: &str) -> Option<Vec<u64>> {
+    let list = map.trim().strip_prefix(r#"{"corrupted_chunks":["#)?.strip_suffix("]}")?;
+    list.split(r#"{"chunk":"#).skip(1).map(|entry| entry.split_once(',')?.0.parse().ok()).collect()
+}
+
+/// The blocks holding the corrupted chunks, one number per line, with `--badblocks-out`
+///
+/// It's the format of the bad blocks list read by `e2fsck -l` and `mkfs -l`.
+pub fn badblocks(chunks: &[CorruptedChunk], block_size: u64) -> String {
+    let mut list = String::new();
+    let mut next = 0;
+    for range in corrupted_ranges(chunks) {
+        // a block shared by two ranges is listed once
+        for block in (range.start / block_size).max(next)..range.end.div_ceil(block_size) {
+            writeln!(list, "{block}").unwrap();
+            next = block + 1;
+        }
+    }
+    list
+}
+
+/// The throughput in bytes per second
+pub fn throughput(bytes: u64, elapsed: Duration) -> u64 {
+    if elapsed.is_zero() { 0 } else { (bytes as f64 / elapsed.as_secs_f64()) as u64 }
+}
+
+/// How much longer the slowest thread took than the mean of the threads, as a fraction, with
+/// several threads
+fn imbalance(threads: &[ThreadStats]) -> Option<f64> {
+    let slowest = threads.iter().map(|t| t.elapsed.as_secs_f64()).reduce(f64::max)?;
+    let mean = threads.iter().map(|t| t.elapsed.as_secs_f64()).sum::<f64>() / threads.len() as f64;
+    (threads.len() > 1 && mean > 0.0).then(|| slowest / mean - 1.0)
+}
+
+fn optional(value: &Option<String>) -> String {
+    value.as_deref().map(quote).unwrap_or_else(|| "null".to_string())
+}
+
+/// A JSON string
+pub(crate) fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[test]
+fn report_to_json() {
+    let mut report = Report::new("validate", OutputFormat::Json);
+    report.bytes = 1000;
+    report.checksum = Some("1234abcd".to_string());
+    report.threads.push(ThreadStats { bytes: 1000, elapsed: Duration::from_secs(2) });
+    report.errors.push("Invalid \"checksum\"\n".to_string());
+    let json = report.to_json(1);
+    assert!(json.starts_with(r#"{"command":"validate","success":false,"exit_code":1,"bytes":1000,"checksum":"1234abcd","digest":null,"duration":"#));
+    assert!(json.ends_with(r#","threads":[{"bytes":1000,"duration":2.000000,"throughput":500}],"errors":["Invalid \"checksum\"\n"]}"#));
+    assert_eq!(quote("\u{1}"), r#""\u0001""#);
+}
+
+#[test]
+fn thread_imbalance() {
+    let thread = |secs| ThreadStats { bytes: 1000, elapsed: Duration::from_secs(secs) };
+    assert_eq!(imbalance(&[thread(2)]), None);
+    assert_eq!(imbalance(&[thread(2), thread(2)]), Some(0.0));
+    assert_eq!(imbalance(&[thread(1), thread(1), thread(4)]), Some(1.0));
+    let mut report = Report::new("validate", OutputFormat::Json);
+    report.add_threads([thread(1), thread(3)], 50.0);
+    report.add_threads([thread(2), thread(2)], 50.0);
+    assert_eq!(report.threads.len(), 4);
+    assert_eq!(report.imbalance, Some(0.5));
+    assert!(report.to_json(0).contains(r#""throughput":500}],"imbalance":0.5000,"#));
+}
+
+#[test]
+fn latency_summary() {
+    let mut latencies = Latencies::default();
+    for micros in (1..=100).rev() {
+        latencies.record(Duration::from_micros(micros));
+    }
+    let mut other = Latencies::default();
+    other.record(Duration::from_millis(10));
+    latencies.merge(&other);
+    let summary = latencies.summary(Duration::from_secs(2)).unwrap();
+    assert_eq!((summary.ops, summary.iops), (101, 50));
+    // 15.05ms of I/O in 2s
+    assert!((summary.queue_depth - 0.007525).abs() < 1e-9, "{}", summary.queue_depth);
+    assert_eq!(summary.min, Duration::from_micros(1));
+    // within the error of the histogram
+    assert_eq!(
+        (summary.p50, summary.p99),
+        (Duration::from_nanos(51_007), Duration::from_nanos(100_031))
+    );
+    assert_eq!(summary.max, Duration::from_millis(10));
+    assert_eq!(summary.mean, Duration::from_nanos((5050 + 10_000) * 1000 / 101));
+    assert_eq!(Latencies::default().summary(Duration::from_secs(1)), None);
+}
+
+#[test]
+fn error_map_to_json() {
+    let chunks = [
+        CorruptedChunk {
+            chunk: 1,
+            offset: 1024,
+            length: 1024,
+            error: ValidationError::ChunkChecksum {
+                chunk: 1,
+                expected: 0xab,
+                found: 0x12,
+                width: 4,
+                sectors: Vec::new(),
+            },
+        },
+        CorruptedChunk { chunk: 2, offset: 2048, length: 2, error: ValidationError::NonZeroTail },
+    ];
+    assert_eq!(
+        error_map(&chunks),
+        r#"{"corrupted_chunks":[{"chunk":1,"offset":1024,"length":1024,"expected":"000000ab","found":"00000012"},{"chunk":2,"offset":2048,"length":2,"expected":null,"found":null}]}"#
+    );
+    assert_eq!(error_map(&[]), r#"{"corrupted_chunks":[]}"#);
+    assert_eq!(error_map_chunks(&error_map(&chunks)), Some(vec![1, 2]));
+    assert_eq!(error_map_chunks(&error_map(&[])), Some(Vec::new()));
+    assert_eq!(error_map_chunks(r#"{"chunks":[]}"#), None);
+}
+
+#[test]
+fn badblocks_list() {
+    let chunk = |chunk, offset, length| CorruptedChunk {
+        chunk,
+        offset,
+        length,
+        error: ValidationError::NonZeroTail,
+    };
+    let chunks = [chunk(1, 1024, 1024), chunk(3, 3072, 1000), chunk(4, 4100, 10)];
+    assert_eq!(badblocks(&chunks, 512), "2\n3\n6\n7\n8\n");
+    assert_eq!(badblocks(&chunks, 4096), "0\n1\n");
+    assert_eq!(badblocks(&[], 512), "");
+
+    // only the damaged sectors, with --sector-checksums
+    let error = ValidationError::ChunkChecksum {
+        chunk: 2,
+        expected: 0xab,
+        found: 0x12,
+        width: 4,
+        sectors: vec![512..1024, 3584..4092],
+    };
+    let chunks = [CorruptedChunk { chunk: 2, offset: 8192, length: 4096, error }];
+    assert_eq!(
+        badblocks(&chunks, 512),
+        "17
+23
+"
+    );
+}
@@ -0,0 +1,94 @@
+//! The retries of the reads failing with an I/O error, with `validate --read-retries`
+//!
+//! A flaky link to the device, like a SAS expander resetting, fails a few reads which succeed
+//! when retried. Each retry waits twice as long as the previous one. The chunks read after
+//! retrying are counted, so the transient errors are reported without failing the run.
+
+use std::io;
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use log::warn;
+
+/// How the failed reads are retried, shared by the threads
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ReadRetry {
+    retries: u32,
+    /// The delay before the first retry
+    delay: Duration,
+    /// Reopen the file, and drop the chunks from the page cache, before retrying
+    pub reopen: bool,
+    /// The number of chunks read after retrying, by all the threads
+    retried: Arc<AtomicU64>,
+}
+
+impl ReadRetry {
+    pub fn new(retries: u32, delay: Duration, reopen: bool) -> Self {
+        ReadRetry { retries, delay, reopen, retried: Arc::default() }
+    }
+
+    /// Read the chunks, retrying on an I/O error
+    ///
+    /// `read` is told whether it's a retry, to prepare the file for it.
+    pub fn run<T>(
+        &self,
+        chunks: Range<u64>,
+        mut read: impl FnMut(bool) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let mut attempt = 0;
+        let mut delay = self.delay;
+        loop {
+            match read(attempt > 0) {
+                Ok(value) => {
+                    if attempt > 0 {
+                        self.retried.fetch_add(chunks.end - chunks.start, Ordering::Relaxed);
+                    }
+                    return Ok(value);
+                }
+                Err(e) if attempt < self.retries => {
+                    warn!(
+                        "can't read chunk {}: {e}, retrying in {}ms",
+                        chunks.start,
+                        delay.as_millis()
+                    );
+                    thread::sleep(delay);
+                    delay *= 2;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// The number of chunks read after retrying
+    pub fn retried(&self) -> u64 {
+        self.retried.load(Ordering::Relaxed)
+    }
+}
+
+#[test]
+fn reads_retried_until_they_succeed() {
+    let retry = ReadRetry::new(2, Duration::from_millis(1), false);
+    let mut failures = 2;
+    let mut retries = Vec::new();
+    let read = retry.run(4..6, |retrying| {
+        retries.push(retrying);
+        if failures > 0 {
+            failures -= 1;
+            return Err(io::Error::other("I/O error"));
+        }
+        Ok(42)
+    });
+    assert_eq!(read.unwrap(), 42);
+    assert_eq!(retries, [false, true, true]);
+    assert_eq!(retry.retried(), 2);
+
+    // out of retries
+    let read = retry.clone().run(7..8, |_| Err::<(), _>(io::Error::other("I/O error")));
+    assert!(read.is_err());
+    assert_eq!(retry.run(8..9, |_| Ok(())).unwrap(), ());
+    assert_eq!(retry.retried(), 2);
+}
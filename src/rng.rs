@@ -0,0 +1,364 @@
+use chacha20::ChaCha20Rng;
+use clap::ValueEnum;
+use rand::Rng as _;
+use rand::SeedableRng as _;
+use rand_pcg::Pcg64Mcg;
+use std::fmt;
+
+use crate::SeekableRng;
+use crate::aes::AesCtr;
+use crate::sha256::Sha256;
+use crate::xoshiro::Xoshiro256;
+
+/// The random generator used to fill the chunks
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RngAlgorithm {
+    /// PCG 64 bits, fast but predictable
+    #[default]
+    Pcg64,
+    /// xoshiro256++, faster than PCG on some targets, and predictable
+    Xoshiro256,
+    /// ChaCha20, cryptographically unpredictable
+    Chacha20,
+    /// AES-128 in counter mode, cryptographically unpredictable and hardware accelerated
+    AesCtr,
+}
+
+/// The seed of the random generator, up to 256 bits
+///
+/// The seeds fitting in 64 bits keep the historical seeding, so a given value always produces the
+/// same stream, whatever the way it's written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Seed {
+    U64(u64),
+    /// A larger seed, in little endian
+    Bytes([u8; 32]),
+}
+
+impl Default for Seed {
+    fn default() -> Self {
+        Seed::U64(0)
+    }
+}
+
+impl Seed {
+    /// Parse a decimal value, or an hexadecimal value of up to 256 bits prefixed with `0x`
+    pub fn parse(s: &str) -> Result<Seed, String> {
+        let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) else {
+            return s.parse().map(Seed::U64).map_err(|e| e.to_string());
+        };
+        let hex = hex.replace('_', "");
+        if hex.is_empty() || hex.len() > 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err("expected up to 64 hexadecimal digits".to_string());
+        }
+        let mut bytes = [0u8; 32];
+        for (i, digit) in hex.bytes().rev().enumerate() {
+            let value = (digit as char).to_digit(16).unwrap() as u8;
+            bytes[i / 2] |= value << (4 * (i % 2));
+        }
+        Ok(Seed::from_bytes(bytes))
+    }
+
+    /// Hash an arbitrary string into a seed
+    pub fn from_string(s: &str) -> Seed {
+        let mut hasher = Sha256::default();
+        hasher.update(s.as_bytes());
+        Seed::from_bytes(hasher.finalize())
+    }
+
+    pub fn to_bytes(self) -> [u8; 32] {
+        match self {
+            Seed::U64(seed) => {
+                let mut bytes = [0u8; 32];
+                bytes[..8].copy_from_slice(&seed.to_le_bytes());
+                bytes
+            }
+            Seed::Bytes(bytes) => bytes,
+        }
+    }
+
+    pub fn from_bytes(bytes: [u8; 32]) -> Seed {
+        if bytes[8..].iter().all(|b| *b == 0) {
+            Seed::U64(u64::from_le_bytes(bytes[..8].try_into().unwrap()))
+        } else {
+            Seed::Bytes(bytes)
+        }
+    }
+
+    /// The seed of a pass of a multi-pass run, the first pass using the seed itself
+    pub fn for_pass(self, pass: u32) -> Seed {
+        if pass == 0 {
+            return self;
+        }
+        let mut hasher = Sha256::default();
+        hasher.update(&self.to_bytes());
+        hasher.update(&pass.to_le_bytes());
+        Seed::from_bytes(hasher.finalize())
+    }
+
+    /// The seed of a device processed along with others, the first device using the seed itself
+    pub fn for_device(self, index: usize) -> Seed {
+        if index == 0 {
+            return self;
+        }
+        let mut hasher = Sha256::default();
+        hasher.update(&self.to_bytes());
+        hasher.update(b"device");
+        hasher.update(&(index as u64).to_le_bytes());
+        Seed::from_bytes(hasher.finalize())
+    }
+
+    /// The seed of a region of the stream, with `--region-size`, the first region using the seed
+    /// itself
+    pub fn for_region(self, region: u64) -> Seed {
+        if region == 0 {
+            return self;
+        }
+        let mut hasher = Sha256::default();
+        hasher.update(&self.to_bytes());
+        hasher.update(b"region");
+        hasher.update(&region.to_le_bytes());
+        Seed::from_bytes(hasher.finalize())
+    }
+
+    /// A short value identifying the seed, recorded in the chunk headers
+    pub fn fingerprint(self) -> u64 {
+        let mut hasher = Sha256::default();
+        hasher.update(&self.to_bytes());
+        u64::from_le_bytes(hasher.finalize()[..8].try_into().unwrap())
+    }
+
+    /// The seed folded to 128 bits
+    fn fold_128(bytes: &[u8; 32]) -> [u8; 16] {
+        std::array::from_fn(|i| bytes[i] ^ bytes[i + 16])
+    }
+}
+
+impl fmt::Display for Seed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Seed::U64(seed) => write!(f, "{seed}"),
+            Seed::Bytes(bytes) => {
+                write!(f, "0x")?;
+                bytes.iter().rev().try_for_each(|b| write!(f, "{b:02x}"))
+            }
+        }
+    }
+}
+
+impl RngAlgorithm {
+    /// The identifier of the algorithm in the stream header
+    pub fn id(self) -> u8 {
+        match self {
+            RngAlgorithm::Pcg64 => 0,
+            RngAlgorithm::Xoshiro256 => 1,
+            RngAlgorithm::Chacha20 => 2,
+            RngAlgorithm::AesCtr => 3,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::value_variants().iter().copied().find(|rng| rng.id() == id)
+    }
+
+    /// The generator of a stream divided in regions of `region_bytes` bytes of output, each one
+    /// with its own seed, derived from `seed`
+    pub fn region_rng(self, seed: Seed, region_bytes: u64) -> StreamRng {
+        StreamRng::Regions(Box::new(RegionRng {
+            algorithm: self,
+            seed,
+            region_bytes,
+            position: 0,
+            region: 0,
+            rng: self.rng(seed),
+        }))
+    }
+
+    pub fn rng(self, seed: Seed) -> StreamRng {
+        match seed {
+            Seed::U64(seed) => self.rng_from_u64(seed),
+            Seed::Bytes(bytes) => match self {
+                RngAlgorithm::Pcg64 => {
+                    StreamRng::Pcg64(Pcg64Mcg::from_seed(Seed::fold_128(&bytes)))
+                }
+                RngAlgorithm::Xoshiro256 => {
+                    StreamRng::Xoshiro256(Xoshiro256::from_state(std::array::from_fn(|i| {
+                        u64::from_le_bytes(bytes[i * 8..i * 8 + 8].try_into().unwrap())
+                    })))
+                }
+                RngAlgorithm::Chacha20 => {
+                    StreamRng::Chacha20(Box::new(ChaCha20Rng::from_seed(bytes)))
+                }
+                RngAlgorithm::AesCtr => {
+                    StreamRng::AesCtr(Box::new(AesCtr::new(Seed::fold_128(&bytes))))
+                }
+            },
+        }
+    }
+
+    fn rng_from_u64(self, seed: u64) -> StreamRng {
+        match self {
+            RngAlgorithm::Pcg64 => StreamRng::Pcg64(Pcg64Mcg::seed_from_u64(seed)),
+            RngAlgorithm::Xoshiro256 => StreamRng::Xoshiro256(Xoshiro256::seed_from_u64(seed)),
+            RngAlgorithm::Chacha20 => {
+                StreamRng::Chacha20(Box::new(ChaCha20Rng::seed_from_u64(seed)))
+            }
+            RngAlgorithm::AesCtr => {
+                let mut key = [0u8; 16];
+                let mut state = seed;
+                key[..8].copy_from_slice(&splitmix64(&mut state).to_le_bytes());
+                key[8..].copy_from_slice(&splitmix64(&mut state).to_le_bytes());
+                StreamRng::AesCtr(Box::new(AesCtr::new(key)))
+            }
+        }
+    }
+}
+
+/// A random generator able to jump to any position of its output
+#[derive(Debug)]
+pub enum StreamRng {
+    Pcg64(Pcg64Mcg),
+    Xoshiro256(Xoshiro256),
+    Chacha20(Box<ChaCha20Rng>),
+    AesCtr(Box<AesCtr>),
+    Regions(Box<RegionRng>),
+}
+
+impl SeekableRng for StreamRng {
+    fn fill_bytes(&mut self, buffer: &mut [u8]) {
+        match self {
+            StreamRng::Pcg64(rng) => rng.fill_bytes(buffer),
+            StreamRng::Xoshiro256(rng) => rng.fill_bytes(buffer),
+            StreamRng::Chacha20(rng) => rng.fill_bytes(buffer),
+            StreamRng::AesCtr(rng) => rng.fill_bytes(buffer),
+            StreamRng::Regions(rng) => rng.fill_bytes(buffer),
+        }
+    }
+
+    fn advance(&mut self, bytes: u64) {
+        match self {
+            StreamRng::Pcg64(rng) => rng.advance((bytes / 8).into()),
+            StreamRng::Xoshiro256(rng) => rng.advance(bytes / 8),
+            StreamRng::Chacha20(rng) => rng.set_word_pos(rng.get_word_pos() + bytes as u128 / 4),
+            StreamRng::AesCtr(rng) => rng.advance(bytes.into()),
+            StreamRng::Regions(rng) => rng.advance(bytes),
+        }
+    }
+}
+
+/// A random generator switching to the seed of each region of the stream, with `--region-size`
+///
+/// The output of each region starts at the start of the output of its seed, so a region is
+/// regenerated from its seed alone. A fill never spans two regions, the regions holding whole
+/// chunks.
+#[derive(Debug)]
+pub struct RegionRng {
+    algorithm: RngAlgorithm,
+    seed: Seed,
+    region_bytes: u64,
+    /// The position in the output of the whole stream
+    position: u64,
+    /// The region `rng` generates
+    region: u64,
+    rng: StreamRng,
+}
+
+impl SeekableRng for RegionRng {
+    fn fill_bytes(&mut self, buffer: &mut [u8]) {
+        let region = self.position / self.region_bytes;
+        if region != self.region {
+            self.rng = self.algorithm.rng(self.seed.for_region(region));
+            self.rng.advance(self.position % self.region_bytes);
+            self.region = region;
+        }
+        self.rng.fill_bytes(buffer);
+        self.position += buffer.len() as u64;
+    }
+
+    fn advance(&mut self, bytes: u64) {
+        self.position += bytes;
+        // the generator of another region is only created when filling
+        if self.position / self.region_bytes == self.region {
+            self.rng.advance(bytes);
+        }
+    }
+}
+
+/// Expand a seed into a larger state
+pub(crate) fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+#[test]
+fn advance_matches_sequential_generation() {
+    for algorithm in RngAlgorithm::value_variants() {
+        let mut sequential = algorithm.rng(Seed::U64(42));
+        let mut skipped = vec![0u8; 8 * 1000];
+        sequential.fill_bytes(&mut skipped);
+        let mut expected = vec![0u8; 1024];
+        sequential.fill_bytes(&mut expected);
+
+        let mut rng = algorithm.rng(Seed::U64(42));
+        rng.fill_bytes(&mut skipped[..8 * 3]);
+        rng.advance(8 * (1000 - 3));
+        let mut buffer = vec![0u8; 1024];
+        rng.fill_bytes(&mut buffer);
+        assert_eq!(buffer, expected, "{algorithm:?}");
+    }
+}
+
+#[test]
+fn regions_with_their_own_seed() {
+    let algorithm = RngAlgorithm::Xoshiro256;
+    let seed = Seed::U64(42);
+    let output = |rng: &mut StreamRng, len| {
+        let mut buffer = vec![0u8; len];
+        rng.fill_bytes(&mut buffer);
+        buffer
+    };
+    let mut rng = algorithm.region_rng(seed, 64);
+    let first = output(&mut rng, 64);
+    assert_eq!(first, output(&mut algorithm.rng(seed), 64));
+    let second = output(&mut rng, 64);
+    assert_eq!(second, output(&mut algorithm.rng(seed.for_region(1)), 64));
+
+    // jumping into a region, and within it
+    let mut rng = algorithm.region_rng(seed, 64);
+    rng.advance(64 + 16);
+    assert_eq!(output(&mut rng, 16), second[16..32]);
+    rng.advance(8);
+    assert_eq!(output(&mut rng, 8), second[40..48]);
+    assert_ne!(seed.for_region(1), seed.for_region(2));
+}
+
+#[test]
+fn parse_seed() {
+    assert_eq!(Seed::parse("42"), Ok(Seed::U64(42)));
+    // the same value always gives the same seed
+    assert_eq!(Seed::parse("0x2a"), Ok(Seed::U64(42)));
+    assert_eq!(Seed::parse("0x000000000000000000002a"), Ok(Seed::U64(42)));
+    let long = "0x0102030405060708090a0b0c0d0e0f10";
+    let seed = Seed::parse(long).unwrap();
+    assert!(matches!(seed, Seed::Bytes(_)));
+    assert_eq!(seed.to_string(), format!("0x{:0>64}", &long[2..]));
+    assert!(Seed::parse("0x").is_err());
+    assert!(Seed::parse("0xfoo").is_err());
+    assert!(Seed::parse(&format!("0x1{}", "0".repeat(64))).is_err());
+    assert_ne!(Seed::from_string("ticket-1234"), Seed::from_string("ticket-1235"));
+}
+
+#[test]
+fn pass_seeds() {
+    let seed = Seed::U64(42);
+    assert_eq!(seed.for_pass(0), seed);
+    assert_ne!(seed.for_pass(1), seed);
+    assert_ne!(seed.for_pass(1), seed.for_pass(2));
+    assert_eq!(seed.for_pass(1), Seed::U64(42).for_pass(1));
+    assert_eq!(seed.for_device(0), seed);
+    assert_ne!(seed.for_device(1), seed.for_pass(1));
+    assert_ne!(seed.for_device(1), seed.for_device(2));
+}
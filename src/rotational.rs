@@ -0,0 +1,51 @@
+//! The rotational devices, read or written by a single thread unless `--jobs` is given
+//!
+//! The threads work on distant regions of the stream: on a hard disk, the heads would keep
+//! seeking between them, and the throughput would collapse.
+
+use std::path::Path;
+
+/// The file or device is on a rotational device, as told by sysfs
+#[cfg(target_os = "linux")]
+pub(crate) fn is_rotational(file: &Path) -> bool {
+    use std::os::unix::fs::{FileTypeExt as _, MetadataExt as _};
+
+    let Ok(metadata) = std::fs::metadata(file) else { return false };
+    let dev = match metadata.file_type().is_block_device() {
+        true => metadata.rdev(),
+        false => metadata.dev(),
+    };
+    let device = format!("/sys/dev/block/{}:{}", nix::libc::major(dev), nix::libc::minor(dev));
+    queue_rotational(Path::new(&device))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn is_rotational(_file: &Path) -> bool {
+    false
+}
+
+/// The `rotational` attribute of the queue of the device, in its sysfs directory
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn queue_rotational(device: &Path) -> bool {
+    // a partition shares the queue of its disk
+    ["queue", "../queue"]
+        .iter()
+        .find_map(|queue| std::fs::read_to_string(device.join(queue).join("rotational")).ok())
+        .is_some_and(|value| value.trim() == "1")
+}
+
+#[test]
+fn rotational_from_sysfs() {
+    use std::fs;
+
+    let dir = tempfile::tempdir().unwrap();
+    let disk = dir.path().join("sda");
+    fs::create_dir_all(disk.join("queue")).unwrap();
+    fs::create_dir_all(disk.join("sda1")).unwrap();
+    assert!(!queue_rotational(&disk));
+    fs::write(disk.join("queue/rotational"), "0\n").unwrap();
+    assert!(!queue_rotational(&disk.join("sda1")));
+    fs::write(disk.join("queue/rotational"), "1\n").unwrap();
+    assert!(queue_rotational(&disk));
+    assert!(queue_rotational(&disk.join("sda1")));
+}
@@ -0,0 +1,378 @@
+//! Validate a set of devices and files periodically, as a daemon, with `randstream scrub`
+//!
+//! ```toml
+//! [defaults]
+//! bwlimit = "100Mi"
+//!
+//! [targets."/dev/sdb"]
+//! seed = 42
+//!
+//! [targets."/srv/images/disk.img"]
+//! incremental = "/var/lib/randstream/disk.img.state"
+//! incremental-size = "1Ti"
+//! ```
+//!
+//! The targets file has the format of the configuration file, with the options of validate. The
+//! targets are validated one after the other, each one once per period. The time and the result
+//! of the last validation of each target are saved in the state file, so a restarted daemon
+//! resumes the schedule instead of validating all the targets again.
+
+use clap::{Args, Command, FromArgMatches as _, ValueHint};
+use log::{error, info, warn};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Write as _};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crate::cli::parse_seconds;
+use crate::config;
+use crate::devices;
+use crate::error::{Error, exit_code, usage};
+use crate::report::{self, OutputFormat, Report};
+use crate::scrub::now;
+use crate::systemd;
+use crate::validate::{ValidateArgs, validate_stream};
+
+const MAGIC: &str = "randstream-schedule 1";
+
+/// How often the cancel flag is checked, between the runs
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Validate the targets of a targets file periodically, until interrupted
+///
+/// A failing target doesn't stop the daemon: its result is logged, and passed to the hook and the
+/// metrics. The bandwidth of each validation is limited with the `bwlimit` option of the targets.
+#[derive(Args, Debug)]
+pub struct ScrubArgs {
+    /// The targets file, with a `[targets."path"]` section of validate options per target
+    #[clap(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub targets: PathBuf,
+
+    /// How often each target is validated: hourly, daily, weekly, monthly, or a duration like
+    /// `12h`, `3d` or `90m`
+    #[clap(long, default_value = "weekly", value_parser = parse_schedule)]
+    pub schedule: u64,
+
+    /// Save the time and the result of the last validation of each target to this file
+    ///
+    /// Without it, all the targets are validated when the daemon starts.
+    #[clap(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub state: Option<PathBuf>,
+
+    /// Validate the targets due once, then exit, like from a timer
+    #[clap(long)]
+    pub once: bool,
+
+    /// Run this shell command after the validation of each target
+    ///
+    /// The result is in the environment: RANDSTREAM_TARGET, RANDSTREAM_EXIT_CODE,
+    /// RANDSTREAM_BYTES, RANDSTREAM_CORRUPTED_CHUNKS, and RANDSTREAM_ERROR on a failure.
+    #[clap(long, value_name = "COMMAND")]
+    pub hook: Option<String>,
+
+    /// Write the result of the last validation of each target to this file, in the Prometheus
+    /// text format, like for the textfile collector of the node exporter
+    #[clap(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub metrics: Option<PathBuf>,
+
+    /// The format of the results of each run
+    #[clap(long, value_enum, default_value_t)]
+    pub output: OutputFormat,
+}
+
+/// The period of a schedule, in seconds
+fn parse_schedule(s: &str) -> Result<u64, String> {
+    let seconds = match s {
+        "hourly" => 3600,
+        "daily" => 86400,
+        "weekly" => 7 * 86400,
+        "monthly" => 30 * 86400,
+        _ => parse_seconds(s)?,
+    };
+    match seconds {
+        0 => Err("the schedule period can't be 0".to_string()),
+        seconds => Ok(seconds),
+    }
+}
+
+/// The outcome of the last validation of a target
+#[derive(Clone, Debug, PartialEq)]
+struct LastRun {
+    /// When it started, in seconds since the epoch
+    time: u64,
+    exit_code: i32,
+    bytes: u64,
+    /// In milliseconds
+    duration: u64,
+    corrupted_chunks: u64,
+}
+
+/// The last validation of each target, saved between the runs
+#[derive(Debug, Default, PartialEq)]
+struct ScheduleState {
+    runs: BTreeMap<PathBuf, LastRun>,
+}
+
+impl ScheduleState {
+    fn load(path: &Path) -> anyhow::Result<ScheduleState> {
+        match fs::read_to_string(path) {
+            Ok(content) => Self::decode(&content)
+                .ok_or_else(|| usage(format!("The scrub state {} is invalid", path.display()))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(ScheduleState::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn decode(content: &str) -> Option<ScheduleState> {
+        let mut lines = content.lines();
+        if lines.next()? != MAGIC {
+            return None;
+        }
+        let mut runs = BTreeMap::new();
+        for line in lines {
+            let mut fields = line.strip_prefix("target ")?.splitn(6, ' ');
+            let mut next = || fields.next();
+            let run = LastRun {
+                time: next()?.parse().ok()?,
+                exit_code: next()?.parse().ok()?,
+                bytes: next()?.parse().ok()?,
+                duration: next()?.parse().ok()?,
+                corrupted_chunks: next()?.parse().ok()?,
+            };
+            runs.insert(PathBuf::from(next()?), run);
+        }
+        Some(ScheduleState { runs })
+    }
+
+    fn encode(&self) -> String {
+        let mut content = format!("{MAGIC}\n");
+        for (target, run) in &self.runs {
+            content.push_str(&format!(
+                "target {} {} {} {} {} {}\n",
+                run.time,
+                run.exit_code,
+                run.bytes,
+                run.duration,
+                run.corrupted_chunks,
+                target.display()
+            ));
+        }
+        content
+    }
+
+    /// When the target is due, in seconds since the epoch
+    fn next_run(&self, target: &Path, period: u64) -> u64 {
+        self.runs.get(target).map_or(0, |run| run.time + period)
+    }
+
+    /// The metrics of the last validation of each target, in the Prometheus text format
+    fn metrics(&self) -> String {
+        type Metric = (&'static str, &'static str, fn(&LastRun) -> String);
+        let metrics: [Metric; 5] = [
+            ("last_run_timestamp_seconds", "When the last validation started", |r| {
+                r.time.to_string()
+            }),
+            ("exit_code", "The exit code of the last validation, 0 if it passed", |r| {
+                r.exit_code.to_string()
+            }),
+            ("bytes", "The bytes read by the last validation", |r| r.bytes.to_string()),
+            ("duration_seconds", "The duration of the last validation", |r| {
+                format!("{:.3}", r.duration as f64 / 1000.0)
+            }),
+            ("corrupted_chunks", "The corrupted chunks found by the last validation", |r| {
+                r.corrupted_chunks.to_string()
+            }),
+        ];
+        let mut content = String::new();
+        for (name, help, value) in metrics {
+            content.push_str(&format!("# HELP randstream_scrub_{name} {help}\n"));
+            content.push_str(&format!("# TYPE randstream_scrub_{name} gauge\n"));
+            for (target, run) in &self.runs {
+                let target = report::quote(&target.display().to_string());
+                content.push_str(&format!(
+                    "randstream_scrub_{name}{{target={target}}} {}\n",
+                    value(run)
+                ));
+            }
+        }
+        content
+    }
+}
+
+/// Replace the file atomically
+fn save(path: &Path, content: &str) -> io::Result<()> {
+    let mut tmp = path.to_path_buf().into_os_string();
+    tmp.push(".tmp");
+    let mut f = File::create(&tmp)?;
+    f.write_all(content.as_bytes())?;
+    f.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+/// The validate arguments of each target of the targets file
+fn targets(path: &Path) -> anyhow::Result<Vec<(PathBuf, ValidateArgs)>> {
+    config::targets(path, "validate")?
+        .into_iter()
+        .map(|(target, mut options)| {
+            options.push(target.display().to_string());
+            let matches = ValidateArgs::augment_args(Command::new("validate"))
+                .no_binary_name(true)
+                .try_get_matches_from(options)
+                .and_then(|matches| ValidateArgs::from_arg_matches(&matches));
+            let mut args = matches.map_err(|e| {
+                // without the usage of the command line
+                let e = e.to_string();
+                let lines = e.lines().take_while(|line| !line.starts_with("Usage:"));
+                let e = lines.map(str::trim).filter(|line| !line.is_empty()).collect::<Vec<_>>();
+                let e = e.join(" ").trim_start_matches("error: ").to_string();
+                usage(format!("Invalid options for the target {}: {e}", target.display()))
+            })?;
+            // logged, not drawn
+            args.common.no_progress = true;
+            Ok((target, args))
+        })
+        .collect()
+}
+
+pub fn scrub(args: &ScrubArgs, cancel: Arc<AtomicBool>) -> Result<i32, Error> {
+    scrub_targets(args, cancel).map_err(Error::from)
+}
+
+fn scrub_targets(args: &ScrubArgs, cancel: Arc<AtomicBool>) -> anyhow::Result<i32> {
+    let targets = targets(&args.targets)?;
+    let mut state = match &args.state {
+        Some(path) => ScheduleState::load(path)?,
+        None => ScheduleState::default(),
+    };
+    info!("scrubbing {} targets, every {}s", targets.len(), args.schedule);
+    loop {
+        let start = now();
+        let mut results = Vec::new();
+        for (target, validate) in &targets {
+            if cancel.load(Ordering::Relaxed) {
+                break;
+            }
+            if state.next_run(target, args.schedule) > start {
+                continue;
+            }
+            info!("validating {}", target.display());
+            systemd::status(&format!("validating {}", target.display()));
+            let time = now();
+            let mut report = Report::new("validate", args.output);
+            let result = validate_stream(validate, cancel.clone(), &mut report);
+            let exit_code = report.record(&result);
+            if exit_code != exit_code::INTERRUPTED {
+                let stats = report.stats();
+                let run = LastRun {
+                    time,
+                    exit_code,
+                    bytes: stats.bytes,
+                    duration: stats.elapsed.as_millis() as u64,
+                    corrupted_chunks: report.corrupted.len() as u64,
+                };
+                state.runs.insert(target.clone(), run);
+                if let Some(path) = &args.state {
+                    save(path, &state.encode())?;
+                }
+                if let Some(path) = &args.metrics {
+                    save(path, &state.metrics())?;
+                }
+                if let Some(hook) = &args.hook {
+                    run_hook(hook, target, &state.runs[target], report.errors.first());
+                }
+            }
+            results.push((target.display().to_string(), report, exit_code));
+        }
+        if args.output == OutputFormat::Json {
+            println!("{}", report::devices_json("scrub", &results));
+        } else if !results.is_empty() {
+            devices::log_table("target", &results);
+        }
+        if args.once || cancel.load(Ordering::Relaxed) {
+            let interrupted = results.iter().any(|(_, _, code)| *code == exit_code::INTERRUPTED);
+            return Ok(match (args.once, interrupted) {
+                (_, true) => exit_code::INTERRUPTED,
+                (true, false) => {
+                    results.iter().map(|(_, _, code)| *code).find(|code| *code != 0).unwrap_or(0)
+                }
+                (false, false) => 0,
+            });
+        }
+
+        let (next, target) = targets
+            .iter()
+            .map(|(target, _)| (state.next_run(target, args.schedule), target))
+            .min()
+            .expect("at least one target");
+        info!("next validation in {}s, of {}", next.saturating_sub(now()), target.display());
+        systemd::status(&format!("idle, next validation of {}", target.display()));
+        while now() < next {
+            systemd::watchdog();
+            if cancel.load(Ordering::Relaxed) {
+                return Ok(0);
+            }
+            thread::sleep(IDLE_POLL_INTERVAL);
+        }
+    }
+}
+
+/// Run the hook with the result of a target in its environment, logging its failure
+fn run_hook(hook: &str, target: &Path, run: &LastRun, error: Option<&String>) {
+    #[cfg(unix)]
+    let mut command = process::Command::new("sh");
+    #[cfg(unix)]
+    command.arg("-c");
+    #[cfg(windows)]
+    let mut command = process::Command::new("cmd");
+    #[cfg(windows)]
+    command.arg("/C");
+    command
+        .arg(hook)
+        .env("RANDSTREAM_TARGET", target)
+        .env("RANDSTREAM_EXIT_CODE", run.exit_code.to_string())
+        .env("RANDSTREAM_BYTES", run.bytes.to_string())
+        .env("RANDSTREAM_CORRUPTED_CHUNKS", run.corrupted_chunks.to_string());
+    if let Some(error) = error {
+        command.env("RANDSTREAM_ERROR", error);
+    }
+    match command.status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => warn!("the hook failed for {}: {status}", target.display()),
+        Err(e) => error!("can't run the hook for {}: {e}", target.display()),
+    }
+}
+
+#[test]
+fn schedules() {
+    assert_eq!(parse_schedule("weekly"), Ok(604800));
+    assert_eq!(parse_schedule("12h"), Ok(43200));
+    assert_eq!(parse_schedule("90"), Ok(90));
+    assert!(parse_schedule("0d").is_err());
+    assert!(parse_schedule("3y").is_err());
+    assert!(parse_schedule("h").is_err());
+}
+
+#[test]
+fn schedule_state_round_trip() {
+    let mut state = ScheduleState::default();
+    let run =
+        LastRun { time: 1000, exit_code: 0, bytes: 4096, duration: 1500, corrupted_chunks: 0 };
+    state.runs.insert(PathBuf::from("/srv/disk image.bin"), run.clone());
+    state
+        .runs
+        .insert(PathBuf::from("/dev/sdb"), LastRun { exit_code: 3, corrupted_chunks: 2, ..run });
+    assert_eq!(
+        state.encode(),
+        "randstream-schedule 1\ntarget 1000 3 4096 1500 2 /dev/sdb\n\
+         target 1000 0 4096 1500 0 /srv/disk image.bin\n"
+    );
+    assert_eq!(ScheduleState::decode(&state.encode()), Some(state));
+    assert_eq!(ScheduleState::decode("randstream-schedule 1\ntarget 1000 0\n"), None);
+    let state = ScheduleState::decode("randstream-schedule 1\n").unwrap();
+    assert_eq!(state.next_run(Path::new("/dev/sdb"), 60), 0);
+}
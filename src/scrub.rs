@@ -0,0 +1,145 @@
+//! The state of an incremental validation, with `validate --incremental`
+//!
+//! The state file records when each chunk of the stream was last validated, as ranges of chunks
+//! validated at the same time. Each run validates the chunks not validated within the window, the
+//! least recently validated first, so a large stream can be scrubbed a part at a time.
+
+use std::fs::{self, File};
+use std::io::{self, Write as _};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::usage;
+
+const MAGIC: &str = "randstream-scrub 1";
+
+/// The time since the epoch, in seconds
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// When each chunk of the stream was last validated
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ScrubState {
+    path: PathBuf,
+    /// Identifies the stream, so the state isn't used with other parameters
+    stream: String,
+    /// The time each chunk was last validated, in seconds since the epoch, or 0 if never
+    times: Vec<u64>,
+}
+
+impl ScrubState {
+    /// Read the state, or start a new one if the file doesn't exist
+    pub fn load(path: &Path, stream: String, num_chunks: u64) -> anyhow::Result<ScrubState> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let times = vec![0; num_chunks as usize];
+                return Ok(ScrubState { path: path.to_path_buf(), stream, times });
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let (saved, times) = decode(&content, num_chunks)
+            .ok_or_else(|| usage(format!("The incremental state {} is invalid", path.display())))?;
+        if saved != stream {
+            return Err(usage(format!(
+                "The incremental state {} was saved for another stream",
+                path.display()
+            )));
+        }
+        Ok(ScrubState { path: path.to_path_buf(), stream, times })
+    }
+
+    /// The chunks not validated within `window` seconds before `now`, the least recently
+    /// validated first
+    pub fn due(&self, now: u64, window: u64) -> Vec<u64> {
+        let mut chunks: Vec<_> = (0..self.times.len() as u64)
+            .filter(|chunk| now.saturating_sub(self.times[*chunk as usize]) >= window)
+            .collect();
+        chunks.sort_by_key(|chunk| self.times[*chunk as usize]);
+        chunks
+    }
+
+    /// Record the chunks as validated at `now`
+    pub fn record(&mut self, chunks: impl IntoIterator<Item = u64>, now: u64) {
+        for chunk in chunks {
+            self.times[chunk as usize] = now;
+        }
+    }
+
+    fn encode(&self) -> String {
+        let mut content = format!("{MAGIC}\nstream {}\nchunks {}\n", self.stream, self.times.len());
+        let mut first = 0;
+        for chunk in 1..=self.times.len() {
+            if chunk == self.times.len() || self.times[chunk] != self.times[first] {
+                if self.times[first] != 0 {
+                    content.push_str(&format!("range {first} {chunk} {}\n", self.times[first]));
+                }
+                first = chunk;
+            }
+        }
+        content
+    }
+
+    /// Save the state, replacing the previous one atomically
+    pub fn save(&self) -> io::Result<()> {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let mut f = File::create(&tmp)?;
+        f.write_all(self.encode().as_bytes())?;
+        f.sync_all()?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+/// The stream and the time of each chunk of a saved state
+fn decode(content: &str, num_chunks: u64) -> Option<(String, Vec<u64>)> {
+    let mut lines = content.lines();
+    if lines.next()? != MAGIC {
+        return None;
+    }
+    let stream = lines.next()?.strip_prefix("stream ")?.to_string();
+    let chunks: u64 = lines.next()?.strip_prefix("chunks ")?.parse().ok()?;
+    if chunks != num_chunks {
+        // another stream, reported as such
+        return Some((String::new(), Vec::new()));
+    }
+    let mut times = vec![0; num_chunks as usize];
+    for line in lines {
+        let values: Vec<u64> = line
+            .strip_prefix("range ")?
+            .split(' ')
+            .map(|v| v.parse().ok())
+            .collect::<Option<_>>()?;
+        let [first, end, time] = values.as_slice() else {
+            return None;
+        };
+        if first >= end || *end > num_chunks {
+            return None;
+        }
+        times[*first as usize..*end as usize].fill(*time);
+    }
+    Some((stream, times))
+}
+
+#[test]
+fn scrub_state_round_trip() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let path = dir.path().join("state");
+    let mut state = ScrubState::load(&path, "validate size=1000".to_string(), 10).unwrap();
+    assert_eq!(state.due(1000, 100), (0..10).collect::<Vec<_>>());
+    state.record(2..5, 900);
+    state.record([7], 950);
+    assert_eq!(state.due(1000, 200), vec![0, 1, 5, 6, 8, 9]);
+    // the least recently validated first
+    assert_eq!(state.due(1000, 60), vec![0, 1, 5, 6, 8, 9, 2, 3, 4]);
+    assert_eq!(
+        state.encode(),
+        "randstream-scrub 1\nstream validate size=1000\nchunks 10\nrange 2 5 900\nrange 7 8 950\n"
+    );
+    state.save().unwrap();
+    assert_eq!(ScrubState::load(&path, "validate size=1000".to_string(), 10).unwrap(), state);
+    assert!(ScrubState::load(&path, "validate size=2000".to_string(), 10).is_err());
+    assert!(ScrubState::load(&path, "validate size=1000".to_string(), 20).is_err());
+    assert_eq!(decode("randstream-scrub 1\nstream s\nchunks 4\nrange 3 5 1\n", 4), None);
+}
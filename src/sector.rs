@@ -0,0 +1,70 @@
+//! The checksums of the sectors of each chunk, with `--sector-checksums`
+//!
+//! Each sector of the chunk ends with the CRC32 of the rest of the sector, written over the random
+//! data before the chunk is sealed by its checksum. The last sector ends before the checksum of
+//! the chunk. When a chunk doesn't match its checksum, the sectors not matching theirs tell where
+//! it's damaged.
+
+use std::ops::Range;
+
+use parse_size::parse_size;
+
+/// The size of the checksum at the end of each sector
+pub const SECTOR_CHECKSUM_SIZE: usize = 4;
+
+/// Parse the size of the sectors, 512 or 4 KiB
+pub fn parse_sector_size(s: &str) -> Result<usize, String> {
+    match parse_size(s).map_err(|e| e.to_string())? {
+        size @ (512 | 4096) => Ok(size as usize),
+        _ => Err("the sector size must be 512 or 4Ki".to_string()),
+    }
+}
+
+/// The sectors of the payload of a chunk holding a checksum
+///
+/// A sector too short to hold anything besides its checksum, at the end of the stream, has none.
+fn sectors(length: usize, sector_size: usize) -> impl Iterator<Item = Range<usize>> {
+    (0..length)
+        .step_by(sector_size)
+        .map(move |start| start..(start + sector_size).min(length))
+        .filter(|sector| sector.len() > SECTOR_CHECKSUM_SIZE)
+}
+
+/// Write the checksum at the end of each sector of the payload of a chunk
+pub fn seal(payload: &mut [u8], sector_size: usize) {
+    for sector in sectors(payload.len(), sector_size) {
+        let end = sector.end - SECTOR_CHECKSUM_SIZE;
+        let crc = crc32fast::hash(&payload[sector.start..end]);
+        payload[end..sector.end].copy_from_slice(&crc.to_le_bytes());
+    }
+}
+
+/// The ranges of the payload of a chunk covered by the sectors not matching their checksum
+pub fn damaged(payload: &[u8], sector_size: usize) -> Vec<Range<u64>> {
+    sectors(payload.len(), sector_size)
+        .filter(|sector| {
+            let end = sector.end - SECTOR_CHECKSUM_SIZE;
+            let crc = u32::from_le_bytes(payload[end..sector.end].try_into().unwrap());
+            crc != crc32fast::hash(&payload[sector.start..end])
+        })
+        .map(|sector| sector.start as u64..sector.end as u64)
+        .collect()
+}
+
+#[test]
+fn damaged_sectors() {
+    let mut payload: Vec<u8> = (0..4096 - 4).map(|i| (i * 7 % 251) as u8).collect();
+    seal(&mut payload, 512);
+    assert_eq!(damaged(&payload, 512), Vec::<Range<u64>>::new());
+    payload[1000] ^= 1;
+    payload[4090] ^= 1;
+    assert_eq!(damaged(&payload, 512), vec![512..1024, 3584..4092]);
+    assert_eq!(damaged(&payload, 4096), vec![0..4092]);
+
+    // the last sector of the stream too short to hold a checksum
+    let mut payload = vec![1u8; 516];
+    seal(&mut payload, 512);
+    assert_eq!(&payload[512..], &[1, 1, 1, 1]);
+    assert_eq!(parse_sector_size("4Ki"), Ok(4096));
+    assert!(parse_sector_size("1Ki").is_err());
+}
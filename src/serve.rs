@@ -0,0 +1,225 @@
+//! Validate the streams sent by several senders at once, as a sink for a lab
+
+use clap::Args;
+use log::{error, info};
+use std::fmt;
+use std::io::{self, Write as _};
+use std::net::{SocketAddr, TcpStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, ScopedJoinHandle};
+
+use crate::cli::AUTO_CHUNK_SIZE;
+use crate::devices;
+use crate::error::{Error, exit_code, usage};
+use crate::net::{self, ACCEPT_POLL_INTERVAL, Endpoint};
+use crate::report::{self, OutputFormat, Report};
+use crate::sumfile::ChecksumFile;
+use crate::systemd;
+use crate::validate::{ValidateArgs, validate_connection};
+
+/// Listen for the streams sent with `randstream send tcp://host:port`, and validate each one
+///
+/// The connections are validated independently, several at once: a corrupted stream doesn't stop
+/// the others. The result of each connection is logged when it ends, and all of them in a table
+/// when the server stops. It's also sent back to the sender on a line, read by `randstream perf`.
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    /// The address to listen on, like `:9000` or `192.168.1.1:9000`
+    #[clap(long)]
+    pub listen: String,
+
+    /// Stop after validating that number of streams, instead of serving until interrupted
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub connections: Option<u64>,
+
+    #[clap(flatten)]
+    pub validate: ValidateArgs,
+}
+
+pub fn serve(args: &ServeArgs, cancel: Arc<AtomicBool>) -> Result<i32, Error> {
+    let loaded;
+    let validate = match &args.validate.checksum_file {
+        // the streams of all the senders are expected to match it
+        Some(path) => {
+            loaded = ChecksumFile::load(path)?.validate_args(&args.validate);
+            &loaded
+        }
+        None => &args.validate,
+    };
+    if validate.file.is_some() {
+        return Err(usage("serve receives the streams on --listen, it takes no file").into());
+    }
+    net::reject_options(
+        "serve",
+        &[
+            ("--checkpoint", validate.checkpoint.is_some()),
+            ("--shard-size", validate.shard_size.is_some()),
+            ("--sample", validate.sample.is_some()),
+            ("--sample-chunks", validate.sample_chunks.is_some()),
+            ("--error-map", validate.corruption.error_map.is_some()),
+            ("--badblocks-out", validate.corruption.badblocks_out.is_some()),
+            ("--print-checksum", validate.common.print_checksum),
+            ("--chunk-size auto", validate.common.chunk_size == AUTO_CHUNK_SIZE),
+        ],
+    )?;
+    let address = format!("tcp://{}", args.listen);
+    let endpoint = Endpoint::parse(Path::new(&address))?.unwrap();
+    let listener = endpoint.listen()?;
+    systemd::ready();
+    systemd::status(&format!("listening on {}", args.listen));
+    let output = validate.common.output;
+
+    let results = thread::scope(|scope| -> io::Result<Vec<(String, Report, i32)>> {
+        let mut connections: Vec<(SocketAddr, Arc<AtomicBool>, ScopedJoinHandle<_>)> = Vec::new();
+        loop {
+            systemd::watchdog();
+            if cancel.load(Ordering::Relaxed) {
+                connections.iter().for_each(|(_, c, _)| c.store(true, Ordering::Relaxed));
+            }
+            let accepting = !cancel.load(Ordering::Relaxed)
+                && args.connections.is_none_or(|n| (connections.len() as u64) < n);
+            if !accepting && connections.iter().all(|(_, _, h)| h.is_finished()) {
+                break;
+            }
+            if accepting {
+                match listener.accept() {
+                    Ok((socket, peer)) => {
+                        info!("connected from {peer}");
+                        socket.set_nonblocking(false)?;
+                        let reply = socket.try_clone()?;
+                        // a failing stream doesn't stop the others
+                        let connection_cancel = Arc::new(AtomicBool::new(false));
+                        let c = connection_cancel.clone();
+                        let handle = scope.spawn(move || {
+                            serve_connection(validate, peer, Box::new(socket), reply, c, output)
+                        });
+                        connections.push((peer, connection_cancel, handle));
+                        systemd::status(&format!(
+                            "listening on {}, {} streams received",
+                            args.listen,
+                            connections.len()
+                        ));
+                        continue;
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            thread::sleep(ACCEPT_POLL_INTERVAL);
+        }
+        Ok(connections
+            .into_iter()
+            .map(|(peer, _, handle)| {
+                let (report, exit_code) = handle.join().unwrap();
+                (peer.to_string(), report, exit_code)
+            })
+            .collect())
+    })?;
+
+    if output == OutputFormat::Json {
+        println!("{}", report::devices_json("serve", &results));
+    } else if !results.is_empty() {
+        devices::log_table("sender", &results);
+    }
+    Ok(results.iter().map(|(_, _, code)| *code).find(|code| *code != 0).unwrap_or(0))
+}
+
+/// Validate the stream of a connection, log its result and send it back
+fn serve_connection(
+    args: &ValidateArgs,
+    peer: SocketAddr,
+    input: Box<dyn io::Read>,
+    reply: TcpStream,
+    cancel: Arc<AtomicBool>,
+    output: OutputFormat,
+) -> (Report, i32) {
+    let args = ValidateArgs {
+        common: args.common.for_device(Path::new(&peer.to_string())),
+        ..args.clone()
+    };
+    let mut report = Report::new("validate", output);
+    let result = validate_connection(&args, input, cancel, &mut report);
+    let exit_code = report.record(&result);
+    match &result {
+        Ok(0) => info!("{peer}: pass"),
+        Ok(code) => error!("{peer}: fail, exit code {code}"),
+        Err(e) => error!("{peer}: fail, {e}"),
+    }
+    if exit_code != exit_code::INTERRUPTED {
+        // the rest of a failed stream is discarded, so the sender can read the result
+        let _ = io::copy(&mut &reply, &mut io::sink());
+        let verdict = Verdict::new(&report, exit_code);
+        let _ = (&reply).write_all(format!("{verdict}\n").as_bytes());
+    }
+    (report, exit_code)
+}
+
+/// The result of a stream, as sent back to its sender: `pass <bytes> <checksum>` or
+/// `fail <bytes> <exit code> <reason>`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Verdict {
+    /// The bytes validated
+    pub bytes: u64,
+    pub exit_code: i32,
+    /// The checksum of the stream, or the reason of the failure
+    pub detail: String,
+}
+
+impl Verdict {
+    fn new(report: &Report, exit_code: i32) -> Self {
+        let detail = match (exit_code, report.errors.first()) {
+            (0, _) => report.checksum.clone().unwrap_or_default(),
+            (_, Some(e)) => e.replace('\n', " "),
+            (code, None) => format!("exit code {code}"),
+        };
+        Verdict { bytes: report.bytes, exit_code, detail }
+    }
+
+    /// The verdict sent on the line, if it's one
+    pub(crate) fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.trim_end().splitn(3, ' ');
+        let result = fields.next()?;
+        let bytes = fields.next()?.parse().ok()?;
+        let rest = fields.next().unwrap_or_default();
+        let (exit_code, detail) = match result {
+            "pass" => (0, rest),
+            "fail" => {
+                let (code, reason) = rest.split_once(' ').unwrap_or((rest, ""));
+                (code.parse().ok().filter(|code| *code != 0)?, reason)
+            }
+            _ => return None,
+        };
+        Some(Verdict { bytes, exit_code, detail: detail.to_string() })
+    }
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.exit_code {
+            0 => write!(f, "pass {} {}", self.bytes, self.detail),
+            code => write!(f, "fail {} {code} {}", self.bytes, self.detail),
+        }
+    }
+}
+
+#[test]
+fn verdicts() {
+    let mut report = Report::new("validate", OutputFormat::Text);
+    report.bytes = 1000;
+    report.checksum = Some("1234abcd".to_string());
+    let verdict = Verdict::new(&report, 0);
+    assert_eq!(verdict.to_string(), "pass 1000 1234abcd");
+    assert_eq!(Verdict::parse("pass 1000 1234abcd\n"), Some(verdict));
+    report.errors.push("Invalid checksum\nat chunk 3.".to_string());
+    let verdict = Verdict::new(&report, 2);
+    assert_eq!(verdict.to_string(), "fail 1000 2 Invalid checksum at chunk 3.");
+    assert_eq!(Verdict::parse(&verdict.to_string()), Some(verdict));
+    assert_eq!(
+        Verdict::new(&Report::new("validate", OutputFormat::Text), 130).detail,
+        "exit code 130"
+    );
+    assert_eq!(Verdict::parse("HTTP/1.1 200 OK"), None);
+    assert_eq!(Verdict::parse("fail 1000 0 none"), None);
+}
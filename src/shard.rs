@@ -0,0 +1,109 @@
+//! The streams split across several files, with `--shard-size`
+
+use anyhow::anyhow;
+use parse_size::parse_size;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::error::usage;
+
+/// The files holding the consecutive parts of a stream
+///
+/// They're named after a template holding the index of the file, like `out-%04d.bin`.
+#[derive(Clone, Debug)]
+pub struct Shards {
+    template: String,
+    shard_size: u64,
+}
+
+impl Shards {
+    pub fn new(template: &Path, shard_size: u64, chunk_size: usize) -> anyhow::Result<Self> {
+        let template = template.to_string_lossy().into_owned();
+        if index_placeholder(&template).is_none() {
+            return Err(usage(
+                "With --shard-size, the file name must hold the index of the shards, with %d or \
+                 %0Nd",
+            ));
+        }
+        if !shard_size.is_multiple_of(chunk_size as u64) {
+            return Err(usage(format!(
+                "The shard size {shard_size} must be a multiple of the chunk size {chunk_size}"
+            )));
+        }
+        Ok(Shards { template, shard_size })
+    }
+
+    /// The path of the shard
+    pub fn path(&self, index: u64) -> PathBuf {
+        let (range, width) = index_placeholder(&self.template).unwrap();
+        let mut path = self.template.clone();
+        path.replace_range(range, &format!("{index:0width$}"));
+        PathBuf::from(path)
+    }
+
+    /// The size of the stream held by the consecutive shards found
+    ///
+    /// All the shards but the last one must be full.
+    pub fn stored_size(&self) -> anyhow::Result<u64> {
+        let mut size = 0;
+        for index in 0.. {
+            let path = self.path(index);
+            let metadata = match fs::metadata(&path) {
+                Err(e) if index > 0 && e.kind() == io::ErrorKind::NotFound => break,
+                metadata => metadata?,
+            };
+            if size % self.shard_size != 0 {
+                let previous = self.path(index - 1);
+                return Err(anyhow!("The shard {} is incomplete", previous.display()));
+            }
+            size += metadata.len();
+        }
+        Ok(size)
+    }
+
+    /// The number of shards holding a stream of that size
+    pub fn count(&self, stream_size: u64) -> u64 {
+        stream_size.div_ceil(self.shard_size).max(1)
+    }
+
+    /// The part of the stream held by the shard
+    pub fn range(&self, index: u64, stream_size: u64) -> Range<u64> {
+        let start = index * self.shard_size;
+        start..(start + self.shard_size).min(stream_size)
+    }
+}
+
+/// The position of the `%d` or `%0Nd` placeholder in the template, and the width of the index
+fn index_placeholder(template: &str) -> Option<(Range<usize>, usize)> {
+    let start = template.find('%')?;
+    let spec = &template[start + 1..];
+    let digits = spec.find(|c: char| !c.is_ascii_digit())?;
+    if !spec[digits..].starts_with('d') {
+        return None;
+    }
+    let width = if digits > 0 { spec[..digits].parse().ok()? } else { 0 };
+    Some((start..start + digits + 2, width))
+}
+
+pub fn parse_shard_size(s: &str) -> Result<u64, String> {
+    match parse_size(s) {
+        Ok(0) => Err("the shard size can't be 0".to_string()),
+        size => size.map_err(|e| e.to_string()),
+    }
+}
+
+#[test]
+fn shard_paths_and_ranges() {
+    let shards = Shards::new(Path::new("dir/out-%04d.bin"), 100, 10).unwrap();
+    assert_eq!(shards.path(12), PathBuf::from("dir/out-0012.bin"));
+    assert_eq!(shards.count(250), 3);
+    assert_eq!(shards.count(0), 1);
+    assert_eq!(shards.range(2, 250), 200..250);
+    let shards = Shards::new(Path::new("part%d"), 100, 10).unwrap();
+    assert_eq!(shards.path(7), PathBuf::from("part7"));
+    assert!(Shards::new(Path::new("out.bin"), 100, 10).is_err());
+    assert!(Shards::new(Path::new("out-%x"), 100, 10).is_err());
+    assert!(Shards::new(Path::new("out-%d"), 100, 30).is_err());
+}
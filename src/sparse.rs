@@ -0,0 +1,76 @@
+//! The holes of the sparse files, found with SEEK_DATA and SEEK_HOLE
+
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+
+/// The parts of the range of the file holding data, the rest being holes
+///
+/// The whole range holds data when the file system can't tell where its holes are.
+#[cfg(any(target_os = "linux", target_os = "freebsd", target_os = "macos"))]
+pub fn data_ranges(file: &File, range: Range<u64>) -> io::Result<Vec<Range<u64>>> {
+    use nix::errno::Errno;
+    use nix::unistd::{Whence, lseek};
+
+    let mut ranges = Vec::new();
+    let mut offset = range.start;
+    while offset < range.end {
+        let data = match lseek(file, offset as i64, Whence::SeekData) {
+            Ok(data) => data as u64,
+            // no data after the offset
+            Err(Errno::ENXIO) => break,
+            // a block device, for instance
+            Err(Errno::EINVAL) => return Ok(vec![range]),
+            Err(e) => return Err(io::Error::from_raw_os_error(e as i32)),
+        };
+        if data >= range.end {
+            break;
+        }
+        let hole = lseek(file, data as i64, Whence::SeekHole)
+            .map_err(|e| io::Error::from_raw_os_error(e as i32))? as u64;
+        ranges.push(data..hole.min(range.end));
+        offset = hole;
+    }
+    Ok(ranges)
+}
+
+/// The parts of the range of the file holding data: all of it, without SEEK_DATA
+#[cfg(not(any(target_os = "linux", target_os = "freebsd", target_os = "macos")))]
+pub fn data_ranges(_file: &File, range: Range<u64>) -> io::Result<Vec<Range<u64>>> {
+    Ok(vec![range])
+}
+
+/// The chunks of the stream at `position` holding some data, in order
+pub fn chunks_with_data(ranges: &[Range<u64>], position: u64, chunk_size: u64) -> Vec<u64> {
+    let mut chunks: Vec<u64> = Vec::new();
+    for range in ranges.iter().filter(|r| !r.is_empty()) {
+        let first = (range.start.saturating_sub(position)) / chunk_size;
+        let last = (range.end - 1).saturating_sub(position) / chunk_size;
+        // a chunk may hold the end of a range and the start of the next one
+        let first = chunks.last().map_or(first, |&chunk| first.max(chunk + 1));
+        chunks.extend(first..=last);
+    }
+    chunks
+}
+
+#[test]
+fn chunks_holding_data() {
+    let ranges = [100..200, 4096..8192, 8200..8300, 20000..20001];
+    assert_eq!(chunks_with_data(&ranges, 100, 4096), vec![0, 1, 2, 4]);
+    assert_eq!(chunks_with_data(&[], 0, 4096), Vec::<u64>::new());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn holes_of_a_file() {
+    use std::io::{Seek as _, SeekFrom, Write as _};
+
+    let mut file = tempfile::tempfile().unwrap();
+    file.set_len(4 << 20).unwrap();
+    file.seek(SeekFrom::Start(2 << 20)).unwrap();
+    file.write_all(b"data").unwrap();
+    let ranges = data_ranges(&file, 0..4 << 20).unwrap();
+    assert!(ranges.iter().any(|r| r.contains(&(2 << 20))), "{ranges:?}");
+    let ranges = data_ranges(&file, 3 << 20..4 << 20).unwrap();
+    assert!(ranges.iter().all(|r| r.start >= 3 << 20), "{ranges:?}");
+}
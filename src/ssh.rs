@@ -0,0 +1,147 @@
+//! The streams written to or read from a file of a remote host, given as
+//! `ssh://[user@]host[:port]/path`
+//!
+//! The stream goes through the standard input or output of `cat`, run on the host by the `ssh`
+//! command, so the keys, agent and configuration of the user apply. `RANDSTREAM_SSH` replaces the
+//! `ssh` command.
+
+use std::env;
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use crate::error::usage;
+
+/// The file of the remote host given as file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SshTarget {
+    /// The host, with the user if given
+    destination: String,
+    port: Option<u16>,
+    path: String,
+}
+
+impl SshTarget {
+    /// The remote file given as file, if it's an `ssh://` URI
+    pub fn parse(file: &Path) -> anyhow::Result<Option<Self>> {
+        let Some(rest) = file.to_str().and_then(|f| f.strip_prefix("ssh://")) else {
+            return Ok(None);
+        };
+        let Some((authority, path)) =
+            rest.split_once('/').filter(|(a, p)| !a.is_empty() && !p.is_empty())
+        else {
+            return Err(usage(format!(
+                "The URI {} must be like ssh://[user@]host[:port]/path",
+                file.display()
+            )));
+        };
+        let (destination, port) = match authority.rsplit_once(':') {
+            // an IPv6 address without a port
+            Some((_, port)) if port.ends_with(']') => (authority, None),
+            Some((destination, port)) => {
+                let port = port.parse().map_err(|_| usage(format!("Invalid port {port}")))?;
+                (destination, Some(port))
+            }
+            None => (authority, None),
+        };
+        let destination = destination.replace(['[', ']'], "");
+        Ok(Some(SshTarget { destination, port, path: format!("/{path}") }))
+    }
+
+    /// The ssh command running the shell command on the host
+    fn command(&self, remote: &str) -> Command {
+        let ssh = env::var("RANDSTREAM_SSH").unwrap_or_else(|_| "ssh".to_string());
+        let mut command = Command::new(ssh);
+        if let Some(port) = self.port {
+            command.arg("-p").arg(port.to_string());
+        }
+        command.arg("--").arg(&self.destination).arg(remote);
+        command
+    }
+
+    /// Write the data to the remote file
+    pub fn write(&self) -> io::Result<(ChildStdin, RemoteCommand)> {
+        let command = format!("cat > {}", quote(&self.path));
+        let mut child =
+            self.command(&command).stdin(Stdio::piped()).spawn().map_err(spawn_error)?;
+        let stdin = child.stdin.take().unwrap();
+        Ok((stdin, RemoteCommand { child }))
+    }
+
+    /// Read the data of the remote file, from the position
+    pub fn read(&self, position: u64) -> io::Result<RemoteReader> {
+        let command = match position {
+            0 => format!("cat {}", quote(&self.path)),
+            position => format!("tail -c +{} {}", position + 1, quote(&self.path)),
+        };
+        let mut child =
+            self.command(&command).stdout(Stdio::piped()).spawn().map_err(spawn_error)?;
+        let stdout = child.stdout.take().unwrap();
+        Ok(RemoteReader { stdout, command: Some(RemoteCommand { child }) })
+    }
+}
+
+/// The command run on the remote host
+pub struct RemoteCommand {
+    child: Child,
+}
+
+impl RemoteCommand {
+    /// Wait for the command to exit, once its input is closed
+    pub fn finish(mut self) -> io::Result<()> {
+        let status = self.child.wait()?;
+        if !status.success() {
+            return Err(io::Error::other(format!("The remote command failed: {status}")));
+        }
+        Ok(())
+    }
+}
+
+/// The output of the remote command, ending with an error if it failed
+pub struct RemoteReader {
+    stdout: ChildStdout,
+    command: Option<RemoteCommand>,
+}
+
+impl Read for RemoteReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.stdout.read(buf)?;
+        if size == 0
+            && !buf.is_empty()
+            && let Some(command) = self.command.take()
+        {
+            command.finish()?;
+        }
+        Ok(size)
+    }
+}
+
+fn spawn_error(e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), format!("Can't run ssh: {e}"))
+}
+
+/// Quote the argument for the remote shell
+fn quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+#[test]
+fn parse_ssh_targets() {
+    let parse = |s: &str| SshTarget::parse(Path::new(s));
+    assert_eq!(parse("out.bin").unwrap(), None);
+    let target = parse("ssh://root@host/dev/sdb").unwrap().unwrap();
+    assert_eq!(
+        target,
+        SshTarget {
+            destination: "root@host".to_string(),
+            port: None,
+            path: "/dev/sdb".to_string()
+        }
+    );
+    let target = parse("ssh://host:2222/tmp/it's").unwrap().unwrap();
+    assert_eq!((target.destination.as_str(), target.port), ("host", Some(2222)));
+    assert_eq!(quote(&target.path), r"'/tmp/it'\''s'");
+    assert_eq!(parse("ssh://[::1]:22/x").unwrap().unwrap().destination, "::1");
+    assert!(parse("ssh://host").is_err());
+    assert!(parse("ssh://host:ssh/x").is_err());
+}
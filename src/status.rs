@@ -0,0 +1,184 @@
+//! A snapshot of the progress of a run, printed on SIGUSR1
+//!
+//! The runs from cron or nohup have no progress bar: `kill -USR1` tells how far they got, with
+//! the offset reached by each thread, the throughput over the last minute, the corrupted chunks
+//! found so far and the estimated remaining time. With `--progress json`, the snapshot is a JSON
+//! object, like the progress events.
+
+use human_units::{FormatDuration as _, FormatSize as _};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::report;
+use crate::work::ThreadPositions;
+use crate::{Progress, ProgressPhase};
+
+/// The window of the throughput reported
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Set by the signal handler, until the snapshot is printed
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Print a snapshot of the progress on SIGUSR1
+#[cfg(unix)]
+pub fn install() -> std::io::Result<()> {
+    use nix::sys::signal::{SaFlags, SigAction, SigHandler, SigSet, Signal, sigaction};
+
+    extern "C" fn request(_: nix::libc::c_int) {
+        REQUESTED.store(true, Ordering::Relaxed);
+    }
+
+    let action = SigAction::new(SigHandler::Handler(request), SaFlags::SA_RESTART, SigSet::empty());
+    // SAFETY: the handler only stores an atomic flag, which is async-signal-safe
+    unsafe { sigaction(Signal::SIGUSR1, &action) }?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn install() -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Whether a snapshot was requested since the last one
+pub(crate) fn requested() -> bool {
+    REQUESTED.swap(false, Ordering::Relaxed)
+}
+
+/// The throughput over the last minute, from the progress of the run
+pub(crate) struct Throughput {
+    /// The bytes done at each progress update in the window, the first one at its start
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl Throughput {
+    pub fn new() -> Self {
+        Throughput { samples: VecDeque::from([(Instant::now(), 0)]) }
+    }
+
+    pub fn add(&mut self, bytes_done: u64) {
+        let now = Instant::now();
+        // keep the last sample before the window, to cover it all
+        while self.samples.get(1).is_some_and(|(time, _)| now.duration_since(*time) >= WINDOW) {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((now, bytes_done));
+    }
+
+    /// The bytes per second since the start of the window
+    pub fn rate(&self, bytes_done: u64) -> u64 {
+        let (start, start_bytes) = self.samples.front().copied().expect("a sample");
+        let elapsed = start.elapsed().as_secs_f64();
+        if elapsed > 0.0 { ((bytes_done - start_bytes) as f64 / elapsed) as u64 } else { 0 }
+    }
+}
+
+/// A snapshot of the progress of the run
+pub(crate) struct Snapshot<'a> {
+    pub phase: ProgressPhase,
+    pub bytes_done: u64,
+    pub total: Option<u64>,
+    /// The bytes per second over the last minute
+    pub rate: u64,
+    pub elapsed: Duration,
+    pub positions: Option<&'a ThreadPositions>,
+}
+
+impl Snapshot<'_> {
+    /// The estimated time to the end of the stream, at the current throughput
+    fn eta(&self) -> Option<Duration> {
+        let remaining = self.total?.saturating_sub(self.bytes_done);
+        (self.rate > 0).then(|| Duration::from_secs_f64(remaining as f64 / self.rate as f64))
+    }
+
+    /// Print the snapshot on stderr, as a JSON object with `--progress json`
+    pub fn print(&self, progress: Option<&Progress>) {
+        match progress {
+            Some(Progress::Json(json)) => eprintln!("{}", self.to_json(json.device.as_deref())),
+            _ => self.print_text(),
+        }
+    }
+
+    fn print_text(&self) {
+        let phase = match self.phase {
+            ProgressPhase::Generate => "generated",
+            ProgressPhase::Validate => "validated",
+        };
+        let mut line = format!("status: {} {phase}", self.bytes_done.format_size());
+        if let Some(total) = self.total.filter(|total| *total > 0) {
+            let percent = self.bytes_done as f64 * 100.0 / total as f64;
+            line.push_str(&format!(" of {} ({percent:.1}%)", total.format_size()));
+        }
+        line.push_str(&format!(
+            " in {}, {}/s over the last minute",
+            self.elapsed.format_duration(),
+            self.rate.format_size()
+        ));
+        if let Some(positions) = self.positions {
+            line.push_str(&format!(", {} corrupted chunks", positions.corrupted()));
+        }
+        if let Some(eta) = self.eta() {
+            line.push_str(&format!(", {} remaining", eta.format_duration()));
+        }
+        eprintln!("{line}");
+        let offsets = self.positions.map(ThreadPositions::offsets).unwrap_or_default();
+        for (thread, offset) in offsets.iter().enumerate() {
+            match offset {
+                Some(offset) => eprintln!("status: thread {thread} at offset {offset}"),
+                None => eprintln!("status: thread {thread} before its first chunk"),
+            }
+        }
+    }
+
+    fn to_json(&self, device: Option<&str>) -> String {
+        let device = match device {
+            Some(device) => format!(",\"device\":{}", report::quote(device)),
+            None => String::new(),
+        };
+        let null = || "null".to_string();
+        let total = self.total.map_or_else(null, |total| total.to_string());
+        let eta = self.eta().map_or_else(null, |eta| format!("{:.1}", eta.as_secs_f64()));
+        let (corrupted, threads) = match self.positions {
+            Some(positions) => {
+                let offsets = positions.offsets();
+                let offsets = offsets.iter().map(|o| o.map_or_else(null, |o| o.to_string()));
+                (
+                    positions.corrupted().to_string(),
+                    format!("[{}]", offsets.collect::<Vec<_>>().join(",")),
+                )
+            }
+            None => (null(), null()),
+        };
+        format!(
+            "{{\"event\":\"status\"{device},\"bytes_done\":{},\"total\":{total},\"rate\":{},\"eta\":{eta},\"elapsed\":{:.1},\"corrupted\":{corrupted},\"threads\":{threads}}}",
+            self.bytes_done,
+            self.rate,
+            self.elapsed.as_secs_f64()
+        )
+    }
+}
+
+#[test]
+fn status_snapshot() {
+    let positions = ThreadPositions::new(2, 0, 1 << 20, 10 << 20);
+    let snapshot = Snapshot {
+        phase: ProgressPhase::Validate,
+        bytes_done: 4 << 20,
+        total: Some(10 << 20),
+        rate: 1 << 20,
+        elapsed: Duration::from_secs(4),
+        positions: Some(&positions),
+    };
+    assert_eq!(snapshot.eta(), Some(Duration::from_secs(6)));
+    assert_eq!(
+        snapshot.to_json(Some("/dev/sdb")),
+        r#"{"event":"status","device":"/dev/sdb","bytes_done":4194304,"total":10485760,"rate":1048576,"eta":6.0,"elapsed":4.0,"corrupted":0,"threads":[null,null]}"#
+    );
+    let snapshot = Snapshot { total: None, positions: None, ..snapshot };
+    assert_eq!(snapshot.eta(), None);
+    assert!(snapshot.to_json(None).ends_with(r#""corrupted":null,"threads":null}"#));
+
+    let mut throughput = Throughput::new();
+    throughput.add(100);
+    assert_eq!(throughput.samples.len(), 2);
+}
@@ -0,0 +1,323 @@
+//! The random streams generated or validated in memory, for the applications using randstream as a
+//! library
+
+use std::io::{self, Read, Write};
+use std::ops::Range;
+
+use crate::ChunkChecksum as _;
+use crate::checksum::{ChecksumAlgorithm, StreamChecksum};
+use crate::error::CorruptedChunk;
+use crate::generate::generate_chunk;
+use crate::rng::{RngAlgorithm, Seed, StreamRng};
+use crate::validate::validate_chunk;
+
+/// A random stream, read chunk after chunk
+///
+/// It's the same stream as the one written by `randstream generate --seed <seed> --chunk-size
+/// <chunk_size> --size <len>`, so it can be validated with `randstream validate`.
+pub struct RandomStream {
+    rng: StreamRng,
+    checksum: StreamChecksum,
+    chunk_size: usize,
+    len: u64,
+    /// The bytes of the stream generated so far
+    generated: u64,
+    buffer: Vec<u8>,
+    /// The part of the buffer not read yet
+    pending: Range<usize>,
+}
+
+impl RandomStream {
+    /// The stream of `len` bytes, with the default random generator and checksum
+    pub fn new(seed: Seed, chunk_size: usize, len: u64) -> Self {
+        Self::with_algorithms(
+            seed,
+            chunk_size,
+            len,
+            RngAlgorithm::default(),
+            ChecksumAlgorithm::default(),
+        )
+    }
+
+    /// The stream of `len` bytes, like with `--rng` and `--checksum`
+    pub fn with_algorithms(
+        seed: Seed,
+        chunk_size: usize,
+        len: u64,
+        rng: RngAlgorithm,
+        checksum: ChecksumAlgorithm,
+    ) -> Self {
+        assert!(chunk_size > 0, "the chunk size must be greater than 0");
+        RandomStream {
+            rng: rng.rng(seed),
+            checksum: checksum.stream_checksum(),
+            chunk_size,
+            len,
+            generated: 0,
+            // the generator is advanced by a multiple of 64 bits
+            buffer: vec![0; chunk_size.div_ceil(8) * 8],
+            pending: 0..0,
+        }
+    }
+
+    /// The size of the stream
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The checksum of the chunks generated so far, as logged by `randstream generate`
+    ///
+    /// It's the checksum of the whole stream, once it has been read up to its end.
+    pub fn checksum(&self) -> String {
+        self.checksum.algorithm().format(self.checksum.finalize())
+    }
+}
+
+impl Read for RandomStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            if self.generated == self.len {
+                return Ok(0);
+            }
+            let write_size = (self.len - self.generated).min(self.chunk_size as u64) as usize;
+            generate_chunk(&mut self.rng, &mut self.buffer, write_size, &mut self.checksum);
+            self.generated += write_size as u64;
+            self.pending = 0..write_size;
+        }
+        let size = buf.len().min(self.pending.len());
+        buf[..size].copy_from_slice(&self.buffer[self.pending.start..self.pending.start + size]);
+        self.pending.start += size;
+        Ok(size)
+    }
+}
+
+/// Validates the chunks of a random stream as it's written, like `randstream validate
+/// --keep-going`
+///
+/// The chunks are checked against their checksum as soon as they are complete, without buffering
+/// the stream. The result is returned by `finish()`, once the whole stream is written.
+pub struct ValidatingWriter {
+    checksum: StreamChecksum,
+    chunk_size: usize,
+    /// The start of the chunk being written
+    buffer: Vec<u8>,
+    /// The bytes of the complete chunks
+    bytes: u64,
+    corrupted: Vec<CorruptedChunk>,
+}
+
+/// The result of the validation of a stream
+#[derive(Clone, Debug)]
+pub struct ValidationReport {
+    pub bytes: u64,
+    /// The checksum of the stream, as logged by `randstream validate`
+    pub checksum: String,
+    /// The chunks which don't match their checksum, in order
+    pub corrupted: Vec<CorruptedChunk>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.corrupted.is_empty()
+    }
+}
+
+impl ValidatingWriter {
+    /// Validate the chunks sealed with the default checksum
+    pub fn new(chunk_size: usize) -> Self {
+        Self::with_checksum(chunk_size, ChecksumAlgorithm::default())
+    }
+
+    /// Validate the chunks sealed with that checksum, like with `--checksum`
+    pub fn with_checksum(chunk_size: usize, checksum: ChecksumAlgorithm) -> Self {
+        assert!(chunk_size > 0, "the chunk size must be greater than 0");
+        ValidatingWriter {
+            checksum: checksum.stream_checksum(),
+            chunk_size,
+            buffer: Vec::with_capacity(chunk_size),
+            bytes: 0,
+            corrupted: Vec::new(),
+        }
+    }
+
+    /// The chunks found corrupted so far
+    pub fn corrupted(&self) -> &[CorruptedChunk] {
+        &self.corrupted
+    }
+
+    /// Validate the last chunk, shorter than the others, and return the result
+    pub fn finish(mut self) -> ValidationReport {
+        if !self.buffer.is_empty() {
+            let chunk = std::mem::take(&mut self.buffer);
+            self.validate(&chunk);
+        }
+        ValidationReport {
+            bytes: self.bytes,
+            checksum: self.checksum.algorithm().format(self.checksum.finalize()),
+            corrupted: self.corrupted,
+        }
+    }
+
+    fn validate(&mut self, data: &[u8]) {
+        let chunk = self.bytes / self.chunk_size as u64;
+        if let Err(e) = validate_chunk(chunk, data, &mut self.checksum) {
+            self.corrupted.push(CorruptedChunk {
+                chunk,
+                offset: self.bytes,
+                length: data.len() as u64,
+                error: e.downcast().expect("a validation error"),
+            });
+        }
+        self.bytes += data.len() as u64;
+    }
+}
+
+impl Write for ValidatingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        if !self.buffer.is_empty() {
+            let size = rest.len().min(self.chunk_size - self.buffer.len());
+            self.buffer.extend_from_slice(&rest[..size]);
+            rest = &rest[size..];
+            if self.buffer.len() < self.chunk_size {
+                return Ok(buf.len());
+            }
+            let mut chunk = std::mem::take(&mut self.buffer);
+            self.validate(&chunk);
+            chunk.clear();
+            self.buffer = chunk;
+        }
+        // the complete chunks are validated without copying them
+        let mut chunks = rest.chunks_exact(self.chunk_size);
+        for chunk in &mut chunks {
+            self.validate(chunk);
+        }
+        self.buffer.extend_from_slice(chunks.remainder());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The streams read or written by an async service, with the `tokio` feature
+///
+/// The chunks are generated or validated as they are read or written, without blocking: they
+/// are always ready.
+#[cfg(feature = "tokio")]
+mod tokio_io {
+    use std::io::{self, Read as _, Write as _};
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+    use super::{RandomStream, ValidatingWriter};
+
+    impl AsyncRead for RandomStream {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            let size = self.get_mut().read(buf.initialize_unfilled())?;
+            buf.advance(size);
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncWrite for ValidatingWriter {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Poll::Ready(self.get_mut().write(buf))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn read_and_write_asynchronously() {
+        use crate::rng::Seed;
+        let mut context = Context::from_waker(std::task::Waker::noop());
+        let mut stream = RandomStream::new(Seed::U64(9), 1000, 2500);
+        let mut writer = ValidatingWriter::new(1000);
+        let mut data = [0; 700];
+        loop {
+            let mut buf = ReadBuf::new(&mut data);
+            let read = Pin::new(&mut stream).poll_read(&mut context, &mut buf);
+            assert!(matches!(read, Poll::Ready(Ok(()))));
+            if buf.filled().is_empty() {
+                break;
+            }
+            let written = Pin::new(&mut writer).poll_write(&mut context, buf.filled());
+            assert!(matches!(written, Poll::Ready(Ok(size)) if size == buf.filled().len()));
+        }
+        let report = writer.finish();
+        assert!(report.is_valid());
+        assert_eq!((report.bytes, report.checksum), (2500, stream.checksum()));
+    }
+}
+
+#[test]
+fn read_a_random_stream() {
+    let mut stream = RandomStream::new(Seed::U64(3), 1000, 2502);
+    assert_eq!(stream.len(), 2502);
+    let mut data = Vec::new();
+    // reads smaller than the chunks
+    let mut buf = [0; 300];
+    loop {
+        let size = stream.read(&mut buf).unwrap();
+        if size == 0 {
+            break;
+        }
+        data.extend(&buf[..size]);
+    }
+    assert_eq!(data.len(), 2502);
+    let mut checksum = ChecksumAlgorithm::default().stream_checksum();
+    for (index, chunk) in data.chunks(1000).enumerate() {
+        validate_chunk(index as u64, chunk, &mut checksum).unwrap();
+    }
+    assert_eq!(stream.checksum(), ChecksumAlgorithm::default().format(checksum.finalize()));
+    // the checksum logged by `generate --size 33Ki --chunk-size 32Ki --seed 2`
+    let mut stream = RandomStream::new(Seed::U64(2), 32 * 1024, 33 * 1024);
+    io::copy(&mut stream, &mut io::sink()).unwrap();
+    assert_eq!(stream.checksum(), "aa0e5a26");
+}
+
+#[test]
+fn validate_a_written_stream() {
+    use crate::error::ValidationError;
+    let mut data = Vec::new();
+    let mut stream = RandomStream::new(Seed::U64(5), 1000, 4500);
+    stream.read_to_end(&mut data).unwrap();
+    let mut writer = ValidatingWriter::new(1000);
+    // writes smaller and larger than the chunks
+    for part in data.chunks(700) {
+        writer.write_all(part).unwrap();
+    }
+    let report = writer.finish();
+    assert!(report.is_valid());
+    assert_eq!(report.bytes, 4500);
+    assert_eq!(report.checksum, stream.checksum());
+    data[2100] ^= 1;
+    let mut writer = ValidatingWriter::new(1000);
+    writer.write_all(&data[..1500]).unwrap();
+    writer.write_all(&data[1500..]).unwrap();
+    let corrupted = writer.finish().corrupted;
+    assert_eq!(corrupted.len(), 1);
+    assert_eq!((corrupted[0].chunk, corrupted[0].offset, corrupted[0].length), (2, 2000, 1000));
+    assert!(matches!(corrupted[0].error, ValidationError::ChunkChecksum { chunk: 2, .. }));
+}
@@ -0,0 +1,251 @@
+//! The checksum file of a stream, with `--checksum-file`, carrying its checksum and parameters
+//!
+//! Generate writes the checksum of the stream, along with the parameters needed to validate it,
+//! and validate reads them back, instead of passing them by hand from a run to the next:
+//!
+//! ```text
+//! randstream-checksum 1
+//! checksum 9f3a12bc
+//! size 104857600
+//! position 0
+//! chunk-size 32768
+//! chunk-checksum crc32
+//! format v1
+//! rng pcg64
+//! seed 12345678
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use clap::ValueEnum;
+
+use crate::checksum::ChecksumAlgorithm;
+use crate::chunk::ChunkFormat;
+use crate::cli::CommonArgs;
+use crate::compress::parse_compress_ratio;
+use crate::digest::DigestAlgorithm;
+use crate::error::usage;
+use crate::generate::GenerateArgs;
+use crate::pattern::Pattern;
+use crate::report::Report;
+use crate::rng::{RngAlgorithm, Seed};
+use crate::sector::parse_sector_size;
+use crate::validate::ValidateArgs;
+
+const MAGIC: &str = "randstream-checksum 1";
+
+/// The checksum of a stream, and the parameters it was generated with
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ChecksumFile {
+    pub checksum: String,
+    pub size: u64,
+    pub position: u64,
+    pub chunk_size: u64,
+    pub chunk_checksum: ChecksumAlgorithm,
+    pub format: ChunkFormat,
+    pub rng: RngAlgorithm,
+    /// The seed, unless recorded in the stream header, with `--random-seed`
+    pub seed: Option<Seed>,
+    pub digest: Option<(DigestAlgorithm, String)>,
+    pub compress_ratio: Option<f64>,
+    pub dedupe_ratio: Option<u16>,
+    pub pattern: Option<Pattern>,
+    pub shard_size: Option<u64>,
+    pub region_size: Option<u64>,
+    pub sector_size: Option<usize>,
+}
+
+impl ChecksumFile {
+    /// The checksum file of a stream generated, if its checksum is known
+    pub fn new(args: &GenerateArgs, report: &Report) -> Option<Self> {
+        let common = &args.common;
+        Some(ChecksumFile {
+            checksum: report.checksum.clone()?,
+            size: report.bytes,
+            position: args.position,
+            chunk_size: common.chunk_size,
+            chunk_checksum: common.checksum,
+            format: common.format,
+            rng: common.rng,
+            seed: (!args.random_seed).then(|| args.seed()),
+            digest: common.digest.zip(report.digest.clone()),
+            compress_ratio: common.compress_ratio,
+            dedupe_ratio: common.dedupe_ratio,
+            pattern: common.pattern,
+            shard_size: args.shard_size,
+            region_size: common.region_size,
+            sector_size: common.sector_checksums,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        fs::write(path, self.encode())?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        Self::decode(&content)
+            .ok_or_else(|| usage(format!("The checksum file {} is invalid", path.display())))
+    }
+
+    /// The arguments validating the stream, with the parameters and checksum of the file
+    pub fn validate_args(&self, args: &ValidateArgs) -> ValidateArgs {
+        let (digest, expected_digest) = self.digest.clone().unzip();
+        ValidateArgs {
+            position: self.position,
+            expected_checksum: Some(self.checksum.clone()),
+            expected_digest,
+            shard_size: self.shard_size,
+            // the seed is only used to regenerate the data
+            seed: self.seed.filter(|_| args.regenerate),
+            common: CommonArgs {
+                size: Some(self.size),
+                chunk_size: self.chunk_size,
+                checksum: self.chunk_checksum,
+                format: self.format,
+                rng: self.rng,
+                digest,
+                compress_ratio: self.compress_ratio,
+                dedupe_ratio: self.dedupe_ratio,
+                pattern: self.pattern,
+                region_size: self.region_size,
+                sector_checksums: self.sector_size,
+                ..args.common.clone()
+            },
+            ..args.clone()
+        }
+    }
+
+    fn encode(&self) -> String {
+        let mut content = format!(
+            "{MAGIC}\nchecksum {}\nsize {}\nposition {}\nchunk-size {}\nchunk-checksum {}\n\
+             format {}\nrng {}\n",
+            self.checksum,
+            self.size,
+            self.position,
+            self.chunk_size,
+            name(self.chunk_checksum),
+            name(self.format),
+            name(self.rng),
+        );
+        if let Some(seed) = self.seed {
+            content += &format!("seed {seed}\n");
+        }
+        if let Some((algorithm, digest)) = &self.digest {
+            content += &format!("digest {} {digest}\n", name(*algorithm));
+        }
+        if let Some(ratio) = self.compress_ratio {
+            content += &format!("compress-ratio {ratio}\n");
+        }
+        if let Some(ratio) = self.dedupe_ratio {
+            content += &format!("dedupe-ratio {ratio}\n");
+        }
+        if let Some(pattern) = self.pattern {
+            content += &format!("pattern {}\n", name(pattern));
+        }
+        if let Some(shard_size) = self.shard_size {
+            content += &format!("shard-size {shard_size}\n");
+        }
+        if let Some(region_size) = self.region_size {
+            content += &format!("region-size {region_size}\n");
+        }
+        if let Some(sector_size) = self.sector_size {
+            content += &format!("sector-checksums {sector_size}\n");
+        }
+        content
+    }
+
+    fn decode(content: &str) -> Option<Self> {
+        let mut lines = content.lines();
+        if lines.next()? != MAGIC {
+            return None;
+        }
+        let mut values = BTreeMap::new();
+        for line in lines {
+            let (key, value) = line.split_once(' ')?;
+            values.insert(key, value);
+        }
+        let digest = match values.remove("digest") {
+            Some(digest) => {
+                let (algorithm, digest) = digest.split_once(' ')?;
+                Some((value_enum(algorithm)?, digest.to_string()))
+            }
+            None => None,
+        };
+        let file = ChecksumFile {
+            checksum: values.remove("checksum")?.to_string(),
+            size: values.remove("size")?.parse().ok()?,
+            position: values.remove("position")?.parse().ok()?,
+            chunk_size: values.remove("chunk-size")?.parse().ok()?,
+            chunk_checksum: value_enum(values.remove("chunk-checksum")?)?,
+            format: value_enum(values.remove("format")?)?,
+            rng: value_enum(values.remove("rng")?)?,
+            seed: values.remove("seed").map(Seed::parse).transpose().ok()?,
+            digest,
+            compress_ratio: values
+                .remove("compress-ratio")
+                .map(parse_compress_ratio)
+                .transpose()
+                .ok()?,
+            dedupe_ratio: values.remove("dedupe-ratio").map(str::parse).transpose().ok()?,
+            pattern: match values.remove("pattern") {
+                Some(pattern) => Some(value_enum(pattern)?),
+                None => None,
+            },
+            shard_size: values.remove("shard-size").map(str::parse).transpose().ok()?,
+            region_size: values.remove("region-size").map(str::parse).transpose().ok()?,
+            sector_size: match values.remove("sector-checksums") {
+                Some(size) => Some(parse_sector_size(size).ok()?),
+                None => None,
+            },
+        };
+        // a file from a later version, with parameters this one doesn't know
+        values.is_empty().then_some(file)
+    }
+}
+
+fn name<T: ValueEnum>(value: T) -> String {
+    value.to_possible_value().unwrap().get_name().to_string()
+}
+
+fn value_enum<T: ValueEnum>(name: &str) -> Option<T> {
+    T::from_str(name, false).ok()
+}
+
+#[test]
+fn checksum_file_round_trip() {
+    let file = ChecksumFile {
+        checksum: "9f3a12bc".to_string(),
+        size: 104857600,
+        position: 4096,
+        chunk_size: 32768,
+        chunk_checksum: ChecksumAlgorithm::default(),
+        format: ChunkFormat::default(),
+        rng: RngAlgorithm::default(),
+        seed: Some(Seed::U64(12345678)),
+        digest: None,
+        compress_ratio: Some(2.5),
+        dedupe_ratio: None,
+        pattern: None,
+        shard_size: None,
+        region_size: None,
+        sector_size: None,
+    };
+    assert_eq!(ChecksumFile::decode(&file.encode()), Some(file.clone()));
+    let file = ChecksumFile {
+        seed: None,
+        dedupe_ratio: Some(4),
+        shard_size: Some(1 << 20),
+        region_size: Some(1 << 30),
+        sector_size: Some(4096),
+        ..file
+    };
+    assert_eq!(ChecksumFile::decode(&file.encode()), Some(file.clone()));
+
+    assert_eq!(ChecksumFile::decode("randstream-checksum 1\nchecksum 9f3a12bc\n"), None);
+    let unknown = file.encode() + "tweak 1\n";
+    assert_eq!(ChecksumFile::decode(&unknown), None);
+}
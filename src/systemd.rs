@@ -0,0 +1,159 @@
+//! The notifications to systemd, for the runs started by a service with `Type=notify`
+//!
+//! The readiness, the progress as a status line, and the watchdog pings are sent to the socket
+//! given in `NOTIFY_SOCKET`, like `sd_notify()` does. Nothing is sent outside of a service, or on
+//! the systems without systemd.
+
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use human_units::FormatSize as _;
+
+use crate::ProgressPhase;
+
+/// How often the status line is updated with the progress
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The socket of the service manager, if run by one
+static NOTIFIER: LazyLock<Option<Notifier>> = LazyLock::new(Notifier::from_env);
+
+struct Notifier {
+    #[cfg(target_os = "linux")]
+    socket: std::os::unix::net::UnixDatagram,
+    #[cfg(target_os = "linux")]
+    address: std::os::unix::net::SocketAddr,
+    /// How often the watchdog must be pinged, half its timeout
+    watchdog: Option<Duration>,
+    last_ping: Mutex<Instant>,
+    last_status: Mutex<Option<Instant>>,
+}
+
+impl Notifier {
+    #[cfg(target_os = "linux")]
+    fn from_env() -> Option<Self> {
+        use std::os::linux::net::SocketAddrExt as _;
+        use std::os::unix::ffi::OsStrExt as _;
+        use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+        let path = std::env::var_os("NOTIFY_SOCKET")?;
+        let address = match path.as_bytes().strip_prefix(b"@") {
+            Some(name) => SocketAddr::from_abstract_name(name),
+            None => SocketAddr::from_pathname(&path),
+        }
+        .ok()?;
+        let socket = UnixDatagram::unbound().ok()?;
+        // the run mustn't stall on a service manager slow to read its socket
+        socket.set_nonblocking(true).ok()?;
+        Some(Notifier {
+            socket,
+            address,
+            watchdog: watchdog_interval(
+                std::env::var("WATCHDOG_USEC").ok().as_deref(),
+                std::env::var("WATCHDOG_PID").ok().as_deref(),
+            ),
+            last_ping: Mutex::new(Instant::now()),
+            last_status: Mutex::new(None),
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn from_env() -> Option<Self> {
+        None
+    }
+
+    /// Send the state, ignoring the failures: the service manager may be gone
+    fn send(&self, state: &str) {
+        #[cfg(target_os = "linux")]
+        let _ = self.socket.send_to_addr(state.as_bytes(), &self.address);
+        #[cfg(not(target_os = "linux"))]
+        let _ = state;
+    }
+
+    /// Ping the watchdog, if it's time to
+    fn ping(&self) {
+        let Some(interval) = self.watchdog else {
+            return;
+        };
+        let mut last_ping = self.last_ping.lock().unwrap();
+        if last_ping.elapsed() >= interval {
+            self.send("WATCHDOG=1");
+            *last_ping = Instant::now();
+        }
+    }
+}
+
+/// The interval of the watchdog pings, if the watchdog is enabled for this process
+fn watchdog_interval(usec: Option<&str>, pid: Option<&str>) -> Option<Duration> {
+    let usec: u64 = usec?.parse().ok().filter(|usec| *usec > 0)?;
+    if let Some(pid) = pid
+        && pid.parse() != Ok(std::process::id())
+    {
+        return None;
+    }
+    Some(Duration::from_micros(usec / 2))
+}
+
+/// Tell the service manager that the run has started, or that the server is listening
+pub fn ready() {
+    if let Some(notifier) = &*NOTIFIER {
+        notifier.send("READY=1");
+    }
+}
+
+/// Update the status line of the service
+pub(crate) fn status(status: &str) {
+    if let Some(notifier) = &*NOTIFIER {
+        notifier.send(&format!("STATUS={status}"));
+        *notifier.last_status.lock().unwrap() = Some(Instant::now());
+    }
+}
+
+/// Ping the watchdog, if it's time to
+pub(crate) fn watchdog() {
+    if let Some(notifier) = &*NOTIFIER {
+        notifier.ping();
+    }
+}
+
+/// Report the progress of the run, in the status line, and ping the watchdog
+///
+/// The watchdog isn't pinged anymore when the progress stalls, like on a hung device.
+pub(crate) fn progress(phase: ProgressPhase, bytes_done: u64, total: Option<u64>) {
+    let Some(notifier) = &*NOTIFIER else {
+        return;
+    };
+    notifier.ping();
+    let due = notifier.last_status.lock().unwrap().is_none_or(|t| t.elapsed() >= STATUS_INTERVAL);
+    if due {
+        status(&progress_status(phase, bytes_done, total));
+    }
+}
+
+/// The status line of the progress, like `37% validated`
+fn progress_status(phase: ProgressPhase, bytes_done: u64, total: Option<u64>) -> String {
+    let verb = match phase {
+        ProgressPhase::Generate => "written",
+        ProgressPhase::Validate => "validated",
+    };
+    match total {
+        Some(total) if total > 0 => format!("{}% {verb}", bytes_done * 100 / total),
+        _ => format!("{} {verb}", bytes_done.format_size()),
+    }
+}
+
+#[test]
+fn watchdog_of_this_process() {
+    let pid = std::process::id().to_string();
+    assert_eq!(watchdog_interval(Some("10000000"), None), Some(Duration::from_secs(5)));
+    assert_eq!(watchdog_interval(Some("10000000"), Some(&pid)), Some(Duration::from_secs(5)));
+    assert_eq!(watchdog_interval(Some("10000000"), Some("1")), None);
+    assert_eq!(watchdog_interval(Some("0"), None), None);
+    assert_eq!(watchdog_interval(None, None), None);
+}
+
+#[test]
+fn progress_status_lines() {
+    assert_eq!(progress_status(ProgressPhase::Validate, 37, Some(100)), "37% validated");
+    assert_eq!(progress_status(ProgressPhase::Generate, 100, Some(100)), "100% written");
+    assert_eq!(progress_status(ProgressPhase::Validate, 0, None), "0 B validated");
+}
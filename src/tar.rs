@@ -0,0 +1,301 @@
+//! A tar archive of random files, with `generate --tar` and `validate --tar`
+//!
+//! The archive is in the ustar format, read by `tar -x` and the backup tools taking archives. Its
+//! members are the files of a tree, with the same paths, sizes and seeds as `generate --tree`, so
+//! the extracted files can be validated with `validate --manifest` too. The modification times
+//! are 0, so the same options write the same archive.
+
+use human_units::FormatSize as _;
+use log::{debug, info};
+use std::cell::{Cell, RefCell};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+
+use crate::cli::CommonArgs;
+use crate::devices;
+use crate::generate::{GenerateArgs, generate_stream_to};
+use crate::read_exact_or_eof;
+use crate::report::{self, OutputFormat, Report};
+use crate::tree::{self, FILES_PER_DIR, Manifest, ManifestEntry};
+use crate::validate::{ValidateArgs, validate_connection};
+
+const BLOCK_SIZE: usize = 512;
+
+/// The archive is padded to a multiple of this size, like `tar` does
+const RECORD_SIZE: u64 = 20 * BLOCK_SIZE as u64;
+
+const REGULAR: u8 = b'0';
+const DIRECTORY: u8 = b'5';
+
+/// The largest size written in octal, in the 11 digits of the field
+const MAX_OCTAL_SIZE: u64 = (1 << 33) - 1;
+
+/// Write a field of the header, as octal digits ended by a NUL
+fn octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    field[..digits].copy_from_slice(format!("{value:0digits$o}").as_bytes());
+}
+
+/// The header of a member
+fn header(name: &str, size: u64, kind: u8) -> anyhow::Result<[u8; BLOCK_SIZE]> {
+    let mut block = [0; BLOCK_SIZE];
+    if name.len() > 100 {
+        return Err(anyhow!("The name {name} doesn't fit in a tar header"));
+    }
+    block[..name.len()].copy_from_slice(name.as_bytes());
+    octal(&mut block[100..108], if kind == DIRECTORY { 0o755 } else { 0o644 });
+    octal(&mut block[108..116], 0);
+    octal(&mut block[116..124], 0);
+    if size <= MAX_OCTAL_SIZE {
+        octal(&mut block[124..136], size);
+    } else {
+        // the base-256 encoding of GNU tar, for the members of 8 GiB and more
+        block[124] = 0x80;
+        block[128..136].copy_from_slice(&size.to_be_bytes());
+    }
+    octal(&mut block[136..148], 0);
+    block[156] = kind;
+    block[257..263].copy_from_slice(b"ustar\0");
+    block[263..265].copy_from_slice(b"00");
+    block[148..156].fill(b' ');
+    let checksum: u64 = block.iter().map(|b| *b as u64).sum();
+    block[148..155].copy_from_slice(format!("{checksum:06o}\0").as_bytes());
+    Ok(block)
+}
+
+/// The name, size and kind of a member, if the block is a valid header
+fn parse_header(block: &[u8; BLOCK_SIZE]) -> Option<(String, u64, u8)> {
+    let field = |range: std::ops::Range<usize>| {
+        let field = &block[range];
+        let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+        String::from_utf8_lossy(&field[..end]).trim().to_string()
+    };
+    let checksum = u64::from_str_radix(&field(148..156), 8).ok()?;
+    // the checksum field counts as spaces
+    let sum: u64 = block
+        .iter()
+        .enumerate()
+        .map(|(i, b)| if (148..156).contains(&i) { b' ' as u64 } else { *b as u64 })
+        .sum();
+    if sum != checksum {
+        return None;
+    }
+    let size = match block[124] {
+        0x80 => u64::from_be_bytes(block[128..136].try_into().unwrap()),
+        _ => u64::from_str_radix(&field(124..136), 8).ok()?,
+    };
+    let name = match field(345..500) {
+        prefix if prefix.is_empty() || &block[257..262] != b"ustar" => field(0..100),
+        prefix => format!("{prefix}/{}", field(0..100)),
+    };
+    Some((name, size, block[156]))
+}
+
+/// Write the archive of random files on stdout, with `generate --tar`
+///
+/// The members are generated one after the other, each with its own stream.
+pub(crate) fn generate_tar(
+    args: &GenerateArgs,
+    cancel: Arc<AtomicBool>,
+    report: &mut Report,
+) -> anyhow::Result<i32> {
+    let count = args.tar.expect("a tar archive");
+    let sizes = tree::file_sizes(args.seed(), count, args.file_size_dist);
+    let total: u64 = sizes.iter().sum();
+    info!("archiving {count} files, {} in all", total.format_size());
+    let mut out = io::stdout();
+    let mut entries = Vec::new();
+    for (index, size) in sizes.into_iter().enumerate() {
+        let path = tree::file_path(index as u64);
+        if (index as u64).is_multiple_of(FILES_PER_DIR) {
+            let dir = path.parent().expect("a directory");
+            out.write_all(&header(&format!("{}/", dir.display()), 0, DIRECTORY)?)?;
+            report.bytes += BLOCK_SIZE as u64;
+        }
+        let name = path.display().to_string();
+        out.write_all(&header(&name, size, REGULAR)?)?;
+        out.flush()?;
+        let member = GenerateArgs {
+            tar: None,
+            manifest: None,
+            common: CommonArgs {
+                size: Some(size),
+                no_progress: true,
+                device: Some(name.clone()),
+                ..args.common.clone()
+            },
+            seed: args.seed().for_device(index),
+            seed_string: None,
+            ..args.clone()
+        };
+        let mut generated = Report::new("generate", OutputFormat::Text);
+        let code =
+            generate_stream_to(&member, Box::new(io::stdout()), cancel.clone(), &mut generated)?;
+        if code != 0 {
+            // the archive can't be completed
+            return Ok(code);
+        }
+        if generated.bytes != size {
+            return Err(anyhow!(
+                "The member {name} holds {} bytes instead of {size}",
+                generated.bytes
+            ));
+        }
+        let padding = size.next_multiple_of(BLOCK_SIZE as u64) - size;
+        out.write_all(&vec![0; padding as usize])?;
+        report.bytes += BLOCK_SIZE as u64 + size + padding;
+        let checksum = generated.checksum.unwrap_or_default();
+        debug!("{name}: {size} bytes, checksum {checksum}");
+        entries.push(ManifestEntry { path, size, checksum });
+    }
+    // the end of the archive, then the padding of the last record
+    let end = (report.bytes + 2 * BLOCK_SIZE as u64).next_multiple_of(RECORD_SIZE);
+    out.write_all(&vec![0; (end - report.bytes) as usize])?;
+    out.flush()?;
+    report.bytes = end;
+    info!("archive: {count} files, {} bytes", report.bytes);
+    if let Some(path) = &args.manifest {
+        Manifest { entries }.save(path)?;
+        info!("manifest: {}", path.display());
+    }
+    Ok(0)
+}
+
+/// The archive read from the file or stdin, shared by the readers of its members
+type Archive = Rc<RefCell<Box<dyn Read>>>;
+
+/// The data of a member, read from the archive
+struct Member {
+    archive: Archive,
+    /// The bytes of the member not read yet
+    remaining: Rc<Cell<u64>>,
+}
+
+impl Read for Member {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.remaining.get().min(usize::MAX as u64) as usize);
+        if len == 0 {
+            return Ok(0);
+        }
+        let read = self.archive.borrow_mut().read(&mut buf[..len])?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining.set(self.remaining.get() - read as u64);
+        Ok(read)
+    }
+}
+
+/// Skip that many bytes of the archive
+fn skip(archive: &Archive, bytes: u64) -> anyhow::Result<()> {
+    let skipped = io::copy(&mut archive.borrow_mut().by_ref().take(bytes), &mut io::sink())?;
+    if skipped < bytes {
+        return Err(anyhow!("The archive ends in the middle of a member"));
+    }
+    Ok(())
+}
+
+/// Validate the stream of each file of the archive read from the file or stdin, with
+/// `validate --tar`
+///
+/// The members are validated one after the other, the other kinds of members being skipped. The
+/// result of each one is logged in a table.
+pub(crate) fn validate_tar(args: &ValidateArgs, cancel: Arc<AtomicBool>) -> anyhow::Result<i32> {
+    let start = Instant::now();
+    let input: Box<dyn Read> = match &args.file {
+        Some(path) => Box::new(BufReader::new(File::open(path)?)),
+        None => Box::new(io::stdin()),
+    };
+    let archive: Archive = Rc::new(RefCell::new(input));
+    // each member has its own cancel flag, so a failing one doesn't stop the others
+    let member_cancel = Mutex::new(Arc::new(AtomicBool::new(false)));
+    let done = AtomicBool::new(false);
+    let results = thread::scope(|scope| {
+        scope.spawn(|| {
+            while !done.load(Ordering::Relaxed) {
+                if cancel.load(Ordering::Relaxed) {
+                    member_cancel.lock().unwrap().store(true, Ordering::Relaxed);
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        });
+        let results = validate_members(args, &archive, &member_cancel, &cancel);
+        done.store(true, Ordering::Relaxed);
+        results
+    })?;
+
+    if args.common.output == OutputFormat::Json {
+        println!("{}", report::devices_json("validate", &results));
+    } else {
+        devices::log_table("member", &results);
+        devices::log_summary(&results, start.elapsed());
+    }
+    Ok(devices::exit_code(&results))
+}
+
+fn validate_members(
+    args: &ValidateArgs,
+    archive: &Archive,
+    member_cancel: &Mutex<Arc<AtomicBool>>,
+    cancel: &AtomicBool,
+) -> anyhow::Result<Vec<(String, Report, i32)>> {
+    let mut results = Vec::new();
+    let mut offset = 0;
+    while !cancel.load(Ordering::Relaxed) {
+        let mut block = [0; BLOCK_SIZE];
+        if read_exact_or_eof(&mut *archive.borrow_mut(), &mut block)? < BLOCK_SIZE {
+            return Err(anyhow!("The archive ends at offset {offset}, without its end blocks"));
+        }
+        if block.iter().all(|b| *b == 0) {
+            break;
+        }
+        let (name, size, kind) =
+            parse_header(&block).ok_or_else(|| anyhow!("Invalid tar header at offset {offset}"))?;
+        let padded = size.next_multiple_of(BLOCK_SIZE as u64);
+        offset += BLOCK_SIZE as u64 + padded;
+        if kind != REGULAR && kind != 0 {
+            skip(archive, padded)?;
+            continue;
+        }
+        let member = ValidateArgs {
+            file: None,
+            common: CommonArgs { no_progress: true, ..args.common.clone() },
+            ..args.for_device(results.len(), Path::new(&name))
+        };
+        let remaining = Rc::new(Cell::new(size));
+        let reader = Member { archive: archive.clone(), remaining: remaining.clone() };
+        let mut report = Report::new("validate", args.common.output);
+        let cancel = {
+            let mut member_cancel = member_cancel.lock().unwrap();
+            *member_cancel = Arc::new(AtomicBool::new(cancel.load(Ordering::Relaxed)));
+            member_cancel.clone()
+        };
+        let result = validate_connection(&member, Box::new(reader), cancel, &mut report);
+        let code = report.record(&result);
+        results.push((name, report, code));
+        // the rest of a member failing early
+        skip(archive, remaining.get() + padded - size)?;
+    }
+    Ok(results)
+}
+
+#[test]
+fn tar_headers() {
+    let block = header("0000/00000001.bin", 1234, REGULAR).unwrap();
+    assert_eq!(&block[124..136], b"00000002322\0");
+    assert_eq!(parse_header(&block), Some(("0000/00000001.bin".to_string(), 1234, REGULAR)));
+    let block = header("0000/00000002.bin", 10 << 30, REGULAR).unwrap();
+    assert_eq!(parse_header(&block), Some(("0000/00000002.bin".to_string(), 10 << 30, REGULAR)));
+    let mut corrupted = block;
+    corrupted[0] = b'1';
+    assert_eq!(parse_header(&corrupted), None);
+    assert!(header(&"a".repeat(101), 0, REGULAR).is_err());
+}
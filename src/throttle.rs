@@ -0,0 +1,52 @@
+//! The bandwidth limit, shared by all the threads
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How much the threads can get ahead of the limit
+const MAX_BURST: Duration = Duration::from_millis(100);
+
+/// A token bucket limiting the throughput of the threads sharing it
+#[derive(Clone, Debug)]
+pub struct Throttle {
+    bytes_per_second: u64,
+    /// The time at which the bytes already consumed are within the limit
+    next: Arc<Mutex<Instant>>,
+}
+
+impl Throttle {
+    pub fn new(bytes_per_second: u64) -> Self {
+        Throttle { bytes_per_second, next: Arc::new(Mutex::new(Instant::now())) }
+    }
+
+    /// Account for `bytes` processed, and wait until they are within the limit
+    pub fn consume(&self, bytes: u64) {
+        let cost = Duration::from_secs_f64(bytes as f64 / self.bytes_per_second as f64);
+        let now = Instant::now();
+        let deadline = {
+            let mut next = self.next.lock().unwrap();
+            // the time spent idle can't be used for a larger burst
+            *next = (*next).max(now) + cost;
+            *next
+        };
+        if let Some(delay) = deadline.checked_duration_since(now + MAX_BURST) {
+            thread::sleep(delay);
+        }
+    }
+}
+
+#[test]
+fn throttle_limits_the_throughput() {
+    let throttle = Throttle::new(10_000_000);
+    let start = Instant::now();
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let throttle = throttle.clone();
+            thread::spawn(move || (0..10).for_each(|_| throttle.consume(100_000)))
+        })
+        .collect();
+    handles.into_iter().for_each(|h| h.join().unwrap());
+    // 4MB at 10MB/s, minus the initial burst
+    assert!(start.elapsed() >= Duration::from_millis(250));
+}
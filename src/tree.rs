@@ -0,0 +1,211 @@
+//! A directory tree of many files, each with its own stream, with `generate --tree`
+//!
+//! The files are spread in subdirectories of `FILES_PER_DIR` files, and their sizes are drawn
+//! from the seed, so the same options generate the same tree. The manifest lists the checksum
+//! and the size of each file, read back by `validate --manifest`:
+//!
+//! ```text
+//! randstream-manifest 1
+//! 9f3a12bc 4096 0000/00000000.bin
+//! 01c8e2a7 753664 0000/00000001.bin
+//! ```
+
+use human_units::FormatSize as _;
+use log::{error, info};
+use parse_size::parse_size;
+use std::fs::{self, File};
+use std::io::{self, Write as _};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::Instant;
+
+use crate::cli::CommonArgs;
+use crate::devices;
+use crate::error::usage;
+use crate::generate::{GenerateArgs, generate_stream};
+use crate::report::{self, OutputFormat};
+use crate::rng::{Seed, splitmix64};
+
+const MAGIC: &str = "randstream-manifest 1";
+
+/// The number of files in each subdirectory of the tree
+pub(crate) const FILES_PER_DIR: u64 = 256;
+
+/// The range of the sizes of the files, like `1Mi..1Gi`, or a single size
+pub(crate) fn parse_size_range(s: &str) -> Result<(u64, u64), String> {
+    let (min, max) = s.split_once("..").unwrap_or((s, s));
+    let min = parse_size(min).map_err(|e| e.to_string())?;
+    let max = parse_size(max).map_err(|e| e.to_string())?;
+    match (min, max) {
+        (0, _) => Err("the files can't be empty".to_string()),
+        (min, max) if min > max => Err(format!("{min} is greater than {max}")),
+        range => Ok(range),
+    }
+}
+
+/// The path of a file in the tree
+pub(crate) fn file_path(index: u64) -> PathBuf {
+    Path::new(&format!("{:04}", index / FILES_PER_DIR)).join(format!("{index:08}.bin"))
+}
+
+/// The size of each file, spread evenly across the orders of magnitude of the range, like the
+/// files of a real file system
+pub(crate) fn file_sizes(seed: Seed, count: u64, (min, max): (u64, u64)) -> Vec<u64> {
+    let mut state = seed.fingerprint();
+    let (low, high) = ((min as f64).ln(), (max as f64 + 1.0).ln());
+    (0..count)
+        .map(|_| {
+            let unit = (splitmix64(&mut state) >> 11) as f64 / (1u64 << 53) as f64;
+            ((low + unit * (high - low)).exp() as u64).clamp(min, max)
+        })
+        .collect()
+}
+
+/// Populate the directory with the files of the tree, `--jobs` at a time
+pub(crate) fn generate_tree(args: &GenerateArgs, cancel: Arc<AtomicBool>) -> anyhow::Result<i32> {
+    let start = Instant::now();
+    let dir = args.tree.as_deref().expect("a tree");
+    let sizes = file_sizes(args.seed(), args.files, args.file_size_dist);
+    let files: Vec<_> = (0..args.files).map(|index| dir.join(file_path(index))).collect();
+    for index in (0..args.files).step_by(FILES_PER_DIR as usize) {
+        fs::create_dir_all(dir.join(file_path(index)).parent().unwrap())?;
+    }
+    let total: u64 = sizes.iter().sum();
+    info!("generating {} files, {} in all", args.files, total.format_size());
+    let jobs = args.common.jobs.unwrap_or(num_cpus::get_physical());
+    let output = args.common.output;
+    let results = devices::process(
+        "generate",
+        output,
+        &files,
+        jobs,
+        &cancel,
+        |index, file, cancel, report| {
+            // the files are written at once, each by a thread
+            let args = GenerateArgs {
+                tree: None,
+                manifest: None,
+                common: CommonArgs {
+                    size: Some(sizes[index]),
+                    jobs: Some(1),
+                    no_progress: true,
+                    // the path in the tree, like in the manifest
+                    device: Some(file_path(index as u64).display().to_string()),
+                    ..args.common.clone()
+                },
+                ..args.for_device(index, file)
+            };
+            generate_stream(&args, cancel, report)
+        },
+    );
+
+    if output == OutputFormat::Json {
+        println!("{}", report::devices_json("generate", &results));
+    } else {
+        for (file, report, code) in results.iter().filter(|(_, _, code)| *code != 0) {
+            match report.errors.first() {
+                Some(e) => error!("{file}: {e}"),
+                None => error!("{file}: exit code {code}"),
+            }
+        }
+        devices::log_summary(&results, start.elapsed());
+    }
+    let exit_code = devices::exit_code(&results);
+    if exit_code == 0
+        && let Some(path) = &args.manifest
+    {
+        let entries = results.iter().enumerate().map(|(index, (_, report, _))| ManifestEntry {
+            path: file_path(index as u64),
+            size: report.bytes,
+            checksum: report.checksum.clone().unwrap_or_default(),
+        });
+        Manifest { entries: entries.collect() }.save(path)?;
+        info!("manifest: {}", path.display());
+    }
+    Ok(exit_code)
+}
+
+/// A file of the tree, as listed in the manifest
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ManifestEntry {
+    /// Relative to the directory of the tree, joined to it once loaded
+    pub path: PathBuf,
+    pub size: u64,
+    pub checksum: String,
+}
+
+/// The files of a tree, with `--manifest`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    /// Read the manifest, with the paths of the files in the directory of the tree
+    pub fn load(path: &Path, tree: &Path) -> anyhow::Result<Manifest> {
+        let content = fs::read(path)?;
+        let mut manifest = Self::decode(&String::from_utf8_lossy(&content))
+            .ok_or_else(|| usage(format!("{} isn't a manifest of a tree", path.display())))?;
+        for entry in &mut manifest.entries {
+            entry.path = tree.join(&entry.path);
+        }
+        Ok(manifest)
+    }
+
+    fn decode(content: &str) -> Option<Manifest> {
+        let mut lines = content.lines();
+        if lines.next()? != MAGIC {
+            return None;
+        }
+        let entries = lines.map(|line| {
+            let mut fields = line.splitn(3, ' ');
+            let checksum = fields.next()?.to_string();
+            let size = fields.next()?.parse().ok()?;
+            Some(ManifestEntry { path: PathBuf::from(fields.next()?), size, checksum })
+        });
+        Some(Manifest { entries: entries.collect::<Option<_>>()? })
+    }
+
+    fn encode(&self) -> String {
+        let mut content = format!("{MAGIC}\n");
+        for entry in &self.entries {
+            let path = entry.path.display();
+            content.push_str(&format!("{} {} {path}\n", entry.checksum, entry.size));
+        }
+        content
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut f = File::create(path)?;
+        f.write_all(self.encode().as_bytes())?;
+        f.sync_all()
+    }
+}
+
+#[test]
+fn tree_files() {
+    assert_eq!(parse_size_range("1Ki..1Mi"), Ok((1024, 1 << 20)));
+    assert_eq!(parse_size_range("4Ki"), Ok((4096, 4096)));
+    assert!(parse_size_range("1Mi..1Ki").is_err());
+    assert!(parse_size_range("0..1Ki").is_err());
+    assert_eq!(file_path(300), Path::new("0001/00000300.bin"));
+
+    let sizes = file_sizes(Seed::U64(1), 1000, (1 << 10, 1 << 30));
+    assert_eq!(sizes, file_sizes(Seed::U64(1), 1000, (1 << 10, 1 << 30)));
+    assert!(sizes.iter().all(|size| (1 << 10..=1 << 30).contains(size)));
+    // as many small files as large ones
+    assert!((400..600).contains(&sizes.iter().filter(|size| **size < 1 << 20).count()));
+    assert_eq!(file_sizes(Seed::U64(1), 3, (4096, 4096)), [4096; 3]);
+
+    let manifest = Manifest {
+        entries: vec![ManifestEntry {
+            path: file_path(1),
+            size: 4096,
+            checksum: "9f3a12bc".to_string(),
+        }],
+    };
+    assert_eq!(manifest.encode(), "randstream-manifest 1\n9f3a12bc 4096 0000/00000001.bin\n");
+    assert_eq!(Manifest::decode(&manifest.encode()), Some(manifest));
+    assert_eq!(Manifest::decode("randstream-manifest 1\n9f3a12bc 4096\n"), None);
+}
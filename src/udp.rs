@@ -0,0 +1,163 @@
+//! The streams sent as UDP datagrams, each one carrying a chunk with its header
+
+use log::info;
+use std::io::{self, Write};
+use std::net::UdpSocket;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::Progress;
+use crate::checksum::ChecksumAlgorithm;
+use crate::chunk::ChunkHeader;
+use crate::validate::validate_chunk;
+
+/// The largest payload of a UDP datagram
+pub const MAX_DATAGRAM_SIZE: usize = 65507;
+
+/// The number of empty datagrams marking the end of the stream, in case some are lost
+const END_MARKERS: usize = 3;
+
+/// How long the receiver waits for the next datagram, before considering the stream over
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often the cancellation is checked, while waiting for a datagram
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Sends each chunk written as a datagram
+pub struct DatagramWriter {
+    socket: UdpSocket,
+}
+
+impl DatagramWriter {
+    pub fn new(socket: UdpSocket) -> Self {
+        DatagramWriter { socket }
+    }
+}
+
+impl Write for DatagramWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.socket.send(buf)
+    }
+
+    /// Mark the end of the stream: it's flushed once, after the last chunk
+    fn flush(&mut self) -> io::Result<()> {
+        for _ in 0..END_MARKERS {
+            match self.socket.send(&[]) {
+                // the receiver has stopped on a previous marker
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => break,
+                result => result?,
+            };
+        }
+        Ok(())
+    }
+}
+
+/// What the receiver got
+#[derive(Debug, Default)]
+pub struct DatagramStats {
+    /// The chunks received, once each
+    pub received: u64,
+    pub lost: u64,
+    /// The chunks received after a following one
+    pub reordered: u64,
+    pub duplicated: u64,
+    /// The datagrams not matching their checksum, or without a chunk header
+    pub corrupted: u64,
+    pub bytes: u64,
+    seen: Vec<bool>,
+}
+
+impl DatagramStats {
+    /// Record a valid chunk
+    fn record(&mut self, index: u64) {
+        let index = index as usize;
+        if self.seen.get(index).copied().unwrap_or(false) {
+            self.duplicated += 1;
+            return;
+        }
+        if index < self.seen.len() {
+            self.reordered += 1;
+        } else {
+            self.seen.resize(index + 1, false);
+        }
+        self.seen[index] = true;
+        self.received += 1;
+    }
+
+    /// Count the chunks lost, out of the expected ones, or else out of the ones up to the last
+    /// received
+    fn finish(&mut self, expected_chunks: Option<u64>) {
+        let expected = expected_chunks.unwrap_or(self.seen.len() as u64);
+        self.lost = expected.saturating_sub(self.received);
+    }
+
+    pub fn log(&self) {
+        info!(
+            "datagrams: {} received, {} lost, {} reordered, {} duplicated, {} corrupted",
+            self.received, self.lost, self.reordered, self.duplicated, self.corrupted
+        );
+    }
+}
+
+/// Receive the chunks of a stream, until its end is marked, or the sender has been idle for a
+/// while
+///
+/// The stream size, if known, gives the number of chunks expected.
+pub fn receive(
+    socket: &UdpSocket,
+    checksum: ChecksumAlgorithm,
+    stream_size: Option<u64>,
+    chunk_size: usize,
+    pb: &mut Option<Progress>,
+    cancel: &AtomicBool,
+) -> io::Result<DatagramStats> {
+    socket.set_read_timeout(Some(POLL_INTERVAL))?;
+    let mut stats = DatagramStats::default();
+    let mut buffer = vec![0; MAX_DATAGRAM_SIZE + 1];
+    let mut last_received = None;
+    while !cancel.load(Ordering::Relaxed) {
+        let size = match socket.recv(&mut buffer) {
+            Ok(size) => size,
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                // the sender may not have started yet
+                if last_received.is_some_and(|t: Instant| t.elapsed() >= IDLE_TIMEOUT) {
+                    break;
+                }
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        if size == 0 {
+            break;
+        }
+        last_received = Some(Instant::now());
+        stats.bytes += size as u64;
+        let data = &buffer[..size];
+        let header = ChunkHeader::decode(data).filter(|h| h.length == size as u64);
+        match header {
+            Some(header)
+                if validate_chunk(header.index, data, &mut checksum.stream_checksum()).is_ok() =>
+            {
+                stats.record(header.index)
+            }
+            _ => stats.corrupted += 1,
+        }
+        if let Some(p) = pb {
+            p.tick(stats.bytes);
+        }
+    }
+    stats.finish(stream_size.map(|s| s.div_ceil(chunk_size as u64)));
+    Ok(stats)
+}
+
+#[test]
+fn datagram_stats() {
+    let mut stats = DatagramStats::default();
+    for index in [0, 2, 1, 2, 5, 4] {
+        stats.record(index);
+    }
+    stats.finish(None);
+    assert_eq!((stats.received, stats.reordered, stats.duplicated, stats.lost), (5, 2, 1, 1));
+    stats.finish(Some(8));
+    assert_eq!(stats.lost, 3);
+}
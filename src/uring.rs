@@ -0,0 +1,365 @@
+//! A minimal io_uring instance, used by the io-uring engine.
+//!
+//! Only what's needed to queue reads and writes at a given offset and reap their completions.
+
+use rustix::io_uring::{
+    IORING_OFF_CQ_RING, IORING_OFF_SQ_RING, IORING_OFF_SQES, IoringEnterFlags, IoringOp,
+    io_uring_cqe, io_uring_enter, io_uring_params, io_uring_ptr, io_uring_setup, io_uring_sqe,
+    io_uring_user_data,
+};
+use rustix::mm::{MapFlags, ProtFlags, mmap, munmap};
+use std::ffi::c_void;
+use std::io;
+use std::os::fd::{AsFd, OwnedFd, RawFd};
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::direct::AlignedBuffer;
+
+/// A memory area shared with the kernel
+struct Mapping {
+    ptr: *mut c_void,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(fd: &OwnedFd, len: usize, offset: u64) -> io::Result<Self> {
+        // SAFETY: a new mapping, not aliasing any existing memory
+        let ptr = unsafe {
+            mmap(
+                ptr::null_mut(),
+                len,
+                ProtFlags::READ | ProtFlags::WRITE,
+                MapFlags::SHARED | MapFlags::POPULATE,
+                fd,
+                offset,
+            )
+        }?;
+        Ok(Mapping { ptr, len })
+    }
+
+    /// # Safety
+    ///
+    /// `offset` must be within the mapping, and aligned for `T`
+    unsafe fn at<T>(&self, offset: u32) -> *mut T {
+        unsafe { self.ptr.byte_add(offset as usize).cast() }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: the mapping isn't used anymore
+        let _ = unsafe { munmap(self.ptr, self.len) };
+    }
+}
+
+pub struct IoUring {
+    fd: OwnedFd,
+    _sq_ring: Mapping,
+    _cq_ring: Mapping,
+    _sqes_ring: Mapping,
+    sq_head: *const AtomicU32,
+    sq_tail: *const AtomicU32,
+    sq_mask: u32,
+    sq_entries: u32,
+    sqes: *mut io_uring_sqe,
+    cq_head: *const AtomicU32,
+    cq_tail: *const AtomicU32,
+    cq_mask: u32,
+    cqes: *const io_uring_cqe,
+    /// The entries queued but not submitted yet
+    queued: u32,
+}
+
+// SAFETY: the rings are only accessed through `&mut self`
+unsafe impl Send for IoUring {}
+
+impl IoUring {
+    pub fn new(entries: u32) -> io::Result<Self> {
+        let mut params = io_uring_params::default();
+        // SAFETY: params is zeroed, as expected by the kernel
+        let fd = unsafe { io_uring_setup(entries, &mut params) }?;
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+        let cq_len =
+            params.cq_off.cqes as usize + params.cq_entries as usize * size_of::<io_uring_cqe>();
+        let sqes_len = params.sq_entries as usize * size_of::<io_uring_sqe>();
+        let sq_ring = Mapping::new(&fd, sq_len, IORING_OFF_SQ_RING)?;
+        let cq_ring = Mapping::new(&fd, cq_len, IORING_OFF_CQ_RING)?;
+        let sqes_ring = Mapping::new(&fd, sqes_len, IORING_OFF_SQES)?;
+        // SAFETY: the offsets are given by the kernel
+        unsafe {
+            let sq_mask = *sq_ring.at::<u32>(params.sq_off.ring_mask);
+            // a fixed mapping between the submission queue and the entries
+            let array = sq_ring.at::<u32>(params.sq_off.array);
+            for i in 0..params.sq_entries {
+                *array.add(i as usize) = i;
+            }
+            Ok(IoUring {
+                sq_head: sq_ring.at(params.sq_off.head),
+                sq_tail: sq_ring.at(params.sq_off.tail),
+                sq_mask,
+                sq_entries: params.sq_entries,
+                sqes: sqes_ring.at(0),
+                cq_head: cq_ring.at(params.cq_off.head),
+                cq_tail: cq_ring.at(params.cq_off.tail),
+                cq_mask: *cq_ring.at::<u32>(params.cq_off.ring_mask),
+                cqes: cq_ring.at(params.cq_off.cqes),
+                fd,
+                _sq_ring: sq_ring,
+                _cq_ring: cq_ring,
+                _sqes_ring: sqes_ring,
+                queued: 0,
+            })
+        }
+    }
+
+    /// Queue a read or a write of `len` bytes at `buf`, at `offset` in `fd`
+    ///
+    /// Returns false if the submission queue is full.
+    ///
+    /// # Safety
+    ///
+    /// The buffer must stay valid, and not be accessed, until the operation completes.
+    pub unsafe fn push(
+        &mut self,
+        op: IoringOp,
+        fd: RawFd,
+        buf: *mut u8,
+        len: u32,
+        offset: u64,
+        user_data: u64,
+    ) -> bool {
+        // SAFETY: the ring pointers are valid as long as self
+        let (head, tail) = unsafe {
+            ((*self.sq_head).load(Ordering::Acquire), (*self.sq_tail).load(Ordering::Relaxed))
+        };
+        if tail.wrapping_sub(head) >= self.sq_entries {
+            return false;
+        }
+        let mut sqe = io_uring_sqe { opcode: op, fd, ..Default::default() };
+        sqe.off_or_addr2.off = offset;
+        sqe.addr_or_splice_off_in.addr = io_uring_ptr::new(buf.cast());
+        sqe.len.len = len;
+        sqe.user_data = io_uring_user_data::from_u64(user_data);
+        // SAFETY: the entry at tail isn't owned by the kernel
+        unsafe {
+            *self.sqes.add((tail & self.sq_mask) as usize) = sqe;
+            (*self.sq_tail).store(tail.wrapping_add(1), Ordering::Release);
+        }
+        self.queued += 1;
+        true
+    }
+
+    /// Submit the queued operations, and wait for at least `min_complete` completions
+    pub fn submit_and_wait(&mut self, min_complete: u32) -> io::Result<()> {
+        let flags =
+            if min_complete > 0 { IoringEnterFlags::GETEVENTS } else { IoringEnterFlags::empty() };
+        loop {
+            // SAFETY: the submitted entries point to buffers kept alive by the caller
+            match unsafe { io_uring_enter(self.fd.as_fd(), self.queued, min_complete, flags) } {
+                Ok(submitted) => {
+                    self.queued -= submitted;
+                    return Ok(());
+                }
+                Err(rustix::io::Errno::INTR) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// The next completion, as its user data and result
+    pub fn pop(&mut self) -> Option<(u64, i32)> {
+        // SAFETY: the ring pointers are valid as long as self
+        unsafe {
+            let head = (*self.cq_head).load(Ordering::Relaxed);
+            if head == (*self.cq_tail).load(Ordering::Acquire) {
+                return None;
+            }
+            let cqe = &*self.cqes.add((head & self.cq_mask) as usize);
+            let completion = (cqe.user_data.u64_(), cqe.res);
+            (*self.cq_head).store(head.wrapping_add(1), Ordering::Release);
+            Some(completion)
+        }
+    }
+}
+
+/// An operation in flight
+#[derive(Clone, Debug, Default)]
+struct Pending {
+    offset: u64,
+    /// The bytes already transferred
+    done: usize,
+    len: usize,
+    tag: u64,
+}
+
+/// A set of buffers, read from or written to a file through an io_uring instance
+///
+/// The short reads and writes are resubmitted until the whole buffer has been transferred, or
+/// the end of the file is reached.
+pub struct BufferQueue {
+    ring: IoUring,
+    op: IoringOp,
+    fd: RawFd,
+    buffers: Vec<AlignedBuffer>,
+    alignment: usize,
+    pending: Vec<Pending>,
+    free: Vec<usize>,
+    in_flight: usize,
+}
+
+impl BufferQueue {
+    /// `op` must be a read or a write, on `fd` which must stay open as long as the queue
+    ///
+    /// The buffers are aligned on `alignment`, for direct I/O.
+    pub fn new(
+        op: IoringOp,
+        fd: RawFd,
+        depth: u32,
+        buffer_size: usize,
+        alignment: usize,
+    ) -> io::Result<Self> {
+        Ok(BufferQueue {
+            ring: IoUring::new(depth)?,
+            op,
+            fd,
+            buffers: (0..depth).map(|_| AlignedBuffer::new(buffer_size, alignment)).collect(),
+            alignment,
+            pending: vec![Pending::default(); depth as usize],
+            free: (0..depth as usize).rev().collect(),
+            in_flight: 0,
+        })
+    }
+
+    /// A buffer not in flight, if any
+    pub fn free_buffer(&mut self) -> Option<usize> {
+        self.free.pop()
+    }
+
+    pub fn buffer_mut(&mut self, index: usize) -> &mut [u8] {
+        &mut self.buffers[index]
+    }
+
+    pub fn buffer(&self, index: usize) -> &[u8] {
+        &self.buffers[index]
+    }
+
+    /// The bytes read or written by the last completed operation on the buffer
+    pub fn transferred(&self, index: usize) -> usize {
+        self.pending[index].done
+    }
+
+    /// Make the buffer available again, once its operation has completed
+    pub fn release(&mut self, index: usize) {
+        self.free.push(index);
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    /// Read or write the first `len` bytes of the buffer at `offset` in the file
+    pub fn submit(&mut self, index: usize, offset: u64, len: usize, tag: u64) -> io::Result<()> {
+        self.pending[index] = Pending { offset, done: 0, len, tag };
+        self.in_flight += 1;
+        self.push(index);
+        self.ring.submit_and_wait(0)
+    }
+
+    fn push(&mut self, index: usize) {
+        let pending = &self.pending[index];
+        let buf = self.buffers[index][pending.done..].as_mut_ptr();
+        let len = (pending.len - pending.done) as u32;
+        // SAFETY: the buffer isn't accessed until its operation completes, and the queue waits
+        // for all the operations before being dropped
+        let pushed = unsafe {
+            self.ring.push(
+                self.op,
+                self.fd,
+                buf,
+                len,
+                pending.offset + pending.done as u64,
+                index as u64,
+            )
+        };
+        // there's never more operations than entries in the submission queue
+        assert!(pushed);
+    }
+
+    /// Wait for at least one operation to complete, and return the buffers and tags of the
+    /// completed operations, in no particular order
+    pub fn wait(&mut self) -> io::Result<Vec<(usize, u64)>> {
+        let mut completed = Vec::new();
+        while completed.is_empty() && self.in_flight > 0 {
+            self.ring.submit_and_wait(1)?;
+            let mut error = None;
+            while let Some((index, res)) = self.ring.pop() {
+                let index = index as usize;
+                if res < 0 || (res == 0 && self.op != IoringOp::Read) {
+                    self.in_flight -= 1;
+                    error.get_or_insert(if res == 0 {
+                        io::Error::from(io::ErrorKind::WriteZero)
+                    } else {
+                        io::Error::from_raw_os_error(-res)
+                    });
+                    continue;
+                }
+                let pending = &mut self.pending[index];
+                pending.done += res as usize;
+                // a read stops early at the end of the file, which may not be aligned
+                if res > 0
+                    && pending.done < pending.len
+                    && pending.done.is_multiple_of(self.alignment)
+                {
+                    self.push(index);
+                } else {
+                    self.in_flight -= 1;
+                    completed.push((index, pending.tag));
+                }
+            }
+            if let Some(error) = error {
+                return Err(error);
+            }
+        }
+        Ok(completed)
+    }
+}
+
+impl Drop for BufferQueue {
+    fn drop(&mut self) {
+        // the kernel may still be using the buffers
+        while self.in_flight > 0 {
+            if self.ring.submit_and_wait(1).is_err() {
+                std::mem::forget(std::mem::take(&mut self.buffers));
+                return;
+            }
+            while self.ring.pop().is_some() {
+                self.in_flight -= 1;
+            }
+        }
+    }
+}
+
+#[test]
+fn uring_write_and_read() {
+    use std::os::fd::AsRawFd as _;
+
+    let file = tempfile::tempfile().unwrap();
+    let fd = file.as_raw_fd();
+    let mut ring = IoUring::new(4).unwrap();
+    let mut data = *b"hello io_uring";
+    unsafe {
+        assert!(ring.push(IoringOp::Write, fd, data.as_mut_ptr(), data.len() as u32, 10, 1));
+    }
+    ring.submit_and_wait(1).unwrap();
+    assert_eq!(ring.pop(), Some((1, data.len() as i32)));
+    assert_eq!(ring.pop(), None);
+
+    let mut buffer = [0u8; 14];
+    unsafe {
+        assert!(ring.push(IoringOp::Read, fd, buffer.as_mut_ptr(), buffer.len() as u32, 10, 2));
+    }
+    ring.submit_and_wait(1).unwrap();
+    assert_eq!(ring.pop(), Some((2, buffer.len() as i32)));
+    assert_eq!(buffer, data);
+}
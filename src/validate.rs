@@ -1,133 +1,1711 @@
-use anyhow::anyhow;
-use clap::Args;
-use crc32fast::Hasher;
+use clap::{Args, ValueEnum as _, ValueHint};
 use itertools::Itertools as _;
-use log::{debug, info};
+use log::{debug, info, warn};
 use parse_size::parse_size;
-use std::fs::File;
-use std::io::{self, Read, Seek};
+use rand::{Rng, RngExt as _};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, IoSliceMut, Read, Seek, Write as _};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, mpsc};
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime};
 
-use crate::cli::CommonArgs;
-use crate::{Progress, log_metrics, read_exact_or_eof, read_file_size, receive_progress};
+#[cfg(unix)]
+use crate::cache::Advice;
+use crate::cache::{self, CachePolicy};
+use crate::checkpoint::{Checkpoint, SavedCheckpoint};
+use crate::checksum::{ChecksumAlgorithm, StreamChecksum};
+use crate::chunk::{CHUNK_HEADER_SIZE, ChunkFormat, ChunkHeader, parse_run_id, region_fingerprint};
+use crate::cli::{AUTO_CHUNK_SIZE, CommonArgs};
+use crate::compress::Compressibility;
+use crate::decompress::Decompress;
+use crate::dedupe::Dedupe;
+use crate::devices;
+use crate::diff::ChunkDiff;
+use crate::direct::{self, AlignedBuffer};
+use crate::engine::IoEngine;
+use crate::error::{CorruptedChunk, Error, ValidationError, corrupted_ranges, exit_code, usage};
+use crate::generate::{ChunkLayout, generate_chunk_with_layout};
+use crate::header::{HEADER_SIZE, StreamHeader};
+use crate::http;
+use crate::identity;
+use crate::journal::{Journal, Operation};
+#[cfg(unix)]
+use crate::mapping::{self, FileMapping};
+use crate::nbd::{ExportStream, NbdUri};
+use crate::net::{self, Endpoint, Scheme};
+use crate::pattern::Pattern;
+use crate::report::{self, Report, ThreadStats};
+use crate::retry::ReadRetry;
+use crate::rng::{RngAlgorithm, Seed, StreamRng};
+use crate::s3::S3Object;
+use crate::scrub::{self, ScrubState};
+use crate::sector;
+use crate::shard::{Shards, parse_shard_size};
+use crate::sparse;
+use crate::ssh::SshTarget;
+use crate::sumfile::ChecksumFile;
+use crate::systemd;
+use crate::tar;
+use crate::throttle::Throttle;
+use crate::tree::Manifest;
+use crate::udp;
+#[cfg(target_os = "linux")]
+use crate::uring::BufferQueue;
+use crate::watch::TargetWatch;
+use crate::work::{
+    self, Assignment, ChunkRecorder, Summarizer, ThreadOutput, ThreadPositions, ThreadWork,
+};
+use crate::{
+    ChunkChecksum, Progress, ProgressPhase, SeekableRng, StreamSummary, combine_summaries,
+    log_metrics, read_exact_or_eof, read_file_size, receive_progress,
+};
+
+/// The number of chunks read from stdin or the network ahead of each validating thread
+const STDIN_QUEUE_DEPTH: usize = 16;
+
+/// The number of chunks at the start of the stream whose headers tell the one of the first chunk,
+/// with `--format v2`
+const SAMPLED_HEADERS: u64 = 8;
+
+/// The size read at the start of the file to detect the chunk size, with `--chunk-size auto`
+const PROBE_SIZE: usize = 32 << 20;
 
 /// Validate a random stream
 ///
 /// If the input is a regular file or a block device, the data will be read
 /// from multiple locations in parallel to maximize the throughput.
-#[derive(Args, Debug)]
-#[command(alias = "read")]
+#[derive(Args, Clone, Debug)]
 pub struct ValidateArgs {
-    /// The input file
+    /// The input file, a `tcp://[host]:port` or `udp://[host]:port` address to receive the stream
+    /// on, an `nbd://host[:port]/export` or `nbd+unix:///export?socket=path` export to read, an
+    /// `http://` or `https://` URL to download the stream from, an `s3://bucket/key` object, or an
+    /// `ssh://[user@]host[:port]/path` remote file
     #[arg()]
     pub file: Option<PathBuf>,
 
+    /// More files or devices, read with the first one, each with its own stream
+    ///
+    /// With `--regenerate`, the seed of each one is derived from the seed, like with generate. A
+    /// table of the result of each file is logged at the end, then the totals. A quoted pattern,
+    /// like `'/exports/*.img'`, is expanded to the files matching it.
+    #[arg(
+        value_name = "FILES",
+        conflicts_with_all = [
+            "expected_checksum", "expected_digest", "checksum_file", "checkpoint", "shard_size",
+            "error_map", "badblocks_out"
+        ]
+    )]
+    pub more_files: Vec<PathBuf>,
+
+    /// Also read the files listed in this file, one per line, or on stdin with `-`
+    ///
+    /// The empty lines and the ones starting with `#` are skipped.
+    #[clap(
+        long,
+        value_name = "LIST",
+        value_hint = ValueHint::FilePath,
+        conflicts_with_all = [
+            "expected_checksum", "expected_digest", "checksum_file", "checkpoint", "shard_size",
+            "error_map", "badblocks_out"
+        ]
+    )]
+    pub from_file: Option<PathBuf>,
+
+    /// Validate the files of a tree generated with `generate --tree`, listed in this manifest,
+    /// each against its checksum
+    ///
+    /// The file is the directory of the tree. The stream options must be the ones of generate.
+    #[clap(
+        long,
+        value_name = "MANIFEST",
+        value_hint = ValueHint::FilePath,
+        requires = "file",
+        conflicts_with_all = [
+            "more_files", "from_file", "expected_checksum", "expected_digest", "checksum_file",
+            "checkpoint", "shard_size", "error_map", "badblocks_out"
+        ]
+    )]
+    pub manifest: Option<PathBuf>,
+
+    /// Read a tar archive, written by `generate --tar`, and validate the stream of each of its
+    /// files
+    ///
+    /// The archive is read from the file, or from stdin. The files are validated one after the
+    /// other, and the other members of the archive skipped. With `--regenerate`, the seed of each
+    /// file is derived from the seed, like with generate.
+    #[clap(
+        long,
+        conflicts_with_all = [
+            "more_files", "from_file", "manifest", "position", "length", "expected_checksum",
+            "expected_digest", "checksum_file", "checkpoint", "shard_size", "sample",
+            "sample_chunks", "sparse", "incremental", "error_map", "badblocks_out",
+            "print_checksum"
+        ]
+    )]
+    pub tar: bool,
+
+    /// Decompress the input, a file or stdin, before validating the stream
+    ///
+    /// With `auto`, a gzip, zstd or xz input is detected from its first bytes, and any other input
+    /// read as it is. The data goes through the `gzip`, `zstd` or `xz` command. The position, and
+    /// the offsets of the corrupted chunks, are the ones of the decompressed stream.
+    #[clap(long, value_enum, default_value_t, value_name = "FORMAT")]
+    pub decompress: Decompress,
+
+    /// The number of files read at once, with several files
+    #[clap(long, default_value = "16", value_name = "COUNT", value_parser = clap::value_parser!(u64).range(1..))]
+    pub file_jobs: u64,
+
     /// The stream position
-    #[clap(short, long, default_value = "0", value_parser=|s: &str| parse_size(s))]
+    #[clap(short, long, default_value = "0", value_name = "SIZE", value_hint = ValueHint::Other, value_parser=|s: &str| parse_size(s))]
     pub position: u64,
 
+    /// The size of the region to validate, from the stream position
+    ///
+    /// A region of a larger stream can be validated on its own, if it starts at a chunk boundary
+    /// of the stream, and spans whole chunks unless it ends with the stream. Its checksum is the
+    /// one of the region only.
+    #[clap(short, long, value_name = "SIZE", value_hint = ValueHint::Other, value_parser=|s: &str| parse_size(s), conflicts_with = "size")]
+    pub length: Option<u64>,
+
     /// The expected checksum
     ///
     /// Generates an error if it doesn't match the stream checksum
     #[clap(short, long)]
     pub expected_checksum: Option<String>,
 
+    /// The expected digest
+    ///
+    /// Generates an error if it doesn't match the stream digest
+    #[clap(long, requires = "digest")]
+    pub expected_digest: Option<String>,
+
+    /// Read the expected checksum, and the parameters of the stream, from this file written by
+    /// `generate --checksum-file`
+    #[clap(
+        long,
+        conflicts_with_all = [
+            "expected_checksum", "expected_digest", "position", "length", "size", "chunk_size",
+            "checksum", "format", "rng", "seed", "seed_string", "digest", "compress_ratio",
+            "dedupe_ratio", "pattern", "region_size", "sector_checksums", "shard_size", "sample",
+            "sample_chunks", "sparse", "incremental"
+        ]
+    )]
+    pub checksum_file: Option<PathBuf>,
+
+    /// Record the progress in this file, and resume from it if it exists
+    ///
+    /// The chunks validated by each thread, and the state of the stream checksum, are saved every
+    /// few seconds, so an interrupted run can be resumed without reading them again. The
+    /// checkpoint is removed once the stream is validated.
+    #[clap(long, requires = "file", conflicts_with_all = ["digest", "keep_going"])]
+    pub checkpoint: Option<PathBuf>,
+
+    /// Validate a stream split across several files of that size, by `generate --shard-size`
+    ///
+    /// The file name holds the index of the files, like `out-%04d.bin`. The stream size defaults to
+    /// the size of the consecutive files found.
+    #[clap(
+        long,
+        value_parser = parse_shard_size,
+        requires = "file",
+        conflicts_with_all = [
+            "position", "length", "checkpoint", "digest", "sample", "sample_chunks", "sparse",
+            "incremental"
+        ]
+    )]
+    pub shard_size: Option<u64>,
+
+    /// The ID of the run which must have written the chunks, with `--format v2`
+    ///
+    /// The chunks left by another run with the same seed are reported as stale. Defaults to the
+    /// run ID recorded in the stream header, or else in the first chunk.
+    #[clap(long, value_parser = parse_run_id)]
+    pub run_id: Option<u64>,
+
+    /// Compare the chunks with the data regenerated from the seed, not only with their checksum
+    ///
+    /// It catches the corruptions which still match the chunk checksum, and locates the bytes
+    /// which differ. The seed is read from the stream header, if any, or else given with `--seed`
+    /// or `--seed-string`, along with `--rng`. The stream must start at the position, unless its
+    /// chunks have headers, with `--format v2`.
+    #[clap(long)]
+    pub regenerate: bool,
+
+    /// Log a byte-level diff of each corrupted chunk against the regenerated data
+    ///
+    /// The damaged regions, the first differing bytes with their expected and found values, and
+    /// the number of differing bytes by number of flipped bits tell a bit flip from a torn or
+    /// misdirected write.
+    #[clap(long, requires = "regenerate")]
+    pub diff: bool,
+
+    /// The random generator seed, with `--regenerate`
+    ///
+    /// A decimal value, or an hexadecimal value of up to 256 bits prefixed with `0x`
+    #[clap(short = 'S', long, value_parser = Seed::parse, requires = "regenerate")]
+    pub seed: Option<Seed>,
+
+    /// The string the random generator seed is derived from, with `--regenerate`
+    #[clap(long, conflicts_with = "seed", requires = "regenerate")]
+    pub seed_string: Option<String>,
+
+    /// Derive the seed from the WWN or serial number of the device, like generate
+    /// `--seed-from-device`
+    ///
+    /// With `--format v2`, the chunks of the stream of another device are reported as coming from
+    /// another stream. Otherwise, it requires `--regenerate`. Only on Linux.
+    #[clap(long, requires = "file", conflicts_with_all = ["seed", "seed_string"])]
+    pub seed_from_device: bool,
+
+    /// Validate a random sample of the chunks, as a percentage of the stream, like `5%`
+    ///
+    /// The chunks are picked at random, one in each of equal regions of the stream, so the sample
+    /// is spread across the whole device. Each chunk is validated on its own: the stream
+    /// checksum isn't computed.
+    #[clap(
+        long,
+        value_parser = parse_percentage,
+        requires = "file",
+        conflicts_with_all = ["sample_chunks", "expected_checksum", "digest", "checkpoint"]
+    )]
+    pub sample: Option<f64>,
+
+    /// Validate a random sample of this number of chunks, spread like with `--sample`
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u64).range(1..),
+        requires = "file",
+        conflicts_with_all = ["expected_checksum", "digest", "checkpoint"]
+    )]
+    pub sample_chunks: Option<u64>,
+
+    /// Skip the chunks in the holes of a sparse file or a thin-provisioned image
+    ///
+    /// The holes are found with SEEK_HOLE, instead of being read as zeros and reported as
+    /// corrupted chunks. The chunks holding some data are validated each on its own, like with
+    /// `--sample`: the stream checksum isn't computed.
+    #[clap(
+        long,
+        requires = "file",
+        conflicts_with_all = ["sample", "sample_chunks", "expected_checksum", "digest", "checkpoint"]
+    )]
+    pub sparse: bool,
+
+    /// Validate only the chunks not validated within `--incremental-window`, recording when each
+    /// chunk was validated in this state file
+    ///
+    /// The state file is created if it doesn't exist. The chunks found corrupted aren't recorded,
+    /// so they're validated again by the next run. Like with `--sample`, each chunk is validated
+    /// on its own: the stream checksum isn't computed.
+    #[clap(
+        long,
+        value_name = "STATE",
+        requires = "file",
+        conflicts_with_all = [
+            "sample", "sample_chunks", "sparse", "expected_checksum", "digest", "checkpoint"
+        ]
+    )]
+    pub incremental: Option<PathBuf>,
+
+    /// The number of days after which a chunk is validated again, with `--incremental`
+    #[clap(long, value_name = "DAYS", default_value = "30", requires = "incremental")]
+    pub incremental_window: u64,
+
+    /// The most data to validate in this run, with `--incremental`, the least recently validated
+    /// chunks first
+    ///
+    /// A full scrub of a large stream is then spread across several runs.
+    #[clap(long, value_name = "SIZE", value_hint = ValueHint::Other, value_parser=|s: &str| parse_size(s), requires = "incremental")]
+    pub incremental_size: Option<u64>,
+
+    /// Retry the reads failing with an I/O error this number of times, before failing
+    ///
+    /// The chunks read after retrying are counted, and logged at the end. Only with the sync
+    /// engine.
+    #[clap(long, default_value = "0", value_name = "COUNT")]
+    pub read_retries: u32,
+
+    /// The delay before the first retry of a read, in milliseconds, doubled at each retry
+    #[clap(long, default_value = "100", value_name = "MILLISECONDS", requires = "read_retries")]
+    pub retry_delay: u64,
+
+    /// Reopen the file, and drop the chunks from the page cache, before retrying a read
+    #[clap(long, requires = "read_retries")]
+    pub retry_reopen: bool,
+
+    #[clap(flatten)]
+    pub corruption: CorruptionArgs,
+
     #[clap(flatten)]
     pub common: CommonArgs,
 }
 
-pub fn validate(args: &ValidateArgs, cancel: Arc<AtomicBool>) -> anyhow::Result<i32> {
+impl ValidateArgs {
+    /// The size to validate, if known before reading the input
+    fn size(&self) -> Option<u64> {
+        self.length.or(self.common.size)
+    }
+
+    /// The seed of the stream, with `--regenerate`
+    fn seed(&self) -> Option<Seed> {
+        self.seed_string.as_deref().map(Seed::from_string).or(self.seed)
+    }
+
+    /// The arguments with the seed derived from the device, with `--seed-from-device`
+    fn with_device_seed(&self) -> anyhow::Result<ValidateArgs> {
+        match &self.file {
+            Some(file) if self.seed_from_device => {
+                if !self.regenerate && self.common.format != ChunkFormat::V2 {
+                    return Err(usage("--seed-from-device requires --format v2 or --regenerate"));
+                }
+                Ok(ValidateArgs {
+                    seed: Some(identity::device_seed(file)?),
+                    seed_string: None,
+                    seed_from_device: false,
+                    ..self.clone()
+                })
+            }
+            _ => Ok(self.clone()),
+        }
+    }
+
+    /// The arguments with the chunk size detected at the start of the file, with `--chunk-size
+    /// auto`
+    ///
+    /// `file` is the local file holding the stream, if any.
+    fn with_chunk_size(&self, file: Option<&Path>) -> anyhow::Result<ValidateArgs> {
+        if self.common.chunk_size != AUTO_CHUNK_SIZE {
+            return Ok(self.clone());
+        }
+        let Some(file) = file else {
+            return Err(usage("--chunk-size auto requires a file"));
+        };
+        let probe_size =
+            self.size().map_or(PROBE_SIZE, |size| size.min(PROBE_SIZE as u64) as usize);
+        let mut data = vec![0; probe_size];
+        let mut f = File::open(file)?;
+        f.seek(io::SeekFrom::Start(self.position))?;
+        let size = read_exact_or_eof(&mut f, &mut data)?;
+        // the whole stream may have been read
+        let whole = size < PROBE_SIZE;
+        let data = match StreamHeader::decode(&data[..size]) {
+            Some(_) => &data[HEADER_SIZE..size],
+            None => &data[..size],
+        };
+        let chunk_size = detect_chunk_size(data, whole, self.common.checksum).ok_or_else(|| {
+            usage(format!(
+                "Can't detect the chunk size of {}: none of the usual sizes matches the checksums \
+                 of the first chunks",
+                file.display()
+            ))
+        })?;
+        info!("detected chunk size: {chunk_size}");
+        Ok(ValidateArgs {
+            common: CommonArgs { chunk_size: chunk_size as u64, ..self.common.clone() },
+            ..self.clone()
+        })
+    }
+
+    /// Whether several files are read, each with its own stream
+    fn is_batch(&self) -> bool {
+        !self.more_files.is_empty()
+            || self.from_file.is_some()
+            || self.manifest.is_some()
+            || self.file.as_deref().is_some_and(devices::is_pattern)
+    }
+
+    /// All the files to read, with `FILES`, the patterns expanded, and `--from-file`, or the
+    /// files of the tree with their expected checksum, with `--manifest`
+    fn files(&self) -> anyhow::Result<Vec<(PathBuf, Option<String>)>> {
+        if let (Some(manifest), Some(tree)) = (&self.manifest, &self.file) {
+            let manifest = Manifest::load(manifest, tree)?;
+            let entries = manifest.entries.into_iter();
+            return Ok(entries.map(|entry| (entry.path, Some(entry.checksum))).collect());
+        }
+        let mut files = Vec::new();
+        for file in self.file.iter().chain(&self.more_files) {
+            match devices::is_pattern(file) {
+                true => files.extend(devices::expand(file)?),
+                false => files.push(file.clone()),
+            }
+        }
+        if let Some(list) = &self.from_file {
+            files.extend(devices::read_list(list)?);
+        }
+        if files.is_empty() {
+            return Err(usage("There's no file to validate"));
+        }
+        Ok(files.into_iter().map(|file| (file, None)).collect())
+    }
+
+    /// The arguments reading one of the files, with its own seed
+    pub(crate) fn for_device(&self, index: usize, file: &Path) -> ValidateArgs {
+        ValidateArgs {
+            file: Some(file.to_path_buf()),
+            more_files: Vec::new(),
+            from_file: None,
+            manifest: None,
+            tar: false,
+            seed: self.seed().map(|seed| seed.for_device(index)),
+            seed_string: None,
+            common: self.common.for_device(file),
+            ..self.clone()
+        }
+    }
+
+    /// How the failed reads are retried, with `--read-retries`
+    fn read_retry(&self) -> anyhow::Result<ReadRetry> {
+        if self.read_retries > 0 && self.common.engine != IoEngine::Sync {
+            return Err(usage("--read-retries requires the sync engine"));
+        }
+        let delay = Duration::from_millis(self.retry_delay);
+        Ok(ReadRetry::new(self.read_retries, delay, self.retry_reopen))
+    }
+
+    /// The number of chunks to validate with `--sample` or `--sample-chunks`
+    fn sample_size(&self, num_chunks: u64) -> Option<u64> {
+        let size = match (self.sample, self.sample_chunks) {
+            (Some(percent), _) => ((num_chunks as f64 * percent / 100.0).ceil() as u64).max(1),
+            (None, Some(chunks)) => chunks,
+            (None, None) => return None,
+        };
+        Some(size.min(num_chunks))
+    }
+}
+
+/// How the corrupted chunks are handled
+#[derive(Args, Clone, Debug)]
+pub struct CorruptionArgs {
+    /// Validate the whole stream, even after a corrupted chunk
+    ///
+    /// The corrupted chunks are summarized at the end, and the validation fails.
+    #[clap(long)]
+    pub keep_going: bool,
+
+    /// Write the map of the corrupted chunks to this file, in JSON
+    ///
+    /// Each chunk is listed with its index, offset in the file, length, and expected and found
+    /// checksums.
+    #[clap(long, requires = "keep_going")]
+    pub error_map: Option<PathBuf>,
+
+    /// Write the blocks holding the corrupted chunks to this file, one number per line
+    ///
+    /// It's the bad blocks list accepted by `e2fsck -l` and `mkfs -l`.
+    #[clap(long, requires = "keep_going")]
+    pub badblocks_out: Option<PathBuf>,
+
+    /// The size of the blocks listed with `--badblocks-out`
+    ///
+    /// Use the block size of the filesystem for `e2fsck -l` and `mkfs -l`.
+    #[clap(long, default_value = "512", requires = "badblocks_out", value_parser = parse_block_size)]
+    pub badblocks_block_size: u64,
+}
+
+pub fn validate(args: &ValidateArgs, cancel: Arc<AtomicBool>) -> Result<i32, Error> {
+    let loaded;
+    let args = match &args.checksum_file {
+        Some(path) => {
+            loaded = ChecksumFile::load(path)?.validate_args(args);
+            &loaded
+        }
+        None => args,
+    };
+    if args.tar {
+        return tar::validate_tar(args, cancel).map_err(Error::from);
+    }
+    if args.common.print_checksum && args.is_batch() {
+        return Err(usage("--print-checksum takes a single file").into());
+    }
+    if args.is_batch() {
+        let (files, checksums): (Vec<_>, Vec<_>) = args.files()?.into_iter().unzip();
+        let output = args.common.output;
+        return Ok(devices::run(
+            "validate",
+            output,
+            &files,
+            args.file_jobs as usize,
+            &cancel,
+            |index, file, cancel, report| {
+                let args = ValidateArgs {
+                    expected_checksum: checksums[index].clone(),
+                    ..args.for_device(index, file)
+                };
+                validate_stream(&args, cancel, report)
+            },
+        ));
+    }
+    let mut report = Report::new("validate", args.common.output);
+    let result = validate_stream(args, cancel, &mut report);
+    if args.common.print_checksum {
+        report.print_checksum(&result);
+    }
+    report.finish(&result, true);
+    result.map_err(Error::from)
+}
+
+pub(crate) fn validate_stream(
+    args: &ValidateArgs,
+    cancel: Arc<AtomicBool>,
+    report: &mut Report,
+) -> anyhow::Result<i32> {
     let start = Instant::now();
+    let args = &args.with_device_seed()?;
+    args.common.check_format()?;
+
+    let endpoint = args.file.as_deref().map(Endpoint::parse).transpose()?.flatten();
+    let url = args.file.as_deref().and_then(http::url);
+    let object = args.file.as_deref().map(S3Object::parse).transpose()?.flatten();
+    let ssh = args.file.as_deref().map(SshTarget::parse).transpose()?.flatten();
+    let remote = endpoint.is_some() || url.is_some() || object.is_some() || ssh.is_some();
+    if remote {
+        reject_file_options(
+            args,
+            "a network address",
+            &[("--decompress", args.decompress != Decompress::None)],
+        )?;
+    }
+    if let Some(endpoint) = &endpoint
+        && endpoint.scheme() == Scheme::Udp
+    {
+        return receive_datagrams(args, endpoint, &cancel, report, start);
+    }
+    let export = match args.file.as_deref().map(NbdUri::parse).transpose()?.flatten() {
+        Some(uri) => {
+            reject_file_options(
+                args,
+                "an NBD export",
+                &[("--decompress", args.decompress != Decompress::None)],
+            )?;
+            let export = uri.connect()?;
+            if args.position > export.size() {
+                return Err(usage(format!(
+                    "The position {} is greater than the export size {}",
+                    args.position,
+                    export.size()
+                )));
+            }
+            Some(export)
+        }
+        None => None,
+    };
+    let local = args.file.as_deref().filter(|_| !remote && export.is_none());
+    let compression = match local {
+        Some(file) if args.decompress == Decompress::Auto => {
+            let mut magic = [0; 6];
+            let size = read_exact_or_eof(&mut File::open(file)?, &mut magic)?;
+            Decompress::detect(&magic[..size])
+        }
+        _ => args.decompress,
+    };
+    // a compressed file is read as a stream, like stdin
+    let compressed = local.filter(|_| compression != Decompress::None);
+    if compressed.is_some() {
+        reject_file_options(args, "a compressed file", &[])?;
+    }
+    let local = local.filter(|_| compressed.is_none());
+    if args.common.duration.is_some() {
+        // the threads must take the chunks from the queue, in the stream order
+        let mut options = seekable_options(args).to_vec();
+        options.push(("--digest sha256", args.common.digest.is_some_and(|d| !d.is_combinable())));
+        net::reject_options("--duration", &options)?;
+    }
+    let args = &args.with_chunk_size(local.filter(|_| args.shard_size.is_none()))?;
     let chunk_size = args.common.chunk_size as usize;
+    let mut expired = false;
+    let (summary, corrupted) = if let Some(shard_size) = args.shard_size {
+        validate_shards(args, shard_size, &cancel, report)?
+    } else if let Some(file) = local {
+        let total_size = resolve_stream_size(args, file)?;
 
-    let (bytes_validated, checksum) = if let Some(file) = &args.file {
-        let stream_size = resolve_stream_size(args, file)?;
-        let mut pb = Progress::new(Some(stream_size), args.common.no_progress)?;
+        let mut prefix = vec![0; HEADER_SIZE.min(total_size as usize)];
+        let mut f = File::open(file)?;
+        f.seek(io::SeekFrom::Start(args.position))?;
+        let prefix_size = read_exact_or_eof(&mut f, &mut prefix)?;
+        let header = read_header(&prefix[..prefix_size]);
+        let header_size = if header.is_some() { HEADER_SIZE as u64 } else { 0 };
+        let stream_size = total_size - header_size;
+        let position = args.position + header_size;
+        let alignment = direct::alignment(&args.common, file)?;
+        let chunk_size = direct::align_chunk_size(chunk_size, position, alignment)?;
+        let region_chunks = region_chunks(args, header, chunk_size)?;
+        let sector_size = args.common.sector_size(chunk_size)?;
+        let mut data = [0; CHUNK_HEADER_SIZE];
+        f.seek(io::SeekFrom::Start(position))?;
+        let size = read_exact_or_eof(&mut f, &mut data)?;
+        let first_header = match chunk_format(args, header, &data[..size]) {
+            ChunkFormat::V1 => None,
+            ChunkFormat::V2 => {
+                let length = stream_size.min(chunk_size as u64);
+                let headers = read_chunk_headers(&mut f, position, chunk_size, stream_size)?;
+                first_chunk_header(&headers, header, length, args, region_chunks)?
+            }
+        };
 
         debug!("position: {}", args.position);
         debug!("stream size: {stream_size}");
         debug!("chunk size: {chunk_size}");
+        debug!("alignment: {alignment}");
 
-        validate_from_file(args, file, stream_size, chunk_size, &mut pb, &cancel)?
+        let cache = args.common.cache_policy();
+        let stream = StreamParams {
+            position,
+            stream_size,
+            chunk_size,
+            alignment,
+            cache,
+            batch_chunks: args.common.batch_chunks as usize,
+            throttle: args.common.throttle(),
+            keep_going: args.corruption.keep_going,
+            first_header,
+            region_chunks,
+            sector_size,
+            checksum: args.common.checksum,
+            regenerate: regeneration(args, header, region_chunks, sector_size)?,
+            diff: args.diff,
+            first_chunk: 0,
+            num_chunks: stream_size.div_ceil(chunk_size as u64),
+            journal: args.common.journal()?,
+            retry: args.read_retry()?,
+        };
+        let num_chunks = stream_size.div_ceil(chunk_size as u64);
+        if let Some(count) = args.sample_size(num_chunks) {
+            let chunks = sample_chunks(num_chunks, count, &mut rand::rng());
+            info!("sampled chunks: {} of {num_chunks}", chunks.len());
+            return validate_sample(args, file, &stream, &chunks, None, &cancel, report, start);
+        }
+        if args.sparse {
+            let range = position..position + stream_size;
+            let ranges = sparse::data_ranges(&File::open(file)?, range)?;
+            let chunks = sparse::chunks_with_data(&ranges, position, chunk_size as u64);
+            info!("chunks in holes: {} of {num_chunks}", num_chunks - chunks.len() as u64);
+            return validate_sample(args, file, &stream, &chunks, None, &cancel, report, start);
+        }
+        if let Some(path) = &args.incremental {
+            let identity = stream.identity(args.common.checksum);
+            let mut state = ScrubState::load(path, identity, num_chunks)?;
+            let window = args.incremental_window * 24 * 3600;
+            let mut chunks = state.due(scrub::now(), window);
+            let due = chunks.len();
+            if let Some(size) = args.incremental_size {
+                chunks.truncate(size.div_ceil(chunk_size as u64) as usize);
+            }
+            chunks.sort();
+            info!("chunks due: {due} of {num_chunks}, validating {}", chunks.len());
+            let state = Some(&mut state);
+            return validate_sample(args, file, &stream, &chunks, state, &cancel, report, start);
+        }
+        let mut pb = Progress::new(
+            Some(total_size),
+            args.common.no_progress,
+            args.common.progress,
+            args.common.device.as_deref(),
+            args.common.progress_callback.as_ref(),
+        )?;
+        let (mut summary, corrupted) =
+            validate_from_file(args, file, &stream, &mut pb, &cancel, report)?;
+        stream.report_retries(report);
+        summary.bytes += header_size;
+        if args.common.duration.is_some()
+            && summary.bytes < total_size
+            && !cancel.load(Ordering::Relaxed)
+        {
+            info!("stopped at the --duration, {} of {total_size} bytes validated", summary.bytes);
+            expired = true;
+        }
+        (summary, corrupted)
     } else {
-        let mut pb = Progress::new(None, args.common.no_progress)?;
+        let (mut input, available): (Box<dyn Read>, _) = if let Some(endpoint) = &endpoint {
+            let Some(socket) = endpoint.accept(&cancel)? else {
+                return Ok(exit_code::INTERRUPTED);
+            };
+            (skip_to_position(Box::new(socket), args.position)?, None)
+        } else if let Some(export) = export {
+            // an export is read up to its end
+            let available = export.size() - args.position;
+            (Box::new(ExportStream::new(export, args.position)), Some(available))
+        } else if let Some(url) = url {
+            let connections = args.common.jobs.unwrap_or(num_cpus::get_physical());
+            http::download(url, args.position, args.size(), connections)?
+        } else if let Some(object) = &object {
+            let connections = args.common.jobs.unwrap_or(num_cpus::get_physical());
+            let (input, available) = object.download(args.position, args.size(), connections)?;
+            (input, Some(available))
+        } else if let Some(ssh) = &ssh {
+            (Box::new(ssh.read(args.position)?), None)
+        } else if let Some(file) = compressed {
+            let input = compression.reader(Box::new(BufReader::new(File::open(file)?)))?;
+            (skip_to_position(input, args.position)?, None)
+        } else {
+            let input = args.decompress.reader(Box::new(io::stdin()))?;
+            (skip_to_position(input, args.position)?, None)
+        };
+        let mut pb = Progress::new(
+            args.size().or(available),
+            args.common.no_progress,
+            args.common.progress,
+            args.common.device.as_deref(),
+            args.common.progress_callback.as_ref(),
+        )?;
 
         debug!("position: {}", args.position);
         debug!(
             "stream size: {}",
-            if let Some(size) = args.common.size { size.to_string() } else { "∞".to_string() }
+            if let Some(size) = args.size() { size.to_string() } else { "∞".to_string() }
         );
         debug!("chunk size: {chunk_size}");
 
-        validate_from_stdin(args, chunk_size, &mut pb)?
+        validate_from_reader(args, &mut input, chunk_size, &mut pb, &cancel, report)?
     };
+    conclude(args, summary, &corrupted, expired, start, &cancel, report)
+}
+
+/// Validate the stream received on a connection
+pub(crate) fn validate_connection(
+    args: &ValidateArgs,
+    input: Box<dyn Read>,
+    cancel: Arc<AtomicBool>,
+    report: &mut Report,
+) -> anyhow::Result<i32> {
+    let start = Instant::now();
+    let mut input = skip_to_position(input, args.position)?;
+    let mut pb = Progress::new(
+        args.size(),
+        args.common.no_progress,
+        args.common.progress,
+        args.common.device.as_deref(),
+        args.common.progress_callback.as_ref(),
+    )?;
+    let chunk_size = args.common.chunk_size as usize;
+    let (summary, corrupted) =
+        validate_from_reader(args, &mut input, chunk_size, &mut pb, &cancel, report)?;
+    conclude(args, summary, &corrupted, false, start, &cancel, report)
+}
+
+/// Check the summary of the stream validated, and the corrupted chunks found
+///
+/// The summary of a run `expired` at the `--duration` is the one of the start of the stream: it's
+/// not compared to the expected checksum and digest.
+#[allow(clippy::too_many_arguments)]
+fn conclude(
+    args: &ValidateArgs,
+    summary: StreamSummary,
+    corrupted: &[CorruptedChunk],
+    expired: bool,
+    start: Instant,
+    cancel: &AtomicBool,
+    report: &mut Report,
+) -> anyhow::Result<i32> {
+    report.bytes = summary.bytes;
 
     // Check if operation was cancelled
     if cancel.load(Ordering::Relaxed) {
-        log_metrics(start, bytes_validated, "read bytes");
-        return Ok(130);
+        log_metrics(start, summary.bytes, "read bytes");
+        return Ok(exit_code::INTERRUPTED);
     }
 
+    write_corruption_maps(&args.corruption, corrupted)?;
+
+    let checksum = summary.checksum.finalize();
+    let digest = summary.digest.as_ref().map(|d| d.finalize());
+    report.checksum = Some(args.common.checksum.format(checksum));
+    report.digest = digest.clone();
+    if !corrupted.is_empty() {
+        let error = summarize_corruption(corrupted, report);
+        log_metrics(start, summary.bytes, "read bytes");
+        return Err(error.into());
+    }
+    if expired && (args.expected_checksum.is_some() || args.expected_digest.is_some()) {
+        warn!("the stream wasn't validated to its end: its expected checksum isn't checked");
+    }
     if let Some(expected_checksum) = &args.expected_checksum
-        && expected_checksum != &format!("{checksum:08x}")
+        && !expired
+        && expected_checksum != &args.common.checksum.format(checksum)
     {
-        return Err(anyhow!(
-            "Checksum mismatch. It was expected to be {expected_checksum}, but is actually {checksum:x}"
-        ));
+        return Err(ValidationError::StreamChecksum {
+            expected: expected_checksum.clone(),
+            actual: format!("{checksum:x}"),
+        }
+        .into());
+    }
+    if !report.is_json() {
+        let checksum = args.common.checksum.format(checksum);
+        info!("{}checksum: {checksum}", args.common.device_prefix());
+    }
+    if let Some(digest) = &digest {
+        if let Some(expected_digest) = &args.expected_digest
+            && !expired
+            && expected_digest != digest
+        {
+            return Err(ValidationError::Digest {
+                expected: expected_digest.clone(),
+                actual: digest.clone(),
+            }
+            .into());
+        }
+        if !report.is_json() {
+            info!("{}digest: {digest}", args.common.device_prefix());
+        }
     }
-    info!("checksum: {checksum:08x}");
-    log_metrics(start, bytes_validated, "read bytes");
+    log_metrics(start, summary.bytes, "read bytes");
     Ok(0)
 }
 
+/// The validation error of a chunk, with its location in the file
+pub(crate) fn locate_corruption(
+    error: anyhow::Error,
+    chunk: u64,
+    offset: u64,
+    length: usize,
+) -> anyhow::Error {
+    match error.downcast() {
+        Ok(error) => CorruptedChunk { chunk, offset, length: length as u64, error }.into(),
+        Err(error) => error,
+    }
+}
+
+/// Write the corrupted chunks to the files requested with `--error-map` and `--badblocks-out`
+fn write_corruption_maps(args: &CorruptionArgs, corrupted: &[CorruptedChunk]) -> io::Result<()> {
+    if let Some(path) = &args.error_map {
+        fs::write(path, report::error_map(corrupted))?;
+    }
+    if let Some(path) = &args.badblocks_out {
+        fs::write(path, report::badblocks(corrupted, args.badblocks_block_size))?;
+    }
+    Ok(())
+}
+
+/// Log the chunks which failed the validation with `--keep-going`, sorted by offset, and return
+/// the error summarizing them
+///
+/// The misplaced chunks are reported apart, since they point at another problem than the
+/// corrupted data, like a bug in the mapping of the blocks of the device.
+fn summarize_corruption(corrupted: &[CorruptedChunk], report: &mut Report) -> ValidationError {
+    let (mut misplaced, mut stale) = (0, 0);
+    for chunk in corrupted {
+        let message = match chunk.error {
+            ValidationError::Misplaced { offset, data_offset, .. } => {
+                misplaced += 1;
+                match data_offset {
+                    Some(data_offset) => format!(
+                        "misplaced chunk at offset {offset}: contains data for offset {data_offset}"
+                    ),
+                    None => format!(
+                        "misplaced chunk at offset {offset}: contains data from outside the stream"
+                    ),
+                }
+            }
+            ValidationError::Stale { offset, found, .. } => {
+                stale += 1;
+                format!("stale chunk at offset {offset}: written by the run {found:016x}")
+            }
+            _ => format!("chunk {} at offset {}: {}", chunk.chunk, chunk.offset, chunk.error),
+        };
+        warn!("{message}");
+        report.errors.push(message);
+    }
+    report.corrupted = corrupted.to_vec();
+    warn!("corrupted chunks: {}", corrupted.len() - misplaced - stale);
+    if misplaced > 0 {
+        warn!("misplaced chunks: {misplaced}");
+    }
+    if stale > 0 {
+        warn!("stale chunks: {stale}");
+    }
+    warn!("first corrupted offset: {}", corrupted[0].offset);
+    warn!("last corrupted offset: {}", corrupted[corrupted.len() - 1].offset);
+    for range in corrupted_ranges(corrupted) {
+        warn!(
+            "corrupted bytes: {}..{} ({} bytes)",
+            range.start,
+            range.end,
+            range.end - range.start
+        );
+    }
+    ValidationError::Corrupted { chunks: corrupted.len(), misplaced, stale }
+}
+
+fn parse_block_size(s: &str) -> Result<u64, String> {
+    match parse_size(s) {
+        Ok(0) => Err("the block size can't be 0".to_string()),
+        size => size.map_err(|e| e.to_string()),
+    }
+}
+
+fn parse_percentage(s: &str) -> Result<f64, String> {
+    let percent: f64 = s.strip_suffix('%').unwrap_or(s).parse().map_err(|e| format!("{e}"))?;
+    if percent > 0.0 && percent <= 100.0 {
+        Ok(percent)
+    } else {
+        Err("expected a percentage between 0 and 100".to_string())
+    }
+}
+
+/// Pick `count` chunks at random, one in each of `count` equal regions of the stream
+///
+/// The chunks are sorted, and spread across the whole stream.
+fn sample_chunks(num_chunks: u64, count: u64, rng: &mut impl Rng) -> Vec<u64> {
+    let count = count.min(num_chunks) as u128;
+    let bound = |i: u128| (i * num_chunks as u128 / count) as u64;
+    (0..count).map(|i| rng.random_range(bound(i)..bound(i + 1))).collect()
+}
+
+/// Validate the stream split across the shards, as a single stream
+///
+/// The corrupted chunks are reported with their index and offset in the whole stream.
+fn validate_shards(
+    args: &ValidateArgs,
+    shard_size: u64,
+    cancel: &Arc<AtomicBool>,
+    report: &mut Report,
+) -> anyhow::Result<(StreamSummary, Vec<CorruptedChunk>)> {
+    let template = args.file.as_deref().unwrap();
+    let alignment = direct::alignment(&args.common, template)?;
+    let chunk_size = direct::align_chunk_size(args.common.chunk_size as usize, 0, alignment)?;
+    let shards = Shards::new(template, shard_size, chunk_size)?;
+    let stream_size = match args.common.size {
+        Some(size) => size,
+        None => shards.stored_size()?,
+    };
+    debug!("stream size: {stream_size}");
+    debug!("chunk size: {chunk_size}");
+    debug!("shards: {}", shards.count(stream_size));
+
+    let region_chunks = region_chunks(args, None, chunk_size)?;
+    let sector_size = args.common.sector_size(chunk_size)?;
+    let mut data = [0; CHUNK_HEADER_SIZE];
+    let mut f = File::open(shards.path(0))?;
+    let size = read_exact_or_eof(&mut f, &mut data)?;
+    let first_header = match chunk_format(args, None, &data[..size]) {
+        ChunkFormat::V1 => None,
+        ChunkFormat::V2 => {
+            let length = stream_size.min(chunk_size as u64);
+            let headers = read_chunk_headers(&mut f, 0, chunk_size, stream_size)?;
+            first_chunk_header(&headers, None, length, args, region_chunks)?
+        }
+    };
+    let stream = StreamParams {
+        position: 0,
+        stream_size,
+        chunk_size,
+        alignment,
+        cache: args.common.cache_policy(),
+        batch_chunks: args.common.batch_chunks as usize,
+        throttle: args.common.throttle(),
+        keep_going: args.corruption.keep_going,
+        first_header,
+        region_chunks,
+        sector_size,
+        checksum: args.common.checksum,
+        regenerate: regeneration(args, None, region_chunks, sector_size)?,
+        diff: args.diff,
+        first_chunk: 0,
+        num_chunks: stream_size.div_ceil(chunk_size as u64),
+        journal: args.common.journal()?,
+        retry: args.read_retry()?,
+    };
+    let mut summaries = Vec::new();
+    let mut corrupted = Vec::new();
+    for index in 0..shards.count(stream_size) {
+        let range = shards.range(index, stream_size);
+        let first_chunk = range.start / chunk_size as u64;
+        let shard = StreamParams {
+            stream_size: range.end - range.start,
+            first_header: first_header.map(|h| h.following(first_chunk, h.length, region_chunks)),
+            first_chunk,
+            ..stream.clone()
+        };
+        let path = shards.path(index);
+        debug!("shard {index}: {}", path.display());
+        // each shard has its own progress, the threads reporting the bytes done since its start
+        let mut pb = Progress::new(
+            Some(shard.stream_size),
+            args.common.no_progress,
+            args.common.progress,
+            args.common.device.as_deref(),
+            args.common.progress_callback.as_ref(),
+        )?;
+        let (summary, shard_corrupted) =
+            validate_from_file(args, &path, &shard, &mut pb, cancel, report)?;
+        summaries.push(summary);
+        corrupted.extend(shard_corrupted);
+        if cancel.load(Ordering::Relaxed) {
+            break;
+        }
+    }
+    stream.report_retries(report);
+    Ok((combine_summaries(summaries), corrupted))
+}
+
+/// Rewrite the chunks of the stream in the file with their regenerated data, with `repair`
+///
+/// `chunks` are the indexes of the chunks in the stream. Once flushed, each one is read back, and
+/// must then match its regenerated data.
+pub(crate) fn repair_chunks(
+    args: &ValidateArgs,
+    file: &Path,
+    chunks: &[u64],
+) -> anyhow::Result<()> {
+    let total_size = resolve_stream_size(args, file)?;
+    let mut f = OpenOptions::new().read(true).write(true).open(file)?;
+    let mut prefix = vec![0; HEADER_SIZE.min(total_size as usize)];
+    f.seek(io::SeekFrom::Start(args.position))?;
+    let prefix_size = read_exact_or_eof(&mut f, &mut prefix)?;
+    let header = read_header(&prefix[..prefix_size]);
+    let header_size = if header.is_some() { HEADER_SIZE as u64 } else { 0 };
+    let stream_size = total_size - header_size;
+    let position = args.position + header_size;
+    let chunk_size = args.common.chunk_size as usize;
+    let region_chunks = region_chunks(args, header, chunk_size)?;
+    let sector_size = args.common.sector_size(chunk_size)?;
+    let mut data = [0; CHUNK_HEADER_SIZE];
+    f.seek(io::SeekFrom::Start(position))?;
+    let size = read_exact_or_eof(&mut f, &mut data)?;
+    let first_header = match chunk_format(args, header, &data[..size]) {
+        ChunkFormat::V1 => None,
+        ChunkFormat::V2 => {
+            let length = stream_size.min(chunk_size as u64);
+            let headers = read_chunk_headers(&mut f, position, chunk_size, stream_size)?;
+            first_chunk_header(&headers, header, length, args, region_chunks)?
+        }
+    };
+    let regeneration = regeneration(args, header, region_chunks, sector_size)?
+        .expect("the chunks repaired are regenerated");
+    let mut regenerator = Regenerator::new(regeneration, chunk_size, args.common.checksum);
+
+    let num_chunks = stream_size.div_ceil(chunk_size as u64);
+    // the place of each chunk in the file and in the stream
+    let chunk_params = |chunk: u64| {
+        let length = (stream_size - chunk * chunk_size as u64).min(chunk_size as u64);
+        let header = first_header.map(|first| first.following(chunk, length, region_chunks));
+        let index = header.map_or(chunk, |h| h.index);
+        (position + chunk * chunk_size as u64, length as usize, index, header)
+    };
+    for chunk in chunks {
+        if *chunk >= num_chunks {
+            return Err(usage(format!("The chunk {chunk} is past the end of the stream")));
+        }
+        let (offset, length, index, header) = chunk_params(*chunk);
+        f.seek(io::SeekFrom::Start(offset))?;
+        f.write_all(regenerator.chunk(index, length, header))?;
+        info!("repaired chunk {chunk} at offset {offset}");
+    }
+    f.sync_all()?;
+    let mut buffer = vec![0; chunk_size];
+    for chunk in chunks {
+        let (offset, length, index, header) = chunk_params(*chunk);
+        // read from the device, not from the page cache
+        cache::evict(&f, offset..offset + length as u64)?;
+        f.seek(io::SeekFrom::Start(offset))?;
+        f.read_exact(&mut buffer[..length])?;
+        regenerator.check(*chunk, index, offset, &buffer[..length], header)?;
+    }
+    Ok(())
+}
+
 fn resolve_stream_size(args: &ValidateArgs, file: &Path) -> anyhow::Result<u64> {
     if let Some(size) = &args.common.size {
         return Ok(*size);
     }
     let size = read_file_size(file)?;
     if args.position > size {
-        return Err(anyhow!("The position {} is greater than the file size {size}", args.position));
+        return Err(usage(format!(
+            "The position {} is greater than the file size {size}",
+            args.position
+        )));
+    }
+    match args.length {
+        Some(length) if args.position + length > size => Err(usage(format!(
+            "The region of {length} bytes at {} goes past the end of the file of size {size}",
+            args.position
+        ))),
+        Some(length) => Ok(length),
+        None => Ok(size - args.position),
     }
-    Ok(size - args.position)
 }
 
-fn validate_from_file(
+/// The header of the first chunk, from which the ones of the other chunks are derived, with
+/// `--format v2`
+///
+/// `headers` are the ones of the first chunks, if any. The first chunk of the stream has the
+/// index 0, and the one of a region, past `--position`, is told by the indexes of its chunks.
+/// Unless they're known from the stream header or the command line, the fingerprint of the seed
+/// and the run ID are the ones of most of the chunks: a single misplaced, stale or corrupted
+/// chunk doesn't make all the others fail.
+fn first_chunk_header(
+    headers: &[Option<ChunkHeader>],
+    header: Option<StreamHeader>,
+    length: u64,
     args: &ValidateArgs,
-    file: &Path,
+    region_chunks: Option<u64>,
+) -> anyhow::Result<Option<ChunkHeader>> {
+    if !ChunkHeader::fits(length as usize, args.common.checksum.width()) {
+        // a single chunk, too short to hold a header
+        return Ok(None);
+    }
+    // the fingerprint of the region of the chunk, from the one of the stream, or back since it's
+    // an involution
+    let region = |fingerprint, index| match region_chunks {
+        Some(region_chunks) => region_fingerprint(fingerprint, index / region_chunks),
+        None => fingerprint,
+    };
+    // the header of the first chunk told by each chunk, with the fingerprint of the stream, since
+    // the chunks sampled may span several regions
+    let firsts: Vec<_> = headers
+        .iter()
+        .enumerate()
+        .filter_map(|(chunk, found)| {
+            let found = (*found)?;
+            let index = found.index.checked_sub(chunk as u64)?;
+            let fingerprint = region(found.fingerprint, found.index);
+            Some(ChunkHeader { index, fingerprint, ..found })
+        })
+        .collect();
+    if firsts.is_empty() {
+        return Err(ValidationError::MissingChunkHeader { chunk: 0 }.into());
+    }
+    let index = match args.position == 0 && args.length.is_none() {
+        true => 0,
+        false => majority(firsts.iter().map(|first| first.index)),
+    };
+    let seed = header.map(|h| h.seed).or(args.seed());
+    let fingerprint = match seed {
+        Some(seed) => seed.fingerprint(),
+        None => majority(firsts.iter().map(|first| first.fingerprint)),
+    };
+    let fingerprint = region(fingerprint, index);
+    let run_id = args
+        .run_id
+        .or(header.filter(|h| h.format == ChunkFormat::V2).map(|h| h.run_id))
+        .unwrap_or_else(|| majority(firsts.iter().map(|first| first.run_id)));
+    Ok(Some(ChunkHeader { index, fingerprint, length, run_id }))
+}
+
+/// The value found the most often, the first one of them on a tie
+///
+/// The values are compared with each other, which is fine for the few `SAMPLED_HEADERS`.
+fn majority<T: Copy + PartialEq>(values: impl Iterator<Item = T>) -> T {
+    let mut counts: Vec<(T, usize)> = Vec::new();
+    for value in values {
+        match counts.iter_mut().find(|(v, _)| *v == value) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((value, 1)),
+        }
+    }
+    // the first of the largest counts
+    let most = counts.iter().map(|(_, count)| *count).max().expect("a value");
+    counts.into_iter().find(|(_, count)| *count == most).expect("a value").0
+}
+
+/// The headers at the start of the first chunks of the stream in the file, which tell the one of
+/// the first chunk
+fn read_chunk_headers(
+    f: &mut File,
+    position: u64,
+    chunk_size: usize,
     stream_size: u64,
+) -> io::Result<Vec<Option<ChunkHeader>>> {
+    let num_chunks = stream_size.div_ceil(chunk_size as u64).min(SAMPLED_HEADERS);
+    let mut data = [0; CHUNK_HEADER_SIZE];
+    (0..num_chunks)
+        .map(|chunk| {
+            f.seek(io::SeekFrom::Start(position + chunk * chunk_size as u64))?;
+            let size = read_exact_or_eof(f, &mut data)?;
+            Ok(ChunkHeader::decode(&data[..size]))
+        })
+        .collect()
+}
+
+/// The format of the chunks, from the stream header, or detected with the header at the start of
+/// the first chunk, or else the one given with `--format`
+///
+/// The streams written with any format are validated, without having to tell which one.
+fn chunk_format(args: &ValidateArgs, header: Option<StreamHeader>, data: &[u8]) -> ChunkFormat {
+    match header {
+        Some(header) => header.format,
+        None if ChunkHeader::decode(data).is_some() => {
+            if args.common.format != ChunkFormat::V2 {
+                info!("chunk format: v2, detected");
+            }
+            ChunkFormat::V2
+        }
+        None => args.common.format,
+    }
+}
+
+/// The number of chunks of each region with its own seed, from the stream header or
+/// `--region-size`
+fn region_chunks(
+    args: &ValidateArgs,
+    header: Option<StreamHeader>,
     chunk_size: usize,
+) -> anyhow::Result<Option<u64>> {
+    match header.and_then(|h| h.region_chunks) {
+        Some(region_chunks) => Ok(Some(region_chunks.into())),
+        None => args.common.region_chunks(chunk_size),
+    }
+}
+
+/// How the stream was generated, to regenerate its data with `--regenerate`
+#[derive(Clone, Copy, Debug)]
+struct Regeneration {
+    rng: RngAlgorithm,
+    seed: Seed,
+    /// The number of chunks of each region with its own seed, with `--region-size`
+    region_chunks: Option<u64>,
+    compressibility: Option<Compressibility>,
+    dedupe_ratio: u16,
+    pattern: Option<Pattern>,
+    /// The size of the sectors sealed by their own checksum, with `--sector-checksums`
+    sector_size: Option<usize>,
+}
+
+impl Regeneration {
+    /// The random generator of the stream, filling `buffer_size` bytes for each chunk
+    fn rng(&self, buffer_size: usize) -> StreamRng {
+        match self.region_chunks {
+            Some(chunks) => self.rng.region_rng(self.seed, chunks * buffer_size as u64),
+            None => self.rng.rng(self.seed),
+        }
+    }
+}
+
+/// How the stream was generated, with `--regenerate`
+///
+/// It's read from the stream header, if any, or else from the command line.
+fn regeneration(
+    args: &ValidateArgs,
+    header: Option<StreamHeader>,
+    region_chunks: Option<u64>,
+    sector_size: Option<usize>,
+) -> anyhow::Result<Option<Regeneration>> {
+    if !args.regenerate {
+        return Ok(None);
+    }
+    match (header, args.seed()) {
+        (Some(header), _) => Ok(Some(Regeneration {
+            rng: header.rng,
+            seed: header.seed,
+            region_chunks,
+            compressibility: header.compressibility,
+            dedupe_ratio: header.dedupe_ratio,
+            pattern: header.pattern,
+            sector_size,
+        })),
+        (None, Some(seed)) => Ok(Some(Regeneration {
+            rng: args.common.rng,
+            seed,
+            region_chunks,
+            compressibility: args.common.compressibility(),
+            dedupe_ratio: args.common.dedupe_ratio.unwrap_or(1),
+            pattern: args.common.pattern,
+            sector_size,
+        })),
+        (None, None) => Err(usage(
+            "--regenerate needs the seed of the stream, with --seed, --seed-string or \
+                 --seed-from-device",
+        )),
+    }
+}
+
+/// Look for the header recording how the stream was generated
+fn read_header(data: &[u8]) -> Option<StreamHeader> {
+    let header = StreamHeader::decode(data)?;
+    info!("seed: {}", header.seed);
+    info!("random generator: {}", header.rng.to_possible_value().unwrap().get_name());
+    if let Some(compressibility) = header.compressibility {
+        info!("compress ratio: {:.2}", compressibility.ratio());
+    }
+    if header.dedupe_ratio > 1 {
+        info!("dedupe ratio: {}", header.dedupe_ratio);
+    }
+    if let Some(pattern) = header.pattern {
+        info!("pattern: {}", pattern.to_possible_value().unwrap().get_name());
+    }
+    Some(header)
+}
+
+/// Describes the part of the file holding the stream
+#[derive(Clone, Debug)]
+struct StreamParams {
+    position: u64,
+    stream_size: u64,
+    chunk_size: usize,
+    /// The alignment of the reads, for direct I/O
+    alignment: usize,
+    cache: CachePolicy,
+    /// The number of consecutive chunks read at once
+    batch_chunks: usize,
+    throttle: Option<Throttle>,
+    /// Record the corrupted chunks instead of failing
+    keep_going: bool,
+    /// The header of the first chunk, with `--format v2`
+    first_header: Option<ChunkHeader>,
+    /// The number of chunks of each region with its own seed, with `--region-size`
+    region_chunks: Option<u64>,
+    /// The size of the sectors sealed by their own checksum, with `--sector-checksums`
+    sector_size: Option<usize>,
+    checksum: ChecksumAlgorithm,
+    /// How the stream was generated, with `--regenerate`
+    regenerate: Option<Regeneration>,
+    /// Log a byte-level diff of the corrupted chunks, with `--diff`
+    diff: bool,
+    /// The index in the stream of the first chunk of the file, with `--shard-size`
+    first_chunk: u64,
+    /// The number of chunks of the whole stream, across the shards with `--shard-size`
+    num_chunks: u64,
+    journal: Option<Journal>,
+    retry: ReadRetry,
+}
+
+/// Regenerates the expected data of the chunks, with `--regenerate`
+struct Regenerator {
+    regeneration: Regeneration,
+    rng: StreamRng,
+    /// The index of the chunk the generator is positioned at
+    next_index: u64,
+    chunk_size: usize,
+    buffer: Vec<u8>,
+    checksum: StreamChecksum,
+    dedupe: Option<Dedupe>,
+}
+
+impl Regenerator {
+    fn new(regeneration: Regeneration, chunk_size: usize, checksum: ChecksumAlgorithm) -> Self {
+        let Regeneration { rng, seed, dedupe_ratio, .. } = regeneration;
+        // the generator fills a multiple of 64 bits for each chunk
+        let buffer = vec![0; chunk_size.div_ceil(8) * 8];
+        Regenerator {
+            regeneration,
+            rng: regeneration.rng(buffer.len()),
+            next_index: 0,
+            chunk_size,
+            buffer,
+            checksum: checksum.stream_checksum(),
+            dedupe: Dedupe::new(dedupe_ratio, rng, seed),
+        }
+    }
+
+    /// Regenerate the payload of a chunk, without its checksum
+    ///
+    /// `index` is the index of the chunk in the stream.
+    fn regenerate(&mut self, index: u64, length: usize, header: Option<ChunkHeader>) -> &[u8] {
+        // the checksum has already been checked, and differs anyway when the payload does
+        let width = self.checksum.width();
+        let payload = if length >= width { length - width } else { length };
+        &self.chunk(index, length, header)[..payload]
+    }
+
+    /// Regenerate a whole chunk, with its checksum
+    fn chunk(&mut self, index: u64, length: usize, header: Option<ChunkHeader>) -> &[u8] {
+        if index < self.next_index {
+            self.rng = self.regeneration.rng(self.buffer.len());
+            self.next_index = 0;
+        }
+        self.rng.advance((index - self.next_index) * self.buffer.len() as u64);
+        self.next_index = index + 1;
+        let layout = ChunkLayout {
+            header,
+            compressibility: self.regeneration.compressibility,
+            dedupe: self.dedupe,
+            pattern: self.regeneration.pattern,
+            sector_size: self.regeneration.sector_size,
+            offset: index * self.chunk_size as u64,
+        };
+        generate_chunk_with_layout(
+            &mut self.rng,
+            &mut self.buffer,
+            length,
+            &layout,
+            &mut self.checksum,
+        );
+        &self.buffer[..length]
+    }
+
+    /// Compare the data of a chunk with its regenerated data
+    ///
+    /// `offset` is the offset of the chunk in the file.
+    fn diff(
+        &mut self,
+        index: u64,
+        offset: u64,
+        data: &[u8],
+        header: Option<ChunkHeader>,
+    ) -> ChunkDiff {
+        ChunkDiff::new(offset, data, self.regenerate(index, data.len(), header))
+    }
+
+    /// Compare the data of a chunk, already validated, with its regenerated data
+    fn check(
+        &mut self,
+        chunk: u64,
+        index: u64,
+        offset: u64,
+        data: &[u8],
+        header: Option<ChunkHeader>,
+    ) -> anyhow::Result<()> {
+        let diff = self.diff(index, offset, data, header);
+        let Some((first_offset, last_offset)) = diff.bounds() else {
+            return Ok(());
+        };
+        Err(ValidationError::DataMismatch { chunk, bytes: diff.bytes, first_offset, last_offset }
+            .into())
+    }
+}
+
+impl StreamParams {
+    /// Identifies the stream in its checkpoint
+    fn identity(&self, checksum: ChecksumAlgorithm) -> String {
+        format!(
+            "validate checksum={} position={} size={} chunk={}",
+            checksum.to_possible_value().unwrap().get_name(),
+            self.position,
+            self.stream_size,
+            self.chunk_size
+        )
+    }
+
+    /// The size of the chunk, and the size to read, aligned for direct I/O
+    fn chunk_read_size(&self, chunk: u64) -> (usize, usize) {
+        let size = (self.stream_size - chunk * self.chunk_size as u64).min(self.chunk_size as u64)
+            as usize;
+        (size, size.next_multiple_of(self.alignment))
+    }
+
+    /// The range of the file read by a thread
+    fn file_range(&self, work: &ThreadWork) -> Range<u64> {
+        let range = work.byte_range(self.chunk_size, self.stream_size);
+        self.position + range.start..self.position + range.end
+    }
+
+    /// Record the chunks in the journal if they couldn't be read, and return the size read
+    fn journal_read_error(
+        &self,
+        read: io::Result<usize>,
+        chunks: &[u64],
+        start: SystemTime,
+    ) -> anyhow::Result<usize> {
+        if let (Err(e), Some(journal)) = (&read, &self.journal) {
+            let error = e.to_string();
+            for chunk in chunks {
+                let offset = self.position + chunk * self.chunk_size as u64;
+                let size = self.chunk_read_size(*chunk).0;
+                let chunk = self.first_chunk + chunk;
+                journal.record(Operation::Read, chunk, offset, size, Err(&error), start)?;
+            }
+        }
+        Ok(read?)
+    }
+
+    /// Read the consecutive chunks at the position of the file, retrying with `--read-retries`
+    fn read_chunks(
+        &self,
+        file: &mut File,
+        path: &Path,
+        chunks: Range<u64>,
+        slices: &mut [IoSliceMut],
+    ) -> io::Result<usize> {
+        let offset = self.position + chunks.start * self.chunk_size as u64;
+        let size = slices.iter().map(|s| s.len() as u64).sum::<u64>();
+        let indexes = self.first_chunk + chunks.start..self.first_chunk + chunks.end;
+        self.retry.run(indexes, |retrying| {
+            if retrying {
+                if self.retry.reopen {
+                    *file = direct::open(path, false, self.alignment, None)?;
+                    cache::evict(file, offset..offset + size)?;
+                }
+                file.seek(io::SeekFrom::Start(offset))?;
+            }
+            direct::read_vectored_aligned(file, slices, self.alignment)
+        })
+    }
+
+    /// Log the number of chunks read after retrying, and record it in the report
+    fn report_retries(&self, report: &mut Report) {
+        report.retried_chunks = self.retry.retried();
+        if report.retried_chunks > 0 {
+            warn!("chunks read after retrying: {}", report.retried_chunks);
+        }
+    }
+
+    /// The regenerator of the expected data of the chunks of a thread, with `--regenerate`
+    fn regenerator(&self) -> Option<Regenerator> {
+        self.regenerate
+            .map(|regeneration| Regenerator::new(regeneration, self.chunk_size, self.checksum))
+    }
+
+    /// Validate a chunk, recording it as corrupted with `--keep-going`
+    ///
+    /// The validation is recorded in the journal, with the time the chunk started to be read.
+    fn check_chunk(
+        &self,
+        chunk: u64,
+        data: &[u8],
+        recorder: &mut ChunkRecorder,
+        regenerator: &mut Option<Regenerator>,
+        start: Option<SystemTime>,
+    ) -> anyhow::Result<()> {
+        let length = self.chunk_read_size(chunk).0 as u64;
+        let expected =
+            self.first_header.map(|first| first.following(chunk, length, self.region_chunks));
+        // the chunks of a shard are numbered in the whole stream
+        let chunk = self.first_chunk + chunk;
+        let offset = self.position + chunk * self.chunk_size as u64;
+        let index = expected.map_or(chunk, |h| h.index);
+        let result = validate_sectors(chunk, data, recorder.checksum(), self.sector_size);
+        let result = result.and_then(|()| {
+            if let Some(expected) = &expected {
+                let width = self.checksum.width();
+                let (chunk_size, num_chunks) = (self.chunk_size, self.num_chunks);
+                validate_chunk_header(
+                    chunk, offset, data, expected, chunk_size, width, num_chunks,
+                )?;
+            }
+            match regenerator {
+                Some(regenerator) => regenerator.check(chunk, index, offset, data, expected),
+                None => Ok(()),
+            }
+        });
+        if result.is_err()
+            && self.diff
+            && let Some(regenerator) = regenerator
+        {
+            regenerator.diff(index, offset, data, expected).log(chunk);
+        }
+        if let (Some(journal), Some(start)) = (&self.journal, start) {
+            let error = result.as_ref().err().map(ToString::to_string);
+            let result = error.as_deref().map_or(Ok(data), Err);
+            journal.record(Operation::Read, chunk, offset, data.len(), result, start)?;
+        }
+        match result {
+            Err(e) if self.keep_going => {
+                recorder.corrupted(CorruptedChunk {
+                    chunk,
+                    offset,
+                    length: data.len() as u64,
+                    error: e.downcast()?,
+                });
+                Ok(())
+            }
+            Err(e) => Err(locate_corruption(e, chunk, offset, data.len())),
+            Ok(()) => Ok(()),
+        }
+    }
+}
+
+fn validate_from_file(
+    args: &ValidateArgs,
+    file: &Path,
+    stream: &StreamParams,
     pb: &mut Option<Progress>,
     cancel: &Arc<AtomicBool>,
-) -> anyhow::Result<(u64, u32)> {
-    let num_threads = args.common.jobs.unwrap_or(num_cpus::get_physical());
+    report: &mut Report,
+) -> anyhow::Result<(StreamSummary, Vec<CorruptedChunk>)> {
+    let num_threads = args.common.num_threads(file);
     debug!("number of threads: {num_threads}");
+    debug!("engine: {:?}", args.common.engine);
+    let affinity = args.common.affinity(Some(file))?;
 
-    let num_chunks = stream_size.div_ceil(chunk_size as u64);
-    let chunks_per_thread = num_chunks.div_ceil(num_threads as u64);
+    let num_chunks = stream.stream_size.div_ceil(stream.chunk_size as u64);
+    let mut summarizer = Summarizer::new(
+        args.common.checksum,
+        args.common.digest,
+        stream.stream_size,
+        num_chunks,
+        num_threads,
+    );
     let (tx, rx) = mpsc::channel::<u64>();
 
-    let handles: Vec<_> = (0..num_threads as u64)
-        .map(|i| {
+    let mut works = summarizer.split(num_chunks, num_threads);
+    let checkpoint = match &args.checkpoint {
+        Some(path) => {
+            let resumed = SavedCheckpoint::load(path)?;
+            let identity = stream.identity(args.common.checksum);
+            let checkpoint =
+                Checkpoint::new(path, None, Seed::default(), 0, identity, resumed, &works)?;
+            let ranges = checkpoint.ranges();
+            let done: u64 = ranges.iter().map(|r| r.bytes).sum();
+            if done > 0 {
+                info!("resuming from the checkpoint, {done} bytes already validated");
+                tx.send(done)?;
+            }
+            works = ranges.iter().map(|r| r.work()).collect();
+            Some(checkpoint)
+        }
+        None => None,
+    };
+    // the threads take the chunks a batch at a time, unless each one must keep its own range
+    let queue = match &checkpoint {
+        None => {
+            let deadline = args.common.duration.map(|duration| Instant::now() + duration);
+            summarizer.queue(
+                num_chunks,
+                num_threads,
+                stream.chunk_size,
+                deadline,
+                args.common.bwlimit,
+            )
+        }
+        Some(_) => None,
+    };
+    if let Some(queue) = &queue {
+        debug!("batches of {} chunks", queue.batch_chunks());
+    }
+
+    let positions =
+        ThreadPositions::new(works.len(), stream.position, stream.chunk_size, stream.stream_size);
+    let watch = TargetWatch::start(file, stream.position + stream.stream_size, cancel.clone())?;
+    let handles: Vec<_> = works
+        .into_iter()
+        .enumerate()
+        .map(|(i, work)| {
             let file = file.to_path_buf();
             let tx = tx.clone();
             let cancel = cancel.clone();
-            let position = args.position;
+            let stream = stream.clone();
+            let assignment = match &queue {
+                Some(queue) => summarizer.queued(queue, stream.chunk_size),
+                None => {
+                    let mut recorder = summarizer.recorder(i, &work, stream.chunk_size);
+                    if let Some(checkpoint) = &checkpoint {
+                        recorder = recorder.checkpointed(checkpoint.tracker(i));
+                    }
+                    Assignment::Fixed(work, Box::new(recorder))
+                }
+            }
+            .positioned(&positions, i);
+            let (engine, queue_depth) = (args.common.engine, args.common.queue_depth);
+            let affinity = affinity.clone();
             thread::spawn(move || -> anyhow::Result<_> {
-                let result = validate_chunk_range(
-                    &file,
-                    chunk_size,
-                    i,
-                    chunks_per_thread,
-                    num_chunks,
-                    stream_size,
-                    position,
-                    &tx,
-                    &cancel,
-                );
+                affinity.pin(i)?;
+                let result = assignment.run(&cancel, |work, recorder| match engine {
+                    IoEngine::Sync => validate_chunks(&file, &stream, work, recorder, &tx, &cancel),
+                    #[cfg(target_os = "linux")]
+                    IoEngine::IoUring => validate_chunks_uring(
+                        &file,
+                        &stream,
+                        work,
+                        recorder,
+                        &tx,
+                        &cancel,
+                        queue_depth,
+                    ),
+                    #[cfg(unix)]
+                    IoEngine::Mmap => {
+                        validate_chunks_mmap(&file, &stream, work, recorder, &tx, &cancel)
+                    }
+                    #[cfg(not(unix))]
+                    IoEngine::Mmap => Err(usage("The mmap engine is only available on Unix")),
+                    #[cfg(not(target_os = "linux"))]
+                    IoEngine::IoUring => {
+                        let _ = queue_depth;
+                        Err(usage("The io-uring engine is only available on Linux"))
+                    }
+                });
                 if result.is_err() {
                     // tell the other threads to stop
                     cancel.store(true, Ordering::Relaxed);
@@ -137,110 +1715,811 @@ fn validate_from_file(
         })
         .collect();
 
-    receive_progress(pb, &rx, tx);
-    let thread_data: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).try_collect()?;
-
-    let read_bytes = thread_data.iter().map(|(b, _)| b).sum();
-    let thread_hashers: Vec<_> = thread_data.iter().map(|(_, h)| h).collect();
-    let mut hasher = thread_hashers[0].clone();
-    for partial_hasher in thread_hashers[1..].iter() {
-        hasher.combine(partial_hasher);
+    let total = Some(stream.stream_size);
+    receive_progress(pb, &rx, tx, ProgressPhase::Validate, total, Some(&positions));
+    let outputs = work::join(handles);
+    if let Some(checkpoint) = &checkpoint {
+        if outputs.is_ok() && !cancel.load(Ordering::Relaxed) {
+            checkpoint.remove()?;
+        } else {
+            checkpoint.save()?;
+            info!("checkpoint saved, the run can be resumed");
+        }
     }
+    watch.finish(&positions.offsets())?;
+    let mut outputs = outputs.map_err(|errors| report.thread_errors(errors))?;
+    report.add_threads(outputs.iter().map(|o| o.stats.clone()), args.common.imbalance_threshold);
+    let mut corrupted: Vec<_> =
+        outputs.iter_mut().flat_map(|o| std::mem::take(&mut o.corrupted)).collect();
+    corrupted.sort_by_key(|c| c.chunk);
 
-    Ok((read_bytes, hasher.finalize()))
+    Ok((summarizer.finish(outputs)?, corrupted))
 }
 
+/// Validate a sample of the chunks, or the ones holding data with `--sparse`, each one on its own
+///
+/// With `--incremental`, the chunks validated and not corrupted are recorded in the state.
 #[allow(clippy::too_many_arguments)]
-fn validate_chunk_range(
+fn validate_sample(
+    args: &ValidateArgs,
     file: &Path,
-    chunk_size: usize,
-    thread_index: u64,
-    chunks_per_thread: u64,
-    num_chunks: u64,
-    stream_size: u64,
-    position: u64,
+    stream: &StreamParams,
+    chunks: &[u64],
+    state: Option<&mut ScrubState>,
+    cancel: &Arc<AtomicBool>,
+    report: &mut Report,
+    start: Instant,
+) -> anyhow::Result<i32> {
+    let sample_size = chunks.iter().map(|c| stream.chunk_read_size(*c).0 as u64).sum();
+    let mut pb = Progress::new(
+        Some(sample_size),
+        args.common.no_progress,
+        args.common.progress,
+        args.common.device.as_deref(),
+        args.common.progress_callback.as_ref(),
+    )?;
+    let num_threads =
+        args.common.jobs.unwrap_or(num_cpus::get_physical()).clamp(1, chunks.len().max(1));
+    debug!("number of threads: {num_threads}");
+    let affinity = args.common.affinity(Some(file))?;
+
+    // the stream checksum of the chunks sampled is meaningless, only their own checksum is checked
+    let mut summarizer =
+        Summarizer::new(args.common.checksum, None, stream.stream_size, 0, num_threads);
+    let (tx, rx) = mpsc::channel::<u64>();
+    let handles: Vec<_> = chunks
+        .chunks(chunks.len().div_ceil(num_threads).max(1))
+        .enumerate()
+        .map(|(i, chunks)| {
+            let file = file.to_path_buf();
+            let tx = tx.clone();
+            let cancel = cancel.clone();
+            let stream = stream.clone();
+            let chunks = chunks.to_vec();
+            let work = ThreadWork { first_chunk: chunks[0], end_chunk: chunks[0] + 1, step: 1 };
+            let recorder = summarizer.recorder(i, &work, stream.chunk_size);
+            let affinity = affinity.clone();
+            thread::spawn(move || -> anyhow::Result<_> {
+                affinity.pin(i)?;
+                let result =
+                    validate_sampled_chunks(&file, &stream, &chunks, recorder, &tx, &cancel);
+                if result.is_err() {
+                    // tell the other threads to stop
+                    cancel.store(true, Ordering::Relaxed);
+                }
+                result
+            })
+        })
+        .collect();
+
+    receive_progress(&mut pb, &rx, tx, ProgressPhase::Validate, Some(sample_size), None);
+    let mut outputs = work::join(handles).map_err(|errors| report.thread_errors(errors))?;
+    report.add_threads(outputs.iter().map(|o| o.stats.clone()), args.common.imbalance_threshold);
+    report.bytes = outputs.iter().map(|o| o.stats.bytes).sum();
+    stream.report_retries(report);
+    let mut corrupted: Vec<_> =
+        outputs.iter_mut().flat_map(|o| std::mem::take(&mut o.corrupted)).collect();
+    corrupted.sort_by_key(|c| c.chunk);
+
+    if cancel.load(Ordering::Relaxed) {
+        log_metrics(start, report.bytes, "read bytes");
+        return Ok(exit_code::INTERRUPTED);
+    }
+    if let Some(state) = state {
+        let validated = chunks.iter().filter(|c| !corrupted.iter().any(|cc| cc.chunk == **c));
+        state.record(validated.copied(), scrub::now());
+        state.save()?;
+    }
+    write_corruption_maps(&args.corruption, &corrupted)?;
+    log_metrics(start, report.bytes, "read bytes");
+    if !corrupted.is_empty() {
+        return Err(summarize_corruption(&corrupted, report).into());
+    }
+    Ok(0)
+}
+
+fn validate_sampled_chunks(
+    path: &Path,
+    stream: &StreamParams,
+    chunks: &[u64],
+    mut recorder: ChunkRecorder,
     tx: &mpsc::Sender<u64>,
     cancel: &AtomicBool,
-) -> anyhow::Result<(u64, Hasher)> {
-    let mut file = File::open(file)?;
-    let mut thread_hasher = Hasher::new();
-    let start_chunk = thread_index * chunks_per_thread;
-    let end_chunk = ((thread_index + 1) * chunks_per_thread).min(num_chunks);
-    let mut buffer = vec![0; chunk_size];
-    file.seek(io::SeekFrom::Start(position + start_chunk * chunk_size as u64))?;
-    let mut total_read_size: u64 = 0;
+) -> anyhow::Result<ThreadOutput> {
+    let mut file = direct::open(path, false, stream.alignment, None)?;
+    let mut buffer = AlignedBuffer::new(stream.chunk_size, stream.alignment);
+    let mut regenerator = stream.regenerator();
+    for chunk in chunks {
+        let (size, read_size) = stream.chunk_read_size(*chunk);
+        if let Some(throttle) = &stream.throttle {
+            throttle.consume(read_size as u64);
+        }
+        let start = SystemTime::now();
+        file.seek(io::SeekFrom::Start(stream.position + chunk * stream.chunk_size as u64))?;
+        let mut slices = [IoSliceMut::new(&mut buffer[..read_size])];
+        let read = stream.read_chunks(&mut file, path, *chunk..chunk + 1, &mut slices);
+        let read = stream.journal_read_error(read, &[*chunk], start)?;
+        let data = &buffer[..size.min(read)];
+        stream.check_chunk(*chunk, data, &mut recorder, &mut regenerator, Some(start))?;
+        recorder.record(data);
+        tx.send(data.len() as u64)?;
+        if cancel.load(Ordering::Relaxed) {
+            break;
+        }
+    }
+    Ok(recorder.finish())
+}
+
+fn validate_chunks(
+    path: &Path,
+    stream: &StreamParams,
+    work: &ThreadWork,
+    mut recorder: ChunkRecorder,
+    tx: &mpsc::Sender<u64>,
+    cancel: &AtomicBool,
+) -> anyhow::Result<ThreadOutput> {
+    let mut file = direct::open(path, false, stream.alignment, None)?;
+    let mut cache = stream.cache.apply(&file, stream.file_range(work), false)?;
+    let mut regenerator = stream.regenerator();
+    let mut buffers: Vec<_> = (0..stream.batch_chunks)
+        .map(|_| AlignedBuffer::new(stream.chunk_size, stream.alignment))
+        .collect();
+    file.seek(io::SeekFrom::Start(stream.position))?;
+    let mut next_chunk = 0;
     let mut progress_bytes: u64 = 0;
-    for chunk in start_chunk..end_chunk {
-        let bytes_done = (chunk - start_chunk) * chunk_size as u64;
-        let remaining = (stream_size - start_chunk * chunk_size as u64 - bytes_done)
-            .min(chunk_size as u64) as usize;
-        let read_size = read_exact_or_eof(&mut file, &mut buffer[..remaining])?;
-        validate_chunk(chunk, &buffer[..read_size], &mut thread_hasher)?;
-        total_read_size += read_size as u64;
-        progress_bytes += read_size as u64;
-        if chunk % 100 == 0 {
+    let mut n: u64 = 0;
+    // only the consecutive chunks can be read at once
+    let batch_chunks = if work.step == 1 { stream.batch_chunks } else { 1 };
+    for batch in &work.chunks().chunks(batch_chunks) {
+        let batch: Vec<_> = batch.collect();
+        if batch[0] != next_chunk {
+            file.seek(io::SeekFrom::Start(stream.position + batch[0] * stream.chunk_size as u64))?;
+        }
+        next_chunk = batch[batch.len() - 1] + 1;
+        let mut slices: Vec<_> = batch
+            .iter()
+            .zip(&mut buffers)
+            .map(|(chunk, buffer)| IoSliceMut::new(&mut buffer[..stream.chunk_read_size(*chunk).1]))
+            .collect();
+        if let Some(throttle) = &stream.throttle {
+            throttle.consume(slices.iter().map(|s| s.len() as u64).sum());
+        }
+        let start = SystemTime::now();
+        let read = stream.read_chunks(&mut file, path, batch[0]..next_chunk, &mut slices);
+        let mut read_size = stream.journal_read_error(read, &batch, start)?;
+        for (chunk, buffer) in batch.iter().zip(&buffers) {
+            // the chunks are contiguous, but the last one may be short
+            let size = stream.chunk_read_size(*chunk).0.min(read_size);
+            read_size -= size.next_multiple_of(stream.alignment).min(read_size);
+            let data = &buffer[..size];
+            stream.check_chunk(*chunk, data, &mut recorder, &mut regenerator, Some(start))?;
+            cache.processed(&file, stream.position + chunk * stream.chunk_size as u64)?;
+            if !recorder.record(&buffer[..size]) {
+                // the digest thread has stopped, because another thread failed
+                return Ok(recorder.finish());
+            }
+            recorder.done(*chunk)?;
+            progress_bytes += size as u64;
+            if n.is_multiple_of(100) {
+                tx.send(progress_bytes)?;
+                progress_bytes = 0;
+            }
+            n += 1;
+        }
+        if cancel.load(Ordering::Relaxed) {
+            break;
+        }
+    }
+    cache.finish(&file)?;
+    // the bytes processed since the last update
+    tx.send(progress_bytes)?;
+    Ok(recorder.finish())
+}
+
+/// Same as validate_chunks, but with up to `queue_depth` reads in flight through io_uring
+///
+/// The reads complete in any order, but the chunks are validated in the stream order, so they
+/// can be added to the stream checksum.
+#[cfg(target_os = "linux")]
+fn validate_chunks_uring(
+    file: &Path,
+    stream: &StreamParams,
+    work: &ThreadWork,
+    mut recorder: ChunkRecorder,
+    tx: &mpsc::Sender<u64>,
+    cancel: &AtomicBool,
+    queue_depth: u32,
+) -> anyhow::Result<ThreadOutput> {
+    use rustix::io_uring::IoringOp;
+    use std::collections::HashMap;
+    use std::os::fd::AsRawFd as _;
+
+    let file = direct::open(file, false, stream.alignment, None)?;
+    let mut queue = BufferQueue::new(
+        IoringOp::Read,
+        file.as_raw_fd(),
+        queue_depth,
+        stream.chunk_size,
+        stream.alignment,
+    )?;
+    let mut cache = stream.cache.apply(&file, stream.file_range(work), false)?;
+    let mut regenerator = stream.regenerator();
+    let mut to_read = work.chunks().peekable();
+    let mut to_validate = work.chunks().peekable();
+    // the buffers holding the chunks read, but not validated yet
+    let mut read = HashMap::new();
+    let mut progress_bytes: u64 = 0;
+    let mut validated_chunks: u64 = 0;
+    loop {
+        while let Some(&chunk) = to_read.peek()
+            && let Some(index) = queue.free_buffer()
+        {
+            to_read.next();
+            if let Some(throttle) = &stream.throttle {
+                throttle.consume(stream.chunk_read_size(chunk).0 as u64);
+            }
+            let offset = stream.position + chunk * stream.chunk_size as u64;
+            queue.submit(index, offset, stream.chunk_read_size(chunk).1, chunk)?;
+        }
+        if queue.in_flight() == 0 {
+            break;
+        }
+        for (index, chunk) in queue.wait()? {
+            read.insert(chunk, index);
+        }
+        while let Some(index) = to_validate.peek().and_then(|chunk| read.remove(chunk)) {
+            let chunk = to_validate.next().unwrap();
+            let read_size = queue.transferred(index).min(stream.chunk_read_size(chunk).0);
+            let data = &queue.buffer(index)[..read_size];
+            stream.check_chunk(chunk, data, &mut recorder, &mut regenerator, None)?;
+            cache.processed(&file, stream.position + chunk * stream.chunk_size as u64)?;
+            if !recorder.record(data) {
+                // the digest thread has stopped, because another thread failed
+                return Ok(recorder.finish());
+            }
+            recorder.done(chunk)?;
+            progress_bytes += data.len() as u64;
+            queue.release(index);
+            validated_chunks += 1;
+            if validated_chunks.is_multiple_of(100) {
+                tx.send(progress_bytes)?;
+                progress_bytes = 0;
+            }
+        }
+        if cancel.load(Ordering::Relaxed) {
+            break;
+        }
+    }
+    cache.finish(&file)?;
+    // the bytes processed since the last update
+    tx.send(progress_bytes)?;
+    Ok(recorder.finish())
+}
+
+/// Same as validate_chunks, but validating the chunks directly in a mapping of the file
+#[cfg(unix)]
+fn validate_chunks_mmap(
+    file: &Path,
+    stream: &StreamParams,
+    work: &ThreadWork,
+    mut recorder: ChunkRecorder,
+    tx: &mpsc::Sender<u64>,
+    cancel: &AtomicBool,
+) -> anyhow::Result<ThreadOutput> {
+    let file = File::open(file)?;
+    let range = stream.file_range(work);
+    let mut cache = stream.cache.apply(&file, range.clone(), false)?;
+    let mut regenerator = stream.regenerator();
+    // the stream may be longer than the file, but the mapping can't
+    let end = range.end.min(mapping::file_size(&file)?);
+    let advice = stream.cache.advice.unwrap_or(Advice::Sequential);
+    let mapping = if range.start < end {
+        Some(FileMapping::new(&file, range.start..end, false, advice)?)
+    } else {
+        None
+    };
+    let mut progress_bytes: u64 = 0;
+    for (n, chunk) in work.chunks().enumerate() {
+        let offset = stream.position + chunk * stream.chunk_size as u64;
+        let chunk_end = (offset + stream.chunk_read_size(chunk).0 as u64).min(end);
+        let data = match &mapping {
+            Some(mapping) if offset < chunk_end => {
+                mapping.slice((offset - range.start) as usize..(chunk_end - range.start) as usize)
+            }
+            _ => &[],
+        };
+        if let Some(throttle) = &stream.throttle {
+            throttle.consume(data.len() as u64);
+        }
+        stream.check_chunk(chunk, data, &mut recorder, &mut regenerator, None)?;
+        cache.processed(&file, offset)?;
+        if !recorder.record(data) {
+            // the digest thread has stopped, because another thread failed
+            break;
+        }
+        recorder.done(chunk)?;
+        progress_bytes += data.len() as u64;
+        if n % 100 == 0 {
             tx.send(progress_bytes)?;
             progress_bytes = 0;
         }
         if cancel.load(Ordering::Relaxed) {
-            return Ok((total_read_size, thread_hasher));
+            break;
         }
     }
-    Ok((total_read_size, thread_hasher))
+    cache.finish(&file)?;
+    // the bytes processed since the last update
+    tx.send(progress_bytes)?;
+    Ok(recorder.finish())
+}
+
+/// The options reading the chunks out of order, only applying to a seekable local file
+fn seekable_options(args: &ValidateArgs) -> [(&'static str, bool); 6] {
+    [
+        ("--checkpoint", args.checkpoint.is_some()),
+        ("--shard-size", args.shard_size.is_some()),
+        ("--sample", args.sample.is_some()),
+        ("--sample-chunks", args.sample_chunks.is_some()),
+        ("--sparse", args.sparse),
+        ("--incremental", args.incremental.is_some()),
+    ]
+}
+
+/// Reject the options only applying to a local file, and `others`, for the target which isn't
+/// one
+fn reject_file_options(
+    args: &ValidateArgs,
+    target: &str,
+    others: &[(&str, bool)],
+) -> anyhow::Result<()> {
+    let mut options = seekable_options(args).to_vec();
+    options.extend([
+        ("--read-retries", args.read_retries > 0),
+        ("--journal", args.common.journal.is_some()),
+        ("--direct", args.common.direct),
+        ("--drop-cache", args.common.drop_cache),
+        ("--advise", args.common.advise.is_some()),
+        ("--chunk-size auto", args.common.chunk_size == AUTO_CHUNK_SIZE),
+    ]);
+    options.extend_from_slice(others);
+    options.push(("--duration", args.common.duration.is_some()));
+    net::reject_options(target, &options)
+}
+
+/// Validate the chunks received as datagrams, and count the lost ones
+fn receive_datagrams(
+    args: &ValidateArgs,
+    endpoint: &Endpoint,
+    cancel: &AtomicBool,
+    report: &mut Report,
+    start: Instant,
+) -> anyhow::Result<i32> {
+    // the datagrams are validated on their own
+    net::reject_options(
+        "udp://",
+        &[
+            ("--expected-checksum", args.expected_checksum.is_some()),
+            ("--digest", args.common.digest.is_some()),
+            ("--regenerate", args.regenerate),
+            ("--keep-going", args.corruption.keep_going),
+        ],
+    )?;
+    let socket = endpoint.datagram_receiver()?;
+    let mut pb = Progress::new(
+        args.size(),
+        args.common.no_progress,
+        args.common.progress,
+        args.common.device.as_deref(),
+        args.common.progress_callback.as_ref(),
+    )?;
+    let chunk_size = args.common.chunk_size as usize;
+    let stats =
+        udp::receive(&socket, args.common.checksum, args.size(), chunk_size, &mut pb, cancel)?;
+    if let Some(p) = &mut pb {
+        p.finish();
+    }
+    report.bytes = stats.bytes;
+    report.threads.push(ThreadStats { bytes: stats.bytes, elapsed: start.elapsed() });
+    log_metrics(start, stats.bytes, "read bytes");
+    if cancel.load(Ordering::Relaxed) {
+        return Ok(exit_code::INTERRUPTED);
+    }
+    stats.log();
+    if stats.lost > 0 || stats.corrupted > 0 {
+        let error = ValidationError::Datagrams { lost: stats.lost, corrupted: stats.corrupted };
+        return Err(error.into());
+    }
+    Ok(0)
+}
+
+/// Discard the data of the input up to the position
+fn skip_to_position(mut input: Box<dyn Read>, position: u64) -> io::Result<Box<dyn Read>> {
+    io::copy(&mut (&mut input).take(position), &mut io::sink())?;
+    Ok(input)
+}
+
+/// A chunk read from stdin or the network, handed over to a worker thread
+struct StdinChunk {
+    chunk: u64,
+    offset: u64,
+    data: Vec<u8>,
+    /// The header of the first chunk, with `--format v2`
+    first_header: Option<ChunkHeader>,
 }
 
-fn validate_from_stdin(
+/// Validate the stream read from stdin, the network or an NBD export, from the position
+///
+/// The chunks are read by this thread, and validated by several others, the stream being
+/// summarized in order by another one.
+fn validate_from_reader(
     args: &ValidateArgs,
+    mut input: &mut dyn Read,
     chunk_size: usize,
     pb: &mut Option<Progress>,
-) -> anyhow::Result<(u64, u32)> {
-    debug!("number of threads: 1");
-    // discard the first values up to position
-    io::copy(&mut io::stdin().take(args.position), &mut io::sink())?;
-    let mut buffer = vec![0; chunk_size];
-    let mut stream_size: u64 = 0;
+    cancel: &Arc<AtomicBool>,
+    report: &mut Report,
+) -> anyhow::Result<(StreamSummary, Vec<CorruptedChunk>)> {
+    let mut prefix = vec![0; HEADER_SIZE];
+    let prefix_size = read_exact_or_eof(&mut input, &mut prefix)?;
+    prefix.truncate(prefix_size);
+    let mut header_size = 0;
+    let header = read_header(&prefix);
+    if header.is_some() {
+        prefix.clear();
+        header_size = HEADER_SIZE as u64;
+    }
+    // the prefix holds the start of the first chunk, without a stream header
+    let format = chunk_format(args, header, &prefix);
+    let args =
+        &ValidateArgs { common: CommonArgs { format, ..args.common.clone() }, ..args.clone() };
+    let region_chunks = region_chunks(args, header, chunk_size)?;
+    let sector_size = args.common.sector_size(chunk_size)?;
+    let regeneration = regeneration(args, header, region_chunks, sector_size)?;
+
+    let num_threads = args.common.jobs.unwrap_or(num_cpus::get_physical()).max(1);
+    debug!("number of threads: {num_threads}");
+    let affinity = args.common.affinity(None)?;
+    // the size of the stream is unknown: the summary ends with the chunks read
+    let mut summarizer =
+        Summarizer::ordered(args.common.checksum, args.common.digest, u64::MAX, num_threads, None);
+    let (senders, handles): (Vec<_>, Vec<_>) = summarizer
+        .split(u64::MAX, num_threads)
+        .iter()
+        .enumerate()
+        .map(|(i, work)| {
+            let (tx, rx) = mpsc::sync_channel::<StdinChunk>(STDIN_QUEUE_DEPTH);
+            let args = args.clone();
+            let cancel = cancel.clone();
+            let recorder = summarizer.recorder(i, work, chunk_size);
+            let affinity = affinity.clone();
+            let handle = thread::spawn(move || -> anyhow::Result<_> {
+                affinity.pin(i)?;
+                let result = validate_read_chunks(
+                    &args,
+                    header,
+                    regeneration,
+                    region_chunks,
+                    sector_size,
+                    rx,
+                    recorder,
+                );
+                if result.is_err() {
+                    // tell the other threads to stop
+                    cancel.store(true, Ordering::Relaxed);
+                }
+                result
+            });
+            (tx, handle)
+        })
+        .unzip();
+
+    let mut input = io::Cursor::new(prefix).chain(input);
+    let send = |sent: StdinChunk| {
+        // fails once the thread has stopped, on an error
+        senders[(sent.chunk % num_threads as u64) as usize].send(sent).is_ok()
+    };
+    // the first chunks are held until their headers tell the one of the first chunk
+    let mut held = Vec::new();
+    let mut headers = Vec::new();
+    let first_header = |held: &[StdinChunk], headers: &[Option<ChunkHeader>]| {
+        let length = held.first().map_or(0, |first| first.data.len() as u64);
+        first_chunk_header(headers, header, length, args, region_chunks).ok().flatten()
+    };
+    let release = |held: &mut Vec<StdinChunk>, first_header: Option<ChunkHeader>| {
+        held.drain(..).all(|chunk| send(StdinChunk { first_header, ..chunk }))
+    };
+    let mut first = None;
     let mut chunk: u64 = 0;
-    let mut hasher = Hasher::new();
-    while args.common.size.map(|s| stream_size < s).unwrap_or(true) {
-        let read_size = read_exact_or_eof(&mut io::stdin(), &mut buffer)?;
+    let mut bytes = 0;
+    let stream_size = args.size().map(|s| s.saturating_sub(header_size));
+    let throttle = args.common.throttle();
+    while stream_size.map(|s| bytes < s).unwrap_or(true) && !cancel.load(Ordering::Relaxed) {
+        let mut data = vec![0; chunk_size];
+        let read_size = read_exact_or_eof(&mut input, &mut data)?;
         if read_size == 0 {
             // End of input stream (EOF)
             break;
         }
-        validate_chunk(chunk, &buffer[..read_size], &mut hasher)?;
-        stream_size += read_size as u64;
+        data.truncate(read_size);
+        if let Some(throttle) = &throttle {
+            throttle.consume(read_size as u64);
+        }
+        let offset = args.position + header_size + chunk * chunk_size as u64;
+        if format == ChunkFormat::V2 && chunk < SAMPLED_HEADERS {
+            // only the headers of the chunks matching their checksum are trusted
+            let valid =
+                validate_chunk(chunk, &data, &mut args.common.checksum.stream_checksum()).is_ok();
+            headers.push(ChunkHeader::decode(&data).filter(|_| valid));
+            held.push(StdinChunk { chunk, offset, data, first_header: None });
+            if chunk + 1 == SAMPLED_HEADERS {
+                first = first_header(&held, &headers);
+                if !release(&mut held, first) {
+                    break;
+                }
+            }
+        } else if !send(StdinChunk { chunk, offset, data, first_header: first }) {
+            break;
+        }
+        bytes += read_size as u64;
         chunk += 1;
         if let Some(p) = pb {
-            p.tick(stream_size);
+            p.tick(bytes);
         }
+        systemd::progress(ProgressPhase::Validate, bytes, args.size());
+    }
+    // the stream is shorter than the chunks sampled
+    if !held.is_empty() {
+        let first = first_header(&held, &headers);
+        release(&mut held, first);
     }
-    Ok((stream_size, hasher.finalize()))
+    drop(senders);
+
+    let mut outputs = work::join(handles).map_err(|errors| report.thread_errors(errors))?;
+    report.add_threads(outputs.iter().map(|o| o.stats.clone()), args.common.imbalance_threshold);
+    let mut corrupted: Vec<_> =
+        outputs.iter_mut().flat_map(|o| std::mem::take(&mut o.corrupted)).collect();
+    corrupted.sort_by_key(|c| c.chunk);
+    let mut summary = summarizer.finish(outputs)?;
+    summary.bytes += header_size;
+    Ok((summary, corrupted))
 }
 
-pub fn validate_chunk(chunk: u64, buffer: &[u8], global_hasher: &mut Hasher) -> anyhow::Result<()> {
-    let mut hasher = Hasher::new();
+/// Validate the chunks read from stdin or the network handed over to the thread
+fn validate_read_chunks(
+    args: &ValidateArgs,
+    header: Option<StreamHeader>,
+    regeneration: Option<Regeneration>,
+    region_chunks: Option<u64>,
+    sector_size: Option<usize>,
+    rx: mpsc::Receiver<StdinChunk>,
+    mut recorder: ChunkRecorder,
+) -> anyhow::Result<ThreadOutput> {
+    let chunk_size = args.common.chunk_size as usize;
+    let format = header.map(|h| h.format).unwrap_or(args.common.format);
+    let mut regenerator = regeneration
+        .map(|regeneration| Regenerator::new(regeneration, chunk_size, args.common.checksum));
+    for StdinChunk { chunk, offset, data, first_header } in rx {
+        let read_size = data.len();
+        let result = validate_sectors(chunk, &data, recorder.checksum(), sector_size);
+        let result = result.and_then(|()| {
+            if format == ChunkFormat::V2 && chunk == 0 {
+                // report a missing header
+                let found = [ChunkHeader::decode(&data)];
+                first_chunk_header(&found, header, read_size as u64, args, region_chunks)?;
+            }
+            let expected =
+                first_header.map(|first| first.following(chunk, read_size as u64, region_chunks));
+            if let Some(expected) = &expected {
+                let width = args.common.checksum.width();
+                // the stream read has an unknown end without `--size`
+                let num_chunks =
+                    args.size().map_or(u64::MAX, |size| size.div_ceil(chunk_size as u64));
+                validate_chunk_header(
+                    chunk, offset, &data, expected, chunk_size, width, num_chunks,
+                )?;
+            }
+            match &mut regenerator {
+                Some(regenerator) => {
+                    let index = expected.map_or(chunk, |h| h.index);
+                    regenerator.check(chunk, index, offset, &data, expected)
+                }
+                None => Ok(()),
+            }
+        });
+        if result.is_err()
+            && args.diff
+            && let Some(regenerator) = &mut regenerator
+        {
+            let expected =
+                first_header.map(|first| first.following(chunk, read_size as u64, region_chunks));
+            let index = expected.map_or(chunk, |h| h.index);
+            regenerator.diff(index, offset, &data, expected).log(chunk);
+        }
+        match result {
+            Err(e) if args.corruption.keep_going => recorder.corrupted(CorruptedChunk {
+                chunk,
+                offset,
+                length: read_size as u64,
+                error: e.downcast()?,
+            }),
+            Err(e) => return Err(locate_corruption(e, chunk, offset, read_size)),
+            Ok(()) => {}
+        }
+        if !recorder.record(&data) {
+            // the summary has stopped, because another thread failed
+            break;
+        }
+    }
+    Ok(recorder.finish())
+}
+
+/// Check the header of a chunk, with `--format v2`, once the chunk matches its checksum
+///
+/// `offset` is the offset of the chunk in the file, from which the offset of the data of a
+/// misplaced chunk is derived, if it belongs to one of the `num_chunks` chunks of the stream.
+/// `chunk` is the one of the chunk in the stream.
+pub fn validate_chunk_header(
+    chunk: u64,
+    offset: u64,
+    buffer: &[u8],
+    expected: &ChunkHeader,
+    chunk_size: usize,
+    width: usize,
+    num_chunks: u64,
+) -> anyhow::Result<()> {
+    if !ChunkHeader::fits(expected.length as usize, width) {
+        // too short to hold a header
+        return Ok(());
+    }
+    let Some(header) = ChunkHeader::decode(buffer) else {
+        return Err(ValidationError::MissingChunkHeader { chunk }.into());
+    };
+    let error = if header.fingerprint != expected.fingerprint {
+        ValidationError::SeedFingerprint {
+            chunk,
+            expected: expected.fingerprint,
+            found: header.fingerprint,
+        }
+    } else if header.run_id != expected.run_id {
+        ValidationError::Stale { chunk, offset, expected: expected.run_id, found: header.run_id }
+    } else if header.index != expected.index {
+        // the index of the first chunk of the stream
+        let first = expected.index - chunk;
+        let data_offset = header
+            .index
+            .checked_sub(first)
+            .filter(|source| *source < num_chunks)
+            .map(|source| offset - chunk * chunk_size as u64 + source * chunk_size as u64);
+        ValidationError::Misplaced { chunk, offset, data_offset }
+    } else if header.length != expected.length {
+        ValidationError::ChunkLength { chunk, expected: expected.length, found: header.length }
+    } else {
+        return Ok(());
+    };
+    Err(error.into())
+}
+
+pub fn validate_chunk<C: ChunkChecksum>(
+    chunk: u64,
+    buffer: &[u8],
+    global_checksum: &mut C,
+) -> anyhow::Result<()> {
+    let width = global_checksum.width();
     let read_size = buffer.len();
-    if read_size >= 4 {
-        hasher.update(&buffer[..read_size - 4]);
-        global_hasher.combine(&hasher);
-        // global_hasher.update(&buffer[read_size - 4..read_size]);
-        let stream_checksum =
-            u32::from_le_bytes(buffer[read_size - 4..read_size].try_into().unwrap());
-        let checksum = hasher.finalize();
+    if read_size >= width {
+        let checksum = global_checksum.update(&buffer[..read_size - width]);
+        let mut checksum_bytes = [0u8; 8];
+        checksum_bytes[..width].copy_from_slice(&buffer[read_size - width..read_size]);
+        let stream_checksum = u64::from_le_bytes(checksum_bytes);
         if stream_checksum != checksum {
-            return Err(anyhow!(
-                "Invalid checksum at chunk {chunk}. Expected {:08x}, found {:08x}.",
-                stream_checksum,
-                checksum
-            ));
+            return Err(ValidationError::ChunkChecksum {
+                chunk,
+                expected: stream_checksum,
+                found: checksum,
+                width,
+                sectors: Vec::new(),
+            }
+            .into());
         }
     } else {
-        global_hasher.update(&buffer[..read_size]);
+        global_checksum.update(&buffer[..read_size]);
         for v in buffer[..read_size].iter() {
             if *v != 0 {
-                return Err(anyhow!("Invalid non-zero value at the end of the file"));
+                return Err(ValidationError::NonZeroTail.into());
             }
         }
     }
     Ok(())
 }
+
+/// The chunk size of the stream starting with `data`, which holds the whole stream if `whole`
+///
+/// It's the length recorded in the header of the first chunk with `--format v2`, or else the
+/// smallest of the usual chunk sizes whose first chunks match their checksum.
+fn detect_chunk_size(data: &[u8], whole: bool, checksum: ChecksumAlgorithm) -> Option<usize> {
+    let matches = |size: usize| {
+        // the last chunk of the probe is complete only if it's the last one of the stream
+        let mut chunks =
+            data.chunks(size).take(2).filter(|chunk| whole || chunk.len() == size).peekable();
+        chunks.peek().is_some()
+            && chunks.all(|chunk| validate_chunk(0, chunk, &mut checksum.stream_checksum()).is_ok())
+    };
+    if let Some(header) = ChunkHeader::decode(data)
+        && header.length > 0
+        && matches(header.length as usize)
+    {
+        return Some(header.length as usize);
+    }
+    (9..=24).map(|shift| 1 << shift).find(|&size| matches(size))
+}
+
+/// Same as validate_chunk, locating the damaged sectors of a chunk not matching its checksum,
+/// with `--sector-checksums`
+fn validate_sectors<C: ChunkChecksum>(
+    chunk: u64,
+    buffer: &[u8],
+    global_checksum: &mut C,
+    sector_size: Option<usize>,
+) -> anyhow::Result<()> {
+    let result = validate_chunk(chunk, buffer, global_checksum);
+    let Some(sector_size) = sector_size else {
+        return result;
+    };
+    result.map_err(|e| match e.downcast() {
+        Ok(ValidationError::ChunkChecksum { chunk, expected, found, width, .. }) => {
+            let sectors = sector::damaged(&buffer[..buffer.len() - width], sector_size);
+            ValidationError::ChunkChecksum { chunk, expected, found, width, sectors }.into()
+        }
+        Ok(error) => error.into(),
+        Err(e) => e,
+    })
+}
+
+#[test]
+fn sample_is_spread_across_the_stream() {
+    let mut rng = rand::rng();
+    let chunks = sample_chunks(1000, 10, &mut rng);
+    assert_eq!(chunks.len(), 10);
+    for (i, chunk) in chunks.iter().enumerate() {
+        assert!((i as u64 * 100..(i as u64 + 1) * 100).contains(chunk), "{chunks:?}");
+    }
+    assert_eq!(sample_chunks(5, 10, &mut rng), vec![0, 1, 2, 3, 4]);
+    assert_eq!(parse_percentage("5%"), Ok(5.0));
+    assert!(parse_percentage("0").is_err());
+    assert!(parse_percentage("101%").is_err());
+}
+
+#[test]
+fn chunk_size_detected_from_the_checksums() {
+    use crate::generate::generate_chunk;
+
+    let stream = |chunk_size: usize, size: usize, layout: &ChunkLayout| {
+        let mut rng = RngAlgorithm::default().rng(Seed::U64(1));
+        let mut checksum = ChecksumAlgorithm::default().stream_checksum();
+        let mut data = Vec::new();
+        for offset in (0..size).step_by(chunk_size) {
+            let mut buffer = vec![0; chunk_size.div_ceil(8) * 8];
+            let length = chunk_size.min(size - offset);
+            generate_chunk_with_layout(&mut rng, &mut buffer, length, layout, &mut checksum);
+            data.extend_from_slice(&buffer[..length]);
+        }
+        data
+    };
+    let crc32 = ChecksumAlgorithm::Crc32;
+    let data = stream(64 << 10, 1 << 20, &ChunkLayout::default());
+    assert_eq!(detect_chunk_size(&data, true, crc32), Some(64 << 10));
+    // the probe ends in the middle of a chunk
+    assert_eq!(detect_chunk_size(&data[..100_000], false, crc32), Some(64 << 10));
+    assert_eq!(detect_chunk_size(&data, true, ChecksumAlgorithm::Xxh3), None);
+
+    // any size from the header of the first chunk
+    let header = ChunkHeader { index: 0, fingerprint: 1, length: 10_000, run_id: 2 };
+    let data = stream(10_000, 50_000, &ChunkLayout { header: Some(header), ..Default::default() });
+    assert_eq!(detect_chunk_size(&data, true, crc32), Some(10_000));
+
+    let mut rng = RngAlgorithm::default().rng(Seed::U64(1));
+    let mut buffer = vec![0; 4096];
+    generate_chunk(&mut rng, &mut buffer, 4096, &mut crc32.stream_checksum());
+    assert_eq!(detect_chunk_size(&buffer, false, crc32), Some(4096));
+    assert_eq!(detect_chunk_size(&[0; 100], false, crc32), None);
+}
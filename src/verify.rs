@@ -0,0 +1,239 @@
+//! Write a random stream and read it back, in a single run
+
+use clap::Args;
+use human_units::{FormatDuration as _, FormatSize as _};
+use log::{error, info};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
+use crate::cli::CommonArgs;
+use crate::decompress::Decompress;
+use crate::devices;
+use crate::error::{Error, UsageError, ValidationError, exit_code, usage};
+use crate::generate::{GenerateArgs, generate_stream, save_checksum_file};
+use crate::net;
+use crate::report::{self, Phase, Report};
+use crate::validate::{CorruptionArgs, ValidateArgs, validate_stream};
+
+/// Generate a random stream in a file, then validate it
+///
+/// The stream is validated with the parameters used to generate it, and its checksum and digest
+/// must match the generated ones.
+#[derive(Args, Debug)]
+pub struct VerifyArgs {
+    #[clap(flatten)]
+    pub generate: GenerateArgs,
+
+    /// The number of write and read cycles, for a burn-in test
+    ///
+    /// Each pass writes a different stream, its seed being derived from the seed of the first
+    /// one. The passes stop at the first failure, unless `--keep-going` is used.
+    #[clap(long, default_value = "1", value_parser = clap::value_parser!(u32).range(1..))]
+    pub passes: u32,
+
+    #[clap(flatten)]
+    pub corruption: CorruptionArgs,
+}
+
+pub fn verify(args: &VerifyArgs, cancel: Arc<AtomicBool>) -> Result<i32, Error> {
+    // the arguments are checked before the run, which a usage error doesn't give a verdict
+    if args.generate.file.is_none() {
+        return Err(usage("verify needs a file to write the stream to").into());
+    }
+    // the stream read back must be the one written
+    net::reject_options("verify", &[("--duration", args.generate.common.duration.is_some())])?;
+    if args.generate.common.print_checksum && !args.generate.more_files.is_empty() {
+        return Err(usage("--print-checksum takes a single file").into());
+    }
+    if !args.generate.more_files.is_empty() {
+        return verify_devices(args, cancel).map_err(Error::from);
+    }
+    let mut report = Report::new("verify", args.generate.common.output);
+    let result = verify_stream(args, cancel, &mut report);
+    match &result {
+        Ok(0) => info!("verdict: pass"),
+        Ok(_) => {}
+        // the arguments refused by generate or validate
+        Err(e) if e.is::<UsageError>() => {}
+        Err(_) => error!("verdict: fail"),
+    }
+    if args.generate.common.print_checksum {
+        report.print_checksum(&result);
+    }
+    report.finish(&result, true);
+    result.map_err(Error::from)
+}
+
+/// Verify all the files at once, each with its own stream
+fn verify_devices(args: &VerifyArgs, cancel: Arc<AtomicBool>) -> anyhow::Result<i32> {
+    if args.corruption.error_map.is_some() || args.corruption.badblocks_out.is_some() {
+        return Err(usage("--error-map and --badblocks-out take a single file"));
+    }
+    let files = args.generate.files();
+    let output = args.generate.common.output;
+    let exit_code = devices::run(
+        "verify",
+        output,
+        &files,
+        files.len(),
+        &cancel,
+        |index, file, cancel, report| {
+            let args = VerifyArgs {
+                generate: args.generate.for_device(index, file),
+                passes: args.passes,
+                corruption: args.corruption.clone(),
+            };
+            verify_stream(&args, cancel, report)
+        },
+    );
+    match exit_code {
+        0 => info!("verdict: pass"),
+        exit_code::USAGE => {}
+        _ => error!("verdict: fail"),
+    }
+    Ok(exit_code)
+}
+
+fn verify_stream(
+    args: &VerifyArgs,
+    cancel: Arc<AtomicBool>,
+    report: &mut Report,
+) -> anyhow::Result<i32> {
+    let generate = args.generate.with_device_seed()?;
+    if args.passes == 1 {
+        return verify_pass(&generate, args, 1, cancel, report);
+    }
+
+    let seed = generate.seed();
+    let mut errors = Vec::new();
+    let mut first_error = None;
+    for pass in 1..=args.passes {
+        info!("pass {pass}/{}", args.passes);
+        let generate =
+            GenerateArgs { seed: seed.for_pass(pass - 1), seed_string: None, ..generate.clone() };
+        match verify_pass(&generate, args, pass, cancel.clone(), report) {
+            Ok(0) => errors.push(0),
+            Ok(code) => return Ok(code),
+            Err(e) => {
+                errors.push(match e.downcast_ref::<ValidationError>() {
+                    Some(ValidationError::Corrupted { chunks, .. }) => *chunks,
+                    _ => 1,
+                });
+                first_error.get_or_insert(e);
+                if !args.corruption.keep_going {
+                    break;
+                }
+            }
+        }
+    }
+    summarize_passes(&report.phases, &errors);
+    match first_error {
+        Some(e) => Err(e),
+        None => Ok(0),
+    }
+}
+
+/// Write the stream, and read it back
+fn verify_pass(
+    generate: &GenerateArgs,
+    args: &VerifyArgs,
+    pass: u32,
+    cancel: Arc<AtomicBool>,
+    report: &mut Report,
+) -> anyhow::Result<i32> {
+    let common = &generate.common;
+
+    info!("writing the stream");
+    let mut written = Report::new("generate", common.output);
+    let result = generate_stream(generate, cancel.clone(), &mut written);
+    report.phases.push(log_phase(&written, pass));
+    report.extend_threads(&written);
+    report.bytes += written.bytes;
+    let code = result?;
+    if code != 0 {
+        return Ok(code);
+    }
+
+    info!("reading the stream back");
+    let validate_args = ValidateArgs {
+        file: generate.file.clone(),
+        more_files: Vec::new(),
+        from_file: None,
+        manifest: None,
+        tar: false,
+        decompress: Decompress::None,
+        file_jobs: 16,
+        position: generate.position,
+        length: None,
+        expected_checksum: written.checksum.clone(),
+        expected_digest: written.digest.clone(),
+        checksum_file: None,
+        checkpoint: None,
+        shard_size: generate.shard_size,
+        run_id: generate.run_id,
+        regenerate: false,
+        diff: false,
+        seed: None,
+        seed_string: None,
+        seed_from_device: false,
+        sample: None,
+        sample_chunks: None,
+        sparse: false,
+        incremental: None,
+        incremental_window: 30,
+        incremental_size: None,
+        read_retries: 0,
+        retry_delay: 100,
+        retry_reopen: false,
+        corruption: args.corruption.clone(),
+        // the file may be larger than the stream, with --no-truncate
+        common: CommonArgs { size: Some(written.bytes), ..common.clone() },
+    };
+    let mut read = Report::new("validate", common.output);
+    let result = validate_stream(&validate_args, cancel, &mut read);
+    report.phases.push(log_phase(&read, pass));
+    report.extend_threads(&read);
+    report.errors.extend(read.errors);
+    report.checksum = read.checksum;
+    report.digest = read.digest;
+    // the checksum file of the stream left in the file, by the last pass
+    result.and_then(|code| save_checksum_file(generate, &written, code))
+}
+
+/// Log the throughput of a phase, and return it for the report
+fn log_phase(report: &Report, pass: u32) -> Phase {
+    let phase = report.phase(pass);
+    info!(
+        "{}: {} in {}, {}/s",
+        phase.name,
+        phase.stats.bytes.format_size(),
+        phase.stats.elapsed.format_duration(),
+        report::throughput(phase.stats.bytes, phase.stats.elapsed).format_size()
+    );
+    phase
+}
+
+/// Log the statistics of all the passes, with the errors of each one
+fn summarize_passes(phases: &[Phase], errors: &[usize]) {
+    let total =
+        |name| -> u64 { phases.iter().filter(|p| p.name == name).map(|p| p.stats.bytes).sum() };
+    info!("total written: {}", total("generate").format_size());
+    info!("total read: {}", total("validate").format_size());
+    for (i, errors) in errors.iter().enumerate() {
+        let pass = i as u32 + 1;
+        let throughput = |name| {
+            phases
+                .iter()
+                .find(|p| p.pass == pass && p.name == name)
+                .map(|p| report::throughput(p.stats.bytes, p.stats.elapsed))
+                .unwrap_or(0)
+        };
+        info!(
+            "pass {pass}: write {}/s, read {}/s, {errors} errors",
+            throughput("generate").format_size(),
+            throughput("validate").format_size()
+        );
+    }
+    let failed = errors.iter().filter(|e| **e > 0).count();
+    info!("failed passes: {failed}/{}", errors.len());
+}
@@ -0,0 +1,131 @@
+//! The file or device watched during a run, its size read again every second
+//!
+//! A device unplugged or shrunk, or a file deleted or truncated by another process, stops the
+//! run with an error telling how far each thread got, instead of the failures of its reads or
+//! writes. A target growing is only logged, the stream not covering the new space.
+
+use log::warn;
+use std::fmt::Write as _;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::read_file_size;
+
+const INTERVAL: Duration = Duration::from_secs(1);
+
+/// What happened to the target, which stopped the run
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Change {
+    Vanished,
+    /// Its new size, smaller than the end of the stream
+    Shrunk(u64),
+}
+
+/// The thread watching the target of the run
+pub(crate) struct TargetWatch {
+    file: PathBuf,
+    /// The size of the target at the start of the run
+    size: u64,
+    /// The end of the stream, or of the target if shorter
+    end: u64,
+    stop: Sender<()>,
+    handle: JoinHandle<Option<Change>>,
+}
+
+impl TargetWatch {
+    /// Watch the file or device holding the stream up to `end`, stopping the run if it can't be
+    /// read or written to the end anymore
+    pub fn start(file: &Path, end: u64, cancel: Arc<AtomicBool>) -> anyhow::Result<Self> {
+        let mut size = read_file_size(file)?;
+        let (initial_size, end) = (size, end.min(size));
+        let (stop, stopped) = mpsc::channel();
+        let path = file.to_path_buf();
+        let handle = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(INTERVAL) {
+                match check(&path, end) {
+                    Ok(new_size) if new_size != size => {
+                        warn!(
+                            "the size of {} changed from {size} to {new_size} bytes",
+                            path.display()
+                        );
+                        size = new_size;
+                    }
+                    Ok(_) => {}
+                    Err(change) => {
+                        // tell the threads to stop
+                        cancel.store(true, Ordering::Relaxed);
+                        return Some(change);
+                    }
+                }
+            }
+            None
+        });
+        Ok(TargetWatch { file: file.to_path_buf(), size: initial_size, end, stop, handle })
+    }
+
+    /// Stop watching, once the threads are done, and fail if the target changed
+    ///
+    /// `offsets` are the ends of the last chunks processed by the threads, if any. The target is
+    /// checked once more, as the threads may have failed first.
+    pub fn finish(self, offsets: &[Option<u64>]) -> io::Result<()> {
+        let _ = self.stop.send(());
+        let change = self.handle.join().ok().flatten();
+        let Some(change) = change.or_else(|| check(&self.file, self.end).err()) else {
+            return Ok(());
+        };
+        let file = self.file.display();
+        let (kind, mut message) = match change {
+            Change::Vanished => {
+                (io::ErrorKind::NotFound, format!("{file} vanished during the run"))
+            }
+            Change::Shrunk(size) => (
+                io::ErrorKind::UnexpectedEof,
+                format!("{file} shrunk from {} to {size} bytes during the run", self.size),
+            ),
+        };
+        message.push_str(&positions(offsets));
+        Err(io::Error::new(kind, message))
+    }
+}
+
+/// The size of the target, or what happened to it if it doesn't hold the stream anymore
+fn check(file: &Path, end: u64) -> Result<u64, Change> {
+    match read_file_size(file) {
+        Ok(size) if size < end => Err(Change::Shrunk(size)),
+        Ok(size) => Ok(size),
+        Err(_) => Err(Change::Vanished),
+    }
+}
+
+/// How far each thread got
+fn positions(offsets: &[Option<u64>]) -> String {
+    let mut positions = String::new();
+    for (thread, offset) in offsets.iter().enumerate() {
+        let separator = if thread == 0 { ": " } else { ", " };
+        match offset {
+            Some(offset) => write!(positions, "{separator}thread {thread} got to offset {offset}"),
+            None => write!(positions, "{separator}thread {thread} processed no chunk"),
+        }
+        .unwrap();
+    }
+    positions
+}
+
+#[test]
+fn target_changes() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("out.bin");
+    std::fs::write(&path, vec![0; 4096]).unwrap();
+    assert_eq!(check(&path, 4096), Ok(4096));
+    assert_eq!(check(&path, 8192), Err(Change::Shrunk(4096)));
+    assert_eq!(check(&dir.path().join("gone.bin"), 4096), Err(Change::Vanished));
+    assert_eq!(
+        positions(&[Some(1 << 20), None]),
+        ": thread 0 got to offset 1048576, thread 1 processed no chunk"
+    );
+}
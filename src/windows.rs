@@ -0,0 +1,79 @@
+//! The Windows specific I/O, for the files and the physical drives like `\\.\PhysicalDrive0`
+
+use std::ffi::c_void;
+use std::fs::File;
+use std::io;
+use std::os::windows::io::AsRawHandle as _;
+use std::path::Path;
+use std::ptr;
+
+use windows_sys::Win32::Storage::FileSystem::{
+    FILE_ALLOCATION_INFO, FileAllocationInfo, SetFileInformationByHandle,
+};
+use windows_sys::Win32::System::IO::DeviceIoControl;
+use windows_sys::Win32::System::Ioctl::{
+    DISK_GEOMETRY, GET_LENGTH_INFORMATION, IOCTL_DISK_GET_DRIVE_GEOMETRY,
+    IOCTL_DISK_GET_LENGTH_INFO,
+};
+
+/// Whether the path designates a device, rather than a file
+pub fn is_device(path: &Path) -> bool {
+    path.to_str().is_some_and(|path| path.starts_with(r"\\.\"))
+}
+
+/// The size of the device, in bytes
+pub fn device_size(file: &File) -> io::Result<u64> {
+    // SAFETY: the output of the ioctl is a GET_LENGTH_INFORMATION
+    let info: GET_LENGTH_INFORMATION = unsafe { ioctl(file, IOCTL_DISK_GET_LENGTH_INFO)? };
+    Ok(info.Length as u64)
+}
+
+/// The logical sector size of the device, to which the unbuffered I/O must be aligned
+pub fn sector_size(file: &File) -> io::Result<usize> {
+    // SAFETY: the output of the ioctl is a DISK_GEOMETRY
+    let geometry: DISK_GEOMETRY = unsafe { ioctl(file, IOCTL_DISK_GET_DRIVE_GEOMETRY)? };
+    Ok(geometry.BytesPerSector as usize)
+}
+
+/// Allocate the space of the file up to `end`
+pub fn allocate(file: &File, end: u64) -> io::Result<()> {
+    let info = FILE_ALLOCATION_INFO { AllocationSize: end as i64 };
+    // SAFETY: the buffer is a FILE_ALLOCATION_INFO, as expected for this class
+    let result = unsafe {
+        SetFileInformationByHandle(
+            file.as_raw_handle(),
+            FileAllocationInfo,
+            (&info as *const FILE_ALLOCATION_INFO).cast::<c_void>(),
+            size_of::<FILE_ALLOCATION_INFO>() as u32,
+        )
+    };
+    if result == 0 { Err(io::Error::last_os_error()) } else { Ok(()) }
+}
+
+/// Run an ioctl without input, returning a `T`
+///
+/// # Safety
+///
+/// `T` must be the output structure of the ioctl `code`.
+unsafe fn ioctl<T>(file: &File, code: u32) -> io::Result<T> {
+    let mut output = std::mem::MaybeUninit::<T>::zeroed();
+    let mut returned = 0;
+    // SAFETY: the output buffer is large enough for a T
+    let result = unsafe {
+        DeviceIoControl(
+            file.as_raw_handle(),
+            code,
+            ptr::null(),
+            0,
+            output.as_mut_ptr().cast::<c_void>(),
+            size_of::<T>() as u32,
+            &mut returned,
+            ptr::null_mut(),
+        )
+    };
+    if result == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: initialized by the ioctl
+    Ok(unsafe { output.assume_init() })
+}
@@ -0,0 +1,549 @@
+//! Distribution of the chunks of the stream between the worker threads
+
+use std::io::{self, Write};
+use std::iter::StepBy;
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use itertools::Itertools as _;
+
+use crate::checkpoint::CheckpointTracker;
+use crate::checksum::{ChecksumAlgorithm, StreamChecksum};
+use crate::digest::DigestAlgorithm;
+use crate::error::CorruptedChunk;
+use crate::report::ThreadStats;
+use crate::{ChunkChecksum, StreamSummary};
+
+/// The number of chunks a worker can get ahead of the thread computing the digest, or writing
+/// the chunks in order
+const ORDERED_QUEUE_DEPTH: usize = 16;
+
+/// The number of batches of the shared queue per thread, so the threads done early take the
+/// batches a slow one would have been left with
+const BATCHES_PER_THREAD: u64 = 16;
+
+/// The largest batch of the shared queue, in bytes
+const MAX_BATCH_SIZE: u64 = 64 << 20;
+
+/// The batches a thread takes per second at least with `--bwlimit`, so the `--duration`, only
+/// checked between the batches, isn't overrun by more than a fraction of a second
+const MIN_BATCHES_PER_SECOND: u64 = 4;
+
+/// The chunks assigned to one worker thread
+#[derive(Clone, Debug)]
+pub(crate) struct ThreadWork {
+    pub first_chunk: u64,
+    pub end_chunk: u64,
+    pub step: u64,
+}
+
+impl ThreadWork {
+    pub fn chunks(&self) -> StepBy<Range<u64>> {
+        (self.first_chunk..self.end_chunk).step_by(self.step as usize)
+    }
+
+    /// The range of the stream spanned by the chunks, in bytes
+    pub fn byte_range(&self, chunk_size: usize, stream_size: u64) -> Range<u64> {
+        let start = (self.first_chunk * chunk_size as u64).min(stream_size);
+        let end = (self.end_chunk * chunk_size as u64).min(stream_size);
+        start..end
+    }
+}
+
+/// The chunks of the stream not processed yet, taken a batch at a time by the threads
+///
+/// A thread slowed down by a region of the device takes fewer batches, instead of holding up the
+/// whole run with a fixed share of the stream.
+///
+/// The batches being taken in the stream order, the chunks taken before the `--duration` has
+/// elapsed are the start of the stream. With `--bwlimit`, the batches are small enough for each
+/// thread to process them in a fraction of a second.
+#[derive(Debug)]
+pub(crate) struct WorkQueue {
+    next_chunk: AtomicU64,
+    end_chunk: u64,
+    batch_chunks: u64,
+    deadline: Option<Instant>,
+}
+
+impl WorkQueue {
+    pub fn new(
+        num_chunks: u64,
+        num_threads: usize,
+        chunk_size: usize,
+        deadline: Option<Instant>,
+        bwlimit: Option<u64>,
+    ) -> Self {
+        // the threads share the bandwidth
+        let max_size = bwlimit.map_or(MAX_BATCH_SIZE, |bwlimit| {
+            MAX_BATCH_SIZE.min(bwlimit / num_threads as u64 / MIN_BATCHES_PER_SECOND)
+        });
+        let max_chunks = (max_size / chunk_size as u64).max(1);
+        let batch_chunks =
+            (num_chunks / (num_threads as u64 * BATCHES_PER_THREAD)).clamp(1, max_chunks);
+        WorkQueue { next_chunk: AtomicU64::new(0), end_chunk: num_chunks, batch_chunks, deadline }
+    }
+
+    pub fn batch_chunks(&self) -> u64 {
+        self.batch_chunks
+    }
+
+    /// Whether chunks were left in the queue at the deadline, once the threads are done
+    ///
+    /// The ones taken, all processed unless the run was cancelled, are the start of the stream.
+    pub fn expired(&self) -> bool {
+        self.next_chunk.load(Ordering::Relaxed) < self.end_chunk
+    }
+
+    /// The next batch of chunks, if any is left
+    fn take(&self) -> Option<ThreadWork> {
+        if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return None;
+        }
+        let first_chunk = self.next_chunk.fetch_add(self.batch_chunks, Ordering::Relaxed);
+        (first_chunk < self.end_chunk).then(|| ThreadWork {
+            first_chunk,
+            end_chunk: (first_chunk + self.batch_chunks).min(self.end_chunk),
+            step: 1,
+        })
+    }
+}
+
+/// How far each worker thread got, and the corrupted chunks found so far
+#[derive(Clone, Debug)]
+pub(crate) struct ThreadPositions(Arc<Positions>);
+
+#[derive(Debug)]
+struct Positions {
+    /// The chunk after the last one processed by each thread, 0 before the first one
+    ends: Box<[AtomicU64]>,
+    corrupted: AtomicU64,
+    /// The offset of the stream in the file
+    position: u64,
+    chunk_size: usize,
+    stream_size: u64,
+}
+
+impl ThreadPositions {
+    pub fn new(num_threads: usize, position: u64, chunk_size: usize, stream_size: u64) -> Self {
+        ThreadPositions(Arc::new(Positions {
+            ends: (0..num_threads).map(|_| AtomicU64::new(0)).collect(),
+            corrupted: AtomicU64::new(0),
+            position,
+            chunk_size,
+            stream_size,
+        }))
+    }
+
+    fn done(&self, thread: usize, chunk: u64) {
+        self.0.ends[thread].store(chunk + 1, Ordering::Relaxed);
+    }
+
+    /// The offset in the file of the end of the last chunk processed by each thread, if any
+    pub fn offsets(&self) -> Vec<Option<u64>> {
+        let Positions { position, chunk_size, stream_size, .. } = *self.0;
+        let offset = |end: u64| position + (end * chunk_size as u64).min(stream_size);
+        let ends = self.0.ends.iter().map(|end| end.load(Ordering::Relaxed));
+        ends.map(|end| Some(end).filter(|end| *end > 0).map(offset)).collect()
+    }
+
+    pub fn corrupted(&self) -> u64 {
+        self.0.corrupted.load(Ordering::Relaxed)
+    }
+}
+
+/// The chunks processed by a worker thread
+pub(crate) enum Assignment {
+    /// Its own chunks, assigned upfront
+    Fixed(ThreadWork, Box<ChunkRecorder>),
+    /// The batches it takes from the queue shared by the threads, each one summarized on its own
+    Queued {
+        queue: Arc<WorkQueue>,
+        checksum: ChecksumAlgorithm,
+        digest: Option<DigestAlgorithm>,
+        stream_size: u64,
+        chunk_size: usize,
+        position: Option<(ThreadPositions, usize)>,
+    },
+}
+
+impl Assignment {
+    /// Keep track of the chunks processed by the thread `thread`
+    pub fn positioned(self, positions: &ThreadPositions, thread: usize) -> Self {
+        match self {
+            Assignment::Fixed(work, recorder) => {
+                Assignment::Fixed(work, Box::new(recorder.positioned(positions, thread)))
+            }
+            Assignment::Queued { queue, checksum, digest, stream_size, chunk_size, .. } => {
+                let position = Some((positions.clone(), thread));
+                Assignment::Queued { queue, checksum, digest, stream_size, chunk_size, position }
+            }
+        }
+    }
+
+    /// Process the chunks with the worker, called once for each batch
+    pub fn run(
+        self,
+        cancel: &AtomicBool,
+        mut worker: impl FnMut(&ThreadWork, ChunkRecorder) -> anyhow::Result<ThreadOutput>,
+    ) -> anyhow::Result<ThreadOutput> {
+        let (queue, checksum, digest, stream_size, chunk_size, position) = match self {
+            Assignment::Fixed(work, recorder) => return worker(&work, *recorder),
+            Assignment::Queued { queue, checksum, digest, stream_size, chunk_size, position } => {
+                (queue, checksum, digest, stream_size, chunk_size, position)
+            }
+        };
+        let start = Instant::now();
+        let mut output = ThreadOutput {
+            summaries: Vec::new(),
+            stats: ThreadStats { bytes: 0, elapsed: Duration::ZERO },
+            corrupted: Vec::new(),
+        };
+        while !cancel.load(Ordering::Relaxed)
+            && let Some(work) = queue.take()
+        {
+            let offset = work.byte_range(chunk_size, stream_size).start;
+            let summary = StreamSummary::new(checksum, digest, offset);
+            let mut recorder = ChunkRecorder::new(Recording::Range { offset, summary });
+            if let Some((positions, thread)) = &position {
+                recorder = recorder.positioned(positions, *thread);
+            }
+            let batch = worker(&work, recorder)?;
+            output.summaries.extend(batch.summaries);
+            output.stats.bytes += batch.stats.bytes;
+            output.corrupted.extend(batch.corrupted);
+        }
+        output.stats.elapsed = start.elapsed();
+        Ok(output)
+    }
+}
+
+/// A chunk, as sent to the thread computing the digest
+pub(crate) struct OrderedChunk {
+    checksum: StreamChecksum,
+    data: Vec<u8>,
+}
+
+/// The result of a worker thread
+pub(crate) struct ThreadOutput {
+    /// The summary of each range processed, by its offset, if summarized by the worker
+    summaries: Vec<(u64, StreamSummary)>,
+    pub stats: ThreadStats,
+    /// The chunks which failed the validation, with `--keep-going`
+    pub corrupted: Vec<CorruptedChunk>,
+}
+
+/// How the chunks processed by a worker thread are summarized
+enum Recording {
+    /// The worker processes a contiguous range of the stream, and summarizes it itself
+    Range { offset: u64, summary: StreamSummary },
+    /// The worker sends its chunks to the thread summarizing the stream in order
+    Ordered { checksum: StreamChecksum, tx: SyncSender<OrderedChunk> },
+}
+
+/// Accounts for the chunks processed by a worker thread
+pub(crate) struct ChunkRecorder {
+    recording: Recording,
+    bytes: u64,
+    start: Instant,
+    corrupted: Vec<CorruptedChunk>,
+    checkpoint: Option<CheckpointTracker>,
+    position: Option<(ThreadPositions, usize)>,
+}
+
+impl ChunkRecorder {
+    fn new(recording: Recording) -> Self {
+        ChunkRecorder {
+            recording,
+            bytes: 0,
+            start: Instant::now(),
+            corrupted: Vec::new(),
+            checkpoint: None,
+            position: None,
+        }
+    }
+
+    /// Keep track of the chunks done by the thread `thread`
+    pub fn positioned(mut self, positions: &ThreadPositions, thread: usize) -> Self {
+        self.position = Some((positions.clone(), thread));
+        self
+    }
+
+    /// Resume the range of the thread from its checkpoint, and keep the checkpoint up to date
+    ///
+    /// The checkpoints require each thread to summarize its own range.
+    pub fn checkpointed(mut self, tracker: CheckpointTracker) -> Self {
+        if let Recording::Range { summary, .. } = &mut self.recording {
+            let range = tracker.range();
+            summary.bytes = range.bytes;
+            summary.checksum = range.stream_checksum(summary.checksum.algorithm());
+            self.checkpoint = Some(tracker);
+        }
+        self
+    }
+
+    /// Mark a recorded chunk as done, for the checkpoint and the position of the thread
+    pub fn done(&mut self, chunk: u64) -> io::Result<()> {
+        if let Some((positions, thread)) = &self.position {
+            positions.done(*thread, chunk);
+        }
+        match &mut self.checkpoint {
+            Some(tracker) => tracker.done(chunk),
+            None => Ok(()),
+        }
+    }
+
+    /// Record a chunk which failed the validation
+    pub fn corrupted(&mut self, chunk: CorruptedChunk) {
+        if let Some((positions, _)) = &self.position {
+            positions.0.corrupted.fetch_add(1, Ordering::Relaxed);
+        }
+        self.corrupted.push(chunk);
+    }
+
+    /// The stream checksum the next chunk must be added to
+    pub fn checksum(&mut self) -> &mut StreamChecksum {
+        match &mut self.recording {
+            Recording::Range { summary, .. } => &mut summary.checksum,
+            Recording::Ordered { checksum, .. } => {
+                *checksum = checksum.algorithm().stream_checksum();
+                checksum
+            }
+        }
+    }
+
+    /// Record the data of the chunk which has just been added to the checksum
+    ///
+    /// Returns `false` if the stream summary is not being computed anymore.
+    pub fn record(&mut self, data: &[u8]) -> bool {
+        self.bytes += data.len() as u64;
+        match &mut self.recording {
+            Recording::Range { summary, .. } => {
+                if let Some(digest) = &mut summary.digest {
+                    digest.update(data);
+                }
+                summary.bytes += data.len() as u64;
+                if let Some(tracker) = &mut self.checkpoint {
+                    tracker.recorded(data.len(), &summary.checksum);
+                }
+                true
+            }
+            Recording::Ordered { checksum, tx } => {
+                tx.send(OrderedChunk { checksum: checksum.clone(), data: data.to_vec() }).is_ok()
+            }
+        }
+    }
+
+    pub fn finish(self) -> ThreadOutput {
+        if let Some(tracker) = self.checkpoint {
+            tracker.finish();
+        }
+        let stats = ThreadStats { bytes: self.bytes, elapsed: self.start.elapsed() };
+        let summaries = match self.recording {
+            Recording::Range { offset, summary } => vec![(offset, summary)],
+            Recording::Ordered { .. } => Vec::new(),
+        };
+        ThreadOutput { summaries, stats, corrupted: self.corrupted }
+    }
+}
+
+/// Wait for the worker threads, and return their output, or the errors of the ones which failed
+pub(crate) fn join(
+    handles: Vec<JoinHandle<anyhow::Result<ThreadOutput>>>,
+) -> Result<Vec<ThreadOutput>, Vec<anyhow::Error>> {
+    let (outputs, errors): (Vec<_>, Vec<_>) =
+        handles.into_iter().map(join_thread).partition_result();
+    if errors.is_empty() { Ok(outputs) } else { Err(errors) }
+}
+
+/// The output of the worker thread, a panic being reported as its error
+fn join_thread<T>(handle: JoinHandle<anyhow::Result<T>>) -> anyhow::Result<T> {
+    handle.join().unwrap_or_else(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        Err(anyhow::anyhow!("A worker thread panicked: {message}"))
+    })
+}
+
+/// How the summary of the stream is computed from the work of the threads
+pub(crate) enum Summarizer {
+    /// Each thread summarizes its own contiguous range of the stream, and the summaries are
+    /// combined at the end
+    Ranges { checksum: ChecksumAlgorithm, digest: Option<DigestAlgorithm>, stream_size: u64 },
+    /// The digest can't be combined, or the chunks must be written in order: the chunks are
+    /// interleaved between the threads, which send them to a dedicated thread summarizing the
+    /// stream in order
+    Ordered {
+        checksum: ChecksumAlgorithm,
+        senders: Vec<Option<SyncSender<OrderedChunk>>>,
+        handle: JoinHandle<io::Result<StreamSummary>>,
+    },
+}
+
+impl Summarizer {
+    pub fn new(
+        checksum: ChecksumAlgorithm,
+        digest: Option<DigestAlgorithm>,
+        stream_size: u64,
+        num_chunks: u64,
+        num_threads: usize,
+    ) -> Self {
+        if digest.is_none_or(|d| d.is_combinable()) {
+            return Summarizer::Ranges { checksum, digest, stream_size };
+        }
+        Self::ordered(checksum, digest, num_chunks, num_threads, None)
+    }
+
+    /// Summarize the stream in order, and write its chunks to `writer` if any
+    pub fn ordered(
+        checksum: ChecksumAlgorithm,
+        digest: Option<DigestAlgorithm>,
+        num_chunks: u64,
+        num_threads: usize,
+        writer: Option<Box<dyn Write + Send>>,
+    ) -> Self {
+        let (senders, receivers): (Vec<_>, Vec<_>) =
+            (0..num_threads).map(|_| mpsc::sync_channel(ORDERED_QUEUE_DEPTH)).unzip();
+        let handle = thread::spawn(move || {
+            summarize_in_order(checksum, digest, num_chunks, receivers, writer)
+        });
+        Summarizer::Ordered { checksum, senders: senders.into_iter().map(Some).collect(), handle }
+    }
+
+    /// Split the stream chunks between the threads
+    pub fn split(&self, num_chunks: u64, num_threads: usize) -> Vec<ThreadWork> {
+        let num_threads = num_threads as u64;
+        match self {
+            Summarizer::Ranges { .. } => {
+                let chunks_per_thread = num_chunks.div_ceil(num_threads);
+                (0..num_threads)
+                    .map(|i| ThreadWork {
+                        first_chunk: (i * chunks_per_thread).min(num_chunks),
+                        end_chunk: ((i + 1) * chunks_per_thread).min(num_chunks),
+                        step: 1,
+                    })
+                    .collect()
+            }
+            Summarizer::Ordered { .. } => (0..num_threads)
+                .map(|i| ThreadWork { first_chunk: i, end_chunk: num_chunks, step: num_threads })
+                .collect(),
+        }
+    }
+
+    /// The queue the threads take their chunks from, a batch at a time, if they summarize them
+    /// themselves
+    ///
+    /// No batch is taken after the deadline, with `--duration`.
+    pub fn queue(
+        &self,
+        num_chunks: u64,
+        num_threads: usize,
+        chunk_size: usize,
+        deadline: Option<Instant>,
+        bwlimit: Option<u64>,
+    ) -> Option<Arc<WorkQueue>> {
+        match self {
+            Summarizer::Ranges { .. } => Some(Arc::new(WorkQueue::new(
+                num_chunks,
+                num_threads,
+                chunk_size,
+                deadline,
+                bwlimit,
+            ))),
+            Summarizer::Ordered { .. } => None,
+        }
+    }
+
+    /// The batches of the queue, taken by a thread
+    pub fn queued(&self, queue: &Arc<WorkQueue>, chunk_size: usize) -> Assignment {
+        match self {
+            Summarizer::Ranges { checksum, digest, stream_size } => Assignment::Queued {
+                queue: queue.clone(),
+                checksum: *checksum,
+                digest: *digest,
+                stream_size: *stream_size,
+                chunk_size,
+                position: None,
+            },
+            Summarizer::Ordered { .. } => unreachable!("the ordered chunks can't be queued"),
+        }
+    }
+
+    /// Create the recorder of the thread `thread_index`
+    pub fn recorder(
+        &mut self,
+        thread_index: usize,
+        work: &ThreadWork,
+        chunk_size: usize,
+    ) -> ChunkRecorder {
+        match self {
+            Summarizer::Ranges { checksum, digest, stream_size } => {
+                let offset = (work.first_chunk * chunk_size as u64).min(*stream_size);
+                let summary = StreamSummary::new(*checksum, *digest, offset);
+                ChunkRecorder::new(Recording::Range { offset, summary })
+            }
+            Summarizer::Ordered { checksum, senders, .. } => {
+                let tx = senders[thread_index].take().expect("a single recorder per thread");
+                ChunkRecorder::new(Recording::Ordered { checksum: checksum.stream_checksum(), tx })
+            }
+        }
+    }
+
+    /// Compute the stream summary, once all the threads are done
+    ///
+    /// Fails if the chunks couldn't be written in order.
+    pub fn finish(self, outputs: Vec<ThreadOutput>) -> io::Result<StreamSummary> {
+        match self {
+            Summarizer::Ranges { checksum, digest, .. } => {
+                let mut summaries: Vec<_> = outputs.into_iter().flat_map(|o| o.summaries).collect();
+                // the batches taken from the queue are summarized in the stream order
+                summaries.sort_by_key(|(offset, _)| *offset);
+                let summaries: Vec<_> = summaries.into_iter().map(|(_, s)| s).collect();
+                match summaries.is_empty() {
+                    true => Ok(StreamSummary::new(checksum, digest, 0)),
+                    false => Ok(crate::combine_summaries(summaries)),
+                }
+            }
+            Summarizer::Ordered { senders, handle, .. } => {
+                drop(senders);
+                handle.join().unwrap()
+            }
+        }
+    }
+}
+
+fn summarize_in_order(
+    checksum: ChecksumAlgorithm,
+    digest: Option<DigestAlgorithm>,
+    num_chunks: u64,
+    receivers: Vec<Receiver<OrderedChunk>>,
+    mut writer: Option<Box<dyn Write + Send>>,
+) -> io::Result<StreamSummary> {
+    let mut summary = StreamSummary::new(checksum, digest, 0);
+    for chunk in 0..num_chunks {
+        // a closed channel means that a thread has stopped early
+        let Ok(received) = receivers[(chunk % receivers.len() as u64) as usize].recv() else {
+            break;
+        };
+        summary.checksum.combine(&received.checksum);
+        if let Some(digest) = &mut summary.digest {
+            digest.update(&received.data);
+        }
+        if let Some(writer) = &mut writer {
+            // on failure, the closed channels stop the threads
+            writer.write_all(&received.data)?;
+        }
+        summary.bytes += received.data.len() as u64;
+    }
+    if let Some(writer) = &mut writer {
+        writer.flush()?;
+    }
+    Ok(summary)
+}
@@ -0,0 +1,201 @@
+//! The xoshiro256++ random generator, able to jump ahead by any number of steps.
+//!
+//! xoshiro256 is linear over GF(2), so `n` steps can be computed from the polynomial
+//! `x^n mod P`, where `P` is the characteristic polynomial of the generator, the same way the
+//! reference `jump()` function skips 2^128 steps.
+
+use std::sync::OnceLock;
+
+use crate::rng::splitmix64;
+
+/// A polynomial over GF(2) of degree up to 256, bit `i` being the coefficient of `x^i`
+type Poly = [u64; 5];
+
+const DEGREE: usize = 256;
+
+#[derive(Clone, Debug)]
+pub struct Xoshiro256 {
+    s: [u64; 4],
+}
+
+impl Xoshiro256 {
+    /// Seed the generator with splitmix64, as recommended by its authors
+    pub fn seed_from_u64(mut seed: u64) -> Self {
+        Xoshiro256 { s: std::array::from_fn(|_| splitmix64(&mut seed)) }
+    }
+
+    /// Create the generator from its state, which must not be all zeros
+    pub fn from_state(s: [u64; 4]) -> Self {
+        assert!(s.iter().any(|w| *w != 0), "the state must not be all zeros");
+        Xoshiro256 { s }
+    }
+
+    fn step(&mut self) {
+        let s = &mut self.s;
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let result = self.s[0].wrapping_add(self.s[3]).rotate_left(23).wrapping_add(self.s[0]);
+        self.step();
+        result
+    }
+
+    pub fn fill_bytes(&mut self, buffer: &mut [u8]) {
+        for chunk in buffer.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    /// Apply the polynomial `jump` of the transition to the state
+    fn jump_with(&mut self, jump: &Poly) {
+        let mut s = [0u64; 4];
+        for bit in 0..DEGREE {
+            if jump[bit / 64] & (1 << (bit % 64)) != 0 {
+                for (acc, word) in s.iter_mut().zip(self.s) {
+                    *acc ^= word;
+                }
+            }
+            self.step();
+        }
+        self.s = s;
+    }
+
+    /// Skip `amount` values
+    pub fn advance(&mut self, amount: u64) {
+        if amount < DEGREE as u64 {
+            for _ in 0..amount {
+                self.step();
+            }
+            return;
+        }
+        let p = characteristic_polynomial();
+        let mut result: Poly = [1, 0, 0, 0, 0];
+        for bit in (0..64 - amount.leading_zeros()).rev() {
+            result = mul_mod(&result, &result, p);
+            if amount & (1 << bit) != 0 {
+                result = mul_x_mod(&result, p);
+            }
+        }
+        self.jump_with(&result);
+    }
+}
+
+/// `a * x mod p`
+fn mul_x_mod(a: &Poly, p: &Poly) -> Poly {
+    let mut r = [0u64; 5];
+    let mut carry = 0;
+    for (r, a) in r.iter_mut().zip(a) {
+        *r = (a << 1) | carry;
+        carry = a >> 63;
+    }
+    if r[4] & 1 != 0 {
+        for (r, p) in r.iter_mut().zip(p) {
+            *r ^= p;
+        }
+    }
+    r
+}
+
+/// `a * b mod p`
+fn mul_mod(a: &Poly, b: &Poly, p: &Poly) -> Poly {
+    let mut r = [0u64; 5];
+    for bit in (0..DEGREE).rev() {
+        r = mul_x_mod(&r, p);
+        if a[bit / 64] & (1 << (bit % 64)) != 0 {
+            for (r, b) in r.iter_mut().zip(b) {
+                *r ^= b;
+            }
+        }
+    }
+    r
+}
+
+/// Find the characteristic polynomial of the transition with the Berlekamp-Massey algorithm
+fn characteristic_polynomial() -> &'static Poly {
+    static POLY: OnceLock<Poly> = OnceLock::new();
+    POLY.get_or_init(|| {
+        let mut rng = Xoshiro256::seed_from_u64(0);
+        let sequence: Vec<u8> = (0..2 * DEGREE)
+            .map(|_| {
+                let bit = (rng.s[0] & 1) as u8;
+                rng.step();
+                bit
+            })
+            .collect();
+        // connection polynomial, c[0] = 1
+        let mut c = vec![0u8; 2 * DEGREE + 1];
+        let mut b = vec![0u8; 2 * DEGREE + 1];
+        c[0] = 1;
+        b[0] = 1;
+        let mut length = 0;
+        let mut m = 1;
+        for n in 0..sequence.len() {
+            let discrepancy = (1..=length).fold(sequence[n], |d, i| d ^ (c[i] & sequence[n - i]));
+            if discrepancy == 0 {
+                m += 1;
+            } else if 2 * length <= n {
+                let previous = c.clone();
+                for i in m..c.len() {
+                    c[i] ^= b[i - m];
+                }
+                length = n + 1 - length;
+                b = previous;
+                m = 1;
+            } else {
+                for i in m..c.len() {
+                    c[i] ^= b[i - m];
+                }
+                m += 1;
+            }
+        }
+        assert_eq!(length, DEGREE);
+        // P(x) = x^L + c1 x^(L-1) + ... + cL
+        let mut p = [0u64; 5];
+        for (i, &coefficient) in c[..=length].iter().enumerate() {
+            let bit = length - i;
+            p[bit / 64] |= (coefficient as u64) << (bit % 64);
+        }
+        p
+    })
+}
+
+#[test]
+fn xoshiro256_reference_values() {
+    let mut rng = Xoshiro256 { s: [1, 2, 3, 4] };
+    assert_eq!([rng.next_u64(), rng.next_u64()], [41943041, 58720359]);
+}
+
+#[test]
+fn xoshiro256_reference_jump_polynomial() {
+    // the polynomial of the reference jump() function, which skips 2^128 values
+    let p = characteristic_polynomial();
+    let mut result: Poly = [1 << 1, 0, 0, 0, 0];
+    for _ in 0..128 {
+        result = mul_mod(&result, &result, p);
+    }
+    assert_eq!(
+        result,
+        [0x180ec6d33cfd0aba, 0xd5a61266f0c9392c, 0xa9582618e03fc9aa, 0x39abdc4529b1661c, 0]
+    );
+}
+
+#[test]
+fn xoshiro256_advance() {
+    for amount in [0, 1, 255, 256, 1000, 12345] {
+        let mut sequential = Xoshiro256::seed_from_u64(7);
+        for _ in 0..amount {
+            sequential.next_u64();
+        }
+        let mut advanced = Xoshiro256::seed_from_u64(7);
+        advanced.advance(amount);
+        assert_eq!(advanced.next_u64(), sequential.next_u64(), "{amount}");
+    }
+}
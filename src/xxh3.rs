@@ -0,0 +1,236 @@
+//! A portable implementation of the 64 bits XXH3 hash, with the default secret and seed.
+
+const PRIME32_1: u64 = 0x9E3779B1;
+const PRIME32_2: u64 = 0x85EBCA77;
+const PRIME32_3: u64 = 0xC2B2AE3D;
+const PRIME64_1: u64 = 0x9E3779B185EBCA87;
+const PRIME64_2: u64 = 0xC2B2AE3D27D4EB4F;
+const PRIME64_3: u64 = 0x165667B19E3779F9;
+const PRIME64_4: u64 = 0x85EBCA77C2B2AE63;
+const PRIME64_5: u64 = 0x27D4EB2F165667C5;
+const PRIME_MX1: u64 = 0x165667919E3779F9;
+const PRIME_MX2: u64 = 0x9FB21C651E98DF25;
+
+const STRIPE_LEN: usize = 64;
+const SECRET_CONSUME_RATE: usize = 8;
+const SECRET_LASTACC_START: usize = 7;
+const SECRET_MERGEACCS_START: usize = 11;
+const MID_SIZE_START_OFFSET: usize = 3;
+const MID_SIZE_LAST_OFFSET: usize = 17;
+const SECRET_SIZE_MIN: usize = 136;
+
+const SECRET: [u8; 192] = [
+    0xb8, 0xfe, 0x6c, 0x39, 0x23, 0xa4, 0x4b, 0xbe, 0x7c, 0x01, 0x81, 0x2c, 0xf7, 0x21, 0xad, 0x1c,
+    0xde, 0xd4, 0x6d, 0xe9, 0x83, 0x90, 0x97, 0xdb, 0x72, 0x40, 0xa4, 0xa4, 0xb7, 0xb3, 0x67, 0x1f,
+    0xcb, 0x79, 0xe6, 0x4e, 0xcc, 0xc0, 0xe5, 0x78, 0x82, 0x5a, 0xd0, 0x7d, 0xcc, 0xff, 0x72, 0x21,
+    0xb8, 0x08, 0x46, 0x74, 0xf7, 0x43, 0x24, 0x8e, 0xe0, 0x35, 0x90, 0xe6, 0x81, 0x3a, 0x26, 0x4c,
+    0x3c, 0x28, 0x52, 0xbb, 0x91, 0xc3, 0x00, 0xcb, 0x88, 0xd0, 0x65, 0x8b, 0x1b, 0x53, 0x2e, 0xa3,
+    0x71, 0x64, 0x48, 0x97, 0xa2, 0x0d, 0xf9, 0x4e, 0x38, 0x19, 0xef, 0x46, 0xa9, 0xde, 0xac, 0xd8,
+    0xa8, 0xfa, 0x76, 0x3f, 0xe3, 0x9c, 0x34, 0x3f, 0xf9, 0xdc, 0xbb, 0xc7, 0xc7, 0x0b, 0x4f, 0x1d,
+    0x8a, 0x51, 0xe0, 0x4b, 0xcd, 0xb4, 0x59, 0x31, 0xc8, 0x9f, 0x7e, 0xc9, 0xd9, 0x78, 0x73, 0x64,
+    0xea, 0xc5, 0xac, 0x83, 0x34, 0xd3, 0xeb, 0xc3, 0xc5, 0x81, 0xa0, 0xff, 0xfa, 0x13, 0x63, 0xeb,
+    0x17, 0x0d, 0xdd, 0x51, 0xb7, 0xf0, 0xda, 0x49, 0xd3, 0x16, 0x55, 0x26, 0x29, 0xd4, 0x68, 0x9e,
+    0x2b, 0x16, 0xbe, 0x58, 0x7d, 0x47, 0xa1, 0xfc, 0x8f, 0xf8, 0xb8, 0xd1, 0x7a, 0xd0, 0x31, 0xce,
+    0x45, 0xcb, 0x3a, 0x8f, 0x95, 0x16, 0x04, 0x28, 0xaf, 0xd7, 0xfb, 0xca, 0xbb, 0x4b, 0x40, 0x7e,
+];
+
+#[inline(always)]
+fn read32(data: &[u8], offset: usize) -> u64 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap()) as u64
+}
+
+#[inline(always)]
+fn read64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+#[inline(always)]
+fn mul128_fold64(lhs: u64, rhs: u64) -> u64 {
+    let product = lhs as u128 * rhs as u128;
+    (product as u64) ^ ((product >> 64) as u64)
+}
+
+fn avalanche(mut h: u64) -> u64 {
+    h ^= h >> 37;
+    h = h.wrapping_mul(PRIME_MX1);
+    h ^ (h >> 32)
+}
+
+fn xxh64_avalanche(mut h: u64) -> u64 {
+    h ^= h >> 33;
+    h = h.wrapping_mul(PRIME64_2);
+    h ^= h >> 29;
+    h = h.wrapping_mul(PRIME64_3);
+    h ^ (h >> 32)
+}
+
+fn rrmxmx(mut h: u64, len: u64) -> u64 {
+    h ^= h.rotate_left(49) ^ h.rotate_left(24);
+    h = h.wrapping_mul(PRIME_MX2);
+    h ^= (h >> 35).wrapping_add(len);
+    h = h.wrapping_mul(PRIME_MX2);
+    h ^ (h >> 28)
+}
+
+fn mix16(data: &[u8], offset: usize, secret_offset: usize) -> u64 {
+    mul128_fold64(
+        read64(data, offset) ^ read64(&SECRET, secret_offset),
+        read64(data, offset + 8) ^ read64(&SECRET, secret_offset + 8),
+    )
+}
+
+fn hash_0to16(data: &[u8]) -> u64 {
+    let len = data.len();
+    if len > 8 {
+        let bitflip1 = read64(&SECRET, 24) ^ read64(&SECRET, 32);
+        let bitflip2 = read64(&SECRET, 40) ^ read64(&SECRET, 48);
+        let lo = read64(data, 0) ^ bitflip1;
+        let hi = read64(data, len - 8) ^ bitflip2;
+        let acc = (len as u64)
+            .wrapping_add(lo.swap_bytes())
+            .wrapping_add(hi)
+            .wrapping_add(mul128_fold64(lo, hi));
+        avalanche(acc)
+    } else if len >= 4 {
+        let input1 = read32(data, 0);
+        let input2 = read32(data, len - 4);
+        let bitflip = read64(&SECRET, 8) ^ read64(&SECRET, 16);
+        let keyed = input2.wrapping_add(input1 << 32) ^ bitflip;
+        rrmxmx(keyed, len as u64)
+    } else if len > 0 {
+        let combined = ((data[0] as u64) << 16)
+            | ((data[len >> 1] as u64) << 24)
+            | (data[len - 1] as u64)
+            | ((len as u64) << 8);
+        let bitflip = read32(&SECRET, 0) ^ read32(&SECRET, 4);
+        xxh64_avalanche(combined ^ bitflip)
+    } else {
+        xxh64_avalanche(read64(&SECRET, 56) ^ read64(&SECRET, 64))
+    }
+}
+
+fn hash_17to128(data: &[u8]) -> u64 {
+    let len = data.len();
+    let mut acc = (len as u64).wrapping_mul(PRIME64_1);
+    if len > 32 {
+        if len > 64 {
+            if len > 96 {
+                acc = acc.wrapping_add(mix16(data, 48, 96));
+                acc = acc.wrapping_add(mix16(data, len - 64, 112));
+            }
+            acc = acc.wrapping_add(mix16(data, 32, 64));
+            acc = acc.wrapping_add(mix16(data, len - 48, 80));
+        }
+        acc = acc.wrapping_add(mix16(data, 16, 32));
+        acc = acc.wrapping_add(mix16(data, len - 32, 48));
+    }
+    acc = acc.wrapping_add(mix16(data, 0, 0));
+    acc = acc.wrapping_add(mix16(data, len - 16, 16));
+    avalanche(acc)
+}
+
+fn hash_129to240(data: &[u8]) -> u64 {
+    let len = data.len();
+    let mut acc = (len as u64).wrapping_mul(PRIME64_1);
+    let rounds = len / 16;
+    for i in 0..8 {
+        acc = acc.wrapping_add(mix16(data, 16 * i, 16 * i));
+    }
+    acc = avalanche(acc);
+    for i in 8..rounds {
+        acc = acc.wrapping_add(mix16(data, 16 * i, 16 * (i - 8) + MID_SIZE_START_OFFSET));
+    }
+    acc = acc.wrapping_add(mix16(data, len - 16, SECRET_SIZE_MIN - MID_SIZE_LAST_OFFSET));
+    avalanche(acc)
+}
+
+#[inline(always)]
+fn accumulate_512(acc: &mut [u64; 8], data: &[u8], secret_offset: usize) {
+    for i in 0..8 {
+        let data_val = read64(data, 8 * i);
+        let data_key = data_val ^ read64(&SECRET, secret_offset + 8 * i);
+        acc[i ^ 1] = acc[i ^ 1].wrapping_add(data_val);
+        acc[i] = acc[i].wrapping_add((data_key & 0xFFFFFFFF).wrapping_mul(data_key >> 32));
+    }
+}
+
+fn scramble(acc: &mut [u64; 8]) {
+    let secret_offset = SECRET.len() - STRIPE_LEN;
+    for (i, a) in acc.iter_mut().enumerate() {
+        let mut v = *a;
+        v ^= v >> 47;
+        v ^= read64(&SECRET, secret_offset + 8 * i);
+        *a = v.wrapping_mul(PRIME32_1);
+    }
+}
+
+fn hash_long(data: &[u8]) -> u64 {
+    let len = data.len();
+    let mut acc =
+        [PRIME32_3, PRIME64_1, PRIME64_2, PRIME64_3, PRIME64_4, PRIME32_2, PRIME64_5, PRIME32_1];
+    let stripes_per_block = (SECRET.len() - STRIPE_LEN) / SECRET_CONSUME_RATE;
+    let block_len = STRIPE_LEN * stripes_per_block;
+    let blocks = (len - 1) / block_len;
+    for block in data.chunks_exact(block_len).take(blocks) {
+        for (n, stripe) in block.chunks_exact(STRIPE_LEN).enumerate() {
+            accumulate_512(&mut acc, stripe, n * SECRET_CONSUME_RATE);
+        }
+        scramble(&mut acc);
+    }
+    let last_block = &data[blocks * block_len..];
+    let stripes = (last_block.len() - 1) / STRIPE_LEN;
+    for (n, stripe) in last_block.chunks_exact(STRIPE_LEN).take(stripes).enumerate() {
+        accumulate_512(&mut acc, stripe, n * SECRET_CONSUME_RATE);
+    }
+    accumulate_512(
+        &mut acc,
+        &data[len - STRIPE_LEN..],
+        SECRET.len() - STRIPE_LEN - SECRET_LASTACC_START,
+    );
+    let mut result = (len as u64).wrapping_mul(PRIME64_1);
+    for i in 0..4 {
+        result = result.wrapping_add(mul128_fold64(
+            acc[2 * i] ^ read64(&SECRET, SECRET_MERGEACCS_START + 16 * i),
+            acc[2 * i + 1] ^ read64(&SECRET, SECRET_MERGEACCS_START + 16 * i + 8),
+        ));
+    }
+    avalanche(result)
+}
+
+/// Compute the XXH3 64 bits hash of `data`
+pub fn xxh3_64(data: &[u8]) -> u64 {
+    match data.len() {
+        0..=16 => hash_0to16(data),
+        17..=128 => hash_17to128(data),
+        129..=240 => hash_129to240(data),
+        _ => hash_long(data),
+    }
+}
+
+#[test]
+fn xxh3_64_reference_values() {
+    // reference values computed with libxxhash 0.8
+    let mut buffer = vec![0u8; 2367];
+    let mut byte_gen = PRIME32_1;
+    for b in buffer.iter_mut() {
+        *b = (byte_gen >> 56) as u8;
+        byte_gen = byte_gen.wrapping_mul(PRIME64_1);
+    }
+    for (len, expected) in [
+        (0, 0x2D06800538D394C2),
+        (1, 0xC44BDFF4074EECDB),
+        (6, 0x3CC50D1B34772C2C),
+        (12, 0x08662ADD2C628C21),
+        (24, 0x6CBF7A5DC0F3B4AB),
+        (48, 0x7DEC70F0C65E9E15),
+        (80, 0x343EA68F9ABB0DA5),
+        (195, 0x64586F630891D72F),
+        (403, 0x8F23B428730C6887),
+        (512, 0x2670A49459B231DA),
+        (2048, 0x8C9A8E3F25D392D6),
+        (2240, 0x644826E2B5FAFEAE),
+        (2367, 0xD4771B3A18E7F2FE),
+    ] {
+        assert_eq!(xxh3_64(&buffer[..len]), expected, "length {len}");
+    }
+}
@@ -37,6 +37,17 @@ fn parse_checksum(output: &std::process::Output) -> String {
     panic!("no checksum found in stderr:\n{stderr}");
 }
 
+/// Extract the `digest: <hex>` value from stderr.
+fn parse_digest(output: &std::process::Output) -> String {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for line in stderr.lines() {
+        if let Some(rest) = line.split("digest: ").nth(1) {
+            return rest.trim().to_string();
+        }
+    }
+    panic!("no digest found in stderr:\n{stderr}");
+}
+
 // ---------------------------------------------------------------------------
 // generate – basic
 // ---------------------------------------------------------------------------
@@ -120,6 +131,86 @@ fn generate_checksum_is_deterministic_regardless_of_job_count() {
     assert_eq!(b1, b2);
 }
 
+#[test]
+fn generate_hex_seed_matches_decimal_seed() {
+    let dir = TempDir::new().unwrap();
+    let o1 = generate(&dir, &["--size", "32Ki", "--seed", "42", "a.bin"]);
+    let o2 = generate(&dir, &["--size", "32Ki", "--seed", "0x2a", "b.bin"]);
+    assert_eq!(parse_checksum(&o1), parse_checksum(&o2));
+}
+
+#[test]
+fn generate_long_seeds() {
+    let dir = TempDir::new().unwrap();
+    let seed_128 = "0x0123456789abcdef0123456789abcdef";
+    let seed_256 = "0x0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+    for rng in ["pcg64", "xoshiro256", "chacha20", "aes-ctr"] {
+        let o1 = generate(&dir, &["--size", "64Ki", "--rng", rng, "--seed", seed_128, "a.bin"]);
+        assert!(o1.status.success(), "{}", String::from_utf8_lossy(&o1.stderr));
+        let o2 = generate(
+            &dir,
+            &["--size", "64Ki", "--rng", rng, "--seed", seed_256, "--jobs", "3", "b.bin"],
+        );
+        assert!(o2.status.success());
+        let v = validate(&dir, &["b.bin"]);
+        assert!(v.status.success());
+        let b1 = fs::read(dir.path().join("a.bin")).unwrap();
+        let b2 = fs::read(dir.path().join("b.bin")).unwrap();
+        assert_ne!(b1, b2, "{rng}");
+    }
+    let out = generate(&dir, &["--size", "64Ki", "--seed", "0xnothex", "a.bin"]);
+    assert!(!out.status.success());
+}
+
+#[test]
+fn generate_seed_string() {
+    let dir = TempDir::new().unwrap();
+    let o1 = generate(&dir, &["--size", "64Ki", "--seed-string", "TICKET-1234", "a.bin"]);
+    let o2 = generate(&dir, &["--size", "64Ki", "--seed-string", "TICKET-1234", "b.bin"]);
+    let o3 = generate(&dir, &["--size", "64Ki", "--seed-string", "TICKET-1235", "c.bin"]);
+    assert!(o1.status.success(), "{}", String::from_utf8_lossy(&o1.stderr));
+    assert_eq!(parse_checksum(&o1), parse_checksum(&o2));
+    assert_ne!(parse_checksum(&o1), parse_checksum(&o3));
+    let out = generate(&dir, &["--size", "64Ki", "--seed", "1", "--seed-string", "x", "d.bin"]);
+    assert!(!out.status.success());
+}
+
+#[test]
+fn generate_random_seed_is_recorded_in_the_stream() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "1Mi", "--random-seed", "--jobs", "3", "out.bin"]);
+    assert!(g.status.success(), "{}", String::from_utf8_lossy(&g.stderr));
+    let stderr = String::from_utf8_lossy(&g.stderr);
+    let seed = stderr.split("random seed: ").nth(1).unwrap().lines().next().unwrap().to_string();
+    let data = fs::read(dir.path().join("out.bin")).unwrap();
+    assert_eq!(data.len(), 1024 * 1024);
+    assert_eq!(&data[..8], b"RANDSTRM");
+
+    // validate finds the seed back, from a file or from stdin
+    let v = validate(&dir, &["out.bin"]);
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+    assert!(String::from_utf8_lossy(&v.stderr).contains(&format!("seed: {seed}")));
+    assert_eq!(parse_checksum(&v), parse_checksum(&g));
+    let mut child = bin()
+        .args(["validate", "--no-progress"])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(&data).unwrap();
+    let v = child.wait_with_output().unwrap();
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+    assert_eq!(parse_checksum(&v), parse_checksum(&g));
+
+    // and the seed reproduces the stream
+    let out = bin()
+        .args(["generate", "--no-progress", "--size", &(1024 * 1024 - 64).to_string()])
+        .args(["--seed", &seed])
+        .output()
+        .unwrap();
+    assert_eq!(out.stdout, data[64..]);
+}
+
 // ---------------------------------------------------------------------------
 // generate – write alias
 // ---------------------------------------------------------------------------
@@ -212,6 +303,22 @@ fn generate_stdout_matches_file_output() {
     assert_eq!(file_bytes, out.stdout);
 }
 
+#[test]
+fn generate_to_stdout_in_parallel_keeps_the_chunks_in_order() {
+    let dir = TempDir::new().unwrap();
+    let args = ["--size", "1000Ki", "-c", "4Ki", "--format", "v2", "--run-id", "1", "--seed", "8"];
+    let g = generate(&dir, &[&args[..], &["-j", "1", "out.bin"]].concat());
+    let file_bytes = fs::read(dir.path().join("out.bin")).unwrap();
+    let out = bin()
+        .args(["generate", "--no-progress", "-j", "4", "--digest", "sha256"])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(file_bytes, out.stdout);
+    assert_eq!(parse_checksum(&out), parse_checksum(&g));
+}
+
 // ---------------------------------------------------------------------------
 // generate – --position
 // ---------------------------------------------------------------------------
@@ -305,6 +412,20 @@ fn generate_no_truncate_preserves_file_size() {
     assert_eq!(fs::metadata(&path).unwrap().len(), 128 * 1024);
 }
 
+#[cfg(unix)]
+#[test]
+fn generate_preallocate_allocates_the_stream() {
+    use std::os::unix::fs::MetadataExt as _;
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "1Mi", "--preallocate", "out.bin"]);
+    assert!(g.status.success(), "{}", String::from_utf8_lossy(&g.stderr));
+    let metadata = fs::metadata(dir.path().join("out.bin")).unwrap();
+    assert_eq!(metadata.len(), 1024 * 1024);
+    assert!(metadata.blocks() * 512 >= 1024 * 1024);
+    let v = validate(&dir, &["out.bin"]);
+    assert_eq!(parse_checksum(&v), parse_checksum(&g));
+}
+
 #[test]
 fn generate_truncates_file_by_default() {
     let dir = TempDir::new().unwrap();
@@ -503,6 +624,39 @@ fn validate_stdin_checksum_matches_file_checksum() {
     assert_eq!(parse_checksum(&out), file_checksum);
 }
 
+#[test]
+fn validate_stdin_in_parallel_reports_the_corrupted_chunks_in_order() {
+    let dir = TempDir::new().unwrap();
+    let args = ["--size", "1000Ki", "-c", "4Ki", "--format", "v2", "--digest", "sha256"];
+    let g = generate(&dir, &[&args[..], &["out.bin"]].concat());
+    let mut data = fs::read(dir.path().join("out.bin")).unwrap();
+    let validate_stdin = |data: &[u8], extra: &[&str]| {
+        let mut child = bin()
+            .args(["validate", "--no-progress", "-j", "4"])
+            .args(&args[2..])
+            .args(extra)
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(data).unwrap();
+        child.wait_with_output().unwrap()
+    };
+    let v = validate_stdin(&data, &[]);
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+    assert_eq!(parse_checksum(&v), parse_checksum(&g));
+    assert_eq!(parse_digest(&v), parse_digest(&g));
+
+    for chunk in [3, 6, 201] {
+        data[chunk * 4096 + 100] ^= 1;
+    }
+    let v = validate_stdin(&data, &["--keep-going"]);
+    assert_eq!(v.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    let chunks = [3, 6, 201].map(|c| stderr.find(&format!("chunk {c} at")).unwrap());
+    assert!(chunks.is_sorted(), "{stderr}");
+}
+
 // ---------------------------------------------------------------------------
 // validate – --position
 // ---------------------------------------------------------------------------
@@ -605,120 +759,2605 @@ fn validate_explicit_size_smaller_than_file_only_checks_that_region() {
 }
 
 // ---------------------------------------------------------------------------
-// generate + validate – round-trip matrix
+// generate + validate – --checksum
 // ---------------------------------------------------------------------------
 
-/// Table-driven round-trip: (size, chunk_size, seed, jobs).
-/// All sizes use binary prefixes (Ki / Mi) for unambiguous byte counts.
 #[test]
-fn round_trip_various_sizes_and_seeds() {
-    struct Case {
-        size: &'static str,
-        chunk_size: &'static str,
-        seed: &'static str,
-        jobs: &'static str,
-        checksum: &'static str,
-    }
-    let cases = [
-        Case { size: "1Ki", chunk_size: "1Ki", seed: "0", jobs: "1", checksum: "48181a23" },
-        Case { size: "32Ki", chunk_size: "32Ki", seed: "1", jobs: "1", checksum: "855bfcd1" },
-        // size not a multiple of chunk_size (tail chunk is smaller)
-        Case { size: "33Ki", chunk_size: "32Ki", seed: "2", jobs: "1", checksum: "aa0e5a26" },
-        Case { size: "64Ki", chunk_size: "32Ki", seed: "3", jobs: "2", checksum: "5af3b3b3" },
-        Case { size: "256Ki", chunk_size: "64Ki", seed: "99", jobs: "3", checksum: "48bdc095" },
-        Case { size: "1Mi", chunk_size: "32Ki", seed: "12345", jobs: "1", checksum: "9e904c23" },
-        // same data, different thread count – checksums must be identical
-        Case { size: "1Mi", chunk_size: "32Ki", seed: "12345", jobs: "4", checksum: "9e904c23" },
-    ];
+fn xxh3_round_trip() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "1Mi", "--checksum", "xxh3", "--jobs", "3", "out.bin"]);
+    assert!(g.status.success(), "{}", String::from_utf8_lossy(&g.stderr));
+    let v = validate(&dir, &["--checksum", "xxh3", "--jobs", "2", "out.bin"]);
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+    let checksum = parse_checksum(&g);
+    assert_eq!(checksum.len(), 16);
+    assert_eq!(checksum, parse_checksum(&v));
+}
 
-    for case in &cases {
-        let dir = TempDir::new().unwrap();
-        let label = format!(
-            "size={} chunk={} seed={} jobs={}",
-            case.size, case.chunk_size, case.seed, case.jobs
-        );
-        let g = generate(
-            &dir,
-            &[
-                "--size",
-                case.size,
-                "--chunk-size",
-                case.chunk_size,
-                "--seed",
-                case.seed,
-                "--jobs",
-                case.jobs,
-                "out.bin",
-            ],
-        );
-        assert!(
-            g.status.success(),
-            "generate failed for {label}: {}",
-            String::from_utf8_lossy(&g.stderr)
-        );
+#[test]
+fn xxh3_stdout_matches_file_output() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "100Ki", "--checksum", "xxh3", "--jobs", "2", "out.bin"]);
+    assert!(g.status.success());
+    let out = bin()
+        .args(["generate", "--no-progress", "--size", "100Ki", "--checksum", "xxh3"])
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    assert_eq!(out.stdout, fs::read(dir.path().join("out.bin")).unwrap());
+    assert_eq!(parse_checksum(&g), parse_checksum(&out));
+}
 
-        let v = validate(&dir, &["--chunk-size", case.chunk_size, "out.bin"]);
-        assert!(
-            v.status.success(),
-            "validate failed for {label}: {}",
-            String::from_utf8_lossy(&v.stderr)
-        );
+#[test]
+fn xxh3_detects_corruption() {
+    let dir = TempDir::new().unwrap();
+    generate(&dir, &["--size", "64Ki", "--checksum", "xxh3", "out.bin"]);
+    let path = dir.path().join("out.bin");
+    let mut data = fs::read(&path).unwrap();
+    data[40000] ^= 0x10;
+    fs::write(&path, &data).unwrap();
+    let v = validate(&dir, &["--checksum", "xxh3", "out.bin"]);
+    assert!(!v.status.success());
+}
 
-        let g_checksum = parse_checksum(&g);
-        let v_checksum = parse_checksum(&v);
-        assert_eq!(
-            g_checksum, case.checksum,
-            "generate checksum changed for {label}: got {g_checksum}, expected {}",
-            case.checksum
-        );
-        assert_eq!(
-            v_checksum, case.checksum,
-            "validate checksum changed for {label}: got {v_checksum}, expected {}",
-            case.checksum
-        );
-    }
+#[test]
+fn checksum_algorithm_mismatch_fails_validation() {
+    let dir = TempDir::new().unwrap();
+    generate(&dir, &["--size", "64Ki", "--checksum", "xxh3", "out.bin"]);
+    let v = validate(&dir, &["out.bin"]);
+    assert!(!v.status.success());
+}
+
+#[test]
+fn crc64_round_trip_with_large_chunks() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(
+        &dir,
+        &["--size", "20Mi", "--chunk-size", "8Mi", "--chunk-checksum", "crc64", "out.bin"],
+    );
+    assert!(g.status.success(), "{}", String::from_utf8_lossy(&g.stderr));
+    let checksum = parse_checksum(&g);
+    assert_eq!(checksum.len(), 16);
+    // the stream checksum is the CRC of the payloads, whatever the number of jobs
+    let v =
+        validate(&dir, &["--chunk-size", "8Mi", "--checksum", "crc64", "--jobs", "1", "out.bin"]);
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+    assert_eq!(parse_checksum(&v), checksum);
+    let path = dir.path().join("out.bin");
+    let mut data = fs::read(&path).unwrap();
+    data[9_000_000] ^= 0x01;
+    fs::write(&path, &data).unwrap();
+    let v = validate(&dir, &["--chunk-size", "8Mi", "--checksum", "crc64", "out.bin"]);
+    assert!(!v.status.success());
 }
 
 // ---------------------------------------------------------------------------
-// CLI argument validation
+// generate + validate – --digest
 // ---------------------------------------------------------------------------
 
 #[test]
-fn no_subcommand_exits_nonzero() {
-    let out = bin().output().unwrap();
-    assert!(!out.status.success());
+fn blake3_digest_is_independent_of_job_count() {
+    let dir = TempDir::new().unwrap();
+    // 1000 bytes chunks, so the thread ranges are not aligned on the BLAKE3 chunks
+    let g = generate(
+        &dir,
+        &["--size", "1Mi", "--chunk-size", "1000", "--digest", "blake3", "--jobs", "3", "out.bin"],
+    );
+    assert!(g.status.success(), "{}", String::from_utf8_lossy(&g.stderr));
+    let digest = parse_digest(&g);
+    assert_eq!(digest.len(), 64);
+    for jobs in ["1", "5"] {
+        let v = validate(
+            &dir,
+            &["--chunk-size", "1000", "--digest", "blake3", "--jobs", jobs, "out.bin"],
+        );
+        assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+        assert_eq!(parse_digest(&v), digest);
+    }
 }
 
 #[test]
-fn unknown_flag_exits_nonzero() {
-    let out = bin().args(["generate", "--does-not-exist"]).output().unwrap();
-    assert!(!out.status.success());
+fn blake3_digest_matches_between_file_and_stdout() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "200Ki", "--digest", "blake3", "--jobs", "4", "out.bin"]);
+    assert!(g.status.success());
+    let out = bin()
+        .args(["generate", "--no-progress", "--size", "200Ki", "--digest", "blake3"])
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    assert_eq!(parse_digest(&g), parse_digest(&out));
 }
 
 #[test]
-fn position_requires_file_argument() {
-    // --position without a file path is rejected by clap (requires="file").
+fn validate_expected_digest() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "64Ki", "--digest", "blake3", "out.bin"]);
+    let digest = parse_digest(&g);
+    let v = validate(&dir, &["--digest", "blake3", "--expected-digest", &digest, "out.bin"]);
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+    let v = validate(&dir, &["--digest", "blake3", "--expected-digest", "00", "out.bin"]);
+    assert!(!v.status.success());
+    // --expected-digest needs --digest
+    let v = validate(&dir, &["--expected-digest", &digest, "out.bin"]);
+    assert!(!v.status.success());
+}
+
+#[test]
+fn sha256_digest_is_independent_of_job_count() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "1Mi", "--chunk-size", "1000", "--jobs", "3", "out.bin"]);
+    let checksum = parse_checksum(&g);
+    let g = generate(
+        &dir,
+        &["--size", "1Mi", "--chunk-size", "1000", "--digest", "sha256", "--jobs", "3", "out.bin"],
+    );
+    assert!(g.status.success(), "{}", String::from_utf8_lossy(&g.stderr));
+    // the chunks are interleaved between the threads, the stream checksum must not change
+    assert_eq!(parse_checksum(&g), checksum);
+    let digest = parse_digest(&g);
+    assert_eq!(digest.len(), 64);
+    for jobs in ["1", "5"] {
+        let v = validate(
+            &dir,
+            &["--chunk-size", "1000", "--digest", "sha256", "--jobs", jobs, "out.bin"],
+        );
+        assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+        assert_eq!(parse_checksum(&v), checksum);
+        assert_eq!(parse_digest(&v), digest);
+    }
     let out = bin()
-        .args(["generate", "--no-progress", "--size", "32Ki", "--position", "0"])
+        .args(["generate", "--no-progress", "--size", "1Mi", "--chunk-size", "1000"])
+        .args(["--digest", "sha256"])
         .output()
         .unwrap();
-    assert!(!out.status.success());
+    assert!(out.status.success());
+    assert_eq!(parse_digest(&out), digest);
 }
 
+// ---------------------------------------------------------------------------
+// generate + validate – --rng
+// ---------------------------------------------------------------------------
+
 #[test]
-fn help_flag_succeeds() {
-    let out = bin().arg("--help").output().unwrap();
-    assert!(out.status.success());
+fn rng_output_is_independent_of_job_count() {
+    for rng in ["xoshiro256", "chacha20", "aes-ctr"] {
+        let dir = TempDir::new().unwrap();
+        let args = ["--size", "300Ki", "--chunk-size", "1001", "--rng", rng];
+        let g = generate(&dir, &[&args[..], &["--jobs", "3", "out.bin"]].concat());
+        assert!(g.status.success(), "{}", String::from_utf8_lossy(&g.stderr));
+        let out = bin().args(["generate", "--no-progress"]).args(args).output().unwrap();
+        assert!(out.status.success());
+        let data = fs::read(dir.path().join("out.bin")).unwrap();
+        assert_eq!(out.stdout, data, "{rng}");
+        let v = validate(&dir, &["--chunk-size", "1001", "out.bin"]);
+        assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+        assert_eq!(parse_checksum(&v), parse_checksum(&g));
+        // and it's not the PCG stream
+        generate(&dir, &["--size", "300Ki", "--chunk-size", "1001", "pcg.bin"]);
+        assert_ne!(fs::read(dir.path().join("pcg.bin")).unwrap(), data, "{rng}");
+    }
 }
 
+// ---------------------------------------------------------------------------
+// generate + validate – --engine
+// ---------------------------------------------------------------------------
+
 #[test]
-fn generate_help_flag_succeeds() {
-    let out = bin().args(["generate", "--help"]).output().unwrap();
-    assert!(out.status.success());
+fn engines_match_sync_engine() {
+    let dir = TempDir::new().unwrap();
+    let args = ["--size", "300Ki", "--chunk-size", "1001"];
+    let g = generate(&dir, &[&args[..], &["--jobs", "3", "sync.bin"]].concat());
+    assert!(g.status.success());
+    for (engine, jobs) in [("io-uring", "1"), ("io-uring", "3"), ("mmap", "1"), ("mmap", "3")] {
+        let options = ["--engine", engine, "--queue-depth", "4", "--jobs", jobs];
+        let e = generate(&dir, &[&args[..], &options, &["engine.bin"]].concat());
+        assert!(e.status.success(), "{}", String::from_utf8_lossy(&e.stderr));
+        assert_eq!(parse_checksum(&e), parse_checksum(&g));
+        assert_eq!(
+            fs::read(dir.path().join("engine.bin")).unwrap(),
+            fs::read(dir.path().join("sync.bin")).unwrap(),
+            "{engine}"
+        );
+        let v = validate(&dir, &[&["--chunk-size", "1001"][..], &options, &["sync.bin"]].concat());
+        assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+        assert_eq!(parse_checksum(&v), parse_checksum(&g));
+    }
 }
 
 #[test]
-fn validate_help_flag_succeeds() {
-    let out = bin().args(["validate", "--help"]).output().unwrap();
-    assert!(out.status.success());
+fn validate_engines_detect_corruption() {
+    let dir = TempDir::new().unwrap();
+    generate(&dir, &["--size", "300Ki", "--chunk-size", "1001", "out.bin"]);
+    let path = dir.path().join("out.bin");
+    let mut data = fs::read(&path).unwrap();
+    data[150 * 1001 + 3] ^= 0xff;
+    fs::write(&path, data).unwrap();
+    for engine in ["io-uring", "mmap"] {
+        let v =
+            validate(&dir, &["--chunk-size", "1001", "--engine", engine, "--jobs", "1", "out.bin"]);
+        assert!(!v.status.success());
+        assert!(String::from_utf8_lossy(&v.stderr).contains("chunk 150"), "{engine}");
+    }
+}
+
+#[test]
+fn batch_chunks_match_single_chunks() {
+    let dir = TempDir::new().unwrap();
+    let args = ["--size", "300001", "--chunk-size", "1001"];
+    let g = generate(&dir, &[&args[..], &["single.bin"]].concat());
+    for jobs in ["1", "3"] {
+        let options = ["--batch-chunks", "7", "--jobs", jobs];
+        let b = generate(&dir, &[&args[..], &options, &["batch.bin"]].concat());
+        assert!(b.status.success(), "{}", String::from_utf8_lossy(&b.stderr));
+        assert_eq!(parse_checksum(&b), parse_checksum(&g));
+        assert_eq!(
+            fs::read(dir.path().join("batch.bin")).unwrap(),
+            fs::read(dir.path().join("single.bin")).unwrap()
+        );
+        let v =
+            validate(&dir, &[&["--chunk-size", "1001"][..], &options, &["single.bin"]].concat());
+        assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+        assert_eq!(parse_checksum(&v), parse_checksum(&g));
+    }
+    // a stream longer than the file
+    let v = validate(
+        &dir,
+        &["--chunk-size", "1001", "--size", "1Mi", "--batch-chunks", "7", "single.bin"],
+    );
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+    assert_eq!(parse_checksum(&v), parse_checksum(&g));
+}
+
+#[test]
+fn mmap_engine_validates_a_stream_longer_than_the_file() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "300Ki", "out.bin"]);
+    let v = validate(&dir, &["--size", "1Mi", "--engine", "mmap", "out.bin"]);
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+    assert_eq!(parse_checksum(&v), parse_checksum(&g));
+}
+
+// ---------------------------------------------------------------------------
+// generate + validate – --direct
+// ---------------------------------------------------------------------------
+
+#[test]
+fn direct_io_matches_buffered_io() {
+    let dir = TempDir::new().unwrap();
+    // the last chunk isn't aligned on the block size
+    let args = ["--size", "300001", "--chunk-size", "64Ki"];
+    let g = generate(&dir, &[&args[..], &["buffered.bin"]].concat());
+    for engine in ["sync", "io-uring"] {
+        let direct = ["--direct", "--engine", engine, "--jobs", "3", "direct.bin"];
+        let d = generate(&dir, &[&args[..], &direct].concat());
+        assert!(d.status.success(), "{}", String::from_utf8_lossy(&d.stderr));
+        assert_eq!(parse_checksum(&d), parse_checksum(&g));
+        assert_eq!(
+            fs::read(dir.path().join("direct.bin")).unwrap(),
+            fs::read(dir.path().join("buffered.bin")).unwrap()
+        );
+        let v =
+            validate(&dir, &["--chunk-size", "64Ki", "--direct", "--engine", engine, "direct.bin"]);
+        assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+        assert_eq!(parse_checksum(&v), parse_checksum(&g));
+    }
+}
+
+#[test]
+fn direct_io_requires_an_aligned_position() {
+    let dir = TempDir::new().unwrap();
+    let out = generate(&dir, &["--size", "64Ki", "--position", "100", "--direct", "out.bin"]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("logical block size"));
+}
+
+// ---------------------------------------------------------------------------
+// generate + validate – page cache control
+// ---------------------------------------------------------------------------
+
+#[test]
+fn drop_cache_and_advise_round_trip() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "300Ki", "--drop-cache", "--advise", "sequential", "a.bin"]);
+    assert!(g.status.success(), "{}", String::from_utf8_lossy(&g.stderr));
+    generate(&dir, &["--size", "300Ki", "b.bin"]);
+    assert_eq!(
+        fs::read(dir.path().join("a.bin")).unwrap(),
+        fs::read(dir.path().join("b.bin")).unwrap()
+    );
+    for engine in ["sync", "io-uring"] {
+        let v =
+            validate(&dir, &["--drop-cache", "--advise", "random", "--engine", engine, "a.bin"]);
+        assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+        assert_eq!(parse_checksum(&v), parse_checksum(&g));
+    }
+}
+
+// ---------------------------------------------------------------------------
+// generate – --fsync-every / --fsync-at-end
+// ---------------------------------------------------------------------------
+
+#[test]
+fn fsync_options_dont_change_the_stream() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "300Ki", "plain.bin"]);
+    for (engine, every) in [("sync", "16chunks"), ("io-uring", "100Ki"), ("mmap", "1chunk")] {
+        let options = ["--engine", engine, "--fsync-every", every, "--fsync-at-end", "--jobs", "3"];
+        let f = generate(&dir, &[&["--size", "300Ki"][..], &options, &["fsync.bin"]].concat());
+        assert!(f.status.success(), "{}", String::from_utf8_lossy(&f.stderr));
+        assert_eq!(parse_checksum(&f), parse_checksum(&g));
+        assert_eq!(
+            fs::read(dir.path().join("fsync.bin")).unwrap(),
+            fs::read(dir.path().join("plain.bin")).unwrap(),
+            "{engine}"
+        );
+    }
+}
+
+#[test]
+fn sync_open_flags_dont_change_the_stream() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "300Ki", "plain.bin"]);
+    for (engine, flag) in [("sync", "--sync"), ("io-uring", "--dsync")] {
+        let s = generate(&dir, &["--size", "300Ki", "--engine", engine, flag, "sync.bin"]);
+        assert!(s.status.success(), "{}", String::from_utf8_lossy(&s.stderr));
+        assert_eq!(parse_checksum(&s), parse_checksum(&g));
+        assert_eq!(
+            fs::read(dir.path().join("sync.bin")).unwrap(),
+            fs::read(dir.path().join("plain.bin")).unwrap(),
+            "{engine}"
+        );
+    }
+    let out = generate(&dir, &["--size", "300Ki", "--engine", "mmap", "--sync", "sync.bin"]);
+    assert!(!out.status.success());
+    let out = generate(&dir, &["--size", "300Ki", "--sync", "--dsync", "sync.bin"]);
+    assert!(!out.status.success());
+}
+
+// ---------------------------------------------------------------------------
+// generate + validate – --bwlimit
+// ---------------------------------------------------------------------------
+
+#[test]
+fn bwlimit_slows_down_generate_and_validate() {
+    let dir = TempDir::new().unwrap();
+    // 2MiB at 4MB/s, shared between the threads
+    let start = std::time::Instant::now();
+    let g = generate(&dir, &["--size", "2Mi", "--bwlimit", "4M", "--jobs", "3", "out.bin"]);
+    assert!(g.status.success(), "{}", String::from_utf8_lossy(&g.stderr));
+    assert!(start.elapsed() >= std::time::Duration::from_millis(350));
+    let start = std::time::Instant::now();
+    let v = validate(&dir, &["--bwlimit", "4M", "--jobs", "3", "out.bin"]);
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+    assert!(start.elapsed() >= std::time::Duration::from_millis(350));
+    assert_eq!(parse_checksum(&v), parse_checksum(&g));
+}
+
+#[test]
+fn bwlimit_must_not_be_zero() {
+    let dir = TempDir::new().unwrap();
+    let out = generate(&dir, &["--size", "1Mi", "--bwlimit", "0", "out.bin"]);
+    assert!(!out.status.success());
+}
+
+// ---------------------------------------------------------------------------
+// generate + validate – --output json
+// ---------------------------------------------------------------------------
+
+#[test]
+fn json_output_reports_the_result() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "1Mi", "out.bin"]);
+    let checksum = parse_checksum(&g);
+    let v = validate(&dir, &["--output", "json", "--jobs", "3", "out.bin"]);
+    assert!(v.status.success());
+    let json = String::from_utf8(v.stdout).unwrap();
+    assert!(json.starts_with(r#"{"command":"validate","success":true,"exit_code":0,"#), "{json}");
+    assert!(json.contains(r#""bytes":1048576,"#), "{json}");
+    assert!(json.contains(&format!(r#""checksum":"{checksum}","#)), "{json}");
+    assert_eq!(json.matches(r#"{"bytes":"#).count(), 3, "{json}");
+    assert!(json.contains(r#"}],"imbalance":"#), "{json}");
+    assert!(json.ends_with("\"errors\":[]}\n"), "{json}");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn threads_are_pinned_to_the_cpus() {
+    let dir = TempDir::new().unwrap();
+    let g =
+        generate(&dir, &["-v", "--size", "1Mi", "--jobs", "2", "--cpu-affinity", "0", "out.bin"]);
+    let stderr = String::from_utf8_lossy(&g.stderr);
+    assert!(g.status.success(), "{stderr}");
+    assert!(stderr.contains("thread 1: CPU 0"), "{stderr}");
+    let v = validate(&dir, &["-v", "--jobs", "2", "--cpu-affinity", "0", "out.bin"]);
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    assert!(v.status.success(), "{stderr}");
+    assert!(stderr.contains("thread 1: CPU 0"), "{stderr}");
+    // pinned to the node of the device holding the file, if it has one
+    let v = validate(&dir, &["--numa-local", "out.bin"]);
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+
+    assert_eq!(validate(&dir, &["--cpu-affinity", "100000", "out.bin"]).status.code(), Some(5));
+    assert_eq!(validate(&dir, &["--cpu-affinity", "2-1", "out.bin"]).status.code(), Some(5));
+    let args = ["--cpu-affinity", "0", "--numa-local", "out.bin"];
+    assert_eq!(validate(&dir, &args).status.code(), Some(5));
+}
+
+#[test]
+fn threads_take_batches_of_chunks_from_a_queue() {
+    let dir = TempDir::new().unwrap();
+    let g =
+        generate(&dir, &["-v", "--size", "1Mi", "--chunk-size", "4Ki", "--jobs", "2", "out.bin"]);
+    let stderr = String::from_utf8_lossy(&g.stderr);
+    assert!(g.status.success(), "{stderr}");
+    // 256 chunks, 16 batches per thread
+    assert!(stderr.contains("batches of 8 chunks"), "{stderr}");
+    for jobs in ["1", "3"] {
+        let v = validate(&dir, &["-v", "--chunk-size", "4Ki", "--jobs", jobs, "out.bin"]);
+        let stderr = String::from_utf8_lossy(&v.stderr);
+        assert!(v.status.success(), "{stderr}");
+        assert_eq!(parse_checksum(&v), parse_checksum(&g));
+    }
+    // each thread resumes its own range
+    let args =
+        ["-v", "--chunk-size", "4Ki", "--jobs", "2", "--checkpoint", "checkpoint", "out.bin"];
+    let v = validate(&dir, &args);
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    assert!(v.status.success(), "{stderr}");
+    assert!(!stderr.contains("batches of"), "{stderr}");
+    assert_eq!(parse_checksum(&v), parse_checksum(&g));
+    // no thread to take the chunks
+    assert_eq!(generate(&dir, &["--size", "1Mi", "--jobs", "0", "out.bin"]).status.code(), Some(5));
+    assert_eq!(validate(&dir, &["--jobs", "0", "out.bin"]).status.code(), Some(5));
+}
+
+#[test]
+fn a_file_vanishing_during_the_run_is_reported() {
+    let dir = TempDir::new().unwrap();
+    generate(&dir, &["--size", "8Mi", "out.bin"]);
+    let child = bin()
+        .args(["validate", "--no-progress", "--bwlimit", "2Mi", "--jobs", "2", "out.bin"])
+        .current_dir(dir.path())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(1500));
+    fs::remove_file(dir.path().join("out.bin")).unwrap();
+    let v = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    assert_eq!(v.status.code(), Some(4), "{stderr}");
+    assert!(
+        stderr.contains("out.bin vanished during the run: thread 0 got to offset "),
+        "{stderr}"
+    );
+    assert!(stderr.contains(", thread 1 "), "{stderr}");
+}
+
+#[cfg(unix)]
+#[test]
+fn sigusr1_prints_a_status_snapshot() {
+    use nix::sys::signal::{Signal, kill};
+    use nix::unistd::Pid;
+
+    let dir = TempDir::new().unwrap();
+    generate(&dir, &["--size", "4Mi", "out.bin"]);
+    let child = bin()
+        .args(["validate", "--no-progress", "--bwlimit", "2Mi", "--jobs", "2", "out.bin"])
+        .current_dir(dir.path())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(1000));
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGUSR1).unwrap();
+    let v = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    assert!(v.status.success(), "{stderr}");
+    assert!(stderr.contains(" validated of 4 MiB ("), "{stderr}");
+    assert!(stderr.contains("/s over the last minute, 0 corrupted chunks"), "{stderr}");
+    assert!(stderr.contains("status: thread 1 "), "{stderr}");
+}
+
+#[test]
+fn duration_stops_the_run_on_the_start_of_the_stream() {
+    let dir = TempDir::new().unwrap();
+    let args = ["--size", "16Mi", "--chunk-size", "64Ki", "--jobs", "2", "--bwlimit", "4Mi"];
+    let g = generate(&dir, &[&args[..], &["--duration", "1", "out.bin"]].concat());
+    let stderr = String::from_utf8_lossy(&g.stderr);
+    assert!(g.status.success(), "{stderr}");
+    let size = stderr
+        .split("validate them with --size ")
+        .nth(1)
+        .and_then(|rest| rest.lines().next())
+        .unwrap_or_else(|| panic!("no size in stderr:\n{stderr}"));
+    let size: u64 = size.parse().unwrap();
+    assert!(size > 0 && size < 16 << 20, "{stderr}");
+    assert!(stderr.contains(&format!("stopped at the --duration, {size} of 16777216 bytes")));
+    // the file is truncated to the stream written
+    assert_eq!(fs::metadata(dir.path().join("out.bin")).unwrap().len(), size);
+    let v = validate(&dir, &["--chunk-size", "64Ki", "out.bin"]);
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+    assert_eq!(parse_checksum(&v), parse_checksum(&g));
+
+    let checksum = parse_checksum(&g);
+    let args = ["--chunk-size", "64Ki", "--bwlimit", "1Mi", "--duration", "1s"];
+    let v = validate(&dir, &[&args[..], &["--expected-checksum", &checksum, "out.bin"]].concat());
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    assert!(v.status.success(), "{stderr}");
+    assert!(stderr.contains("stopped at the --duration, "), "{stderr}");
+    assert!(stderr.contains("its expected checksum isn't checked"), "{stderr}");
+
+    // the batches are small enough to stop soon after the --duration, even with --bwlimit
+    let args = ["--size", "1Gi", "--chunk-size", "64Ki", "--jobs", "2", "--bwlimit", "4Mi"];
+    let start = std::time::Instant::now();
+    let g = generate(&dir, &[&args[..], &["--duration", "1", "large.bin"]].concat());
+    assert!(g.status.success(), "{}", String::from_utf8_lossy(&g.stderr));
+    assert!(start.elapsed() < std::time::Duration::from_secs(3), "{:?}", start.elapsed());
+
+    let args = ["--duration", "1m", "--checkpoint", "checkpoint", "out.bin"];
+    assert_eq!(validate(&dir, &args).status.code(), Some(5));
+    assert_eq!(
+        generate(&dir, &["--size", "1Mi", "--duration", "0", "out.bin"]).status.code(),
+        Some(5)
+    );
+}
+
+#[test]
+fn thread_imbalance_is_logged() {
+    let dir = TempDir::new().unwrap();
+    generate(&dir, &["--size", "4Mi", "out.bin"]);
+    let v = validate(&dir, &["-v", "--jobs", "2", "out.bin"]);
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    assert!(v.status.success(), "{stderr}");
+    // the threads take their chunks from a shared queue, in batches
+    let bytes: u64 = (0..2)
+        .map(|thread| {
+            let line = stderr.lines().find(|line| line.contains(&format!("thread {thread}: ")));
+            let line = line.expect(&stderr).split(&format!("thread {thread}: ")).nth(1).unwrap();
+            line.split(' ').next().unwrap().parse::<u64>().unwrap()
+        })
+        .sum();
+    assert_eq!(bytes, 4 << 20, "{stderr}");
+    // any difference between the threads is over a threshold of 0
+    let v = validate(&dir, &["--jobs", "2", "--imbalance-threshold", "0", "out.bin"]);
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    assert!(v.status.success(), "{stderr}");
+    assert!(stderr.contains("thread imbalance: the slowest thread took "), "{stderr}");
+    // a run that short isn't worth a warning
+    assert!(!stderr.contains("warn: thread imbalance"), "{stderr}");
+    assert_eq!(validate(&dir, &["--imbalance-threshold", "-1", "out.bin"]).status.code(), Some(5));
+}
+
+#[test]
+fn json_output_reports_the_errors() {
+    let dir = TempDir::new().unwrap();
+    generate(&dir, &["--size", "300Ki", "out.bin"]);
+    let path = dir.path().join("out.bin");
+    let mut data = fs::read(&path).unwrap();
+    data[100] ^= 0xff;
+    fs::write(&path, data).unwrap();
+    let v = validate(&dir, &["--output", "json", "out.bin"]);
+    assert!(!v.status.success());
+    let json = String::from_utf8(v.stdout).unwrap();
+    assert!(json.contains(r#""success":false,"#), "{json}");
+    assert!(json.contains(r#""errors":["Invalid checksum at chunk 0."#), "{json}");
+}
+
+#[test]
+fn json_output_goes_to_stderr_when_generating_to_stdout() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "1000", "--output", "json"]);
+    assert!(g.status.success());
+    assert_eq!(g.stdout.len(), 1000);
+    assert!(String::from_utf8_lossy(&g.stderr).contains(r#"{"command":"generate","success":true"#));
+}
+
+#[test]
+fn json_progress_reports_the_bytes_done() {
+    let dir = TempDir::new().unwrap();
+    let out = bin()
+        .current_dir(dir.path())
+        .args(["generate", "--size", "1Mi", "--jobs", "3", "--progress", "json", "out.bin"])
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains(r#"{"event":"done","bytes_done":1048576,"total":1048576,"#),
+        "{stderr}"
+    );
+}
+
+// ---------------------------------------------------------------------------
+// library – RandomStream
+// ---------------------------------------------------------------------------
+
+#[test]
+fn random_stream_is_validated_by_the_cli() {
+    use randstream::checksum::ChecksumAlgorithm;
+    use randstream::rng::{RngAlgorithm, Seed};
+    use randstream::stream::RandomStream;
+    let dir = TempDir::new().unwrap();
+    let mut stream = RandomStream::with_algorithms(
+        Seed::U64(7),
+        4096,
+        100_000,
+        RngAlgorithm::Xoshiro256,
+        ChecksumAlgorithm::Xxh3,
+    );
+    let mut file = fs::File::create(dir.path().join("out.bin")).unwrap();
+    std::io::copy(&mut stream, &mut file).unwrap();
+    let out = validate(&dir, &["-c", "4Ki", "--checksum", "xxh3", "out.bin"]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(parse_checksum(&out), stream.checksum());
+    // the same stream as the one on stdout
+    let out = generate(
+        &dir,
+        &[
+            "--size",
+            "100000",
+            "-c",
+            "4Ki",
+            "--seed",
+            "7",
+            "--rng",
+            "xoshiro256",
+            "--checksum",
+            "xxh3",
+        ],
+    );
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(out.stdout, fs::read(dir.path().join("out.bin")).unwrap());
+}
+
+// ---------------------------------------------------------------------------
+// generate + validate – round-trip matrix
+// ---------------------------------------------------------------------------
+
+/// Table-driven round-trip: (size, chunk_size, seed, jobs).
+/// All sizes use binary prefixes (Ki / Mi) for unambiguous byte counts.
+#[test]
+fn round_trip_various_sizes_and_seeds() {
+    struct Case {
+        size: &'static str,
+        chunk_size: &'static str,
+        seed: &'static str,
+        jobs: &'static str,
+        checksum: &'static str,
+    }
+    let cases = [
+        Case { size: "1Ki", chunk_size: "1Ki", seed: "0", jobs: "1", checksum: "48181a23" },
+        Case { size: "32Ki", chunk_size: "32Ki", seed: "1", jobs: "1", checksum: "855bfcd1" },
+        // size not a multiple of chunk_size (tail chunk is smaller)
+        Case { size: "33Ki", chunk_size: "32Ki", seed: "2", jobs: "1", checksum: "aa0e5a26" },
+        Case { size: "64Ki", chunk_size: "32Ki", seed: "3", jobs: "2", checksum: "5af3b3b3" },
+        Case { size: "256Ki", chunk_size: "64Ki", seed: "99", jobs: "3", checksum: "48bdc095" },
+        Case { size: "1Mi", chunk_size: "32Ki", seed: "12345", jobs: "1", checksum: "9e904c23" },
+        // same data, different thread count – checksums must be identical
+        Case { size: "1Mi", chunk_size: "32Ki", seed: "12345", jobs: "4", checksum: "9e904c23" },
+    ];
+
+    for case in &cases {
+        let dir = TempDir::new().unwrap();
+        let label = format!(
+            "size={} chunk={} seed={} jobs={}",
+            case.size, case.chunk_size, case.seed, case.jobs
+        );
+        let g = generate(
+            &dir,
+            &[
+                "--size",
+                case.size,
+                "--chunk-size",
+                case.chunk_size,
+                "--seed",
+                case.seed,
+                "--jobs",
+                case.jobs,
+                "out.bin",
+            ],
+        );
+        assert!(
+            g.status.success(),
+            "generate failed for {label}: {}",
+            String::from_utf8_lossy(&g.stderr)
+        );
+
+        let v = validate(&dir, &["--chunk-size", case.chunk_size, "out.bin"]);
+        assert!(
+            v.status.success(),
+            "validate failed for {label}: {}",
+            String::from_utf8_lossy(&v.stderr)
+        );
+
+        let g_checksum = parse_checksum(&g);
+        let v_checksum = parse_checksum(&v);
+        assert_eq!(
+            g_checksum, case.checksum,
+            "generate checksum changed for {label}: got {g_checksum}, expected {}",
+            case.checksum
+        );
+        assert_eq!(
+            v_checksum, case.checksum,
+            "validate checksum changed for {label}: got {v_checksum}, expected {}",
+            case.checksum
+        );
+    }
+}
+
+// ---------------------------------------------------------------------------
+// CLI argument validation
+// ---------------------------------------------------------------------------
+
+#[test]
+fn no_subcommand_exits_nonzero() {
+    let out = bin().output().unwrap();
+    assert!(!out.status.success());
+}
+
+#[test]
+fn unknown_flag_exits_nonzero() {
+    let out = bin().args(["generate", "--does-not-exist"]).output().unwrap();
+    assert!(!out.status.success());
+    assert_eq!(out.status.code(), Some(5));
+}
+
+#[test]
+fn exit_codes_match_the_failure_class() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "300Ki", "out.bin"]);
+    let v = validate(&dir, &["--expected-checksum", "00000000", "out.bin"]);
+    assert_eq!(v.status.code(), Some(3));
+    let v = validate(&dir, &["--digest", "blake3", "--expected-digest", "00", "out.bin"]);
+    assert_eq!(v.status.code(), Some(3));
+    let v = validate(&dir, &["missing.bin"]);
+    assert_eq!(v.status.code(), Some(4));
+    let v = validate(&dir, &["--position", "1Mi", "out.bin"]);
+    assert_eq!(v.status.code(), Some(5));
+    let path = dir.path().join("out.bin");
+    let mut data = fs::read(&path).unwrap();
+    data[100] ^= 0xff;
+    fs::write(&path, data).unwrap();
+    let v = validate(&dir, &["out.bin"]);
+    assert_eq!(v.status.code(), Some(2));
+    let v = validate(&dir, &["--output", "json", "out.bin"]);
+    assert!(String::from_utf8_lossy(&v.stdout).contains(r#""exit_code":2,"#));
+    assert!(g.status.success());
+}
+
+#[test]
+fn keep_going_summarizes_the_corrupted_chunks() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "1Mi", "--chunk-size", "64Ki", "out.bin"]);
+    assert!(g.status.success());
+    let path = dir.path().join("out.bin");
+    let mut data = fs::read(&path).unwrap();
+    for offset in [100, 65536 + 100, 5 * 65536] {
+        data[offset] ^= 0xff;
+    }
+    fs::write(&path, data).unwrap();
+    for extra in [&["-j", "4"][..], &["--digest", "blake3"]] {
+        let mut args = vec!["--keep-going", "--chunk-size", "64Ki", "out.bin"];
+        args.extend_from_slice(extra);
+        let v = validate(&dir, &args);
+        assert_eq!(v.status.code(), Some(2));
+        let stderr = String::from_utf8_lossy(&v.stderr);
+        assert!(stderr.contains("corrupted chunks: 3"), "{stderr}");
+        assert!(stderr.contains("first corrupted offset: 0"), "{stderr}");
+        assert!(stderr.contains("last corrupted offset: 327680"), "{stderr}");
+        assert!(stderr.contains("corrupted bytes: 0..131072"), "{stderr}");
+        assert!(stderr.contains("corrupted bytes: 327680..393216"), "{stderr}");
+    }
+    // the stdin path too
+    let out = bin()
+        .args(["validate", "--no-progress", "--keep-going", "--chunk-size", "64Ki"])
+        .stdin(fs::File::open(&path).unwrap())
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("corrupted chunks: 3"));
+}
+
+#[test]
+fn error_map_lists_the_corrupted_chunks() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "1Mi", "--chunk-size", "64Ki", "out.bin"]);
+    assert!(g.status.success());
+    let v = validate(
+        &dir,
+        &["--keep-going", "--error-map", "map.json", "--chunk-size", "64Ki", "out.bin"],
+    );
+    assert!(v.status.success());
+    let map = fs::read_to_string(dir.path().join("map.json")).unwrap();
+    assert_eq!(map, r#"{"corrupted_chunks":[]}"#);
+
+    let path = dir.path().join("out.bin");
+    let mut data = fs::read(&path).unwrap();
+    data[3 * 65536] ^= 0xff;
+    fs::write(&path, data).unwrap();
+    let v = validate(
+        &dir,
+        &["--keep-going", "--error-map", "map.json", "--chunk-size", "64Ki", "out.bin"],
+    );
+    assert_eq!(v.status.code(), Some(2));
+    let map = fs::read_to_string(dir.path().join("map.json")).unwrap();
+    assert!(
+        map.starts_with(
+            r#"{"corrupted_chunks":[{"chunk":3,"offset":196608,"length":65536,"expected":""#
+        ),
+        "{map}"
+    );
+    // the map requires --keep-going
+    let v = validate(&dir, &["--error-map", "map.json", "out.bin"]);
+    assert_eq!(v.status.code(), Some(5));
+}
+
+#[test]
+fn badblocks_out_lists_the_corrupted_blocks() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "1Mi", "--chunk-size", "64Ki", "out.bin"]);
+    assert!(g.status.success());
+    let path = dir.path().join("out.bin");
+    let mut data = fs::read(&path).unwrap();
+    data[3 * 65536] ^= 0xff;
+    fs::write(&path, data).unwrap();
+    let v = validate(
+        &dir,
+        &["--keep-going", "--badblocks-out", "bad.txt", "--chunk-size", "64Ki", "out.bin"],
+    );
+    assert_eq!(v.status.code(), Some(2));
+    let expected: String = (384..512).map(|b| format!("{b}\n")).collect();
+    assert_eq!(fs::read_to_string(dir.path().join("bad.txt")).unwrap(), expected);
+    let v = validate(
+        &dir,
+        &[
+            "--keep-going",
+            "--badblocks-out",
+            "bad.txt",
+            "--badblocks-block-size",
+            "4Ki",
+            "--chunk-size",
+            "64Ki",
+            "out.bin",
+        ],
+    );
+    assert_eq!(v.status.code(), Some(2));
+    let expected: String = (48..64).map(|b| format!("{b}\n")).collect();
+    assert_eq!(fs::read_to_string(dir.path().join("bad.txt")).unwrap(), expected);
+}
+
+#[test]
+fn verify_writes_and_reads_back_the_stream() {
+    let dir = TempDir::new().unwrap();
+    let out = bin()
+        .current_dir(dir.path())
+        .args(["verify", "--no-progress", "--size", "1Mi", "--digest", "blake3", "out.bin"])
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("generate: 1 MiB"), "{stderr}");
+    assert!(stderr.contains("validate: 1 MiB"), "{stderr}");
+    assert!(stderr.contains("verdict: pass"), "{stderr}");
+    let g = generate(&dir, &["--size", "1Mi", "expected.bin"]);
+    assert_eq!(
+        fs::read(dir.path().join("out.bin")).unwrap(),
+        fs::read(dir.path().join("expected.bin")).unwrap()
+    );
+    assert!(g.status.success());
+
+    let out = bin()
+        .current_dir(dir.path())
+        .args(["verify", "--no-progress", "--size", "1Mi", "--output", "json", "out.bin"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.starts_with(r#"{"command":"verify","success":true,"#), "{stdout}");
+    assert!(
+        stdout.contains(r#""phases":[{"name":"generate","pass":1,"bytes":1048576,"#),
+        "{stdout}"
+    );
+
+    // the stream can't be read back from stdout
+    let out = bin().args(["verify", "--no-progress", "--size", "1Mi"]).output().unwrap();
+    assert_eq!(out.status.code(), Some(5));
+    // a usage error isn't a verdict
+    assert!(!String::from_utf8_lossy(&out.stderr).contains("verdict"));
+    let args = ["verify", "--no-progress", "--size", "1Mi", "--run-id", "1", "out.bin"];
+    let out = bin().current_dir(dir.path()).args(args).output().unwrap();
+    assert_eq!(out.status.code(), Some(5));
+    assert!(!String::from_utf8_lossy(&out.stderr).contains("verdict"));
+}
+
+#[test]
+fn verify_passes_write_a_different_stream_each_time() {
+    let dir = TempDir::new().unwrap();
+    let out = bin()
+        .current_dir(dir.path())
+        .args(["verify", "--no-progress", "--size", "1Mi", "--passes", "3", "out.bin"])
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("pass 3/3"), "{stderr}");
+    assert!(stderr.contains("total written: 3 MiB"), "{stderr}");
+    assert!(stderr.contains("pass 2: write "), "{stderr}");
+    assert!(stderr.contains("failed passes: 0/3"), "{stderr}");
+    let checksums: Vec<_> = stderr.lines().filter(|l| l.contains("checksum: ")).collect();
+    assert_eq!(checksums.len(), 6);
+    assert_eq!(checksums[0], checksums[1]);
+    assert_ne!(checksums[0], checksums[2]);
+    assert_ne!(checksums[2], checksums[4]);
+
+    let out = bin().args(["verify", "--passes", "0", "out.bin"]).output().unwrap();
+    assert_eq!(out.status.code(), Some(5));
+}
+
+#[cfg(unix)]
+#[test]
+fn generate_resumes_from_its_checkpoint() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "8Mi", "--chunk-size", "64Ki", "expected.bin"]);
+    assert!(g.status.success());
+    let expected = fs::read(dir.path().join("expected.bin")).unwrap();
+    for (engine, checksum) in [("sync", "crc32"), ("io-uring", "crc64"), ("mmap", "xxh3")] {
+        let args = [
+            "generate",
+            "--no-progress",
+            "--size",
+            "8Mi",
+            "--chunk-size",
+            "64Ki",
+            "--engine",
+            engine,
+            "--checksum",
+            checksum,
+            "--checkpoint",
+            "checkpoint",
+            "out.bin",
+        ];
+        let child = bin()
+            .current_dir(dir.path())
+            .args(args)
+            .args(["--jobs", "3", "--bwlimit", "4Mi"])
+            .spawn()
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(800));
+        let kill = Command::new("kill").args(["-INT", &child.id().to_string()]).status().unwrap();
+        assert!(kill.success());
+        let out = child.wait_with_output().unwrap();
+        assert_eq!(out.status.code(), Some(130), "{engine}");
+        let checkpoint = fs::read_to_string(dir.path().join("checkpoint")).unwrap();
+        assert!(checkpoint.starts_with("randstream-checkpoint 1\n"), "{checkpoint}");
+
+        // resumed with another number of jobs, which is ignored
+        let out = bin().current_dir(dir.path()).args(args).args(["--jobs", "2"]).output().unwrap();
+        assert!(out.status.success(), "{engine}");
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(stderr.contains("resuming from the checkpoint"), "{stderr}");
+        assert!(!dir.path().join("checkpoint").exists());
+        let full = bin()
+            .current_dir(dir.path())
+            .args(["generate", "--no-progress", "--size", "8Mi", "--chunk-size", "64Ki"])
+            .args(["--checksum", checksum, "full.bin"])
+            .output()
+            .unwrap();
+        assert_eq!(parse_checksum(&out), parse_checksum(&full), "{engine}");
+        if checksum == "crc32" {
+            assert!(fs::read(dir.path().join("out.bin")).unwrap() == expected);
+        }
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn validate_resumes_from_its_checkpoint() {
+    let dir = TempDir::new().unwrap();
+    for (engine, checksum) in [("sync", "crc32"), ("io-uring", "crc64"), ("mmap", "xxh3")] {
+        let g = generate(
+            &dir,
+            &["--size", "8Mi", "--chunk-size", "64Ki", "--checksum", checksum, "out.bin"],
+        );
+        assert!(g.status.success());
+        let args = [
+            "validate",
+            "--no-progress",
+            "--chunk-size",
+            "64Ki",
+            "--engine",
+            engine,
+            "--checksum",
+            checksum,
+            "--checkpoint",
+            "checkpoint",
+            "out.bin",
+        ];
+        let child = bin()
+            .current_dir(dir.path())
+            .args(args)
+            .args(["--jobs", "3", "--bwlimit", "4Mi"])
+            .spawn()
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(800));
+        let kill = Command::new("kill").args(["-INT", &child.id().to_string()]).status().unwrap();
+        assert!(kill.success());
+        let out = child.wait_with_output().unwrap();
+        assert_eq!(out.status.code(), Some(130), "{engine}");
+        assert!(dir.path().join("checkpoint").exists());
+
+        let out = bin().current_dir(dir.path()).args(args).output().unwrap();
+        assert!(out.status.success(), "{engine}");
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(stderr.contains("resuming from the checkpoint"), "{stderr}");
+        assert!(!dir.path().join("checkpoint").exists());
+        // the stream checksum is recombined across the resume boundary
+        assert_eq!(parse_checksum(&out), parse_checksum(&g), "{engine}");
+    }
+}
+
+#[test]
+fn checkpoint_of_another_stream_is_rejected() {
+    let dir = TempDir::new().unwrap();
+    let checkpoint = "randstream-checkpoint 1\nseed 0\nstream generate rng=pcg64 checksum=crc32 \
+                      position=0 size=1 chunk=1\nrange 0 0 1 0 0 0\n";
+    fs::write(dir.path().join("checkpoint"), checkpoint).unwrap();
+    let g = generate(&dir, &["--size", "1Mi", "--checkpoint", "checkpoint", "out.bin"]);
+    assert_eq!(g.status.code(), Some(5));
+    fs::write(dir.path().join("checkpoint"), "garbage").unwrap();
+    let g = generate(&dir, &["--size", "1Mi", "--checkpoint", "checkpoint", "out.bin"]);
+    assert_eq!(g.status.code(), Some(5));
+    let g = generate(&dir, &["--digest", "blake3", "--checkpoint", "checkpoint", "out.bin"]);
+    assert_eq!(g.status.code(), Some(5));
+}
+
+#[test]
+fn validate_a_region_of_the_stream() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "1Mi", "--chunk-size", "64Ki", "out.bin"]);
+    assert!(g.status.success());
+    let path = dir.path().join("out.bin");
+    let mut data = fs::read(&path).unwrap();
+    data[10 * 65536 + 100] ^= 0xff;
+    fs::write(&path, &data).unwrap();
+    let region = |position: &str, length: &str| {
+        validate(
+            &dir,
+            &["--chunk-size", "64Ki", "--position", position, "--length", length, "out.bin"],
+        )
+    };
+    assert!(region("0", "512Ki").status.success());
+    assert!(region("704Ki", "320Ki").status.success());
+    assert_eq!(region("640Ki", "64Ki").status.code(), Some(2));
+    assert_eq!(region("960Ki", "128Ki").status.code(), Some(5));
+    let out = bin()
+        .args(["validate", "--no-progress", "--chunk-size", "64Ki", "--length", "640Ki"])
+        .stdin(fs::File::open(&path).unwrap())
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+}
+
+#[test]
+fn sample_validates_a_subset_of_the_chunks() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "1Mi", "--chunk-size", "64Ki", "out.bin"]);
+    assert!(g.status.success());
+    let v = validate(&dir, &["--sample-chunks", "4", "--chunk-size", "64Ki", "out.bin"]);
+    assert!(v.status.success());
+    assert!(String::from_utf8_lossy(&v.stderr).contains("sampled chunks: 4 of 16"));
+
+    let path = dir.path().join("out.bin");
+    let mut data = fs::read(&path).unwrap();
+    data[5 * 65536 + 100] ^= 0xff;
+    fs::write(&path, data).unwrap();
+    let v = validate(&dir, &["--sample", "100%", "--chunk-size", "64Ki", "-j", "4", "out.bin"]);
+    assert_eq!(v.status.code(), Some(2));
+    let v = validate(
+        &dir,
+        &["--sample", "50%", "--keep-going", "--chunk-size", "64Ki", "-j", "2", "out.bin"],
+    );
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    assert!(stderr.contains("sampled chunks: 8 of 16"), "{stderr}");
+    // the checksum of the stream can't be checked from a sample
+    let v = validate(&dir, &["--sample", "5%", "--expected-checksum", "0", "out.bin"]);
+    assert_eq!(v.status.code(), Some(5));
+}
+
+#[test]
+fn sparse_skips_the_chunks_in_holes() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "1Mi", "--chunk-size", "64Ki", "out.bin"]);
+    assert!(g.status.success());
+    // a hole at the end of the file, read as zeros
+    let path = dir.path().join("out.bin");
+    fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(2 << 20).unwrap();
+    let v = validate(&dir, &["--chunk-size", "64Ki", "out.bin"]);
+    assert_eq!(v.status.code(), Some(2));
+    let v = validate(&dir, &["--sparse", "--chunk-size", "64Ki", "-j", "3", "out.bin"]);
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    assert!(v.status.success(), "{stderr}");
+    assert!(stderr.contains("chunks in holes: 16 of 32"), "{stderr}");
+
+    // the chunks holding data are still validated
+    let mut data = fs::read(&path).unwrap();
+    data[5 * 65536 + 100] ^= 0xff;
+    fs::write(&path, &data[..1 << 20]).unwrap();
+    fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(2 << 20).unwrap();
+    let v = validate(&dir, &["--sparse", "--chunk-size", "64Ki", "out.bin"]);
+    assert_eq!(v.status.code(), Some(2));
+    let v = validate(&dir, &["--sparse", "--expected-checksum", "0", "out.bin"]);
+    assert_eq!(v.status.code(), Some(5));
+}
+
+#[test]
+fn read_retries_options() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "1Mi", "out.bin"]);
+    assert!(g.status.success());
+    let v = validate(&dir, &["--read-retries", "2", "--retry-reopen", "out.bin"]);
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+    assert_eq!(parse_checksum(&v), parse_checksum(&g));
+    // the retries only apply to the sync engine
+    let v = validate(&dir, &["--read-retries", "2", "--engine", "mmap", "out.bin"]);
+    assert_eq!(v.status.code(), Some(5));
+    assert_eq!(validate(&dir, &["--retry-reopen", "out.bin"]).status.code(), Some(5));
+}
+
+#[test]
+fn corrupt_each_kind_of_corruption() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "1Mi", "--format", "v2", "out.bin"]);
+    assert!(g.status.success());
+    let c = bin()
+        .current_dir(dir.path())
+        .args(["corrupt", "--swap-chunks", "2,5", "--zero-chunk", "7", "--flip-at", "300000"])
+        .args(["--truncate", "1000", "out.bin"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&c.stderr);
+    assert!(c.status.success(), "{stderr}");
+    assert!(stderr.contains("flipped bit 0 at offset 300000, in chunk 9"), "{stderr}");
+    assert_eq!(fs::metadata(dir.path().join("out.bin")).unwrap().len(), (1 << 20) - 1000);
+
+    let v = validate(&dir, &["--keep-going", "--error-map", "map.json", "out.bin"]);
+    assert_eq!(v.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    assert!(stderr.contains("error: 3 corrupted chunks, 2 misplaced chunks"), "{stderr}");
+    let map = fs::read_to_string(dir.path().join("map.json")).unwrap();
+    for chunk in [2, 5, 7, 9, 31] {
+        assert!(map.contains(&format!("{{\"chunk\":{chunk},")), "{map}");
+    }
+
+    // the random bits flipped are reproduced from the seed
+    let flip = |file: &str| {
+        let c = bin()
+            .current_dir(dir.path())
+            .args(["corrupt", "--flip-bits", "4", "-S", "7", file])
+            .output()
+            .unwrap();
+        assert!(c.status.success());
+        fs::read(dir.path().join(file)).unwrap()
+    };
+    fs::copy(dir.path().join("out.bin"), dir.path().join("copy.bin")).unwrap();
+    assert_eq!(flip("out.bin"), flip("copy.bin"));
+    let c = bin().current_dir(dir.path()).args(["corrupt", "out.bin"]).output().unwrap();
+    assert_eq!(c.status.code(), Some(5));
+}
+
+#[test]
+fn repair_rewrites_the_corrupted_chunks() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "1Mi", "-S", "5", "out.bin"]);
+    assert!(g.status.success());
+    let original = fs::read(dir.path().join("out.bin")).unwrap();
+    let corrupt = |args: &[&str]| {
+        let c = bin().current_dir(dir.path()).arg("corrupt").args(args).output().unwrap();
+        assert!(c.status.success(), "{}", String::from_utf8_lossy(&c.stderr));
+    };
+    corrupt(&["--zero-chunk", "7", "--flip-at", "300000", "out.bin"]);
+    let repair = |args: &[&str]| {
+        bin().current_dir(dir.path()).args(["repair", "-S", "5"]).args(args).output().unwrap()
+    };
+    let r = repair(&["out.bin"]);
+    let stderr = String::from_utf8_lossy(&r.stderr);
+    assert!(r.status.success(), "{stderr}");
+    assert!(stderr.contains("repaired chunks: 2"), "{stderr}");
+    assert_eq!(fs::read(dir.path().join("out.bin")).unwrap(), original);
+    let r = repair(&["out.bin"]);
+    assert!(String::from_utf8_lossy(&r.stderr).contains("no corrupted chunk to repair"));
+
+    // from the map of the corrupted chunks
+    corrupt(&["--flip-bits", "3", "-S", "9", "out.bin"]);
+    let v = validate(&dir, &["--keep-going", "--error-map", "map.json", "out.bin"]);
+    assert_eq!(v.status.code(), Some(2));
+    let r = repair(&["--error-map", "map.json", "out.bin"]);
+    assert!(r.status.success(), "{}", String::from_utf8_lossy(&r.stderr));
+    assert_eq!(fs::read(dir.path().join("out.bin")).unwrap(), original);
+    assert_eq!(repair(&["--error-map", "out.bin", "out.bin"]).status.code(), Some(5));
+    // the options of generate don't describe the stream to repair
+    assert_eq!(repair(&["--tree", "dir", "out.bin"]).status.code(), Some(5));
+    assert_eq!(repair(&["--no-truncate", "out.bin"]).status.code(), Some(5));
+    assert_eq!(repair(&["--digest", "sha256", "out.bin"]).status.code(), Some(5));
+}
+
+#[test]
+fn incremental_validates_the_chunks_due() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "1Mi", "--chunk-size", "64Ki", "out.bin"]);
+    assert!(g.status.success());
+    let args = ["--chunk-size", "64Ki", "--incremental", "state", "--incremental-size", "256Ki"];
+    let v = validate(&dir, &[&args[..], &["out.bin"]].concat());
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    assert!(v.status.success(), "{stderr}");
+    assert!(stderr.contains("chunks due: 16 of 16, validating 4"), "{stderr}");
+    let v = validate(&dir, &[&args[..], &["out.bin"]].concat());
+    assert!(String::from_utf8_lossy(&v.stderr).contains("chunks due: 12 of 16, validating 4"));
+
+    // the corrupted chunks are validated again by the next run
+    let path = dir.path().join("out.bin");
+    let mut data = fs::read(&path).unwrap();
+    data[9 * 65536 + 100] ^= 0xff;
+    fs::write(&path, data).unwrap();
+    let v = validate(&dir, &[&args[..], &["--keep-going", "out.bin"]].concat());
+    assert_eq!(v.status.code(), Some(2));
+    let v = validate(&dir, &["--chunk-size", "64Ki", "--incremental", "state", "out.bin"]);
+    assert_eq!(v.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&v.stderr).contains("chunks due: 5 of 16, validating 5"));
+
+    // the state of another stream
+    let v = validate(&dir, &["--chunk-size", "128Ki", "--incremental", "state", "out.bin"]);
+    assert_eq!(v.status.code(), Some(5));
+}
+
+#[test]
+fn format_v2_detects_the_chunks_out_of_place() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "1Mi", "--chunk-size", "64Ki", "--format", "v2", "out.bin"]);
+    assert!(g.status.success());
+    let v = validate(&dir, &["--chunk-size", "64Ki", "--format", "v2", "-j", "4", "out.bin"]);
+    assert!(v.status.success());
+    // a region of the stream
+    let v = validate(
+        &dir,
+        &["--chunk-size", "64Ki", "--format", "v2", "--position", "192Ki", "out.bin"],
+    );
+    assert!(v.status.success());
+
+    // two chunks swapped still match their checksum
+    let path = dir.path().join("out.bin");
+    let original = fs::read(&path).unwrap();
+    let mut data = original.clone();
+    data[2 * 65536..3 * 65536].copy_from_slice(&original[5 * 65536..6 * 65536]);
+    fs::write(&path, &data).unwrap();
+    let v = validate(&dir, &["--chunk-size", "64Ki", "out.bin"]);
+    assert_eq!(v.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    assert!(
+        stderr.contains("Misplaced chunk 2 at offset 131072: contains data for offset 327680."),
+        "{stderr}"
+    );
+
+    // the first chunk swapped doesn't make the others misplaced
+    let mut data = original.clone();
+    data[..65536].copy_from_slice(&original[5 * 65536..6 * 65536]);
+    data[5 * 65536..6 * 65536].copy_from_slice(&original[..65536]);
+    fs::write(&path, &data).unwrap();
+    let v = validate(&dir, &["--chunk-size", "64Ki", "--keep-going", "out.bin"]);
+    assert_eq!(v.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    assert!(stderr.contains("misplaced chunk at offset 0: contains data for offset 327680"));
+    assert!(stderr.contains("misplaced chunk at offset 327680: contains data for offset 0"));
+    assert!(stderr.contains("error: 0 corrupted chunks, 2 misplaced chunks"), "{stderr}");
+    // nor the ones of a region, whose chunk holds data from past its end
+    let mut data = original.clone();
+    data[3 * 65536..4 * 65536].copy_from_slice(&original[9 * 65536..10 * 65536]);
+    fs::write(&path, &data).unwrap();
+    let region = ["--chunk-size", "64Ki", "--position", "128Ki", "--length", "256Ki"];
+    let v = validate(&dir, &[&region[..], &["--keep-going", "out.bin"]].concat());
+    assert_eq!(v.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    assert!(
+        stderr.contains("misplaced chunk at offset 196608: contains data from outside the stream"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("error: 0 corrupted chunks, 1 misplaced chunks"), "{stderr}");
+
+    // a chunk of another stream
+    let g = generate(
+        &dir,
+        &["--size", "1Mi", "--chunk-size", "64Ki", "--format", "v2", "--seed", "1", "other.bin"],
+    );
+    assert!(g.status.success());
+    let other = fs::read(dir.path().join("other.bin")).unwrap();
+    let mut data = original.clone();
+    data[3 * 65536..4 * 65536].copy_from_slice(&other[3 * 65536..4 * 65536]);
+    fs::write(&path, &data).unwrap();
+    let v = validate(&dir, &["--chunk-size", "64Ki", "--format", "v2", "out.bin"]);
+    assert_eq!(v.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&v.stderr).contains("Chunk 3 comes from another stream"));
+
+    // chunks too short to hold a header
+    let args = ["--size", "1Ki", "--chunk-size", "16", "--format", "v2", "short.bin"];
+    assert_eq!(generate(&dir, &args).status.code(), Some(5));
+    let args = ["--size", "1Ki", "--chunk-size", "16", "short.bin"];
+    assert!(generate(&dir, &args).status.success());
+    let v = validate(&dir, &["--chunk-size", "16", "--format", "v2", "short.bin"]);
+    assert_eq!(v.status.code(), Some(5));
+    let args = ["--size", "1Ki", "--chunk-size", "44", "--format", "v2", "short.bin"];
+    assert!(generate(&dir, &args).status.success());
+}
+
+#[test]
+fn format_is_recorded_in_the_stream_header() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(
+        &dir,
+        &["--size", "1Mi", "--chunk-size", "64Ki", "--format", "v2", "--random-seed", "out.bin"],
+    );
+    assert!(g.status.success());
+    let path = dir.path().join("out.bin");
+    let original = fs::read(&path).unwrap();
+    let mut data = original.clone();
+    let chunk = |i: usize| 64 + i * 65536..64 + (i + 1) * 65536;
+    data[chunk(1)].copy_from_slice(&original[chunk(4)]);
+    fs::write(&path, &data).unwrap();
+    // no need for --format v2
+    let v = validate(&dir, &["--chunk-size", "64Ki", "out.bin"]);
+    assert_eq!(v.status.code(), Some(2));
+    let out = bin()
+        .args(["validate", "--no-progress", "--chunk-size", "64Ki"])
+        .stdin(fs::File::open(&path).unwrap())
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(2));
+}
+
+#[test]
+fn keep_going_reports_the_misplaced_chunks_apart() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "1Mi", "--chunk-size", "64Ki", "--format", "v2", "out.bin"]);
+    assert!(g.status.success());
+    let path = dir.path().join("out.bin");
+    let original = fs::read(&path).unwrap();
+    let mut data = original.clone();
+    data[2 * 65536..3 * 65536].copy_from_slice(&original[5 * 65536..6 * 65536]);
+    data[5 * 65536..6 * 65536].copy_from_slice(&original[2 * 65536..3 * 65536]);
+    data[9 * 65536 + 100] ^= 0xff;
+    fs::write(&path, &data).unwrap();
+    let v = validate(
+        &dir,
+        &["--chunk-size", "64Ki", "--format", "v2", "--keep-going", "-j", "3", "out.bin"],
+    );
+    assert_eq!(v.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    assert!(
+        stderr.contains("misplaced chunk at offset 131072: contains data for offset 327680"),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains("misplaced chunk at offset 327680: contains data for offset 131072"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("corrupted chunks: 1"), "{stderr}");
+    assert!(stderr.contains("misplaced chunks: 2"), "{stderr}");
+    assert!(stderr.contains("1 corrupted chunks, 2 misplaced chunks"), "{stderr}");
+}
+
+#[test]
+fn run_id_tells_the_stale_chunks_from_the_corrupted_ones() {
+    let dir = TempDir::new().unwrap();
+    let run = |run_id: &str, file: &str| {
+        let args = ["--size", "1Mi", "--chunk-size", "64Ki", "--format", "v2", "--run-id", run_id];
+        let mut args = args.to_vec();
+        args.push(file);
+        assert!(generate(&dir, &args).status.success());
+        fs::read(dir.path().join(file)).unwrap()
+    };
+    let old = run("1", "old.bin");
+    let mut data = run("2", "out.bin");
+    // a chunk the second run failed to write
+    data[3 * 65536..4 * 65536].copy_from_slice(&old[3 * 65536..4 * 65536]);
+    fs::write(dir.path().join("out.bin"), &data).unwrap();
+    let v = validate(&dir, &["--chunk-size", "64Ki", "--format", "v2", "--run-id", "2", "out.bin"]);
+    assert_eq!(v.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    assert!(
+        stderr.contains(
+            "Stale chunk 3 at offset 196608: written by the run 0000000000000001, instead of \
+             0000000000000002."
+        ),
+        "{stderr}"
+    );
+    // the run ID of most of the first chunks is the expected one by default
+    let v = validate(&dir, &["--chunk-size", "64Ki", "--format", "v2", "--keep-going", "out.bin"]);
+    assert_eq!(v.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    assert!(stderr.contains("stale chunks: 1"), "{stderr}");
+    assert!(stderr.contains("corrupted chunks: 0"), "{stderr}");
+    // even when the first chunk is the stale one
+    data[3 * 65536..4 * 65536].copy_from_slice(&run("2", "new.bin")[3 * 65536..4 * 65536]);
+    data[..65536].copy_from_slice(&old[..65536]);
+    fs::write(dir.path().join("out.bin"), &data).unwrap();
+    let v = validate(&dir, &["--chunk-size", "64Ki", "--format", "v2", "--keep-going", "out.bin"]);
+    assert_eq!(v.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    assert!(
+        stderr.contains("stale chunk at offset 0: written by the run 0000000000000001"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("stale chunks: 1"), "{stderr}");
+    assert!(stderr.contains("corrupted chunks: 0"), "{stderr}");
+
+    let g = generate(&dir, &["--size", "1Mi", "--run-id", "1", "v1.bin"]);
+    assert_eq!(g.status.code(), Some(5));
+}
+
+#[test]
+fn regenerate_detects_the_corruption_matching_the_chunk_checksum() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "1Mi", "--chunk-size", "64Ki", "--seed", "7", "out.bin"]);
+    assert!(g.status.success());
+    let regenerate = |extra: &[&str]| {
+        let mut args = vec!["--chunk-size", "64Ki", "--regenerate", "out.bin"];
+        args.extend_from_slice(extra);
+        validate(&dir, &args)
+    };
+    assert!(regenerate(&["--seed", "7", "-j", "3"]).status.success());
+    assert!(regenerate(&["--seed", "7", "--engine", "mmap"]).status.success());
+
+    // alter a chunk, and seal it again with a valid checksum
+    let path = dir.path().join("out.bin");
+    let mut data = fs::read(&path).unwrap();
+    let chunk = 2 * 65536..3 * 65536;
+    data[chunk.start + 1000] ^= 0x10;
+    let crc = crc32fast::hash(&data[chunk.start..chunk.end - 4]);
+    data[chunk.end - 4..chunk.end].copy_from_slice(&crc.to_le_bytes());
+    fs::write(&path, &data).unwrap();
+    assert!(validate(&dir, &["--chunk-size", "64Ki", "out.bin"]).status.success());
+    let v = regenerate(&["--seed", "7", "-j", "3"]);
+    assert_eq!(v.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    assert!(
+        stderr.contains(
+            "Chunk 2 doesn't match the regenerated data: 1 bytes differ, from offset 132072 to \
+             132072."
+        ),
+        "{stderr}"
+    );
+    let out = bin()
+        .args(["validate", "--no-progress", "--chunk-size", "64Ki", "--regenerate", "-S", "7"])
+        .stdin(fs::File::open(&path).unwrap())
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(2));
+
+    // the seed is needed
+    assert_eq!(regenerate(&[]).status.code(), Some(5));
+    assert_eq!(validate(&dir, &["--seed", "7", "out.bin"]).status.code(), Some(5));
+}
+
+#[test]
+fn regenerate_a_region_of_a_v2_stream() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(
+        &dir,
+        &["--size", "1Mi", "--chunk-size", "64Ki", "--format", "v2", "--random-seed", "out.bin"],
+    );
+    assert!(g.status.success());
+    // the seed is read from the stream header
+    let v = validate(&dir, &["--chunk-size", "64Ki", "--regenerate", "out.bin"]);
+    assert!(v.status.success());
+
+    let g = generate(
+        &dir,
+        &["--size", "1Mi", "--chunk-size", "64Ki", "--format", "v2", "--seed", "9", "out.bin"],
+    );
+    assert!(g.status.success());
+    // the index of the first chunk of the region is read from its header
+    let region = ["--chunk-size", "64Ki", "--format", "v2", "--position", "128Ki", "out.bin"];
+    let v =
+        validate(&dir, &[&region[..], &["--length", "256Ki", "--regenerate", "-S", "9"]].concat());
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+    let v = validate(&dir, &[&region[..], &["--regenerate", "-S", "10"]].concat());
+    assert_eq!(v.status.code(), Some(2));
+}
+
+#[test]
+fn diff_details_the_damage_of_the_corrupted_chunks() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "1Mi", "--chunk-size", "64Ki", "--seed", "7", "out.bin"]);
+    assert!(g.status.success());
+    let path = dir.path().join("out.bin");
+    let mut data = fs::read(&path).unwrap();
+    // a bit flip in chunk 1, and a torn write in chunk 3
+    data[65536 + 10] ^= 0x01;
+    data[3 * 65536 + 4096..3 * 65536 + 8192].fill(0);
+    fs::write(&path, &data).unwrap();
+
+    let args = ["--chunk-size", "64Ki", "--keep-going", "--regenerate", "-S", "7", "--diff"];
+    let v = validate(&dir, &[&args[..], &["out.bin"]].concat());
+    assert_eq!(v.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    assert!(
+        stderr.contains("diff of chunk 1: 1 bytes differ, 1 bits flipped, in 1 damaged regions"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("damaged region: 65546..65547 (1 bytes)"), "{stderr}");
+    assert!(stderr.contains("bytes by flipped bits: 1 bits: 1\n"), "{stderr}");
+    assert!(stderr.contains("damaged region: 200704..204800 (4096 bytes)"), "{stderr}");
+
+    // --diff needs the regenerated data
+    let v = validate(&dir, &["--chunk-size", "64Ki", "--diff", "out.bin"]);
+    assert_eq!(v.status.code(), Some(5));
+}
+
+#[test]
+fn compress_ratio_mixes_zeros_with_the_random_data() {
+    let dir = TempDir::new().unwrap();
+    let args = ["--size", "1Mi", "--seed", "3", "--compress-ratio", "4", "out.bin"];
+    assert!(generate(&dir, &args).status.success());
+    let data = fs::read(dir.path().join("out.bin")).unwrap();
+    let zeros = data.iter().filter(|b| **b == 0).count();
+    assert!(zeros > data.len() * 3 / 4 && zeros < data.len() * 4 / 5, "{zeros}");
+    assert!(validate(&dir, &["out.bin"]).status.success());
+    let regenerate = ["--regenerate", "-S", "3", "out.bin"];
+    let v = validate(&dir, &[&["--compress-ratio", "4"][..], &regenerate].concat());
+    assert!(v.status.success());
+    assert_eq!(validate(&dir, &regenerate).status.code(), Some(2));
+
+    // recorded in the stream header
+    let args = ["--size", "1Mi", "--random-seed", "--compress-ratio", "2.5", "out.bin"];
+    assert!(generate(&dir, &args).status.success());
+    let v = validate(&dir, &["--regenerate", "out.bin"]);
+    assert!(v.status.success());
+    assert!(String::from_utf8_lossy(&v.stderr).contains("compress ratio: 2.50"));
+
+    let v = validate(&dir, &["--compress-ratio", "0.5", "out.bin"]);
+    assert_eq!(v.status.code(), Some(5));
+}
+
+#[test]
+fn dedupe_ratio_repeats_the_blocks() {
+    let dir = TempDir::new().unwrap();
+    let args = ["--size", "1Mi", "-c", "64Ki", "--seed", "3", "--dedupe-ratio", "4", "out.bin"];
+    let g = generate(&dir, &args);
+    assert!(g.status.success());
+    // the ratio achieved is logged, not the one requested
+    let stderr = String::from_utf8_lossy(&g.stderr);
+    assert!(stderr.contains("dedupe ratio: 3.20, with groups of 4 blocks"), "{stderr}");
+    let data = fs::read(dir.path().join("out.bin")).unwrap();
+    let blocks: std::collections::HashSet<_> = data.chunks(4096).collect();
+    // 16 chunks of 4 groups of blocks, the last block of each chunk holding its checksum
+    assert_eq!(blocks.len(), 16 * 5);
+    assert!(validate(&dir, &["-c", "64Ki", "out.bin"]).status.success());
+    let regenerate = ["-c", "64Ki", "--regenerate", "-S", "3", "out.bin"];
+    let v = validate(&dir, &[&["--dedupe-ratio", "4"][..], &regenerate].concat());
+    assert!(v.status.success());
+    assert_eq!(validate(&dir, &regenerate).status.code(), Some(2));
+
+    let args = ["--size", "1Mi", "-c", "6000", "--dedupe-ratio", "4", "out.bin"];
+    assert_eq!(generate(&dir, &args).status.code(), Some(5));
+}
+
+#[test]
+fn pattern_replaces_the_random_data() {
+    let dir = TempDir::new().unwrap();
+    let args = ["--size", "64Ki", "-c", "4Ki", "--pattern", "walking-ones", "out.bin"];
+    assert!(generate(&dir, &args).status.success());
+    let data = fs::read(dir.path().join("out.bin")).unwrap();
+    assert_eq!(data[4096..4104], [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80]);
+    assert_eq!(data[4096 * 3 - 16..4096 * 3 - 8], [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80]);
+    assert!(validate(&dir, &["-c", "4Ki", "out.bin"]).status.success());
+    let regenerate = ["-c", "4Ki", "--regenerate", "-S", "0", "out.bin"];
+    let v = validate(&dir, &[&["--pattern", "walking-ones"][..], &regenerate].concat());
+    assert!(v.status.success());
+    assert_eq!(validate(&dir, &regenerate).status.code(), Some(2));
+
+    let args = ["--size", "64Ki", "--random-seed", "--pattern", "checkerboard", "out.bin"];
+    assert!(generate(&dir, &args).status.success());
+    let data = fs::read(dir.path().join("out.bin")).unwrap();
+    assert_eq!(data[64..68], [0x55, 0xaa, 0x55, 0xaa]);
+    assert!(validate(&dir, &["--regenerate", "out.bin"]).status.success());
+
+    let args = ["--size", "64Ki", "--pattern", "ones", "--compress-ratio", "2", "out.bin"];
+    assert_eq!(generate(&dir, &args).status.code(), Some(5));
+}
+
+#[test]
+fn shard_size_splits_the_stream_across_files() {
+    let dir = TempDir::new().unwrap();
+    let args = ["--size", "1000Ki", "-c", "64Ki", "--seed", "5", "out.bin"];
+    let single = generate(&dir, &args);
+    assert!(single.status.success());
+    let whole = fs::read(dir.path().join("out.bin")).unwrap();
+
+    let sharded = ["-c", "64Ki", "--shard-size", "256Ki", "out-%03d.bin"];
+    let g =
+        generate(&dir, &[&["--size", "1000Ki", "--seed", "5", "-j", "3"][..], &sharded].concat());
+    assert!(g.status.success());
+    let mut joined = Vec::new();
+    for index in 0..4 {
+        joined.extend(fs::read(dir.path().join(format!("out-{index:03}.bin"))).unwrap());
+    }
+    assert!(!dir.path().join("out-004.bin").exists());
+    assert_eq!(joined, whole);
+    // the shards are validated as a single stream, with the same checksum
+    let checksum = |output: &std::process::Output| {
+        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        stderr.lines().find(|l| l.contains("checksum:")).unwrap().to_string()
+    };
+    let v = validate(&dir, &sharded);
+    assert!(v.status.success());
+    assert_eq!(checksum(&v), checksum(&single));
+    let v = validate(&dir, &[&sharded[..], &["--regenerate", "-S", "5"]].concat());
+    assert!(v.status.success());
+
+    // the corrupted chunks are reported at their offset in the stream
+    let path = dir.path().join("out-002.bin");
+    let mut data = fs::read(&path).unwrap();
+    data[65536 + 10] ^= 1;
+    fs::write(&path, &data).unwrap();
+    let v = validate(&dir, &[&sharded[..], &["--keep-going"]].concat());
+    assert_eq!(v.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    assert!(stderr.contains("chunk 9 at offset 589824"), "{stderr}");
+
+    // the shards in the wrong order are detected with the v2 format
+    let v2 = ["-c", "64Ki", "--format", "v2", "--shard-size", "256Ki", "v2-%d.bin"];
+    assert!(generate(&dir, &[&["--size", "1Mi"][..], &v2].concat()).status.success());
+    fs::rename(dir.path().join("v2-1.bin"), dir.path().join("tmp.bin")).unwrap();
+    fs::rename(dir.path().join("v2-2.bin"), dir.path().join("v2-1.bin")).unwrap();
+    fs::rename(dir.path().join("tmp.bin"), dir.path().join("v2-2.bin")).unwrap();
+    let v = validate(&dir, &v2);
+    assert_eq!(v.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&v.stderr).contains("Misplaced chunk 4"));
+
+    let args = ["--size", "1Mi", "--shard-size", "256Ki", "out.bin"];
+    assert_eq!(generate(&dir, &args).status.code(), Some(5));
+}
+
+#[test]
+fn sharded_runs_report_their_progress() {
+    let dir = TempDir::new().unwrap();
+    let sharded = ["-c", "64Ki", "--shard-size", "256Ki", "out-%03d.bin"];
+    let run = |command: &str, extra: &[&str]| {
+        let output =
+            bin().current_dir(dir.path()).arg(command).args(extra).args(sharded).output().unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        assert!(output.status.success(), "{stderr}");
+        stderr
+    };
+    for command in ["generate", "validate"] {
+        let size: &[&str] = if command == "generate" { &["--size", "1000Ki"] } else { &[] };
+        run(command, size);
+        // a progress per shard, up to its size
+        let stderr = run(command, &[size, &["--progress", "json"]].concat());
+        let done: Vec<_> = stderr.lines().filter(|l| l.contains(r#""event":"done""#)).collect();
+        assert_eq!(done.len(), 4, "{stderr}");
+        assert!(done[0].contains(r#""bytes_done":262144,"total":262144"#), "{stderr}");
+        assert!(done[3].contains(r#""bytes_done":237568,"total":237568"#), "{stderr}");
+    }
+}
+
+#[test]
+fn several_devices_are_processed_at_once() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "256Ki", "--seed", "7", "a.bin", "b.bin"]);
+    assert!(g.status.success(), "{}", String::from_utf8_lossy(&g.stderr));
+    // the first device uses the seed itself, and the others a derived one
+    assert!(generate(&dir, &["--size", "256Ki", "--seed", "7", "single.bin"]).status.success());
+    let read = |name: &str| fs::read(dir.path().join(name)).unwrap();
+    assert_eq!(read("a.bin"), read("single.bin"));
+    assert_ne!(read("a.bin"), read("b.bin"));
+    let v = validate(&dir, &["--regenerate", "--seed", "7", "a.bin", "b.bin"]);
+    assert!(v.status.success());
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    assert!(stderr.lines().any(|l| l.starts_with("a.bin") && l.contains(" pass ")), "{stderr}");
+    assert!(stderr.lines().any(|l| l.starts_with("b.bin") && l.contains(" pass ")), "{stderr}");
+
+    // a corrupted device fails, without stopping the others
+    let mut data = read("b.bin");
+    data[1000] ^= 1;
+    fs::write(dir.path().join("b.bin"), &data).unwrap();
+    let v = validate(&dir, &["--output", "json", "a.bin", "b.bin"]);
+    assert_eq!(v.status.code(), Some(2));
+    let stdout = String::from_utf8_lossy(&v.stdout);
+    assert!(
+        stdout.starts_with(
+            r#"{"command":"validate","success":false,"exit_code":2,"passed":1,"failed":1,"bytes":"#
+        ),
+        "{stdout}"
+    );
+    assert!(stdout.contains(r#""devices":[{"device":"a.bin","command":"validate","success":true"#));
+    assert!(
+        stdout.contains(r#"{"device":"b.bin","command":"validate","success":false,"exit_code":2"#)
+    );
+
+    let out = bin()
+        .current_dir(dir.path())
+        .args(["verify", "--no-progress", "--size", "256Ki", "c.bin", "d.bin"])
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.lines().any(|l| l.starts_with("d.bin") && l.contains(" pass ")), "{stderr}");
+    assert!(stderr.contains("verdict: pass"));
+}
+
+#[test]
+fn send_and_receive_a_stream_over_tcp() {
+    // a free port
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let address = format!("tcp://127.0.0.1:{port}");
+    let receiver = bin()
+        .args(["receive", "--no-progress", "--format", "v2", "-j", "2", &address])
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let sent = bin()
+        .args(["send", "--no-progress", "--size", "1Mi", "--format", "v2", "--seed", "3"])
+        .args(["--connect-retries", "30", &address])
+        .output()
+        .unwrap();
+    assert!(sent.status.success(), "{}", String::from_utf8_lossy(&sent.stderr));
+    let received = receiver.wait_with_output().unwrap();
+    assert!(received.status.success(), "{}", String::from_utf8_lossy(&received.stderr));
+    assert_eq!(parse_checksum(&received), parse_checksum(&sent));
+
+    let sent = bin()
+        .args(["send", "--no-progress", "--size", "1Mi", "--preallocate", &address])
+        .output()
+        .unwrap();
+    assert_eq!(sent.status.code(), Some(5));
+    let sent = bin()
+        .args(["send", "--no-progress", "--size", "1Mi", "--connect-retries", "0", &address])
+        .output()
+        .unwrap();
+    assert_eq!(sent.status.code(), Some(4));
+}
+
+#[cfg(unix)]
+#[test]
+fn generate_and_validate_a_remote_file_over_ssh() {
+    use std::os::unix::fs::PermissionsExt as _;
+    let dir = TempDir::new().unwrap();
+    // runs the remote command locally, after the options and the destination
+    let ssh = dir.path().join("ssh");
+    fs::write(&ssh, "#!/bin/sh\nwhile [ $# -gt 1 ]; do shift; done\nexec sh -c \"$1\"\n").unwrap();
+    fs::set_permissions(&ssh, fs::Permissions::from_mode(0o755)).unwrap();
+    let target = format!("ssh://user@host:2222{}/out.bin", dir.path().display());
+    let run = |command: &str, args: &[&str]| {
+        bin()
+            .env("RANDSTREAM_SSH", &ssh)
+            .args([command, "--no-progress"])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let g = run("generate", &["--size", "1Mi", "--seed", "5", &target]);
+    assert!(g.status.success(), "{}", String::from_utf8_lossy(&g.stderr));
+    assert_eq!(fs::metadata(dir.path().join("out.bin")).unwrap().len(), 1024 * 1024);
+    let v = run("validate", &[&target]);
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+    assert_eq!(parse_checksum(&v), parse_checksum(&g));
+    let missing = format!("ssh://host{}/missing.bin", dir.path().display());
+    assert_eq!(run("validate", &[&missing]).status.code(), Some(4));
+}
+
+#[test]
+fn serve_validates_each_connection_independently() {
+    use std::io::{BufRead as _, BufReader, Read as _};
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let mut server = bin()
+        .args(["serve", "--no-progress", "--listen", &format!("127.0.0.1:{port}")])
+        .args(["--connections", "3"])
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stderr = BufReader::new(server.stderr.take().unwrap());
+    let mut log = String::new();
+    stderr.read_line(&mut log).unwrap();
+    assert!(log.contains("listening on"), "{log}");
+    let address = format!("tcp://127.0.0.1:{port}");
+    let senders: Vec<_> = ["1", "2"]
+        .map(|seed| {
+            bin()
+                .args(["send", "--no-progress", "--size", "1Mi", "--seed", seed, &address])
+                .stderr(Stdio::null())
+                .spawn()
+                .unwrap()
+        })
+        .into_iter()
+        .collect();
+    for mut sender in senders {
+        assert!(sender.wait().unwrap().success());
+    }
+    // a stream which isn't a random one
+    let mut socket = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+    socket.write_all(&[0x55; 100_000]).unwrap();
+    drop(socket);
+    stderr.read_to_string(&mut log).unwrap();
+    assert_eq!(server.wait().unwrap().code(), Some(2), "{log}");
+    assert_eq!(log.matches(": pass").count(), 2, "{log}");
+    assert_eq!(log.matches(": fail").count(), 1, "{log}");
+    assert!(log.contains("sender"), "{log}");
+}
+
+#[test]
+fn perf_reports_the_throughput_and_the_result_of_the_server() {
+    use std::io::{BufRead as _, BufReader, Read as _};
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let mut server = bin()
+        .args(["serve", "--no-progress", "--listen", &format!("127.0.0.1:{port}")])
+        .args(["--connections", "3"])
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stderr = BufReader::new(server.stderr.take().unwrap());
+    let mut log = String::new();
+    stderr.read_line(&mut log).unwrap();
+    assert!(log.contains("listening on"), "{log}");
+    let address = format!("tcp://127.0.0.1:{port}");
+    let out = bin()
+        .args(["perf", "--time", "1", "--parallel", "2", "--bwlimit", "10Mi", &address])
+        .output()
+        .unwrap();
+    let perf_log = String::from_utf8_lossy(&out.stderr);
+    assert!(out.status.success(), "{perf_log}");
+    assert!(perf_log.contains("0-1s"), "{perf_log}");
+    assert!(perf_log.contains("2 of 2 streams valid"), "{perf_log}");
+    // the server doesn't expect that chunk size
+    let out = bin().args(["perf", "--time", "1", "-c", "64Ki", &address]).output().unwrap();
+    let perf_log = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.code(), Some(2), "{perf_log}");
+    assert!(perf_log.contains("Invalid checksum at chunk 0"), "{perf_log}");
+    // only the options of perf are listed
+    let out = bin().args(["perf", "--help"]).output().unwrap();
+    let help = String::from_utf8_lossy(&out.stdout);
+    assert!(help.contains("Usage: randstream perf [OPTIONS] <ENDPOINT>"), "{help}");
+    assert!(!help.contains("--tree"), "{help}");
+    assert_eq!(bin().args(["perf", "--tree", "dir", &address]).status().unwrap().code(), Some(5));
+    stderr.read_to_string(&mut log).unwrap();
+    assert_eq!(server.wait().unwrap().code(), Some(2), "{log}");
+    assert_eq!(log.matches(": pass").count(), 2, "{log}");
+}
+
+#[test]
+fn send_and_receive_datagrams_over_udp() {
+    use std::io::{BufRead as _, BufReader, Read as _};
+    let port = std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let address = format!("udp://127.0.0.1:{port}");
+    let receive = |size: &str| {
+        let mut receiver = bin()
+            .args(["receive", "--no-progress", "-c", "4Ki", "--size", size, &address])
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        // wait for the receiver to listen
+        let mut stderr = BufReader::new(receiver.stderr.take().unwrap());
+        let mut line = String::new();
+        stderr.read_line(&mut line).unwrap();
+        assert!(line.contains("listening on"), "{line}");
+        let sent = bin()
+            .args(["send", "--no-progress", "--size", "1Mi", "-c", "4Ki", "--format", "v2"])
+            .args(["--bwlimit", "10Mi", &address])
+            .output()
+            .unwrap();
+        assert!(sent.status.success(), "{}", String::from_utf8_lossy(&sent.stderr));
+        stderr.read_to_string(&mut line).unwrap();
+        (receiver.wait().unwrap().code(), line)
+    };
+    let (code, stderr) = receive("1Mi");
+    assert_eq!(code, Some(0), "{stderr}");
+    assert!(stderr.contains("datagrams: 256 received, 0 lost"), "{stderr}");
+    // the receiver expects a larger stream
+    let (code, stderr) = receive("2Mi");
+    assert_eq!(code, Some(2));
+    assert!(stderr.contains("256 lost datagrams"), "{stderr}");
+
+    let sent = bin().args(["send", "--no-progress", "--size", "1Mi", &address]).output().unwrap();
+    assert_eq!(sent.status.code(), Some(5));
+}
+
+#[test]
+fn position_requires_file_argument() {
+    // --position without a file path is rejected by clap (requires="file").
+    let out = bin()
+        .args(["generate", "--no-progress", "--size", "32Ki", "--position", "0"])
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+}
+
+#[test]
+fn help_flag_succeeds() {
+    let out = bin().arg("--help").output().unwrap();
+    assert!(out.status.success());
+}
+
+#[test]
+fn generate_help_flag_succeeds() {
+    let out = bin().args(["generate", "--help"]).output().unwrap();
+    assert!(out.status.success());
+}
+
+#[test]
+fn validate_help_flag_succeeds() {
+    let out = bin().args(["validate", "--help"]).output().unwrap();
+    assert!(out.status.success());
+}
+
+#[test]
+fn bench_reports_the_iops_and_the_latency_of_the_chunks_validated() {
+    let dir = TempDir::new().unwrap();
+    let out = bin()
+        .current_dir(dir.path())
+        .args(["bench", "--size", "1Mi", "--time", "1", "--jobs", "2", "out.bin"])
+        .args(["--latency-hist", "lat.hgrm"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(out.status.success(), "{stderr}");
+    assert!(stderr.contains("write: ") && stderr.contains(" IOPS, "), "{stderr}");
+    assert!(stderr.contains("read latency: min "), "{stderr}");
+    for phase in ["write", "read"] {
+        let hgrm = fs::read_to_string(dir.path().join(format!("lat.{phase}.hgrm"))).unwrap();
+        assert!(hgrm.starts_with("       Value     Percentile TotalCount"), "{hgrm}");
+        assert!(hgrm.contains("#[Max     = "), "{hgrm}");
+    }
+    // the region is written in full, chunk after chunk
+    let v = validate(&dir, &["--chunk-size", "4Ki", "out.bin"]);
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+    assert_eq!(fs::metadata(dir.path().join("out.bin")).unwrap().len(), 1 << 20);
+
+    let out = bin()
+        .current_dir(dir.path())
+        .args(["bench", "--time", "1", "-c", "8Ki", "--output", "json", "out.bin"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.starts_with(r#"{"command":"bench","success":true,"#), "{stdout}");
+    assert!(stdout.contains(r#""phases":[{"name":"write","pass":1,"#), "{stdout}");
+    assert!(stdout.contains(r#","iops":"#) && stdout.contains(r#","latency":{"min":"#), "{stdout}");
+    let out = bin().current_dir(dir.path()).args(["bench", "missing.bin"]).output().unwrap();
+    assert_eq!(out.status.code(), Some(5));
+}
+
+#[test]
+fn bench_with_random_access_validates_each_chunk_against_its_index() {
+    let dir = TempDir::new().unwrap();
+    let out = bin()
+        .current_dir(dir.path())
+        .args(["bench", "--size", "1Mi", "--time", "1", "--jobs", "3", "out.bin"])
+        .args(["--access", "random", "--format", "v2"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(out.status.success(), "{stderr}");
+    assert!(stderr.contains("read: ") && stderr.contains(" IOPS, "), "{stderr}");
+    // the chunks are at their place in the stream, with their header
+    let v = validate(&dir, &["--chunk-size", "4Ki", "--format", "v2", "out.bin"]);
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+}
+
+#[test]
+fn discard_test_punches_holes_between_chunks_kept_valid() {
+    let dir = TempDir::new().unwrap();
+    let out = bin()
+        .current_dir(dir.path())
+        .args(["discard-test", "-P", "--size", "1Mi", "--chunk-size", "4Ki", "--ranges", "4"])
+        .args(["--output", "json", "out.bin"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(out.status.success(), "{stderr}");
+    assert!(stderr.contains(" zeroed, 0 unchanged, 0 other"), "{stderr}");
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains(r#""phases":[{"name":"generate","#), "{stdout}");
+    assert!(stdout.contains(r#"{"name":"discard","#), "{stdout}");
+    assert_eq!(fs::metadata(dir.path().join("out.bin")).unwrap().len(), 1 << 20);
+    // the discarded chunks are zeroed
+    let v = validate(&dir, &["--chunk-size", "4Ki", "out.bin"]);
+    assert_eq!(v.status.code(), Some(2));
+
+    let out = bin()
+        .current_dir(dir.path())
+        .args(["discard-test", "-P", "--size", "8Ki", "--chunk-size", "4Ki", "out.bin"])
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(5));
+}
+
+#[test]
+fn bench_keeps_several_chunks_in_flight_with_io_uring() {
+    let dir = TempDir::new().unwrap();
+    let out = bin()
+        .current_dir(dir.path())
+        .args(["bench", "--size", "1Mi", "--time", "1", "--jobs", "2", "out.bin"])
+        .args(["--engine", "io-uring", "--iodepth", "8", "--access", "random", "--output", "json"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert!(stdout.contains(r#""name":"read","pass":1,"#), "{stdout}");
+    assert!(stdout.contains(r#","queue_depth":"#), "{stdout}");
+    let v = validate(&dir, &["--chunk-size", "4Ki", "out.bin"]);
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+
+    let out = bin()
+        .current_dir(dir.path())
+        .args(["bench", "--time", "1", "--iodepth", "8", "out.bin"])
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(5));
+    assert!(
+        String::from_utf8_lossy(&out.stderr).contains("--iodepth requires the io-uring engine")
+    );
+}
+
+#[test]
+fn generate_reads_each_chunk_back_after_writing_it() {
+    let dir = TempDir::new().unwrap();
+    let args = ["--size", "1000000", "--chunk-size", "4Ki", "--batch-chunks", "4"];
+    let g = generate(&dir, &[&args[..], &["--verify-after-write", "out.bin"]].concat());
+    assert!(g.status.success(), "{}", String::from_utf8_lossy(&g.stderr));
+    let v = validate(&dir, &["--chunk-size", "4Ki", "out.bin"]);
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+    assert_eq!(parse_checksum(&g), parse_checksum(&v));
+
+    let g = generate(&dir, &["--verify-after-write", "--engine", "mmap", "out.bin"]);
+    assert_eq!(g.status.code(), Some(5));
+    assert!(String::from_utf8_lossy(&g.stderr).contains("requires the sync engine"));
+}
+
+#[test]
+fn journal_records_each_chunk_operation() {
+    let dir = TempDir::new().unwrap();
+    let args = ["--size", "10000", "--chunk-size", "4Ki", "--journal", "run.log", "out.bin"];
+    let g = generate(&dir, &args);
+    assert!(g.status.success(), "{}", String::from_utf8_lossy(&g.stderr));
+    let path = dir.path().join("out.bin");
+    let mut data = fs::read(&path).unwrap();
+    data[5000] ^= 1;
+    fs::write(&path, &data).unwrap();
+    let v = validate(&dir, &args);
+    assert_eq!(v.status.code(), Some(2));
+
+    let journal = fs::read_to_string(dir.path().join("run.log")).unwrap();
+    let lines: Vec<_> = journal.lines().collect();
+    assert_eq!(lines.len(), 5, "{journal}");
+    assert_eq!(lines.iter().filter(|l| l.starts_with(r#"{"op":"write","#)).count(), 3);
+    assert!(lines[3].starts_with(r#"{"op":"read","chunk":0,"offset":0,"size":4096,"#));
+    assert!(lines[3].contains(r#""result":"ok","checksum":""#), "{journal}");
+    assert!(lines[4].starts_with(r#"{"op":"read","chunk":1,"offset":4096,"size":4096,"#));
+    assert!(lines[4].contains(r#""result":"error","error":"Invalid checksum"#), "{journal}");
+
+    let g = generate(&dir, &["--journal", "run.log", "--engine", "mmap", "out.bin"]);
+    assert_eq!(g.status.code(), Some(5));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn systemd_is_notified_of_the_readiness_and_progress() {
+    use std::os::unix::net::UnixDatagram;
+
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "1Mi", "out.bin"]);
+    assert!(g.status.success());
+    let socket_path = dir.path().join("notify.sock");
+    let socket = UnixDatagram::bind(&socket_path).unwrap();
+    let v = bin()
+        .current_dir(dir.path())
+        .args(["validate", "--no-progress", "out.bin"])
+        .env("NOTIFY_SOCKET", &socket_path)
+        .env("WATCHDOG_USEC", "1")
+        .output()
+        .unwrap();
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+    socket.set_nonblocking(true).unwrap();
+    let mut messages = Vec::new();
+    let mut buffer = [0; 256];
+    while let Ok(n) = socket.recv(&mut buffer) {
+        messages.push(String::from_utf8_lossy(&buffer[..n]).to_string());
+    }
+    assert_eq!(messages.first().map(String::as_str), Some("READY=1"), "{messages:?}");
+    assert!(messages.iter().any(|m| m.starts_with("STATUS=") && m.ends_with("% validated")));
+    assert!(messages.iter().any(|m| m == "WATCHDOG=1"), "{messages:?}");
+}
+
+#[test]
+fn configuration_file_supplies_the_default_options() {
+    let dir = TempDir::new().unwrap();
+    let config = "[defaults]\nchunk-size = \"4Ki\"\nkeep-going = true\n\n\
+                  [targets.\"out.bin\"]\nchecksum = \"xxh3\"\n";
+    fs::write(dir.path().join("randstream.toml"), config).unwrap();
+    let with_config = |command: &str, args: &[&str]| {
+        bin()
+            .current_dir(dir.path())
+            .args(["--config", "randstream.toml", command, "-P"])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let g = with_config("generate", &["--size", "100000", "out.bin"]);
+    assert!(g.status.success(), "{}", String::from_utf8_lossy(&g.stderr));
+    assert_eq!(parse_checksum(&g).len(), 16);
+    let v = with_config("validate", &["out.bin"]);
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+    // the command line takes precedence
+    let v = with_config("validate", &["--chunk-size", "8Ki", "out.bin"]);
+    assert_eq!(v.status.code(), Some(2));
+    let v = validate(&dir, &["--no-config", "--chunk-size", "4Ki", "out.bin"]);
+    assert_eq!(v.status.code(), Some(2));
+    // the defaults conflicting with the command line are skipped
+    let g = with_config("generate", &["--size", "100000", "--checksum-file", "sum.txt", "a.bin"]);
+    assert!(g.status.success(), "{}", String::from_utf8_lossy(&g.stderr));
+    let v = with_config("validate", &["--checksum-file", "sum.txt", "a.bin"]);
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+
+    fs::write(dir.path().join("randstream.toml"), "[defaults]\nchunk-sise = \"4Ki\"\n").unwrap();
+    let v = with_config("validate", &["out.bin"]);
+    assert_eq!(v.status.code(), Some(5));
+    assert!(String::from_utf8_lossy(&v.stderr).contains("Unknown option chunk-sise"));
+}
+
+#[test]
+fn completion_scripts() {
+    let out = bin().args(["completions", "bash"]).output().unwrap();
+    assert!(out.status.success());
+    let script = String::from_utf8_lossy(&out.stdout);
+    assert!(script.contains("randstream,send)"));
+    assert!(script.contains("--chunk-size"));
+    let out = bin().args(["completions", "zsh"]).output().unwrap();
+    assert!(String::from_utf8_lossy(&out.stdout).contains("[The chunk size]:SIZE:"));
+    let out = bin().args(["completions", "fish"]).output().unwrap();
+    assert!(String::from_utf8_lossy(&out.stdout).contains("-l chunk-size"));
+    let out = bin().args(["completions", "tcsh"]).output().unwrap();
+    assert_eq!(out.status.code(), Some(5));
+}
+
+#[test]
+fn print_checksum_alone_on_stdout() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["-q", "--print-checksum", "--size", "100000", "out.bin"]);
+    assert!(g.status.success());
+    let checksum = String::from_utf8(g.stdout).unwrap();
+    assert_eq!(checksum.trim().len(), 8);
+    assert!(g.stderr.is_empty());
+    let v = validate(&dir, &["-q", "--print-checksum", "-e", checksum.trim(), "out.bin"]);
+    assert!(v.status.success());
+    assert_eq!(String::from_utf8(v.stdout).unwrap(), checksum);
+
+    // nothing printed on a failure
+    let v = validate(&dir, &["-q", "--print-checksum", "-e", "00000000", "out.bin"]);
+    assert_eq!(v.status.code(), Some(3));
+    assert!(v.stdout.is_empty());
+    // the stream is written on stdout without a file
+    let g = generate(&dir, &["--print-checksum", "--size", "100000"]);
+    assert_eq!(g.status.code(), Some(5));
+}
+
+#[test]
+fn checksum_file_carries_the_parameters() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(
+        &dir,
+        &[
+            "--size",
+            "100000",
+            "-c",
+            "8Ki",
+            "--checksum",
+            "xxh3",
+            "--checksum-file",
+            "sum.txt",
+            "out.bin",
+        ],
+    );
+    assert!(g.status.success(), "{}", String::from_utf8_lossy(&g.stderr));
+    let sums = fs::read_to_string(dir.path().join("sum.txt")).unwrap();
+    assert!(sums.starts_with("randstream-checksum 1\n"));
+    assert!(sums.contains(&format!("checksum {}\n", parse_checksum(&g))));
+    let v = validate(&dir, &["--checksum-file", "sum.txt", "--regenerate", "out.bin"]);
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+    assert_eq!(parse_checksum(&v), parse_checksum(&g));
+
+    // another stream in the file
+    let g = generate(
+        &dir,
+        &["--size", "100000", "-c", "8Ki", "--checksum", "xxh3", "-S", "1", "out.bin"],
+    );
+    assert!(g.status.success());
+    let v = validate(&dir, &["--checksum-file", "sum.txt", "out.bin"]);
+    assert_eq!(v.status.code(), Some(3));
+    let v = validate(&dir, &["--checksum-file", "sum.txt", "-c", "8Ki", "out.bin"]);
+    assert_eq!(v.status.code(), Some(5));
+    fs::write(dir.path().join("sum.txt"), "9f3a12bc\n").unwrap();
+    let v = validate(&dir, &["--checksum-file", "sum.txt", "out.bin"]);
+    assert_eq!(v.status.code(), Some(5));
+}
+
+#[test]
+fn regions_with_their_own_seed() {
+    let dir = TempDir::new().unwrap();
+    let common = ["-c", "4Ki", "--format", "v2", "--region-size", "64Ki"];
+    let g = generate(&dir, &[&common[..], &["--size", "256Ki", "-S", "5", "out.bin"]].concat());
+    assert!(g.status.success(), "{}", String::from_utf8_lossy(&g.stderr));
+    let data = fs::read(dir.path().join("out.bin")).unwrap();
+    // past the chunk headers, the regions hold different data
+    assert_ne!(data[64..4096], data[65536 + 64..65536 + 4096]);
+
+    let v = validate(&dir, &[&common[..], &["out.bin"]].concat());
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+    assert_eq!(parse_checksum(&v), parse_checksum(&g));
+    let v = validate(&dir, &[&common[..], &["--regenerate", "-S", "5", "out.bin"]].concat());
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+    // a region on its own
+    let region = ["--regenerate", "-S", "5", "--position", "128Ki", "--length", "64Ki", "out.bin"];
+    let v = validate(&dir, &[&common[..], &region].concat());
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+    // from within a region to the end of the stream, its first chunks spanning two regions
+    for seed in [&[][..], &["--regenerate", "-S", "5"]] {
+        let v = validate(&dir, &[&common[..], seed, &["--position", "120Ki", "out.bin"]].concat());
+        assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+    }
+    let args = ["--regenerate", "-S", "6", "--position", "120Ki", "out.bin"];
+    let v = validate(&dir, &[&common[..], &args].concat());
+    assert_eq!(v.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&v.stderr).contains("comes from another stream"));
+
+    // the chunk headers of the second region have another fingerprint
+    let v = validate(&dir, &["-c", "4Ki", "--format", "v2", "out.bin"]);
+    assert_eq!(v.status.code(), Some(2));
+    let v = validate(&dir, &["-c", "4Ki", "--region-size", "6Ki", "out.bin"]);
+    assert_eq!(v.status.code(), Some(5));
+}
+
+#[test]
+fn seed_from_device() {
+    let dir = TempDir::new().unwrap();
+    // a regular file has no identity
+    let g = generate(&dir, &["--size", "64Ki", "--seed-from-device", "out.bin"]);
+    assert_eq!(g.status.code(), Some(5));
+    assert!(String::from_utf8_lossy(&g.stderr).contains("requires a block device"));
+    let g = generate(&dir, &["--size", "64Ki", "--seed-from-device", "-S", "5", "out.bin"]);
+    assert_eq!(g.status.code(), Some(5));
+
+    // nothing would tell the stream of another device, without the chunk headers
+    let g = generate(&dir, &["--size", "64Ki", "out.bin"]);
+    assert!(g.status.success(), "{}", String::from_utf8_lossy(&g.stderr));
+    let v = validate(&dir, &["--seed-from-device", "out.bin"]);
+    assert_eq!(v.status.code(), Some(5));
+    assert!(String::from_utf8_lossy(&v.stderr).contains("requires --format v2 or --regenerate"));
+
+    // the seed given for the regeneration is checked against the chunk headers
+    let common = ["-c", "4Ki", "--format", "v2"];
+    let g = generate(&dir, &[&common[..], &["--size", "64Ki", "-S", "5", "out.bin"]].concat());
+    assert!(g.status.success(), "{}", String::from_utf8_lossy(&g.stderr));
+    let v = validate(&dir, &[&common[..], &["--regenerate", "-S", "6", "out.bin"]].concat());
+    assert_eq!(v.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&v.stderr).contains("Chunk 0 comes from another stream"));
+}
+
+#[test]
+fn sector_checksums_locate_the_damaged_sectors() {
+    let dir = TempDir::new().unwrap();
+    let common = ["-c", "4Ki", "--sector-checksums", "512"];
+    let g = generate(&dir, &[&common[..], &["--size", "64Ki", "-S", "3", "out.bin"]].concat());
+    assert!(g.status.success(), "{}", String::from_utf8_lossy(&g.stderr));
+    let v = validate(&dir, &[&common[..], &["--regenerate", "-S", "3", "out.bin"]].concat());
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+    // the chunks are still sealed by their checksum
+    let v = validate(&dir, &["-c", "4Ki", "out.bin"]);
+    assert_eq!(parse_checksum(&v), parse_checksum(&g));
+
+    let path = dir.path().join("out.bin");
+    let mut data = fs::read(&path).unwrap();
+    data[2 * 4096 + 1000] ^= 0xff;
+    fs::write(&path, data).unwrap();
+    let corruption = ["--keep-going", "--badblocks-out", "bad.txt", "out.bin"];
+    let v = validate(&dir, &[&common[..], &corruption].concat());
+    assert_eq!(v.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    assert!(stderr.contains("Damaged sectors at bytes 512..1024 of the chunk"), "{stderr}");
+    assert_eq!(fs::read_to_string(dir.path().join("bad.txt")).unwrap(), "17\n");
+
+    let g =
+        generate(&dir, &["-c", "1000", "--sector-checksums", "512", "--size", "64Ki", "out.bin"]);
+    assert_eq!(g.status.code(), Some(5));
+}
+
+#[test]
+fn chunk_size_auto_detected() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "1Mi", "-c", "128Ki", "out.bin"]);
+    assert!(g.status.success(), "{}", String::from_utf8_lossy(&g.stderr));
+    let v = validate(&dir, &["-c", "auto", "out.bin"]);
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+    assert_eq!(parse_checksum(&v), parse_checksum(&g));
+    assert!(String::from_utf8_lossy(&v.stderr).contains("detected chunk size: 131072"));
+
+    // with a stream header, and a chunk size recorded in the chunk headers
+    let g = generate(
+        &dir,
+        &["--size", "1Mi", "-c", "100000", "--format", "v2", "--random-seed", "out.bin"],
+    );
+    assert!(g.status.success(), "{}", String::from_utf8_lossy(&g.stderr));
+    let v = validate(&dir, &["-c", "auto", "out.bin"]);
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+    assert_eq!(parse_checksum(&v), parse_checksum(&g));
+
+    fs::write(dir.path().join("ones.bin"), vec![1; 1 << 20]).unwrap();
+    let v = validate(&dir, &["-c", "auto", "ones.bin"]);
+    assert_eq!(v.status.code(), Some(5));
+    let g = generate(&dir, &["--size", "1Mi", "-c", "auto", "out.bin"]);
+    assert_eq!(g.status.code(), Some(5));
+    let v = validate(&dir, &["-c", "0", "out.bin"]);
+    assert_eq!(v.status.code(), Some(5));
+}
+
+#[test]
+fn chunk_format_detected() {
+    let dir = TempDir::new().unwrap();
+    // the layout of the v2 chunks never changes, so the archived streams can still be validated
+    let g = generate(
+        &dir,
+        &["--size", "256Ki", "-S", "7", "--format-version", "v2", "--run-id", "1", "out.bin"],
+    );
+    assert!(g.status.success(), "{}", String::from_utf8_lossy(&g.stderr));
+    assert_eq!(parse_checksum(&g), "66943b54");
+    let v = validate(&dir, &["out.bin"]);
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+    assert!(String::from_utf8_lossy(&v.stderr).contains("chunk format: v2, detected"));
+
+    // the chunks swapped are reported without --format v2
+    let path = dir.path().join("out.bin");
+    let mut data = fs::read(&path).unwrap();
+    let (first, second) = data.split_at_mut(32768);
+    first.swap_with_slice(&mut second[..32768]);
+    fs::write(&path, data).unwrap();
+    let v = validate(&dir, &["out.bin"]);
+    assert_eq!(v.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&v.stderr).contains("Misplaced chunk 0"));
+    let mut child = bin()
+        .args(["validate", "--no-progress"])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // validate stops at the first error, without reading the rest
+    let _ = child.stdin.take().unwrap().write_all(&fs::read(&path).unwrap());
+    let v = child.wait_with_output().unwrap();
+    assert_eq!(v.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&v.stderr).contains("Misplaced chunk 0"));
+}
+
+#[test]
+fn scrub_validates_the_targets_due() {
+    let dir = TempDir::new().unwrap();
+    assert!(generate(&dir, &["--size", "256Ki", "-S", "1", "a.bin"]).status.success());
+    assert!(generate(&dir, &["--size", "256Ki", "-S", "2", "b.bin"]).status.success());
+    let path = dir.path().join("b.bin");
+    let mut data = fs::read(&path).unwrap();
+    data[100000] ^= 0xff;
+    fs::write(&path, data).unwrap();
+    let targets = "[defaults]\nbwlimit = \"100Mi\"\n\n[targets.\"a.bin\"]\nregenerate = true\nseed = 1\n\n\
+                   [targets.\"b.bin\"]\nkeep-going = true\n";
+    fs::write(dir.path().join("targets.toml"), targets).unwrap();
+    let scrub = |args: &[&str]| {
+        let mut cmd = bin();
+        cmd.current_dir(dir.path()).args(["scrub", "--once", "--targets", "targets.toml"]);
+        cmd.args(["--state", "state", "--metrics", "scrub.prom"]);
+        cmd.args(["--hook", "echo $RANDSTREAM_TARGET $RANDSTREAM_EXIT_CODE >> hook.log"]);
+        cmd.args(args).output().unwrap()
+    };
+    let s = scrub(&[]);
+    let stderr = String::from_utf8_lossy(&s.stderr);
+    assert_eq!(s.status.code(), Some(2), "{stderr}");
+    assert!(stderr.contains("validating a.bin"), "{stderr}");
+    let hook = fs::read_to_string(dir.path().join("hook.log")).unwrap();
+    assert_eq!(hook, "a.bin 0\nb.bin 2\n");
+    let metrics = fs::read_to_string(dir.path().join("scrub.prom")).unwrap();
+    assert!(metrics.contains("randstream_scrub_exit_code{target=\"b.bin\"} 2\n"), "{metrics}");
+    assert!(metrics.contains("randstream_scrub_corrupted_chunks{target=\"b.bin\"} 1\n"));
+
+    // the targets validated within the period aren't due
+    let s = scrub(&["--schedule", "daily"]);
+    assert!(s.status.success(), "{}", String::from_utf8_lossy(&s.stderr));
+    assert_eq!(fs::read_to_string(dir.path().join("hook.log")).unwrap(), hook);
+
+    fs::write(dir.path().join("targets.toml"), "[targets.\"a.bin\"]\nseed = 1\n").unwrap();
+    assert_eq!(scrub(&[]).status.code(), Some(5));
+    assert_eq!(scrub(&["--schedule", "3y"]).status.code(), Some(5));
+}
+
+#[test]
+fn batch_validates_the_listed_files() {
+    let dir = TempDir::new().unwrap();
+    fs::create_dir(dir.path().join("images")).unwrap();
+    let names: Vec<_> = (0..6).map(|i| format!("images/{i}.img")).collect();
+    for name in &names {
+        assert!(generate(&dir, &["--size", "64Ki", name]).status.success());
+    }
+    fs::write(dir.path().join("list.txt"), "# exported\nimages/4.img\n\nimages/5.img\n").unwrap();
+    let v = validate(&dir, &["--file-jobs", "2", "images/[0-3].img", "--from-file", "list.txt"]);
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    assert!(v.status.success(), "{stderr}");
+    for name in &names {
+        assert!(stderr.lines().any(|l| l.starts_with(name) && l.contains(" pass ")), "{stderr}");
+    }
+    assert!(stderr.contains("files: 6, passed: 6, failed: 0, 384 KiB in "), "{stderr}");
+
+    // the list on stdin
+    let mut child = bin()
+        .current_dir(dir.path())
+        .args(["validate", "--no-progress", "--from-file", "-"])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"images/0.img\nimages/missing.img\n").unwrap();
+    let v = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    assert_eq!(v.status.code(), Some(4), "{stderr}");
+    assert!(stderr.contains("files: 2, passed: 1, failed: 1"), "{stderr}");
+
+    assert_eq!(validate(&dir, &["images/*.iso"]).status.code(), Some(5));
+    assert_eq!(validate(&dir, &["--file-jobs", "0", "images/*.img"]).status.code(), Some(5));
+}
+
+#[test]
+fn tree_of_files_with_a_manifest() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--tree", "tree", "--files", "300", "--file-size-dist", "1Ki..64Ki"]);
+    let stderr = String::from_utf8_lossy(&g.stderr);
+    assert!(g.status.success(), "{stderr}");
+    assert!(stderr.contains("files: 300, passed: 300, failed: 0"), "{stderr}");
+    let g = generate(&dir, &["--tree", "tree", "--files", "300", "--file-size-dist", "1Ki..64Ki"]);
+    assert!(g.status.success());
+    assert!(!dir.path().join("manifest").exists());
+    let args = ["--tree", "tree", "--files", "300", "--file-size-dist", "1Ki..64Ki"];
+    let g = generate(&dir, &[&args[..], &["--manifest", "manifest", "-j", "3"]].concat());
+    assert!(g.status.success());
+    let manifest = fs::read_to_string(dir.path().join("manifest")).unwrap();
+    assert_eq!(manifest.lines().count(), 301);
+    assert!(manifest.lines().nth(300).unwrap().ends_with(" 0001/00000299.bin"), "{manifest}");
+    // the checksum of each file is logged with its path in the tree
+    let stderr = String::from_utf8_lossy(&g.stderr);
+    let checksum = manifest.lines().nth(300).unwrap().split(' ').next().unwrap();
+    assert!(stderr.contains(&format!("0001/00000299.bin: checksum: {checksum}\n")), "{stderr}");
+    let size = |name: &str| fs::metadata(dir.path().join("tree").join(name)).unwrap().len();
+    assert!((1024..=65536).contains(&size("0000/00000000.bin")));
+
+    let v = validate(&dir, &["--manifest", "manifest", "tree"]);
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    assert!(v.status.success(), "{stderr}");
+    assert!(stderr.contains("files: 300, passed: 300, failed: 0"), "{stderr}");
+    // a file of the tree replaced by another stream
+    let file = dir.path().join("tree/0000/00000007.bin");
+    fs::copy(dir.path().join("tree/0000/00000008.bin"), &file).unwrap();
+    let v = validate(&dir, &["--manifest", "manifest", "tree"]);
+    assert_eq!(v.status.code(), Some(3), "{}", String::from_utf8_lossy(&v.stderr));
+
+    assert_eq!(generate(&dir, &["--tree", "tree", "--size", "1Mi"]).status.code(), Some(5));
+    assert_eq!(
+        generate(&dir, &["--tree", "t", "--file-size-dist", "2Ki..1Ki"]).status.code(),
+        Some(5)
+    );
+    let v = validate(&dir, &["--manifest", "tree/0000/00000000.bin", "tree"]);
+    assert_eq!(v.status.code(), Some(5));
+}
+
+#[test]
+fn tar_archive_of_random_files() {
+    let dir = TempDir::new().unwrap();
+    let args = ["--tar", "20", "--file-size-dist", "1Ki..256Ki", "-S", "3"];
+    let g = generate(&dir, &[&args[..], &["--manifest", "manifest"]].concat());
+    let stderr = String::from_utf8_lossy(&g.stderr);
+    assert!(g.status.success(), "{stderr}");
+    let archive = g.stdout;
+    assert_eq!(archive.len() % 10240, 0);
+    assert_eq!(&archive[..6], b"0000/\0");
+    assert_eq!(&archive[257..263], b"ustar\0");
+    // the same options write the same archive
+    assert_eq!(generate(&dir, &args).stdout, archive);
+    fs::write(dir.path().join("out.tar"), &archive).unwrap();
+
+    let v = validate(&dir, &["--tar", "--regenerate", "-S", "3", "out.tar"]);
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    assert!(v.status.success(), "{stderr}");
+    assert!(stderr.lines().any(|l| l.starts_with("0000/00000019.bin") && l.contains(" pass ")));
+    assert!(stderr.contains("files: 20, passed: 20, failed: 0"), "{stderr}");
+
+    // the files of the tree, once extracted
+    if Command::new("tar").arg("--version").output().is_ok_and(|o| o.status.success()) {
+        fs::create_dir(dir.path().join("tree")).unwrap();
+        let mut tar = Command::new("tar");
+        tar.current_dir(dir.path()).args(["-xf", "out.tar", "-C", "tree"]);
+        assert!(tar.status().unwrap().success());
+        let v = validate(&dir, &["--manifest", "manifest", "tree"]);
+        assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+    }
+
+    // a corrupted member, read on stdin
+    let mut corrupted = archive.clone();
+    corrupted[3000] ^= 0xff;
+    let mut child = bin()
+        .current_dir(dir.path())
+        .args(["validate", "--no-progress", "--tar"])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(&corrupted).unwrap();
+    let v = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8_lossy(&v.stderr);
+    assert_eq!(v.status.code(), Some(2), "{stderr}");
+    assert!(stderr.contains("files: 20, passed: 19, failed: 1"), "{stderr}");
+
+    fs::write(dir.path().join("short.tar"), &archive[..archive.len() / 2]).unwrap();
+    assert_eq!(validate(&dir, &["--tar", "short.tar"]).status.code(), Some(1));
+    assert_eq!(generate(&dir, &["--tar", "2", "out.bin"]).status.code(), Some(5));
+}
+
+#[test]
+fn compressed_streams_are_decompressed() {
+    let dir = TempDir::new().unwrap();
+    let g = generate(&dir, &["--size", "3Mi", "--chunk-size", "64Ki", "out.bin"]);
+    assert!(g.status.success(), "{}", String::from_utf8_lossy(&g.stderr));
+    let data = fs::read(dir.path().join("out.bin")).unwrap();
+    // an uncompressed file is read as it is
+    let v = validate(&dir, &["--decompress", "auto", "--chunk-size", "64Ki", "out.bin"]);
+    assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+
+    for (program, extension) in [("gzip", "gz"), ("zstd", "zst"), ("xz", "xz")] {
+        let installed = Command::new(program).arg("--version").output();
+        if !installed.is_ok_and(|o| o.status.success()) {
+            continue;
+        }
+        let mut compress = Command::new(program);
+        compress.current_dir(dir.path()).args(["-k", "-q", "out.bin"]);
+        assert!(compress.status().unwrap().success());
+        let file = format!("out.bin.{extension}");
+        let v = validate(&dir, &["--decompress", "auto", "--chunk-size", "64Ki", &file]);
+        let stderr = String::from_utf8_lossy(&v.stderr);
+        assert!(v.status.success(), "{stderr}");
+        assert!(stderr.contains(&format!("decompressing the input with {program}")), "{stderr}");
+
+        // on stdin
+        let compressed = fs::read(dir.path().join(&file)).unwrap();
+        let mut child = bin()
+            .current_dir(dir.path())
+            .args(["validate", "--no-progress", "--decompress", program, "--chunk-size", "64Ki"])
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(&compressed).unwrap();
+        let v = child.wait_with_output().unwrap();
+        assert!(v.status.success(), "{}", String::from_utf8_lossy(&v.stderr));
+
+        // a truncated input is an I/O error
+        fs::write(dir.path().join(&file), &compressed[..compressed.len() / 2]).unwrap();
+        let v = validate(&dir, &["--decompress", "auto", "--chunk-size", "64Ki", &file]);
+        assert_eq!(v.status.code(), Some(4), "{}", String::from_utf8_lossy(&v.stderr));
+        fs::remove_file(dir.path().join(&file)).unwrap();
+    }
+
+    // the corrupted chunks are located in the decompressed stream
+    if Command::new("gzip").arg("--version").output().is_ok_and(|o| o.status.success()) {
+        let mut corrupted = data.clone();
+        corrupted[(1 << 20) + 100] ^= 0xff;
+        fs::write(dir.path().join("corrupted.bin"), &corrupted).unwrap();
+        let mut gzip = Command::new("gzip");
+        gzip.current_dir(dir.path()).arg("corrupted.bin");
+        assert!(gzip.status().unwrap().success());
+        let v =
+            validate(&dir, &["--decompress", "auto", "--chunk-size", "64Ki", "corrupted.bin.gz"]);
+        let stderr = String::from_utf8_lossy(&v.stderr);
+        assert_eq!(v.status.code(), Some(2), "{stderr}");
+        assert!(stderr.contains("at chunk 16"), "{stderr}");
+        let args = ["--decompress", "gzip", "--sample", "5%", "corrupted.bin.gz"];
+        assert_eq!(validate(&dir, &args).status.code(), Some(5));
+    }
 }
@@ -37,6 +37,11 @@ fn release(args: &Release) -> anyhow::Result<()> {
     let mut doc = toml.parse::<DocumentMut>()?;
     doc["workspace"]["package"]["version"] = value(&args.version);
     std::fs::File::create("Cargo.toml")?.write_all(doc.to_string().as_bytes())?;
+    // and the one of the python bindings, out of the workspace
+    let toml = std::fs::read_to_string("python/Cargo.toml")?;
+    let mut doc = toml.parse::<DocumentMut>()?;
+    doc["package"]["version"] = value(&args.version);
+    std::fs::File::create("python/Cargo.toml")?.write_all(doc.to_string().as_bytes())?;
     cmd!(sh, "cargo test").run()?;
     let version = &args.version;
     // commit, tag and push